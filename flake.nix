@@ -67,9 +67,12 @@
           ) + "/openssl";
         };
 
+        # Built from the workspace root for the shared hydra-log crate
         mailCommonArgs = {
-          src = craneLib.cleanCargoSource (craneLib.path ./hydra-mail);
+          src = craneLib.cleanCargoSource (craneLib.path ./.);
           buildInputs = mailBuildInputs;
+          pname = "hydra-mail";
+          cargoExtraArgs = "--package hydra-mail";
         } // mailEnvVars;
 
         mailCargoArtifacts = craneLib.buildDepsOnly mailCommonArgs;
@@ -138,7 +141,9 @@
         # HYDRA-WT (Worktree Manager)
         # ============================================================
         wtCommonArgs = {
-          src = craneLib.cleanCargoSource (craneLib.path ./hydra-wt);
+          src = craneLib.cleanCargoSource (craneLib.path ./.);
+          pname = "hydra-wt";
+          cargoExtraArgs = "--package hydra-wt";
           buildInputs = lib.optionals pkgs.stdenv.isDarwin [
             pkgs.libiconv
           ];
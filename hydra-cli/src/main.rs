@@ -3,9 +3,14 @@
 // ═══════════════════════════════════════════════════════════════════════════
 
 use clap::{Parser, Subcommand};
-use hydra_orchestrator::{find_project_root, Orchestrator, SessionConfig, SessionId};
+use hydra_orchestrator::{
+    compact_eta, find_project_root, human_duration, relative_time, Delivery, EventFollower, InboxEntry, NotBlocked,
+    Orchestrator, ResourceLimits, ResourceUsage, Scenario, Session, SessionConfig, SessionId, Simulator,
+};
 use std::fs;
+use std::io::Write;
 use ansi_term::Colour;
+use tracing::{error, info, warn};
 
 const HYDRA_BANNER: &str = r#"
 ⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⡀⠀⠀⠀⠀⢠⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀
@@ -52,6 +57,9 @@ struct Cli {
     #[arg(short = 'V', long = "version")]
     version: bool,
 
+    #[command(flatten)]
+    verbosity: hydra_log::VerbosityArgs,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -80,6 +88,30 @@ enum Commands {
 
         #[arg(long)]
         branch: Option<String>,
+
+        /// Start the worktree branch at this ref instead of HEAD
+        #[arg(long, value_name = "REF", requires = "worktree")]
+        from: Option<String>,
+
+        /// Give the worktree this port instead of the next free one
+        #[arg(long, requires = "worktree")]
+        port: Option<u16>,
+
+        /// Create the worktree under this directory instead of worktrees.directory
+        #[arg(long, value_name = "DIR", requires = "worktree")]
+        worktree_dir: Option<std::path::PathBuf>,
+
+        /// Scheduling priority for the agent (-20..19)
+        #[arg(long, allow_hyphen_values = true)]
+        nice: Option<i32>,
+
+        /// Memory cap for the agent process tree (e.g., 4G; needs systemd)
+        #[arg(long)]
+        memory_max: Option<String>,
+
+        /// CPU cap for the agent process tree (e.g., 200%; needs systemd)
+        #[arg(long)]
+        cpu_quota: Option<String>,
     },
 
     /// List active sessions
@@ -111,22 +143,149 @@ enum Commands {
         message: String,
     },
 
+    /// Continue a blocked session with guidance for the agent
+    RetryBlocked {
+        id: String,
+
+        /// Guidance for the agent (default: the blocker has been addressed)
+        #[arg(long, conflicts_with = "message_file")]
+        message: Option<String>,
+
+        /// Read the guidance from a file
+        #[arg(long)]
+        message_file: Option<std::path::PathBuf>,
+
+        /// Add N iterations to the budget, for a session blocked on its last one
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        reset_iteration_budget: u32,
+    },
+
+    /// List unanswered agent questions
+    Inbox {
+        /// Print the questions as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Answer an agent question
+    Answer {
+        /// Question id from `hydra inbox` (a unique prefix is enough)
+        question_id: String,
+
+        /// Answer text
+        #[arg(required_unless_present = "file", conflicts_with = "file")]
+        text: Option<String>,
+
+        /// Read the answer from a file
+        #[arg(long)]
+        file: Option<std::path::PathBuf>,
+    },
+
+    /// Rename a worktree session's branch
+    Rename {
+        id: String,
+        #[arg(long)]
+        branch: String,
+        /// Leave the worktree directory where it is
+        #[arg(long)]
+        keep_path: bool,
+        /// Rename even if the session is running or the worktree is dirty
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Stream orchestrator events as JSON lines
+    Events {
+        /// Keep running and print new events as they happen
+        #[arg(long)]
+        follow: bool,
+
+        /// Backfill events from this far back (e.g., 1h, 30m)
+        #[arg(long)]
+        since: Option<String>,
+
+        /// Only these event types (comma-separated: session, inject, health, merge)
+        #[arg(long, value_delimiter = ',')]
+        types: Vec<String>,
+
+        /// Remember the stream position here and resume from it on restart
+        #[arg(long)]
+        cursor_file: Option<std::path::PathBuf>,
+
+        /// Only events for this workflow trace id
+        #[arg(long)]
+        trace: Option<String>,
+    },
+
     /// Kill session
     Kill {
         id: String,
         #[arg(long)]
         reason: Option<String>,
     },
+
+    /// Run a monitor scenario against a fake clock and tmux (dev)
+    #[command(hide = true)]
+    Simulate {
+        scenario: std::path::PathBuf,
+    },
 }
 
-fn format_duration(d: std::time::Duration) -> String {
-    let secs = d.as_secs();
-    if secs < 60 {
-        format!("{}s", secs)
-    } else if secs < 3600 {
-        format!("{}m {}s", secs / 60, secs % 60)
+/// "3h ago" style start time for a session that has been running for `running`
+fn started_ago(running: std::time::Duration) -> String {
+    let started = std::time::SystemTime::now()
+        .checked_sub(running)
+        .unwrap_or(std::time::UNIX_EPOCH);
+    relative_time(started)
+}
+
+/// Unanswered questions for flagging blocked sessions; never fails the command
+fn open_questions(orch: &mut Orchestrator) -> Vec<InboxEntry> {
+    orch.inbox().unwrap_or_else(|e| {
+        warn!("failed to read questions: {:#}", e);
+        Vec::new()
+    })
+}
+
+/// Requested limits and how they were applied, e.g. "nice 10, memory 4G (systemd scope)"
+fn format_limits(session: &Session) -> Option<String> {
+    let limits = &session.config.resource_limits;
+    if limits.is_empty() {
+        return None;
+    }
+    let mut parts = Vec::new();
+    if let Some(nice) = limits.nice {
+        parts.push(format!("nice {}", nice));
+    }
+    if let Some(mem) = &limits.memory_max {
+        parts.push(format!("memory {}", mem));
+    }
+    if let Some(cpu) = &limits.cpu_quota {
+        parts.push(format!("cpu {}", cpu));
+    }
+    let how = match (&session.limit_mechanism, &session.scope_unit) {
+        (Some(mechanism), Some(unit)) => format!("{}: {}", mechanism, unit),
+        (Some(mechanism), None) => mechanism.to_string(),
+        (None, _) => "not applied".to_string(),
+    };
+    Some(format!("{} ({})", parts.join(", "), how))
+}
+
+fn format_usage(usage: &ResourceUsage) -> String {
+    let mut parts = Vec::new();
+    if let Some(bytes) = usage.memory_bytes {
+        parts.push(format!("memory {:.1} MiB", bytes as f64 / (1024.0 * 1024.0)));
+    }
+    if let Some(usec) = usage.cpu_usec {
+        parts.push(format!("cpu {}", human_duration(std::time::Duration::from_micros(usec))));
+    }
+    if let Some(tasks) = usage.tasks {
+        parts.push(format!("{} tasks", tasks));
+    }
+    if parts.is_empty() {
+        "unavailable".to_string()
     } else {
-        format!("{}h {}m", secs / 3600, (secs % 3600) / 60)
+        parts.join(", ")
     }
 }
 
@@ -164,6 +323,7 @@ fn parse_duration(s: &str) -> Result<u64, String> {
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Cli::parse();
+    hydra_log::init(cli.verbosity.level());
 
     // Handle version flag
     if cli.version {
@@ -181,16 +341,26 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     };
 
+    // Simulations run in their own scratch project
+    if let Commands::Simulate { scenario } = &command {
+        simulate(scenario);
+        return Ok(());
+    }
+
     // Try to connect to hydra-mail if available, otherwise run standalone
     let root = find_project_root();
     let mut orch = match Orchestrator::with_mail(&root) {
         Ok(o) => o,
         Err(_) => Orchestrator::new(),
     };
+    // Scopes are stopped and inspected with the same programs that start them
+    if let Ok(ralph_config) = hydra_orchestrator::HydralphConfig::load() {
+        orch.set_systemd_commands(ralph_config.systemd);
+    }
 
     match command {
         Commands::Init => {
-            println!("{}", Colour::Cyan.paint(HYDRA_BANNER));
+            info!("{}", Colour::Cyan.paint(HYDRA_BANNER));
 
             let root = find_project_root();
             let ralph_dir = root.join(".hydra/ralph");
@@ -213,14 +383,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
   ]
 }"#;
                 fs::write(&prd_path, example_prd)?;
-                println!("   Created prd.json (edit this with your stories)");
+                info!("   Created prd.json (edit this with your stories)");
             }
 
             // Create progress.txt if not exists
             let progress_path = ralph_dir.join("progress.txt");
             if !progress_path.exists() {
                 fs::write(&progress_path, "# Hydralph Progress Log\n")?;
-                println!("   Created progress.txt");
+                info!("   Created progress.txt");
             }
 
             // Copy hydralph templates from project root
@@ -238,7 +408,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     perms.set_mode(0o755);
                     fs::set_permissions(&script_dst, perms)?;
                 }
-                println!("   Created hydralph.sh");
+                info!("   Created hydralph.sh");
             }
 
             // Copy prompt.md
@@ -246,7 +416,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             let prompt_dst = ralph_dir.join("prompt.md");
             if prompt_src.exists() && !prompt_dst.exists() {
                 fs::copy(&prompt_src, &prompt_dst)?;
-                println!("   Created prompt.md");
+                info!("   Created prompt.md");
             }
 
             println!();
@@ -255,12 +425,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             println!("  2. Run: hydra spawn");
         }
 
-        Commands::Spawn { prd, max_iterations, max_duration, agent, worktree, branch } => {
+        Commands::Spawn { prd, max_iterations, max_duration, agent, worktree, branch, from, port, worktree_dir, nice, memory_max, cpu_quota } => {
             // Load config for defaults
             let ralph_config = match hydra_orchestrator::HydralphConfig::load() {
                 Ok(cfg) => cfg,
                 Err(e) => {
-                    eprintln!("Warning: Failed to load config: {}", e);
+                    warn!("failed to load config: {}", e);
                     hydra_orchestrator::HydralphConfig::default()
                 }
             };
@@ -271,7 +441,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 ralph_config.max_duration().as_secs()
             } else {
                 parse_duration(&max_duration).unwrap_or_else(|_| {
-                    eprintln!("Invalid duration format: {}. Use e.g., 4h, 30m, 1h30m", max_duration);
+                    error!("invalid duration format: {}. Use e.g., 4h, 30m, 1h30m", max_duration);
                     std::process::exit(1);
                 })
             };
@@ -283,6 +453,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 agent_cli: agent,
                 use_worktree: worktree,
                 branch_name: branch,
+                base_ref: from,
+                requested_port: port,
+                worktree_dir,
+                resource_limits: ResourceLimits { nice, memory_max, cpu_quota }.or(&ralph_config.limits),
                 ..Default::default()
             };
             match orch.spawn(config) {
@@ -292,7 +466,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     println!("   List:   hydra ls");
                 }
                 Err(e) => {
-                    eprintln!("❌ Failed to spawn: {}", e);
+                    error!("failed to spawn: {}", e);
                     std::process::exit(1);
                 }
             }
@@ -300,15 +474,21 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
         Commands::Ls => {
             let sessions = orch.list();
+            let inbox = open_questions(&mut orch);
             if sessions.is_empty() {
                 println!("No active sessions");
             } else {
-                println!("{:<12} {:<20} {:<10} {}", "ID", "STATE", "DURATION", "TMUX");
+                println!("{:<12} {:<20} {:<10} {:<16} TMUX", "ID", "STATE", "DURATION", "STARTED");
                 for s in sessions {
-                    println!("{:<12} {:<20} {:<10} {}",
+                    let state = match orch.waiting_on(&SessionId(s.id.clone()), &inbox) {
+                        Some(question) => format!("Blocked on q:{}", question.short_id()),
+                        None => s.state,
+                    };
+                    println!("{:<12} {:<20} {:<10} {:<16} {}",
                         s.id,
-                        s.state,
-                        format_duration(s.duration),
+                        state,
+                        human_duration(s.duration),
+                        started_ago(s.duration),
                         s.tmux
                     );
                 }
@@ -318,6 +498,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         Commands::Status { id } => {
             use hydra_orchestrator::SessionState;
             let session_id = hydra_orchestrator::SessionId(id.clone());
+            let branch_issues = orch.worktree_issues(&session_id);
+            let usage = orch.resource_usage(&session_id);
+            let inbox = open_questions(&mut orch);
+            let waiting_on = orch.waiting_on(&session_id, &inbox).cloned();
             if let Some(session) = orch.get_status(&session_id) {
                 let state_color = match &session.state {
                     SessionState::Running { .. } => Colour::Green,
@@ -330,7 +514,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
                 println!("Session:  {}", Colour::Cyan.bold().paint(&session.id.0));
                 println!("State:    {}", state_color.bold().paint(format!("{:?}", session.state)));
-                println!("Duration: {}", format_duration(session.started_at.elapsed()));
+                let running = session.started_at.elapsed();
+                println!("Started:  {}", started_ago(running));
+                println!("Duration: {} (limit {}, {} left)",
+                    human_duration(running),
+                    human_duration(session.config.max_duration),
+                    compact_eta(session.config.max_duration.saturating_sub(running))
+                );
                 println!("TMUX:     {}", session.tmux_session);
                 if let Some(port) = session.allocated_port {
                     println!("Port:     {}", port);
@@ -338,6 +528,18 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 if let Some(wt) = &session.worktree_path {
                     println!("Worktree: {}", wt.display());
                 }
+                if let Some(branch) = &session.config.branch_name {
+                    println!("Branch:   {}", branch);
+                }
+                if let Some(trace_id) = &session.trace_id {
+                    println!("Trace:    {}", trace_id);
+                }
+                if let Some(line) = format_limits(session) {
+                    println!("Limits:   {}", line);
+                }
+                if let Some(usage) = &usage {
+                    println!("Usage:    {}", format_usage(usage));
+                }
 
                 // Show additional details based on state
                 match &session.state {
@@ -351,6 +553,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     SessionState::Blocked { iteration, reason } => {
                         println!("{}", Colour::Red.bold().paint(format!("⛔ Blocked at iteration {}", iteration)));
                         println!("Reason: {}", reason);
+                        if let Some(entry) = &waiting_on {
+                            println!("Question: {} \"{}\"", entry.question.id, entry.question.question);
+                            println!("   Answer: hydra answer {} \"...\"", entry.short_id());
+                        }
                     }
                     SessionState::MaxIterations { iterations } => {
                         println!("{}", Colour::Yellow.bold().paint(format!("⚠️  Hit max iterations ({})", iterations)));
@@ -360,16 +566,27 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     }
                     SessionState::Stuck { since, last_iteration } => {
                         let stuck_duration = since.elapsed();
-                        println!("{}", Colour::Yellow.bold().paint(format!("⚠️  Stuck for {}", format_duration(stuck_duration))));
+                        println!("{}", Colour::Yellow.bold().paint(format!("⚠️  Stuck for {}", human_duration(stuck_duration))));
                         println!("Last iteration: {}", last_iteration);
                     }
                     SessionState::Paused => {
                         println!("{}", Colour::Yellow.bold().paint("⏸️  Paused"));
                     }
+                    SessionState::Queued if !session.config.depends_on.is_empty() => {
+                        let after: Vec<&str> = session.config.depends_on.iter().map(|id| id.0.as_str()).collect();
+                        println!("{}", Colour::Yellow.bold().paint(format!("⏳ Queued after {}", after.join(", "))));
+                    }
+                    SessionState::Queued => {
+                        println!("{}", Colour::Yellow.bold().paint("⏳ Queued for a free slot"));
+                    }
                     _ => {}
                 }
+
+                for issue in &branch_issues {
+                    println!("{}", Colour::Yellow.bold().paint(format!("⚠️  Branch {}", issue)));
+                }
             } else {
-                eprintln!("Session not found: {}", id);
+                error!("session not found: {}", id);
                 std::process::exit(1);
             }
         }
@@ -378,7 +595,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             match orch.attach(&SessionId(id)) {
                 Ok(_) => unreachable!(), // exec replaces process
                 Err(e) => {
-                    eprintln!("❌ Failed to attach: {}", e);
+                    error!("failed to attach: {}", e);
                     std::process::exit(1);
                 }
             }
@@ -388,7 +605,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             match orch.pause(&SessionId(id)) {
                 Ok(_) => println!("⏸️  Paused"),
                 Err(e) => {
-                    eprintln!("❌ Failed to pause: {}", e);
+                    error!("failed to pause: {}", e);
                     std::process::exit(1);
                 }
             }
@@ -398,7 +615,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             match orch.resume(&SessionId(id)) {
                 Ok(_) => println!("▶️  Resumed"),
                 Err(e) => {
-                    eprintln!("❌ Failed to resume: {}", e);
+                    error!("failed to resume: {}", e);
                     std::process::exit(1);
                 }
             }
@@ -408,23 +625,177 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             match orch.inject(&SessionId(id), &message) {
                 Ok(_) => println!("💉 Injected message for next iteration"),
                 Err(e) => {
-                    eprintln!("❌ Failed to inject: {}", e);
+                    error!("failed to inject: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        Commands::RetryBlocked { id, message, message_file, reset_iteration_budget } => {
+            let guidance = match (message, message_file) {
+                (Some(text), _) => Some(text),
+                (None, Some(path)) => Some(fs::read_to_string(&path).unwrap_or_else(|e| {
+                    error!("failed to read {}: {}", path.display(), e);
+                    std::process::exit(1);
+                })),
+                (None, None) => None,
+            };
+            match orch.retry_blocked(&SessionId(id), guidance.as_deref(), reset_iteration_budget) {
+                Ok(outcome) => {
+                    println!("▶️  Unblocked at iteration {}/{}: {}",
+                        outcome.iteration, outcome.max_iterations, outcome.guidance_preview);
+                    if outcome.iteration >= outcome.max_iterations {
+                        warn!("the session has no iterations left and will stop; pass --reset-iteration-budget N to give it more");
+                    }
+                }
+                Err(e) if e.downcast_ref::<NotBlocked>().is_some() => {
+                    error!("{}", e);
+                    std::process::exit(2);
+                }
+                Err(e) => {
+                    error!("failed to retry: {:#}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        Commands::Inbox { json } => {
+            let entries = match orch.inbox() {
+                Ok(entries) => entries,
+                Err(e) => {
+                    error!("failed to read questions: {:#}", e);
+                    std::process::exit(1);
+                }
+            };
+            if json {
+                println!("{}", serde_json::to_string_pretty(&entries)?);
+            } else if entries.is_empty() {
+                println!("No unanswered questions");
+            } else {
+                println!("{:<10} {:<12} {:<16} QUESTION", "ID", "SESSION", "ASKED");
+                for entry in &entries {
+                    println!("{:<10} {:<12} {:<16} {}",
+                        entry.short_id(),
+                        entry.session,
+                        relative_time(entry.asked_at.into()),
+                        entry.question.question
+                    );
+                }
+            }
+        }
+
+        Commands::Answer { question_id, text, file } => {
+            let text = match (text, file) {
+                (Some(text), _) => text,
+                (None, Some(path)) => fs::read_to_string(&path).unwrap_or_else(|e| {
+                    error!("failed to read {}: {}", path.display(), e);
+                    std::process::exit(1);
+                }),
+                (None, None) => unreachable!("clap requires text or --file"),
+            };
+            match orch.answer(&question_id, text.trim_end()) {
+                Ok((entry, Delivery::Channel { channel })) => {
+                    println!("💬 Answered {} on {}", entry.short_id(), channel);
+                }
+                Ok((entry, Delivery::Inject { session })) => {
+                    println!("💉 Answered {} via inject for session {}", entry.short_id(), session);
+                }
+                Err(e) => {
+                    error!("failed to answer: {:#}", e);
                     std::process::exit(1);
                 }
             }
         }
 
+        Commands::Rename { id, branch, keep_path, force } => {
+            match orch.rename(&SessionId(id), &branch, keep_path, force) {
+                Ok(_) => println!("🏷️  Renamed branch to {}", branch),
+                Err(e) => {
+                    error!("failed to rename: {:#}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        Commands::Events { follow, since, types, cursor_file, trace } => {
+            let since = match since {
+                Some(s) => {
+                    let secs = parse_duration(&s).unwrap_or_else(|e| {
+                        error!("invalid --since '{}': {}. Use e.g., 1h, 30m", s, e);
+                        std::process::exit(1);
+                    });
+                    Some(chrono::Utc::now() - chrono::Duration::seconds(secs as i64))
+                }
+                None => None,
+            };
+
+            let mut follower = match EventFollower::new(orch.journal(), since, types, cursor_file) {
+                Ok(f) => f.with_trace(trace),
+                Err(e) => {
+                    error!("{:#}", e);
+                    std::process::exit(1);
+                }
+            };
+
+            let stdout = std::io::stdout();
+            loop {
+                // Refreshing session state records transitions made by agents
+                if follow {
+                    orch.list();
+                }
+
+                for event in follower.poll()? {
+                    let mut out = stdout.lock();
+                    // Consumer went away (e.g. `| head`): stop quietly
+                    if writeln!(out, "{}", serde_json::to_string(&event)?).is_err() || out.flush().is_err() {
+                        return Ok(());
+                    }
+                }
+
+                if !follow {
+                    break;
+                }
+                std::thread::sleep(std::time::Duration::from_secs(1));
+            }
+        }
+
         Commands::Kill { id, reason } => {
             let reason = reason.as_deref().unwrap_or("user request");
             match orch.kill(&SessionId(id), reason) {
                 Ok(_) => println!("💀 Killed"),
                 Err(e) => {
-                    eprintln!("❌ Failed to kill: {}", e);
+                    error!("failed to kill: {}", e);
                     std::process::exit(1);
                 }
             }
         }
+
+        Commands::Simulate { .. } => unreachable!("handled before connecting"),
     }
 
     Ok(())
 }
+
+/// Run a scenario file and print its timeline; exits non-zero on failure
+fn simulate(path: &std::path::Path) {
+    let report = match Scenario::load(path).and_then(|scenario| Simulator::run(&scenario)) {
+        Ok(report) => report,
+        Err(e) => {
+            error!("{:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    println!("{}", Colour::White.bold().paint(&report.name));
+    for line in &report.log {
+        println!("  {}", Colour::White.dimmed().paint(line));
+    }
+    if report.passed() {
+        println!("✅ All expectations met");
+        return;
+    }
+    for failure in &report.failures {
+        println!("❌ {}", failure);
+    }
+    std::process::exit(1);
+}
@@ -0,0 +1,253 @@
+//! Shared logging setup for the Hydra binaries.
+//!
+//! Every binary takes the same `-q/--quiet` and `-v/--verbose` flags and
+//! routes progress and diagnostics through `tracing` to stderr. Results a
+//! user or script asked for (tables, JSON, status) are printed to stdout
+//! directly and don't depend on the verbosity.
+//!
+//! | Flag      | Level | Shows                                             |
+//! |-----------|-------|---------------------------------------------------|
+//! | `-q`      | error | errors only                                       |
+//! | (none)    | info  | progress lines and warnings                       |
+//! | `-v`      | debug | external commands, config decisions, file writes  |
+//!
+//! `RUST_LOG`, when set, overrides the flags.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::process::{Command, ExitStatus, Output};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use tracing::{debug, Event, Level, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, MakeWriter};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::EnvFilter;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Verbosity {
+    Quiet,
+    #[default]
+    Normal,
+    Verbose,
+}
+
+impl Verbosity {
+    fn directive(self) -> &'static str {
+        match self {
+            Self::Quiet => "error",
+            Self::Normal => "info",
+            Self::Verbose => "debug",
+        }
+    }
+}
+
+/// Global verbosity flags; `#[command(flatten)]` them into a CLI
+#[derive(clap::Args, Debug, Clone, Copy, Default)]
+pub struct VerbosityArgs {
+    /// Only print errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Print debug detail: external commands, config decisions, file writes
+    #[arg(short, long, global = true)]
+    pub verbose: bool,
+}
+
+impl VerbosityArgs {
+    pub fn level(&self) -> Verbosity {
+        if self.quiet {
+            Verbosity::Quiet
+        } else if self.verbose {
+            Verbosity::Verbose
+        } else {
+            Verbosity::Normal
+        }
+    }
+}
+
+/// Install the stderr subscriber for a binary. `RUST_LOG` overrides the
+/// verbosity. Calling it twice is harmless.
+pub fn init(verbosity: Verbosity) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(verbosity.directive()));
+    let _ = builder(filter, io::stderr).try_init();
+}
+
+/// A subscriber writing into an in-memory buffer, for tests. Ignores `RUST_LOG`.
+pub fn capture(verbosity: Verbosity) -> (impl Subscriber + Send + Sync, Capture) {
+    let buffer = Capture::default();
+    let subscriber = builder(EnvFilter::new(verbosity.directive()), buffer.clone()).finish();
+    (subscriber, buffer)
+}
+
+fn builder<W>(filter: EnvFilter, writer: W) -> tracing_subscriber::fmt::SubscriberBuilder<
+    tracing_subscriber::fmt::format::DefaultFields,
+    Plain,
+    EnvFilter,
+    W,
+>
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .event_format(Plain)
+        .with_writer(writer)
+}
+
+/// Line format for a terminal: info lines as-is, everything else prefixed
+/// with its level the way the binaries printed them before
+struct Plain;
+
+impl<S, N> FormatEvent<S, N> for Plain
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(&self, ctx: &FmtContext<'_, S, N>, mut writer: Writer<'_>, event: &Event<'_>) -> fmt::Result {
+        match *event.metadata().level() {
+            Level::ERROR => write!(writer, "error: ")?,
+            Level::WARN => write!(writer, "warning: ")?,
+            Level::INFO => {}
+            _ => write!(writer, "debug: ")?,
+        }
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
+
+/// Shared buffer that collects formatted log lines
+#[derive(Clone, Default)]
+pub struct Capture(Arc<Mutex<Vec<u8>>>);
+
+impl Capture {
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().expect("capture lock poisoned")).into_owned()
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().expect("capture lock poisoned").extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for Capture {
+    type Writer = Capture;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+/// `Command::output`/`status` that log the command line, working
+/// directory, duration and exit status at debug level
+pub trait LoggedCommand {
+    fn logged_output(&mut self) -> io::Result<Output>;
+    fn logged_status(&mut self) -> io::Result<ExitStatus>;
+}
+
+impl LoggedCommand for Command {
+    fn logged_output(&mut self) -> io::Result<Output> {
+        let started = Instant::now();
+        let output = self.output();
+        log_finished(self, started, output.as_ref().map(|o| o.status));
+        output
+    }
+
+    fn logged_status(&mut self) -> io::Result<ExitStatus> {
+        let started = Instant::now();
+        let status = self.status();
+        log_finished(self, started, status.as_ref().copied());
+        status
+    }
+}
+
+fn log_finished(cmd: &Command, started: Instant, status: Result<ExitStatus, &io::Error>) {
+    let elapsed = started.elapsed().as_millis();
+    let line = command_line(cmd);
+    let dir = cmd.get_current_dir().map(|d| format!(" (in {})", d.display())).unwrap_or_default();
+    match status {
+        Ok(status) => match status.code() {
+            Some(code) => debug!("$ {}{} -> exit {} in {}ms", line, dir, code, elapsed),
+            None => debug!("$ {}{} -> killed by signal in {}ms", line, dir, elapsed),
+        },
+        Err(e) => debug!("$ {}{} -> failed to start: {}", line, dir, e),
+    }
+}
+
+/// The command as it would be typed, quoting arguments with spaces
+pub fn command_line(cmd: &Command) -> String {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| {
+            let arg = arg.to_string_lossy();
+            if arg.is_empty() || arg.contains(char::is_whitespace) {
+                format!("'{}'", arg)
+            } else {
+                arg.into_owned()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use tracing::{error, info, warn};
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        verbosity: VerbosityArgs,
+        #[arg(long)]
+        name: Option<String>,
+    }
+
+    fn emit_all() {
+        debug!("detail");
+        info!("progress");
+        warn!("careful");
+        error!("broken");
+    }
+
+    fn logged(verbosity: Verbosity) -> String {
+        let (subscriber, buffer) = capture(verbosity);
+        tracing::subscriber::with_default(subscriber, emit_all);
+        buffer.contents()
+    }
+
+    #[test]
+    fn test_levels() {
+        assert_eq!(logged(Verbosity::Quiet), "error: broken\n");
+        assert_eq!(logged(Verbosity::Normal), "progress\nwarning: careful\nerror: broken\n");
+        assert_eq!(logged(Verbosity::Verbose), "debug: detail\nprogress\nwarning: careful\nerror: broken\n");
+    }
+
+    #[test]
+    fn test_flags() {
+        let level = |args: &[&str]| Cli::try_parse_from(args).map(|cli| cli.verbosity.level());
+        assert_eq!(level(&["x"]).unwrap(), Verbosity::Normal);
+        assert_eq!(level(&["x", "-q"]).unwrap(), Verbosity::Quiet);
+        assert_eq!(level(&["x", "--verbose", "--name", "a"]).unwrap(), Verbosity::Verbose);
+        assert!(level(&["x", "-q", "-v"]).is_err());
+    }
+
+    #[test]
+    fn test_logged_command() {
+        let (subscriber, buffer) = capture(Verbosity::Verbose);
+        tracing::subscriber::with_default(subscriber, || {
+            Command::new("sh").args(["-c", "exit 3"]).current_dir("/").logged_output().unwrap();
+        });
+        let out = buffer.contents();
+        assert!(out.starts_with("debug: $ sh -c 'exit 3' (in /) -> exit 3 in "), "{}", out);
+    }
+}
@@ -5,8 +5,9 @@
 //! - <5ms latency for message delivery
 //! - High throughput for broadcast operations
 
-use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput, BenchmarkId};
-use hydra_mail::channels::{emit_and_store, subscribe_broadcast, get_or_create_broadcast_tx};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput, BenchmarkId};
+use hydra_mail::channels::{emit_and_store, history, subscribe_broadcast, get_or_create_broadcast_tx, HistoryFilter};
+use std::hint::black_box;
 use uuid::Uuid;
 use tokio::runtime::Runtime;
 
@@ -254,6 +255,57 @@ fn bench_concurrent_emits(c: &mut Criterion) {
     group.finish();
 }
 
+/// Benchmark emits to distinct channels of one project, each task on its
+/// own channel, while as many tasks read histories: the channels contend only
+/// on the project's channel map
+fn bench_distinct_channels(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let uuid = Uuid::new_v4();
+    let message = r#"{"action":"test"}"#.to_string();
+
+    let mut group = c.benchmark_group("distinct_channels");
+
+    for num_tasks in [2, 4, 8, 16].iter() {
+        let topics: Vec<String> = (0..*num_tasks).map(|i| format!("bench:distinct:{}", i)).collect();
+        // Histories to read from the start
+        rt.block_on(async {
+            for topic in &topics {
+                for _ in 0..50 {
+                    emit_and_store(uuid, topic, message.clone()).await;
+                }
+            }
+        });
+
+        group.bench_with_input(
+            BenchmarkId::from_parameter(num_tasks),
+            &topics,
+            |b, topics| {
+                b.to_async(&rt).iter(|| async {
+                    let mut handles = Vec::new();
+                    for topic in topics {
+                        let (emit_topic, msg) = (topic.clone(), message.clone());
+                        handles.push(tokio::spawn(async move {
+                            for _ in 0..10 {
+                                emit_and_store(uuid, &emit_topic, msg.clone()).await;
+                            }
+                        }));
+                        let topic = topic.clone();
+                        handles.push(tokio::spawn(async move {
+                            for _ in 0..10 {
+                                black_box(history(uuid, &topic, None, &HistoryFilter::default()).await);
+                            }
+                        }));
+                    }
+                    for handle in handles {
+                        let _ = handle.await;
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
 /// Benchmark multi-project isolation overhead
 fn bench_multi_project(c: &mut Criterion) {
     let rt = Runtime::new().unwrap();
@@ -306,7 +358,7 @@ fn bench_slow_consumer(c: &mut Criterion) {
 
             // Consumer catches up
             let mut count = 0;
-            while let Ok(_) = rx.try_recv() {
+            while rx.try_recv().is_ok() {
                 count += 1;
             }
             black_box(count)
@@ -477,6 +529,38 @@ fn bench_channel_churn(c: &mut Criterion) {
     });
 }
 
+/// Channel churn with the idle collector running: the map stays the size of
+/// one batch instead of growing with every channel ever used
+fn bench_channel_churn_collected(c: &mut Criterion) {
+    use hydra_mail::channels::{collect_idle_channels, list_channels};
+    use std::time::Duration;
+
+    let rt = Runtime::new().unwrap();
+    let uuid = Uuid::new_v4();
+    let message = r#"{"data":"test"}"#.to_string();
+    let mut batch = 0u64;
+
+    c.bench_function("channel_churn_collected_100", |b| {
+        b.to_async(&rt).iter(|| {
+            batch += 1;
+            let message = message.clone();
+            async move {
+                for i in 0..100 {
+                    let topic = format!("temp:{}:{}", batch, i);
+                    let (mut rx, _) = subscribe_broadcast(uuid, &topic).await;
+                    emit_and_store(uuid, &topic, message.clone()).await;
+                    let _ = rx.recv().await;
+                }
+                // Every channel is idle by now with a zero TTL
+                black_box(collect_idle_channels(Duration::ZERO).await);
+            }
+        })
+    });
+
+    let left = rt.block_on(list_channels(uuid)).len();
+    assert_eq!(left, 0, "{} channels left after {} batches of churn", left, batch);
+}
+
 criterion_group!(
     benches,
     bench_emit_latency,
@@ -488,6 +572,7 @@ criterion_group!(
     bench_message_sizes,
     bench_json_serialization,
     bench_concurrent_emits,
+    bench_distinct_channels,
     bench_multi_project,
     bench_slow_consumer,
     bench_burst_throughput,
@@ -495,6 +580,7 @@ criterion_group!(
     bench_encoding_formats,
     bench_realistic_patterns,
     bench_channel_churn,
+    bench_channel_churn_collected,
 );
 
 criterion_main!(benches);
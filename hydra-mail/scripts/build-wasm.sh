@@ -0,0 +1,25 @@
+#!/usr/bin/env bash
+# Build the hydra-mail pulse layer as a JS package (pkg/), or test it.
+#
+#   scripts/build-wasm.sh [web|bundler|nodejs]   # default: web
+#   scripts/build-wasm.sh test                   # wasm-pack test --node
+#
+# Only the `pulse` module and the wasm exports are compiled; the daemon,
+# socket and filesystem code is left out of wasm32 builds.
+
+set -euo pipefail
+
+cd "$(dirname "${BASH_SOURCE[0]}")/.."
+
+if ! command -v wasm-pack >/dev/null; then
+    echo "wasm-pack not found (cargo install wasm-pack)" >&2
+    exit 1
+fi
+rustup target add wasm32-unknown-unknown >/dev/null
+
+if [ "${1:-}" = "test" ]; then
+    exec wasm-pack test --node -- --features wasm --test wasm
+fi
+
+wasm-pack build --target "${1:-web}" --out-dir pkg --out-name hydra_mail -- --features wasm
+echo "JS package written to $(pwd)/pkg"
@@ -0,0 +1,448 @@
+//! Daemon-side SQLite archive of every message.
+//!
+//! The emit path only calls [`ArchiveSink::record`], a `try_send` onto a
+//! bounded queue, so it never waits on disk. A dedicated writer thread drains
+//! the queue in batches into a WAL-mode database and is its only writer. When
+//! the queue is full the message is left out of the archive (it is still
+//! delivered) and counted as dropped in [`ArchiveStats`].
+//!
+//! Readers (`hydra-mail query`, datasette, sqlite3) open the file read-only.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Connection, OpenFlags};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use crate::pulse::decode_pulse;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS messages (
+    seq       INTEGER PRIMARY KEY AUTOINCREMENT,
+    channel   TEXT NOT NULL,
+    type      TEXT,
+    timestamp TEXT NOT NULL,
+    priority  TEXT,
+    sender    TEXT,
+    trace_id  TEXT,
+    payload   TEXT,
+    raw_size  INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS messages_channel_timestamp ON messages (channel, timestamp);
+CREATE INDEX IF NOT EXISTS messages_trace_id ON messages (trace_id);
+";
+
+/// Most messages written per transaction
+const WRITE_BATCH: usize = 256;
+
+/// Fraction of rows removed per pruning pass once over the size cap
+const PRUNE_FRACTION: f64 = 0.1;
+
+/// Timestamps are stored as fixed-width RFC 3339 so text order is time order
+fn format_timestamp(ts: DateTime<Utc>) -> String {
+    ts.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// A message accepted by the daemon, still TOON-encoded
+#[derive(Debug, Clone)]
+pub struct ArchiveRecord {
+    pub channel: String,
+    pub timestamp: DateTime<Utc>,
+    pub message: String,
+}
+
+/// Counters reported by the daemon's `stats` command
+#[derive(Debug, Default)]
+pub struct ArchiveStats {
+    written: AtomicU64,
+    dropped: AtomicU64,
+    errors: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ArchiveStatsSnapshot {
+    pub written: u64,
+    pub dropped: u64,
+    pub errors: u64,
+}
+
+impl ArchiveStats {
+    pub fn snapshot(&self) -> ArchiveStatsSnapshot {
+        ArchiveStatsSnapshot {
+            written: self.written.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Producer side, cheap to clone into the emit path
+#[derive(Clone)]
+pub struct ArchiveSink {
+    tx: mpsc::Sender<ArchiveRecord>,
+    stats: Arc<ArchiveStats>,
+}
+
+impl ArchiveSink {
+    /// Queue a message; drops it (and counts the drop) if the writer is behind
+    pub fn record(&self, channel: &str, message: &str) {
+        let record = ArchiveRecord {
+            channel: channel.to_string(),
+            timestamp: Utc::now(),
+            message: message.to_string(),
+        };
+        if self.tx.try_send(record).is_err() {
+            self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn stats(&self) -> ArchiveStatsSnapshot {
+        self.stats.snapshot()
+    }
+}
+
+/// Queue between the sink and the writer
+pub fn queue(capacity: usize) -> (ArchiveSink, mpsc::Receiver<ArchiveRecord>) {
+    let (tx, rx) = mpsc::channel(capacity.max(1));
+    (ArchiveSink { tx, stats: Arc::default() }, rx)
+}
+
+/// Open the database at `path` and start its writer thread. The thread exits
+/// once every sink clone is dropped and the queue is drained.
+pub fn start(path: &Path, max_size_bytes: u64, queue_capacity: usize) -> Result<(ArchiveSink, JoinHandle<()>)> {
+    let archive = Archive::open(path)?;
+    let (sink, rx) = queue(queue_capacity);
+    let stats = sink.stats.clone();
+    let handle = std::thread::Builder::new()
+        .name("hydra-archive".to_string())
+        .spawn(move || run_writer(archive, rx, &stats, max_size_bytes))
+        .context("Failed to start archive writer")?;
+    Ok((sink, handle))
+}
+
+/// Writer loop: block for the next message, then take whatever else is queued
+pub fn run_writer(mut archive: Archive, mut rx: mpsc::Receiver<ArchiveRecord>, stats: &ArchiveStats, max_size_bytes: u64) {
+    let mut batch = Vec::with_capacity(WRITE_BATCH);
+    while let Some(first) = rx.blocking_recv() {
+        batch.push(first);
+        while batch.len() < WRITE_BATCH {
+            match rx.try_recv() {
+                Ok(record) => batch.push(record),
+                Err(_) => break,
+            }
+        }
+
+        match archive.insert(&batch) {
+            Ok(()) => {
+                stats.written.fetch_add(batch.len() as u64, Ordering::Relaxed);
+            }
+            Err(e) => {
+                stats.errors.fetch_add(batch.len() as u64, Ordering::Relaxed);
+                warn!("failed to archive {} messages: {:#}", batch.len(), e);
+            }
+        }
+        batch.clear();
+
+        if let Err(e) = archive.prune(max_size_bytes) {
+            warn!("failed to prune archive: {:#}", e);
+        }
+    }
+}
+
+/// Write handle on the archive database
+pub struct Archive {
+    conn: Connection,
+}
+
+impl Archive {
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path)
+            .with_context(|| format!("Failed to open archive {}", path.display()))?;
+        conn.pragma_update(None, "journal_mode", "WAL")
+            .context("Failed to enable WAL mode")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")
+            .context("Failed to set synchronous mode")?;
+        conn.execute_batch(SCHEMA).context("Failed to create archive schema")?;
+        Ok(Self { conn })
+    }
+
+    /// Append messages in one transaction, in order
+    pub fn insert(&mut self, records: &[ArchiveRecord]) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT INTO messages (channel, type, timestamp, priority, sender, trace_id, payload, raw_size)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?;
+            for record in records {
+                // Undecodable messages are still archived, just without fields
+                let pulse = decode_pulse(&record.message).ok();
+                let field = |path: &[&str]| {
+                    let mut value = pulse.as_ref()?;
+                    for key in path {
+                        value = value.get(key)?;
+                    }
+                    value.as_str().map(str::to_string)
+                };
+                stmt.execute(params![
+                    record.channel,
+                    field(&["type"]),
+                    format_timestamp(record.timestamp),
+                    field(&["metadata", "priority"]),
+                    field(&["metadata", "sender"]),
+                    field(&["metadata", "trace_id"]),
+                    pulse.as_ref().map(Value::to_string),
+                    record.message.len() as i64,
+                ])?;
+            }
+        }
+        tx.commit().context("Failed to commit archive batch")
+    }
+
+    /// Bytes in use by the database, not counting free pages
+    pub fn used_bytes(&self) -> Result<u64> {
+        let pragma = |name: &str| -> Result<u64> {
+            let value: i64 = self.conn.pragma_query_value(None, name, |row| row.get(0))?;
+            Ok(value.max(0) as u64)
+        };
+        Ok((pragma("page_count")? - pragma("freelist_count")?) * pragma("page_size")?)
+    }
+
+    /// Delete the oldest messages until the data fits in `max_bytes`. Freed
+    /// pages are reused by later inserts rather than returned to the OS.
+    pub fn prune(&mut self, max_bytes: u64) -> Result<usize> {
+        let mut removed = 0;
+        while self.used_bytes()? > max_bytes {
+            let rows: i64 = self.conn.query_row("SELECT COUNT(*) FROM messages", [], |row| row.get(0))?;
+            if rows == 0 {
+                break;
+            }
+            let batch = ((rows as f64 * PRUNE_FRACTION).ceil() as i64).max(1);
+            removed += self.conn.execute(
+                "DELETE FROM messages WHERE seq IN (SELECT seq FROM messages ORDER BY seq LIMIT ?1)",
+                params![batch],
+            )?;
+        }
+        Ok(removed)
+    }
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Queries
+// ─────────────────────────────────────────────────────────────────────────────
+
+/// Open an archive without write access, safe alongside the running daemon
+pub fn open_read_only(path: &Path) -> Result<Connection> {
+    if !path.exists() {
+        bail!("No archive at {} (is [archive] sqlite set and the daemon running?)", path.display());
+    }
+    Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX)
+        .with_context(|| format!("Failed to open archive {}", path.display()))
+}
+
+/// Run arbitrary SQL, returning one JSON object per row
+pub fn query_sql(conn: &Connection, sql: &str) -> Result<Vec<Map<String, Value>>> {
+    let mut stmt = conn.prepare(sql).context("Invalid query")?;
+    let columns: Vec<String> = stmt.column_names().into_iter().map(str::to_string).collect();
+    let mut rows = stmt.query([])?;
+    let mut out = Vec::new();
+    while let Some(row) = rows.next()? {
+        let mut object = Map::new();
+        for (i, column) in columns.iter().enumerate() {
+            let value = match row.get_ref(i)? {
+                ValueRef::Null => Value::Null,
+                ValueRef::Integer(n) => Value::from(n),
+                ValueRef::Real(f) => Value::from(f),
+                ValueRef::Text(t) => Value::from(String::from_utf8_lossy(t).into_owned()),
+                ValueRef::Blob(b) => Value::from(format!("<{} bytes>", b.len())),
+            };
+            object.insert(column.clone(), value);
+        }
+        out.push(object);
+    }
+    Ok(out)
+}
+
+/// Grouping for the canned `--count-by` query
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CountBy {
+    Channel,
+    Type,
+    Sender,
+    Day,
+}
+
+impl CountBy {
+    fn column(self) -> &'static str {
+        match self {
+            Self::Channel => "channel",
+            Self::Type => "type",
+            Self::Sender => "sender",
+            Self::Day => "substr(timestamp, 1, 10)",
+        }
+    }
+}
+
+impl FromStr for CountBy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "channel" => Ok(Self::Channel),
+            "type" => Ok(Self::Type),
+            "sender" => Ok(Self::Sender),
+            "day" => Ok(Self::Day),
+            _ => bail!("Unknown grouping '{}' (expected channel, type, sender or day)", s),
+        }
+    }
+}
+
+/// Message counts per group, most frequent first
+pub fn count_by(conn: &Connection, by: CountBy, since: Option<DateTime<Utc>>) -> Result<Vec<(Option<String>, u64)>> {
+    let sql = format!(
+        "SELECT {col} AS key, COUNT(*) FROM messages WHERE timestamp >= ?1 GROUP BY key ORDER BY COUNT(*) DESC, key",
+        col = by.column()
+    );
+    let since = since.map(format_timestamp).unwrap_or_default();
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map(params![since], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as u64)))?;
+    rows.collect::<rusqlite::Result<_>>().context("Failed to count messages")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pulse::{encode_pulse, new_pulse};
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    fn pulse(r#type: &str, channel: &str, metadata: Value) -> String {
+        encode_pulse(&new_pulse(r#type, channel, json!({"n": 1}), metadata)).unwrap()
+    }
+
+    fn record(channel: &str, timestamp: DateTime<Utc>, message: String) -> ArchiveRecord {
+        ArchiveRecord { channel: channel.to_string(), timestamp, message }
+    }
+
+    #[test]
+    fn test_writer_preserves_order() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("mail.db");
+        let (sink, handle) = start(&path, u64::MAX, 1024).unwrap();
+
+        for i in 0..300 {
+            sink.record("repo:delta", &pulse(&format!("t{}", i), "repo:delta", json!({"trace_id": "wf-1"})));
+        }
+        let stats = sink.stats.clone();
+        drop(sink);
+        handle.join().unwrap();
+        assert_eq!(stats.snapshot(), ArchiveStatsSnapshot { written: 300, dropped: 0, errors: 0 });
+
+        let conn = open_read_only(&path).unwrap();
+        let rows = query_sql(&conn, "SELECT seq, type, trace_id FROM messages ORDER BY seq").unwrap();
+        let types: Vec<&str> = rows.iter().map(|r| r["type"].as_str().unwrap()).collect();
+        let expected: Vec<String> = (0..300).map(|i| format!("t{}", i)).collect();
+        assert_eq!(types, expected);
+        assert!(rows.iter().all(|r| r["trace_id"] == "wf-1"));
+    }
+
+    #[test]
+    fn test_overflow_is_counted_not_blocking() {
+        let dir = TempDir::new().unwrap();
+        let (sink, rx) = queue(4);
+
+        // Writer not running yet: everything past the queue capacity drops
+        for i in 0..10 {
+            sink.record("repo:delta", &pulse(&format!("t{}", i), "repo:delta", Value::Null));
+        }
+        assert_eq!(sink.stats().dropped, 6);
+
+        let archive = Archive::open(&dir.path().join("mail.db")).unwrap();
+        let stats = sink.stats.clone();
+        drop(sink);
+        run_writer(archive, rx, &stats, u64::MAX);
+        assert_eq!(stats.snapshot(), ArchiveStatsSnapshot { written: 4, dropped: 6, errors: 0 });
+
+        let conn = open_read_only(&dir.path().join("mail.db")).unwrap();
+        let kept = query_sql(&conn, "SELECT type FROM messages ORDER BY seq").unwrap();
+        let kept: Vec<&str> = kept.iter().map(|r| r["type"].as_str().unwrap()).collect();
+        assert_eq!(kept, vec!["t0", "t1", "t2", "t3"]);
+    }
+
+    #[test]
+    fn test_retention_prunes_oldest() {
+        let dir = TempDir::new().unwrap();
+        let mut archive = Archive::open(&dir.path().join("mail.db")).unwrap();
+        let big = json!({"blob": "x".repeat(2000)});
+        let records: Vec<_> = (0..500)
+            .map(|i| {
+                let message = encode_pulse(&new_pulse(&format!("t{}", i), "repo:delta", big.clone(), Value::Null)).unwrap();
+                record("repo:delta", Utc::now(), message)
+            })
+            .collect();
+        archive.insert(&records).unwrap();
+        assert!(archive.used_bytes().unwrap() > 1_000_000);
+
+        let removed = archive.prune(256 * 1024).unwrap();
+        assert!(removed > 0);
+        assert!(archive.used_bytes().unwrap() <= 256 * 1024);
+
+        let rows = query_sql(&archive.conn, "SELECT MIN(seq) AS first, MAX(seq) AS last, COUNT(*) AS n FROM messages").unwrap();
+        assert_eq!(rows[0]["last"], 500, "newest message kept");
+        assert_eq!(rows[0]["first"], removed as i64 + 1, "oldest removed first");
+        assert_eq!(rows[0]["n"], 500 - removed as i64);
+
+        // Under the cap nothing happens
+        assert_eq!(archive.prune(u64::MAX).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_count_by_channel_since() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("mail.db");
+        let mut archive = Archive::open(&path).unwrap();
+        let day = |d: u32| DateTime::parse_from_rfc3339(&format!("2026-10-{:02}T12:00:00Z", d)).unwrap().with_timezone(&Utc);
+        let seed = [
+            ("repo:delta", 1), ("repo:delta", 10), ("repo:delta", 11), ("repo:delta", 12),
+            ("team:alert", 2), ("team:alert", 11),
+            ("agent:presence", 12),
+        ];
+        let records: Vec<_> = seed
+            .iter()
+            .map(|(channel, d)| record(channel, day(*d), pulse("delta", channel, Value::Null)))
+            .collect();
+        archive.insert(&records).unwrap();
+
+        let conn = open_read_only(&path).unwrap();
+        let counts = count_by(&conn, CountBy::Channel, Some(day(9))).unwrap();
+        assert_eq!(counts, vec![
+            (Some("repo:delta".to_string()), 3),
+            (Some("agent:presence".to_string()), 1),
+            (Some("team:alert".to_string()), 1),
+        ]);
+
+        let per_day = count_by(&conn, CountBy::Day, Some(day(11))).unwrap();
+        assert_eq!(per_day, vec![(Some("2026-10-11".to_string()), 2), (Some("2026-10-12".to_string()), 2)]);
+
+        // Read-only really is read-only
+        assert!(conn.execute("DELETE FROM messages", []).is_err());
+    }
+
+    #[test]
+    fn test_undecodable_message_still_archived() {
+        let dir = TempDir::new().unwrap();
+        let mut archive = Archive::open(&dir.path().join("mail.db")).unwrap();
+        archive.insert(&[record("raw", Utc::now(), "not: [valid".to_string())]).unwrap();
+        let rows = query_sql(&archive.conn, "SELECT type, payload, raw_size FROM messages").unwrap();
+        assert_eq!(rows[0]["raw_size"], 11);
+        assert_eq!(rows[0]["type"], Value::Null);
+    }
+}
@@ -1,48 +1,536 @@
-use std::collections::{HashMap, VecDeque};
-use std::sync::{Arc, LazyLock, Mutex};
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::ops::Deref;
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard, RwLock};
+use std::time::{Duration, Instant};
 use tokio::sync::broadcast;
 use uuid::Uuid;
-use crate::constants::{REPLAY_BUFFER_CAPACITY, BROADCAST_CHANNEL_CAPACITY};
+use crate::constants::{REPLAY_BUFFER_CAPACITY, REPLAY_MAX_BYTES_PER_CHANNEL, BROADCAST_CHANNEL_CAPACITY};
 use std::path::PathBuf;
+use crate::config::ChannelPolicy;
+
+/// A message with the agent it is addressed to, `None` for everyone
+pub type TargetedMessage = (Option<String>, String);
+
+/// A message as subscribers receive it, after its sequence number on its
+/// channel: 1 for the channel's first message, one up for each emit after
+pub type Sequenced<T> = (u64, T);
+
+/// Whether a message addressed to `target` goes to a subscriber that
+/// named itself `agent`. Untargeted messages go to everyone, and so does
+/// everything to a subscriber that names no agent, like a monitor.
+pub fn delivers_to(target: Option<&str>, agent: Option<&str>) -> bool {
+    match (target, agent) {
+        (Some(target), Some(agent)) => target == agent,
+        _ => true,
+    }
+}
 
-/// Stores the last N messages per channel for late subscribers.
+/// Stores the last N messages per channel for late subscribers, each with
+/// the time it was emitted.
 /// Uses a ring buffer (VecDeque) to maintain constant memory usage.
-/// Default capacity: 100 messages per channel.
+/// Default capacity: 100 messages and 1MB of message text per channel.
 struct ReplayBuffer {
-    messages: VecDeque<String>,
+    messages: VecDeque<(DateTime<Utc>, TargetedMessage)>,
     capacity: usize,
+    /// Budget for the text of the buffered messages together, 0 for none
+    max_bytes: usize,
+    /// Text of the buffered messages together, in bytes
+    bytes: usize,
 }
 
 impl ReplayBuffer {
-    fn new(capacity: usize) -> Self {
+    fn new(capacity: usize, max_bytes: usize) -> Self {
         Self {
             messages: VecDeque::with_capacity(capacity),
             capacity,
+            max_bytes,
+            bytes: 0,
         }
     }
 
-    fn push(&mut self, msg: String) {
-        if self.messages.len() >= self.capacity {
-            self.messages.pop_front();
+    /// Buffer a message, evicting the oldest until both the count and the
+    /// byte budget are met. The newest always stays, even alone over budget.
+    fn push(&mut self, at: DateTime<Utc>, msg: TargetedMessage) {
+        self.bytes += msg.1.len();
+        self.messages.push_back((at, msg));
+        self.evict();
+    }
+
+    /// Change the byte budget, evicting at once if the buffer is over it
+    fn set_max_bytes(&mut self, max_bytes: usize) {
+        self.max_bytes = max_bytes;
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.messages.len() > 1 && (self.messages.len() > self.capacity || self.over_budget()) {
+            self.pop_front();
+        }
+    }
+
+    fn over_budget(&self) -> bool {
+        self.max_bytes > 0 && self.bytes > self.max_bytes
+    }
+
+    fn pop_front(&mut self) {
+        if let Some((_, (_, message))) = self.messages.pop_front() {
+            self.bytes -= message.len();
         }
-        self.messages.push_back(msg);
     }
 
-    fn get_all(&self) -> Vec<String> {
-        self.messages.iter().cloned().collect()
+    fn clear(&mut self) {
+        self.messages.clear();
+        self.bytes = 0;
+    }
+
+    /// Drop the messages emitted before `cutoff`, returning how many went
+    fn trim_before(&mut self, cutoff: DateTime<Utc>) -> usize {
+        let before = self.messages.len();
+        self.messages.retain(|(at, _)| *at >= cutoff);
+        self.bytes = self.messages.iter().map(|(_, (_, message))| message.len()).sum();
+        before - self.messages.len()
+    }
+
+    /// The buffered messages for `agent` that `filter` lets through, oldest
+    /// first, leaving out those emitted before `cutoff`
+    fn get(&self, filter: &HistoryFilter, agent: Option<&str>, cutoff: Option<DateTime<Utc>>) -> Vec<String> {
+        filter.apply(
+            self.messages
+                .iter()
+                .filter(|(at, (target, _))| !is_expired(*at, cutoff) && delivers_to(target.as_deref(), agent))
+                .map(|(at, (_, message))| (*at, message.clone())),
+        )
+    }
+
+    /// Buffered messages emitted before `cutoff`
+    fn expired(&self, cutoff: Option<DateTime<Utc>>) -> usize {
+        self.messages.iter().filter(|(at, _)| is_expired(*at, cutoff)).count()
+    }
+}
+
+fn is_expired(at: DateTime<Utc>, cutoff: Option<DateTime<Utc>>) -> bool {
+    cutoff.is_some_and(|cutoff| at < cutoff)
+}
+
+/// How long a project's buffered messages are replayed: `limits.replay_ttl_secs`
+/// and its per-channel overrides, in seconds with 0 for good
+#[derive(Debug, Clone, Default)]
+struct ReplayTtls {
+    default: u64,
+    channels: BTreeMap<String, u64>,
+}
+
+impl ReplayTtls {
+    /// The emit time before which messages of `channel` have expired at
+    /// `now`, `None` if they never do
+    fn cutoff(&self, channel: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let secs = most_specific(&self.channels, channel).copied().unwrap_or(self.default);
+        if secs == 0 {
+            return None;
+        }
+        now.checked_sub_signed(chrono::Duration::try_seconds(i64::try_from(secs).ok()?)?)
+    }
+}
+
+/// Which part of a channel's replay history a new subscriber gets; the
+/// default is all of it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HistoryFilter {
+    /// Only the newest this many messages
+    pub last: Option<usize>,
+    /// Only messages emitted at or after this time
+    pub since: Option<DateTime<Utc>>,
+}
+
+impl HistoryFilter {
+    /// No history at all, only live messages
+    pub fn none() -> Self {
+        Self { last: Some(0), since: None }
+    }
+
+    /// The messages of a history, oldest first, that pass the filter
+    fn apply<T>(&self, history: impl Iterator<Item = (DateTime<Utc>, T)>) -> Vec<T> {
+        let since: Vec<T> = history
+            .filter(|(at, _)| self.since.is_none_or(|since| *at >= since))
+            .map(|(_, message)| message)
+            .collect();
+        let skip = self.last.map_or(0, |last| since.len().saturating_sub(last));
+        since.into_iter().skip(skip).collect()
     }
 }
 
 type ChannelKey = (Uuid, String);
-type ChannelValue = (broadcast::Sender<String>, ReplayBuffer);
-type ChannelMap = HashMap<ChannelKey, ChannelValue>;
+/// Each channel behind a lock of its own, so the map is only locked to find
+/// or create one and emits to different channels never wait on each other
+type ChannelMap = HashMap<ChannelKey, Arc<Mutex<Channel>>>;
+
+/// Subscribers per agent ID, shared with their receivers so a dropped
+/// receiver can take itself out
+type AgentCounts = Arc<Mutex<HashMap<String, usize>>>;
+
+/// One channel: its subscribers and replay buffer
+struct Channel {
+    /// Subscribers that named no agent; they get every message
+    tx: broadcast::Sender<String>,
+    /// The same, for subscribers that want the sequence numbers
+    seq_tx: broadcast::Sender<Sequenced<String>>,
+    /// Subscribers that named an agent, each keeping the messages for it
+    agent_tx: broadcast::Sender<Sequenced<TargetedMessage>>,
+    agents: AgentCounts,
+    buffer: ReplayBuffer,
+    /// Messages trimmed from the buffer for being past the replay TTL
+    expired: u64,
+    /// Sequence number of the last message emitted, 0 before the first
+    seq: u64,
+    /// Last emit or subscribe, or last time the collector saw a subscriber
+    last_activity: Instant,
+    /// Taken out of the map; whoever got hold of it before looks it up again
+    removed: bool,
+    /// Subscribers of channels folded into this one when their name became
+    /// an alias of it; they keep getting its messages
+    merged: Vec<Forward>,
+}
 
-static BROADCAST_CHANNELS: LazyLock<Arc<tokio::sync::Mutex<ChannelMap>>> =
-    LazyLock::new(|| Arc::new(tokio::sync::Mutex::new(HashMap::new())));
+/// The senders of a channel folded into another, see [`merge_aliased`]
+struct Forward {
+    tx: broadcast::Sender<String>,
+    seq_tx: broadcast::Sender<Sequenced<String>>,
+    agent_tx: broadcast::Sender<Sequenced<TargetedMessage>>,
+    agents: AgentCounts,
+}
+
+impl Forward {
+    fn subscriber_count(&self) -> usize {
+        self.tx.receiver_count() + self.seq_tx.receiver_count() + self.agent_tx.receiver_count()
+    }
+
+    fn recipients(&self, target: Option<&str>) -> usize {
+        match target {
+            Some(target) => {
+                self.tx.receiver_count()
+                    + self.seq_tx.receiver_count()
+                    + self.agents.lock().unwrap().get(target).copied().unwrap_or(0)
+            }
+            None => self.subscriber_count(),
+        }
+    }
+}
+
+impl Channel {
+    fn new(max_bytes: usize) -> Self {
+        Self {
+            tx: broadcast::channel(BROADCAST_CHANNEL_CAPACITY).0,
+            seq_tx: broadcast::channel(BROADCAST_CHANNEL_CAPACITY).0,
+            agent_tx: broadcast::channel(BROADCAST_CHANNEL_CAPACITY).0,
+            agents: AgentCounts::default(),
+            buffer: ReplayBuffer::new(REPLAY_BUFFER_CAPACITY, max_bytes),
+            expired: 0,
+            seq: 0,
+            last_activity: Instant::now(),
+            removed: false,
+            merged: Vec::new(),
+        }
+    }
+
+    /// The channel under `key`, created if missing, locked and marked active
+    fn touch(map: &mut ChannelMap, key: ChannelKey) -> MutexGuard<'_, Self> {
+        let max_bytes = replay_max_bytes(key.0);
+        let mut channel = map.entry(key).or_insert_with(|| Arc::new(Mutex::new(Self::new(max_bytes)))).lock().unwrap();
+        channel.last_activity = Instant::now();
+        channel
+    }
+
+    fn subscriber_count(&self) -> usize {
+        self.tx.receiver_count()
+            + self.seq_tx.receiver_count()
+            + self.agent_tx.receiver_count()
+            + self.merged.iter().map(Forward::subscriber_count).sum::<usize>()
+    }
+
+    /// Subscribers a message addressed to `target` reaches
+    fn recipients(&self, target: Option<&str>) -> usize {
+        let merged = self.merged.iter().map(|forward| forward.recipients(target)).sum::<usize>();
+        let everyone = self.tx.receiver_count() + self.seq_tx.receiver_count();
+        merged + everyone + match target {
+            Some(target) => self.agents.lock().unwrap().get(target).copied().unwrap_or(0),
+            None => self.agent_tx.receiver_count(),
+        }
+    }
+
+    /// Send a message to every subscriber, those of merged channels too
+    fn send(&mut self, seq: u64, target: Option<&str>, message: &str) {
+        self.merged.retain(|forward| forward.subscriber_count() > 0);
+        let senders = self.merged.iter().map(|forward| (&forward.tx, &forward.seq_tx, &forward.agent_tx));
+        for (tx, seq_tx, agent_tx) in std::iter::once((&self.tx, &self.seq_tx, &self.agent_tx)).chain(senders) {
+            if agent_tx.receiver_count() > 0 {
+                let _ = agent_tx.send((seq, (target.map(str::to_string), message.to_string())));
+            }
+            if seq_tx.receiver_count() > 0 {
+                let _ = seq_tx.send((seq, message.to_string()));
+            }
+            let _ = tx.send(message.to_string());
+        }
+    }
+
+    /// Take over the history and subscribers of `other`, which goes out of
+    /// the map: the histories are merged in emit order, and sequence numbers
+    /// carry on from the higher of the two so neither side's subscribers
+    /// see them go back
+    fn absorb(&mut self, other: &mut Channel) {
+        let mut messages: Vec<_> = self.buffer.messages.drain(..).chain(other.buffer.messages.drain(..)).collect();
+        messages.sort_by_key(|(at, _)| *at);
+        self.buffer.clear();
+        other.buffer.clear();
+        for (at, message) in messages {
+            self.buffer.push(at, message);
+        }
+        self.expired += other.expired;
+        self.seq = self.seq.max(other.seq);
+        self.merged.append(&mut other.merged);
+        self.merged.push(Forward {
+            tx: other.tx.clone(),
+            seq_tx: other.seq_tx.clone(),
+            agent_tx: other.agent_tx.clone(),
+            agents: other.agents.clone(),
+        });
+        self.merged.retain(|forward| forward.subscriber_count() > 0);
+        other.removed = true;
+    }
+}
+
+static BROADCAST_CHANNELS: LazyLock<RwLock<ChannelMap>> = LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// Run `f` on the channel under `key`, created if missing, marked active.
+/// The map is only read-locked to find an existing channel and released
+/// before `f` runs, so `f` must not lock the map itself.
+fn with_channel<T>(key: ChannelKey, f: impl FnOnce(&mut Channel) -> T) -> T {
+    let found = BROADCAST_CHANNELS.read().unwrap().get(&key).cloned();
+    if let Some(channel) = found {
+        let mut channel = channel.lock().unwrap();
+        if !channel.removed {
+            channel.last_activity = Instant::now();
+            return f(&mut channel);
+        }
+    }
+    // New, or collected since we looked it up
+    let mut map = BROADCAST_CHANNELS.write().unwrap();
+    let mut channel = Channel::touch(&mut map, key);
+    f(&mut channel)
+}
+
+/// The channels of a project that `pattern` matches, sorted by name
+fn matching(project_uuid: Uuid, pattern: &str) -> Vec<(String, Arc<Mutex<Channel>>)> {
+    channels_where(|uuid, name| uuid == project_uuid && pattern_matches(pattern, name))
+}
+
+/// The channels whose key passes `keep`, sorted by key; the map is unlocked
+/// again by the time the caller locks any of them
+fn channels_where(keep: impl Fn(Uuid, &str) -> bool) -> Vec<(String, Arc<Mutex<Channel>>)> {
+    let mut channels: Vec<_> = BROADCAST_CHANNELS.read().unwrap()
+        .iter()
+        .filter(|((uuid, name), _)| keep(*uuid, name))
+        .map(|((_, name), channel)| (name.clone(), channel.clone()))
+        .collect();
+    channels.sort_by(|a, b| a.0.cmp(&b.0));
+    channels
+}
+
+/// A message and the channel it was stored under, as pattern subscribers get it
+pub type ChannelMessage = (String, String);
+
+/// What goes through a project's pattern sender: channel, sequence number,
+/// target, message
+type PatternMessage = (String, u64, Option<String>, String);
+
+/// Per project, the sender pattern subscribers listen on: every message of
+/// the project goes through it, and each subscriber keeps the channels its
+/// pattern matches. Created by the first pattern subscriber, so projects
+/// without one don't pay for it.
+static PATTERN_CHANNELS: LazyLock<RwLock<HashMap<Uuid, broadcast::Sender<PatternMessage>>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
 
 static MESSAGE_LOG: LazyLock<Arc<Mutex<Option<crate::message_log::MessageLog>>>> =
     LazyLock::new(|| Arc::new(Mutex::new(None)));
 
+/// `[channels.aliases]` per project: old name -> canonical name
+static ALIASES: LazyLock<RwLock<HashMap<Uuid, BTreeMap<String, String>>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// Emits and subscribes that named a channel by an alias, per (project, alias)
+static ALIAS_USES: LazyLock<Mutex<HashMap<ChannelKey, u64>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Replay TTLs per project
+static REPLAY_TTLS: LazyLock<RwLock<HashMap<Uuid, ReplayTtls>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// Channel policies per project
+static CHANNEL_POLICIES: LazyLock<RwLock<HashMap<Uuid, ChannelPolicy>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// Replay buffer byte budgets per project
+static REPLAY_MAX_BYTES: LazyLock<RwLock<HashMap<Uuid, usize>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// Emits turned away by a rate limit, per (project, channel)
+static RATE_LIMITED: LazyLock<Mutex<HashMap<ChannelKey, u64>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// `[[hooks]]` per project
+static HOOKS: LazyLock<RwLock<HashMap<Uuid, crate::hooks::Hooks>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+#[cfg(feature = "sqlite")]
+static ARCHIVE: LazyLock<Mutex<Option<crate::archive::ArchiveSink>>> =
+    LazyLock::new(|| Mutex::new(None));
+
+/// Copy every emitted message to the SQLite archive (None to stop).
+/// Install after replaying the message log so replayed messages aren't
+/// archived twice.
+#[cfg(feature = "sqlite")]
+pub fn set_archive_sink(sink: Option<crate::archive::ArchiveSink>) {
+    *ARCHIVE.lock().unwrap() = sink;
+}
+
+/// Writer counters, if the archive is enabled
+#[cfg(feature = "sqlite")]
+pub fn archive_stats() -> Option<crate::archive::ArchiveStatsSnapshot> {
+    ARCHIVE.lock().unwrap().as_ref().map(|sink| sink.stats())
+}
+
+/// Run a project's hooks on every emitted message. Install after replaying
+/// the message log so replayed messages don't fire them again.
+pub fn set_hooks(project_uuid: Uuid, hooks: crate::hooks::Hooks) {
+    HOOKS.write().unwrap().insert(project_uuid, hooks);
+}
+
+/// Counters of a project's hooks, empty without any
+pub fn hook_stats(project_uuid: Uuid) -> Vec<crate::hooks::HookStats> {
+    HOOKS.read().unwrap().get(&project_uuid).map(|hooks| hooks.stats()).unwrap_or_default()
+}
+
+/// Install a project's channel aliases, replacing the previous set.
+/// Expects a validated map (no alias points at another alias); use counts
+/// survive the swap.
+pub fn set_aliases(project_uuid: Uuid, aliases: BTreeMap<String, String>) {
+    ALIASES.write().unwrap().insert(project_uuid, aliases.clone());
+    merge_aliased(project_uuid, &aliases);
+}
+
+/// Move the channels stored under names that are now aliases to their
+/// canonical names, so a rename on reload doesn't split a channel's history
+/// in two. Where the canonical channel exists already, it absorbs the
+/// other's history, and the other's subscribers get its messages from then
+/// on. Entries logged under the old name land on the canonical channel
+/// when the log is replayed, as aliases are installed first.
+fn merge_aliased(project_uuid: Uuid, aliases: &BTreeMap<String, String>) {
+    let mut map = BROADCAST_CHANNELS.write().unwrap();
+    for (alias, name) in aliases {
+        let Some(old) = map.remove(&(project_uuid, alias.clone())) else {
+            continue;
+        };
+        match map.get(&(project_uuid, name.clone())) {
+            Some(channel) => channel.lock().unwrap().absorb(&mut old.lock().unwrap()),
+            None => {
+                map.insert((project_uuid, name.clone()), old);
+            }
+        }
+    }
+}
+
+/// Install a project's replay TTLs: `default_secs` for every channel, or
+/// the most specific of `channels` (keyed by channel or pattern) matching
+/// it. Messages older than that are left out of replay histories and
+/// trimmed by [`trim_expired`]; 0 keeps them for good.
+pub fn set_replay_ttls(project_uuid: Uuid, default_secs: u64, channels: BTreeMap<String, u64>) {
+    REPLAY_TTLS.write().unwrap().insert(project_uuid, ReplayTtls { default: default_secs, channels });
+}
+
+/// Install a project's channel policy, replacing the previous one
+pub fn set_channel_policy(project_uuid: Uuid, policy: ChannelPolicy) {
+    CHANNEL_POLICIES.write().unwrap().insert(project_uuid, policy);
+}
+
+/// A project's channel policy, the default until one is installed
+pub fn channel_policy(project_uuid: Uuid) -> ChannelPolicy {
+    CHANNEL_POLICIES.read().unwrap().get(&project_uuid).cloned().unwrap_or_default()
+}
+
+/// Install a project's replay buffer byte budget: past `max_bytes` of
+/// buffered message text a channel evicts its oldest messages, as it does
+/// past its message capacity; 0 leaves only the count. Channels that
+/// exist already take it too, evicting right away if they are over it.
+pub fn set_replay_max_bytes(project_uuid: Uuid, max_bytes: usize) {
+    REPLAY_MAX_BYTES.write().unwrap().insert(project_uuid, max_bytes);
+    for (_, channel) in channels_where(|uuid, _| uuid == project_uuid) {
+        channel.lock().unwrap().buffer.set_max_bytes(max_bytes);
+    }
+}
+
+fn replay_max_bytes(project_uuid: Uuid) -> usize {
+    REPLAY_MAX_BYTES.read().unwrap().get(&project_uuid).copied().unwrap_or(REPLAY_MAX_BYTES_PER_CHANNEL)
+}
+
+fn replay_ttls(project_uuid: Uuid) -> ReplayTtls {
+    REPLAY_TTLS.read().unwrap().get(&project_uuid).cloned().unwrap_or_default()
+}
+
+/// The name a channel is stored under
+fn canonical(project_uuid: Uuid, topic: &str) -> String {
+    ALIASES.read().unwrap()
+        .get(&project_uuid)
+        .and_then(|aliases| aliases.get(topic))
+        .cloned()
+        .unwrap_or_else(|| topic.to_string())
+}
+
+/// A channel name as a client gave it, resolved to its canonical name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedChannel {
+    pub channel: String,
+    /// The alias the client used, if it wasn't the canonical name
+    pub alias: Option<String>,
+}
+
+/// Resolve a client-supplied channel name, counting the use if it was an alias
+pub fn resolve_channel(project_uuid: Uuid, name: &str) -> ResolvedChannel {
+    let channel = canonical(project_uuid, name);
+    if channel == name {
+        return ResolvedChannel { channel, alias: None };
+    }
+    *ALIAS_USES.lock().unwrap().entry((project_uuid, name.to_string())).or_default() += 1;
+    ResolvedChannel { channel, alias: Some(name.to_string()) }
+}
+
+/// Count an emit to `channel` turned away by a rate limit, for the stats
+pub fn record_rate_limited(project_uuid: Uuid, channel: &str) {
+    *RATE_LIMITED.lock().unwrap().entry((project_uuid, channel.to_string())).or_default() += 1;
+}
+
+/// A configured alias and how often clients still use it
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct AliasInfo {
+    pub alias: String,
+    pub channel: String,
+    pub uses: u64,
+}
+
+/// A project's aliases, sorted by alias name
+pub fn list_aliases(project_uuid: Uuid) -> Vec<AliasInfo> {
+    let aliases = ALIASES.read().unwrap();
+    let uses = ALIAS_USES.lock().unwrap();
+    aliases.get(&project_uuid)
+        .into_iter()
+        .flatten()
+        .map(|(alias, channel)| AliasInfo {
+            alias: alias.clone(),
+            channel: channel.clone(),
+            uses: uses.get(&(project_uuid, alias.clone())).copied().unwrap_or(0),
+        })
+        .collect()
+}
+
 /// Set the path for message logging (for crash recovery)
 pub fn set_message_log_path(path: Option<PathBuf>) {
     use crate::message_log::MessageLog;
@@ -51,18 +539,21 @@ pub fn set_message_log_path(path: Option<PathBuf>) {
     *log = path.and_then(|p| MessageLog::open(&p).ok());
 }
 
-/// Append message to log file (if logging is enabled)
-fn log_message(project_uuid: Uuid, channel: &str, message: &str) {
-    // Try to get lock without blocking - if we can't get it, skip logging this message
-    // This prevents blocking the async runtime if the log is temporarily locked
-    if let Ok(mut log_guard) = MESSAGE_LOG.try_lock() {
-        if let Some(log) = log_guard.as_mut() {
-            let _ = log.append(project_uuid, channel, message);
-        }
+/// Append message to log file (if logging is enabled), stamped with the
+/// time it was buffered at so purges cut both at the same point
+fn log_message(project_uuid: Uuid, channel: &str, message: &str, target: Option<&str>, at: DateTime<Utc>) {
+    // The lock is only held for one append, or for the end of a compaction,
+    // which carries over what was appended while it ran; waiting that out is
+    // short and keeps the message in the restored history
+    if let Some(log) = MESSAGE_LOG.lock().unwrap().as_mut() {
+        let _ = log.append_to(project_uuid, channel, message, target, at);
     }
 }
 
 /// Replay message log to restore replay buffers after crash
+///
+/// Replayed messages fill the replay buffers, each capped at its capacity,
+/// without being logged or archived again.
 pub async fn replay_message_log(log_path: &std::path::Path) -> anyhow::Result<usize> {
     use crate::message_log::MessageLog;
 
@@ -71,92 +562,481 @@ pub async fn replay_message_log(log_path: &std::path::Path) -> anyhow::Result<us
     let count = entries.len();
 
     for entry in entries {
-        emit_and_store(entry.project_uuid, &entry.channel, entry.message).await;
+        store(entry.project_uuid, &entry.channel, entry.message, entry.target, Some(entry.timestamp), false).await;
     }
 
     Ok(count)
 }
 
+/// Restore the replay buffers from the log at `log_path`, then log every
+/// message emitted from here on to it. What a daemon does on start when
+/// persistence is enabled.
+pub async fn restore_message_log(log_path: &std::path::Path) -> anyhow::Result<usize> {
+    set_message_log_path(None);
+    let count = if log_path.exists() { replay_message_log(log_path).await? } else { 0 };
+    set_message_log_path(Some(log_path.to_path_buf()));
+    Ok(count)
+}
+
+/// Compact the message log to the last `keep_per_channel` messages per
+/// channel if it holds more than `above_bytes`, returning whether it did.
+/// The compacted copy is written without holding the log, so emits go on
+/// meanwhile; they only wait for the swap, which carries them over. This
+/// reads and writes the whole log, so call it off the async runtime.
+pub fn compact_message_log(keep_per_channel: usize, above_bytes: u64) -> anyhow::Result<bool> {
+    use crate::message_log::MessageLog;
+
+    let Some(path) = MESSAGE_LOG.lock().unwrap().as_ref().map(|log| log.path().to_path_buf()) else {
+        return Ok(false);
+    };
+    if std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0) <= above_bytes {
+        return Ok(false);
+    }
+    let rewrite = MessageLog::prepare_compaction(&path, keep_per_channel)?;
+
+    let mut log = MESSAGE_LOG.lock().unwrap();
+    match log.as_mut() {
+        // Purged or moved meanwhile; the next check compacts it
+        Some(current) if current.path() == path => current.finish_rewrite(rewrite),
+        _ => Ok(false),
+    }
+}
+
+/// Empty a channel's replay buffer, and drop its messages from the message
+/// log so a restart doesn't bring them back. Subscribers stay attached.
+/// Returns how many buffered messages went.
+pub async fn purge(project_uuid: Uuid, topic: &str) -> anyhow::Result<usize> {
+    let topic = canonical(project_uuid, topic);
+    let found = BROADCAST_CHANNELS.read().unwrap().get(&(project_uuid, topic.clone())).cloned();
+    let Some(channel) = found else {
+        return Ok(0);
+    };
+    let (purged, until) = {
+        let mut channel = channel.lock().unwrap();
+        if channel.removed {
+            return Ok(0);
+        }
+        let purged = channel.buffer.messages.len();
+        channel.buffer.clear();
+        (purged, Utc::now())
+    };
+
+    // Rewrites the whole log, so not on a runtime thread. What is logged
+    // after the buffer was emptied stays.
+    tokio::task::spawn_blocking(move || purge_message_log(project_uuid, &topic, until)).await??;
+    Ok(purged)
+}
+
+/// Drop the entries of one channel logged up to `until` from the message log
+fn purge_message_log(project_uuid: Uuid, channel: &str, until: DateTime<Utc>) -> anyhow::Result<()> {
+    use crate::message_log::MessageLog;
+
+    loop {
+        let Some(path) = MESSAGE_LOG.lock().unwrap().as_ref().map(|log| log.path().to_path_buf()) else {
+            return Ok(());
+        };
+        let Some(rewrite) = MessageLog::prepare_purge(&path, project_uuid, channel, until)? else {
+            return Ok(());
+        };
+        let mut log = MESSAGE_LOG.lock().unwrap();
+        match log.as_mut() {
+            Some(current) if current.path() == path => {
+                if current.finish_rewrite(rewrite)? {
+                    return Ok(());
+                }
+                // Compacted meanwhile; purge the compacted log instead
+            }
+            _ => return Ok(()),
+        }
+    }
+}
+
 pub async fn get_or_create_broadcast_tx(project_uuid: Uuid, topic: &str) -> broadcast::Sender<String> {
-    let key = (project_uuid, topic.to_string());
-    let mut map = BROADCAST_CHANNELS.lock().await;
-    // Get or create the sender+buffer tuple - the HashMap keeps the original sender alive
+    let key = (project_uuid, canonical(project_uuid, topic));
+    // Get or create the channel - the HashMap keeps the original sender alive
     // which keeps the channel open. We clone the sender to return.
-    let (tx, _buffer) = map.entry(key.clone())
-        .or_insert_with(|| {
-            let (tx, _rx) = broadcast::channel(BROADCAST_CHANNEL_CAPACITY);
-            let buffer = ReplayBuffer::new(REPLAY_BUFFER_CAPACITY);
-            (tx, buffer)
-        });
-    tx.clone()
+    with_channel(key, |channel| channel.tx.clone())
 }
 
 /// Emit a message and store it in the replay buffer atomically
-/// Returns the number of receivers that received the message (0 if no active receivers)
+/// Returns the number of receivers that received the message (0 if no active receivers);
+/// pattern subscribers aren't counted
+///
+/// An aliased `topic` is stored, logged and broadcast under its canonical name.
 pub async fn emit_and_store(project_uuid: Uuid, topic: &str, message: String) -> usize {
-    let key = (project_uuid, topic.to_string());
-
-    // Clone message and get sender outside the critical section to reduce lock time
-    let message_clone = message.clone();
-    let sender = {
-        let mut map = BROADCAST_CHANNELS.lock().await;
-        let (tx, buffer) = map.entry(key.clone())
-            .or_insert_with(|| {
-                let (tx, _rx) = broadcast::channel(BROADCAST_CHANNEL_CAPACITY);
-                let buffer = ReplayBuffer::new(REPLAY_BUFFER_CAPACITY);
-                (tx, buffer)
-            });
+    emit_to(project_uuid, topic, message, None).await
+}
+
+/// [`emit_and_store`] of a message addressed to `target`: subscribers that
+/// named another agent don't get it, live or in their history. It is
+/// buffered either way, and the count is of the subscribers it reached.
+pub async fn emit_to(project_uuid: Uuid, topic: &str, message: String, target: Option<String>) -> usize {
+    store(project_uuid, topic, message, target, None, true).await
+}
+
+/// [`emit_to`] of a message emitted `at` (now if `None`), copying it to the
+/// log and archive and running hooks only if `persist` is set
+async fn store(
+    project_uuid: Uuid,
+    topic: &str,
+    message: String,
+    target: Option<String>,
+    at: Option<DateTime<Utc>>,
+    persist: bool,
+) -> usize {
+    let topic = canonical(project_uuid, topic);
+    let key = (project_uuid, topic.clone());
+
+    let (recipients, at) = with_channel(key, |channel| {
+        channel.seq += 1;
+        let seq = channel.seq;
+        // Stamped under the lock, so a purge's cutoff falls after everything
+        // it emptied and before everything buffered since
+        let at = at.unwrap_or_else(Utc::now);
 
         // Store in replay buffer (always succeeds)
-        buffer.push(message_clone);
+        channel.buffer.push(at, (target.clone(), message.clone()));
+
+        // Broadcast under the channel's lock, so every subscriber sees the
+        // sequence numbers in order; sending never waits. If there are no
+        // receivers, that's OK, we stored it: the replay buffer lets late
+        // subscribers catch up.
+        if let Some(tx) = PATTERN_CHANNELS.read().unwrap().get(&project_uuid) {
+            let _ = tx.send((topic.clone(), seq, target.clone(), message.clone()));
+        }
+        let recipients = channel.recipients(target.as_deref());
+        channel.send(seq, target.as_deref(), &message);
+        (recipients, at)
+    });
+    // Lock released here
+
+    if persist {
+        // Log message for crash recovery (async, non-blocking)
+        if !is_ephemeral(&topic) {
+            log_message(project_uuid, &topic, &message, target.as_deref(), at);
+        }
 
-        // Clone sender to use outside lock
-        tx.clone()
+        // Queue for the archive writer; never waits on disk
+        #[cfg(feature = "sqlite")]
+        if let Some(sink) = ARCHIVE.lock().unwrap().as_ref() {
+            sink.record(&topic, &message);
+        }
+
+        // Spawns the runs; never waits on them
+        if let Some(hooks) = HOOKS.read().unwrap().get(&project_uuid) {
+            hooks.fire(&topic, &message);
+        }
+    }
+    recipients
+}
+
+/// Whether messages on `channel` are kept out of the message log: replies
+/// on `reply:<correlation id>` only matter to the request waiting for them,
+/// so a restart doesn't need to bring them back
+pub fn is_ephemeral(channel: &str) -> bool {
+    channel.starts_with("reply:")
+}
+
+/// Whether a channel name given to subscribe is a pattern such as `repo:*`
+/// or `*`
+pub fn is_pattern(name: &str) -> bool {
+    name.contains('*')
+}
+
+/// Whether `channel` matches `pattern`, where `*` stands for any run of
+/// characters, `:` included
+pub fn pattern_matches(pattern: &str, channel: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = channel.strip_prefix(first) else {
+        return false;
     };
-    // Lock released here
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(last) = parts.pop() else {
+        // No `*` at all
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+/// The entry for `channel` in a map keyed by channel names and patterns:
+/// its own, else that of the matching pattern with the most fixed characters
+pub fn most_specific<'a, T>(entries: &'a BTreeMap<String, T>, channel: &str) -> Option<&'a T> {
+    if let Some(entry) = entries.get(channel) {
+        return Some(entry);
+    }
+    entries
+        .iter()
+        .filter(|(pattern, _)| is_pattern(pattern) && pattern_matches(pattern, channel))
+        .max_by_key(|(pattern, _)| pattern.chars().filter(|c| *c != '*').count())
+        .map(|(_, entry)| entry)
+}
 
-    // Log message for crash recovery (async, non-blocking)
-    log_message(project_uuid, topic, &message);
+/// Receives the messages of every channel a pattern matches, including
+/// channels created after subscribing
+pub struct PatternReceiver {
+    pattern: String,
+    agent: Option<String>,
+    rx: broadcast::Receiver<PatternMessage>,
+}
 
-    // Broadcast outside the lock - if there are no receivers, that's OK, we stored it
-    // The replay buffer ensures late subscribers can catch up
-    sender.send(message).unwrap_or(0)
+impl PatternReceiver {
+    /// The next matching message with its channel; `None` once the daemon
+    /// drops the project. A subscriber that falls too far behind skips the
+    /// messages it missed.
+    pub async fn recv(&mut self) -> Option<ChannelMessage> {
+        loop {
+            match self.recv_sequenced().await {
+                Ok((_, message)) => return Some(message),
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// [`PatternReceiver::recv`] with the message's sequence number, as a
+    /// broadcast receiver would return it: `Lagged` when the subscriber fell
+    /// so far behind that messages of the project were dropped for it,
+    /// counting those of every channel, and `Closed` once the daemon drops
+    /// the project
+    pub async fn recv_sequenced(&mut self) -> Result<Sequenced<ChannelMessage>, broadcast::error::RecvError> {
+        loop {
+            let (channel, seq, target, message) = self.rx.recv().await?;
+            if pattern_matches(&self.pattern, &channel) && delivers_to(target.as_deref(), self.agent.as_deref()) {
+                return Ok((seq, (channel, message)));
+            }
+        }
+    }
 }
 
-/// Subscribe to a broadcast channel and get message history
+/// The buffered messages of the channels `pattern` matches that go to
+/// `agent`, oldest first across channels and picked by `filter` as a whole,
+/// with how many of them were left out as expired
+fn buffered<'a>(
+    channels: impl IntoIterator<Item = (&'a String, impl Deref<Target = Channel>)>,
+    project_uuid: Uuid,
+    agent: Option<&str>,
+    filter: &HistoryFilter,
+) -> (Vec<ChannelMessage>, usize) {
+    let ttls = replay_ttls(project_uuid);
+    let now = Utc::now();
+    let mut timed: Vec<(DateTime<Utc>, ChannelMessage)> = Vec::new();
+    let mut expired = 0;
+    for (name, channel) in channels {
+        let cutoff = ttls.cutoff(name, now);
+        for (at, (target, message)) in &channel.buffer.messages {
+            if !delivers_to(target.as_deref(), agent) {
+                continue;
+            }
+            if is_expired(*at, cutoff) {
+                expired += 1;
+            } else {
+                timed.push((*at, (name.clone(), message.clone())));
+            }
+        }
+    }
+    timed.sort_by_key(|(at, _)| *at);
+    (filter.apply(timed.into_iter()), expired)
+}
+
+/// Subscribe to every channel of a project that `pattern` matches, now or
+/// later, with the replay history of the channels that exist already,
+/// oldest first across channels and picked by `filter` as a whole
+pub async fn subscribe_pattern(
+    project_uuid: Uuid,
+    pattern: &str,
+    filter: &HistoryFilter,
+) -> (PatternReceiver, Vec<ChannelMessage>) {
+    subscribe_pattern_for(project_uuid, pattern, None, filter)
+}
+
+/// [`subscribe_pattern`] as `agent`: messages addressed to another agent
+/// are left out of both the history and the live stream
+pub async fn subscribe_pattern_as(
+    project_uuid: Uuid,
+    pattern: &str,
+    agent: &str,
+    filter: &HistoryFilter,
+) -> (PatternReceiver, Vec<ChannelMessage>) {
+    subscribe_pattern_for(project_uuid, pattern, Some(agent), filter)
+}
+
+fn subscribe_pattern_for(
+    project_uuid: Uuid,
+    pattern: &str,
+    agent: Option<&str>,
+    filter: &HistoryFilter,
+) -> (PatternReceiver, Vec<ChannelMessage>) {
+    // Subscribe while holding the map, so no channel is created, and every
+    // matching channel, so no message falls between the history and the
+    // live stream. Locked in name order, like any other multi-channel lock.
+    let map = BROADCAST_CHANNELS.read().unwrap();
+    let mut channels: Vec<(&String, &Mutex<Channel>)> = map
+        .iter()
+        .filter(|((uuid, name), _)| *uuid == project_uuid && pattern_matches(pattern, name))
+        .map(|((_, name), channel)| (name, &**channel))
+        .collect();
+    channels.sort_by_key(|(name, _)| *name);
+    let locked: Vec<_> = channels.into_iter().map(|(name, channel)| (name, channel.lock().unwrap())).collect();
+    let (history, _) = buffered(locked.iter().map(|(name, channel)| (*name, &**channel)), project_uuid, agent, filter);
+    let rx = PATTERN_CHANNELS.write().unwrap()
+        .entry(project_uuid)
+        .or_insert_with(|| broadcast::channel(BROADCAST_CHANNEL_CAPACITY).0)
+        .subscribe();
+    drop(locked);
+    drop(map);
+
+    (PatternReceiver { pattern: pattern.to_string(), agent: agent.map(str::to_string), rx }, history)
+}
+
+/// Receives the messages of one channel for one agent: those addressed to
+/// it and those addressed to no one
+pub struct AgentReceiver {
+    agent: String,
+    agents: AgentCounts,
+    rx: broadcast::Receiver<Sequenced<TargetedMessage>>,
+}
+
+impl AgentReceiver {
+    /// The next message for the agent; `None` once the channel is gone. A
+    /// subscriber that falls too far behind skips the messages it missed.
+    pub async fn recv(&mut self) -> Option<String> {
+        loop {
+            match self.recv_sequenced().await {
+                Ok((_, message)) => return Some(message),
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// [`AgentReceiver::recv`] with the message's sequence number, as a
+    /// broadcast receiver would return it: `Lagged` when the subscriber fell
+    /// so far behind that messages were dropped for it, counting those for
+    /// other agents, and `Closed` once the channel is gone
+    pub async fn recv_sequenced(&mut self) -> Result<Sequenced<String>, broadcast::error::RecvError> {
+        loop {
+            let (seq, (target, message)) = self.rx.recv().await?;
+            if delivers_to(target.as_deref(), Some(&self.agent)) {
+                return Ok((seq, message));
+            }
+        }
+    }
+}
+
+impl Drop for AgentReceiver {
+    fn drop(&mut self) {
+        let mut agents = self.agents.lock().unwrap();
+        if let Some(count) = agents.get_mut(&self.agent) {
+            *count -= 1;
+            if *count == 0 {
+                agents.remove(&self.agent);
+            }
+        }
+    }
+}
+
+/// [`subscribe_with_history`] as `agent`: messages addressed to another
+/// agent are left out of both the history and the live stream
+pub async fn subscribe_as(
+    project_uuid: Uuid,
+    topic: &str,
+    agent: &str,
+    filter: &HistoryFilter,
+) -> (AgentReceiver, Vec<String>) {
+    let topic = canonical(project_uuid, topic);
+    let cutoff = replay_ttls(project_uuid).cutoff(&topic, Utc::now());
+    with_channel((project_uuid, topic), |channel| {
+        let history = channel.buffer.get(filter, Some(agent), cutoff);
+        *channel.agents.lock().unwrap().entry(agent.to_string()).or_default() += 1;
+        let receiver = AgentReceiver {
+            agent: agent.to_string(),
+            agents: channel.agents.clone(),
+            rx: channel.agent_tx.subscribe(),
+        };
+        (receiver, history)
+    })
+}
+
+/// Subscribe to a broadcast channel and get message history, less the
+/// messages past the channel's replay TTL
 ///
 /// IMPORTANT: Gets history BEFORE subscribing to avoid race condition where messages
 /// emitted between subscribe and get_history appear in both live stream and history (duplicates).
+/// Subscribing by an alias joins the canonical channel.
 pub async fn subscribe_broadcast(project_uuid: Uuid, topic: &str) -> (broadcast::Receiver<String>, Vec<String>) {
-    let key = (project_uuid, topic.to_string());
+    subscribe_with_history(project_uuid, topic, &HistoryFilter::default()).await
+}
 
-    // Get history and receiver atomically with minimal lock time
-    let (rx, history) = {
-        let mut map = BROADCAST_CHANNELS.lock().await;
+/// [`subscribe_broadcast`] with only the part of the history `filter` picks
+pub async fn subscribe_with_history(
+    project_uuid: Uuid,
+    topic: &str,
+    filter: &HistoryFilter,
+) -> (broadcast::Receiver<String>, Vec<String>) {
+    subscribe_to(project_uuid, topic, filter, |channel| channel.tx.subscribe())
+}
 
-        // Use entry API to atomically get-or-create
-        let (tx, buffer) = map.entry(key)
-            .or_insert_with(|| {
-                let (tx, _rx) = broadcast::channel(BROADCAST_CHANNEL_CAPACITY);
-                let buffer = ReplayBuffer::new(REPLAY_BUFFER_CAPACITY);
-                (tx, buffer)
-            });
+/// [`subscribe_with_history`] with each live message's sequence number, so
+/// a subscriber that lags can tell where it picks up again
+pub async fn subscribe_sequenced(
+    project_uuid: Uuid,
+    topic: &str,
+    filter: &HistoryFilter,
+) -> (broadcast::Receiver<Sequenced<String>>, Vec<String>) {
+    subscribe_to(project_uuid, topic, filter, |channel| channel.seq_tx.subscribe())
+}
 
+fn subscribe_to<R>(
+    project_uuid: Uuid,
+    topic: &str,
+    filter: &HistoryFilter,
+    subscribe: impl FnOnce(&Channel) -> R,
+) -> (R, Vec<String>) {
+    let topic = canonical(project_uuid, topic);
+    let cutoff = replay_ttls(project_uuid).cutoff(&topic, Utc::now());
+
+    // Get history and receiver atomically under the channel's own lock;
+    // the map is only locked to find it
+    let (rx, history) = with_channel((project_uuid, topic), |channel| {
         // Get history FIRST, then subscribe
         // This ensures messages don't appear in both history and live stream
-        let history = buffer.get_all();
-        let rx = tx.subscribe();
+        let history = channel.buffer.get(filter, None, cutoff);
+        let rx = subscribe(channel);
 
         (rx, history)
-    };
+    });
     // Lock released here
 
     (rx, history)
 }
 
+/// The replay history of a channel, or of every channel a pattern matches,
+/// as a subscriber with the same `agent` and `filter` would get it, without
+/// subscribing or creating the channel. Also returns how many messages for
+/// `agent` were left out as past their replay TTL.
+pub async fn history(
+    project_uuid: Uuid,
+    name: &str,
+    agent: Option<&str>,
+    filter: &HistoryFilter,
+) -> (Vec<ChannelMessage>, usize) {
+    let pattern = if is_pattern(name) { name.to_string() } else { canonical(project_uuid, name) };
+    let channels = matching(project_uuid, &pattern);
+    // One channel locked at a time: a history isn't a subscription, so it
+    // needn't line up with anything live
+    buffered(channels.iter().map(|(name, channel)| (name, channel.lock().unwrap())), project_uuid, agent, filter)
+}
+
 // List active channels for a project
 pub async fn list_channels(project_uuid: Uuid) -> Vec<String> {
-    let broadcast_map = BROADCAST_CHANNELS.lock().await;
+    let broadcast_map = BROADCAST_CHANNELS.read().unwrap();
     let mut channels: Vec<String> = broadcast_map
         .keys()
         .filter_map(|(uuid, topic)| {
@@ -172,11 +1052,93 @@ pub async fn list_channels(project_uuid: Uuid) -> Vec<String> {
     channels
 }
 
+/// Send every live subscriber of every channel of a project the message
+/// `message` builds for its channel, pattern subscribers included. Unlike an
+/// emit it is neither buffered nor logged, so no later subscriber sees it,
+/// and it takes no sequence number: it goes out as 0. Returns the
+/// subscribers it reached.
+pub async fn notify_subscribers(project_uuid: Uuid, message: impl Fn(&str) -> String) -> usize {
+    let pattern_sender = PATTERN_CHANNELS.read().unwrap().get(&project_uuid).cloned();
+    let mut notified = pattern_sender.as_ref().map_or(0, |tx| tx.receiver_count());
+    for (name, channel) in channels_where(|uuid, _| uuid == project_uuid) {
+        let mut channel = channel.lock().unwrap();
+        let message = message(&name);
+        if let Some(tx) = &pattern_sender {
+            let _ = tx.send((name.clone(), 0, None, message.clone()));
+        }
+        channel.send(0, None, &message);
+        notified += channel.subscriber_count();
+    }
+    notified
+}
+
+/// Drop the channels idle for `ttl`: no subscribers, and no emit or
+/// subscribe since. A channel that still has subscribers counts as active
+/// now, so its TTL starts when the last one leaves. The next emit or
+/// subscribe creates a dropped channel afresh, with an empty history.
+/// Returns the channels dropped.
+pub async fn collect_idle_channels(ttl: Duration) -> Vec<(Uuid, String)> {
+    let dropped = collect_idle(&mut BROADCAST_CHANNELS.write().unwrap(), ttl, Instant::now());
+    // Their counts go with them, as a channel's stats start over when it comes back
+    let mut rate_limited = RATE_LIMITED.lock().unwrap();
+    for key in &dropped {
+        rate_limited.remove(key);
+    }
+    dropped
+}
+
+fn collect_idle(map: &mut ChannelMap, ttl: Duration, now: Instant) -> Vec<ChannelKey> {
+    let mut dropped = Vec::new();
+    map.retain(|key, channel| {
+        // Subscribers join under this lock, so none can sneak in before the
+        // channel is marked removed
+        let mut channel = channel.lock().unwrap();
+        if channel.subscriber_count() > 0 {
+            channel.last_activity = now;
+            return true;
+        }
+        let keep = now.saturating_duration_since(channel.last_activity) < ttl;
+        if !keep {
+            channel.removed = true;
+            dropped.push(key.clone());
+        }
+        keep
+    });
+    dropped.sort();
+    dropped
+}
+
+/// Drop the buffered messages past their channel's replay TTL, returning
+/// how many went
+pub async fn trim_expired() -> usize {
+    let ttls = REPLAY_TTLS.read().unwrap().clone();
+    let now = Utc::now();
+    let channels: Vec<(ChannelKey, Arc<Mutex<Channel>>)> = BROADCAST_CHANNELS.read().unwrap()
+        .iter()
+        .map(|(key, channel)| (key.clone(), channel.clone()))
+        .collect();
+    let mut trimmed = 0;
+    for ((uuid, name), channel) in channels {
+        let Some(cutoff) = ttls.get(&uuid).and_then(|ttls| ttls.cutoff(&name, now)) else {
+            continue;
+        };
+        let mut channel = channel.lock().unwrap();
+        let removed = channel.buffer.trim_before(cutoff);
+        channel.expired += removed as u64;
+        trimmed += removed;
+    }
+    trimmed
+}
+
 /// Clear all channels (for testing crash recovery)
 #[doc(hidden)]
 pub async fn clear_all_channels() {
-    let mut map = BROADCAST_CHANNELS.lock().await;
+    let mut map = BROADCAST_CHANNELS.write().unwrap();
+    for channel in map.values() {
+        channel.lock().unwrap().removed = true;
+    }
     map.clear();
+    PATTERN_CHANNELS.write().unwrap().clear();
 }
 
 /// Channel statistics
@@ -184,22 +1146,50 @@ pub async fn clear_all_channels() {
 pub struct ChannelStats {
     pub channel: String,
     pub replay_buffer_size: usize,
+    /// Text of the buffered messages together, in bytes
+    pub replay_buffer_bytes: usize,
     pub subscriber_count: usize,
+    /// Old names that resolve to this channel
+    pub aliases: Vec<String>,
+    /// Emits and subscribes that arrived under one of those aliases
+    pub alias_uses: u64,
+    /// Emits turned away by a rate limit
+    pub rate_limited: u64,
+    /// Messages past the replay TTL: trimmed, or still buffered but left
+    /// out of histories
+    pub expired: u64,
+    /// Sequence number of the channel's last message, 0 before the first
+    pub latest_seq: u64,
+    /// Outside the namespaces of the project's channel policy
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub unknown_namespace: bool,
 }
 
 /// Get statistics for all channels of a project
 pub async fn get_channel_stats(project_uuid: Uuid) -> Vec<ChannelStats> {
-    let map = BROADCAST_CHANNELS.lock().await;
+    let aliases = list_aliases(project_uuid);
+    let channels = channels_where(|uuid, _| uuid == project_uuid);
+    let rate_limited = RATE_LIMITED.lock().unwrap();
+    let ttls = replay_ttls(project_uuid);
+    let policy = channel_policy(project_uuid);
+    let now = Utc::now();
     let mut stats = Vec::new();
 
-    for ((uuid, channel), (tx, buffer)) in map.iter() {
-        if *uuid == project_uuid {
-            stats.push(ChannelStats {
-                channel: channel.clone(),
-                replay_buffer_size: buffer.messages.len(),
-                subscriber_count: tx.receiver_count(),
-            });
-        }
+    for (name, channel) in channels {
+        let channel = channel.lock().unwrap();
+        let own: Vec<&AliasInfo> = aliases.iter().filter(|a| a.channel == name).collect();
+        stats.push(ChannelStats {
+            replay_buffer_size: channel.buffer.messages.len(),
+            replay_buffer_bytes: channel.buffer.bytes,
+            subscriber_count: channel.subscriber_count(),
+            aliases: own.iter().map(|a| a.alias.clone()).collect(),
+            alias_uses: own.iter().map(|a| a.uses).sum(),
+            rate_limited: rate_limited.get(&(project_uuid, name.clone())).copied().unwrap_or(0),
+            expired: channel.expired + channel.buffer.expired(ttls.cutoff(&name, now)) as u64,
+            latest_seq: channel.seq,
+            unknown_namespace: policy.flags(&name),
+            channel: name,
+        });
     }
 
     stats.sort_by(|a, b| a.channel.cmp(&b.channel));
@@ -209,6 +1199,7 @@ pub async fn get_channel_stats(project_uuid: Uuid) -> Vec<ChannelStats> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::constants::MAX_MESSAGE_SIZE;
 
     #[tokio::test]
     async fn test_get_or_create_broadcast_tx_same_instance() {
@@ -280,6 +1271,78 @@ mod tests {
         assert_eq!(history[99], "msg149");
     }
 
+    #[test]
+    fn test_replay_buffer_byte_budget() {
+        let mut buffer = ReplayBuffer::new(10, 100);
+        let push = |buffer: &mut ReplayBuffer, size: usize| buffer.push(Utc::now(), (None, "x".repeat(size)));
+        let sizes = |buffer: &ReplayBuffer| buffer.messages.iter().map(|(_, (_, m))| m.len()).collect::<Vec<_>>();
+
+        for size in [10, 40, 30, 20] {
+            push(&mut buffer, size);
+        }
+        assert_eq!(sizes(&buffer), vec![10, 40, 30, 20]);
+        assert_eq!(buffer.bytes, 100);
+
+        // One byte over evicts from the front until it fits again
+        push(&mut buffer, 1);
+        assert_eq!(sizes(&buffer), vec![40, 30, 20, 1]);
+        assert_eq!(buffer.bytes, 91);
+        push(&mut buffer, 50);
+        assert_eq!(sizes(&buffer), vec![20, 1, 50]);
+        assert_eq!(buffer.bytes, 71);
+
+        // The count still applies under the byte budget
+        for _ in 0..10 {
+            push(&mut buffer, 2);
+        }
+        assert_eq!(sizes(&buffer), vec![2; 10]);
+        assert_eq!(buffer.bytes, 20);
+    }
+
+    #[test]
+    fn test_replay_buffer_oversized_message_stored_alone() {
+        let mut buffer = ReplayBuffer::new(10, 1024);
+        for size in [100, 500, 300] {
+            buffer.push(Utc::now(), (None, "x".repeat(size)));
+        }
+
+        // Within MAX_MESSAGE_SIZE but over the budget on its own
+        let oversized = "y".repeat(MAX_MESSAGE_SIZE);
+        buffer.push(Utc::now(), (None, oversized.clone()));
+        assert_eq!(buffer.messages.len(), 1);
+        assert_eq!(buffer.messages[0].1 .1, oversized);
+        assert_eq!(buffer.bytes, MAX_MESSAGE_SIZE);
+
+        // The next message pushes it out
+        buffer.push(Utc::now(), (None, "z".repeat(10)));
+        assert_eq!(buffer.messages.len(), 1);
+        assert_eq!(buffer.bytes, 10);
+        buffer.clear();
+        assert_eq!(buffer.bytes, 0);
+    }
+
+    #[tokio::test]
+    async fn test_stats_report_buffered_bytes() {
+        let uuid = Uuid::new_v4();
+        set_replay_max_bytes(uuid, 25);
+        for message in ["0123456789", "0123456789", "01234"] {
+            emit_and_store(uuid, "repo:delta", message.to_string()).await;
+        }
+        let stats = get_channel_stats(uuid).await;
+        assert_eq!((stats[0].replay_buffer_size, stats[0].replay_buffer_bytes), (3, 25));
+
+        emit_and_store(uuid, "repo:delta", "0".to_string()).await;
+        let stats = get_channel_stats(uuid).await;
+        assert_eq!((stats[0].replay_buffer_size, stats[0].replay_buffer_bytes), (3, 16));
+
+        // A lower budget, as on SIGHUP, applies to the live buffer at once
+        set_replay_max_bytes(uuid, 10);
+        let stats = get_channel_stats(uuid).await;
+        assert_eq!((stats[0].replay_buffer_size, stats[0].replay_buffer_bytes), (2, 6));
+        assert_eq!(purge(uuid, "repo:delta").await.unwrap(), 2);
+        assert_eq!(get_channel_stats(uuid).await[0].replay_buffer_bytes, 0);
+    }
+
     #[tokio::test]
     async fn test_replay_buffer_late_subscriber() {
         let uuid = Uuid::new_v4();
@@ -356,6 +1419,347 @@ mod tests {
         assert_eq!(history2[0], "project2_msg");
     }
 
+    #[tokio::test]
+    async fn test_history_filter() {
+        let uuid = Uuid::new_v4();
+        let topic = "history:filter";
+        let start = Utc::now();
+        for (minutes_ago, msg) in [(30, "old1"), (20, "old2"), (5, "recent1"), (1, "recent2")] {
+            store(uuid, topic, msg.to_string(), None, Some(start - chrono::Duration::minutes(minutes_ago)), false).await;
+        }
+
+        let history = |filter: HistoryFilter| async move { subscribe_with_history(uuid, topic, &filter).await.1 };
+        assert_eq!(history(HistoryFilter::default()).await, vec!["old1", "old2", "recent1", "recent2"]);
+        assert_eq!(history(HistoryFilter { last: Some(3), since: None }).await, vec!["old2", "recent1", "recent2"]);
+        assert_eq!(history(HistoryFilter { last: Some(10), since: None }).await.len(), 4);
+        let since = Some(start - chrono::Duration::minutes(10));
+        assert_eq!(history(HistoryFilter { last: None, since }).await, vec!["recent1", "recent2"]);
+        assert_eq!(history(HistoryFilter { last: Some(1), since }).await, vec!["recent2"]);
+        assert!(history(HistoryFilter::none()).await.is_empty());
+
+        // A filtered subscriber still gets live messages
+        let (mut rx, _) = subscribe_with_history(uuid, topic, &HistoryFilter::none()).await;
+        emit_and_store(uuid, topic, "live".to_string()).await;
+        assert_eq!(rx.recv().await.unwrap(), "live");
+    }
+
+    #[tokio::test]
+    async fn test_purge_keeps_subscribers() {
+        let uuid = Uuid::new_v4();
+        let topic = "team:alert";
+        emit_and_store(uuid, topic, "token=secret".to_string()).await;
+        emit_and_store(uuid, "team:status", "status".to_string()).await;
+        let (mut rx, history) = subscribe_broadcast(uuid, topic).await;
+        assert_eq!(history.len(), 1);
+
+        assert_eq!(purge(uuid, topic).await.unwrap(), 1);
+        assert!(subscribe_broadcast(uuid, topic).await.1.is_empty());
+        assert_eq!(subscribe_broadcast(uuid, "team:status").await.1, vec!["status"]);
+        assert_eq!(purge(uuid, "never:used").await.unwrap(), 0);
+        assert!(!list_channels(uuid).await.contains(&"never:used".to_string()));
+
+        emit_and_store(uuid, topic, "after".to_string()).await;
+        assert_eq!(rx.recv().await.unwrap(), "after");
+    }
+
+    #[tokio::test]
+    async fn test_notify_subscribers() {
+        let uuid = Uuid::new_v4();
+        emit_and_store(uuid, "repo:delta", "kept".to_string()).await;
+        let (mut rx, _) = subscribe_broadcast(uuid, "repo:delta").await;
+        let (mut agent_rx, _) = subscribe_as(uuid, "team:alert", "builder-1", &HistoryFilter::none()).await;
+        let (mut pattern_rx, _) = subscribe_pattern(uuid, "team:*", &HistoryFilter::none()).await;
+
+        assert_eq!(notify_subscribers(uuid, |channel| format!("bye {}", channel)).await, 3);
+        assert_eq!(rx.recv().await.unwrap(), "bye repo:delta");
+        assert_eq!(agent_rx.recv().await.unwrap(), "bye team:alert");
+        assert_eq!(pattern_rx.recv().await.unwrap(), ("team:alert".to_string(), "bye team:alert".to_string()));
+
+        // Nothing of it in the history
+        assert_eq!(subscribe_broadcast(uuid, "repo:delta").await.1, vec!["kept"]);
+        assert!(subscribe_broadcast(uuid, "team:alert").await.1.is_empty());
+    }
+
+    #[test]
+    fn test_collect_idle_channels() {
+        // A map of its own: the global one is shared with the other tests
+        let uuid = Uuid::new_v4();
+        let key = |topic: &str| (uuid, topic.to_string());
+        let mut map = ChannelMap::new();
+        Channel::touch(&mut map, key("idle")).buffer.push(Utc::now(), (None, "old".to_string()));
+        let rx = Channel::touch(&mut map, key("watched")).tx.subscribe();
+        let ttl = Duration::from_secs(60);
+        let start = Instant::now();
+
+        assert!(collect_idle(&mut map, ttl, start + Duration::from_secs(30)).is_empty());
+        assert_eq!(collect_idle(&mut map, ttl, start + Duration::from_secs(90)), vec![key("idle")]);
+        assert_eq!(map.len(), 1);
+
+        // The subscriber kept the channel active until it left
+        drop(rx);
+        assert!(collect_idle(&mut map, ttl, start + Duration::from_secs(120)).is_empty());
+        assert_eq!(collect_idle(&mut map, ttl, start + Duration::from_secs(150)), vec![key("watched")]);
+        assert!(map.is_empty());
+
+        // Coming back is a fresh channel
+        assert!(Channel::touch(&mut map, key("idle")).buffer.messages.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_history_without_subscribing() {
+        let uuid = Uuid::new_v4();
+        assert!(history(uuid, "repo:delta", None, &HistoryFilter::default()).await.0.is_empty());
+        assert!(list_channels(uuid).await.is_empty());
+
+        emit_and_store(uuid, "repo:delta", "delta".to_string()).await;
+        emit_to(uuid, "repo:merge", "merge".to_string(), Some("agent-1".to_string())).await;
+        let pair = |channel: &str, msg: &str| (channel.to_string(), msg.to_string());
+        let last = HistoryFilter { last: Some(1), since: None };
+        assert_eq!(history(uuid, "repo:delta", None, &last).await.0, vec![pair("repo:delta", "delta")]);
+        assert_eq!(history(uuid, "repo:*", None, &last).await.0, vec![pair("repo:merge", "merge")]);
+        assert_eq!(history(uuid, "repo:*", Some("agent-2"), &last).await.0, vec![pair("repo:delta", "delta")]);
+        assert_eq!(get_channel_stats(uuid).await.iter().map(|s| s.subscriber_count).sum::<usize>(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_replay_ttl() {
+        let uuid = Uuid::new_v4();
+        set_replay_ttls(uuid, 60, BTreeMap::from([("team:*".to_string(), 0)]));
+        let hour_ago = Utc::now() - chrono::Duration::hours(1);
+        store(uuid, "repo:delta", "stale".to_string(), None, Some(hour_ago), false).await;
+        store(uuid, "repo:delta", "fresh".to_string(), None, None, false).await;
+        store(uuid, "team:status", "kept".to_string(), None, Some(hour_ago), false).await;
+
+        let (_rx, replayed) = subscribe_broadcast(uuid, "repo:delta").await;
+        assert_eq!(replayed, vec!["fresh"]);
+        let (messages, expired) = history(uuid, "*", None, &HistoryFilter::default()).await;
+        assert_eq!(messages.iter().map(|(_, m)| m.as_str()).collect::<Vec<_>>(), vec!["kept", "fresh"]);
+        assert_eq!(expired, 1);
+
+        // Trimming frees the memory but keeps the count
+        let expired_of = |stats: &[ChannelStats], name: &str| {
+            stats.iter().find(|s| s.channel == name).map(|s| (s.replay_buffer_size, s.expired))
+        };
+        assert_eq!(expired_of(&get_channel_stats(uuid).await, "repo:delta"), Some((2, 1)));
+        assert!(trim_expired().await >= 1);
+        let stats = get_channel_stats(uuid).await;
+        assert_eq!(expired_of(&stats, "repo:delta"), Some((1, 1)));
+        assert_eq!(expired_of(&stats, "team:status"), Some((1, 0)));
+    }
+
+    #[test]
+    fn test_pattern_matches() {
+        assert!(pattern_matches("*", "repo:delta"));
+        assert!(pattern_matches("repo:*", "repo:delta"));
+        assert!(pattern_matches("repo:*", "repo:"));
+        assert!(!pattern_matches("repo:*", "team:status"));
+        assert!(!pattern_matches("repo:*", "repository:x"));
+        assert!(pattern_matches("*:alert", "team:alert"));
+        assert!(pattern_matches("session:*:done", "session:a:b:done"));
+        assert!(!pattern_matches("a*a", "a"));
+        assert!(pattern_matches("team:status", "team:status"));
+        assert!(!is_pattern("team:status"));
+        assert!(is_pattern("team:*"));
+    }
+
+    #[tokio::test]
+    async fn test_pattern_subscription() {
+        let uuid = Uuid::new_v4();
+        let start = Utc::now();
+        store(uuid, "repo:delta", "delta".to_string(), None, Some(start - chrono::Duration::minutes(2)), false).await;
+        store(uuid, "team:status", "status".to_string(), None, Some(start - chrono::Duration::minutes(3)), false).await;
+        store(uuid, "repo:merge", "merge".to_string(), None, Some(start - chrono::Duration::minutes(1)), false).await;
+
+        let (mut rx, history) = subscribe_pattern(uuid, "repo:*", &HistoryFilter::default()).await;
+        let pair = |channel: &str, msg: &str| (channel.to_string(), msg.to_string());
+        assert_eq!(history, vec![pair("repo:delta", "delta"), pair("repo:merge", "merge")]);
+        let (_, all) = subscribe_pattern(uuid, "*", &HistoryFilter { last: Some(2), since: None }).await;
+        assert_eq!(all, vec![pair("repo:delta", "delta"), pair("repo:merge", "merge")]);
+
+        // Channels created after subscribing are picked up; others skipped
+        emit_and_store(uuid, "team:alert", "alert".to_string()).await;
+        emit_and_store(uuid, "repo:new", "new".to_string()).await;
+        assert_eq!(rx.recv().await, Some(pair("repo:new", "new")));
+
+        // Other projects don't leak in
+        emit_and_store(Uuid::new_v4(), "repo:delta", "elsewhere".to_string()).await;
+        emit_and_store(uuid, "repo:delta", "again".to_string()).await;
+        assert_eq!(rx.recv().await, Some(pair("repo:delta", "again")));
+    }
+
+    #[tokio::test]
+    async fn test_lagged_subscribers_resume() {
+        use broadcast::error::RecvError;
+
+        let uuid = Uuid::new_v4();
+        let (mut rx, _) = subscribe_sequenced(uuid, "repo:delta", &HistoryFilter::none()).await;
+        let (mut agent_rx, _) = subscribe_as(uuid, "repo:delta", "agent-1", &HistoryFilter::none()).await;
+        let (mut pattern_rx, _) = subscribe_pattern(uuid, "repo:*", &HistoryFilter::none()).await;
+
+        // Nobody reads while the emits overrun the channel
+        let emitted = BROADCAST_CHANNEL_CAPACITY as u64 + 10;
+        for n in 1..=emitted {
+            emit_and_store(uuid, "repo:delta", format!("msg {}", n)).await;
+        }
+        assert_eq!(rx.recv().await, Err(RecvError::Lagged(10)));
+        assert_eq!(rx.recv().await, Ok((11, "msg 11".to_string())));
+        assert_eq!(agent_rx.recv_sequenced().await, Err(RecvError::Lagged(10)));
+        assert_eq!(agent_rx.recv_sequenced().await, Ok((11, "msg 11".to_string())));
+        assert_eq!(pattern_rx.recv_sequenced().await, Err(RecvError::Lagged(10)));
+        assert_eq!(pattern_rx.recv_sequenced().await, Ok((11, ("repo:delta".to_string(), "msg 11".to_string()))));
+
+        let stats = get_channel_stats(uuid).await;
+        assert_eq!(stats[0].latest_seq, emitted);
+    }
+
+    #[tokio::test]
+    async fn test_targeted_delivery() {
+        let uuid = Uuid::new_v4();
+        let topic = "team:task";
+        let (mut agent1, _) = subscribe_as(uuid, topic, "agent-1", &HistoryFilter::default()).await;
+        let (mut agent2, _) = subscribe_as(uuid, topic, "agent-2", &HistoryFilter::default()).await;
+        let (mut monitor, _) = subscribe_broadcast(uuid, topic).await;
+
+        // Targeted: the matching agent and the monitor
+        assert_eq!(emit_to(uuid, topic, "for 1".to_string(), Some("agent-1".to_string())).await, 2);
+        // Untargeted: everyone
+        assert_eq!(emit_and_store(uuid, topic, "all".to_string()).await, 3);
+        assert_eq!(agent1.recv().await.unwrap(), "for 1");
+        assert_eq!(agent1.recv().await.unwrap(), "all");
+        assert_eq!(agent2.recv().await.unwrap(), "all");
+        assert_eq!(monitor.recv().await.unwrap(), "for 1");
+        assert_eq!(monitor.recv().await.unwrap(), "all");
+
+        // No matching subscriber: only the monitor, but it is buffered for
+        // the agent to find in its history
+        drop(agent1);
+        assert_eq!(emit_to(uuid, topic, "for 1 later".to_string(), Some("agent-1".to_string())).await, 1);
+        let (_, history) = subscribe_as(uuid, topic, "agent-1", &HistoryFilter::default()).await;
+        assert_eq!(history, vec!["for 1", "all", "for 1 later"]);
+        let (_, history) = subscribe_as(uuid, topic, "agent-2", &HistoryFilter::default()).await;
+        assert_eq!(history, vec!["all"]);
+
+        let (_, history) = subscribe_pattern_as(uuid, "team:*", "agent-2", &HistoryFilter::default()).await;
+        assert_eq!(history, vec![(topic.to_string(), "all".to_string())]);
+    }
+
+    fn alias(project_uuid: Uuid, old: &str, new: &str) {
+        set_aliases(project_uuid, BTreeMap::from([(old.to_string(), new.to_string())]));
+    }
+
+    #[tokio::test]
+    async fn test_alias_emit_old_subscribe_new() {
+        let uuid = Uuid::new_v4();
+        alias(uuid, "repo:delta", "repo:changes");
+
+        let (mut rx, _) = subscribe_broadcast(uuid, "repo:changes").await;
+        emit_and_store(uuid, "repo:delta", "via old".to_string()).await;
+        assert_eq!(rx.recv().await.unwrap(), "via old");
+        assert_eq!(list_channels(uuid).await, vec!["repo:changes"]);
+    }
+
+    #[tokio::test]
+    async fn test_alias_emit_new_subscribe_old() {
+        let uuid = Uuid::new_v4();
+        alias(uuid, "repo:delta", "repo:changes");
+
+        let (mut rx, _) = subscribe_broadcast(uuid, "repo:delta").await;
+        emit_and_store(uuid, "repo:changes", "via new".to_string()).await;
+        assert_eq!(rx.recv().await.unwrap(), "via new");
+    }
+
+    #[tokio::test]
+    async fn test_alias_shares_one_history() {
+        let uuid = Uuid::new_v4();
+        alias(uuid, "repo:delta", "repo:changes");
+
+        emit_and_store(uuid, "repo:delta", "one".to_string()).await;
+        emit_and_store(uuid, "repo:changes", "two".to_string()).await;
+        emit_and_store(uuid, "repo:delta", "three".to_string()).await;
+
+        let (_, via_old) = subscribe_broadcast(uuid, "repo:delta").await;
+        let (_, via_new) = subscribe_broadcast(uuid, "repo:changes").await;
+        assert_eq!(via_old, vec!["one", "two", "three"]);
+        assert_eq!(via_new, via_old);
+        let stats = get_channel_stats(uuid).await;
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].replay_buffer_size, 3);
+    }
+
+    #[tokio::test]
+    async fn test_alias_uses_counted_per_alias() {
+        let uuid = Uuid::new_v4();
+        alias(uuid, "repo:delta", "repo:changes");
+
+        let resolved = resolve_channel(uuid, "repo:delta");
+        assert_eq!(resolved, ResolvedChannel {
+            channel: "repo:changes".to_string(),
+            alias: Some("repo:delta".to_string()),
+        });
+        emit_and_store(uuid, &resolved.channel, "m".to_string()).await;
+        resolve_channel(uuid, "repo:delta");
+        // The canonical name and unrelated channels aren't alias uses
+        assert_eq!(resolve_channel(uuid, "repo:changes").alias, None);
+        assert_eq!(resolve_channel(uuid, "team:alert").channel, "team:alert");
+
+        assert_eq!(list_aliases(uuid), vec![AliasInfo {
+            alias: "repo:delta".to_string(),
+            channel: "repo:changes".to_string(),
+            uses: 2,
+        }]);
+        let stats = get_channel_stats(uuid).await;
+        assert_eq!(stats[0].channel, "repo:changes");
+        assert_eq!(stats[0].aliases, vec!["repo:delta"]);
+        assert_eq!(stats[0].alias_uses, 2);
+
+        // Counts survive a reload; a retired alias stops resolving
+        alias(uuid, "repo:delta", "repo:changes");
+        assert_eq!(list_aliases(uuid)[0].uses, 2);
+        set_aliases(uuid, BTreeMap::new());
+        assert_eq!(resolve_channel(uuid, "repo:delta").alias, None);
+        assert!(list_aliases(uuid).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_alias_reload_moves_the_old_channel() {
+        let uuid = Uuid::new_v4();
+        let (mut rx, _) = subscribe_sequenced(uuid, "repo:delta", &HistoryFilter::default()).await;
+        emit_and_store(uuid, "repo:delta", "before".to_string()).await;
+        assert_eq!(rx.recv().await.unwrap(), (1, "before".to_string()));
+
+        alias(uuid, "repo:delta", "repo:changes");
+        assert_eq!(list_channels(uuid).await, vec!["repo:changes"]);
+        assert_eq!(emit_and_store(uuid, "repo:changes", "after".to_string()).await, 1);
+        assert_eq!(rx.recv().await.unwrap(), (2, "after".to_string()));
+        let (_, history) = subscribe_broadcast(uuid, "repo:changes").await;
+        assert_eq!(history, vec!["before", "after"]);
+    }
+
+    #[tokio::test]
+    async fn test_alias_reload_merges_into_the_canonical_channel() {
+        let uuid = Uuid::new_v4();
+        let (mut old_rx, _) = subscribe_sequenced(uuid, "repo:delta", &HistoryFilter::default()).await;
+        let (mut new_rx, _) = subscribe_broadcast(uuid, "repo:changes").await;
+        emit_and_store(uuid, "repo:delta", "old one".to_string()).await;
+        emit_and_store(uuid, "repo:changes", "new one".to_string()).await;
+        emit_and_store(uuid, "repo:delta", "old two".to_string()).await;
+        old_rx.recv().await.unwrap();
+        old_rx.recv().await.unwrap();
+        new_rx.recv().await.unwrap();
+
+        alias(uuid, "repo:delta", "repo:changes");
+        assert_eq!(list_channels(uuid).await, vec!["repo:changes"]);
+        let (_, history) = subscribe_broadcast(uuid, "repo:delta").await;
+        assert_eq!(history, vec!["old one", "new one", "old two"]);
+
+        // Both sides still get messages, numbered past either's last
+        assert_eq!(emit_and_store(uuid, "repo:delta", "merged".to_string()).await, 2);
+        assert_eq!(old_rx.recv().await.unwrap(), (3, "merged".to_string()));
+        assert_eq!(new_rx.recv().await.unwrap(), "merged");
+        let stats = get_channel_stats(uuid).await;
+        assert_eq!((stats.len(), stats[0].subscriber_count), (1, 2));
+    }
+
     // ============ STRESS TESTS ============
 
     #[tokio::test]
@@ -378,14 +1782,11 @@ mod tests {
         // Spawn receiver task
         let receiver_handle = tokio::spawn(async move {
             let mut count = 0;
-            loop {
-                match tokio::time::timeout(
-                    std::time::Duration::from_millis(500),
-                    rx.recv()
-                ).await {
-                    Ok(Ok(_)) => count += 1,
-                    _ => break,
-                }
+            while let Ok(Ok(_)) = tokio::time::timeout(
+                std::time::Duration::from_millis(500),
+                rx.recv()
+            ).await {
+                count += 1;
             }
             received_clone.store(count, Ordering::SeqCst);
         });
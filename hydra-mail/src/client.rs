@@ -0,0 +1,641 @@
+//! Client for the daemon socket.
+//!
+//! The requests and responses the daemon speaks, shared by both ends, and
+//! [`MailClient`] for other crates: it finds the socket from the project
+//! config, TOON-encodes pulses on the way out and decodes them on the way
+//! in. The async methods are for tokio code; sync callers such as the
+//! orchestrator and hydra-wt use the `_blocking` ones, which send the same
+//! requests over a std socket.
+
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose, Engine as _};
+use chrono::{DateTime, Utc};
+use futures_core::Stream;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{ready, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::UnixStream;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::constants::{HISTORY_END_LINE, MAX_MESSAGE_SIZE, PING_TIMEOUT_SECS};
+use crate::pulse;
+use crate::trace::TraceContext;
+
+/// A decoded pulse, as built by [`pulse::new_pulse`]
+pub type Pulse = Value;
+
+/// How long the `_blocking` calls wait on the daemon
+const BLOCKING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A request to the daemon: one JSON line, its `cmd` naming what it is
+pub trait Request: Serialize {
+    const CMD: &'static str;
+    type Response: DeserializeOwned;
+
+    /// The request as sent, with its `cmd`
+    fn command(&self) -> Value {
+        let mut command = serde_json::to_value(self).unwrap_or_default();
+        command["cmd"] = json!(Self::CMD);
+        command
+    }
+}
+
+/// Emit one pulse
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EmitRequest {
+    /// A channel or alias; not a pattern
+    pub channel: String,
+    /// Always `toon`
+    #[serde(default = "toon_format")]
+    pub format: String,
+    /// The TOON pulse, base64
+    pub data: String,
+    /// The pulse's `metadata.target`, duplicated so routing never decodes TOON
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// Hold the pulse until then
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deliver_at: Option<DateTime<Utc>>,
+}
+
+impl EmitRequest {
+    /// Encode `pulse` for `channel`, refusing one over [`MAX_MESSAGE_SIZE`]
+    pub fn new(channel: &str, pulse: &Pulse) -> Result<Self> {
+        let toon = pulse::encode_pulse(pulse)?;
+        if toon.len() > MAX_MESSAGE_SIZE {
+            anyhow::bail!("Message too large: {} bytes (max {} bytes)", toon.len(), MAX_MESSAGE_SIZE);
+        }
+        Ok(Self {
+            channel: channel.to_string(),
+            format: toon_format(),
+            data: general_purpose::STANDARD.encode(toon.as_bytes()),
+            target: pulse["metadata"]["target"].as_str().map(str::to_string),
+            deliver_at: None,
+        })
+    }
+}
+
+fn toon_format() -> String {
+    "toon".to_string()
+}
+
+impl Request for EmitRequest {
+    const CMD: &'static str = "emit";
+    type Response = EmitAck;
+}
+
+/// The daemon's answer to an emit it took
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EmitAck {
+    /// Where the pulse went, after alias resolution
+    pub channel: String,
+    /// The name the emit used, when it was an alias of `channel`
+    #[serde(default)]
+    pub alias: Option<String>,
+    /// Size of the TOON pulse in bytes
+    #[serde(default)]
+    pub size: usize,
+    /// Subscribers it reached; none yet when it is scheduled
+    #[serde(default)]
+    pub receivers: usize,
+    /// Held for later delivery
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub scheduled: bool,
+    /// Cancels a scheduled delivery
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deliver_at: Option<DateTime<Utc>>,
+    /// Taken, but something looks off, e.g. `unknown namespace` for a
+    /// channel outside the project's channel policy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+}
+
+/// Subscribe to a channel, alias or pattern
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SubscribeRequest {
+    pub channel: String,
+    #[serde(flatten)]
+    pub opts: SubscribeOptions,
+    /// Send each message as one line, a JSON string of its TOON
+    #[serde(default)]
+    pub framed: bool,
+    /// Send [`HISTORY_END_LINE`] between the history and the live messages;
+    /// framed streams only
+    #[serde(default)]
+    pub mark_history: bool,
+}
+
+/// What a subscriber gets besides live messages, and who it is
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SubscribeOptions {
+    /// Replay the history first
+    #[serde(default = "replay_history")]
+    pub history: bool,
+    /// Only the last N messages of the history
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last: Option<usize>,
+    /// Only history newer than this
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<DateTime<Utc>>,
+    /// Leaves out messages targeted at other agents, and lists the
+    /// subscriber in `agents`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+fn replay_history() -> bool {
+    true
+}
+
+impl Default for SubscribeOptions {
+    fn default() -> Self {
+        Self {
+            history: true,
+            last: None,
+            since: None,
+            agent_id: None,
+            role: None,
+            description: None,
+        }
+    }
+}
+
+/// A channel's history, as a subscriber would get it
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HistoryRequest {
+    /// A channel, alias or pattern
+    pub channel: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<DateTime<Utc>>,
+}
+
+impl Request for HistoryRequest {
+    const CMD: &'static str = "history";
+    type Response = HistoryResponse;
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HistoryResponse {
+    /// Oldest first
+    pub messages: Vec<HistoryMessage>,
+    /// Messages left out for being older than the replay TTL
+    #[serde(default)]
+    pub expired: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryMessage {
+    pub channel: String,
+    /// The pulse, TOON
+    pub message: String,
+}
+
+#[derive(Serialize)]
+struct Ping;
+
+impl Request for Ping {
+    const CMD: &'static str = "ping";
+    type Response = Value;
+}
+
+/// A request the daemon turned down, e.g. over a rate limit. Connection
+/// failures are other errors, so a caller can tell a refusal, which
+/// sending again won't change, from a daemon that went away.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Refused {
+    pub msg: String,
+    /// When a rate limit has room again
+    pub retry_after: Option<Duration>,
+}
+
+impl fmt::Display for Refused {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+impl std::error::Error for Refused {}
+
+/// Parse a response line: the `ok` body, or [`Refused`]
+fn parse_response<T: DeserializeOwned>(line: &str) -> Result<T> {
+    let resp: Value = serde_json::from_str(line).context("Failed to parse response")?;
+    if resp["status"] != "ok" {
+        return Err(Refused {
+            msg: resp["msg"].as_str().unwrap_or("unknown error").to_string(),
+            retry_after: resp["retry_after_ms"].as_u64().map(Duration::from_millis),
+        }
+        .into());
+    }
+    serde_json::from_value(resp).context("Unexpected response")
+}
+
+/// One line of a framed subscribe stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    /// A message, TOON
+    Message(String),
+    /// `==> channel <==`, naming the channel of the message after it in a
+    /// pattern subscription
+    Header(String),
+    /// [`HISTORY_END_LINE`]
+    HistoryEnd,
+    /// The subscriber fell behind: `missed` messages were dropped for it,
+    /// and the next one it gets is number `resume_seq` of `channel`
+    Lagged { channel: String, missed: u64, resume_seq: u64 },
+}
+
+impl Frame {
+    /// Framed messages are JSON strings, so an object is a control line. A
+    /// line of an unframed stream is a message line as it came.
+    pub fn parse(line: &str) -> Self {
+        if line == HISTORY_END_LINE {
+            return Self::HistoryEnd;
+        }
+        if let Some(channel) = line.strip_prefix("==> ").and_then(|l| l.strip_suffix(" <==")) {
+            return Self::Header(channel.to_string());
+        }
+        if line.starts_with('{') {
+            if let Ok(notice) = serde_json::from_str::<Value>(line) {
+                if notice["status"] == "lagged" {
+                    return Self::Lagged {
+                        channel: notice["channel"].as_str().unwrap_or_default().to_string(),
+                        missed: notice["missed"].as_u64().unwrap_or(0),
+                        resume_seq: notice["resume_seq"].as_u64().unwrap_or(0),
+                    };
+                }
+            }
+        }
+        Self::Message(serde_json::from_str::<String>(line).unwrap_or_else(|_| line.to_string()))
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Message(toon) => write!(f, "{}", Value::String(toon.clone())),
+            Self::Header(channel) => write!(f, "==> {} <==", channel),
+            Self::HistoryEnd => f.write_str(HISTORY_END_LINE),
+            Self::Lagged { channel, missed, resume_seq } => write!(
+                f,
+                "{}",
+                json!({"status": "lagged", "channel": channel, "missed": missed, "resume_seq": resume_seq})
+            ),
+        }
+    }
+}
+
+/// Client for the daemon of one project
+#[derive(Debug, Clone)]
+pub struct MailClient {
+    project_root: PathBuf,
+    socket: PathBuf,
+}
+
+impl MailClient {
+    /// The client for the daemon of the project at `project_root`, once it
+    /// answers a ping: a socket file left behind by a daemon that died
+    /// doesn't count
+    pub async fn connect(project_root: &Path) -> Result<Self> {
+        let client = Self::locate(project_root)?;
+        tokio::time::timeout(Duration::from_secs(PING_TIMEOUT_SECS), client.ping())
+            .await
+            .context("hydra-mail daemon not responding (stale socket?)")??;
+        Ok(client)
+    }
+
+    /// [`MailClient::connect`] for sync callers
+    pub fn connect_blocking(project_root: &Path) -> Result<Self> {
+        let client = Self::locate(project_root)?;
+        client.ping_blocking().context("hydra-mail daemon not responding (stale socket?)")?;
+        Ok(client)
+    }
+
+    /// The client for the socket the project config names, without checking
+    /// that the daemon answers. A relative socket path is taken from the
+    /// project root.
+    pub fn locate(project_root: &Path) -> Result<Self> {
+        if !project_root.join(".hydra/config.toml").exists() {
+            anyhow::bail!("Hydra not initialized. Run: hydra-mail init");
+        }
+        let config = Config::load(project_root)?;
+        Ok(Self {
+            project_root: project_root.to_path_buf(),
+            socket: project_root.join(config.socket()),
+        })
+    }
+
+    pub fn project_root(&self) -> &Path {
+        &self.project_root
+    }
+
+    pub fn socket(&self) -> &Path {
+        &self.socket
+    }
+
+    /// Build a pulse and emit it. As with `hydra-mail emit`, the trace
+    /// context of the environment goes into metadata without a `trace_id`.
+    pub async fn emit(&self, channel: &str, pulse_type: &str, data: Value, metadata: Value) -> Result<EmitAck> {
+        self.emit_pulse(channel, &build_pulse(channel, pulse_type, data, metadata)).await
+    }
+
+    /// Emit a pulse as it is
+    pub async fn emit_pulse(&self, channel: &str, pulse: &Pulse) -> Result<EmitAck> {
+        self.call(&EmitRequest::new(channel, pulse)?).await
+    }
+
+    /// [`MailClient::emit`] for sync callers
+    pub fn emit_blocking(&self, channel: &str, pulse_type: &str, data: Value, metadata: Value) -> Result<EmitAck> {
+        self.emit_pulse_blocking(channel, &build_pulse(channel, pulse_type, data, metadata))
+    }
+
+    /// [`MailClient::emit_pulse`] for sync callers
+    pub fn emit_pulse_blocking(&self, channel: &str, pulse: &Pulse) -> Result<EmitAck> {
+        self.call_blocking(&EmitRequest::new(channel, pulse)?)
+    }
+
+    /// The pulses of `channel` (or of every channel matching a pattern), its
+    /// history first unless `opts` leaves it out, until the daemon shuts
+    /// down or hangs up
+    pub async fn subscribe(&self, channel: &str, opts: SubscribeOptions) -> Result<Subscription> {
+        let stream = self.open().await?;
+        let (reader, mut writer) = stream.into_split();
+        let request = SubscribeRequest {
+            channel: channel.to_string(),
+            opts,
+            framed: true,
+            mark_history: false,
+        };
+        writer.write_all(format!("{}\n", request.command()).as_bytes()).await
+            .context("Failed to send subscribe request")?;
+        writer.flush().await?;
+        Ok(Subscription {
+            lines: BufReader::new(reader).lines(),
+            _writer: writer,
+            channel: request.channel,
+            missed: 0,
+            done: false,
+        })
+    }
+
+    /// The history of `channel` (or of every channel matching a pattern),
+    /// oldest first. Messages that don't decode are left out.
+    pub async fn history(&self, channel: &str) -> Result<Vec<Pulse>> {
+        let request = HistoryRequest { channel: channel.to_string(), ..HistoryRequest::default() };
+        let resp = self.call(&request).await?;
+        Ok(resp
+            .messages
+            .into_iter()
+            .filter_map(|entry| match pulse::decode_pulse(&entry.message) {
+                Ok(pulse) => Some(pulse),
+                Err(e) => {
+                    warn!("could not decode message on {}, skipping it: {:#}", entry.channel, e);
+                    None
+                }
+            })
+            .collect())
+    }
+
+    /// The daemon's uptime and version
+    pub async fn ping(&self) -> Result<Value> {
+        self.call(&Ping).await
+    }
+
+    /// [`MailClient::ping`] for sync callers
+    pub fn ping_blocking(&self) -> Result<Value> {
+        self.call_blocking(&Ping)
+    }
+
+    async fn open(&self) -> Result<UnixStream> {
+        UnixStream::connect(&self.socket)
+            .await
+            .with_context(|| format!("Failed to connect to hydra-mail socket at {}", self.socket.display()))
+    }
+
+    /// Send `request` on a connection of its own and read the response
+    pub async fn call<R: Request>(&self, request: &R) -> Result<R::Response> {
+        let mut stream = self.open().await?;
+        let (reader, mut writer) = stream.split();
+        writer.write_all(format!("{}\n", request.command()).as_bytes()).await
+            .with_context(|| format!("Failed to send {} request", R::CMD))?;
+        writer.flush().await?;
+
+        let line = BufReader::new(reader).lines().next_line().await
+            .context("Failed to read response")?
+            .context("Daemon closed the connection without responding")?;
+        parse_response(&line)
+    }
+
+    /// One connection kept open for a run of requests, as `emit --batch`
+    /// and `import` send them: they share it, and so share the daemon's
+    /// rate limit windows, which are per connection
+    pub async fn connection(&self) -> Result<Connection> {
+        let (reader, writer) = self.open().await?.into_split();
+        Ok(Connection { writer, responses: BufReader::new(reader).lines() })
+    }
+
+    /// [`MailClient::call`] for sync callers, giving up after a few seconds
+    pub fn call_blocking<R: Request>(&self, request: &R) -> Result<R::Response> {
+        use std::io::{BufRead, Write};
+        use std::os::unix::net::UnixStream as StdUnixStream;
+
+        let mut stream = StdUnixStream::connect(&self.socket)
+            .with_context(|| format!("Failed to connect to hydra-mail socket at {}", self.socket.display()))?;
+        stream.set_read_timeout(Some(BLOCKING_TIMEOUT))?;
+        stream.set_write_timeout(Some(BLOCKING_TIMEOUT))?;
+        writeln!(stream, "{}", request.command())
+            .with_context(|| format!("Failed to send {} request", R::CMD))?;
+
+        let mut line = String::new();
+        std::io::BufReader::new(&stream).read_line(&mut line).context("Failed to read response")?;
+        if line.is_empty() {
+            anyhow::bail!("Daemon closed the connection without responding");
+        }
+        parse_response(&line)
+    }
+}
+
+/// The open end of [`MailClient::connection`]. Dropping it hangs up.
+pub struct Connection {
+    writer: OwnedWriteHalf,
+    responses: Lines<BufReader<OwnedReadHalf>>,
+}
+
+impl Connection {
+    /// [`MailClient::call`] on this connection. A refusal leaves it usable;
+    /// any other error means the daemon is gone.
+    pub async fn call<R: Request>(&mut self, request: &R) -> Result<R::Response> {
+        self.writer.write_all(format!("{}\n", request.command()).as_bytes()).await
+            .with_context(|| format!("Failed to send {} request", R::CMD))?;
+        self.writer.flush().await?;
+
+        let line = self.responses.next_line().await
+            .context("Failed to read response")?
+            .context("Daemon closed the connection")?;
+        parse_response(&line)
+    }
+}
+
+impl SubscribeRequest {
+    /// The request as sent. A subscribe has no single response, so it isn't
+    /// a [`Request`].
+    pub fn command(&self) -> Value {
+        let mut command = serde_json::to_value(self).unwrap_or_default();
+        command["cmd"] = json!("subscribe");
+        command
+    }
+}
+
+/// A pulse of `pulse_type` carrying the trace context of the environment
+/// unless `metadata` names a trace already
+fn build_pulse(channel: &str, pulse_type: &str, data: Value, mut metadata: Value) -> Pulse {
+    if metadata["trace_id"].is_null() {
+        if let Some(ctx) = TraceContext::from_env() {
+            ctx.attach(&mut metadata);
+        }
+    }
+    pulse::new_pulse(pulse_type, channel, data, metadata)
+}
+
+/// The live end of [`MailClient::subscribe`]: a stream of decoded pulses.
+/// Dropping it hangs up.
+pub struct Subscription {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    // The daemon takes a closed write half for a hang-up
+    _writer: OwnedWriteHalf,
+    channel: String,
+    missed: u64,
+    done: bool,
+}
+
+impl Subscription {
+    /// The next pulse, or `None` once the stream has ended
+    pub async fn next(&mut self) -> Option<Pulse> {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+
+    /// Messages the daemon dropped because this subscriber fell behind
+    pub fn missed(&self) -> u64 {
+        self.missed
+    }
+}
+
+impl Stream for Subscription {
+    type Item = Pulse;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Option<Pulse>> {
+        let this = self.get_mut();
+        while !this.done {
+            let line = match ready!(Pin::new(&mut this.lines).poll_next_line(cx)) {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("lost the subscription to {}: {}", this.channel, e);
+                    break;
+                }
+            };
+            match Frame::parse(&line) {
+                Frame::Message(toon) => match pulse::decode_pulse(&toon) {
+                    Ok(pulse) if pulse::is_shutdown(&pulse) => {
+                        debug!("daemon shut down, ending the subscription to {}", this.channel);
+                        break;
+                    }
+                    Ok(pulse) => return Poll::Ready(Some(pulse)),
+                    Err(e) => warn!("could not decode message on {}, skipping it: {:#}", this.channel, e),
+                },
+                Frame::Lagged { channel, missed, resume_seq } => {
+                    warn!("fell behind: {} message(s) dropped, resuming at {} #{}", missed, channel, resume_seq);
+                    this.missed += missed;
+                }
+                Frame::Header(_) | Frame::HistoryEnd => {}
+            }
+        }
+        this.done = true;
+        Poll::Ready(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_emit_request_round_trip() {
+        let pulse = pulse::new_pulse("status", "team:status", json!({"ok": true}), json!({"target": "agent-1"}));
+        let request = EmitRequest::new("team:status", &pulse).unwrap();
+        assert_eq!(request.target.as_deref(), Some("agent-1"));
+
+        let command = request.command();
+        assert_eq!(command["cmd"], "emit");
+        assert!(command.get("deliver_at").is_none());
+        assert_eq!(EmitRequest::deserialize(&command).unwrap(), request);
+
+        let toon = general_purpose::STANDARD.decode(&request.data).unwrap();
+        assert_eq!(pulse::decode_pulse(std::str::from_utf8(&toon).unwrap()).unwrap()["data"]["ok"], true);
+
+        let big = pulse::new_pulse("status", "team:status", json!("x".repeat(MAX_MESSAGE_SIZE)), Value::Null);
+        assert!(EmitRequest::new("team:status", &big).is_err());
+    }
+
+    #[test]
+    fn test_responses() {
+        let ack: EmitAck = parse_response(r#"{"status":"ok","format":"toon","size":42,"receivers":2,"channel":"team:status","alias":null}"#).unwrap();
+        assert_eq!((ack.channel.as_str(), ack.receivers, ack.scheduled), ("team:status", 2, false));
+
+        let err = parse_response::<EmitAck>(r#"{"status":"error","msg":"Rate limit exceeded on x: 1/s","retry_after_ms":250}"#)
+            .unwrap_err();
+        let refused = err.downcast_ref::<Refused>().unwrap();
+        assert_eq!(refused.retry_after, Some(Duration::from_millis(250)));
+        assert!(err.to_string().starts_with("Rate limit exceeded"));
+    }
+
+    #[test]
+    fn test_subscribe_request_defaults() {
+        let request = SubscribeRequest::deserialize(&json!({"cmd": "subscribe", "channel": "repo:*"})).unwrap();
+        assert!(request.opts.history && !request.framed);
+        assert_eq!(request.opts.agent_id, None);
+
+        let request = SubscribeRequest {
+            channel: "team:alert".into(),
+            opts: SubscribeOptions { history: false, last: Some(3), ..Default::default() },
+            framed: true,
+            mark_history: false,
+        };
+        let command = request.command();
+        assert_eq!((command["cmd"].as_str(), command["history"].as_bool(), command["last"].as_u64()), (Some("subscribe"), Some(false), Some(3)));
+        assert_eq!(SubscribeRequest::deserialize(&command).unwrap(), request);
+    }
+
+    #[test]
+    fn test_frames_round_trip() {
+        let frames = [
+            Frame::Message("id: 1\ntype: status".into()),
+            Frame::Header("repo:delta".into()),
+            Frame::HistoryEnd,
+            Frame::Lagged { channel: "repo:delta".into(), missed: 7, resume_seq: 42 },
+        ];
+        for frame in frames {
+            assert_eq!(Frame::parse(&frame.to_string()), frame);
+        }
+        // An unframed line is a message line as it came
+        assert_eq!(Frame::parse("type: status"), Frame::Message("type: status".into()));
+    }
+}
@@ -1,11 +1,16 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs::{self, File};
 use std::io::Write;
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
+use tracing::debug;
 use uuid::Uuid;
 use crate::constants::HYDRA_DIR_PERMISSIONS;
+use crate::rate_limit::RateLimit;
+use crate::skills::Role;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Limits {
@@ -18,14 +23,47 @@ pub struct Limits {
     /// Broadcast channel capacity (default: 1024)
     #[serde(default = "default_broadcast_channel_capacity")]
     pub broadcast_channel_capacity: usize,
-    /// Rate limit: max messages per second per client (0 = unlimited)
+    /// Rate limit: max messages per second per client and channel, for
+    /// channels `rate_limits` has no entry for (0 = unlimited)
     #[serde(default)]
     pub rate_limit_per_second: usize,
+    /// Maximum scheduled messages pending per project (default: 1000)
+    #[serde(default = "default_max_scheduled_pending")]
+    pub max_scheduled_pending: usize,
+    /// Furthest ahead a message can be scheduled, in seconds (default: 7 days)
+    #[serde(default = "default_max_schedule_horizon_secs")]
+    pub max_schedule_horizon_secs: u64,
+    /// Drop a channel, with its replay buffer, after this long without
+    /// subscribers or emits, in seconds (default: 1 day, 0 = never)
+    #[serde(default = "default_channel_ttl_secs")]
+    pub channel_ttl_secs: u64,
+    /// Leave buffered messages older than this, in seconds, out of the
+    /// history late subscribers get, for channels `replay_ttls` has no entry
+    /// for (0 = keep them for good)
+    #[serde(default)]
+    pub replay_ttl_secs: u64,
+    /// Buffered message text per channel, in bytes, past which the oldest
+    /// messages are evicted like past `replay_buffer_capacity` (default: 1MB,
+    /// 0 = count only)
+    #[serde(default = "default_replay_max_bytes_per_channel")]
+    pub replay_max_bytes_per_channel: usize,
+    /// Rate limits by channel or pattern, e.g. `"repo:*" = "50/s"`; the
+    /// most specific match applies
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rate_limits: BTreeMap<String, RateLimit>,
+    /// Replay TTLs in seconds by channel or pattern, e.g. `"repo:delta" = 600`;
+    /// the most specific match applies
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub replay_ttls: BTreeMap<String, u64>,
 }
 
 fn default_max_message_size() -> usize { crate::constants::MAX_MESSAGE_SIZE }
 fn default_replay_buffer_capacity() -> usize { crate::constants::REPLAY_BUFFER_CAPACITY }
 fn default_broadcast_channel_capacity() -> usize { crate::constants::BROADCAST_CHANNEL_CAPACITY }
+fn default_max_scheduled_pending() -> usize { crate::constants::MAX_SCHEDULED_PENDING }
+fn default_max_schedule_horizon_secs() -> u64 { crate::constants::MAX_SCHEDULE_HORIZON_SECS }
+fn default_channel_ttl_secs() -> u64 { crate::constants::CHANNEL_TTL_SECS }
+fn default_replay_max_bytes_per_channel() -> usize { crate::constants::REPLAY_MAX_BYTES_PER_CHANNEL }
 
 impl Default for Limits {
     fn default() -> Self {
@@ -34,54 +72,392 @@ impl Default for Limits {
             replay_buffer_capacity: default_replay_buffer_capacity(),
             broadcast_channel_capacity: default_broadcast_channel_capacity(),
             rate_limit_per_second: 0,
+            max_scheduled_pending: default_max_scheduled_pending(),
+            max_schedule_horizon_secs: default_max_schedule_horizon_secs(),
+            channel_ttl_secs: default_channel_ttl_secs(),
+            replay_ttl_secs: 0,
+            replay_max_bytes_per_channel: default_replay_max_bytes_per_channel(),
+            rate_limits: BTreeMap::new(),
+            replay_ttls: BTreeMap::new(),
+        }
+    }
+}
+
+impl Limits {
+    /// `max_schedule_horizon_secs` as a duration, or `None` if it is too
+    /// large for one
+    pub fn schedule_horizon(&self) -> Option<chrono::Duration> {
+        i64::try_from(self.max_schedule_horizon_secs).ok().and_then(chrono::Duration::try_seconds)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.schedule_horizon().is_none() {
+            anyhow::bail!(
+                "max_schedule_horizon_secs = {} is out of range (max {})",
+                self.max_schedule_horizon_secs,
+                chrono::Duration::MAX.num_seconds()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Daemon-side message archive (`[archive]`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveConfig {
+    /// SQLite database every accepted emit is copied to, relative to the
+    /// project root (e.g. ".hydra/mail.db"). Needs the `sqlite` feature.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sqlite: Option<PathBuf>,
+    /// Oldest messages are pruned once the data exceeds this (default: 256 MB)
+    #[serde(default = "default_archive_max_size_mb")]
+    pub max_size_mb: u64,
+    /// Messages waiting for the writer before new ones are dropped (default: 4096)
+    #[serde(default = "default_archive_queue_capacity")]
+    pub queue_capacity: usize,
+}
+
+fn default_archive_max_size_mb() -> u64 { crate::constants::ARCHIVE_MAX_SIZE_MB }
+fn default_archive_queue_capacity() -> usize { crate::constants::ARCHIVE_QUEUE_CAPACITY }
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        Self {
+            sqlite: None,
+            max_size_mb: default_archive_max_size_mb(),
+            queue_capacity: default_archive_queue_capacity(),
+        }
+    }
+}
+
+impl ArchiveConfig {
+    fn is_disabled(&self) -> bool {
+        self.sqlite.is_none()
+    }
+
+    /// Database path for a project, if the archive is enabled
+    pub fn sqlite_path(&self, project_root: &Path) -> Option<PathBuf> {
+        self.sqlite.as_ref().map(|p| project_root.join(p))
+    }
+}
+
+/// Replay history kept across daemon restarts (`[persistence]`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PersistenceConfig {
+    /// Log every message to `.hydra/messages.log` and replay it into the
+    /// replay buffers on start (default: true)
+    #[serde(default = "default_persistence_enabled")]
+    pub enabled: bool,
+    /// Compact the log to the last `replay_buffer_capacity` messages per
+    /// channel once it grows past this (default: 1024 KB); it is also
+    /// compacted every 10 minutes
+    #[serde(default = "default_compact_above_kb")]
+    pub compact_above_kb: u64,
+}
+
+fn default_persistence_enabled() -> bool { true }
+fn default_compact_above_kb() -> u64 { crate::constants::MESSAGE_LOG_COMPACT_ABOVE_KB }
+
+impl Default for PersistenceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_persistence_enabled(),
+            compact_above_kb: default_compact_above_kb(),
+        }
+    }
+}
+
+impl PersistenceConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Channel naming (`[channels]`)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChannelsConfig {
+    /// Old channel name -> the name it was renamed to. Emits and subscribes
+    /// on either name share one channel, stored under the new name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub aliases: BTreeMap<String, String>,
+}
+
+impl ChannelsConfig {
+    fn is_empty(&self) -> bool {
+        self.aliases.is_empty()
+    }
+
+    /// Reject aliases that point at other aliases (including cycles), so
+    /// every name resolves in one step
+    pub fn validate(&self) -> Result<()> {
+        for (alias, target) in &self.aliases {
+            if alias.trim().is_empty() || target.trim().is_empty() {
+                anyhow::bail!("Channel alias names cannot be empty");
+            }
+            if !self.aliases.contains_key(target) {
+                continue;
+            }
+            // Walk the chain to tell a cycle from a plain chain
+            let mut chain = vec![alias.as_str()];
+            let mut next = target.as_str();
+            while let Some(after) = self.aliases.get(next) {
+                if chain.contains(&next) {
+                    chain.push(next);
+                    anyhow::bail!("Channel aliases form a cycle: {}", chain.join(" → "));
+                }
+                chain.push(next);
+                next = after;
+            }
+            chain.push(next);
+            anyhow::bail!(
+                "Channel alias '{}' points at another alias ({}); point it at '{}' directly",
+                alias, chain.join(" → "), next
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Which namespaces channels are expected in (`[channel_policy]`), so a
+/// typo like `rep:delta` doesn't quietly start a channel nobody reads
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelPolicy {
+    /// What comes before the first `:` of a channel name, e.g. `repo` of
+    /// `repo:delta` (default: the namespaces hydra tools use)
+    #[serde(default = "default_namespaces")]
+    pub namespaces: Vec<String>,
+    /// What an emit outside them gets (default: warn)
+    #[serde(default)]
+    pub enforce: Enforcement,
+}
+
+/// How [`ChannelPolicy`] treats an emit to an unknown namespace
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Enforcement {
+    /// Emit it, with a warning in the response
+    #[default]
+    Warn,
+    /// Refuse it
+    Reject,
+    /// Don't check
+    Off,
+}
+
+fn default_namespaces() -> Vec<String> {
+    // Past the documented five, the ones hydra-wt, the orchestrator and
+    // `request` emit to
+    ["repo", "team", "agent", "wt", "session", "health", "reply", "sys", "merge"]
+        .map(String::from)
+        .to_vec()
+}
+
+impl Default for ChannelPolicy {
+    fn default() -> Self {
+        Self { namespaces: default_namespaces(), enforce: Enforcement::default() }
+    }
+}
+
+impl ChannelPolicy {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Whether `channel` is outside the namespaces while the policy is on
+    pub fn flags(&self, channel: &str) -> bool {
+        if self.enforce == Enforcement::Off {
+            return false;
         }
+        !channel.split_once(':').is_some_and(|(namespace, _)| self.namespaces.iter().any(|n| n == namespace))
     }
 }
 
+/// A command the daemon runs for every message on matching channels
+/// (`[[hooks]]`), with the decoded message on stdin and `HYDRA_CHANNEL` set
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HookConfig {
+    /// Channel name, or a pattern such as `team:*`
+    pub channel_pattern: String,
+    /// Run via `sh -c` in the project root
+    pub command: String,
+    /// Runs at a time; a message arriving while all are busy skips the hook
+    /// (default: 2)
+    #[serde(default = "default_hook_max_concurrent")]
+    pub max_concurrent: usize,
+    /// Kill a run after this long (default: 30s, 0 = never)
+    #[serde(default = "default_hook_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_hook_max_concurrent() -> usize { crate::constants::HOOK_MAX_CONCURRENT }
+fn default_hook_timeout_secs() -> u64 { crate::constants::HOOK_TIMEOUT_SECS }
+
+impl HookConfig {
+    pub fn matches(&self, channel: &str) -> bool {
+        crate::channels::pattern_matches(&self.channel_pattern, channel)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.channel_pattern.trim().is_empty() || self.command.trim().is_empty() {
+            anyhow::bail!("Hooks need a channel_pattern and a command");
+        }
+        if self.max_concurrent == 0 {
+            anyhow::bail!("Hook '{}' has max_concurrent = 0, so it would never run", self.command);
+        }
+        Ok(())
+    }
+}
+
+/// What decided where the daemon's socket is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketSource {
+    /// The `HYDRA_SOCKET` environment variable
+    Env,
+    /// `socket_dir` in config.toml
+    SocketDir,
+    /// `socket_path` in config.toml
+    SocketPath,
+}
+
+impl fmt::Display for SocketSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SocketSource::Env => "HYDRA_SOCKET",
+            SocketSource::SocketDir => "socket_dir in config.toml",
+            SocketSource::SocketPath => "socket_path in config.toml",
+        })
+    }
+}
+
+/// `$XDG_RUNTIME_DIR/hydra`, where new projects put their socket when there
+/// is a runtime directory: it is local, private to the user and never inside
+/// a repository
+pub fn default_socket_dir() -> Option<PathBuf> {
+    let runtime_dir = PathBuf::from(std::env::var_os("XDG_RUNTIME_DIR")?);
+    (runtime_dir.is_absolute() && runtime_dir.is_dir()).then(|| runtime_dir.join("hydra"))
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
     pub project_uuid: Uuid,
+    /// The daemon's socket, unless `socket_dir` is set; kept in step with it
+    /// for tools that read config.toml themselves
     pub socket_path: PathBuf,
+    /// Directory the socket goes in as `<project-uuid>.sock`, taking
+    /// precedence over `socket_path`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub socket_dir: Option<PathBuf>,
     pub default_topics: Vec<String>,
+    /// Agent roles with generated skill variants; the first is the default HYDRA_ROLE
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<Role>,
     #[serde(default)]
     pub limits: Limits,
+    #[serde(default, skip_serializing_if = "ArchiveConfig::is_disabled")]
+    pub archive: ArchiveConfig,
+    #[serde(default, skip_serializing_if = "ChannelsConfig::is_empty")]
+    pub channels: ChannelsConfig,
+    #[serde(default, skip_serializing_if = "PersistenceConfig::is_default")]
+    pub persistence: PersistenceConfig,
+    #[serde(default, skip_serializing_if = "ChannelPolicy::is_default")]
+    pub channel_policy: ChannelPolicy,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hooks: Vec<HookConfig>,
 }
 
 impl Config {
+    /// Initialize `.hydra/`, with the socket in [`default_socket_dir`] if
+    /// there is one and in `.hydra/` otherwise
     pub fn init(project_root: &Path) -> Result<Self> {
+        Self::init_with_socket_dir(project_root, default_socket_dir())
+    }
+
+    /// [`Config::init`] with the socket in `socket_dir`, or at
+    /// `.hydra/hydra.sock` without one
+    pub fn init_with_socket_dir(project_root: &Path, socket_dir: Option<PathBuf>) -> Result<Self> {
         let hydra_dir = project_root.join(".hydra");
         fs::create_dir_all(&hydra_dir).context("Failed to create .hydra directory")?;
         fs::set_permissions(&hydra_dir, fs::Permissions::from_mode(HYDRA_DIR_PERMISSIONS))
             .context("Failed to set .hydra permissions")?;
 
         let project_uuid = Uuid::new_v4();
-        // Use absolute path for socket to avoid issues with daemon cwd
-        let socket_path = hydra_dir.canonicalize()
-            .unwrap_or_else(|_| hydra_dir.to_path_buf())
-            .join("hydra.sock");
+        // Use absolute paths for the socket to avoid issues with daemon cwd
+        let absolute = |dir: &Path| dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+        let socket_dir = match socket_dir {
+            Some(dir) => {
+                create_socket_dir(&dir)?;
+                Some(absolute(&dir))
+            }
+            None => None,
+        };
+        let socket_path = match &socket_dir {
+            Some(dir) => socket_in(dir, project_uuid),
+            None => absolute(&hydra_dir).join("hydra.sock"),
+        };
 
         let config = Config {
             project_uuid,
             socket_path,
+            socket_dir,
             default_topics: vec![
                 "repo:delta".to_string(),
                 "agent:presence".to_string(),
             ],
+            roles: Vec::new(),
             limits: Limits::default(),
+            archive: ArchiveConfig::default(),
+            channels: ChannelsConfig::default(),
+            persistence: PersistenceConfig::default(),
+            channel_policy: ChannelPolicy::default(),
+            hooks: Vec::new(),
         };
+        config.save(project_root)?;
+
+        Ok(config)
+    }
 
-        let config_path = hydra_dir.join("config.toml");
-        let toml_str = toml::to_string(&config).context("Failed to serialize config to TOML")?;
+    pub fn save(&self, project_root: &Path) -> Result<()> {
+        let config_path = project_root.join(".hydra").join("config.toml");
+        let toml_str = toml::to_string(self).context("Failed to serialize config to TOML")?;
         let mut file = File::create(&config_path).context("Failed to create config.toml")?;
         file.write_all(toml_str.as_bytes()).context("Failed to write config.toml")?;
-
-        Ok(config)
+        debug!("wrote {}", config_path.display());
+        Ok(())
     }
 
     pub fn load(project_root: &Path) -> Result<Self> {
         let config_path = project_root.join(".hydra").join("config.toml");
         let config_str = fs::read_to_string(&config_path).context("Failed to read config.toml")?;
-        toml::from_str(&config_str).context("Failed to parse config.toml")
+        debug!("config: loaded {}", config_path.display());
+        let config: Config = toml::from_str(&config_str).context("Failed to parse config.toml")?;
+        config.limits.validate().context("Invalid [limits] in config.toml")?;
+        config.channels.validate().context("Invalid [channels.aliases] in config.toml")?;
+        for hook in &config.hooks {
+            hook.validate().context("Invalid [[hooks]] in config.toml")?;
+        }
+        Ok(config)
+    }
+
+    /// The daemon's socket: `HYDRA_SOCKET` if set, else `<socket_dir>/<project-uuid>.sock`,
+    /// else `socket_path`
+    pub fn socket(&self) -> PathBuf {
+        self.socket_location().0
+    }
+
+    /// [`Config::socket`] and what decided it
+    pub fn socket_location(&self) -> (PathBuf, SocketSource) {
+        match std::env::var_os("HYDRA_SOCKET").filter(|path| !path.is_empty()) {
+            Some(path) => (PathBuf::from(path), SocketSource::Env),
+            None => self.configured_socket(),
+        }
+    }
+
+    /// The socket config.toml names, ignoring `HYDRA_SOCKET`
+    fn configured_socket(&self) -> (PathBuf, SocketSource) {
+        match &self.socket_dir {
+            Some(dir) => (socket_in(dir, self.project_uuid), SocketSource::SocketDir),
+            None => (self.socket_path.clone(), SocketSource::SocketPath),
+        }
     }
 
     /// Generate config.sh for shell integration
@@ -93,10 +469,35 @@ impl Config {
 export HYDRA_UUID="{}"
 export HYDRA_SOCKET="{}"
 export HYDRA_FORMAT="toon"
-"#, self.project_uuid, self.socket_path.display())
+{}"#, self.project_uuid, self.configured_socket().0.display(), self.role_export())
+    }
+
+    /// Default role for agents that don't set HYDRA_ROLE themselves
+    fn role_export(&self) -> String {
+        match self.roles.first() {
+            Some(role) => format!("export HYDRA_ROLE=\"${{HYDRA_ROLE:-{}}}\"\n", role),
+            None => String::new(),
+        }
     }
 }
 
+/// A project's socket in `dir`
+fn socket_in(dir: &Path, project_uuid: Uuid) -> PathBuf {
+    dir.join(format!("{}.sock", project_uuid))
+}
+
+/// Create the directory sockets go in if missing, private to the user like
+/// `.hydra/`; an existing one is left as it is. Runtime directories are
+/// emptied on reboot, so the daemon calls this again before binding.
+pub fn create_socket_dir(dir: &Path) -> Result<()> {
+    if dir.is_dir() {
+        return Ok(());
+    }
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create socket directory {}", dir.display()))?;
+    fs::set_permissions(dir, fs::Permissions::from_mode(HYDRA_DIR_PERMISSIONS))
+        .with_context(|| format!("Failed to set permissions of socket directory {}", dir.display()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -107,8 +508,15 @@ mod tests {
         let config = Config {
             project_uuid: Uuid::parse_str("a1b2c3d4-e5f6-7890-abcd-ef1234567890").unwrap(),
             socket_path: PathBuf::from(".hydra/hydra.sock"),
+            socket_dir: None,
             default_topics: vec!["repo:delta".to_string(), "agent:presence".to_string()],
+            roles: vec![Role::Reviewer],
             limits: Limits::default(),
+            archive: ArchiveConfig::default(),
+            channels: ChannelsConfig::default(),
+            persistence: PersistenceConfig::default(),
+            channel_policy: ChannelPolicy::default(),
+            hooks: Vec::new(),
         };
 
         let toml_str = toml::to_string(&config).unwrap();
@@ -117,6 +525,7 @@ mod tests {
         assert_eq!(config.project_uuid, loaded.project_uuid);
         assert_eq!(config.socket_path, loaded.socket_path);
         assert_eq!(config.default_topics, loaded.default_topics);
+        assert_eq!(config.roles, loaded.roles);
         assert_eq!(config.limits.max_message_size, loaded.limits.max_message_size);
     }
 
@@ -125,12 +534,33 @@ mod tests {
         let temp = TempDir::new().unwrap();
         let project_root = temp.path();
 
-        let config = Config::init(project_root).unwrap();
+        let config = Config::init_with_socket_dir(project_root, None).unwrap();
         assert!(project_root.join(".hydra").exists());
 
         let loaded = Config::load(project_root).unwrap();
         assert_eq!(config.project_uuid, loaded.project_uuid);
         assert!(config.socket_path.starts_with(project_root.join(".hydra")));
+        assert_eq!(loaded.configured_socket(), (config.socket_path.clone(), SocketSource::SocketPath));
+        assert!(!toml::to_string(&loaded).unwrap().contains("socket_dir"));
+    }
+
+    #[test]
+    fn test_init_with_socket_dir() {
+        let temp = TempDir::new().unwrap();
+        let project_root = temp.path().join("project");
+        fs::create_dir_all(&project_root).unwrap();
+        let runtime = temp.path().join("runtime/hydra");
+
+        let config = Config::init_with_socket_dir(&project_root, Some(runtime.clone())).unwrap();
+        let expected = runtime.canonicalize().unwrap().join(format!("{}.sock", config.project_uuid));
+        assert_eq!(config.socket_path, expected);
+        assert_eq!(fs::metadata(&runtime).unwrap().permissions().mode() & 0o777, HYDRA_DIR_PERMISSIONS);
+        assert!(config.generate_config_sh().contains(&format!("HYDRA_SOCKET=\"{}\"", expected.display())));
+
+        // socket_dir wins over a socket_path edited out of step with it
+        let mut loaded = Config::load(&project_root).unwrap();
+        loaded.socket_path = PathBuf::from("/elsewhere/hydra.sock");
+        assert_eq!(loaded.configured_socket(), (expected, SocketSource::SocketDir));
     }
 
     #[test]
@@ -145,6 +575,165 @@ default_topics = ["repo:delta"]
         assert_eq!(loaded.limits.max_message_size, crate::constants::MAX_MESSAGE_SIZE);
         assert_eq!(loaded.limits.replay_buffer_capacity, crate::constants::REPLAY_BUFFER_CAPACITY);
         assert_eq!(loaded.limits.rate_limit_per_second, 0);
+        assert_eq!(loaded.limits.max_scheduled_pending, crate::constants::MAX_SCHEDULED_PENDING);
+        assert_eq!(loaded.limits.channel_ttl_secs, crate::constants::CHANNEL_TTL_SECS);
+        assert_eq!(loaded.limits.max_schedule_horizon_secs, crate::constants::MAX_SCHEDULE_HORIZON_SECS);
+        assert_eq!(loaded.limits.replay_max_bytes_per_channel, crate::constants::REPLAY_MAX_BYTES_PER_CHANNEL);
+        assert!(loaded.archive.sqlite.is_none());
+        assert!(loaded.persistence.enabled);
+    }
+
+    #[test]
+    fn test_config_persistence() {
+        let disabled = r#"
+project_uuid = "a1b2c3d4-e5f6-7890-abcd-ef1234567890"
+socket_path = ".hydra/hydra.sock"
+default_topics = ["repo:delta"]
+
+[persistence]
+enabled = false
+"#;
+        let loaded: Config = toml::from_str(disabled).unwrap();
+        assert!(!loaded.persistence.enabled);
+        assert_eq!(loaded.persistence.compact_above_kb, crate::constants::MESSAGE_LOG_COMPACT_ABOVE_KB);
+        assert!(toml::to_string(&loaded).unwrap().contains("[persistence]"));
+
+        // The default stays out of config.toml
+        let plain = Config { persistence: PersistenceConfig::default(), ..loaded };
+        assert!(!toml::to_string(&plain).unwrap().contains("persistence"));
+    }
+
+    #[test]
+    fn test_config_channel_policy() {
+        let strict = r#"
+project_uuid = "a1b2c3d4-e5f6-7890-abcd-ef1234567890"
+socket_path = ".hydra/hydra.sock"
+default_topics = ["repo:delta"]
+
+[channel_policy]
+namespaces = ["repo", "ci"]
+enforce = "reject"
+"#;
+        let loaded: Config = toml::from_str(strict).unwrap();
+        let policy = &loaded.channel_policy;
+        assert_eq!(policy.enforce, Enforcement::Reject);
+        assert!(!policy.flags("ci:build"));
+        assert!(policy.flags("team:alert"));
+        // No namespace at all, or a typo of one
+        assert!(policy.flags("repo-delta"));
+        assert!(policy.flags("rep:delta"));
+
+        let default = ChannelPolicy::default();
+        assert_eq!(default.enforce, Enforcement::Warn);
+        assert!(["repo:delta", "team:alert", "agent:presence", "wt:created", "session:spawned"]
+            .iter()
+            .all(|channel| !default.flags(channel)));
+        assert!(default.flags("rep:delta"));
+        let off = ChannelPolicy { enforce: Enforcement::Off, ..default };
+        assert!(!off.flags("rep:delta"));
+
+        // The default stays out of config.toml
+        let plain = Config { channel_policy: ChannelPolicy::default(), ..loaded };
+        assert!(!toml::to_string(&plain).unwrap().contains("channel_policy"));
+    }
+
+    #[test]
+    fn test_config_hooks() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let mut config = Config::init_with_socket_dir(temp_dir.path(), None)?;
+        assert!(!fs::read_to_string(temp_dir.path().join(".hydra/config.toml"))?.contains("hooks"));
+
+        let hooks = r#"
+[[hooks]]
+channel_pattern = "team:alert"
+command = "notify-send hydra \"$(jq -r .data.message)\""
+
+[[hooks]]
+channel_pattern = "repo:*"
+command = "./scripts/on-delta.sh"
+max_concurrent = 1
+timeout_secs = 0
+"#;
+        let parsed: Config = toml::from_str(&format!("{}{}", toml::to_string(&config)?, hooks))?;
+        assert_eq!(parsed.hooks.len(), 2);
+        assert_eq!(parsed.hooks[0].max_concurrent, crate::constants::HOOK_MAX_CONCURRENT);
+        assert_eq!(parsed.hooks[0].timeout_secs, crate::constants::HOOK_TIMEOUT_SECS);
+        assert_eq!(parsed.hooks[1].timeout_secs, 0);
+        assert!(parsed.hooks[0].matches("team:alert"));
+        assert!(!parsed.hooks[0].matches("team:alerts"));
+        assert!(parsed.hooks[1].matches("repo:delta"));
+
+        // Saved and loaded back
+        config.hooks = parsed.hooks;
+        config.save(temp_dir.path())?;
+        assert_eq!(Config::load(temp_dir.path())?.hooks, config.hooks);
+
+        // A hook that could never run is a config error
+        config.hooks[1].max_concurrent = 0;
+        config.save(temp_dir.path())?;
+        let err = Config::load(temp_dir.path()).unwrap_err();
+        assert!(format!("{:#}", err).contains("max_concurrent = 0"), "{:#}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_archive() {
+        let with_archive = r#"
+project_uuid = "a1b2c3d4-e5f6-7890-abcd-ef1234567890"
+socket_path = ".hydra/hydra.sock"
+default_topics = ["repo:delta"]
+
+[archive]
+sqlite = ".hydra/mail.db"
+"#;
+        let loaded: Config = toml::from_str(with_archive).unwrap();
+        assert_eq!(loaded.archive.sqlite_path(Path::new("/proj")), Some(PathBuf::from("/proj/.hydra/mail.db")));
+        assert_eq!(loaded.archive.max_size_mb, crate::constants::ARCHIVE_MAX_SIZE_MB);
+        assert_eq!(loaded.archive.queue_capacity, crate::constants::ARCHIVE_QUEUE_CAPACITY);
+        assert!(toml::to_string(&loaded).unwrap().contains("[archive]"));
+
+        // Disabled archives stay out of config.toml
+        let plain = Config { archive: ArchiveConfig::default(), ..loaded };
+        assert!(!toml::to_string(&plain).unwrap().contains("archive"));
+    }
+
+    #[test]
+    fn test_config_channel_aliases() {
+        let temp = TempDir::new().unwrap();
+        let mut config = Config::init_with_socket_dir(temp.path(), None).unwrap();
+        assert!(!toml::to_string(&config).unwrap().contains("channels"));
+
+        config.channels.aliases.insert("repo:delta".into(), "repo:changes".into());
+        config.save(temp.path()).unwrap();
+        let saved = fs::read_to_string(temp.path().join(".hydra/config.toml")).unwrap();
+        assert!(saved.contains("[channels.aliases]"), "{}", saved);
+        let loaded = Config::load(temp.path()).unwrap();
+        assert_eq!(loaded.channels.aliases["repo:delta"], "repo:changes");
+    }
+
+    #[test]
+    fn test_chained_and_cyclic_aliases_rejected() {
+        let aliases = |pairs: &[(&str, &str)]| ChannelsConfig {
+            aliases: pairs.iter().map(|(a, b)| (a.to_string(), b.to_string())).collect(),
+        };
+
+        let chained = aliases(&[("a:old", "a:mid"), ("a:mid", "a:new")]).validate().unwrap_err();
+        assert!(chained.to_string().contains("a:old → a:mid → a:new"), "{}", chained);
+
+        let cycle = aliases(&[("a:one", "a:two"), ("a:two", "a:one")]).validate().unwrap_err();
+        assert!(cycle.to_string().contains("cycle"), "{}", cycle);
+        let self_alias = aliases(&[("a:one", "a:one")]).validate().unwrap_err();
+        assert!(self_alias.to_string().contains("cycle"), "{}", self_alias);
+
+        aliases(&[("a:old", "a:new"), ("b:old", "a:new")]).validate().unwrap();
+
+        // Config::load refuses the file outright
+        let temp = TempDir::new().unwrap();
+        let mut config = Config::init_with_socket_dir(temp.path(), None).unwrap();
+        config.channels = aliases(&[("a:one", "a:two"), ("a:two", "a:one")]);
+        config.save(temp.path()).unwrap();
+        let err = Config::load(temp.path()).unwrap_err();
+        assert!(format!("{:#}", err).contains("cycle"), "{:#}", err);
     }
 
     #[test]
@@ -158,11 +747,58 @@ default_topics = ["repo:delta"]
 max_message_size = 51200
 replay_buffer_capacity = 500
 rate_limit_per_second = 100
+replay_ttl_secs = 3600
+replay_max_bytes_per_channel = 262144
+
+[limits.replay_ttls]
+"repo:delta" = 600
 "#;
         let loaded: Config = toml::from_str(custom_config).unwrap();
         assert_eq!(loaded.limits.max_message_size, 51200);
         assert_eq!(loaded.limits.replay_buffer_capacity, 500);
         assert_eq!(loaded.limits.rate_limit_per_second, 100);
+        assert!(loaded.limits.rate_limits.is_empty());
+        assert_eq!(loaded.limits.replay_ttl_secs, 3600);
+        assert_eq!(loaded.limits.replay_ttls["repo:delta"], 600);
+        assert_eq!(loaded.limits.replay_max_bytes_per_channel, 262144);
+    }
+
+    #[test]
+    fn test_out_of_range_schedule_horizon_rejected() {
+        let limits = |secs| Limits { max_schedule_horizon_secs: secs, ..Limits::default() };
+        assert_eq!(limits(60).schedule_horizon(), Some(chrono::Duration::seconds(60)));
+        assert_eq!(limits(u64::MAX).schedule_horizon(), None);
+        assert_eq!(limits(i64::MAX as u64).schedule_horizon(), None);
+
+        // Config::load refuses the file, so neither start nor SIGHUP takes it
+        let temp = TempDir::new().unwrap();
+        let mut config = Config::init_with_socket_dir(temp.path(), None).unwrap();
+        config.limits = limits(u64::MAX);
+        config.save(temp.path()).unwrap();
+        let err = Config::load(temp.path()).unwrap_err();
+        assert!(format!("{:#}", err).contains("max_schedule_horizon_secs"), "{:#}", err);
+    }
+
+    #[test]
+    fn test_config_rate_limits() {
+        let with_limits = r#"
+project_uuid = "a1b2c3d4-e5f6-7890-abcd-ef1234567890"
+socket_path = ".hydra/hydra.sock"
+default_topics = ["repo:delta"]
+
+[limits.rate_limits]
+"repo:*" = "50/s"
+"team:alert" = "600/m"
+"#;
+        let loaded: Config = toml::from_str(with_limits).unwrap();
+        assert_eq!(loaded.limits.rate_limits["repo:*"], RateLimit::per_second(50));
+        assert_eq!(loaded.limits.rate_limits["team:alert"].to_string(), "600/m");
+        let saved = toml::to_string(&loaded).unwrap();
+        assert!(saved.contains("[limits.rate_limits]") && saved.contains(r#""repo:*" = "50/s""#), "{}", saved);
+
+        let invalid = with_limits.replace("50/s", "lots");
+        let err = toml::from_str::<Config>(&invalid).unwrap_err();
+        assert!(err.to_string().contains("Invalid rate limit 'lots'"), "{}", err);
     }
 
     #[test]
@@ -236,4 +872,19 @@ rate_limit_per_second = 0
         assert_eq!(loaded.limits.replay_buffer_capacity, 0);
         assert_eq!(loaded.limits.rate_limit_per_second, 0);
     }
+
+    #[test]
+    fn test_config_sh_exports_default_role() {
+        let temp = TempDir::new().unwrap();
+        let mut config = Config::init_with_socket_dir(temp.path(), None).unwrap();
+        assert!(!config.generate_config_sh().contains("HYDRA_ROLE"));
+
+        config.roles = vec![Role::Builder, Role::Reviewer];
+        config.save(temp.path()).unwrap();
+        let loaded = Config::load(temp.path()).unwrap();
+        assert_eq!(loaded.roles, vec![Role::Builder, Role::Reviewer]);
+        assert!(loaded
+            .generate_config_sh()
+            .contains("export HYDRA_ROLE=\"${HYDRA_ROLE:-builder}\""));
+    }
 }
@@ -6,12 +6,71 @@ pub const MAX_MESSAGE_SIZE: usize = 10_240;
 /// Maximum size for stdin input in bytes (100KB)
 pub const MAX_STDIN_SIZE: usize = 102_400;
 
+/// How long `hydra-mail ping` waits for the daemon by default, in seconds
+pub const PING_TIMEOUT_SECS: u64 = 2;
+
+/// Exit code of `hydra-mail subscribe` when `--idle-timeout` or
+/// `--max-duration` runs out, as timeout(1) uses
+pub const SUBSCRIBE_TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// The line a subscribe stream with `"mark_history": true` sends between
+/// the history and the live messages. Framed messages are JSON strings, so
+/// it can't be mistaken for one.
+pub const HISTORY_END_LINE: &str = r#"{"event":"history_end"}"#;
+
+/// How long the daemon keeps connections open after telling subscribers it
+/// is shutting down, so the notice reaches them, in milliseconds
+pub const SHUTDOWN_DRAIN_MS: u64 = 500;
+
+/// Permissions for .hydra/daemon.token (owner read/write only)
+pub const DAEMON_TOKEN_PERMISSIONS: u32 = 0o600;
+
+/// How long `hydra-mail stop` waits for the daemon to exit, in seconds
+pub const STOP_TIMEOUT_SECS: u64 = 10;
+
+/// How long a channel with no subscribers and no emits is kept, in seconds
+/// (1 day)
+pub const CHANNEL_TTL_SECS: u64 = 24 * 3600;
+
 /// Replay buffer capacity (messages per channel)
 pub const REPLAY_BUFFER_CAPACITY: usize = 100;
 
+/// Replay buffer budget for message text per channel, in bytes (1MB)
+pub const REPLAY_MAX_BYTES_PER_CHANNEL: usize = 1_048_576;
+
+/// Message log size past which the daemon compacts it, in kilobytes (1MB)
+pub const MESSAGE_LOG_COMPACT_ABOVE_KB: u64 = 1024;
+
+/// How often the daemon checks the message log size, in seconds
+pub const MESSAGE_LOG_CHECK_INTERVAL_SECS: u64 = 60;
+
+/// How often the daemon compacts the message log whatever its size, in seconds
+pub const MESSAGE_LOG_COMPACT_INTERVAL_SECS: u64 = 600;
+
 /// Broadcast channel capacity (concurrent in-flight messages)
 pub const BROADCAST_CHANNEL_CAPACITY: usize = 1024;
 
+/// Maximum scheduled messages pending per project
+pub const MAX_SCHEDULED_PENDING: usize = 1000;
+
+/// Furthest ahead a message can be scheduled, in seconds (7 days)
+pub const MAX_SCHEDULE_HORIZON_SECS: u64 = 7 * 24 * 3600;
+
+/// Fired tokens remembered so late cancels report "already delivered"
+pub const SCHEDULER_FIRED_HISTORY: usize = 1024;
+
+/// Default size cap for the SQLite archive, in megabytes
+pub const ARCHIVE_MAX_SIZE_MB: u64 = 256;
+
+/// Messages queued for the SQLite archive writer before emits start dropping
+pub const ARCHIVE_QUEUE_CAPACITY: usize = 4096;
+
+/// Runs of one `[[hooks]]` command at a time; messages past it skip the hook
+pub const HOOK_MAX_CONCURRENT: usize = 2;
+
+/// Seconds a hook command may run before it is killed
+pub const HOOK_TIMEOUT_SECS: u64 = 30;
+
 /// Socket file permissions (owner read/write only)
 pub const SOCKET_PERMISSIONS: u32 = 0o600;
 
@@ -0,0 +1,280 @@
+//! Daemon-side hooks
+//!
+//! Each `[[hooks]]` entry of config.toml names a command to run for every
+//! message stored on a matching channel. The emit path only calls
+//! [`Hooks::fire`], which spawns a task per run, so an emit never waits on a
+//! hook and its response never depends on one. A hook runs at most
+//! `max_concurrent` times at once: a message arriving while every run is
+//! busy skips the hook instead of queueing, so a stuck script can't pile up
+//! processes, and runs past `timeout_secs` are killed along with whatever
+//! they started. Outcomes are logged and counted in [`HookStats`].
+
+use anyhow::{Context, Result};
+use nix::sys::signal::{killpg, Signal};
+use nix::unistd::Pid;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Semaphore;
+use tracing::{debug, warn};
+
+use crate::config::HookConfig;
+use crate::pulse::decode_pulse;
+
+/// The hooks of one project
+pub struct Hooks {
+    project_root: PathBuf,
+    hooks: Vec<Hook>,
+}
+
+struct Hook {
+    config: HookConfig,
+    slots: Arc<Semaphore>,
+    counters: Arc<HookCounters>,
+}
+
+#[derive(Debug, Default)]
+struct HookCounters {
+    fired: AtomicU64,
+    failed: AtomicU64,
+    timed_out: AtomicU64,
+    skipped: AtomicU64,
+}
+
+/// Counters of one hook, reported by the daemon's `stats` command
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HookStats {
+    pub channel_pattern: String,
+    pub command: String,
+    /// Runs started
+    pub fired: u64,
+    /// Runs that exited non-zero or couldn't start
+    pub failed: u64,
+    /// Runs killed at the timeout
+    pub timed_out: u64,
+    /// Messages that found every run busy
+    pub skipped: u64,
+    /// Runs going on right now
+    pub running: usize,
+}
+
+impl Hooks {
+    /// Commands run in `project_root`
+    pub fn new(project_root: &Path, configs: Vec<HookConfig>) -> Self {
+        let hooks = configs
+            .into_iter()
+            .map(|config| Hook {
+                slots: Arc::new(Semaphore::new(config.max_concurrent)),
+                counters: Arc::default(),
+                config,
+            })
+            .collect();
+        Self { project_root: project_root.to_path_buf(), hooks }
+    }
+
+    /// Start the hooks matching `channel` on a stored message, returning
+    /// right away. Call from within the tokio runtime.
+    pub fn fire(&self, channel: &str, message: &str) {
+        let mut input = None;
+        for hook in self.hooks.iter().filter(|hook| hook.config.matches(channel)) {
+            let Ok(slot) = hook.slots.clone().try_acquire_owned() else {
+                hook.counters.skipped.fetch_add(1, Ordering::Relaxed);
+                warn!(
+                    "hook '{}' skipped for a {} message: {} run(s) still busy",
+                    hook.config.command, channel, hook.config.max_concurrent
+                );
+                continue;
+            };
+            hook.counters.fired.fetch_add(1, Ordering::Relaxed);
+            // Decoded once, however many hooks match
+            let input: &String = input.get_or_insert_with(|| hook_input(message));
+            let (input, channel) = (input.clone(), channel.to_string());
+            let (command, counters) = (hook.config.command.clone(), hook.counters.clone());
+            let cwd = self.project_root.clone();
+            let timeout = (hook.config.timeout_secs > 0).then(|| Duration::from_secs(hook.config.timeout_secs));
+            tokio::spawn(async move {
+                let outcome = run_script(&command, &channel, &input, Some(&cwd), timeout).await;
+                drop(slot);
+                match outcome {
+                    Ok(Some(status)) if status.success() => debug!("hook '{}' ran for a {} message", command, channel),
+                    Ok(Some(status)) => {
+                        counters.failed.fetch_add(1, Ordering::Relaxed);
+                        warn!("hook '{}' for a {} message {}", command, channel, status);
+                    }
+                    Ok(None) => {
+                        counters.timed_out.fetch_add(1, Ordering::Relaxed);
+                        warn!("hook '{}' for a {} message timed out after {:?}, killed", command, channel, timeout.unwrap_or_default());
+                    }
+                    Err(e) => {
+                        counters.failed.fetch_add(1, Ordering::Relaxed);
+                        warn!("{:#}", e);
+                    }
+                }
+            });
+        }
+    }
+
+    pub fn stats(&self) -> Vec<HookStats> {
+        self.hooks
+            .iter()
+            .map(|hook| HookStats {
+                channel_pattern: hook.config.channel_pattern.clone(),
+                command: hook.config.command.clone(),
+                fired: hook.counters.fired.load(Ordering::Relaxed),
+                failed: hook.counters.failed.load(Ordering::Relaxed),
+                timed_out: hook.counters.timed_out.load(Ordering::Relaxed),
+                skipped: hook.counters.skipped.load(Ordering::Relaxed),
+                running: hook.config.max_concurrent - hook.slots.available_permits(),
+            })
+            .collect()
+    }
+}
+
+/// The hooks that would run for a message on `channel`
+pub fn matching<'a>(hooks: &'a [HookConfig], channel: &str) -> Vec<&'a HookConfig> {
+    hooks.iter().filter(|hook| hook.matches(channel)).collect()
+}
+
+/// A message as a hook gets it: the decoded pulse as one line of JSON, or
+/// the message itself if it isn't a TOON pulse
+fn hook_input(message: &str) -> String {
+    match decode_pulse(message) {
+        Ok(pulse) => pulse.to_string(),
+        Err(_) => message.to_string(),
+    }
+}
+
+/// Run a script for one message: via `sh -c`, with the message on stdin
+/// and `HYDRA_CHANNEL` set. `None` when it ran past `timeout` and was
+/// killed.
+pub async fn run_script(
+    script: &str,
+    channel: &str,
+    message: &str,
+    cwd: Option<&Path>,
+    timeout: Option<Duration>,
+) -> Result<Option<ExitStatus>> {
+    let mut command = tokio::process::Command::new("sh");
+    command
+        .args(["-c", script])
+        .env("HYDRA_CHANNEL", channel)
+        .stdin(Stdio::piped())
+        .kill_on_drop(true)
+        // Its own process group, so a timeout can kill whatever the script started
+        .process_group(0);
+    if let Some(cwd) = cwd {
+        command.current_dir(cwd);
+    }
+    let mut child = command.spawn()
+        .with_context(|| format!("Failed to run '{}'", script))?;
+
+    let mut stdin = child.stdin.take();
+    let run = async {
+        if let Some(stdin) = stdin.as_mut() {
+            // A script that doesn't read its input may close the pipe first
+            let _ = stdin.write_all(message.as_bytes()).await;
+            let _ = stdin.write_all(b"\n").await;
+        }
+        drop(stdin);
+        child.wait().await
+    };
+    let status = match timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, run).await {
+            Ok(status) => status,
+            Err(_) => {
+                // The group the script leads, whatever it started included
+                if let Some(pid) = child.id().and_then(|pid| i32::try_from(pid).ok()) {
+                    let _ = killpg(Pid::from_raw(pid), Signal::SIGKILL);
+                }
+                let _ = child.kill().await;
+                return Ok(None);
+            }
+        },
+        None => run.await,
+    };
+    Ok(Some(status.with_context(|| format!("Failed to wait for '{}'", script))?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pulse::{encode_pulse, new_pulse};
+    use serde_json::{json, Value};
+    use tempfile::TempDir;
+
+    fn hook(channel_pattern: &str, command: &str, max_concurrent: usize, timeout_secs: u64) -> HookConfig {
+        HookConfig {
+            channel_pattern: channel_pattern.to_string(),
+            command: command.to_string(),
+            max_concurrent,
+            timeout_secs,
+        }
+    }
+
+    /// Wait for every run to end, up to a few seconds
+    async fn settle(hooks: &Hooks) {
+        for _ in 0..100 {
+            if hooks.stats().iter().all(|stats| stats.running == 0) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("hooks still running: {:?}", hooks.stats());
+    }
+
+    #[test]
+    fn test_matching_hooks() {
+        let hooks = [hook("team:alert", "a", 1, 1), hook("team:*", "b", 1, 1), hook("repo:*", "c", 1, 1)];
+        let commands = |channel| matching(&hooks, channel).iter().map(|h| h.command.as_str()).collect::<Vec<_>>();
+        assert_eq!(commands("team:alert"), vec!["a", "b"]);
+        assert_eq!(commands("team:status"), vec!["b"]);
+        assert!(commands("agent:presence").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fire_runs_matching_hooks_with_the_decoded_message() {
+        let temp = TempDir::new().unwrap();
+        let hooks = Hooks::new(temp.path(), vec![
+            hook("team:*", "{ echo \"$HYDRA_CHANNEL\"; cat; } >> received", 2, 5),
+            hook("repo:*", "touch wrong", 2, 5),
+        ]);
+        let pulse = new_pulse("alert", "team:alert", json!({"message": "build broke"}), Value::Null);
+        hooks.fire("team:alert", &encode_pulse(&pulse).unwrap());
+        settle(&hooks).await;
+
+        // Relative paths are the project root's
+        assert!(!temp.path().join("wrong").exists());
+        let received = std::fs::read_to_string(temp.path().join("received")).unwrap();
+        let (channel, input) = received.split_once('\n').unwrap();
+        assert_eq!(channel, "team:alert");
+        let input: Value = serde_json::from_str(input.trim()).unwrap();
+        assert_eq!(input["data"]["message"], "build broke");
+
+        let stats = hooks.stats();
+        assert_eq!((stats[0].fired, stats[0].failed), (1, 0));
+        assert_eq!(stats[1].fired, 0);
+    }
+
+    #[tokio::test]
+    async fn test_busy_hooks_skip_and_stuck_ones_are_killed() {
+        let temp = TempDir::new().unwrap();
+        let hooks = Hooks::new(temp.path(), vec![
+            hook("team:alert", "sleep 30", 1, 1),
+            hook("team:alert", "exit 3", 4, 5),
+        ]);
+        hooks.fire("team:alert", "not a pulse");
+        hooks.fire("team:alert", "not a pulse");
+        let stats = hooks.stats();
+        assert_eq!((stats[0].fired, stats[0].skipped, stats[0].running), (1, 1, 1));
+        assert_eq!(stats[1].fired, 2);
+
+        settle(&hooks).await;
+        let stats = hooks.stats();
+        assert_eq!((stats[0].timed_out, stats[0].failed), (1, 0));
+        assert_eq!((stats[1].failed, stats[1].skipped), (2, 0));
+    }
+}
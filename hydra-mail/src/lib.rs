@@ -1,7 +1,32 @@
+pub mod constants;
+pub mod pulse;
+
+// Daemon, socket and filesystem modules stay out of the wasm32 build
+#[cfg(not(target_arch = "wasm32"))]
+pub mod client;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod config;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod channels;
-pub mod constants;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod hooks;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod message_log;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod presence;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod rate_limit;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod scheduler;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod skills;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod trace;
+#[cfg(all(feature = "sqlite", not(target_arch = "wasm32")))]
+pub mod archive;
+
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
 // Removed modules (dead code):
 // - schema: Pulse struct was never used, main.rs builds JSON directly
@@ -11,7 +36,7 @@ pub mod message_log;
 // pub mod broadcast;
 // pub mod mpsc;
 
-#[cfg(test)]
+#[cfg(all(test, not(target_arch = "wasm32")))]
 mod tests {
     use super::*;
     use anyhow::Result;
@@ -23,7 +48,7 @@ mod tests {
         let temp_dir = TempDir::new()?;
         let project_path = temp_dir.path();
 
-        let config = config::Config::init(project_path)?;
+        let config = config::Config::init_with_socket_dir(project_path, None)?;
 
         let hydra_dir = project_path.join(".hydra");
         assert!(hydra_dir.exists());
@@ -53,7 +78,7 @@ mod tests {
         let temp_dir = TempDir::new()?;
         let project_path = temp_dir.path();
 
-        let config = config::Config::init(project_path)?;
+        let config = config::Config::init_with_socket_dir(project_path, None)?;
         let loaded = config::Config::load(project_path)?;
 
         assert_eq!(config.project_uuid, loaded.project_uuid);
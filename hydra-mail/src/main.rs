@@ -1,6 +1,18 @@
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
-use hydra_mail::{config::{Config, Limits}, channels, constants::*};
+use hydra_mail::{config::{self, Config, Limits}, channels, constants::*};
+use hydra_mail::client::{
+    EmitAck, EmitRequest, Frame, HistoryMessage, HistoryRequest, HistoryResponse, MailClient, Refused,
+    Request, SubscribeOptions, SubscribeRequest,
+};
+use hydra_mail::scheduler::{self, CancelResult, Scheduler, SchedulerHandle};
+use hydra_mail::skills::{self, Role};
+use hydra_log::LoggedCommand;
+use hydra_mail::{hooks, presence, pulse};
+use hydra_mail::rate_limit::{RateLimit, RateLimiter};
+use hydra_mail::trace::{self, TraceContext};
+use chrono::SubsecRound;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use std::fs;
 use std::os::unix::fs::PermissionsExt;
@@ -8,19 +20,48 @@ use std::path::Path;
 use std::process::Command;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 use base64::{Engine as _, engine::general_purpose};
-use toon_format::{encode, EncodeOptions};
-use toon_format::types::KeyFoldingMode;
 
 #[derive(Parser)]
 #[command(name = "hydra-mail")]
 #[command(about = "Lightweight in-memory pub/sub for local agent collaboration")]
 struct Cli {
+    #[command(flatten)]
+    verbosity: hydra_log::VerbosityArgs,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+/// Client-side filters of `subscribe` and `history`, tested on each decoded
+/// message; the daemon sends everything
+#[derive(clap::Args, Debug, Clone, Default)]
+struct FilterArgs {
+    /// Only pulses of this type
+    #[arg(long = "type")]
+    pulse_type: Option<String>,
+    /// Only pulses whose field equals the value, e.g. data.file=src/main.rs
+    /// (repeat to require several)
+    #[arg(long = "match", value_name = "KEY=VALUE")]
+    matches: Vec<String>,
+    /// Only messages containing this text
+    #[arg(long)]
+    contains: Option<String>,
+}
+
+impl FilterArgs {
+    fn to_filter(&self) -> Result<pulse::PulseFilter> {
+        Ok(pulse::PulseFilter {
+            r#type: self.pulse_type.clone(),
+            fields: self.matches.iter().map(|m| pulse::PulseFilter::parse_field(m)).collect::<Result<_>>()?,
+            contains: self.contains.clone(),
+        })
+    }
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Initialize Hydra in the current project
@@ -28,6 +69,25 @@ enum Commands {
         /// Spawn daemon after init
         #[arg(long)]
         daemon: bool,
+        /// Generate a skill variant per agent role (builder, reviewer, orchestrator)
+        #[arg(long, value_delimiter = ',')]
+        role: Vec<Role>,
+        /// Directory for the daemon's socket (default: $XDG_RUNTIME_DIR/hydra
+        /// if set, else the socket is .hydra/hydra.sock)
+        #[arg(long, value_name = "DIR")]
+        socket_dir: Option<std::path::PathBuf>,
+    },
+    /// Regenerate role skill variants and config.sh
+    Regenerate {
+        /// Project path (default: .)
+        #[arg(short, long, default_value = ".")]
+        project: String,
+        /// Add variants for these roles (builder, reviewer, orchestrator)
+        #[arg(long, value_delimiter = ',')]
+        role: Vec<Role>,
+        /// Only report generated files that are stale or hand-edited
+        #[arg(long, conflicts_with = "role")]
+        check: bool,
     },
     /// Start the persistent daemon
     Start {
@@ -40,36 +100,239 @@ enum Commands {
         /// Project path (default: .)
         #[arg(short, long, default_value = ".")]
         project: String,
-        /// Pulse type (e.g., delta, ack)
-        #[arg(short, long)]
-        r#type: String,
+        /// Pulse type (e.g., delta, ack); with --batch, for lines without `_type`
+        #[arg(short, long, required_unless_present = "batch")]
+        r#type: Option<String>,
         /// JSON data (use --data @- for stdin)
         #[arg(short, long)]
         data: Option<String>,
-        /// Channel/topic
-        #[arg(short, long)]
-        channel: String,
+        /// Channel/topic; with --batch, for lines without `_channel`
+        #[arg(short, long, required_unless_present = "batch")]
+        channel: Option<String>,
         /// Message format (only 'toon' supported currently)
         #[arg(short = 'F', long, default_value = "toon")]
         format: String,
         /// Target agent ID (stored in metadata, agents can filter)
         #[arg(long)]
         target: Option<String>,
+        /// Deliver after a delay instead of now (e.g., 90s, 30m, 1h30m)
+        #[arg(long, conflicts_with = "deliver_at")]
+        deliver_in: Option<String>,
+        /// Deliver at a specific time (RFC 3339, e.g., 2025-01-01T12:00:00Z)
+        #[arg(long)]
+        deliver_at: Option<String>,
+        /// Ask for a reply on this channel, with a new correlation id
+        #[arg(long)]
+        reply_to: Option<String>,
+        /// When rate limited, wait as long as the daemon says and try once more
+        #[arg(long)]
+        retry: bool,
+        /// Emit one pulse per line of newline-delimited JSON objects on stdin,
+        /// over one connection
+        #[arg(long, conflicts_with_all = ["data", "reply_to"])]
+        batch: bool,
+    },
+    /// Emit a request and wait for the reply to it
+    Request {
+        /// Project path (default: .)
+        #[arg(short, long, default_value = ".")]
+        project: String,
+        /// Channel/topic
+        #[arg(short, long)]
+        channel: String,
+        /// Pulse type
+        #[arg(short, long, default_value = "request")]
+        r#type: String,
+        /// JSON data (use --data @- for stdin)
+        #[arg(short, long)]
+        data: Option<String>,
+        /// Target agent ID
+        #[arg(long)]
+        target: Option<String>,
+        /// How long to wait for the reply (e.g., 30s, 5m)
+        #[arg(long, default_value = "30s")]
+        timeout: String,
+        /// Output format: toon or raw (as emitted), json (decoded)
+        #[arg(short, long, default_value = "toon")]
+        format: String,
+        /// Indent --format json output
+        #[arg(long)]
+        pretty: bool,
+    },
+    /// Answer a request by its correlation id
+    Reply {
+        /// Project path (default: .)
+        #[arg(short, long, default_value = ".")]
+        project: String,
+        /// Correlation id of the request
+        #[arg(long)]
+        to: String,
+        /// Reply channel the request named (default: reply:<correlation id>)
+        #[arg(short, long)]
+        channel: Option<String>,
+        /// Pulse type
+        #[arg(short, long, default_value = "reply")]
+        r#type: String,
+        /// JSON data (use --data @- for stdin)
+        #[arg(short, long)]
+        data: Option<String>,
     },
     /// Subscribe to a channel
     Subscribe {
         /// Project path (default: .)
         #[arg(short, long, default_value = ".")]
         project: String,
-        /// Channel/topic
+        /// Channel/topic, or a pattern like repo:* or *
         #[arg(short, long)]
         channel: String,
-        /// Output format (only 'toon' supported currently)
+        /// Output format: toon or raw (as emitted), json (decoded, one per line)
         #[arg(short, long, default_value = "toon")]
         format: String,
+        /// Indent --format json output
+        #[arg(long)]
+        pretty: bool,
         /// Get one message and exit
         #[arg(short, long)]
         once: bool,
+        /// Exit after N messages, history included
+        #[arg(long, conflicts_with = "once", value_parser = clap::value_parser!(u64).range(1..))]
+        count: Option<u64>,
+        /// Exit with 124 when no message arrives for this long (e.g., 30s);
+        /// each message restarts it. Counts from the end of the history.
+        #[arg(long, visible_alias = "timeout")]
+        idle_timeout: Option<String>,
+        /// Exit with 124 when still subscribed after this long (e.g., 5m),
+        /// whatever arrives. Counts from the end of the history.
+        #[arg(long)]
+        max_duration: Option<String>,
+        /// Only the newest N messages of the history
+        #[arg(long, conflicts_with = "no_history")]
+        last: Option<usize>,
+        /// Only history since this time (RFC 3339, or relative like 10m, 2h)
+        #[arg(long, conflicts_with = "no_history")]
+        since: Option<String>,
+        /// Skip the history, only live messages
+        #[arg(long)]
+        no_history: bool,
+        /// Agent ID to subscribe as: messages targeted at other agents are
+        /// left out (default: $HYDRA_AGENT_ID)
+        #[arg(long)]
+        agent_id: Option<String>,
+        /// Role to list the agent under in `agents` (default: $HYDRA_ROLE)
+        #[arg(long)]
+        role: Option<String>,
+        /// What the agent is doing, listed in `agents`
+        #[arg(long)]
+        description: Option<String>,
+        #[command(flatten)]
+        filter: FilterArgs,
+        /// Run this script (via sh -c) for every message instead of printing
+        /// it: the message on stdin, its channel in $HYDRA_CHANNEL
+        #[arg(long)]
+        callback: Option<String>,
+        /// Kill a callback that runs longer than this (e.g., 30s, 5m)
+        #[arg(long, requires = "callback")]
+        callback_timeout: Option<String>,
+        /// How many callbacks may run at once
+        #[arg(long, default_value_t = 1, requires = "callback", value_parser = clap::value_parser!(u32).range(1..))]
+        concurrency: u32,
+    },
+    /// Print a channel's newest buffered messages, then follow it live
+    Tail {
+        /// Project path (default: .)
+        #[arg(short, long, default_value = ".")]
+        project: String,
+        /// Channel/topic, or a pattern like repo:* or *
+        #[arg(short, long)]
+        channel: String,
+        /// How many buffered messages to print first (0 for none)
+        #[arg(short = 'n', long, default_value_t = 10)]
+        last: usize,
+        /// Output format: pretty (one line per message), json (decoded, one
+        /// per line) or toon (as emitted)
+        #[arg(short, long, default_value = "pretty")]
+        format: String,
+    },
+    /// Print what a channel has buffered and exit
+    History {
+        /// Project path (default: .)
+        #[arg(short, long, default_value = ".")]
+        project: String,
+        /// Channel/topic, or a pattern like repo:* or *
+        #[arg(short, long)]
+        channel: String,
+        /// Only the newest N messages
+        #[arg(long)]
+        last: Option<usize>,
+        /// Output format: toon or raw (as emitted), json (decoded, one per line)
+        #[arg(short, long, default_value = "toon")]
+        format: String,
+        /// Indent --format json output
+        #[arg(long)]
+        pretty: bool,
+        /// Agent ID to read as: messages targeted at other agents are left
+        /// out (default: $HYDRA_AGENT_ID)
+        #[arg(long)]
+        agent_id: Option<String>,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Write a channel's history to a file, one decoded pulse per line
+    Export {
+        /// Project path (default: .)
+        #[arg(short, long, default_value = ".")]
+        project: String,
+        /// Channel/topic, or a pattern like repo:* or *
+        #[arg(short, long)]
+        channel: String,
+        /// File to write (default: stdout)
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Emit the pulses of an export to a channel, paced for its rate limit
+    Import {
+        /// Project path (default: .)
+        #[arg(short, long, default_value = ".")]
+        project: String,
+        /// Channel to emit them to
+        #[arg(short, long)]
+        channel: String,
+        /// File written by export, one pulse per line (- for stdin)
+        #[arg(short, long)]
+        input: String,
+        /// Keep each pulse's timestamp instead of stamping it with the time
+        /// of the import
+        #[arg(long)]
+        preserve_timestamps: bool,
+        /// How fast to emit, e.g. 50/s (default: the channel's rate limit,
+        /// as fast as possible without one)
+        #[arg(long)]
+        rate: Option<String>,
+    },
+    /// Clear the replay history of a channel
+    Purge {
+        /// Project path (default: .)
+        #[arg(short, long, default_value = ".")]
+        project: String,
+        /// Channel/topic
+        #[arg(short, long, required_unless_present = "all", conflicts_with = "all")]
+        channel: Option<String>,
+        /// Every channel of the project
+        #[arg(long)]
+        all: bool,
+        /// Skip confirmation prompt
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Check that the daemon answers: exit 0 if it does, 1 without a
+    /// socket, 2 if the socket is there but the daemon doesn't answer
+    Ping {
+        /// Project path (default: .)
+        #[arg(short, long, default_value = ".")]
+        project: String,
+        /// How long to wait for the answer (e.g., 2s, 1m)
+        #[arg(long)]
+        timeout: Option<String>,
     },
     /// Show daemon status
     Status {
@@ -83,11 +346,99 @@ enum Commands {
         #[arg(short, long, default_value = ".")]
         project: String,
     },
+    /// List the daemon's active channels with subscribers and buffered messages
+    Channels {
+        /// Project path (default: .)
+        #[arg(short, long, default_value = ".")]
+        project: String,
+        /// Print one JSON object per channel
+        #[arg(long)]
+        json: bool,
+    },
+    /// List the agents subscribed right now
+    Agents {
+        /// Project path (default: .)
+        #[arg(short, long, default_value = ".")]
+        project: String,
+        /// Print one JSON object per agent
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show every message and orchestrator event for one workflow trace
+    Trace {
+        /// Trace id (HYDRA_TRACE_ID of the session)
+        trace_id: String,
+        /// Project path (default: .)
+        #[arg(short, long, default_value = ".")]
+        project: String,
+        /// Print one JSON object per line instead of a summary
+        #[arg(long)]
+        json: bool,
+    },
+    /// Query the SQLite message archive
+    #[cfg(feature = "sqlite")]
+    Query {
+        /// Project path (default: .)
+        #[arg(short, long, default_value = ".")]
+        project: String,
+        /// Run this SQL against the archive (read-only)
+        #[arg(long, conflicts_with = "count_by")]
+        sql: Option<String>,
+        /// Count messages per channel, type, sender or day
+        #[arg(long)]
+        count_by: Option<hydra_mail::archive::CountBy>,
+        /// Only messages newer than this (e.g., 7d, 12h); applies to --count-by
+        #[arg(long, requires = "count_by")]
+        since: Option<String>,
+        /// Print one JSON object per row
+        #[arg(long)]
+        json: bool,
+    },
+    /// Inspect or cancel scheduled messages
+    Scheduled {
+        #[command(subcommand)]
+        action: ScheduledAction,
+    },
     /// Handle Claude Code hook events
     Hook {
         #[command(subcommand)]
         event: HookEvent,
     },
+    /// Check the daemon's `[[hooks]]` from config.toml
+    Hooks {
+        #[command(subcommand)]
+        action: HooksAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum ScheduledAction {
+    /// List messages waiting for delivery
+    List {
+        /// Project path (default: .)
+        #[arg(short, long, default_value = ".")]
+        project: String,
+    },
+    /// Cancel a scheduled message before it is delivered
+    Cancel {
+        /// Cancel token returned when the message was scheduled
+        token: String,
+        /// Project path (default: .)
+        #[arg(short, long, default_value = ".")]
+        project: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum HooksAction {
+    /// Print which hooks a message on a channel would run, without running them
+    Test {
+        /// Channel a message would be emitted to
+        channel: String,
+        /// Project path (default: .)
+        #[arg(short, long, default_value = ".")]
+        project: String,
+    },
 }
 
 #[derive(Subcommand)]
@@ -109,9 +460,10 @@ enum HookEvent {
 #[tokio::main(flavor = "multi_thread")]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
+    hydra_log::init(cli.verbosity.level());
 
     match cli.command {
-        Commands::Init { daemon } => {
+        Commands::Init { daemon, role, socket_dir } => {
             let project_path = Path::new(".");
             let hydra_dir = project_path.join(".hydra");
             
@@ -121,8 +473,11 @@ async fn main() -> Result<()> {
                     Ok(config) => {
                         println!("Hydra is already initialized in {:?}", project_path);
                         println!("Project UUID: {}", config.project_uuid);
-                        println!("Socket path: {:?}", config.socket_path);
+                        println!("Socket path: {:?}", config.socket());
                         println!("Default topics: {}", config.default_topics.join(", "));
+                        if !role.is_empty() {
+                            println!("To add skill variants, run: hydra-mail regenerate --role <role>");
+                        }
                         
                         // Check if daemon is running
                         let pid_path = hydra_dir.join("daemon.pid");
@@ -130,11 +485,10 @@ async fn main() -> Result<()> {
                             if let Ok(pid_str) = fs::read_to_string(&pid_path) {
                                 if let Ok(pid) = pid_str.trim().parse::<u32>() {
                                     // Check if process is still alive
-                                    use std::process::Command as CheckCmd;
-                                    let check = CheckCmd::new("ps")
+                                    let check = Command::new("ps")
                                         .arg("-p")
                                         .arg(pid.to_string())
-                                        .output();
+                                        .logged_output();
                                     if let Ok(output) = check {
                                         if output.status.success() {
                                             println!("Daemon is running with PID: {}", pid);
@@ -153,17 +507,29 @@ async fn main() -> Result<()> {
                         return Ok(());
                     }
                     Err(e) => {
-                        eprintln!("Warning: .hydra directory exists but config.toml is invalid: {}", e);
-                        eprintln!("If you want to reinitialize, remove .hydra directory first");
+                        warn!(".hydra directory exists but config.toml is invalid: {}", e);
+                        warn!("if you want to reinitialize, remove .hydra directory first");
                         return Err(anyhow::anyhow!("Cannot initialize: existing .hydra with invalid config"));
                     }
                 }
             }
             
             // Initialize new project
-            let config = Config::init(project_path)?;
+            let mut config = Config::init_with_socket_dir(project_path, socket_dir.or_else(config::default_socket_dir))?;
             println!("Hydra initialized in {:?} with UUID: {}", project_path, config.project_uuid);
-            println!("Socket path: {:?}", config.socket_path);
+            println!("Socket path: {:?}", config.socket());
+
+            if !role.is_empty() {
+                for r in role {
+                    if !config.roles.contains(&r) {
+                        config.roles.push(r);
+                    }
+                }
+                config.save(project_path)?;
+                for path in skills::write_skills(&hydra_dir, &config.roles)? {
+                    info!("✓ Generated {}", path.display());
+                }
+            }
 
             // Generate config.sh for shell integration
             let sh_path = hydra_dir.join("config.sh");
@@ -171,13 +537,15 @@ async fn main() -> Result<()> {
                 .context("Failed to write config.sh")?;
             fs::set_permissions(&sh_path, fs::Permissions::from_mode(CONFIG_SH_PERMISSIONS))
                 .context("Failed to set config.sh permissions")?;
-            println!("✓ Generated .hydra/config.sh");
+            info!("✓ Generated .hydra/config.sh");
 
-            println!("\nTo use with Claude Code, install the hydra-mail skill from:");
-            println!("   https://github.com/0xPD33/hydra-tools/hydra-mail/skills/hydra-mail.yaml");
+            if config.roles.is_empty() {
+                println!("\nTo use with Claude Code, install the hydra-mail skill from:");
+                println!("   https://github.com/0xPD33/hydra-tools/hydra-mail/skills/hydra-mail.yaml");
+            }
 
             if daemon {
-                eprintln!("Spawning daemon process...");
+                info!("Spawning daemon process...");
 
                 // Copy current binary to .hydra/hydra-daemon for reliable spawn
                 let exe = std::env::current_exe()
@@ -195,30 +563,32 @@ async fn main() -> Result<()> {
                 let err_file = fs::File::create(&err_log)
                     .context("Failed to create daemon.err")?;
 
-                let child = Command::new(&daemon_binary)
+                let mut daemon_cmd = Command::new(&daemon_binary);
+                daemon_cmd
                     .arg("start")
                     .arg("--project")
                     .arg(".")
                     .stdin(std::process::Stdio::null())
                     .stdout(std::process::Stdio::null())
-                    .stderr(err_file)
-                    .spawn()
+                    .stderr(err_file);
+                debug!("$ {} &", hydra_log::command_line(&daemon_cmd));
+                let child = daemon_cmd.spawn()
                     .context("Failed to spawn daemon process")?;
                 let pid = child.id();
                 let pid_path = hydra_dir.join("daemon.pid");
                 fs::write(&pid_path, pid.to_string().as_bytes())
                     .context("Failed to write daemon.pid")?;
-                println!("Daemon spawned with PID: {}", pid);
+                info!("Daemon spawned with PID: {}", pid);
 
                 // Wait for socket to be created (up to 2 seconds)
-                let socket_path = &config.socket_path;
+                let socket_path = config.socket();
                 let mut attempts = 0;
                 while !socket_path.exists() && attempts < 20 {
                     tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
                     attempts += 1;
                 }
                 if !socket_path.exists() {
-                    eprintln!("Warning: Daemon socket not created after 2s. Check {:?}", hydra_dir.join("daemon.err"));
+                    warn!("daemon socket not created after 2s. Check {:?}", hydra_dir.join("daemon.err"));
                 } else {
                     println!("Daemon ready at {:?}", socket_path);
                 }
@@ -226,9 +596,96 @@ async fn main() -> Result<()> {
                 println!("To start the daemon, run: hydra-mail start");
             }
         }
+        Commands::Trace { trace_id, project, json } => {
+            let project_path = Path::new(&project);
+            let config = Config::load(project_path)?;
+            let hydra_dir = project_path.join(".hydra");
+
+            let log_path = hydra_dir.join("messages.log");
+            let entries = if log_path.exists() {
+                hydra_mail::message_log::MessageLog::open(&log_path)?.replay()?
+            } else {
+                Vec::new()
+            };
+            let journal = hydra_dir.join("orchestrator").join("events.jsonl");
+            let found = trace::reconstruct(&entries, config.project_uuid, &journal, &trace_id);
+
+            if found.is_empty() {
+                println!("No messages or events recorded for trace {}", trace_id);
+                return Ok(());
+            }
+            for entry in found {
+                if json {
+                    println!("{}", serde_json::to_string(&entry)?);
+                    continue;
+                }
+                let ts = entry.timestamp.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+                let summary = if entry.source == "orchestrator" {
+                    format!("{}:{} session={}", entry.body["type"].as_str().unwrap_or("?"),
+                        entry.body["event"].as_str().unwrap_or("?"),
+                        entry.body["session"].as_str().unwrap_or("?"))
+                } else {
+                    let span = entry.body["metadata"]["span"].as_str()
+                        .map(|s| format!(" [{}]", s))
+                        .unwrap_or_default();
+                    format!("{}{} {}", entry.body["type"].as_str().unwrap_or("?"), span, entry.body["data"])
+                };
+                println!("{}  {:<14} {}", ts, entry.source, summary);
+            }
+        }
+
+        Commands::Regenerate { project, role, check } => {
+            let project_path = Path::new(&project);
+            let hydra_dir = project_path.join(".hydra");
+            let mut config = Config::load(project_path)?;
+            let sh_path = hydra_dir.join("config.sh");
+
+            if check {
+                let mut drifted = skills::detect_drift(&hydra_dir, &config.roles)?;
+                if fs::read_to_string(&sh_path).ok().as_deref() != Some(config.generate_config_sh().as_str()) {
+                    drifted.push(sh_path);
+                }
+                if drifted.is_empty() {
+                    println!("✓ Generated files are up to date");
+                    return Ok(());
+                }
+                for path in &drifted {
+                    println!("  out of date: {}", path.display());
+                }
+                anyhow::bail!("{} generated file(s) out of date. Run: hydra-mail regenerate", drifted.len());
+            }
+
+            for r in role {
+                if !config.roles.contains(&r) {
+                    config.roles.push(r);
+                }
+            }
+            if config.roles.is_empty() {
+                anyhow::bail!("No roles configured. Pass --role builder|reviewer|orchestrator");
+            }
+            config.save(project_path)?;
+
+            for path in skills::write_skills(&hydra_dir, &config.roles)? {
+                info!("✓ Generated {}", path.display());
+            }
+            // Anything still drifting is a variant for a role that was dropped
+            for path in skills::detect_drift(&hydra_dir, &config.roles)? {
+                fs::remove_file(&path)
+                    .with_context(|| format!("Failed to remove {}", path.display()))?;
+                info!("✓ Removed {}", path.display());
+            }
+
+            fs::write(&sh_path, config.generate_config_sh())
+                .context("Failed to write config.sh")?;
+            fs::set_permissions(&sh_path, fs::Permissions::from_mode(CONFIG_SH_PERMISSIONS))
+                .context("Failed to set config.sh permissions")?;
+            info!("✓ Generated .hydra/config.sh (HYDRA_ROLE default: {})", config.roles[0]);
+        }
+
         Commands::Start { project } => {
             let project_path = Path::new(&project);
             let config = Config::load(project_path)?;
+            let (socket_path, socket_source) = config.socket_location();
 
             // Clean up stale files from previous daemon (if any)
             let project_path_abs = std::env::current_dir()?.join(&project);
@@ -250,13 +707,13 @@ async fn main() -> Result<()> {
                             let check = Command::new("ps")
                                 .arg("-p")
                                 .arg(pid.to_string())
-                                .output();
+                                .logged_output();
                             if let Ok(output) = check {
                                 if !output.status.success() {
                                     // Process not running, clean up stale files
                                     let _ = fs::remove_file(&pid_file);
-                                    let _ = fs::remove_file(&config.socket_path);
-                                    eprintln!("Cleaned up stale daemon files (PID {} not running)", pid);
+                                    let _ = fs::remove_file(&socket_path);
+                                    info!("Cleaned up stale daemon files (PID {} not running)", pid);
                                 } else {
                                     anyhow::bail!("Daemon already running with PID {}. Use 'hydra-mail stop' first.", pid);
                                 }
@@ -267,58 +724,150 @@ async fn main() -> Result<()> {
             }
 
             // Remove existing socket if present
-            let _ = fs::remove_file(&config.socket_path);
+            let _ = fs::remove_file(&socket_path);
+            if let Some(dir) = socket_path.parent() {
+                config::create_socket_dir(dir)?;
+            }
 
-            let listener = UnixListener::bind(&config.socket_path)
+            let listener = UnixListener::bind(&socket_path)
                 .context("Failed to bind Unix socket")?;
+            info!("Listening on {} (from {})", socket_path.display(), socket_source);
 
             // Set socket permissions to 0600
-            fs::set_permissions(&config.socket_path, fs::Permissions::from_mode(SOCKET_PERMISSIONS))
+            fs::set_permissions(&socket_path, fs::Permissions::from_mode(SOCKET_PERMISSIONS))
                 .context("Failed to set socket permissions")?;
 
             // Write PID file
             fs::write(&pid_file, std::process::id().to_string())
                 .context("Failed to write daemon.pid")?;
 
-            // Enable message logging for crash recovery
+            // A shutdown request must carry this token, so only those who
+            // can read .hydra/ can stop the daemon over the socket
+            let token_file = hydra_dir.join("daemon.token");
+            let shutdown = Shutdown {
+                token: Uuid::new_v4().simple().to_string().into(),
+                requested: Default::default(),
+            };
+            fs::write(&token_file, &*shutdown.token).context("Failed to write daemon.token")?;
+            fs::set_permissions(&token_file, fs::Permissions::from_mode(DAEMON_TOKEN_PERMISSIONS))
+                .context("Failed to set daemon.token permissions")?;
+
+            // Aliases first, so replayed messages land on their canonical channels
+            channels::set_aliases(config.project_uuid, config.channels.aliases.clone());
+            channels::set_replay_ttls(
+                config.project_uuid,
+                config.limits.replay_ttl_secs,
+                config.limits.replay_ttls.clone(),
+            );
+            channels::set_replay_max_bytes(config.project_uuid, config.limits.replay_max_bytes_per_channel);
+            channels::set_channel_policy(config.project_uuid, config.channel_policy.clone());
+
+            // Replay the message log to restore history, then log from here on
             let log_path = hydra_dir.join("messages.log");
-            channels::set_message_log_path(Some(log_path.clone()));
-
-            // Replay message log to restore state after crash
-            if log_path.exists() {
-                match channels::replay_message_log(&log_path).await {
+            if config.persistence.enabled {
+                match channels::restore_message_log(&log_path).await {
                     Ok(count) => {
                         if count > 0 {
-                            eprintln!("Restored {} messages from log", count);
+                            info!("Restored {} messages from log", count);
                         }
                     }
                     Err(e) => {
-                        eprintln!("Warning: Failed to replay message log: {}", e);
+                        warn!("failed to replay message log: {}", e);
+                        channels::set_message_log_path(Some(log_path.clone()));
                     }
                 }
+            } else {
+                info!("Persistence disabled, replay history starts empty");
+            }
+
+            // Archive from here on, so the replay above isn't archived twice
+            let archive_writer = start_archive(&config, &project_path_abs);
+            // Same for hooks
+            if !config.hooks.is_empty() {
+                info!("Loaded {} hook(s)", config.hooks.len());
+                channels::set_hooks(config.project_uuid, hooks::Hooks::new(&project_path_abs, config.hooks.clone()));
             }
 
+            // Load scheduled messages left over from the previous run
+            let scheduled_path = hydra_dir.join("scheduled.json");
+            let scheduler = match Scheduler::with_persistence(&scheduled_path) {
+                Ok(s) => {
+                    if !s.is_empty() {
+                        info!("Restored {} scheduled messages", s.len());
+                    }
+                    s
+                }
+                Err(e) => {
+                    warn!("failed to load scheduled messages: {:#}", e);
+                    Scheduler::new()
+                }
+            };
+            let scheduler = SchedulerHandle::new(scheduler);
+            let scheduler_task = tokio::spawn(scheduler.clone().run());
+            let started = std::time::Instant::now();
+
             // Set up signal handling for graceful shutdown
             let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
                 .context("Failed to install SIGTERM handler")?;
             let mut sigint = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::interrupt())
                 .context("Failed to install SIGINT handler")?;
+            let mut sighup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+                .context("Failed to install SIGHUP handler")?;
 
-            eprintln!("Daemon started (PID: {}). Press Ctrl+C or send SIGTERM to stop.", std::process::id());
+            info!("Daemon started (PID: {}). Press Ctrl+C or send SIGTERM to stop.", std::process::id());
 
-            // Spawn log compaction task (runs every 10 minutes)
-            let log_path_compact = log_path.clone();
+            // Compact the log once it outgrows persistence.compact_above_kb,
+            // and every 10 minutes regardless
+            let compact_above = config.persistence.compact_above_kb * 1024;
             let compaction_task = tokio::spawn(async move {
-                use hydra_mail::message_log::MessageLog;
-                let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(600)); // 10 min
+                let check = tokio::time::Duration::from_secs(MESSAGE_LOG_CHECK_INTERVAL_SECS);
+                let checks_per_compaction = MESSAGE_LOG_COMPACT_INTERVAL_SECS / MESSAGE_LOG_CHECK_INTERVAL_SECS;
+                let mut interval = tokio::time::interval(check);
+                let mut ticks = 0u64;
                 loop {
                     interval.tick().await;
-                    if let Ok(log) = MessageLog::open(&log_path_compact) {
-                        let _ = log.compact(REPLAY_BUFFER_CAPACITY);
+                    let above = if ticks.is_multiple_of(checks_per_compaction) { 0 } else { compact_above };
+                    // Reads and rewrites the whole log, so not on a runtime thread
+                    let compaction = tokio::task::spawn_blocking(move || {
+                        channels::compact_message_log(REPLAY_BUFFER_CAPACITY, above)
+                    });
+                    match compaction.await {
+                        Ok(Err(e)) => warn!("failed to compact message log: {:#}", e),
+                        Err(e) => warn!("message log compaction panicked: {}", e),
+                        Ok(Ok(_)) => {}
                     }
+                    ticks += 1;
                 }
             });
 
+            // Drop channels idle past limits.channel_ttl_secs and trim messages
+            // past their replay TTL, checking every half of the shortest TTL
+            // so nothing outlives it by more than that
+            let channel_ttl = config.limits.channel_ttl_secs;
+            let shortest_ttl = std::iter::once(channel_ttl)
+                .chain(std::iter::once(config.limits.replay_ttl_secs))
+                .chain(config.limits.replay_ttls.values().copied())
+                .filter(|secs| *secs > 0)
+                .min();
+            let gc_task = shortest_ttl.map(|shortest| tokio::spawn(async move {
+                let check = tokio::time::Duration::from_secs(shortest) / 2;
+                let mut interval = tokio::time::interval(check.max(tokio::time::Duration::from_secs(1)));
+                loop {
+                    interval.tick().await;
+                    if channel_ttl > 0 {
+                        let dropped = channels::collect_idle_channels(tokio::time::Duration::from_secs(channel_ttl)).await;
+                        if !dropped.is_empty() {
+                            let names: Vec<&str> = dropped.iter().map(|(_, name)| name.as_str()).collect();
+                            info!("Dropped {} idle channel(s): {}", names.len(), names.join(", "));
+                        }
+                    }
+                    let trimmed = channels::trim_expired().await;
+                    if trimmed > 0 {
+                        debug!("Trimmed {} expired message(s) from replay buffers", trimmed);
+                    }
+                }
+            }));
+
             // Run the accepting loop with graceful shutdown
             loop {
                 tokio::select! {
@@ -327,208 +876,490 @@ async fn main() -> Result<()> {
                             Ok((stream, _)) => {
                                 let project_uuid = config.project_uuid;
                                 let limits = config.limits.clone();
+                                let scheduler = scheduler.clone();
+                                let shutdown = shutdown.clone();
                                 tokio::spawn(async move {
-                                    if let Err(e) = handle_conn(stream, project_uuid, limits).await {
-                                        eprintln!("Connection handler error: {:#}", e);
+                                    if let Err(e) = handle_conn(stream, project_uuid, limits, scheduler, started, shutdown).await {
+                                        warn!("connection handler error: {:#}", e);
                                     }
                                 });
                             }
                             Err(e) => {
-                                eprintln!("Accept error: {}", e);
+                                warn!("accept error: {}", e);
                                 break;
                             }
                         }
                     }
                     _ = sigterm.recv() => {
-                        eprintln!("Received SIGTERM, shutting down gracefully...");
+                        info!("Received SIGTERM, shutting down gracefully...");
                         break;
                     }
                     _ = sigint.recv() => {
-                        eprintln!("Received SIGINT (Ctrl+C), shutting down gracefully...");
+                        info!("Received SIGINT (Ctrl+C), shutting down gracefully...");
+                        break;
+                    }
+                    _ = shutdown.requested.notified() => {
+                        info!("Received shutdown request, shutting down gracefully...");
                         break;
                     }
+                    _ = sighup.recv() => {
+                        match Config::load(&project_path_abs) {
+                            Ok(reloaded) => {
+                                info!("Received SIGHUP, reloaded {} channel alias(es)", reloaded.channels.aliases.len());
+                                channels::set_aliases(config.project_uuid, reloaded.channels.aliases);
+                                channels::set_channel_policy(config.project_uuid, reloaded.channel_policy);
+                                channels::set_replay_ttls(
+                                    config.project_uuid,
+                                    reloaded.limits.replay_ttl_secs,
+                                    reloaded.limits.replay_ttls,
+                                );
+                                channels::set_replay_max_bytes(config.project_uuid, reloaded.limits.replay_max_bytes_per_channel);
+                            }
+                            Err(e) => warn!("SIGHUP: keeping current channel aliases, policy and replay limits: {:#}", e),
+                        }
+                    }
                 }
             }
 
+            // Tell the subscribers, then give their connections a moment to
+            // write the notice out before the process takes them down
+            let notified = channels::notify_subscribers(config.project_uuid, |channel| {
+                pulse::encode_pulse(&pulse::shutdown_pulse(channel)).unwrap_or_default()
+            }).await;
+            if notified > 0 {
+                info!("Told {} subscriber(s) about the shutdown", notified);
+                tokio::time::sleep(tokio::time::Duration::from_millis(SHUTDOWN_DRAIN_MS)).await;
+            }
+
             // Cleanup on shutdown
             compaction_task.abort();
+            if let Some(gc_task) = gc_task {
+                gc_task.abort();
+            }
+            scheduler_task.abort();
+            stop_archive(archive_writer);
             let _ = fs::remove_file(&pid_file);
-            let _ = fs::remove_file(&config.socket_path);
-            eprintln!("Daemon stopped cleanly.");
+            let _ = fs::remove_file(&token_file);
+            let _ = fs::remove_file(&socket_path);
+            info!("Daemon stopped cleanly.");
         }
-        Commands::Emit { project, r#type, data, channel, format, target } => {
+        Commands::Emit {
+            project, r#type, data, channel, format, target, deliver_in, deliver_at, reply_to, retry, batch,
+        } => {
             // Validate format parameter
             if format != "toon" {
                 anyhow::bail!("Only 'toon' format is supported (got: {})", format);
             }
 
-            // Validate channel name
-            if channel.trim().is_empty() {
-                anyhow::bail!("Channel name cannot be empty");
+            // Validate channel name; a batch checks each line's
+            if let Some(channel) = &channel {
+                if let Some(problem) = emit_channel_problem(channel) {
+                    anyhow::bail!(problem);
+                }
+                warn_unexpected_emit(channel);
             }
 
+            // Resolve delivery time up front so bad input fails before reading stdin
+            let deliver_at = match (deliver_in, deliver_at) {
+                (Some(delay), _) => Some(scheduler::after(chrono::Utc::now(), scheduler::parse_delay(&delay)?)?),
+                (None, Some(at)) => Some(
+                    chrono::DateTime::parse_from_rfc3339(&at)
+                        .with_context(|| format!("Invalid --deliver-at time: {}", at))?
+                        .with_timezone(&chrono::Utc),
+                ),
+                (None, None) => None,
+            };
+
             let project_path = Path::new(&project);
             let config = Config::load(project_path)?;
 
-            let mut stream = UnixStream::connect(&config.socket_path)
-                .await
-                .context(format!(
-                    "Failed to connect to daemon socket at {:?}. \
-                    Is the daemon running? Try:\n  \
-                    1. Check status: hydra-mail status\n  \
-                    2. Start daemon: hydra-mail start --daemon",
-                    config.socket_path
-                ))?;
-
-            // Read data from stdin if --data not provided or if --data @-
-            let data_json: Value = if let Some(data_str) = data {
-                if data_str == "@-" {
-                    // Read from stdin with size limit
-                    use tokio::io::AsyncReadExt;
-                    let stdin = tokio::io::stdin();
-                    let mut buffer = Vec::with_capacity(MAX_STDIN_SIZE);
-                    let bytes_read = stdin.take(MAX_STDIN_SIZE as u64).read_to_end(&mut buffer).await
-                        .context("Failed to read stdin")?;
-                    if bytes_read == MAX_STDIN_SIZE {
-                        anyhow::bail!("Stdin data too large (max {} bytes)", MAX_STDIN_SIZE);
-                    }
-                    let full_data = String::from_utf8(buffer)
-                        .context("Invalid UTF-8 in stdin")?;
-                    serde_json::from_str(&full_data).context("Failed to parse stdin JSON")?
-                } else {
-                    serde_json::from_str(&data_str).context("Failed to parse --data JSON")?
+            if batch {
+                let defaults = BatchDefaults { r#type, channel, target, deliver_at, retry };
+                let outcomes = emit_batch(&MailClient::locate(project_path)?, &defaults).await?;
+                let failed = outcomes.iter().filter(|(_, outcome)| outcome.is_err()).count();
+                for (line, outcome) in &outcomes {
+                    match outcome {
+                        Ok(channel) => println!("line {}: ok ({})", line, channel),
+                        Err(e) => println!("line {}: failed: {}", line, e),
+                    }
                 }
-            } else {
-                // Read stdin with size limit
-                use tokio::io::AsyncReadExt;
-                let stdin = tokio::io::stdin();
-                let mut buffer = Vec::with_capacity(MAX_STDIN_SIZE);
-                let bytes_read = stdin.take(MAX_STDIN_SIZE as u64).read_to_end(&mut buffer).await
-                    .context("Failed to read stdin")?;
-                if bytes_read == MAX_STDIN_SIZE {
-                    anyhow::bail!("Stdin data too large (max {} bytes)", MAX_STDIN_SIZE);
+                if failed > 0 {
+                    error!("{} of {} message(s) failed", failed, outcomes.len());
+                    std::process::exit(1);
                 }
-                let full_data = String::from_utf8(buffer)
-                    .context("Invalid UTF-8 in stdin")?;
-                serde_json::from_str(&full_data).context("Failed to parse stdin JSON")?
+                println!("Emitted {} message(s)", outcomes.len());
+                return Ok(());
+            }
+            let (Some(r#type), Some(channel)) = (r#type, channel) else {
+                anyhow::bail!("--type and --channel are required without --batch");
             };
 
-            // Build Pulse JSON directly and encode to TOON (skip Pulse struct)
-            let pulse_json = if let Some(target_id) = target {
-                json!({
-                    "id": Uuid::new_v4(),
-                    "timestamp": chrono::Utc::now(),
-                    "type": r#type,
-                    "channel": channel.clone(),
-                    "data": data_json,
-                    "metadata": json!({"target": target_id})
-                })
+            // Read data from stdin if --data not provided or if --data @-
+            let data_json = read_data(data).await?;
+
+            let mut metadata = emit_metadata(target.as_deref());
+            // A request names where its reply goes, and the id the reply carries
+            let correlation = reply_to.map(|reply_to| pulse::Correlation::request(Some(&reply_to)));
+            if let Some(correlation) = &correlation {
+                correlation.attach(&mut metadata);
+            }
+            let pulse_json = pulse::new_pulse(&r#type, &channel, data_json, metadata);
+            let mut resp = send_pulse(&config.socket(), &channel, &pulse_json, deliver_at).await?;
+            if let Some(retry_after) = resp["retry_after_ms"].as_u64().filter(|_| retry) {
+                info!("Rate limited, retrying in {}ms", retry_after);
+                tokio::time::sleep(tokio::time::Duration::from_millis(retry_after)).await;
+                resp = send_pulse(&config.socket(), &channel, &pulse_json, deliver_at).await?;
+            }
+            if resp.get("status").and_then(|s| s.as_str()) == Some("error") {
+                let error_msg = resp.get("msg")
+                    .and_then(|m| m.as_str())
+                    .unwrap_or("Unknown error (missing or invalid 'msg' field)");
+                error!("emit failed: {}", error_msg);
+                std::process::exit(1);
+            } else if resp.get("scheduled").and_then(|s| s.as_bool()) == Some(true) {
+                println!("Scheduled for {}", resp["deliver_at"].as_str().unwrap_or("?"));
+                println!("Cancel token: {}", resp["token"].as_str().unwrap_or("?"));
             } else {
-                json!({
-                    "id": Uuid::new_v4(),
-                    "timestamp": chrono::Utc::now(),
-                    "type": r#type,
-                    "channel": channel.clone(),
-                    "data": data_json,
-                    "metadata": null
-                })
+                if let Some(alias) = resp["alias"].as_str() {
+                    info!("{} → {} (alias)", alias, resp["channel"].as_str().unwrap_or("?"));
+                }
+                if let Some(warning) = resp["warning"].as_str() {
+                    warn!("{}: {}", resp["channel"].as_str().unwrap_or("?"), warning);
+                }
+                println!("Emit successful");
+            }
+            if let Some(correlation) = correlation {
+                println!("Correlation id: {}", correlation.id);
+            }
+        }
+        Commands::Request { project, channel, r#type, data, target, timeout, format, pretty } => {
+            let decode = match format.as_str() {
+                "toon" | "raw" => false,
+                "json" => true,
+                other => anyhow::bail!("Unknown format '{}' (use toon, raw or json)", other),
             };
-
-            // Encode directly to TOON with key folding
-            let encode_opts = EncodeOptions::new()
-                .with_key_folding(KeyFoldingMode::Safe);
-            let toon_str = encode(&pulse_json, &encode_opts)
-                .context("Failed to encode to TOON")?;
-
-            // Message size validation
-            if toon_str.len() > MAX_MESSAGE_SIZE {
-                anyhow::bail!("Message too large: {} bytes (max {} bytes)", toon_str.len(), MAX_MESSAGE_SIZE);
+            if channels::is_pattern(&channel) {
+                anyhow::bail!("Cannot send a request to the pattern '{}'", channel);
             }
+            let timeout = scheduler::parse_delay(&timeout)?.to_std()?;
+            let project_path = Path::new(&project);
+            let config = Config::load(project_path)?;
+            let data_json = read_data(data).await?;
 
-            let encoded_data = toon_str.into_bytes();
-
-            let cmd_json = json!({
-                "cmd": "emit",
-                "channel": channel,
-                "format": "toon",
-                "data": general_purpose::STANDARD.encode(&encoded_data)
-            });
+            let correlation = pulse::Correlation::request(None);
+            let reply_channel = pulse::Correlation::reply_channel(&correlation.id);
 
-            let cmd_str = serde_json::to_string(&cmd_json).context("Failed to serialize command")?;
-            
-            // Split stream for read/write
-            let (reader_side, mut writer) = stream.split();
-            writer.write_all(cmd_str.as_bytes()).await?;
-            writer.write_all(b"\n").await?;
-            writer.flush().await?;
-            
-            // Read response
-            let mut reader = BufReader::new(reader_side).lines();
-            if let Some(resp_line) = reader.next_line().await.context("Failed to read response")? {
-                let resp: Value = serde_json::from_str(&resp_line).context("Failed to parse response")?;
-                if resp.get("status").and_then(|s| s.as_str()) == Some("error") {
-                    let error_msg = resp.get("msg")
-                        .and_then(|m| m.as_str())
-                        .unwrap_or("Unknown error (missing or invalid 'msg' field)");
-                    eprintln!("Emit failed: {}", error_msg);
+            // Listen before asking. The reply channel is new, so its history
+            // holds a reply that lands before the subscription does.
+            let mut stream = UnixStream::connect(config.socket())
+                .await
+                .with_context(|| format!(
+                    "Failed to connect to daemon socket at {:?}. Is the daemon running?",
+                    config.socket()
+                ))?;
+            let subscribe = SubscribeRequest { channel: reply_channel.clone(), framed: true, ..Default::default() }.command();
+            stream.write_all(subscribe.to_string().as_bytes()).await?;
+            stream.write_all(b"\n").await?;
+            stream.flush().await?;
+
+            let mut metadata = emit_metadata(target.as_deref());
+            correlation.attach(&mut metadata);
+            let pulse_json = pulse::new_pulse(&r#type, &channel, data_json, metadata);
+            let resp = send_pulse(&config.socket(), &channel, &pulse_json, None).await?;
+            if resp["status"] == "error" {
+                error!("request failed: {}", resp["msg"].as_str().unwrap_or("unknown error"));
+                std::process::exit(1);
+            }
+            info!("Waiting for a reply on {}", reply_channel);
+
+            let mut lines = BufReader::new(stream).lines();
+            let wait = async {
+                while let Some(line) = lines.next_line().await? {
+                    let Frame::Message(toon) = Frame::parse(&line) else { continue };
+                    if is_shutdown_notice(&toon) {
+                        return Ok(None);
+                    }
+                    let matches = pulse::decode_pulse(&toon)
+                        .ok()
+                        .and_then(|reply| pulse::Correlation::from_pulse(&reply))
+                        .is_some_and(|c| c.id == correlation.id);
+                    if matches {
+                        return Ok(Some(toon));
+                    }
+                }
+                Ok::<_, anyhow::Error>(None)
+            };
+            match tokio::time::timeout(timeout, wait).await {
+                Ok(Ok(Some(toon))) if decode => print_decoded(&toon, pretty),
+                Ok(Ok(Some(toon))) => println!("{}", toon),
+                Ok(Ok(None)) => {
+                    error!("daemon shut down before a reply came");
+                    std::process::exit(1);
+                }
+                Ok(Err(e)) => return Err(e.context("Failed to read reply")),
+                Err(_) => {
+                    error!("no reply to {} within {:?}", correlation.id, timeout);
                     std::process::exit(1);
-                } else {
-                    println!("Emit successful");
                 }
             }
         }
-        Commands::Subscribe { project, channel, format, once } => {
-            // Validate format parameter
-            if format != "toon" {
-                anyhow::bail!("Only 'toon' format is supported (got: {})", format);
+        Commands::Reply { project, to, channel, r#type, data } => {
+            if to.trim().is_empty() {
+                anyhow::bail!("--to needs the correlation id of the request");
             }
+            let channel = channel.unwrap_or_else(|| pulse::Correlation::reply_channel(&to));
+            let project_path = Path::new(&project);
+            let config = Config::load(project_path)?;
+            let data_json = read_data(data).await?;
+
+            let mut metadata = emit_metadata(None);
+            pulse::Correlation::reply(&to).attach(&mut metadata);
+            let pulse_json = pulse::new_pulse(&r#type, &channel, data_json, metadata);
+            let resp = send_pulse(&config.socket(), &channel, &pulse_json, None).await?;
+            if resp["status"] == "error" {
+                error!("reply failed: {}", resp["msg"].as_str().unwrap_or("unknown error"));
+                std::process::exit(1);
+            }
+            println!("Replied on {}", channel);
+        }
+        Commands::Subscribe {
+            project, channel, format, pretty, once, count, idle_timeout, max_duration,
+            last, since, no_history, agent_id, role, description, filter,
+            callback, callback_timeout, concurrency,
+        } => {
+            // Validate format parameter; toon and raw both pass messages through
+            let decode = match format.as_str() {
+                "toon" | "raw" => false,
+                "json" => true,
+                _ => anyhow::bail!("Unknown format '{}' (expected toon, raw or json)", format),
+            };
 
             // Validate channel name
             if channel.trim().is_empty() {
                 anyhow::bail!("Channel name cannot be empty");
             }
 
+            // Resolve a relative --since here, so the daemon only sees a timestamp
+            let since = match since {
+                Some(since) => Some(match chrono::DateTime::parse_from_rfc3339(&since) {
+                    Ok(at) => at.with_timezone(&chrono::Utc),
+                    Err(_) => scheduler::parse_delay(&since)
+                        .and_then(|delay| scheduler::before(chrono::Utc::now(), delay))
+                        .with_context(|| format!("Invalid --since (expected RFC 3339 or e.g. 10m): {}", since))?,
+                }),
+                None => None,
+            };
+            let parse_duration = |duration: Option<String>| -> Result<Option<std::time::Duration>> {
+                match duration {
+                    Some(duration) => Ok(Some(scheduler::parse_delay(&duration)?.to_std()?)),
+                    None => Ok(None),
+                }
+            };
+            let callback_timeout = parse_duration(callback_timeout)?;
+            let idle_timeout = parse_duration(idle_timeout)?;
+            let max_duration = parse_duration(max_duration)?;
+            let timed = idle_timeout.is_some() || max_duration.is_some();
+            let limit = if once { Some(1) } else { count };
+            let filter = filter.to_filter()?;
+
             let project_path = Path::new(&project);
             let config = Config::load(project_path)?;
             
-            let mut stream = UnixStream::connect(&config.socket_path)
+            let mut stream = UnixStream::connect(config.socket())
                 .await
                 .context(format!(
                     "Failed to connect to daemon socket at {:?}. \
                     Is the daemon running? Try:\n  \
                     1. Check status: hydra-mail status\n  \
                     2. Start daemon: hydra-mail start --daemon",
-                    config.socket_path
+                    config.socket()
                 ))?;
             
             let (reader_side, mut writer) = stream.split();
             let mut reader = BufReader::new(reader_side).lines();
             
-            let cmd_json = json!({
-                "cmd": "subscribe",
-                "channel": channel
-            });
+            let mut opts = SubscribeOptions { history: !no_history, last, since, ..Default::default() };
+            if let Some(agent_id) = agent_id.or_else(|| std::env::var("HYDRA_AGENT_ID").ok()) {
+                opts.agent_id = Some(agent_id);
+                // What `hydra-mail agents` shows about this subscriber
+                opts.role = role.or_else(|| std::env::var("HYDRA_ROLE").ok());
+                opts.description = description;
+            }
+            // Framed, so a multi-line message arrives as one line: decoding,
+            // counting, filtering, callbacks and spotting the daemon's
+            // shutdown notice all need to know where a message ends. The
+            // timeouts need to know where the history ends, which only a
+            // framed stream can mark.
+            let cmd_json = SubscribeRequest { channel: channel.clone(), opts, framed: true, mark_history: timed };
             
-            let cmd_str = serde_json::to_string(&cmd_json).context("Failed to serialize command")?;
+            let cmd_str = cmd_json.command().to_string();
             writer.write_all(cmd_str.as_bytes()).await?;
             writer.write_all(b"\n").await?;
             writer.flush().await?;
             
             // Stream messages
             let mut message_count = 0;
-            while let Some(line) = reader.next_line().await.context("Failed to read from daemon")? {
+            let mut message_channel = channel.clone();
+            let mut pending_header = None;
+            let callbacks = std::sync::Arc::new(tokio::sync::Semaphore::new(concurrency as usize));
+            // Without timeouts there is no history marker to wait for
+            let mut history_done = !timed;
+            let mut deadline: Option<tokio::time::Instant> = None;
+            let mut timed_out = false;
+            let mut shut_down = false;
+            loop {
+                let next = reader.next_line();
+                let wait = match (history_done, idle_timeout, deadline) {
+                    (false, _, _) => None,
+                    (true, idle, Some(deadline)) => {
+                        let left = deadline.saturating_duration_since(tokio::time::Instant::now());
+                        Some(idle.map_or(left, |idle| idle.min(left)))
+                    }
+                    (true, idle, None) => idle,
+                };
+                let line = match wait {
+                    Some(wait) => match tokio::time::timeout(wait, next).await {
+                        Ok(line) => line,
+                        Err(_) => {
+                            timed_out = true;
+                            break;
+                        }
+                    },
+                    None => next.await,
+                };
+                let Some(line) = line.context("Failed to read from daemon")? else {
+                    break;
+                };
+                let toon = match Frame::parse(&line) {
+                    Frame::Message(toon) => toon,
+                    Frame::HistoryEnd => {
+                        history_done = true;
+                        deadline = max_duration.map(|max| tokio::time::Instant::now() + max);
+                        continue;
+                    }
+                    Frame::Lagged { channel, missed, resume_seq } => {
+                        warn!("fell behind: {} message(s) dropped, resuming at {} #{}", missed, channel, resume_seq);
+                        continue;
+                    }
+                    // A pattern's channel headers name the channel of what follows
+                    Frame::Header(header) => {
+                        message_channel = header;
+                        pending_header = Some(line);
+                        continue;
+                    }
+                };
+                if is_shutdown_notice(&toon) {
+                    shut_down = true;
+                    break;
+                }
+                if !filter.is_empty() {
+                    match filter.matches(&toon) {
+                        Ok(true) => {}
+                        Ok(false) => continue,
+                        Err(e) => {
+                            warn!("could not decode message, skipping it: {:#}", e);
+                            continue;
+                        }
+                    }
+                }
                 message_count += 1;
-                println!("{}", line);
-                
-                if once {
+
+                if let Some(script) = &callback {
+                    let message = if decode {
+                        match pulse::decode_pulse(&toon) {
+                            Ok(value) if pretty => format!("{:#}", value),
+                            Ok(value) => value.to_string(),
+                            Err(e) => {
+                                warn!("could not decode message, passing it to the callback as is: {:#}", e);
+                                toon
+                            }
+                        }
+                    } else {
+                        toon
+                    };
+
+                    if once {
+                        let outcome = hooks::run_script(script, &message_channel, &message, None, callback_timeout).await;
+                        report_callback(&outcome, &message_channel, callback_timeout);
+                        std::process::exit(match outcome {
+                            Ok(Some(status)) => status.code().unwrap_or(1),
+                            _ => 1,
+                        });
+                    }
+                    // Waits here while `concurrency` callbacks are running
+                    let permit = callbacks.clone().acquire_owned().await?;
+                    let script = script.clone();
+                    let message_channel = message_channel.clone();
+                    tokio::spawn(async move {
+                        let outcome = hooks::run_script(&script, &message_channel, &message, None, callback_timeout).await;
+                        report_callback(&outcome, &message_channel, callback_timeout);
+                        drop(permit);
+                    });
+                } else if decode {
+                    // Each pulse names its channel, so the headers stay out
+                    print_decoded(&toon, pretty);
+                } else {
+                    if let Some(header) = pending_header.take() {
+                        println!("{}", header);
+                    }
+                    println!("{}", toon);
+                }
+
+                if limit.is_some_and(|limit| message_count >= limit) {
                     break;
                 }
             }
-            
-            if message_count == 0 {
+            // Let the callbacks still running finish
+            let _ = callbacks.acquire_many(concurrency).await;
+
+            if timed_out {
+                error!("subscription timed out after {} message(s)", message_count);
+                std::process::exit(SUBSCRIBE_TIMEOUT_EXIT_CODE);
+            }
+            if shut_down {
+                info!("Daemon shut down after {} message(s)", message_count);
+            } else if message_count == 0 {
                 println!("No messages received (channel may be empty)");
             }
         }
+        Commands::Ping { project, timeout } => {
+            let timeout = match timeout {
+                Some(timeout) => scheduler::parse_delay(&timeout)?.to_std()?,
+                None => std::time::Duration::from_secs(PING_TIMEOUT_SECS),
+            };
+            let project_path = Path::new(&project);
+            let socket_path = match Config::load(project_path).map(|config| config.socket()) {
+                Ok(socket_path) if socket_path.exists() => socket_path,
+                Ok(socket_path) => {
+                    error!("daemon not running: no socket at {}", socket_path.display());
+                    std::process::exit(1);
+                }
+                Err(e) => {
+                    error!("daemon not running: {:#}", e);
+                    std::process::exit(1);
+                }
+            };
+            match tokio::time::timeout(timeout, request(&socket_path, &json!({"cmd": "ping"}))).await {
+                Ok(Ok(resp)) if resp["status"].as_str() == Some("ok") => {
+                    println!("ok: daemon {} up for {}s",
+                        resp["version"].as_str().unwrap_or("?"),
+                        resp["uptime_secs"].as_u64().unwrap_or(0)
+                    );
+                }
+                Ok(Ok(resp)) => {
+                    error!("daemon answered with an error: {}", resp["msg"].as_str().unwrap_or("unknown error"));
+                    std::process::exit(2);
+                }
+                Ok(Err(e)) => {
+                    error!("daemon not responding at {}: {:#}", socket_path.display(), e);
+                    std::process::exit(2);
+                }
+                Err(_) => {
+                    error!("daemon did not answer within {:?} (stuck?)", timeout);
+                    std::process::exit(2);
+                }
+            }
+        }
         Commands::Status { project } => {
             let project_path = Path::new(&project);
             let hydra_dir = project_path.join(".hydra");
@@ -539,12 +1370,13 @@ async fn main() -> Result<()> {
             }
             
             let config = Config::load(project_path)?;
+            let (socket_path, socket_source) = config.socket_location();
             println!("Hydra Status for {:?}", project_path);
             println!("Project UUID: {}", config.project_uuid);
-            println!("Socket path: {:?}", config.socket_path);
+            println!("Socket path: {:?} (from {})", socket_path, socket_source);
             
             // Check if socket exists
-            if config.socket_path.exists() {
+            if socket_path.exists() {
                 println!("Socket: ✓ exists");
             } else {
                 println!("Socket: ✗ missing (daemon not running?)");
@@ -559,15 +1391,11 @@ async fn main() -> Result<()> {
                         let check = Command::new("ps")
                             .arg("-p")
                             .arg(pid.to_string())
-                            .output();
+                            .logged_output();
                         
                         match check {
                             Ok(output) if output.status.success() => {
                                 println!("Daemon: ✓ running (PID: {})", pid);
-                                
-                                // List active channels (if daemon is running, we can query it)
-                                // For now, just show config defaults
-                                println!("Default topics: {}", config.default_topics.join(", "));
                             }
                             _ => {
                                 println!("Daemon: ✗ PID file exists but process not found (PID: {})", pid);
@@ -581,8 +1409,8 @@ async fn main() -> Result<()> {
             }
             
             // Try to get stats from daemon if running
-            if config.socket_path.exists() {
-                match UnixStream::connect(&config.socket_path).await {
+            if socket_path.exists() {
+                match UnixStream::connect(&socket_path).await {
                     Ok(mut stream) => {
                         use tokio::io::{AsyncWriteExt, AsyncBufReadExt, BufReader};
 
@@ -605,10 +1433,43 @@ async fn main() -> Result<()> {
                                             let name = ch["channel"].as_str().unwrap_or("?");
                                             let buffer_size = ch["replay_buffer_size"].as_u64().unwrap_or(0);
                                             let subs = ch["subscriber_count"].as_u64().unwrap_or(0);
-                                            println!("  {} - {} msgs buffered, {} subscribers", name, buffer_size, subs);
+                                            let limited = match ch["rate_limited"].as_u64().unwrap_or(0) {
+                                                0 => String::new(),
+                                                n => format!(", {} emits rate limited", n),
+                                            };
+                                            let expired = match ch["expired"].as_u64().unwrap_or(0) {
+                                                0 => String::new(),
+                                                n => format!(", {} msgs expired", n),
+                                            };
+                                            println!("  {} - {} msgs buffered, {} subscribers{}{}", name, buffer_size, subs, limited, expired);
                                         }
                                     }
                                 }
+                                if let Some(aliases) = resp["aliases"].as_array().filter(|a| !a.is_empty()) {
+                                    println!("\nChannel Aliases:");
+                                    for alias in aliases {
+                                        println!("  {} → {} - {} uses",
+                                            alias["alias"].as_str().unwrap_or("?"),
+                                            alias["channel"].as_str().unwrap_or("?"),
+                                            alias["uses"].as_u64().unwrap_or(0));
+                                    }
+                                }
+                                if let Some(hooks) = resp["hooks"].as_array() {
+                                    println!("\nHooks:");
+                                    for hook in hooks {
+                                        let count = |key: &str| hook[key].as_u64().unwrap_or(0);
+                                        println!("  {} → {} - {} runs, {} failed, {} timed out, {} skipped",
+                                            hook["channel_pattern"].as_str().unwrap_or("?"),
+                                            hook["command"].as_str().unwrap_or("?"),
+                                            count("fired"), count("failed"), count("timed_out"), count("skipped"));
+                                    }
+                                }
+                                if let Some(archive) = resp.get("archive") {
+                                    println!("\nArchive: {} written, {} dropped, {} failed",
+                                        archive["written"].as_u64().unwrap_or(0),
+                                        archive["dropped"].as_u64().unwrap_or(0),
+                                        archive["errors"].as_u64().unwrap_or(0));
+                                }
                             }
                         }
 
@@ -633,40 +1494,448 @@ async fn main() -> Result<()> {
 
             // Load config to get socket path
             let config = Config::load(project_path)?;
-
-            // Read PID
             let pid_path = hydra_dir.join("daemon.pid");
-            if !pid_path.exists() {
-                println!("No daemon.pid found in {:?}. Daemon not running?", project_path);
-                return Ok(());
-            }
-
-            let pid_str = fs::read_to_string(&pid_path)
-                .context("Failed to read daemon.pid")?;
-            let pid: u32 = pid_str.trim().parse()
-                .context("Invalid PID in daemon.pid")?;
-
-            // Kill the process
-            let kill_output = Command::new("kill")
-                .arg("-TERM")
-                .arg(pid.to_string())
-                .output();
-
-            match kill_output {
-                Ok(output) if output.status.success() => {
+            let token_path = hydra_dir.join("daemon.token");
+
+            // Ask over the socket first: unlike a signal, that works when the
+            // daemon runs as another user or in another pid namespace
+            let token = fs::read_to_string(&token_path).ok();
+            match request_shutdown(&config.socket(), token.as_deref()).await {
+                Ok((pid, mut lines)) => {
+                    // The daemon tells its subscribers and lets them drain,
+                    // and the connection closes when it exits
+                    let closed = tokio::time::timeout(
+                        tokio::time::Duration::from_secs(STOP_TIMEOUT_SECS),
+                        async { while let Ok(Some(_)) = lines.next_line().await {} },
+                    ).await;
+                    if closed.is_err() {
+                        error!("daemon (PID: {}) still running after {}s", pid, STOP_TIMEOUT_SECS);
+                        std::process::exit(1);
+                    }
                     println!("Daemon (PID: {}) terminated gracefully", pid);
                 }
-                Ok(_) | Err(_) => {
-                    println!("Daemon (PID: {}) may not be running or already terminated", pid);
+                Err(e) => {
+                    info!("Shutdown request failed, falling back to SIGTERM: {:#}", e);
+
+                    // Read PID
+                    if !pid_path.exists() {
+                        println!("No daemon.pid found in {:?}. Daemon not running?", project_path);
+                        return Ok(());
+                    }
+
+                    let pid_str = fs::read_to_string(&pid_path)
+                        .context("Failed to read daemon.pid")?;
+                    let pid: u32 = pid_str.trim().parse()
+                        .context("Invalid PID in daemon.pid")?;
+
+                    // Kill the process
+                    let kill_output = Command::new("kill")
+                        .arg("-TERM")
+                        .arg(pid.to_string())
+                        .logged_output();
+
+                    match kill_output {
+                        Ok(output) if output.status.success() => {
+                            // The daemon tells its subscribers and lets them drain
+                            // before exiting; only then is it stopped
+                            let deadline = std::time::Instant::now() + std::time::Duration::from_secs(STOP_TIMEOUT_SECS);
+                            while process_running(pid) {
+                                if std::time::Instant::now() >= deadline {
+                                    error!("daemon (PID: {}) still running after {}s", pid, STOP_TIMEOUT_SECS);
+                                    std::process::exit(1);
+                                }
+                                tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+                            }
+                            println!("Daemon (PID: {}) terminated gracefully", pid);
+                        }
+                        Ok(_) | Err(_) => {
+                            println!("Daemon (PID: {}) may not be running or already terminated", pid);
+                        }
+                    }
                 }
             }
 
             // Clean up files using config socket path
             let _ = fs::remove_file(&pid_path);
-            let _ = fs::remove_file(&config.socket_path);
+            let _ = fs::remove_file(&token_path);
+            let _ = fs::remove_file(config.socket());
             let _ = fs::remove_file(hydra_dir.join("daemon.err"));
             println!("Cleaned up daemon files in {:?}", project_path);
         }
+        Commands::Tail { project, channel, last, format } => {
+            if !["pretty", "json", "toon"].contains(&format.as_str()) {
+                anyhow::bail!("Unknown format '{}' (expected pretty, json or toon)", format);
+            }
+            if channel.trim().is_empty() {
+                anyhow::bail!("Channel name cannot be empty");
+            }
+            let color = format == "pretty"
+                && std::io::IsTerminal::is_terminal(&std::io::stdout())
+                && std::env::var_os("NO_COLOR").is_none();
+
+            // Everything for everyone: tail is for watching, not for acting
+            // as an agent
+            let client = MailClient::connect(Path::new(&project)).await?;
+            let opts = SubscribeOptions { last: Some(last), ..Default::default() };
+            let mut subscription = client.subscribe(&channel, opts).await?;
+            // As subscribe prints a pattern's messages
+            let headers = channels::is_pattern(&channel);
+            // Ends when the daemon shuts down or hangs up
+            while let Some(pulse) = subscription.next().await {
+                match format.as_str() {
+                    "toon" => {
+                        if headers {
+                            println!("==> {} <==", pulse["channel"].as_str().unwrap_or("?"));
+                        }
+                        println!("{}", pulse::encode_pulse(&pulse)?);
+                    }
+                    "json" => println!("{}", pulse),
+                    _ => println!("{}", pretty_line(&pulse, color)),
+                }
+            }
+        }
+        Commands::History { project, channel, last, format, pretty, agent_id, filter } => {
+            let decode = match format.as_str() {
+                "toon" | "raw" => false,
+                "json" => true,
+                _ => anyhow::bail!("Unknown format '{}' (expected toon, raw or json)", format),
+            };
+            if channel.trim().is_empty() {
+                anyhow::bail!("Channel name cannot be empty");
+            }
+            let filter = filter.to_filter()?;
+
+            let config = Config::load(Path::new(&project))?;
+            let cmd = HistoryRequest {
+                channel: channel.clone(),
+                agent_id: agent_id.or_else(|| std::env::var("HYDRA_AGENT_ID").ok()),
+                last,
+                since: None,
+            };
+            let resp = request(&config.socket(), &cmd.command()).await?;
+            if resp["status"].as_str() == Some("error") {
+                error!("{}", resp["msg"].as_str().unwrap_or("unknown error"));
+                std::process::exit(1);
+            }
+
+            let HistoryResponse { mut messages, expired } =
+                serde_json::from_value(resp).context("Failed to parse history response")?;
+            if expired > 0 {
+                info!("Left out {} message(s) older than the replay TTL", expired);
+            }
+            if !filter.is_empty() {
+                messages.retain(|entry| match filter.matches(&entry.message) {
+                    Ok(matches) => matches,
+                    Err(e) => {
+                        warn!("could not decode message, skipping it: {:#}", e);
+                        false
+                    }
+                });
+            }
+            if messages.is_empty() {
+                // Keep stdout clean for whatever parses the JSON
+                if decode {
+                    eprintln!("No messages in {}", channel);
+                } else {
+                    println!("No messages in {}", channel);
+                }
+            }
+            for entry in &messages {
+                if decode {
+                    print_decoded(&entry.message, pretty);
+                } else if channels::is_pattern(&channel) {
+                    println!("{}\n{}", Frame::Header(entry.channel.clone()), entry.message);
+                } else {
+                    println!("{}", entry.message);
+                }
+            }
+        }
+        Commands::Export { project, channel, output } => {
+            if channel.trim().is_empty() {
+                anyhow::bail!("Channel name cannot be empty");
+            }
+            let project_path = Path::new(&project);
+            let config = Config::load(project_path)?;
+
+            // The log keeps more than the buffer and doesn't need the daemon
+            let log_path = project_path.join(".hydra").join("messages.log");
+            let (messages, source) = if config.persistence.enabled && log_path.exists() {
+                (logged_messages(&config, &log_path, &channel)?, "the message log")
+            } else {
+                let cmd = HistoryRequest { channel: channel.clone(), ..HistoryRequest::default() };
+                let HistoryResponse { messages, .. } = MailClient::locate(project_path)?.call(&cmd).await?;
+                (messages.into_iter().map(|entry| entry.message).collect(), "the replay buffer")
+            };
+
+            let mut out: Box<dyn std::io::Write> = match &output {
+                Some(path) => Box::new(std::io::BufWriter::new(
+                    fs::File::create(path).with_context(|| format!("Failed to create {}", path))?,
+                )),
+                None => Box::new(std::io::stdout().lock()),
+            };
+            let mut exported = 0;
+            for toon in &messages {
+                match pulse::decode_pulse(toon) {
+                    Ok(pulse) => {
+                        writeln!(out, "{}", pulse)?;
+                        exported += 1;
+                    }
+                    Err(e) => warn!("could not decode message, leaving it out: {:#}", e),
+                }
+            }
+            out.flush()?;
+            let summary = format!("Exported {} message(s) of {} from {}", exported, channel, source);
+            // Keep stdout to the pulses when they go there
+            if output.is_some() {
+                println!("{}", summary);
+            } else {
+                eprintln!("{}", summary);
+            }
+        }
+        Commands::Import { project, channel, input, preserve_timestamps, rate } => {
+            if let Some(problem) = emit_channel_problem(&channel) {
+                anyhow::bail!(problem);
+            }
+            let config = Config::load(Path::new(&project))?;
+            let pace: RateLimit = match rate {
+                Some(rate) => rate.parse()?,
+                None => RateLimiter::new(&config.limits).limit_for(&channel),
+            };
+            let text = if input == "-" {
+                let mut text = String::new();
+                tokio::io::AsyncReadExt::read_to_string(&mut tokio::io::stdin(), &mut text).await
+                    .context("Failed to read stdin")?;
+                text
+            } else {
+                fs::read_to_string(&input).with_context(|| format!("Failed to read {}", input))?
+            };
+
+            // Every line has to check out before anything is sent
+            let mut emits = Vec::new();
+            let mut invalid = 0;
+            for (number, line) in text.lines().enumerate().map(|(i, line)| (i + 1, line)) {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match import_pulse(line, &channel, preserve_timestamps) {
+                    Ok(emit) => emits.push((number, emit)),
+                    Err(e) => {
+                        error!("line {}: {:#}", number, e);
+                        invalid += 1;
+                    }
+                }
+            }
+            if invalid > 0 {
+                error!("Nothing imported: {} invalid line(s)", invalid);
+                std::process::exit(1);
+            }
+            warn_unexpected_emit(&channel);
+
+            let client = MailClient::locate(Path::new(&project))?;
+            let (imported, skipped) = import_emits(&client, &emits, pace).await?;
+            println!("Imported {} message(s) to {}, skipped {}", imported, channel, skipped);
+        }
+        Commands::Purge { project, channel, all, yes } => {
+            let what = match &channel {
+                Some(channel) => format!("the history of {}", channel),
+                None => "the history of every channel".to_string(),
+            };
+            if !yes {
+                print!("Purge {}? Subscribers stay connected. [y/N] ", what);
+                std::io::Write::flush(&mut std::io::stdout())?;
+
+                let mut input = String::new();
+                std::io::stdin().read_line(&mut input)?;
+                if !input.trim().eq_ignore_ascii_case("y") {
+                    println!("Purge cancelled.");
+                    return Ok(());
+                }
+            }
+
+            let config = Config::load(Path::new(&project))?;
+            let cmd = match channel {
+                Some(channel) => json!({"cmd": "purge", "channel": channel}),
+                None => json!({"cmd": "purge", "all": all}),
+            };
+            let resp = request(&config.socket(), &cmd).await?;
+            if resp["status"].as_str() == Some("error") {
+                error!("{}", resp["msg"].as_str().unwrap_or("unknown error"));
+                std::process::exit(1);
+            }
+            let purged = resp["purged"].as_array().cloned().unwrap_or_default();
+            if purged.is_empty() {
+                println!("Nothing to purge");
+            }
+            for entry in &purged {
+                println!("✓ {}: purged {} message(s)",
+                    entry["channel"].as_str().unwrap_or("?"),
+                    entry["messages"].as_u64().unwrap_or(0)
+                );
+            }
+        }
+        Commands::Channels { project, json } => {
+            let config = Config::load(Path::new(&project))?;
+            let resp = request(&config.socket(), &json!({"cmd": "list_channels"})).await?;
+            if resp["status"].as_str() == Some("error") {
+                error!("{}", resp["msg"].as_str().unwrap_or("unknown error"));
+                std::process::exit(1);
+            }
+
+            let channels = resp["channels"].as_array().cloned().unwrap_or_default();
+            if json {
+                for channel in &channels {
+                    println!("{}", channel);
+                }
+            } else if channels.is_empty() {
+                println!("No active channels");
+            } else {
+                println!("{:<32} {:>11} {:>8}", "CHANNEL", "SUBSCRIBERS", "BUFFERED");
+                for channel in &channels {
+                    let unknown = channel["unknown_namespace"].as_bool() == Some(true);
+                    println!("{:<32} {:>11} {:>8}{}",
+                        channel["channel"].as_str().unwrap_or("?"),
+                        channel["subscriber_count"].as_u64().unwrap_or(0),
+                        channel["replay_buffer_size"].as_u64().unwrap_or(0),
+                        if unknown { "  unknown namespace" } else { "" }
+                    );
+                }
+            }
+        }
+        Commands::Agents { project, json } => {
+            let config = Config::load(Path::new(&project))?;
+            let resp = request(&config.socket(), &json!({"cmd": "agents"})).await?;
+            if resp["status"].as_str() == Some("error") {
+                error!("{}", resp["msg"].as_str().unwrap_or("unknown error"));
+                std::process::exit(1);
+            }
+
+            let agents = resp["agents"].as_array().cloned().unwrap_or_default();
+            if json {
+                for agent in &agents {
+                    println!("{}", agent);
+                }
+            } else if agents.is_empty() {
+                println!("No agents connected");
+            } else {
+                println!("{:<20} {:<12} {:<20} CHANNELS", "AGENT", "ROLE", "CONNECTED");
+                for agent in &agents {
+                    let connected = agent["connected_at"].as_str()
+                        .and_then(|at| chrono::DateTime::parse_from_rfc3339(at).ok())
+                        .map(|at| at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+                        .unwrap_or_else(|| "?".to_string());
+                    let channels: Vec<&str> = agent["channels"].as_array()
+                        .map(|c| c.iter().filter_map(|c| c.as_str()).collect())
+                        .unwrap_or_default();
+                    println!("{:<20} {:<12} {:<20} {}",
+                        agent["agent_id"].as_str().unwrap_or("?"),
+                        agent["role"].as_str().unwrap_or("-"),
+                        connected,
+                        channels.join(", ")
+                    );
+                    if let Some(description) = agent["description"].as_str() {
+                        println!("{:<20} {}", "", description);
+                    }
+                }
+            }
+        }
+        #[cfg(feature = "sqlite")]
+        Commands::Query { project, sql, count_by, since, json } => {
+            use hydra_mail::archive;
+
+            let project_path = Path::new(&project);
+            let config = Config::load(project_path)?;
+            let Some(db_path) = config.archive.sqlite_path(project_path) else {
+                anyhow::bail!("No archive configured. Set [archive] sqlite = \".hydra/mail.db\" in .hydra/config.toml");
+            };
+            let conn = archive::open_read_only(&db_path)?;
+
+            let rows: Vec<Value> = match (sql, count_by) {
+                (Some(sql), _) => archive::query_sql(&conn, &sql)?.into_iter().map(Value::Object).collect(),
+                (None, Some(by)) => {
+                    let since = since
+                        .map(|s| scheduler::parse_delay(&s).and_then(|d| scheduler::before(chrono::Utc::now(), d)))
+                        .transpose()
+                        .context("Invalid --since")?;
+                    archive::count_by(&conn, by, since)?
+                        .into_iter()
+                        .map(|(key, count)| json!({"key": key, "count": count}))
+                        .collect()
+                }
+                (None, None) => anyhow::bail!("Pass --sql or --count-by"),
+            };
+
+            for row in &rows {
+                if json {
+                    println!("{}", row);
+                    continue;
+                }
+                let cells: Vec<String> = row.as_object()
+                    .map(|o| o.values().map(|v| match v {
+                        Value::String(s) => s.clone(),
+                        other => other.to_string(),
+                    }).collect())
+                    .unwrap_or_default();
+                println!("{}", cells.join("\t"));
+            }
+        }
+        Commands::Scheduled { action } => {
+            let (project, cmd_json) = match action {
+                ScheduledAction::List { project } => (project, json!({"cmd": "list_scheduled"})),
+                ScheduledAction::Cancel { token, project } => {
+                    (project, json!({"cmd": "cancel_scheduled", "token": token}))
+                }
+            };
+
+            let config = Config::load(Path::new(&project))?;
+            let resp = request(&config.socket(), &cmd_json).await?;
+            if resp["status"].as_str() == Some("error") {
+                error!("{}", resp["msg"].as_str().unwrap_or("unknown error"));
+                std::process::exit(1);
+            }
+
+            if cmd_json["cmd"] == "cancel_scheduled" {
+                println!("Cancelled {}", cmd_json["token"].as_str().unwrap_or("?"));
+            } else {
+                let scheduled = resp["scheduled"].as_array().cloned().unwrap_or_default();
+                if scheduled.is_empty() {
+                    println!("No scheduled messages");
+                } else {
+                    println!("{:<38} {:<27} {:<20} SIZE", "TOKEN", "DELIVER AT", "CHANNEL");
+                    for entry in scheduled {
+                        println!("{:<38} {:<27} {:<20} {}",
+                            entry["token"].as_str().unwrap_or("?"),
+                            entry["deliver_at"].as_str().unwrap_or("?"),
+                            entry["channel"].as_str().unwrap_or("?"),
+                            entry["size"].as_u64().unwrap_or(0)
+                        );
+                    }
+                }
+            }
+        }
+        Commands::Hooks { action: HooksAction::Test { channel, project } } => {
+            let config = Config::load(Path::new(&project))?;
+            // The daemon matches the channel an alias resolves to
+            let channel = match config.channels.aliases.get(&channel) {
+                Some(canonical) => {
+                    info!("{} → {} (alias)", channel, canonical);
+                    canonical.clone()
+                }
+                None => channel,
+            };
+            let matched = hooks::matching(&config.hooks, &channel);
+            if matched.is_empty() {
+                println!("No hooks would run for {} ({} configured)", channel, config.hooks.len());
+                return Ok(());
+            }
+            println!("{} hook(s) would run for {}:", matched.len(), channel);
+            for hook in matched {
+                let timeout = match hook.timeout_secs {
+                    0 => "no timeout".to_string(),
+                    secs => format!("timeout {}s", secs),
+                };
+                println!("  {} → {} ({} at a time, {})", hook.channel_pattern, hook.command, hook.max_concurrent, timeout);
+            }
+        }
         Commands::Hook { event } => {
             match event {
                 HookEvent::SessionStart { project } => {
@@ -695,7 +1964,7 @@ async fn main() -> Result<()> {
                     };
 
                     // Check if daemon is running
-                    if !config.socket_path.exists() {
+                    if !config.socket().exists() {
                         println!("{}", json!({
                             "result": "continue",
                             "message": "Hydra Mail: Daemon not running. Run: hydra-mail init --daemon"
@@ -704,7 +1973,7 @@ async fn main() -> Result<()> {
                     }
 
                     // Connect and get recent messages
-                    match UnixStream::connect(&config.socket_path).await {
+                    match UnixStream::connect(config.socket()).await {
                         Ok(stream) => {
                             let (reader_side, mut writer) = stream.into_split();
                             let mut reader = BufReader::new(reader_side).lines();
@@ -724,21 +1993,16 @@ async fn main() -> Result<()> {
                             // Read messages with timeout (TOON format is multi-line YAML-like)
                             let mut content = String::new();
                             let mut line_count = 0;
-                            loop {
-                                match tokio::time::timeout(
-                                    std::time::Duration::from_millis(100),
-                                    reader.next_line()
-                                ).await {
-                                    Ok(Ok(Some(line))) => {
-                                        content.push_str(&line);
-                                        content.push('\n');
-                                        line_count += 1;
-                                        // Stop after ~50 lines to avoid too much context
-                                        if line_count >= 50 {
-                                            break;
-                                        }
-                                    }
-                                    _ => break,
+                            while let Ok(Ok(Some(line))) = tokio::time::timeout(
+                                std::time::Duration::from_millis(100),
+                                reader.next_line()
+                            ).await {
+                                content.push_str(&line);
+                                content.push('\n');
+                                line_count += 1;
+                                // Stop after ~50 lines to avoid too much context
+                                if line_count >= 50 {
+                                    break;
                                 }
                             }
 
@@ -790,52 +2054,523 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-async fn handle_conn(mut stream: UnixStream, project_uuid: Uuid, limits: Limits) -> Result<()> {
-    use std::collections::VecDeque;
-    use std::time::Instant;
+/// Send a single command to the daemon and read its one-line response
+async fn request(socket_path: &Path, cmd: &Value) -> Result<Value> {
+    let mut stream = UnixStream::connect(socket_path)
+        .await
+        .with_context(|| format!(
+            "Failed to connect to daemon socket at {:?}. Is the daemon running?",
+            socket_path
+        ))?;
+
+    let (reader_side, mut writer) = stream.split();
+    writer.write_all(cmd.to_string().as_bytes()).await?;
+    writer.write_all(b"\n").await?;
+    writer.flush().await?;
+
+    let mut reader = BufReader::new(reader_side).lines();
+    let line = reader.next_line().await
+        .context("Failed to read response")?
+        .context("Daemon closed the connection without responding")?;
+    serde_json::from_str(&line).context("Failed to parse response")
+}
+
+/// The `--data` of an emit as JSON: the argument itself, or stdin when it is
+/// `@-` or missing
+async fn read_data(data: Option<String>) -> Result<Value> {
+    if let Some(data_str) = data.as_deref().filter(|d| *d != "@-") {
+        return serde_json::from_str(data_str).context("Failed to parse --data JSON");
+    }
+    // Read stdin with size limit
+    use tokio::io::AsyncReadExt;
+    let stdin = tokio::io::stdin();
+    let mut buffer = Vec::with_capacity(MAX_STDIN_SIZE);
+    let bytes_read = stdin.take(MAX_STDIN_SIZE as u64).read_to_end(&mut buffer).await
+        .context("Failed to read stdin")?;
+    if bytes_read == MAX_STDIN_SIZE {
+        anyhow::bail!("Stdin data too large (max {} bytes)", MAX_STDIN_SIZE);
+    }
+    let full_data = String::from_utf8(buffer)
+        .context("Invalid UTF-8 in stdin")?;
+    serde_json::from_str(&full_data).context("Failed to parse stdin JSON")
+}
+
+/// Why `channel` can't be emitted to, if it can't
+fn emit_channel_problem(channel: &str) -> Option<String> {
+    if channel.trim().is_empty() {
+        Some("Channel name cannot be empty".to_string())
+    } else if channels::is_pattern(channel) {
+        Some(format!("Cannot emit to a channel pattern: {}", channel))
+    } else {
+        None
+    }
+}
+
+/// Warn when `$HYDRA_ROLE` doesn't emit to `channel`. Roles only get a
+/// warning: the ACL guides agents, it doesn't police them.
+fn warn_unexpected_emit(channel: &str) {
+    if let Some(role) = std::env::var("HYDRA_ROLE").ok().and_then(|r| r.parse::<Role>().ok()) {
+        if !role.may_emit(channel) {
+            warn!(
+                "the {} role does not emit to '{}' (expected: {})",
+                role, channel, role.spec().emits.join(", ")
+            );
+        }
+    }
+}
+
+/// The flags of `emit --batch`, applying to every line
+struct BatchDefaults {
+    /// For lines without `_type`
+    r#type: Option<String>,
+    /// For lines without `_channel`
+    channel: Option<String>,
+    target: Option<String>,
+    deliver_at: Option<chrono::DateTime<chrono::Utc>>,
+    retry: bool,
+}
+
+/// The pulse for one line of `emit --batch`: a JSON object, less the
+/// `_type` and `_channel` overriding the flags
+fn batch_pulse(line: &str, defaults: &BatchDefaults) -> Result<EmitRequest> {
+    let mut data: Value = serde_json::from_str(line).context("Invalid JSON")?;
+    let fields = data.as_object_mut().context("Not a JSON object")?;
+    let mut take = |field: &str, default: &Option<String>| -> Result<Option<String>> {
+        match fields.remove(field) {
+            Some(Value::String(value)) => Ok(Some(value)),
+            Some(_) => anyhow::bail!("{} must be a string", field),
+            None => Ok(default.clone()),
+        }
+    };
+    let pulse_type = take("_type", &defaults.r#type)?.context("No _type and no --type")?;
+    let channel = take("_channel", &defaults.channel)?.context("No _channel and no --channel")?;
+    if let Some(problem) = emit_channel_problem(&channel) {
+        anyhow::bail!(problem);
+    }
+    warn_unexpected_emit(&channel);
+
+    let pulse_json = pulse::new_pulse(&pulse_type, &channel, data, emit_metadata(defaults.target.as_deref()));
+    let mut emit = EmitRequest::new(&channel, &pulse_json)?;
+    emit.deliver_at = defaults.deliver_at.map(|at| at.trunc_subsecs(0));
+    Ok(emit)
+}
+
+/// Emit each line of stdin over one connection, returning how each went,
+/// by line number: the channel it went to, or why it didn't. Blank lines
+/// are skipped; the size limit applies to each message.
+async fn emit_batch(client: &MailClient, defaults: &BatchDefaults) -> Result<Vec<(usize, Result<String, String>)>> {
+    let mut connection = client.connection().await?;
+    let mut stdin = BufReader::new(tokio::io::stdin()).lines();
+    let mut outcomes = Vec::new();
+    let mut number = 0;
+    let mut lost = None;
+    while let Some(line) = stdin.next_line().await.context("Failed to read stdin")? {
+        number += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        // Once the daemon is gone the rest can't be sent either
+        if let Some(lost) = &lost {
+            outcomes.push((number, Err(format!("not sent: {}", lost))));
+            continue;
+        }
+        let emit = match batch_pulse(&line, defaults) {
+            Ok(emit) => emit,
+            Err(e) => {
+                outcomes.push((number, Err(format!("{:#}", e))));
+                continue;
+            }
+        };
+        let mut resp = connection.call(&emit).await;
+        if let Some(retry_after) = retry_after(&resp).filter(|_| defaults.retry) {
+            info!("Rate limited, retrying line {} in {}ms", number, retry_after.as_millis());
+            tokio::time::sleep(retry_after).await;
+            resp = connection.call(&emit).await;
+        }
+        let outcome = match resp {
+            Ok(ack) => Ok(match ack.warning {
+                Some(warning) => format!("{}, {}", ack.channel, warning),
+                None => ack.channel,
+            }),
+            Err(e) if e.downcast_ref::<Refused>().is_some() => Err(e.to_string()),
+            Err(e) => {
+                let e = format!("{:#}", e);
+                lost = Some(e.clone());
+                Err(e)
+            }
+        };
+        outcomes.push((number, outcome));
+    }
+    Ok(outcomes)
+}
+
+/// The buffered messages of `channel` (or a pattern) as the message log at
+/// `log_path` has them, oldest first
+fn logged_messages(config: &Config, log_path: &Path, channel: &str) -> Result<Vec<String>> {
+    let channel = config.channels.aliases.get(channel).map_or(channel, String::as_str);
+    let entries = hydra_mail::message_log::MessageLog::open(log_path)?.replay()?;
+    Ok(entries
+        .into_iter()
+        .filter(|entry| entry.project_uuid == config.project_uuid && channels::pattern_matches(channel, &entry.channel))
+        .map(|entry| entry.message)
+        .collect())
+}
+
+/// The emit for one line of `import`: an exported pulse, moved to `channel`
+/// and stamped now unless `preserve_timestamp`
+fn import_pulse(line: &str, channel: &str, preserve_timestamp: bool) -> Result<EmitRequest> {
+    let mut pulse: Value = serde_json::from_str(line).context("Invalid JSON")?;
+    let fields = pulse.as_object_mut().context("Not a JSON object")?;
+    if !fields.get("type").is_some_and(Value::is_string) {
+        anyhow::bail!("Not a pulse: no type");
+    }
+    fields.insert("channel".to_string(), json!(channel));
+    if !preserve_timestamp || !fields.contains_key("timestamp") {
+        fields.insert("timestamp".to_string(), json!(chrono::Utc::now()));
+    }
+    EmitRequest::new(channel, &pulse)
+}
+
+/// Send `emits` over one connection, evenly spread so no window of `pace`
+/// sees more than it allows, returning how many the daemon took and how
+/// many it refused. A rate limited emit is retried once.
+async fn import_emits(client: &MailClient, emits: &[(usize, EmitRequest)], pace: RateLimit) -> Result<(usize, usize)> {
+    let mut connection = client.connection().await?;
+    let gap = (pace.max > 0).then(|| pace.per / u32::try_from(pace.max).unwrap_or(u32::MAX));
+    let start = tokio::time::Instant::now();
+    let (mut imported, mut skipped) = (0, 0);
+    for (i, (line, emit)) in emits.iter().enumerate() {
+        if let Some(gap) = gap {
+            tokio::time::sleep_until(start + gap * i as u32).await;
+        }
+        let lost = || format!("Lost the daemon after importing {} message(s)", imported);
+        let mut resp = connection.call(emit).await;
+        if let Some(retry_after) = retry_after(&resp) {
+            info!("Rate limited, retrying line {} in {}ms", line, retry_after.as_millis());
+            tokio::time::sleep(retry_after).await;
+            resp = connection.call(emit).await;
+        }
+        match resp {
+            Ok(_) => imported += 1,
+            Err(e) if e.downcast_ref::<Refused>().is_some() => {
+                warn!("line {}: skipped: {}", line, e);
+                skipped += 1;
+            }
+            Err(e) => return Err(e.context(lost())),
+        }
+    }
+    Ok((imported, skipped))
+}
+
+/// How long to wait before sending again, when the daemon refused over a
+/// rate limit
+fn retry_after<T>(resp: &Result<T>) -> Option<std::time::Duration> {
+    resp.as_ref().err()?.downcast_ref::<Refused>()?.retry_after
+}
+
+/// Pulse metadata for an emit: its target, and the trace id of the
+/// orchestrated session it comes from
+fn emit_metadata(target: Option<&str>) -> Value {
+    let mut metadata = match target {
+        Some(target_id) => json!({"target": target_id}),
+        None => Value::Null,
+    };
+    if let Some(ctx) = TraceContext::from_env() {
+        ctx.attach(&mut metadata);
+    }
+    metadata
+}
+
+/// Encode a pulse and emit it, returning the daemon's response
+async fn send_pulse(
+    socket_path: &Path,
+    channel: &str,
+    pulse_json: &Value,
+    deliver_at: Option<chrono::DateTime<chrono::Utc>>,
+) -> Result<Value> {
+    let mut emit = EmitRequest::new(channel, pulse_json)?;
+    emit.deliver_at = deliver_at.map(|at| at.trunc_subsecs(0));
+    request(socket_path, &emit.command()).await
+}
+
+type ArchiveWriter = Option<std::thread::JoinHandle<()>>;
+
+/// Start the SQLite archive writer if `[archive] sqlite` is set
+#[cfg(feature = "sqlite")]
+fn start_archive(config: &Config, project_root: &Path) -> ArchiveWriter {
+    let path = config.archive.sqlite_path(project_root)?;
+    let max_bytes = config.archive.max_size_mb.saturating_mul(1024 * 1024);
+    match hydra_mail::archive::start(&path, max_bytes, config.archive.queue_capacity) {
+        Ok((sink, handle)) => {
+            channels::set_archive_sink(Some(sink));
+            info!("Archiving messages to {}", path.display());
+            Some(handle)
+        }
+        Err(e) => {
+            warn!("failed to start message archive: {:#}", e);
+            None
+        }
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn start_archive(config: &Config, _project_root: &Path) -> ArchiveWriter {
+    if config.archive.sqlite.is_some() {
+        warn!("[archive] sqlite is set but hydra-mail was built without the 'sqlite' feature");
+    }
+    None
+}
+
+/// Drop the sink and wait for the writer to flush what is queued
+fn stop_archive(writer: ArchiveWriter) {
+    #[cfg(feature = "sqlite")]
+    channels::set_archive_sink(None);
+    if let Some(handle) = writer {
+        let _ = handle.join();
+    }
+}
+
+/// Send the daemon a `shutdown` request, returning the PID it reports and
+/// the connection, which it holds open until it exits
+async fn request_shutdown(
+    socket_path: &Path,
+    token: Option<&str>,
+) -> Result<(u64, tokio::io::Lines<BufReader<tokio::net::unix::OwnedReadHalf>>)> {
+    let stream = UnixStream::connect(socket_path)
+        .await
+        .with_context(|| format!("Failed to connect to daemon socket at {:?}", socket_path))?;
+    let (reader, mut writer) = stream.into_split();
+    let cmd = json!({"cmd": "shutdown", "token": token.map(str::trim)});
+    writer.write_all(cmd.to_string().as_bytes()).await?;
+    writer.write_all(b"\n").await?;
+    writer.flush().await?;
+
+    let mut lines = BufReader::new(reader).lines();
+    let line = tokio::time::timeout(tokio::time::Duration::from_secs(PING_TIMEOUT_SECS), lines.next_line())
+        .await
+        .context("No answer to the shutdown request")??
+        .context("Daemon closed the connection without answering")?;
+    let resp: Value = serde_json::from_str(&line).context("Failed to parse daemon response")?;
+    if resp["status"] != "ok" {
+        anyhow::bail!("{}", resp["msg"].as_str().unwrap_or("unknown error"));
+    }
+    Ok((resp["pid"].as_u64().unwrap_or_default(), lines))
+}
+
+/// Whether process `pid` is still running; a zombie waiting to be reaped
+/// has exited
+fn process_running(pid: u32) -> bool {
+    match Command::new("ps").args(["-o", "stat=", "-p", &pid.to_string()]).logged_output() {
+        Ok(output) if output.status.success() => {
+            !String::from_utf8_lossy(&output.stdout).trim_start().starts_with('Z')
+        }
+        _ => false,
+    }
+}
+
+/// Whether a streamed message is the daemon's shutdown notice. Only
+/// messages that name the event are decoded to check.
+fn is_shutdown_notice(toon: &str) -> bool {
+    toon.contains("daemon_shutdown") && pulse::decode_pulse(toon).is_ok_and(|pulse| pulse::is_shutdown(&pulse))
+}
+
+/// Print one TOON message as JSON. A message that doesn't decode goes to
+/// stderr as it came, so the stream keeps going.
+fn print_decoded(toon: &str, pretty: bool) {
+    match pulse::decode_pulse(toon) {
+        Ok(value) if pretty => println!("{:#}", value),
+        Ok(value) => println!("{}", value),
+        Err(e) => {
+            warn!("could not decode message, passing it to stderr: {:#}", e);
+            eprintln!("{}", toon);
+        }
+    }
+}
+
+/// One message as `tail --format pretty` prints it: local time, type and
+/// channel, then the data's fields as `key=value`, with nested objects one
+/// level deep flattened to `key.field=value`. ANSI colors with `color`.
+/// Control characters from the pulse are escaped, so a message can't move
+/// the cursor or recolor the terminal.
+fn pretty_line(pulse: &Value, color: bool) -> String {
+    let paint = |code: &str, text: &str| {
+        let text = escape_controls(text);
+        if color { format!("\x1b[{}m{}\x1b[0m", code, text) } else { text }
+    };
+    // Strings bare unless they'd be ambiguous, everything else as JSON
+    let show = |value: &Value| match value {
+        Value::String(s) if !s.is_empty() && !s.contains(|c: char| c.is_whitespace() || c.is_control()) => s.clone(),
+        // JSON escapes the C0 controls, but not the C1 ones
+        other => escape_controls(&other.to_string()),
+    };
+
+    let time = pulse["timestamp"]
+        .as_str()
+        .and_then(|at| chrono::DateTime::parse_from_rfc3339(at).ok())
+        .map_or_else(|| "--:--:--".to_string(), |at| at.with_timezone(&chrono::Local).format("%H:%M:%S").to_string());
+    let mut line = format!(
+        "{} {} {}",
+        paint("2", &time),
+        paint("1;33", pulse["type"].as_str().unwrap_or("?")),
+        paint("36", pulse["channel"].as_str().unwrap_or("?")),
+    );
+    let mut field = |key: &str, value: &Value| {
+        line.push_str(&format!(" {}{}", paint("2", &format!("{}=", key)), show(value)));
+    };
+    match &pulse["data"] {
+        Value::Object(data) => {
+            for (key, value) in data {
+                match value {
+                    Value::Object(inner) if !inner.is_empty() => {
+                        for (inner_key, value) in inner {
+                            field(&format!("{}.{}", key, inner_key), value);
+                        }
+                    }
+                    value => field(key, value),
+                }
+            }
+        }
+        Value::Null => {}
+        data => line.push_str(&format!(" {}", show(data))),
+    }
+    line
+}
 
+/// `text` with its control characters as Rust escapes (`\n`, `\u{1b}`)
+fn escape_controls(text: &str) -> String {
+    text.chars()
+        .map(|c| if c.is_control() { c.escape_default().to_string() } else { c.to_string() })
+        .collect()
+}
+
+/// Log a callback that failed; the subscription goes on either way
+fn report_callback(
+    outcome: &Result<Option<std::process::ExitStatus>>,
+    channel: &str,
+    timeout: Option<std::time::Duration>,
+) {
+    match outcome {
+        Ok(Some(status)) if status.success() => {}
+        Ok(Some(status)) => warn!("callback for a {} message {}", channel, status),
+        Ok(None) => warn!("callback for a {} message timed out after {:?}, killed", channel, timeout.unwrap_or_default()),
+        Err(e) => warn!("{:#}", e),
+    }
+}
+
+/// Whether a read from a subscriber means it went away. Subscribers don't
+/// send anything after the subscribe command, so what they do send is
+/// ignored; watching the read side is how a closed connection is noticed
+/// before a message has to be written to it.
+fn hung_up(line: std::io::Result<Option<String>>) -> bool {
+    !matches!(line, Ok(Some(_)))
+}
+
+/// A message as it goes down a subscribe stream: as-is, or as one JSON
+/// string for a subscriber that asked for `"framed": true`, since a TOON
+/// message spans several lines
+fn stream_line(msg: &str, framed: bool) -> std::borrow::Cow<'_, str> {
+    if framed {
+        Value::String(msg.to_string()).to_string().into()
+    } else {
+        msg.into()
+    }
+}
+
+/// The line telling a subscriber it fell behind: `missed` messages were
+/// dropped for it, and the next one it gets is number `resume_seq` of
+/// `channel`
+fn lag_line(channel: &str, missed: u64, resume_seq: u64) -> String {
+    format!("{}\n", Frame::Lagged { channel: channel.to_string(), missed, resume_seq })
+}
+
+/// An `ok` response carrying `body`'s fields
+fn ok_response(body: &impl Serialize) -> Result<Value> {
+    let mut resp = serde_json::to_value(body)?;
+    resp["status"] = json!("ok");
+    Ok(resp)
+}
+
+/// How a connection stops the daemon: a `shutdown` request carrying the
+/// token from .hydra/daemon.token wakes the accept loop
+#[derive(Clone)]
+struct Shutdown {
+    token: std::sync::Arc<str>,
+    requested: std::sync::Arc<tokio::sync::Notify>,
+}
+
+async fn handle_conn(
+    mut stream: UnixStream,
+    project_uuid: Uuid,
+    limits: Limits,
+    scheduler: SchedulerHandle,
+    started: std::time::Instant,
+    shutdown: Shutdown,
+) -> Result<()> {
     let (reader, mut writer) = stream.split();
     let mut reader = BufReader::new(reader).lines();
 
-    // Rate limiting: sliding window of emit timestamps
-    let mut emit_times: VecDeque<Instant> = VecDeque::new();
-    let rate_limit = limits.rate_limit_per_second;
+    // Rate limiting: a sliding window of emits per channel
+    let mut rate_limiter = RateLimiter::new(&limits);
 
     while let Some(line) = reader.next_line().await? {
         let cmd: Value = serde_json::from_str(&line).context("Failed to parse JSON command")?;
 
         match cmd["cmd"].as_str() {
             Some("emit") => {
-                // Check rate limit (if enabled)
-                if rate_limit > 0 {
-                    let now = Instant::now();
-                    // Remove timestamps older than 1 second
-                    while let Some(&oldest) = emit_times.front() {
-                        if now.duration_since(oldest).as_secs_f64() > 1.0 {
-                            emit_times.pop_front();
-                        } else {
-                            break;
-                        }
-                    }
-                    // Check if we're over the limit
-                    if emit_times.len() >= rate_limit {
+                let emit = EmitRequest::deserialize(&cmd).context("Invalid emit request")?;
+                let requested = emit.channel.as_str();
+                if channels::is_pattern(requested) {
+                    let err_resp = json!({
+                        "status": "error",
+                        "msg": format!("Cannot emit to a channel pattern: {}", requested)
+                    });
+                    writer.write_all(err_resp.to_string().as_bytes()).await?;
+                    writer.write_all(b"\n").await?;
+                    writer.flush().await?;
+                    continue;
+                }
+                let resolved = channels::resolve_channel(project_uuid, requested);
+                let channel = resolved.channel;
+
+                // Typos in a namespace start a channel nobody reads
+                let policy = channels::channel_policy(project_uuid);
+                let mut warning = None;
+                if policy.flags(&channel) {
+                    if policy.enforce == config::Enforcement::Reject {
                         let err_resp = json!({
                             "status": "error",
-                            "msg": format!("Rate limit exceeded: {} msgs/sec", rate_limit)
+                            "msg": format!(
+                                "Unknown namespace in channel '{}' (valid namespaces: {}); \
+                                 fix the name, or add it to [channel_policy] namespaces",
+                                channel, policy.namespaces.join(", ")
+                            ),
+                            "channel": channel
                         });
                         writer.write_all(err_resp.to_string().as_bytes()).await?;
                         writer.write_all(b"\n").await?;
                         writer.flush().await?;
                         continue;
                     }
-                    emit_times.push_back(now);
+                    warning = Some("unknown namespace".to_string());
                 }
 
-                let channel = cmd["channel"].as_str().context("Missing channel")?.to_string();
+                // Check the channel's rate limit (if it has one)
+                if let Err(limited) = rate_limiter.check(&channel, std::time::Instant::now()) {
+                    channels::record_rate_limited(project_uuid, &channel);
+                    let err_resp = json!({
+                        "status": "error",
+                        "msg": format!("Rate limit exceeded on {}: {}", channel, limited.limit),
+                        "channel": channel,
+                        "retry_after_ms": limited.retry_after.as_nanos().div_ceil(1_000_000) as u64
+                    });
+                    writer.write_all(err_resp.to_string().as_bytes()).await?;
+                    writer.write_all(b"\n").await?;
+                    writer.flush().await?;
+                    continue;
+                }
 
                 // Get the base64 encoded TOON data and store as-is (no decode needed!)
-                let encoded_data = cmd["data"].as_str().context("Missing data")?;
-                let decoded_bytes = general_purpose::STANDARD.decode(encoded_data)
+                let decoded_bytes = general_purpose::STANDARD.decode(&emit.data)
                     .context("Failed to decode base64 data")?;
 
                 // Check message size limit
@@ -854,40 +2589,356 @@ async fn handle_conn(mut stream: UnixStream, project_uuid: Uuid, limits: Limits)
                 let toon_str = String::from_utf8(decoded_bytes)
                     .context("Invalid UTF-8 in TOON data")?;
 
+                // Future delivery time: park the message until the timer fires it
+                if let Some(at) = emit.deliver_at.filter(|at| *at > chrono::Utc::now()) {
+                    let resp = match scheduler.schedule(project_uuid, &channel, toon_str, emit.target, at, &limits).await {
+                        Ok(token) => ok_response(&EmitAck {
+                            channel,
+                            alias: resolved.alias,
+                            size: 0,
+                            receivers: 0,
+                            scheduled: true,
+                            token: Some(token),
+                            deliver_at: Some(at.trunc_subsecs(0)),
+                            warning,
+                        })?,
+                        Err(e) => json!({"status": "error", "msg": e.to_string()}),
+                    };
+                    writer.write_all(resp.to_string().as_bytes()).await?;
+                    writer.write_all(b"\n").await?;
+                    writer.flush().await?;
+                    continue;
+                }
+
                 // Emit and store in replay buffer atomically (daemon just passes through TOON)
                 let toon_size = toon_str.len();
-                let receiver_count = channels::emit_and_store(project_uuid, &channel, toon_str).await;
-                let ok_resp = json!({"status": "ok", "format": "toon", "size": toon_size, "receivers": receiver_count});
+                let receiver_count = channels::emit_to(project_uuid, &channel, toon_str, emit.target).await;
+                let mut ok_resp = ok_response(&EmitAck {
+                    channel,
+                    alias: resolved.alias,
+                    size: toon_size,
+                    receivers: receiver_count,
+                    scheduled: false,
+                    token: None,
+                    deliver_at: None,
+                    warning,
+                })?;
+                ok_resp["format"] = json!("toon");
                 writer.write_all(ok_resp.to_string().as_bytes()).await?;
                 writer.write_all(b"\n").await?;
                 writer.flush().await?;
             }
             Some("subscribe") => {
-                let channel = cmd["channel"].as_str().context("Missing channel")?.to_string();
+                let subscribe = SubscribeRequest::deserialize(&cmd).context("Invalid subscribe request")?;
+                let requested = subscribe.channel.as_str();
+                let opts = &subscribe.opts;
+
+                // Optional history controls: "history": false, "last": N, "since": RFC 3339
+                let send_history = opts.history;
+                let filter = if send_history {
+                    channels::HistoryFilter { last: opts.last, since: opts.since }
+                } else {
+                    channels::HistoryFilter::none()
+                };
+
+                // A subscriber naming its agent ID gets no messages targeted
+                // at other agents
+                let agent_id = opts.agent_id.as_deref().filter(|id| !id.is_empty());
+                let framed = subscribe.framed;
+                // A framed subscriber can ask for a line marking where the
+                // history ends and live messages begin
+                let mark_history = framed && subscribe.mark_history;
+
+                // Named subscribers are listed by `agents` until they hang up
+                let _presence = match agent_id {
+                    Some(agent_id) => {
+                        let identity = presence::Identity {
+                            agent_id: agent_id.to_string(),
+                            role: opts.role.clone().filter(|r| !r.is_empty()),
+                            description: opts.description.clone().filter(|d| !d.is_empty()),
+                        };
+                        Some(presence::join(project_uuid, identity, requested).await)
+                    }
+                    None => None,
+                };
+
+                // A pattern (`repo:*`, `*`) gets every matching channel, each
+                // message headed by a `==> channel <==` line
+                if channels::is_pattern(requested) {
+                    let (mut rx, history) = match agent_id {
+                        Some(agent_id) => channels::subscribe_pattern_as(project_uuid, requested, agent_id, &filter).await,
+                        None => channels::subscribe_pattern(project_uuid, requested, &filter).await,
+                    };
+                    for (channel, msg) in history {
+                        writer.write_all(format!("==> {} <==\n{}\n", channel, stream_line(&msg, framed)).as_bytes()).await?;
+                    }
+                    if mark_history {
+                        writer.write_all(format!("{}\n", Frame::HistoryEnd).as_bytes()).await?;
+                    }
+                    writer.flush().await?;
+                    let mut missed = 0;
+                    loop {
+                        tokio::select! {
+                            next = rx.recv_sequenced() => {
+                                let (seq, (channel, msg)) = match next {
+                                    Ok(next) => next,
+                                    Err(RecvError::Lagged(n)) => { missed += n; continue }
+                                    Err(RecvError::Closed) => break,
+                                };
+                                if missed > 0 {
+                                    writer.write_all(lag_line(&channel, missed, seq).as_bytes()).await?;
+                                    missed = 0;
+                                }
+                                writer.write_all(format!("==> {} <==\n{}\n", channel, stream_line(&msg, framed)).as_bytes()).await?;
+                                writer.flush().await?;
+                            }
+                            line = reader.next_line() => if hung_up(line) { break },
+                        }
+                    }
+                    continue;
+                }
 
-                let (mut rx, history) = channels::subscribe_broadcast(project_uuid, &channel).await;
+                let channel = channels::resolve_channel(project_uuid, requested).channel;
+                if let Some(agent_id) = agent_id {
+                    let (mut rx, history) = channels::subscribe_as(project_uuid, &channel, agent_id, &filter).await;
+                    for msg in history {
+                        writer.write_all(stream_line(&msg, framed).as_bytes()).await?;
+                        writer.write_all(b"\n").await?;
+                    }
+                    if mark_history {
+                        writer.write_all(format!("{}\n", Frame::HistoryEnd).as_bytes()).await?;
+                    }
+                    writer.flush().await?;
+                    let mut missed = 0;
+                    loop {
+                        tokio::select! {
+                            next = rx.recv_sequenced() => {
+                                let (seq, msg) = match next {
+                                    Ok(next) => next,
+                                    Err(RecvError::Lagged(n)) => { missed += n; continue }
+                                    Err(RecvError::Closed) => break,
+                                };
+                                if missed > 0 {
+                                    writer.write_all(lag_line(&channel, missed, seq).as_bytes()).await?;
+                                    missed = 0;
+                                }
+                                writer.write_all(stream_line(&msg, framed).as_bytes()).await?;
+                                writer.write_all(b"\n").await?;
+                                writer.flush().await?;
+                            }
+                            line = reader.next_line() => if hung_up(line) { break },
+                        }
+                    }
+                    continue;
+                }
+                let (mut rx, history) = channels::subscribe_sequenced(project_uuid, &channel, &filter).await;
 
-                // Send history first (messages already in TOON format)
-                for msg in history {
-                    writer.write_all(msg.as_bytes()).await?;
-                    writer.write_all(b"\n").await?;
+                if send_history {
+                    // Send history first (messages already in TOON format)
+                    for msg in history {
+                        writer.write_all(stream_line(&msg, framed).as_bytes()).await?;
+                        writer.write_all(b"\n").await?;
+                    }
+                }
+                if mark_history {
+                    writer.write_all(format!("{}\n", Frame::HistoryEnd).as_bytes()).await?;
                 }
                 // Flush history so clients receive it immediately
                 writer.flush().await?;
 
-                // Then stream live messages until connection closes or error
-                while let Ok(msg) = rx.recv().await {
-                    writer.write_all(msg.as_bytes()).await?;
+                // Then stream live messages until connection closes. A
+                // subscriber too slow to keep up loses the oldest messages;
+                // it's told how many before the next one it gets.
+                let mut missed = 0;
+                loop {
+                    tokio::select! {
+                        next = rx.recv() => {
+                            let (seq, msg) = match next {
+                                Ok(next) => next,
+                                Err(RecvError::Lagged(n)) => { missed += n; continue }
+                                Err(RecvError::Closed) => break,
+                            };
+                            if missed > 0 {
+                                writer.write_all(lag_line(&channel, missed, seq).as_bytes()).await?;
+                                missed = 0;
+                            }
+                            writer.write_all(stream_line(&msg, framed).as_bytes()).await?;
+                            writer.write_all(b"\n").await?;
+                            writer.flush().await?;
+                        }
+                        line = reader.next_line() => if hung_up(line) { break },
+                    }
+                }
+            }
+            Some("history") => {
+                // The replay buffer as a subscriber would get it, then hang up
+                let history = HistoryRequest::deserialize(&cmd).context("Invalid history request")?;
+                let agent_id = history.agent_id.as_deref().filter(|id| !id.is_empty());
+                let filter = channels::HistoryFilter { last: history.last, since: history.since };
+                let (messages, expired) = channels::history(project_uuid, &history.channel, agent_id, &filter).await;
+                let resp = ok_response(&HistoryResponse {
+                    messages: messages
+                        .into_iter()
+                        .map(|(channel, message)| HistoryMessage { channel, message })
+                        .collect(),
+                    expired,
+                })?;
+                writer.write_all(resp.to_string().as_bytes()).await?;
+                writer.write_all(b"\n").await?;
+                writer.flush().await?;
+                break;
+            }
+            Some("purge") => {
+                // One channel, or every channel of the project with "all": true
+                let targets = if cmd["all"].as_bool().unwrap_or(false) {
+                    Ok(channels::list_channels(project_uuid).await)
+                } else {
+                    let requested = cmd["channel"].as_str().context("Missing channel")?;
+                    if channels::is_pattern(requested) {
+                        Err(format!("Can't purge a pattern ({}); name one channel or use --all", requested))
+                    } else {
+                        Ok(vec![channels::resolve_channel(project_uuid, requested).channel])
+                    }
+                };
+                let resp = match targets {
+                    Ok(targets) => {
+                        let mut purged = Vec::new();
+                        for channel in targets {
+                            let messages = channels::purge(project_uuid, &channel).await?;
+                            // Unknown and empty channels have no gap to explain
+                            if messages == 0 {
+                                continue;
+                            }
+                            // Tell subscribers the gap in the history is on purpose
+                            let notice = pulse::new_pulse(
+                                "purge",
+                                &channel,
+                                json!({"action": "purged", "messages": messages}),
+                                json!({"admin": true}),
+                            );
+                            channels::emit_and_store(project_uuid, &channel, pulse::encode_pulse(&notice)?).await;
+                            purged.push(json!({"channel": channel, "messages": messages}));
+                        }
+                        json!({"status": "ok", "purged": purged})
+                    }
+                    Err(msg) => json!({"status": "error", "msg": msg}),
+                };
+                writer.write_all(resp.to_string().as_bytes()).await?;
+                writer.write_all(b"\n").await?;
+                writer.flush().await?;
+            }
+            Some("cancel_scheduled") => {
+                let resp = match cmd["token"].as_str().map(Uuid::parse_str) {
+                    Some(Ok(token)) => match scheduler.cancel(project_uuid, token).await {
+                        CancelResult::Cancelled => json!({"status": "ok", "result": "cancelled"}),
+                        CancelResult::AlreadyDelivered => {
+                            json!({"status": "error", "msg": "Message was already delivered"})
+                        }
+                        CancelResult::NotFound => {
+                            json!({"status": "error", "msg": "No scheduled message with that token"})
+                        }
+                    },
+                    Some(Err(_)) => json!({"status": "error", "msg": "Invalid token"}),
+                    None => json!({"status": "error", "msg": "Missing token"}),
+                };
+                writer.write_all(resp.to_string().as_bytes()).await?;
+                writer.write_all(b"\n").await?;
+                writer.flush().await?;
+            }
+            Some("list_scheduled") => {
+                let scheduled: Vec<Value> = scheduler.list(project_uuid).await
+                    .into_iter()
+                    .map(|m| json!({
+                        "token": m.token,
+                        "channel": m.channel,
+                        "deliver_at": m.deliver_at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+                        "submitted_at": m.submitted_at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+                        "size": m.message.len()
+                    }))
+                    .collect();
+                let resp = json!({"status": "ok", "scheduled": scheduled});
+                writer.write_all(resp.to_string().as_bytes()).await?;
+                writer.write_all(b"\n").await?;
+                writer.flush().await?;
+            }
+            Some("list") => {
+                let aliases: Vec<Value> = channels::list_aliases(project_uuid)
+                    .into_iter()
+                    .map(|a| json!({
+                        "alias": a.alias,
+                        "channel": a.channel,
+                        "uses": a.uses,
+                        "resolves": format!("{} → {}", a.alias, a.channel)
+                    }))
+                    .collect();
+                let resp = json!({
+                    "status": "ok",
+                    "channels": channels::list_channels(project_uuid).await,
+                    "aliases": aliases
+                });
+                writer.write_all(resp.to_string().as_bytes()).await?;
+                writer.write_all(b"\n").await?;
+                writer.flush().await?;
+            }
+            Some("ping") => {
+                let resp = json!({
+                    "status": "ok",
+                    "uptime_secs": started.elapsed().as_secs(),
+                    "version": env!("CARGO_PKG_VERSION")
+                });
+                writer.write_all(resp.to_string().as_bytes()).await?;
+                writer.write_all(b"\n").await?;
+                writer.flush().await?;
+            }
+            Some("shutdown") => {
+                if cmd["token"].as_str() != Some(&*shutdown.token) {
+                    let err_resp = json!({"status": "error", "msg": "Invalid or missing shutdown token"});
+                    writer.write_all(err_resp.to_string().as_bytes()).await?;
                     writer.write_all(b"\n").await?;
                     writer.flush().await?;
+                    continue;
                 }
+                let resp = json!({"status": "ok", "pid": std::process::id()});
+                writer.write_all(resp.to_string().as_bytes()).await?;
+                writer.write_all(b"\n").await?;
+                writer.flush().await?;
+                shutdown.requested.notify_one();
+                // Hold the connection until the daemon exits: its close tells
+                // the client the shutdown is done
+                std::future::pending::<()>().await;
+            }
+            Some("agents") => {
+                let resp = json!({"status": "ok", "agents": presence::agents(project_uuid)});
+                writer.write_all(resp.to_string().as_bytes()).await?;
+                writer.write_all(b"\n").await?;
+                writer.flush().await?;
+            }
+            Some("list_channels") => {
+                let resp = json!({
+                    "status": "ok",
+                    "topics": channels::list_channels(project_uuid).await,
+                    "channels": channels::get_channel_stats(project_uuid).await
+                });
+                writer.write_all(resp.to_string().as_bytes()).await?;
+                writer.write_all(b"\n").await?;
+                writer.flush().await?;
             }
             Some("stats") => {
                 let stats = channels::get_channel_stats(project_uuid).await;
-                let resp = json!({
+                #[allow(unused_mut)]
+                let mut resp = json!({
                     "status": "ok",
-                    "channels": stats
+                    "channels": stats,
+                    "aliases": channels::list_aliases(project_uuid)
                 });
+                let hooks = channels::hook_stats(project_uuid);
+                if !hooks.is_empty() {
+                    resp["hooks"] = json!(hooks);
+                }
+                #[cfg(feature = "sqlite")]
+                if let Some(archive) = channels::archive_stats() {
+                    resp["archive"] = json!(archive);
+                }
                 writer.write_all(resp.to_string().as_bytes()).await?;
                 writer.write_all(b"\n").await?;
                 writer.flush().await?;
@@ -1,7 +1,8 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use std::fs::{File, OpenOptions};
-use std::io::{BufRead, BufReader, Write};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
 use uuid::Uuid;
 
@@ -12,6 +13,30 @@ pub struct LogEntry {
     pub channel: String,
     pub message: String,
     pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// The agent the message was addressed to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+}
+
+/// A compacted or purged copy of a log, made by
+/// [`MessageLog::prepare_compaction`] or [`MessageLog::prepare_purge`] while
+/// the log stays open for appends, and put in its place by
+/// [`MessageLog::finish_rewrite`]
+pub struct Rewrite {
+    temp_path: PathBuf,
+    /// Inode of the file the copy was made from; a log rewritten since
+    /// can't take it any more
+    inode: u64,
+    /// Bytes of that file the copy covers; what was appended past them is
+    /// carried over when the copy replaces it
+    covered: u64,
+}
+
+impl Drop for Rewrite {
+    /// A rewrite that didn't replace the log leaves nothing behind
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.temp_path);
+    }
 }
 
 /// Append-only message log for crash recovery
@@ -35,13 +60,32 @@ impl MessageLog {
         })
     }
 
+    /// Where the log lives
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
     /// Append a message to the log
     pub fn append(&mut self, project_uuid: Uuid, channel: &str, message: &str) -> Result<()> {
+        self.append_to(project_uuid, channel, message, None, chrono::Utc::now())
+    }
+
+    /// Append a message addressed to agent `target`, emitted at `timestamp`,
+    /// to the log
+    pub fn append_to(
+        &mut self,
+        project_uuid: Uuid,
+        channel: &str,
+        message: &str,
+        target: Option<&str>,
+        timestamp: chrono::DateTime<chrono::Utc>,
+    ) -> Result<()> {
         let entry = LogEntry {
             project_uuid,
             channel: channel.to_string(),
             message: message.to_string(),
-            timestamp: chrono::Utc::now(),
+            timestamp,
+            target: target.map(str::to_string),
         };
 
         let json = serde_json::to_string(&entry).context("Failed to serialize log entry")?;
@@ -71,45 +115,124 @@ impl MessageLog {
     }
 
     /// Compact log to keep only last N messages per channel
-    pub fn compact(&self, keep_per_channel: usize) -> Result<()> {
-        use std::collections::HashMap;
+    pub fn compact(&mut self, keep_per_channel: usize) -> Result<()> {
+        let rewrite = Self::prepare_compaction(&self.path, keep_per_channel)?;
+        self.finish_rewrite(rewrite)?;
+        Ok(())
+    }
 
-        let entries = self.replay()?;
+    /// Write a compacted copy of the log at `log_path`, as [`Self::compact`]
+    /// would leave it, without touching the log. Takes its time, so call it
+    /// without holding the log; only [`Self::finish_rewrite`] needs that.
+    pub fn prepare_compaction(log_path: &Path, keep_per_channel: usize) -> Result<Rewrite> {
+        let (entries, rewrite) = Self::read_for_rewrite(log_path)?;
+        write_entries(&rewrite.temp_path, &keep_last(entries, keep_per_channel))?;
+        Ok(rewrite)
+    }
 
-        // Group by (project_uuid, channel)
-        let mut by_channel: HashMap<(Uuid, String), Vec<LogEntry>> = HashMap::new();
-        for entry in entries {
-            let key = (entry.project_uuid, entry.channel.clone());
-            by_channel.entry(key).or_default().push(entry);
+    /// Write a copy of the log at `log_path` without the entries of one
+    /// channel logged up to `until`, like [`Self::prepare_compaction`].
+    /// `None` if there are none.
+    pub fn prepare_purge(
+        log_path: &Path,
+        project_uuid: Uuid,
+        channel: &str,
+        until: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Option<Rewrite>> {
+        let (entries, rewrite) = Self::read_for_rewrite(log_path)?;
+        let total = entries.len();
+        let kept: Vec<LogEntry> = entries
+            .into_iter()
+            .filter(|e| e.project_uuid != project_uuid || e.channel != channel || e.timestamp > until)
+            .collect();
+        if kept.len() == total {
+            return Ok(None);
         }
+        write_entries(&rewrite.temp_path, &kept)?;
+        Ok(Some(rewrite))
+    }
+
+    /// The complete entries of the log at `log_path`, and a [`Rewrite`] of
+    /// them still to be written
+    fn read_for_rewrite(log_path: &Path) -> Result<(Vec<LogEntry>, Rewrite)> {
+        let mut file = File::open(log_path).context("Failed to open log for rewriting")?;
+        let inode = file.metadata().context("Failed to read log metadata")?.ino();
+        let mut content = Vec::new();
+        file.read_to_end(&mut content).context("Failed to read log for rewriting")?;
+        // An append may be halfway through its line; it's carried over later
+        let covered = content.iter().rposition(|b| *b == b'\n').map_or(0, |end| end + 1);
 
-        // Keep only last N per channel
-        let mut kept_entries = Vec::new();
-        for (_key, mut entries) in by_channel {
-            entries.sort_by_key(|e| e.timestamp);
-            let start = entries.len().saturating_sub(keep_per_channel);
-            kept_entries.extend(entries.into_iter().skip(start));
+        let mut entries = Vec::new();
+        for line in content[..covered].split(|b| *b == b'\n') {
+            let line = String::from_utf8_lossy(line);
+            if line.trim().is_empty() {
+                continue;
+            }
+            entries.push(serde_json::from_str::<LogEntry>(&line).context("Failed to parse log entry")?);
         }
 
-        // Sort by timestamp for replay order
-        kept_entries.sort_by_key(|e| e.timestamp);
+        let temp_path = log_path.with_extension(format!("{}.tmp", Uuid::new_v4().simple()));
+        Ok((entries, Rewrite { temp_path, inode, covered: covered as u64 }))
+    }
 
-        // Write compacted log
-        let temp_path = self.path.with_extension("tmp");
-        let mut temp_file = File::create(&temp_path)
-            .context("Failed to create temp log file")?;
+    /// Replace the log with `rewrite`, carrying over what was appended since
+    /// it was prepared, and keep appending to the new file. Returns `false`,
+    /// dropping the rewrite, if the log was replaced in the meantime.
+    pub fn finish_rewrite(&mut self, rewrite: Rewrite) -> Result<bool> {
+        (|| -> Result<bool> {
+            let mut live = File::open(&self.path).context("Failed to open log")?;
+            if live.metadata().context("Failed to read log metadata")?.ino() != rewrite.inode {
+                return Ok(false);
+            }
+            live.seek(SeekFrom::Start(rewrite.covered)).context("Failed to read log")?;
+            let mut temp_file = OpenOptions::new()
+                .append(true)
+                .open(&rewrite.temp_path)
+                .context("Failed to open rewritten log")?;
+            std::io::copy(&mut live, &mut temp_file).context("Failed to carry over new log entries")?;
+            temp_file.flush()?;
+            std::fs::rename(&rewrite.temp_path, &self.path)
+                .context("Failed to replace log with rewritten version")?;
+            // Appends would keep going to the replaced inode otherwise
+            *self = Self::open(&self.path)?;
+            Ok(true)
+        })()
+    }
+}
 
-        for entry in kept_entries {
-            let json = serde_json::to_string(&entry)?;
-            writeln!(temp_file, "{}", json)?;
-        }
-        temp_file.flush()?;
-        drop(temp_file);
+/// The last `keep_per_channel` of `entries` per channel, in time order
+fn keep_last(entries: Vec<LogEntry>, keep_per_channel: usize) -> Vec<LogEntry> {
+    use std::collections::HashMap;
 
-        // Atomic rename
-        std::fs::rename(&temp_path, &self.path)
-            .context("Failed to replace log with compacted version")?;
+    // Group by (project_uuid, channel)
+    let mut by_channel: HashMap<(Uuid, String), Vec<LogEntry>> = HashMap::new();
+    for entry in entries {
+        let key = (entry.project_uuid, entry.channel.clone());
+        by_channel.entry(key).or_default().push(entry);
+    }
 
-        Ok(())
+    // Keep only last N per channel
+    let mut kept_entries = Vec::new();
+    for (_key, mut entries) in by_channel {
+        entries.sort_by_key(|e| e.timestamp);
+        let start = entries.len().saturating_sub(keep_per_channel);
+        kept_entries.extend(entries.into_iter().skip(start));
+    }
+
+    // Sort by timestamp for replay order
+    kept_entries.sort_by_key(|e| e.timestamp);
+    kept_entries
+}
+
+/// Write `entries` to a new file at `path`, one JSON line each
+fn write_entries(path: &Path, entries: &[LogEntry]) -> Result<()> {
+    let mut file = File::create(path)
+        .context("Failed to create temp log file")?;
+
+    for entry in entries {
+        let json = serde_json::to_string(entry)?;
+        writeln!(file, "{}", json)?;
     }
+    file.flush()?;
+    Ok(())
 }
@@ -0,0 +1,181 @@
+//! Who is subscribed right now
+//!
+//! A subscriber that names an agent ID is registered for as long as its
+//! connection stays open, one entry per connection. Joins and leaves are
+//! announced as `presence` pulses on `agent:presence`, and the `agents`
+//! request reads the table, one row per agent.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{channels, pulse};
+
+/// Where joins and leaves are announced
+pub const PRESENCE_CHANNEL: &str = "agent:presence";
+
+/// What a subscriber says about itself
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Identity {
+    pub agent_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+struct Connection {
+    project_uuid: Uuid,
+    identity: Identity,
+    channel: String,
+    connected_at: DateTime<Utc>,
+}
+
+static CONNECTIONS: LazyLock<Mutex<HashMap<u64, Connection>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(0);
+
+/// A connected agent: its connections merged
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Agent {
+    #[serde(flatten)]
+    pub identity: Identity,
+    /// Channels (or patterns) it is subscribed to, sorted
+    pub channels: Vec<String>,
+    /// When its first open connection subscribed
+    pub connected_at: DateTime<Utc>,
+    pub connections: usize,
+}
+
+/// One registered subscriber connection. Dropping it, when the connection
+/// ends, takes it out of the table and announces the leave.
+pub struct Registration {
+    id: u64,
+}
+
+/// Register a connection of `identity` subscribed to `channel` and
+/// announce the join
+pub async fn join(project_uuid: Uuid, identity: Identity, channel: &str) -> Registration {
+    let id = NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed);
+    announce(project_uuid, &identity, channel, "joined").await;
+    CONNECTIONS.lock().unwrap().insert(
+        id,
+        Connection { project_uuid, identity, channel: channel.to_string(), connected_at: Utc::now() },
+    );
+    Registration { id }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let Some(connection) = CONNECTIONS.lock().unwrap().remove(&self.id) else {
+            return;
+        };
+        // Drop can't wait, so the leave goes out from a task of its own
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move {
+                announce(connection.project_uuid, &connection.identity, &connection.channel, "left").await;
+            });
+        }
+    }
+}
+
+/// The agents connected to a project, by agent ID
+pub fn agents(project_uuid: Uuid) -> Vec<Agent> {
+    let connections = CONNECTIONS.lock().unwrap();
+    let mut agents: BTreeMap<&str, Agent> = BTreeMap::new();
+    for connection in connections.values().filter(|c| c.project_uuid == project_uuid) {
+        let agent = agents.entry(&connection.identity.agent_id).or_insert_with(|| Agent {
+            identity: connection.identity.clone(),
+            channels: Vec::new(),
+            connected_at: connection.connected_at,
+            connections: 0,
+        });
+        agent.channels.push(connection.channel.clone());
+        agent.connected_at = agent.connected_at.min(connection.connected_at);
+        agent.connections += 1;
+    }
+    let mut agents: Vec<Agent> = agents.into_values().collect();
+    for agent in &mut agents {
+        agent.channels.sort();
+        agent.channels.dedup();
+    }
+    agents
+}
+
+/// The data of a presence pulse
+fn presence_data(identity: &Identity, channel: &str, status: &str) -> Value {
+    let mut data = json!({"agent": identity.agent_id, "status": status, "channel": channel});
+    if let Some(role) = &identity.role {
+        data["role"] = json!(role);
+    }
+    if let Some(description) = &identity.description {
+        data["description"] = json!(description);
+    }
+    data
+}
+
+async fn announce(project_uuid: Uuid, identity: &Identity, channel: &str, status: &str) {
+    let pulse = pulse::new_pulse("presence", PRESENCE_CHANNEL, presence_data(identity, channel, status), Value::Null);
+    match pulse::encode_pulse(&pulse) {
+        Ok(toon) => {
+            channels::emit_and_store(project_uuid, PRESENCE_CHANNEL, toon).await;
+        }
+        Err(e) => warn!("failed to encode presence pulse: {:#}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(agent_id: &str, role: Option<&str>) -> Identity {
+        Identity { agent_id: agent_id.to_string(), role: role.map(str::to_string), description: None }
+    }
+
+    #[tokio::test]
+    async fn test_join_and_leave() {
+        let uuid = Uuid::new_v4();
+        let (mut rx, _) = channels::subscribe_broadcast(uuid, PRESENCE_CHANNEL).await;
+
+        let first = join(uuid, identity("builder-1", Some("builder")), "repo:delta").await;
+        let second = join(uuid, identity("builder-1", Some("builder")), "team:*").await;
+        let other = join(uuid, identity("reviewer-1", None), "repo:delta").await;
+        assert!(agents(Uuid::new_v4()).is_empty());
+
+        let listed = agents(uuid);
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[0].identity, identity("builder-1", Some("builder")));
+        assert_eq!(listed[0].channels, vec!["repo:delta", "team:*"]);
+        assert_eq!(listed[0].connections, 2);
+        assert_eq!(listed[1].identity.agent_id, "reviewer-1");
+
+        let joined = pulse::decode_pulse(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(joined["type"], "presence");
+        assert_eq!(joined["data"], json!({"agent": "builder-1", "status": "joined", "channel": "repo:delta", "role": "builder"}));
+
+        drop(first);
+        drop(other);
+        let listed = agents(uuid);
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].channels, vec!["team:*"]);
+
+        // Two more joins, then the leaves
+        rx.recv().await.unwrap();
+        rx.recv().await.unwrap();
+        let mut left = Vec::new();
+        for _ in 0..2 {
+            let pulse = pulse::decode_pulse(&rx.recv().await.unwrap()).unwrap();
+            assert_eq!(pulse["data"]["status"], "left");
+            left.push(pulse["data"]["agent"].as_str().unwrap().to_string());
+        }
+        left.sort();
+        assert_eq!(left, vec!["builder-1", "reviewer-1"]);
+
+        drop(second);
+        assert!(agents(uuid).is_empty());
+    }
+}
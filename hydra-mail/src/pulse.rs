@@ -0,0 +1,615 @@
+//! Pulse data layer.
+//!
+//! Pure functions over the pulse schema: building, TOON encode/decode,
+//! well-known channel parsing, filters and envelope checks. Nothing here touches sockets, tokio or the
+//! filesystem, so it also builds for wasm32 (see the `wasm` feature).
+
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use serde_json::{json, Value};
+use toon_format::types::{KeyFoldingMode, PathExpansionMode};
+use toon_format::{decode, encode, DecodeOptions, EncodeOptions};
+use uuid::Uuid;
+
+/// Build a pulse as sent by `hydra-mail emit`.
+///
+/// Fields: `id`, `timestamp`, `type`, `channel`, `data`, `metadata` (null when
+/// there is nothing to attach).
+pub fn new_pulse(r#type: &str, channel: &str, data: Value, metadata: Value) -> Value {
+    json!({
+        "id": Uuid::new_v4(),
+        "timestamp": chrono::Utc::now(),
+        "type": r#type,
+        "channel": channel,
+        "data": data,
+        "metadata": metadata
+    })
+}
+
+/// Encode a pulse to TOON with key folding, as stored by the daemon
+pub fn encode_pulse(pulse: &Value) -> Result<String> {
+    let opts = EncodeOptions::new().with_key_folding(KeyFoldingMode::Safe);
+    encode(pulse, &opts).context("Failed to encode to TOON")
+}
+
+/// Decode a stored TOON pulse back into JSON
+pub fn decode_pulse(toon: &str) -> Result<Value> {
+    let opts = DecodeOptions::new().with_expand_paths(PathExpansionMode::Safe);
+    decode(toon, &opts).context("Failed to decode TOON pulse")
+}
+
+/// The `system` pulse the daemon sends the subscribers of `channel` as it
+/// shuts down
+pub fn shutdown_pulse(channel: &str) -> Value {
+    new_pulse("system", channel, json!({"event": "daemon_shutdown"}), Value::Null)
+}
+
+/// Whether `pulse` is the daemon's shutdown notice
+pub fn is_shutdown(pulse: &Value) -> bool {
+    pulse["type"] == "system" && pulse["data"]["event"] == "daemon_shutdown"
+}
+
+/// Channels with a fixed meaning across hydra tools.
+///
+/// `session:*` and `health:*` are published by the orchestrator, one channel
+/// per event (`session:spawned`, `health:killed`, ...).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum WellKnownChannel {
+    RepoDelta,
+    TeamAlert,
+    TeamStatus,
+    TeamQuestion,
+    AgentPresence,
+    Session { event: String },
+    Health { event: String },
+}
+
+impl WellKnownChannel {
+    /// `None` for custom channels
+    pub fn parse(channel: &str) -> Option<Self> {
+        let parsed = match channel {
+            "repo:delta" => Self::RepoDelta,
+            "team:alert" => Self::TeamAlert,
+            "team:status" => Self::TeamStatus,
+            "team:question" => Self::TeamQuestion,
+            "agent:presence" => Self::AgentPresence,
+            _ => {
+                let (prefix, event) = channel.split_once(':')?;
+                if event.is_empty() {
+                    return None;
+                }
+                match prefix {
+                    "session" => Self::Session { event: event.to_string() },
+                    "health" => Self::Health { event: event.to_string() },
+                    _ => return None,
+                }
+            }
+        };
+        Some(parsed)
+    }
+
+    pub fn channel(&self) -> String {
+        match self {
+            Self::RepoDelta => "repo:delta".to_string(),
+            Self::TeamAlert => "team:alert".to_string(),
+            Self::TeamStatus => "team:status".to_string(),
+            Self::TeamQuestion => "team:question".to_string(),
+            Self::AgentPresence => "agent:presence".to_string(),
+            Self::Session { event } => format!("session:{}", event),
+            Self::Health { event } => format!("health:{}", event),
+        }
+    }
+}
+
+/// Question text, asker and correlation id of a `team:question` pulse.
+///
+/// Data fields: `question` (or `summary`, as the reviewer skill sends it),
+/// optional `question_id`, `from`, `session` and `reply_channel`. The trace
+/// id comes from pulse metadata.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Question {
+    /// Correlation id answers refer to: `data.question_id`, else the pulse id
+    pub id: String,
+    pub question: String,
+    pub from: Option<String>,
+    /// Orchestrator session that asked (`HYDRALPH_SESSION_ID`)
+    pub session: Option<String>,
+    pub trace_id: Option<String>,
+    /// Where the asker listens for the answer
+    pub reply_channel: Option<String>,
+}
+
+impl Question {
+    /// `None` unless `pulse` is a question on `team:question`
+    pub fn from_pulse(pulse: &Value) -> Option<Self> {
+        if pulse["channel"].as_str() != Some("team:question") {
+            return None;
+        }
+        let data = &pulse["data"];
+        let text = |key: &str| data[key].as_str().filter(|s| !s.is_empty()).map(str::to_string);
+        Some(Self {
+            id: text("question_id").or_else(|| pulse["id"].as_str().map(str::to_string))?,
+            question: text("question").or_else(|| text("summary"))?,
+            from: text("from"),
+            session: text("session"),
+            trace_id: pulse["metadata"]["trace_id"].as_str().map(str::to_string),
+            reply_channel: text("reply_channel"),
+        })
+    }
+
+    pub fn to_pulse(&self) -> Value {
+        let mut data = json!({ "question_id": self.id, "question": self.question });
+        for (key, value) in [("from", &self.from), ("session", &self.session), ("reply_channel", &self.reply_channel)] {
+            if let Some(value) = value {
+                data[key] = json!(value);
+            }
+        }
+        new_pulse("question", "team:question", data, trace_metadata(self.trace_id.as_deref()))
+    }
+}
+
+/// Reply to a [`Question`], sent on the question's reply channel with type
+/// `answer`; `data.question_id` carries the correlation id
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Answer {
+    pub question_id: String,
+    pub answer: String,
+    pub from: Option<String>,
+}
+
+impl Answer {
+    /// `None` unless `pulse` is an answer, on any channel
+    pub fn from_pulse(pulse: &Value) -> Option<Self> {
+        if pulse["type"].as_str() != Some("answer") {
+            return None;
+        }
+        let data = &pulse["data"];
+        Some(Self {
+            question_id: data["question_id"].as_str()?.to_string(),
+            answer: data["answer"].as_str()?.to_string(),
+            from: data["from"].as_str().map(str::to_string),
+        })
+    }
+
+    /// The answer pulse on `channel`, in the asker's trace
+    pub fn to_pulse(&self, channel: &str, trace_id: Option<&str>) -> Value {
+        let mut data = json!({ "question_id": self.question_id, "answer": self.answer });
+        if let Some(from) = &self.from {
+            data["from"] = json!(from);
+        }
+        new_pulse("answer", channel, data, trace_metadata(trace_id))
+    }
+}
+
+/// Request/reply correlation in pulse metadata: a request carries
+/// `correlation_id` and the `reply_to` channel it listens on, and its
+/// reply carries the same `correlation_id`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Correlation {
+    pub id: String,
+    /// Only set on the request
+    pub reply_to: Option<String>,
+}
+
+impl Correlation {
+    /// A fresh correlation id, replied to on `reply_to` or else on the
+    /// id's own [`Correlation::reply_channel`]
+    pub fn request(reply_to: Option<&str>) -> Self {
+        let id = Uuid::new_v4().to_string();
+        let reply_to = reply_to.map_or_else(|| Self::reply_channel(&id), str::to_string);
+        Self { id, reply_to: Some(reply_to) }
+    }
+
+    /// What a reply to the request `id` carries
+    pub fn reply(id: &str) -> Self {
+        Self { id: id.to_string(), reply_to: None }
+    }
+
+    /// The channel `hydra-mail request` listens on for the reply to `id`
+    pub fn reply_channel(id: &str) -> String {
+        format!("reply:{}", id)
+    }
+
+    /// `None` unless `pulse` has a correlation id
+    pub fn from_pulse(pulse: &Value) -> Option<Self> {
+        let metadata = &pulse["metadata"];
+        Some(Self {
+            id: metadata["correlation_id"].as_str().filter(|id| !id.is_empty())?.to_string(),
+            reply_to: metadata["reply_to"].as_str().map(str::to_string),
+        })
+    }
+
+    /// Add `correlation_id` (and `reply_to`) to pulse metadata, creating it
+    /// if null
+    pub fn attach(&self, metadata: &mut Value) {
+        if !metadata.is_object() {
+            *metadata = json!({});
+        }
+        metadata["correlation_id"] = json!(self.id);
+        if let Some(reply_to) = &self.reply_to {
+            metadata["reply_to"] = json!(reply_to);
+        }
+    }
+}
+
+/// What a consumer wants from a stream: a pulse type, field values and a
+/// substring, all of which must match. An empty filter passes everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PulseFilter {
+    pub r#type: Option<String>,
+    /// `(path, value)`: the field at the dotted `path` from the pulse root
+    /// (`data.file`, `metadata.target`) equals `value`
+    pub fields: Vec<(String, String)>,
+    /// Found anywhere in the message text as emitted
+    pub contains: Option<String>,
+}
+
+impl PulseFilter {
+    /// Parse a `key=value` field match
+    pub fn parse_field(field: &str) -> Result<(String, String)> {
+        match field.split_once('=') {
+            Some((path, value)) if !path.trim().is_empty() => Ok((path.trim().to_string(), value.to_string())),
+            _ => anyhow::bail!("Invalid match '{}' (expected key=value, e.g. data.file=src/main.rs)", field),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.r#type.is_none() && self.fields.is_empty() && self.contains.is_none()
+    }
+
+    /// Parse the JSON form used by the wasm exports:
+    /// `{"type": "delta", "match": ["data.file=src/main.rs"], "contains": "auth"}`,
+    /// every key optional
+    pub fn from_json(json: &str) -> Result<Self> {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Spec {
+            r#type: Option<String>,
+            #[serde(default, rename = "match")]
+            fields: Vec<String>,
+            contains: Option<String>,
+        }
+        let spec: Spec = serde_json::from_str(json).context("Invalid filter")?;
+        Ok(Self {
+            r#type: spec.r#type,
+            fields: spec.fields.iter().map(|field| Self::parse_field(field)).collect::<Result<_>>()?,
+            contains: spec.contains,
+        })
+    }
+
+    /// Whether the TOON message `toon` passes; errors when the type or a
+    /// field has to be checked and it doesn't decode
+    pub fn matches(&self, toon: &str) -> Result<bool> {
+        if self.contains.as_deref().is_some_and(|needle| !toon.contains(needle)) {
+            return Ok(false);
+        }
+        if self.r#type.is_none() && self.fields.is_empty() {
+            return Ok(true);
+        }
+        let pulse = decode_pulse(toon)?;
+        if self.r#type.as_deref().is_some_and(|t| pulse["type"].as_str() != Some(t)) {
+            return Ok(false);
+        }
+        Ok(self.fields.iter().all(|(path, want)| {
+            match path.split('.').try_fold(&pulse, |value, key| value.get(key)) {
+                Some(Value::String(value)) => value == want,
+                // Numbers and booleans compare as written: data.tests=42
+                Some(value) => serde_json::from_str::<Value>(want).is_ok_and(|want| want == *value),
+                None => false,
+            }
+        }))
+    }
+}
+
+/// Check a decoded pulse against the envelope schema: `id` is a UUID,
+/// `timestamp` RFC 3339, `type` and `channel` non-empty strings, `data`
+/// present and `metadata` an object or null. The optional envelope flags in
+/// metadata must have their types: `seq` an unsigned integer, `priority` a
+/// string, `sticky` a boolean.
+///
+/// With `key`, the pulse must also carry a `metadata.signature` made by
+/// [`sign_pulse`] with the same key. Without one, a signature is left alone.
+pub fn verify_pulse(pulse: &Value, key: Option<&[u8]>) -> Result<()> {
+    let field = |name: &str| pulse.get(name).with_context(|| format!("Pulse has no {}", name));
+    let id = field("id")?.as_str().context("Pulse id is not a string")?;
+    Uuid::parse_str(id).with_context(|| format!("Pulse id '{}' is not a UUID", id))?;
+    let timestamp = field("timestamp")?.as_str().context("Pulse timestamp is not a string")?;
+    chrono::DateTime::parse_from_rfc3339(timestamp)
+        .with_context(|| format!("Pulse timestamp '{}' is not RFC 3339", timestamp))?;
+    for name in ["type", "channel"] {
+        if !field(name)?.as_str().is_some_and(|value| !value.is_empty()) {
+            anyhow::bail!("Pulse {} is not a non-empty string", name);
+        }
+    }
+    field("data")?;
+    let metadata = match pulse.get("metadata") {
+        None | Some(Value::Null) => None,
+        Some(Value::Object(metadata)) => Some(metadata),
+        Some(_) => anyhow::bail!("Pulse metadata is not an object"),
+    };
+    let flag = |name: &str| metadata.and_then(|metadata| metadata.get(name)).filter(|value| !value.is_null());
+    if flag("seq").is_some_and(|seq| !seq.is_u64()) {
+        anyhow::bail!("Pulse seq is not an unsigned integer");
+    }
+    if flag("priority").is_some_and(|priority| !priority.is_string()) {
+        anyhow::bail!("Pulse priority is not a string");
+    }
+    if flag("sticky").is_some_and(|sticky| !sticky.is_boolean()) {
+        anyhow::bail!("Pulse sticky flag is not a boolean");
+    }
+
+    let Some(key) = key else { return Ok(()) };
+    let signature = flag("signature")
+        .context("Pulse is not signed")?
+        .as_str()
+        .context("Pulse signature is not a string")?;
+    let signature = decode_hex(signature).context("Pulse signature is not hex")?;
+    signature_mac(pulse, key)
+        .verify_slice(&signature)
+        .map_err(|_| anyhow::anyhow!("Pulse signature does not match"))
+}
+
+/// Set `metadata.signature` to the hex HMAC-SHA256 of the pulse under `key`,
+/// creating metadata if null. The MAC covers every field but the signature,
+/// with object keys sorted, so it survives a TOON round trip.
+pub fn sign_pulse(pulse: &mut Value, key: &[u8]) {
+    let signature = signature_mac(pulse, key).finalize().into_bytes();
+    let signature: String = signature.iter().map(|byte| format!("{:02x}", byte)).collect();
+    if !pulse["metadata"].is_object() {
+        pulse["metadata"] = json!({});
+    }
+    pulse["metadata"]["signature"] = json!(signature);
+}
+
+fn signature_mac(pulse: &Value, key: &[u8]) -> Hmac<Sha256> {
+    let mut unsigned = pulse.clone();
+    if let Some(metadata) = unsigned.get_mut("metadata").and_then(Value::as_object_mut) {
+        metadata.remove("signature");
+        // Signing a pulse without metadata creates it
+        if metadata.is_empty() {
+            unsigned["metadata"] = Value::Null;
+        }
+    }
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    let mut canonical = String::new();
+    write_canonical(&unsigned, &mut canonical);
+    mac.update(canonical.as_bytes());
+    mac
+}
+
+/// JSON with object keys sorted, whatever order the map keeps them in
+fn write_canonical(value: &Value, out: &mut String) {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by_key(|(key, _)| *key);
+            out.push('{');
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&Value::String(key.clone()).to_string());
+                out.push(':');
+                write_canonical(value, out);
+            }
+            out.push('}');
+        }
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(item, out);
+            }
+            out.push(']');
+        }
+        scalar => out.push_str(&scalar.to_string()),
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn trace_metadata(trace_id: Option<&str>) -> Value {
+    match trace_id {
+        Some(trace_id) => json!({ "trace_id": trace_id }),
+        None => Value::Null,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Vectors shared with the wasm test in tests/wasm.rs
+    const FIXTURES: &str = include_str!("../tests/fixtures/pulses.json");
+
+    #[test]
+    fn test_fixture_vectors() {
+        let vectors: Vec<Value> = serde_json::from_str(FIXTURES).unwrap();
+        assert!(!vectors.is_empty());
+        for vector in vectors {
+            let toon = vector["toon"].as_str().unwrap();
+            assert_eq!(decode_pulse(toon).unwrap(), vector["pulse"], "decode {}", vector["name"]);
+            assert_eq!(encode_pulse(&vector["pulse"]).unwrap(), toon, "encode {}", vector["name"]);
+        }
+    }
+
+    #[test]
+    fn test_new_pulse_round_trips() {
+        let pulse = new_pulse("delta", "repo:delta", json!({"action": "fixed"}), Value::Null);
+        let decoded = decode_pulse(&encode_pulse(&pulse).unwrap()).unwrap();
+        assert_eq!(decoded, pulse);
+    }
+
+    #[test]
+    fn test_well_known_channels() {
+        assert_eq!(WellKnownChannel::parse("repo:delta"), Some(WellKnownChannel::RepoDelta));
+        let spawned = WellKnownChannel::parse("session:spawned").unwrap();
+        assert_eq!(spawned, WellKnownChannel::Session { event: "spawned".into() });
+        assert_eq!(spawned.channel(), "session:spawned");
+        assert_eq!(serde_json::to_value(&spawned).unwrap(), json!({"kind": "session", "event": "spawned"}));
+        assert_eq!(WellKnownChannel::parse("health:"), None);
+        assert_eq!(WellKnownChannel::parse("custom:builds"), None);
+        assert_eq!(WellKnownChannel::parse("repo"), None);
+    }
+
+    #[test]
+    fn test_question_round_trip() {
+        let question = Question {
+            id: "q-1".into(),
+            question: "Which auth provider?".into(),
+            from: Some("builder".into()),
+            session: Some("abc12345".into()),
+            trace_id: Some("t1".into()),
+            reply_channel: Some("team:answers".into()),
+        };
+        let pulse = question.to_pulse();
+        assert_eq!(pulse["channel"], "team:question");
+        assert_eq!(pulse["metadata"]["trace_id"], "t1");
+        let decoded = decode_pulse(&encode_pulse(&pulse).unwrap()).unwrap();
+        assert_eq!(Question::from_pulse(&decoded), Some(question));
+    }
+
+    #[test]
+    fn test_correlation_round_trip() {
+        let request = Correlation::request(None);
+        assert_eq!(request.reply_to, Some(format!("reply:{}", request.id)));
+        assert_eq!(Correlation::request(Some("team:answers")).reply_to.as_deref(), Some("team:answers"));
+
+        // Attaching keeps what the metadata already had
+        let mut metadata = json!({"target": "agent-2"});
+        request.attach(&mut metadata);
+        let pulse = new_pulse("request", "team:question", json!({}), metadata);
+        let decoded = decode_pulse(&encode_pulse(&pulse).unwrap()).unwrap();
+        assert_eq!(decoded["metadata"]["target"], "agent-2");
+        assert_eq!(Correlation::from_pulse(&decoded), Some(request.clone()));
+
+        let mut metadata = Value::Null;
+        Correlation::reply(&request.id).attach(&mut metadata);
+        assert_eq!(metadata, json!({"correlation_id": request.id}));
+        assert_eq!(Correlation::from_pulse(&new_pulse("delta", "repo:delta", json!({}), Value::Null)), None);
+    }
+
+    #[test]
+    fn test_pulse_filter() {
+        let pulse = new_pulse("delta", "repo:delta", json!({"file": "src/auth.rs", "lines": 42}), json!({"target": "agent-2"}));
+        let toon = encode_pulse(&pulse).unwrap();
+        let filter = |r#type: Option<&str>, fields: &[&str], contains: Option<&str>| PulseFilter {
+            r#type: r#type.map(str::to_string),
+            fields: fields.iter().map(|f| PulseFilter::parse_field(f).unwrap()).collect(),
+            contains: contains.map(str::to_string),
+        };
+
+        assert!(PulseFilter::default().is_empty());
+        assert!(PulseFilter::default().matches("not toon: [").unwrap());
+        assert!(filter(Some("delta"), &[], None).matches(&toon).unwrap());
+        assert!(!filter(Some("status"), &[], None).matches(&toon).unwrap());
+        // Field matches AND together
+        assert!(filter(None, &["data.file=src/auth.rs", "data.lines=42", "metadata.target=agent-2"], None).matches(&toon).unwrap());
+        assert!(!filter(None, &["data.file=src/auth.rs", "data.lines=41"], None).matches(&toon).unwrap());
+        assert!(!filter(None, &["data.missing=x"], None).matches(&toon).unwrap());
+        assert!(filter(Some("delta"), &[], Some("auth.rs")).matches(&toon).unwrap());
+        assert!(!filter(Some("delta"), &[], Some("billing.rs")).matches(&toon).unwrap());
+
+        assert!(PulseFilter::parse_field("data.file").is_err());
+        assert!(PulseFilter::parse_field("=x").is_err());
+        assert_eq!(PulseFilter::parse_field("data.note=a=b").unwrap(), ("data.note".into(), "a=b".into()));
+    }
+
+    #[test]
+    fn test_pulse_filter_from_json() {
+        let parsed = PulseFilter::from_json(r#"{"type": "delta", "match": ["data.file=src/auth.rs"], "contains": "auth"}"#).unwrap();
+        assert_eq!(parsed, PulseFilter {
+            r#type: Some("delta".into()),
+            fields: vec![("data.file".into(), "src/auth.rs".into())],
+            contains: Some("auth".into()),
+        });
+        assert!(PulseFilter::from_json("{}").unwrap().is_empty());
+        assert!(PulseFilter::from_json(r#"{"match": ["data.file"]}"#).is_err());
+        assert!(PulseFilter::from_json(r#"{"kind": "delta"}"#).is_err());
+    }
+
+    #[test]
+    fn test_verify_fixture_vectors() {
+        let vectors: Vec<Value> = serde_json::from_str(FIXTURES).unwrap();
+        for vector in vectors {
+            let pulse = decode_pulse(vector["toon"].as_str().unwrap()).unwrap();
+            let key = vector["key"].as_str().map(str::as_bytes);
+            assert!(verify_pulse(&pulse, None).is_ok(), "verify {}", vector["name"]);
+            if let Some(key) = key {
+                verify_pulse(&pulse, Some(key)).unwrap();
+                assert!(verify_pulse(&pulse, Some(b"other key")).is_err(), "wrong key {}", vector["name"]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_verify_pulse_envelope() {
+        let pulse = new_pulse("delta", "repo:delta", json!({"action": "fixed"}), json!({"seq": 4, "priority": "high", "sticky": true}));
+        verify_pulse(&pulse, None).unwrap();
+
+        let broken = |edit: fn(&mut Value)| {
+            let mut pulse = pulse.clone();
+            edit(&mut pulse);
+            verify_pulse(&pulse, None).unwrap_err().to_string()
+        };
+        assert!(broken(|p| p["id"] = json!("q-1")).contains("not a UUID"));
+        assert!(broken(|p| p["timestamp"] = json!("yesterday")).contains("RFC 3339"));
+        assert!(broken(|p| p["channel"] = json!("")).contains("channel"));
+        assert!(broken(|p| { p.as_object_mut().unwrap().remove("data"); }).contains("no data"));
+        assert!(broken(|p| p["metadata"] = json!("x")).contains("metadata"));
+        assert!(broken(|p| p["metadata"]["seq"] = json!(-1)).contains("seq"));
+        assert!(broken(|p| p["metadata"]["priority"] = json!(1)).contains("priority"));
+        assert!(broken(|p| p["metadata"]["sticky"] = json!("yes")).contains("sticky"));
+    }
+
+    #[test]
+    fn test_signed_pulse_round_trips() {
+        let mut pulse = new_pulse("delta", "repo:delta", json!({"action": "fixed", "files": ["a.rs"]}), Value::Null);
+        assert!(verify_pulse(&pulse, Some(b"secret")).unwrap_err().to_string().contains("not signed"));
+        sign_pulse(&mut pulse, b"secret");
+        // Still valid after the trip through TOON, which may reorder keys
+        let decoded = decode_pulse(&encode_pulse(&pulse).unwrap()).unwrap();
+        verify_pulse(&decoded, Some(b"secret")).unwrap();
+
+        let mut tampered = decoded.clone();
+        tampered["data"]["action"] = json!("broke");
+        assert!(verify_pulse(&tampered, Some(b"secret")).unwrap_err().to_string().contains("does not match"));
+        tampered["metadata"]["signature"] = json!("zz");
+        assert!(verify_pulse(&tampered, Some(b"secret")).unwrap_err().to_string().contains("not hex"));
+    }
+
+    #[test]
+    fn test_question_from_skill_pulse() {
+        // What the reviewer skill emits: no question_id, text in `summary`
+        let pulse = new_pulse("question", "team:question", json!({"summary": "why is the TTL 0?"}), Value::Null);
+        let question = Question::from_pulse(&pulse).unwrap();
+        assert_eq!(question.id, pulse["id"].as_str().unwrap());
+        assert_eq!(question.question, "why is the TTL 0?");
+        assert_eq!(question.reply_channel, None);
+
+        let elsewhere = new_pulse("question", "team:status", json!({"question": "?"}), Value::Null);
+        assert_eq!(Question::from_pulse(&elsewhere), None);
+        let empty = new_pulse("question", "team:question", json!({"action": "question"}), Value::Null);
+        assert_eq!(Question::from_pulse(&empty), None);
+    }
+
+    #[test]
+    fn test_answer_round_trip() {
+        let answer = Answer { question_id: "q-1".into(), answer: "Use OIDC".into(), from: Some("human".into()) };
+        let pulse = answer.to_pulse("team:answers", Some("t1"));
+        assert_eq!(pulse["type"], "answer");
+        assert_eq!(pulse["channel"], "team:answers");
+        assert_eq!(Answer::from_pulse(&pulse), Some(answer));
+        assert_eq!(Answer::from_pulse(&new_pulse("status", "team:answers", json!({"question_id": "q-1"}), Value::Null)), None);
+    }
+}
@@ -0,0 +1,195 @@
+//! Emit rate limits
+//!
+//! Every connection keeps a sliding window per channel it emits to, so a
+//! chatty channel only uses up its own budget. The budget is the one of the
+//! channel's `[limits.rate_limits]` entry, or of the most specific pattern
+//! there matching it, and `rate_limit_per_second` for channels without one.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::channels;
+use crate::config::Limits;
+
+/// Windows a connection keeps before it drops the ones that have emptied
+const MAX_IDLE_WINDOWS: usize = 256;
+
+/// At most `max` emits per `per`, written `50/s`, `600/m` or `1000/h`.
+/// A `max` of 0 means no limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct RateLimit {
+    pub max: usize,
+    pub per: Duration,
+}
+
+impl RateLimit {
+    pub fn per_second(max: usize) -> Self {
+        Self { max, per: Duration::from_secs(1) }
+    }
+
+    fn is_unlimited(&self) -> bool {
+        self.max == 0
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || format!("Invalid rate limit '{}' (expected e.g. 50/s, 600/m or 1000/h)", s);
+        let (max, unit) = s.trim().split_once('/').with_context(invalid)?;
+        let per = match unit.trim() {
+            "s" => Duration::from_secs(1),
+            "m" => Duration::from_secs(60),
+            "h" => Duration::from_secs(3600),
+            _ => anyhow::bail!(invalid()),
+        };
+        Ok(Self { max: max.trim().parse().with_context(invalid)?, per })
+    }
+}
+
+impl TryFrom<String> for RateLimit {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = match self.per.as_secs() {
+            3600 => "h",
+            60 => "m",
+            _ => "s",
+        };
+        write!(f, "{}/{}", self.max, unit)
+    }
+}
+
+impl From<RateLimit> for String {
+    fn from(limit: RateLimit) -> Self {
+        limit.to_string()
+    }
+}
+
+/// An emit over its channel's limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimited {
+    pub limit: RateLimit,
+    /// How long until the window has room again
+    pub retry_after: Duration,
+}
+
+/// The emit windows of one connection
+pub struct RateLimiter {
+    limits: BTreeMap<String, RateLimit>,
+    default: RateLimit,
+    windows: HashMap<String, VecDeque<Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: &Limits) -> Self {
+        Self {
+            limits: limits.rate_limits.clone(),
+            default: RateLimit::per_second(limits.rate_limit_per_second),
+            windows: HashMap::new(),
+        }
+    }
+
+    /// The limit of `channel`: its own entry, else the matching pattern with
+    /// the most fixed characters, else the default
+    pub fn limit_for(&self, channel: &str) -> RateLimit {
+        lookup(&self.limits, self.default, channel)
+    }
+
+    /// Count an emit to `channel` at `now`, unless its window is full
+    pub fn check(&mut self, channel: &str, now: Instant) -> Result<(), RateLimited> {
+        let limit = self.limit_for(channel);
+        if limit.is_unlimited() {
+            return Ok(());
+        }
+        if self.windows.len() >= MAX_IDLE_WINDOWS {
+            let (limits, default) = (&self.limits, self.default);
+            self.windows.retain(|channel, window| {
+                window.back().is_some_and(|last| now.duration_since(*last) < lookup(limits, default, channel).per)
+            });
+        }
+        let window = self.windows.entry(channel.to_string()).or_default();
+        while window.front().is_some_and(|oldest| now.duration_since(*oldest) >= limit.per) {
+            window.pop_front();
+        }
+        if window.len() >= limit.max {
+            let oldest = window[window.len() - limit.max];
+            return Err(RateLimited { limit, retry_after: limit.per - now.duration_since(oldest) });
+        }
+        window.push_back(now);
+        Ok(())
+    }
+}
+
+fn lookup(limits: &BTreeMap<String, RateLimit>, default: RateLimit, channel: &str) -> RateLimit {
+    channels::most_specific(limits, channel).copied().unwrap_or(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(default: usize, limits: &[(&str, &str)]) -> RateLimiter {
+        let limits = Limits {
+            rate_limit_per_second: default,
+            rate_limits: limits.iter().map(|(p, l)| (p.to_string(), l.parse().unwrap())).collect(),
+            ..Limits::default()
+        };
+        RateLimiter::new(&limits)
+    }
+
+    #[test]
+    fn test_parse_rate_limit() {
+        assert_eq!("50/s".parse::<RateLimit>().unwrap(), RateLimit::per_second(50));
+        let per_minute: RateLimit = "600/m".parse().unwrap();
+        assert_eq!(per_minute.per, Duration::from_secs(60));
+        assert_eq!(per_minute.to_string(), "600/m");
+        assert!("50".parse::<RateLimit>().is_err());
+        assert!("fast/s".parse::<RateLimit>().is_err());
+        assert!("5/d".parse::<RateLimit>().is_err());
+    }
+
+    #[test]
+    fn test_most_specific_limit_wins() {
+        let limiter = limiter(10, &[("repo:*", "50/s"), ("repo:delta:*", "5/s"), ("team:alert", "1/s")]);
+        assert_eq!(limiter.limit_for("repo:delta"), RateLimit::per_second(50));
+        assert_eq!(limiter.limit_for("repo:delta:big"), RateLimit::per_second(5));
+        assert_eq!(limiter.limit_for("team:alert"), RateLimit::per_second(1));
+        assert_eq!(limiter.limit_for("team:status"), RateLimit::per_second(10));
+    }
+
+    #[test]
+    fn test_windows_per_channel() {
+        let mut limiter = limiter(0, &[("team:*", "2/s")]);
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+
+        assert!(limiter.check("team:alert", at(0)).is_ok());
+        assert!(limiter.check("team:alert", at(400)).is_ok());
+        let limited = limiter.check("team:alert", at(600)).unwrap_err();
+        assert_eq!(limited.limit, RateLimit::per_second(2));
+        assert_eq!(limited.retry_after, Duration::from_millis(400));
+
+        // Another channel has a window of its own, and the default is unlimited
+        assert!(limiter.check("team:status", at(600)).is_ok());
+        for _ in 0..100 {
+            assert!(limiter.check("repo:delta", at(600)).is_ok());
+        }
+
+        // Once the first emit leaves the window there is room again
+        assert!(limiter.check("team:alert", at(1000)).is_ok());
+        assert!(limiter.check("team:alert", at(1100)).is_err());
+    }
+}
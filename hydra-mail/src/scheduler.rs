@@ -0,0 +1,557 @@
+//! Delayed message delivery
+//!
+//! Pending messages sit in a min-heap keyed by due time. A single timer task
+//! in the daemon pops whatever is due and pushes it through the normal
+//! `channels::emit_to` path, so a scheduled message is indistinguishable
+//! from one emitted at delivery time.
+//!
+//! Every method takes `now` explicitly instead of reading the wall clock, which
+//! keeps the ordering and cancellation logic testable without sleeping.
+//!
+//! Persistence is best-effort: when a path is set the pending set is rewritten
+//! to disk after every change, and reloaded on the next daemon start. Without a
+//! path (or if a write fails) pending messages are lost when the daemon exits.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::{Mutex, Notify};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::channels;
+use crate::config::Limits;
+use crate::constants::SCHEDULER_FIRED_HISTORY;
+
+/// A message waiting for its delivery time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledMessage {
+    pub token: Uuid,
+    pub project_uuid: Uuid,
+    pub channel: String,
+    pub message: String,
+    /// The agent the message is addressed to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    pub deliver_at: DateTime<Utc>,
+    pub submitted_at: DateTime<Utc>,
+}
+
+/// Outcome of a cancellation request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelResult {
+    /// Removed before delivery
+    Cancelled,
+    /// Too late: the message has already been handed to its channel
+    AlreadyDelivered,
+    /// No such token for this project
+    NotFound,
+}
+
+/// Pending deliveries ordered by due time
+pub struct Scheduler {
+    /// (due, insertion order, token); cancelled tokens are skipped lazily
+    heap: BinaryHeap<Reverse<(DateTime<Utc>, u64, Uuid)>>,
+    pending: HashMap<Uuid, ScheduledMessage>,
+    /// Recently fired tokens, so a late cancel can say "already delivered"
+    fired: VecDeque<(Uuid, Uuid)>,
+    next_order: u64,
+    persist_path: Option<PathBuf>,
+}
+
+impl Scheduler {
+    /// Scheduler without persistence
+    pub fn new() -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+            pending: HashMap::new(),
+            fired: VecDeque::new(),
+            next_order: 0,
+            persist_path: None,
+        }
+    }
+
+    /// Scheduler backed by a JSON file, loading whatever it holds
+    pub fn with_persistence(path: &Path) -> Result<Self> {
+        let mut scheduler = Self::new();
+        scheduler.persist_path = Some(path.to_path_buf());
+
+        if path.exists() {
+            let content = std::fs::read_to_string(path)
+                .context("Failed to read scheduled messages")?;
+            let messages: Vec<ScheduledMessage> = serde_json::from_str(&content)
+                .context("Failed to parse scheduled messages")?;
+            for msg in messages {
+                scheduler.insert(msg);
+            }
+        }
+
+        Ok(scheduler)
+    }
+
+    /// Queue a message for later delivery, returning its cancel token
+    #[allow(clippy::too_many_arguments)]
+    pub fn schedule(
+        &mut self,
+        project_uuid: Uuid,
+        channel: &str,
+        message: String,
+        target: Option<String>,
+        deliver_at: DateTime<Utc>,
+        now: DateTime<Utc>,
+        limits: &Limits,
+    ) -> Result<Uuid> {
+        let Some(horizon) = limits.schedule_horizon() else {
+            bail!(
+                "max_schedule_horizon_secs = {} is out of range",
+                limits.max_schedule_horizon_secs
+            );
+        };
+        if deliver_at - now > horizon {
+            bail!(
+                "Delivery time is too far out (max {}s ahead)",
+                limits.max_schedule_horizon_secs
+            );
+        }
+
+        let pending_for_project = self
+            .pending
+            .values()
+            .filter(|m| m.project_uuid == project_uuid)
+            .count();
+        if pending_for_project >= limits.max_scheduled_pending {
+            bail!(
+                "Too many scheduled messages pending (max {})",
+                limits.max_scheduled_pending
+            );
+        }
+
+        let token = Uuid::new_v4();
+        self.insert(ScheduledMessage {
+            token,
+            project_uuid,
+            channel: channel.to_string(),
+            message,
+            target,
+            deliver_at,
+            submitted_at: now,
+        });
+        self.persist();
+
+        Ok(token)
+    }
+
+    /// Cancel a pending message
+    pub fn cancel(&mut self, project_uuid: Uuid, token: Uuid) -> CancelResult {
+        match self.pending.get(&token) {
+            Some(msg) if msg.project_uuid == project_uuid => {
+                self.pending.remove(&token);
+                self.persist();
+                CancelResult::Cancelled
+            }
+            Some(_) => CancelResult::NotFound,
+            None if self.fired.contains(&(project_uuid, token)) => CancelResult::AlreadyDelivered,
+            None => CancelResult::NotFound,
+        }
+    }
+
+    /// Remove and return every message due at `now`, earliest first
+    pub fn take_due(&mut self, now: DateTime<Utc>) -> Vec<ScheduledMessage> {
+        let mut due = Vec::new();
+        while let Some(Reverse((deliver_at, _, token))) = self.heap.peek().copied() {
+            if deliver_at > now {
+                break;
+            }
+            self.heap.pop();
+            // Cancelled entries are still in the heap; skip them here
+            if let Some(msg) = self.pending.remove(&token) {
+                if self.fired.len() >= SCHEDULER_FIRED_HISTORY {
+                    self.fired.pop_front();
+                }
+                self.fired.push_back((msg.project_uuid, token));
+                due.push(msg);
+            }
+        }
+        if !due.is_empty() {
+            self.persist();
+        }
+        due
+    }
+
+    /// Due time of the earliest pending message
+    pub fn next_due(&mut self) -> Option<DateTime<Utc>> {
+        while let Some(Reverse((deliver_at, _, token))) = self.heap.peek().copied() {
+            if self.pending.contains_key(&token) {
+                return Some(deliver_at);
+            }
+            self.heap.pop();
+        }
+        None
+    }
+
+    /// Pending messages for a project, earliest first
+    pub fn list(&self, project_uuid: Uuid) -> Vec<ScheduledMessage> {
+        let mut messages: Vec<ScheduledMessage> = self
+            .pending
+            .values()
+            .filter(|m| m.project_uuid == project_uuid)
+            .cloned()
+            .collect();
+        messages.sort_by_key(|m| m.deliver_at);
+        messages
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    fn insert(&mut self, msg: ScheduledMessage) {
+        self.heap.push(Reverse((msg.deliver_at, self.next_order, msg.token)));
+        self.next_order += 1;
+        self.pending.insert(msg.token, msg);
+    }
+
+    /// Rewrite the persistence file (best-effort, errors are only logged)
+    fn persist(&self) {
+        let Some(path) = &self.persist_path else {
+            return;
+        };
+        if let Err(e) = self.write_to(path) {
+            warn!("failed to persist scheduled messages: {:#}", e);
+        }
+    }
+
+    fn write_to(&self, path: &Path) -> Result<()> {
+        let mut messages: Vec<&ScheduledMessage> = self.pending.values().collect();
+        messages.sort_by_key(|m| m.deliver_at);
+        let json = serde_json::to_string(&messages).context("Failed to serialize scheduled messages")?;
+
+        let temp_path = path.with_extension("tmp");
+        std::fs::write(&temp_path, json).context("Failed to write scheduled messages")?;
+        std::fs::rename(&temp_path, path).context("Failed to replace scheduled messages file")?;
+        Ok(())
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Scheduler shared between connection handlers and the timer task
+#[derive(Clone)]
+pub struct SchedulerHandle {
+    inner: Arc<Mutex<Scheduler>>,
+    wake: Arc<Notify>,
+}
+
+impl SchedulerHandle {
+    pub fn new(scheduler: Scheduler) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(scheduler)),
+            wake: Arc::new(Notify::new()),
+        }
+    }
+
+    pub async fn schedule(
+        &self,
+        project_uuid: Uuid,
+        channel: &str,
+        message: String,
+        target: Option<String>,
+        deliver_at: DateTime<Utc>,
+        limits: &Limits,
+    ) -> Result<Uuid> {
+        let token = self
+            .inner
+            .lock()
+            .await
+            .schedule(project_uuid, channel, message, target, deliver_at, Utc::now(), limits)?;
+        // The new message may be due earlier than whatever the timer sleeps on
+        self.wake.notify_one();
+        Ok(token)
+    }
+
+    pub async fn cancel(&self, project_uuid: Uuid, token: Uuid) -> CancelResult {
+        self.inner.lock().await.cancel(project_uuid, token)
+    }
+
+    pub async fn list(&self, project_uuid: Uuid) -> Vec<ScheduledMessage> {
+        self.inner.lock().await.list(project_uuid)
+    }
+
+    /// Deliver everything due at `now`; returns how many messages were sent
+    pub async fn fire_due(&self, now: DateTime<Utc>) -> usize {
+        let due = self.inner.lock().await.take_due(now);
+        let count = due.len();
+        for msg in due {
+            channels::emit_to(msg.project_uuid, &msg.channel, msg.message, msg.target).await;
+        }
+        count
+    }
+
+    /// Timer loop: sleep until the next due time (or until woken by a new
+    /// schedule), then deliver. Runs until the task is aborted.
+    pub async fn run(self) {
+        loop {
+            self.fire_due(Utc::now()).await;
+
+            let next = self.inner.lock().await.next_due();
+            match next {
+                Some(due) => {
+                    let wait = (due - Utc::now()).to_std().unwrap_or_default();
+                    tokio::select! {
+                        _ = tokio::time::sleep(wait) => {}
+                        _ = self.wake.notified() => {}
+                    }
+                }
+                None => self.wake.notified().await,
+            }
+        }
+    }
+}
+
+/// Parse a delay like "90s", "30m", "2h" or "1h30m"
+pub fn parse_delay(s: &str) -> Result<chrono::Duration> {
+    let mut total: i64 = 0;
+    let mut current = String::new();
+
+    for ch in s.trim().chars() {
+        if ch.is_ascii_digit() {
+            current.push(ch);
+            continue;
+        }
+        let unit = match ch {
+            'd' | 'D' => 86_400,
+            'h' | 'H' => 3_600,
+            'm' | 'M' => 60,
+            's' | 'S' => 1,
+            _ => bail!("Invalid character in delay: {}", ch),
+        };
+        let value: i64 = current
+            .parse()
+            .with_context(|| format!("Invalid delay: {}", s))?;
+        total = value
+            .checked_mul(unit)
+            .and_then(|secs| total.checked_add(secs))
+            .with_context(|| format!("Delay '{}' is too large", s))?;
+        current.clear();
+    }
+
+    if !current.is_empty() {
+        bail!("Delay '{}' is missing a unit (s, m, h or d)", s);
+    }
+    if total == 0 {
+        bail!("Delay must be greater than 0");
+    }
+
+    chrono::Duration::try_seconds(total).with_context(|| format!("Delay '{}' is too large", s))
+}
+
+/// The time `delay` from `now`, failing instead of overflowing
+pub fn after(now: DateTime<Utc>, delay: chrono::Duration) -> Result<DateTime<Utc>> {
+    now.checked_add_signed(delay).context("Delay reaches past the latest representable time")
+}
+
+/// The time `delay` before `now`, failing instead of overflowing
+pub fn before(now: DateTime<Utc>, delay: chrono::Duration) -> Result<DateTime<Utc>> {
+    now.checked_sub_signed(delay).context("Delay reaches past the earliest representable time")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn t0() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2025-01-01T12:00:00Z").unwrap().with_timezone(&Utc)
+    }
+
+    fn secs(n: i64) -> chrono::Duration {
+        chrono::Duration::seconds(n)
+    }
+
+    #[test]
+    fn test_delivery_ordering() {
+        let limits = Limits::default();
+        let project = Uuid::new_v4();
+        let mut s = Scheduler::new();
+
+        s.schedule(project, "ch", "third".into(), None, t0() + secs(30), t0(), &limits).unwrap();
+        s.schedule(project, "ch", "first".into(), None, t0() + secs(10), t0(), &limits).unwrap();
+        s.schedule(project, "ch", "second".into(), None, t0() + secs(20), t0(), &limits).unwrap();
+        s.schedule(project, "ch", "second-b".into(), None, t0() + secs(20), t0(), &limits).unwrap();
+
+        assert!(s.take_due(t0() + secs(5)).is_empty());
+        assert_eq!(s.next_due(), Some(t0() + secs(10)));
+
+        let due: Vec<String> = s.take_due(t0() + secs(25)).into_iter().map(|m| m.message).collect();
+        assert_eq!(due, vec!["first", "second", "second-b"]);
+
+        let due: Vec<String> = s.take_due(t0() + secs(60)).into_iter().map(|m| m.message).collect();
+        assert_eq!(due, vec!["third"]);
+        assert!(s.is_empty());
+        assert_eq!(s.next_due(), None);
+    }
+
+    #[test]
+    fn test_cancel_before_delivery() {
+        let limits = Limits::default();
+        let project = Uuid::new_v4();
+        let mut s = Scheduler::new();
+
+        let token = s.schedule(project, "ch", "msg".into(), None, t0() + secs(10), t0(), &limits).unwrap();
+        assert_eq!(s.cancel(project, token), CancelResult::Cancelled);
+        assert_eq!(s.cancel(project, token), CancelResult::NotFound);
+
+        assert!(s.take_due(t0() + secs(60)).is_empty());
+        assert_eq!(s.next_due(), None);
+    }
+
+    #[test]
+    fn test_cancel_just_after_fire() {
+        let limits = Limits::default();
+        let project = Uuid::new_v4();
+        let mut s = Scheduler::new();
+
+        let token = s.schedule(project, "ch", "msg".into(), None, t0() + secs(10), t0(), &limits).unwrap();
+        assert_eq!(s.take_due(t0() + secs(10)).len(), 1);
+        assert_eq!(s.cancel(project, token), CancelResult::AlreadyDelivered);
+    }
+
+    #[test]
+    fn test_cancel_other_project_not_found() {
+        let limits = Limits::default();
+        let project = Uuid::new_v4();
+        let mut s = Scheduler::new();
+
+        let token = s.schedule(project, "ch", "msg".into(), None, t0() + secs(10), t0(), &limits).unwrap();
+        assert_eq!(s.cancel(Uuid::new_v4(), token), CancelResult::NotFound);
+        assert_eq!(s.len(), 1);
+    }
+
+    #[test]
+    fn test_per_project_cap() {
+        let limits = Limits { max_scheduled_pending: 2, ..Limits::default() };
+        let project = Uuid::new_v4();
+        let other = Uuid::new_v4();
+        let mut s = Scheduler::new();
+
+        s.schedule(project, "ch", "a".into(), None, t0() + secs(10), t0(), &limits).unwrap();
+        s.schedule(project, "ch", "b".into(), None, t0() + secs(10), t0(), &limits).unwrap();
+        let err = s.schedule(project, "ch", "c".into(), None, t0() + secs(10), t0(), &limits).unwrap_err();
+        assert!(err.to_string().contains("Too many scheduled messages"));
+
+        // Other projects have their own budget
+        s.schedule(other, "ch", "d".into(), None, t0() + secs(10), t0(), &limits).unwrap();
+
+        // Delivery frees a slot
+        s.take_due(t0() + secs(10));
+        s.schedule(project, "ch", "e".into(), None, t0() + secs(20), t0(), &limits).unwrap();
+    }
+
+    #[test]
+    fn test_horizon_limit() {
+        let limits = Limits { max_schedule_horizon_secs: 60, ..Limits::default() };
+        let mut s = Scheduler::new();
+
+        assert!(s.schedule(Uuid::new_v4(), "ch", "ok".into(), None, t0() + secs(60), t0(), &limits).is_ok());
+        let err = s.schedule(Uuid::new_v4(), "ch", "late".into(), None, t0() + secs(61), t0(), &limits).unwrap_err();
+        assert!(err.to_string().contains("too far out"));
+
+        // A horizon past what a duration holds is an error, not a panic
+        let limits = Limits { max_schedule_horizon_secs: u64::MAX, ..Limits::default() };
+        let err = s.schedule(Uuid::new_v4(), "ch", "any".into(), None, t0() + secs(1), t0(), &limits).unwrap_err();
+        assert!(err.to_string().contains("out of range"));
+    }
+
+    #[test]
+    fn test_restart_with_persistence() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("scheduled.json");
+        let limits = Limits::default();
+        let project = Uuid::new_v4();
+
+        let (kept, cancelled) = {
+            let mut s = Scheduler::with_persistence(&path).unwrap();
+            let kept = s.schedule(project, "ch", "kept".into(), None, t0() + secs(10), t0(), &limits).unwrap();
+            let cancelled = s.schedule(project, "ch", "gone".into(), None, t0() + secs(20), t0(), &limits).unwrap();
+            s.cancel(project, cancelled);
+            (kept, cancelled)
+        };
+
+        let mut s = Scheduler::with_persistence(&path).unwrap();
+        let pending = s.list(project);
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].token, kept);
+        assert_eq!(s.cancel(project, cancelled), CancelResult::NotFound);
+
+        // Overdue messages fire straight away after restart
+        let due = s.take_due(t0() + secs(3600));
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].message, "kept");
+
+        let s = Scheduler::with_persistence(&path).unwrap();
+        assert!(s.is_empty());
+    }
+
+    #[test]
+    fn test_restart_without_persistence() {
+        let limits = Limits::default();
+        let project = Uuid::new_v4();
+
+        {
+            let mut s = Scheduler::new();
+            s.schedule(project, "ch", "lost".into(), None, t0() + secs(10), t0(), &limits).unwrap();
+        }
+
+        let s = Scheduler::new();
+        assert!(s.list(project).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fire_due_uses_emit_path() {
+        let limits = Limits::default();
+        let project = Uuid::new_v4();
+        let handle = SchedulerHandle::new(Scheduler::new());
+
+        let deliver_at = Utc::now() + secs(3600);
+        handle.schedule(project, "sched:test", "later".into(), None, deliver_at, &limits).await.unwrap();
+
+        assert_eq!(handle.fire_due(Utc::now()).await, 0);
+        let (_rx, history) = channels::subscribe_broadcast(project, "sched:test").await;
+        assert!(history.is_empty());
+
+        assert_eq!(handle.fire_due(deliver_at).await, 1);
+        let (_rx, history) = channels::subscribe_broadcast(project, "sched:test").await;
+        assert_eq!(history, vec!["later".to_string()]);
+    }
+
+    #[test]
+    fn test_parse_delay() {
+        assert_eq!(parse_delay("30m").unwrap(), secs(1800));
+        assert_eq!(parse_delay("1h30m").unwrap(), secs(5400));
+        assert_eq!(parse_delay("90s").unwrap(), secs(90));
+        assert_eq!(parse_delay("2d").unwrap(), secs(172_800));
+        assert!(parse_delay("30").is_err());
+        assert!(parse_delay("0m").is_err());
+        assert!(parse_delay("5x").is_err());
+        // Too large for the arithmetic, or for a duration or time
+        assert!(parse_delay("9223372036854775807d").is_err());
+        assert!(parse_delay("9223372036854775807s1s").is_err());
+        assert!(parse_delay("99999999999999999999s").is_err());
+        let huge = parse_delay("9223372036854775s").unwrap();
+        assert!(after(t0(), huge).is_err());
+        assert!(before(t0(), huge).is_err());
+        assert_eq!(before(t0(), secs(60)).unwrap(), t0() - secs(60));
+    }
+}
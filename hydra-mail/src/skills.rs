@@ -0,0 +1,343 @@
+//! Role-aware skill generation.
+//!
+//! Every agent role gets its own `hydra-mail.<role>.yaml` rendered from one
+//! shared template. What a role emits and listens to comes from [`ROLES`], so
+//! the skill text and the default emit ACL never disagree.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Agent role a skill variant is generated for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Builder,
+    Reviewer,
+    Orchestrator,
+}
+
+/// Channel guidance for one role.
+#[derive(Debug)]
+pub struct RoleSpec {
+    pub role: Role,
+    pub summary: &'static str,
+    /// Channels the role is expected to emit to (default ACL); `prefix:*`
+    /// covers every channel in that namespace
+    pub emits: &'static [&'static str],
+    /// Channels the role should watch
+    pub subscribes: &'static [&'static str],
+}
+
+/// Single source of truth for role behaviour.
+pub const ROLES: &[RoleSpec] = &[
+    RoleSpec {
+        role: Role::Builder,
+        summary: "Implements changes and announces each one as a delta",
+        emits: &["repo:delta", "team:status", "team:alert", "team:question"],
+        subscribes: &["repo:delta", "team:question"],
+    },
+    RoleSpec {
+        role: Role::Reviewer,
+        summary: "Reads other agents' work and raises questions without producing deltas",
+        emits: &["team:question", "team:status"],
+        subscribes: &["repo:delta", "team:status", "team:alert"],
+    },
+    RoleSpec {
+        role: Role::Orchestrator,
+        summary: "Coordinates sessions and tracks their lifecycle",
+        emits: &["session:*", "health:*", "team:status", "team:alert"],
+        subscribes: &["team:status", "team:alert", "team:question", "session:*"],
+    },
+];
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Builder => "builder",
+            Role::Reviewer => "reviewer",
+            Role::Orchestrator => "orchestrator",
+        }
+    }
+
+    pub fn spec(&self) -> &'static RoleSpec {
+        ROLES
+            .iter()
+            .find(|s| s.role == *self)
+            .expect("every role has an entry in ROLES")
+    }
+
+    /// Whether the role's default ACL lets it emit to `channel`
+    pub fn may_emit(&self, channel: &str) -> bool {
+        self.spec().emits.iter().any(|allowed| match allowed.strip_suffix('*') {
+            Some(prefix) => channel.starts_with(prefix),
+            None => *allowed == channel,
+        })
+    }
+
+    pub fn skill_file_name(&self) -> String {
+        format!("hydra-mail.{}.yaml", self.as_str())
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match ROLES.iter().find(|spec| spec.role.as_str() == s) {
+            Some(spec) => Ok(spec.role),
+            None => bail!(
+                "unknown role '{}' (expected one of: {})",
+                s,
+                ROLES.iter().map(|spec| spec.role.as_str()).collect::<Vec<_>>().join(", ")
+            ),
+        }
+    }
+}
+
+/// Shared skill template.
+///
+/// `{{#role}}...{{/role}}` sections are kept only for that role; `{{name}}`
+/// placeholders are filled from the role's [`RoleSpec`].
+const SKILL_TEMPLATE: &str = r#"# Generated by hydra-mail for the {{role}} role - do not edit.
+# Regenerate with: hydra-mail regenerate --role {{role}}
+name: hydra-mail-{{role}}
+description: >
+  Hydra Mail coordination for {{role}} agents. {{summary}}.
+role: {{role}}
+channels:
+  emit: [{{emits}}]
+  subscribe: [{{subscribes}}]
+hooks:
+  SessionStart: "hydra-mail hook session-start --project ."
+  Stop: "hydra-mail hook stop --project ."
+instructions: |
+  # Hydra Mail ({{role}})
+
+  You share this project with other agents. Emit only to: {{emits}}.
+  Watch: {{subscribes}}.
+
+  Messages use `{"action":"<verb>","target":"<what>","summary":"<impact>"}`.
+{{#builder}}
+
+  ## Emitting deltas
+
+  After every meaningful change, emit one delta:
+
+      hydra-mail emit --channel repo:delta --type delta \
+        --data '{"action":"fixed","target":"auth.py","summary":"token validation works"}'
+
+  Actions: fixed, added, updated, refactored, completed, blocked.
+  Report blockers on team:alert and ask on team:question instead of guessing.
+  Before touching a file, check recent deltas:
+
+      hydra-mail subscribe --channel repo:delta --once
+{{/builder}}
+{{#reviewer}}
+
+  ## Reviewing
+
+  Follow what builders change:
+
+      hydra-mail subscribe --channel repo:delta
+
+  Do not emit to repo:delta - reading or reviewing a file is not a change.
+  Ask about anything unclear on team:question:
+
+      hydra-mail emit --channel team:question --type question \
+        --data '{"action":"question","target":"auth.py","summary":"why is the token TTL 0?"}'
+
+  Post review verdicts on team:status:
+
+      hydra-mail emit --channel team:status --type status \
+        --data '{"action":"reviewed","target":"auth.py","summary":"approved"}'
+{{/reviewer}}
+{{#orchestrator}}
+
+  ## Session conventions
+
+  Session lifecycle goes on `session:*` channels, one per event:
+  spawned, paused, resumed, injected, renamed, state, stuck, killed and
+  exited. Health actions go on `health:*` (e.g. health:killed).
+  Every payload carries `{"session":"<id>"}` plus event details.
+
+      hydra-mail emit --channel session:spawned --type session \
+        --data '{"session":"abc123","branch":"feature/auth"}'
+
+  Watch team:alert and team:question to decide when to inject guidance
+  or kill a stuck session, and summarise progress on team:status.
+{{/orchestrator}}
+"#;
+
+/// Render the skill variant for `role`.
+pub fn render_skill(role: Role) -> String {
+    let spec = role.spec();
+    let mut out = String::new();
+    // Role of the conditional section currently being read, if any
+    let mut section: Option<&str> = None;
+
+    for line in SKILL_TEMPLATE.lines() {
+        let trimmed = line.trim();
+        if let Some(name) = trimmed.strip_prefix("{{#").and_then(|l| l.strip_suffix("}}")) {
+            section = Some(name);
+            continue;
+        }
+        if trimmed.starts_with("{{/") {
+            section = None;
+            continue;
+        }
+        if section.is_some_and(|name| name != role.as_str()) {
+            continue;
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+
+    out.replace("{{role}}", role.as_str())
+        .replace("{{summary}}", spec.summary)
+        .replace("{{emits}}", &spec.emits.join(", "))
+        .replace("{{subscribes}}", &spec.subscribes.join(", "))
+}
+
+/// Directory holding generated skill variants
+pub fn skills_dir(hydra_dir: &Path) -> PathBuf {
+    hydra_dir.join("skills")
+}
+
+/// Write one skill variant per role, returning the paths written.
+pub fn write_skills(hydra_dir: &Path, roles: &[Role]) -> Result<Vec<PathBuf>> {
+    let dir = skills_dir(hydra_dir);
+    fs::create_dir_all(&dir).context("Failed to create .hydra/skills")?;
+
+    roles
+        .iter()
+        .map(|role| {
+            let path = dir.join(role.skill_file_name());
+            fs::write(&path, render_skill(*role))
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            Ok(path)
+        })
+        .collect()
+}
+
+/// Generated files that no longer match what would be rendered now.
+///
+/// Covers every configured role plus any `hydra-mail.*.yaml` left behind for
+/// a role that is no longer configured.
+pub fn detect_drift(hydra_dir: &Path, roles: &[Role]) -> Result<Vec<PathBuf>> {
+    let dir = skills_dir(hydra_dir);
+    let mut drifted = Vec::new();
+
+    for role in roles {
+        let path = dir.join(role.skill_file_name());
+        match fs::read_to_string(&path) {
+            Ok(current) if current == render_skill(*role) => {}
+            _ => drifted.push(path),
+        }
+    }
+
+    if dir.exists() {
+        let expected: Vec<String> = roles.iter().map(|r| r.skill_file_name()).collect();
+        for entry in fs::read_dir(&dir).context("Failed to read .hydra/skills")? {
+            let path = entry?.path();
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+            if name.starts_with("hydra-mail.") && name.ends_with(".yaml") && !expected.iter().any(|e| e == name) {
+                drifted.push(path);
+            }
+        }
+    }
+
+    drifted.sort();
+    Ok(drifted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Compare against the checked-in snapshot; set UPDATE_SNAPSHOTS=1 to refresh.
+    fn assert_snapshot(role: Role) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/snapshots")
+            .join(role.skill_file_name());
+        let rendered = render_skill(role);
+
+        if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            fs::write(&path, &rendered).unwrap();
+            return;
+        }
+        let expected = fs::read_to_string(&path).unwrap();
+        assert_eq!(rendered, expected, "snapshot mismatch for {}", role);
+    }
+
+    #[test]
+    fn test_builder_snapshot() {
+        assert_snapshot(Role::Builder);
+    }
+
+    #[test]
+    fn test_reviewer_snapshot() {
+        assert_snapshot(Role::Reviewer);
+    }
+
+    #[test]
+    fn test_orchestrator_snapshot() {
+        assert_snapshot(Role::Orchestrator);
+    }
+
+    #[test]
+    fn test_sections_are_role_specific() {
+        let reviewer = render_skill(Role::Reviewer);
+        assert!(reviewer.contains("Do not emit to repo:delta"));
+        assert!(!reviewer.contains("Emitting deltas"));
+        assert!(!reviewer.contains("{{"));
+
+        let builder = render_skill(Role::Builder);
+        assert!(builder.contains("--channel repo:delta --type delta"));
+        assert!(!builder.contains("Session conventions"));
+    }
+
+    #[test]
+    fn test_acl_matches_roles_table() {
+        assert!(Role::Builder.may_emit("repo:delta"));
+        assert!(!Role::Reviewer.may_emit("repo:delta"));
+        assert!(Role::Reviewer.may_emit("team:question"));
+        assert!(Role::Orchestrator.may_emit("session:spawned"));
+        assert!(!Role::Orchestrator.may_emit("sessions"));
+        assert!("auditor".parse::<Role>().is_err());
+        assert_eq!("reviewer".parse::<Role>().unwrap(), Role::Reviewer);
+    }
+
+    #[test]
+    fn test_detect_drift() {
+        let temp = TempDir::new().unwrap();
+        let hydra_dir = temp.path();
+        let roles = [Role::Builder, Role::Reviewer];
+
+        // Nothing generated yet: every configured variant is missing
+        assert_eq!(detect_drift(hydra_dir, &roles).unwrap().len(), 2);
+
+        write_skills(hydra_dir, &roles).unwrap();
+        assert!(detect_drift(hydra_dir, &roles).unwrap().is_empty());
+
+        // Hand edit
+        let reviewer = skills_dir(hydra_dir).join("hydra-mail.reviewer.yaml");
+        fs::write(&reviewer, "edited").unwrap();
+        assert_eq!(detect_drift(hydra_dir, &roles).unwrap(), vec![reviewer.clone()]);
+
+        // Leftover variant for a role no longer configured
+        write_skills(hydra_dir, &roles).unwrap();
+        let stale = detect_drift(hydra_dir, &[Role::Builder]).unwrap();
+        assert_eq!(stale, vec![reviewer]);
+    }
+}
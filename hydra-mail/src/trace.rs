@@ -0,0 +1,181 @@
+//! Trace context propagation.
+//!
+//! The orchestrator exports `HYDRA_TRACE_ID` into each session's environment.
+//! Every emit made from inside that session (agent skill, hooks, hydra-wt)
+//! copies it into pulse metadata, so one workflow can be pulled back together
+//! across channels and the orchestrator event journal.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use uuid::Uuid;
+
+use crate::message_log::LogEntry;
+use crate::pulse::decode_pulse;
+
+/// Workflow id set by the orchestrator at spawn
+pub const TRACE_ENV: &str = "HYDRA_TRACE_ID";
+/// Optional label for the step within a workflow
+pub const SPAN_ENV: &str = "HYDRA_SPAN";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: String,
+    pub span: Option<String>,
+}
+
+impl TraceContext {
+    /// Read the trace context from the process environment
+    pub fn from_env() -> Option<Self> {
+        Self::from_vars(std::env::var(TRACE_ENV).ok(), std::env::var(SPAN_ENV).ok())
+    }
+
+    /// Build from raw variable values; blank values count as unset
+    pub fn from_vars(trace_id: Option<String>, span: Option<String>) -> Option<Self> {
+        let trace_id = trace_id.filter(|t| !t.trim().is_empty())?;
+        Some(Self {
+            trace_id,
+            span: span.filter(|s| !s.trim().is_empty()),
+        })
+    }
+
+    /// Add `trace_id` (and `span`) to pulse metadata, creating it if null
+    pub fn attach(&self, metadata: &mut Value) {
+        if !metadata.is_object() {
+            *metadata = json!({});
+        }
+        metadata["trace_id"] = json!(self.trace_id);
+        if let Some(span) = &self.span {
+            metadata["span"] = json!(span);
+        }
+    }
+}
+
+/// One message or orchestrator event belonging to a trace
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceEntry {
+    pub timestamp: DateTime<Utc>,
+    /// Channel for pulses, `orchestrator` for journal events
+    pub source: String,
+    pub body: Value,
+}
+
+/// Pulses in `entries` for `project` whose metadata carries `trace_id`
+pub fn collect_messages(entries: &[LogEntry], project: Uuid, trace_id: &str) -> Vec<TraceEntry> {
+    entries.iter()
+        .filter(|e| e.project_uuid == project)
+        .filter_map(|e| {
+            let pulse = decode_pulse(&e.message).ok()?;
+            (pulse["metadata"]["trace_id"].as_str() == Some(trace_id)).then(|| TraceEntry {
+                timestamp: e.timestamp,
+                source: e.channel.clone(),
+                body: pulse,
+            })
+        })
+        .collect()
+}
+
+/// Orchestrator journal events (JSON lines) whose data carries `trace_id`.
+/// A missing journal yields nothing.
+pub fn collect_journal(path: &Path, trace_id: &str) -> Vec<TraceEntry> {
+    let Ok(file) = std::fs::File::open(path) else {
+        return Vec::new();
+    };
+    BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| serde_json::from_str::<Value>(&line).ok())
+        .filter(|event| event["data"]["trace_id"].as_str() == Some(trace_id))
+        .filter_map(|event| {
+            let timestamp = event["ts"].as_str()?.parse().ok()?;
+            Some(TraceEntry { timestamp, source: "orchestrator".to_string(), body: event })
+        })
+        .collect()
+}
+
+/// Everything recorded for one trace, oldest first
+pub fn reconstruct(entries: &[LogEntry], project: Uuid, journal: &Path, trace_id: &str) -> Vec<TraceEntry> {
+    let mut all = collect_messages(entries, project, trace_id);
+    all.extend(collect_journal(journal, trace_id));
+    // Stable sort keeps log order for equal timestamps
+    all.sort_by_key(|e| e.timestamp);
+    all
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pulse::encode_pulse;
+
+    fn pulse(channel: &str, metadata: Value) -> String {
+        encode_pulse(&json!({
+            "id": Uuid::new_v4(),
+            "type": "delta",
+            "channel": channel,
+            "data": {"action": "fixed"},
+            "metadata": metadata,
+        })).unwrap()
+    }
+
+    fn entry(project: Uuid, channel: &str, secs: i64, metadata: Value) -> LogEntry {
+        LogEntry {
+            project_uuid: project,
+            channel: channel.to_string(),
+            message: pulse(channel, metadata),
+            timestamp: DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap(),
+            target: None,
+        }
+    }
+
+    #[test]
+    fn test_from_vars() {
+        assert_eq!(TraceContext::from_vars(None, Some("build".into())), None);
+        assert_eq!(TraceContext::from_vars(Some(" ".into()), None), None);
+        let ctx = TraceContext::from_vars(Some("t1".into()), Some("".into())).unwrap();
+        assert_eq!(ctx, TraceContext { trace_id: "t1".into(), span: None });
+    }
+
+    #[test]
+    fn test_attach_keeps_existing_metadata() {
+        let ctx = TraceContext { trace_id: "t1".into(), span: Some("review".into()) };
+
+        let mut metadata = Value::Null;
+        ctx.attach(&mut metadata);
+        assert_eq!(metadata, json!({"trace_id": "t1", "span": "review"}));
+
+        let mut metadata = json!({"target": "agent-2"});
+        ctx.attach(&mut metadata);
+        assert_eq!(metadata, json!({"target": "agent-2", "trace_id": "t1", "span": "review"}));
+    }
+
+    #[test]
+    fn test_reconstruct_across_channels_and_journal() {
+        let project = Uuid::new_v4();
+        let entries = vec![
+            entry(project, "repo:delta", 30, json!({"trace_id": "t1"})),
+            entry(project, "repo:delta", 10, Value::Null),
+            entry(project, "team:status", 20, json!({"trace_id": "t1", "span": "review"})),
+            entry(project, "team:status", 25, json!({"trace_id": "t2"})),
+            entry(Uuid::new_v4(), "repo:delta", 5, json!({"trace_id": "t1"})),
+        ];
+
+        let dir = std::env::temp_dir().join(format!("hydra-trace-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let journal = dir.join("events.jsonl");
+        std::fs::write(&journal, concat!(
+            r#"{"ts":"2023-11-14T22:13:35Z","type":"session","event":"spawned","session":"abc","data":{"trace_id":"t1"}}"#, "\n",
+            r#"{"ts":"2023-11-14T22:13:36Z","type":"session","event":"spawned","session":"def","data":{"trace_id":"t2"}}"#, "\n",
+        )).unwrap();
+
+        let trace = reconstruct(&entries, project, &journal, "t1");
+        let sources: Vec<&str> = trace.iter().map(|e| e.source.as_str()).collect();
+        assert_eq!(sources, vec!["orchestrator", "team:status", "repo:delta"]);
+        assert_eq!(trace[1].body["metadata"]["span"], "review");
+
+        // No journal: messages only
+        assert_eq!(reconstruct(&entries, project, &dir.join("missing"), "t1").len(), 2);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,48 @@
+//! wasm-bindgen exports for browser dashboards.
+//!
+//! Thin wrappers over [`crate::pulse`] so JS decodes pulses with the same code
+//! as the daemon and CLI. Values cross the boundary as JSON strings; callers
+//! `JSON.parse` the result. Build with `scripts/build-wasm.sh`.
+
+use wasm_bindgen::prelude::*;
+
+use crate::pulse::{self, PulseFilter, WellKnownChannel};
+
+/// Decode a TOON pulse (as found in archives or on the wire) to JSON
+#[wasm_bindgen(js_name = decodePulse)]
+pub fn decode_pulse(toon: &str) -> Result<String, JsError> {
+    let value = pulse::decode_pulse(toon).map_err(|e| JsError::new(&format!("{:#}", e)))?;
+    Ok(value.to_string())
+}
+
+/// Encode a JSON pulse to TOON
+#[wasm_bindgen(js_name = encodePulse)]
+pub fn encode_pulse(json: &str) -> Result<String, JsError> {
+    let value: serde_json::Value = serde_json::from_str(json)?;
+    pulse::encode_pulse(&value).map_err(|e| JsError::new(&format!("{:#}", e)))
+}
+
+/// `{"kind": ...}` for well-known channels, `undefined` for custom ones
+#[wasm_bindgen(js_name = parseWellKnown)]
+pub fn parse_well_known(channel: &str) -> Option<String> {
+    WellKnownChannel::parse(channel).map(|parsed| serde_json::to_string(&parsed).unwrap_or_default())
+}
+
+/// Whether a TOON message passes a filter given as
+/// `{"type": ..., "match": ["data.file=..."], "contains": ...}`, as
+/// `subscribe --type/--match/--contains` would decide
+#[wasm_bindgen(js_name = evaluateFilter)]
+pub fn evaluate_filter(filter: &str, toon: &str) -> Result<bool, JsError> {
+    let filter = PulseFilter::from_json(filter).map_err(|e| JsError::new(&format!("{:#}", e)))?;
+    filter.matches(toon).map_err(|e| JsError::new(&format!("{:#}", e)))
+}
+
+/// Decode a TOON pulse and check its envelope, and its signature when a
+/// `key` is given; throws on the first problem, else returns the pulse as
+/// JSON
+#[wasm_bindgen(js_name = verifyPulse)]
+pub fn verify_pulse(toon: &str, key: Option<String>) -> Result<String, JsError> {
+    let value = pulse::decode_pulse(toon).map_err(|e| JsError::new(&format!("{:#}", e)))?;
+    pulse::verify_pulse(&value, key.as_deref().map(str::as_bytes)).map_err(|e| JsError::new(&format!("{:#}", e)))?;
+    Ok(value.to_string())
+}
@@ -59,18 +59,55 @@ async fn test_log_compaction() -> Result<()> {
 
     // Compact to keep only last 100
     {
-        let log = MessageLog::open(&log_path)?;
+        let mut log = MessageLog::open(&log_path)?;
         log.compact(100)?;
+        // Lands in the compacted log, not the file it replaced
+        log.append(project_uuid, "test:channel", "after")?;
     }
 
-    // Verify only 100 remain
+    // Verify only 100 remain, plus the one appended since
     {
         let log = MessageLog::open(&log_path)?;
         let entries = log.replay()?;
-        assert_eq!(entries.len(), 100, "Should keep only 100 messages after compaction");
+        assert_eq!(entries.len(), 101, "Should keep only 100 messages after compaction");
         assert_eq!(entries[0].message, "msg50", "First message should be msg50 (50-149)");
         assert_eq!(entries[99].message, "msg149", "Last message should be msg149");
+        assert_eq!(entries[100].message, "after");
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_compaction_carries_over_appends_made_while_it_ran() -> Result<()> {
+    use hydra_mail::message_log::MessageLog;
+
+    let temp_dir = TempDir::new()?;
+    let log_path = temp_dir.path().join("messages.log");
+    let project_uuid = Uuid::new_v4();
+
+    let mut log = MessageLog::open(&log_path)?;
+    for i in 0..150 {
+        log.append(project_uuid, "test:channel", &format!("msg{}", i))?;
     }
+    let rewrite = MessageLog::prepare_compaction(&log_path, 100)?;
+    // Appended between the copy and the swap
+    log.append(project_uuid, "test:channel", "late")?;
+    assert!(log.finish_rewrite(rewrite)?);
+    log.append(project_uuid, "test:channel", "after")?;
+
+    let entries = MessageLog::open(&log_path)?.replay()?;
+    let messages: Vec<&str> = entries.iter().map(|e| e.message.as_str()).collect();
+    assert_eq!(messages.len(), 102);
+    assert_eq!((messages[0], messages[100], messages[101]), ("msg50", "late", "after"));
+
+    // A log rewritten meanwhile doesn't take a stale copy
+    let rewrite = MessageLog::prepare_compaction(&log_path, 10)?;
+    log.compact(50)?;
+    assert!(!log.finish_rewrite(rewrite)?);
+    assert_eq!(MessageLog::open(&log_path)?.replay()?.len(), 50);
+    let leftovers = std::fs::read_dir(temp_dir.path())?.count();
+    assert_eq!(leftovers, 1, "temporary copies should be removed");
 
     Ok(())
 }
@@ -3,6 +3,14 @@ use std::env;
 use std::fs;
 use anyhow::Result;
 
+/// The binary, kept away from the caller's `XDG_RUNTIME_DIR` and
+/// `HYDRA_SOCKET` so every socket stays inside the test's directory
+fn hydra_mail() -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_hydra-mail"));
+    command.env_remove("XDG_RUNTIME_DIR").env_remove("HYDRA_SOCKET");
+    command
+}
+
 #[tokio::test]
 async fn test_init_creates_hydra() -> Result<()> {
     let temp_dir = env::temp_dir().join("hydra_test_init");
@@ -11,10 +19,8 @@ async fn test_init_creates_hydra() -> Result<()> {
     }
     fs::create_dir_all(&temp_dir)?;
 
-    let project_root = env::current_dir()?;
-    let binary_path = project_root.join("target/release/hydra-mail");
 
-    let output = Command::new(&binary_path)
+    let output = hydra_mail()
         .arg("init")
         .current_dir(&temp_dir)
         .output()?;
@@ -35,10 +41,8 @@ async fn test_emit_subscribe_end_to_end() -> Result<()> {
     }
     fs::create_dir_all(&temp_dir)?;
 
-    let project_root = env::current_dir()?;
-    let binary_path = project_root.join("target/release/hydra-mail");
 
-    Command::new(&binary_path)
+    hydra_mail()
         .arg("init")
         .arg("--daemon")
         .current_dir(&temp_dir)
@@ -46,7 +50,7 @@ async fn test_emit_subscribe_end_to_end() -> Result<()> {
 
     tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
 
-    Command::new(&binary_path)
+    hydra_mail()
         .arg("emit")
         .arg("--type").arg("delta")
         .arg("--channel").arg("test:channel")
@@ -54,7 +58,7 @@ async fn test_emit_subscribe_end_to_end() -> Result<()> {
         .current_dir(&temp_dir)
         .output()?;
 
-    let subscribe_output = Command::new(&binary_path)
+    let subscribe_output = hydra_mail()
         .arg("subscribe")
         .arg("--channel").arg("test:channel")
         .arg("--once")
@@ -68,7 +72,1839 @@ async fn test_emit_subscribe_end_to_end() -> Result<()> {
     // TOON encoding should contain the message content
     assert!(!output_str.trim().is_empty(), "Message should not be empty");
 
-    let _ = Command::new(&binary_path).arg("stop").current_dir(&temp_dir).output()?;
+    let _ = hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
+    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_trace_id_propagates_from_session_env() -> Result<()> {
+    use hydra_mail::{config::Config, message_log::MessageLog, pulse, trace};
+
+    let temp_dir = env::temp_dir().join(format!("hydra_test_trace_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)?;
+
+    hydra_mail()
+        .arg("init")
+        .arg("--daemon")
+        .current_dir(&temp_dir)
+        .output()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    let emit = |channel: &str, trace_env: Option<(&str, &str)>| -> Result<()> {
+        let mut cmd = hydra_mail();
+        cmd.args(["emit", "--type", "delta", "--channel", channel, "--data", "{\"file\":\"a.rs\"}"])
+            .current_dir(&temp_dir)
+            .env_remove("HYDRA_TRACE_ID")
+            .env_remove("HYDRA_SPAN");
+        if let Some((trace_id, span)) = trace_env {
+            cmd.env("HYDRA_TRACE_ID", trace_id).env("HYDRA_SPAN", span);
+        }
+        assert!(cmd.output()?.status.success());
+        Ok(())
+    };
+    // Two channels from inside the session, one from outside it
+    emit("repo:delta", Some(("trace-abc", "build")))?;
+    emit("team:status", Some(("trace-abc", "review")))?;
+    emit("repo:delta", None)?;
+
+    let _ = hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
+
+    let config = Config::load(&temp_dir)?;
+    let entries = MessageLog::open(&temp_dir.join(".hydra/messages.log"))?.replay()?;
+    assert_eq!(entries.len(), 3);
+
+    // Without the env var the pulse is exactly what it used to be
+    let untraced = pulse::decode_pulse(&entries[2].message).expect("pulse decodes");
+    assert!(untraced["metadata"].is_null());
+
+    // Orchestrator history for the same workflow
+    let journal = temp_dir.join(".hydra/orchestrator/events.jsonl");
+    fs::create_dir_all(journal.parent().unwrap())?;
+    fs::write(&journal, format!(
+        "{{\"ts\":\"{}\",\"type\":\"session\",\"event\":\"spawned\",\"session\":\"s1\",\"data\":{{\"trace_id\":\"trace-abc\"}}}}\n",
+        (entries[0].timestamp - chrono::Duration::seconds(1)).to_rfc3339()
+    ))?;
+
+    let found = trace::reconstruct(&entries, config.project_uuid, &journal, "trace-abc");
+    let sources: Vec<&str> = found.iter().map(|e| e.source.as_str()).collect();
+    assert_eq!(sources, vec!["orchestrator", "repo:delta", "team:status"]);
+    assert_eq!(found[1].body["metadata"]["span"], "build");
+    assert_eq!(found[2].body["metadata"]["trace_id"], "trace-abc");
+
+    let output = hydra_mail()
+        .args(["trace", "trace-abc", "--json"])
+        .current_dir(&temp_dir)
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout).lines().count(), 3);
+
+    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_channel_alias_reloaded_on_sighup() -> Result<()> {
+    use hydra_mail::config::Config;
+    use std::io::{BufRead, Write};
+
+    let temp_dir = env::temp_dir().join(format!("hydra_test_alias_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)?;
+
+    hydra_mail()
+        .arg("init")
+        .arg("--daemon")
+        .current_dir(&temp_dir)
+        .output()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+    let before = hydra_mail()
+        .args(["emit", "--type", "delta", "--channel", "repo:delta", "--data", "{\"file\":\"before.rs\"}"])
+        .current_dir(&temp_dir)
+        .output()?;
+    assert!(before.status.success());
+
+    // Rename repo:delta while the daemon is running
+    let mut config = Config::load(&temp_dir)?;
+    config.channels.aliases.insert("repo:delta".into(), "repo:changes".into());
+    config.save(&temp_dir)?;
+    let pid = fs::read_to_string(temp_dir.join(".hydra/daemon.pid"))?;
+    assert!(Command::new("kill").args(["-HUP", pid.trim()]).status()?.success());
+    tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+
+    // The channel moved to its new name, history and all
+    let socket = std::os::unix::net::UnixStream::connect(&config.socket_path)?;
+    writeln!(&socket, "{}", serde_json::json!({"cmd": "list"}))?;
+    let mut resp = String::new();
+    std::io::BufReader::new(&socket).read_line(&mut resp)?;
+    let list: serde_json::Value = serde_json::from_str(&resp)?;
+    assert_eq!(list["channels"], serde_json::json!(["repo:changes"]), "{}", resp);
+    assert_eq!(list["aliases"][0]["alias"], "repo:delta", "{}", resp);
+    assert_eq!(list["aliases"][0]["channel"], "repo:changes", "{}", resp);
+    assert_eq!(list["aliases"][0]["resolves"], "repo:delta → repo:changes", "{}", resp);
+
+    let emit = hydra_mail()
+        .args(["emit", "--type", "delta", "--channel", "repo:delta", "--data", "{\"file\":\"old.rs\"}"])
+        .current_dir(&temp_dir)
+        .env_remove("RUST_LOG")
+        .output()?;
+    assert!(emit.status.success());
+    let stderr = String::from_utf8_lossy(&emit.stderr);
+    assert!(stderr.contains("repo:delta → repo:changes"), "{}", stderr);
+
+    let subscribe = hydra_mail()
+        .args(["subscribe", "--channel", "repo:changes", "--once"])
+        .current_dir(&temp_dir)
+        .output()?;
+    let received = String::from_utf8_lossy(&subscribe.stdout);
+    assert!(received.starts_with("id: "), "{}", received);
+    let history = hydra_mail()
+        .args(["subscribe", "--channel", "repo:changes", "--format", "json"])
+        .current_dir(&temp_dir)
+        .stdout(std::process::Stdio::piped())
+        .spawn()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+    // Nothing else was emitted, so both came in under the old name
+    let _ = Command::new("kill").arg(history.id().to_string()).status();
+    let history = String::from_utf8_lossy(&history.wait_with_output()?.stdout).into_owned();
+    let files: Vec<String> = history
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).map(|pulse| pulse["data"]["file"].to_string()))
+        .collect::<Result<_, _>>()?;
+    assert_eq!(files, vec!["\"before.rs\"", "\"old.rs\""], "{}", history);
+
+    let status = hydra_mail().arg("status").current_dir(&temp_dir).output()?;
+    let stdout = String::from_utf8_lossy(&status.stdout);
+    assert!(stdout.contains("repo:delta → repo:changes - 1 uses"), "{}", stdout);
+
+    let _ = hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
+    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_channel_policy_warns_then_rejects() -> Result<()> {
+    use hydra_mail::config::{Config, Enforcement};
+
+    let temp_dir = env::temp_dir().join(format!("hydra_test_policy_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)?;
+
+    hydra_mail()
+        .arg("init")
+        .arg("--daemon")
+        .current_dir(&temp_dir)
+        .output()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    let emit = |channel: &str| -> Result<std::process::Output> {
+        Ok(hydra_mail()
+            .args(["emit", "--type", "delta", "--channel", channel, "--data", "{\"file\":\"a.rs\"}"])
+            .current_dir(&temp_dir)
+            .env_remove("RUST_LOG")
+            .output()?)
+    };
+
+    // The default policy lets a typo through, with a warning
+    let typo = emit("rep:delta")?;
+    assert!(typo.status.success());
+    let stderr = String::from_utf8_lossy(&typo.stderr);
+    assert!(stderr.contains("rep:delta: unknown namespace"), "{}", stderr);
+    let known = emit("repo:delta")?;
+    assert!(!String::from_utf8_lossy(&known.stderr).contains("unknown namespace"));
+
+    let mut config = Config::load(&temp_dir)?;
+    config.channel_policy.enforce = Enforcement::Reject;
+    config.save(&temp_dir)?;
+    let pid = fs::read_to_string(temp_dir.join(".hydra/daemon.pid"))?;
+    assert!(Command::new("kill").args(["-HUP", pid.trim()]).status()?.success());
+    tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+
+    let rejected = emit("rep:delta")?;
+    assert!(!rejected.status.success());
+    let stderr = String::from_utf8_lossy(&rejected.stderr);
+    assert!(stderr.contains("valid namespaces: repo, team"), "{}", stderr);
+    assert!(emit("repo:delta")?.status.success());
+
+    // Subscribing is never refused, and channels marks the typo
+    let subscribe = hydra_mail()
+        .args(["subscribe", "--channel", "rep:delta", "--once"])
+        .current_dir(&temp_dir)
+        .output()?;
+    assert!(String::from_utf8_lossy(&subscribe.stdout).starts_with("id: "));
+    let channels = hydra_mail().arg("channels").current_dir(&temp_dir).output()?;
+    let stdout = String::from_utf8_lossy(&channels.stdout);
+    let marked: Vec<&str> = stdout.lines().filter(|line| line.ends_with("unknown namespace")).collect();
+    assert_eq!(marked.len(), 1, "{}", stdout);
+    assert!(marked[0].starts_with("rep:delta "), "{}", stdout);
+
+    let _ = hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
+    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_channels_lists_daemon_channels() -> Result<()> {
+    let temp_dir = env::temp_dir().join(format!("hydra_test_channels_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)?;
+
+    hydra_mail()
+        .arg("init")
+        .arg("--daemon")
+        .current_dir(&temp_dir)
+        .output()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    for file in ["a.rs", "b.rs"] {
+        let emit = hydra_mail()
+            .args(["emit", "--type", "delta", "--channel", "repo:delta", "--data"])
+            .arg(format!("{{\"file\":\"{}\"}}", file))
+            .current_dir(&temp_dir)
+            .output()?;
+        assert!(emit.status.success());
+    }
+
+    let channels = hydra_mail().arg("channels").current_dir(&temp_dir).output()?;
+    assert!(channels.status.success());
+    let stdout = String::from_utf8_lossy(&channels.stdout);
+    assert!(stdout.starts_with("CHANNEL"), "{}", stdout);
+    let row = stdout.lines().find(|l| l.starts_with("repo:delta")).expect("repo:delta listed");
+    assert_eq!(row.split_whitespace().collect::<Vec<_>>(), vec!["repo:delta", "0", "2"]);
+
+    let channels = hydra_mail().args(["channels", "--json"]).current_dir(&temp_dir).output()?;
+    let rows: Vec<serde_json::Value> = String::from_utf8_lossy(&channels.stdout)
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    let delta = rows.iter().find(|r| r["channel"] == "repo:delta").expect("repo:delta listed");
+    assert_eq!(delta["subscriber_count"], 0);
+    assert_eq!(delta["replay_buffer_size"], 2);
+
+    let status = hydra_mail().arg("status").current_dir(&temp_dir).output()?;
+    let stdout = String::from_utf8_lossy(&status.stdout);
+    assert!(!stdout.contains("Default topics"), "{}", stdout);
+    assert!(stdout.contains("repo:delta - 2 msgs buffered, 0 subscribers"), "{}", stdout);
+
+    let _ = hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
+    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_subscribe_history_controls() -> Result<()> {
+    let temp_dir = env::temp_dir().join(format!("hydra_test_history_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)?;
+
+    hydra_mail()
+        .arg("init")
+        .arg("--daemon")
+        .current_dir(&temp_dir)
+        .output()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    let emit = |file: &str| -> Result<()> {
+        let emit = hydra_mail()
+            .args(["emit", "--type", "delta", "--channel", "repo:delta", "--data"])
+            .arg(format!("{{\"file\":\"{}\"}}", file))
+            .current_dir(&temp_dir)
+            .output()?;
+        assert!(emit.status.success());
+        Ok(())
+    };
+    for file in ["first.rs", "second.rs", "third.rs"] {
+        emit(file)?;
+    }
+
+    // Messages span several lines, so run each subscriber for a moment and
+    // collect what it printed
+    let subscribe = |args: &[&str]| -> Result<std::process::Child> {
+        Ok(hydra_mail()
+            .args(["subscribe", "--channel", "repo:delta"])
+            .args(args)
+            .current_dir(&temp_dir)
+            .stdout(std::process::Stdio::piped())
+            .spawn()?)
+    };
+    let collect = |mut child: std::process::Child| -> Result<String> {
+        child.kill()?;
+        let output = child.wait_with_output()?;
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+    let future = (chrono::Utc::now() + chrono::Duration::hours(1)).to_rfc3339();
+    let last = subscribe(&["--last", "1"])?;
+    let since = subscribe(&["--since", "1h", "--last", "2"])?;
+    let live = subscribe(&["--no-history"])?;
+    let after = subscribe(&["--since", &future])?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+    emit("live.rs")?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(300)).await;
+
+    let last = collect(last)?;
+    assert!(!last.contains("second.rs") && last.contains("third.rs") && last.contains("live.rs"), "{}", last);
+    let since = collect(since)?;
+    assert!(!since.contains("first.rs") && since.contains("second.rs") && since.contains("third.rs"), "{}", since);
+    // Nothing in the history passes, so only the live message arrives
+    for stdout in [collect(live)?, collect(after)?] {
+        assert!(!stdout.contains("third.rs") && stdout.contains("live.rs"), "{}", stdout);
+    }
+
+    let _ = hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
+    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_subscribe_channel_pattern() -> Result<()> {
+    let temp_dir = env::temp_dir().join(format!("hydra_test_pattern_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)?;
+
+    hydra_mail()
+        .arg("init")
+        .arg("--daemon")
+        .current_dir(&temp_dir)
+        .output()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    let emit = |channel: &str, file: &str| -> Result<()> {
+        let emit = hydra_mail()
+            .args(["emit", "--type", "delta", "--channel", channel, "--data"])
+            .arg(format!("{{\"file\":\"{}\"}}", file))
+            .current_dir(&temp_dir)
+            .output()?;
+        assert!(emit.status.success());
+        Ok(())
+    };
+    emit("repo:delta", "history.rs")?;
+
+    let mut child = hydra_mail()
+        .args(["subscribe", "--channel", "repo:*"])
+        .current_dir(&temp_dir)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+    emit("repo:delta", "live.rs")?;
+    emit("repo:review", "new-channel.rs")?;
+    emit("team:status", "other.rs")?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(300)).await;
+    child.kill()?;
+    let stdout = String::from_utf8_lossy(&child.wait_with_output()?.stdout).into_owned();
+
+    assert_eq!(stdout.matches("==> repo:delta <==").count(), 2, "{}", stdout);
+    assert_eq!(stdout.matches("==> repo:review <==").count(), 1, "{}", stdout);
+    for file in ["history.rs", "live.rs", "new-channel.rs"] {
+        assert!(stdout.contains(file), "{}", stdout);
+    }
+    assert!(!stdout.contains("team:status") && !stdout.contains("other.rs"), "{}", stdout);
+
+    // Patterns are only for subscribing
+    let emit = hydra_mail()
+        .args(["emit", "--type", "delta", "--channel", "repo:*", "--data", "{}"])
+        .current_dir(&temp_dir)
+        .output()?;
+    assert!(!emit.status.success());
+
+    let _ = hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
+    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_subscribe_json_format() -> Result<()> {
+    use base64::Engine as _;
+    use hydra_mail::config::Config;
+    use std::io::{BufRead, Write};
+
+    let temp_dir = env::temp_dir().join(format!("hydra_test_json_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)?;
+
+    hydra_mail()
+        .arg("init")
+        .arg("--daemon")
+        .current_dir(&temp_dir)
+        .output()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    let emit = |file: &str| -> Result<()> {
+        let emit = hydra_mail()
+            .args(["emit", "--type", "delta", "--channel", "repo:delta", "--data"])
+            .arg(format!("{{\"file\":\"{}\"}}", file))
+            .current_dir(&temp_dir)
+            .output()?;
+        assert!(emit.status.success());
+        Ok(())
+    };
+    emit("first.rs")?;
+    // Something that isn't TOON, straight to the socket
+    let config = Config::load(&temp_dir)?;
+    let mut socket = std::os::unix::net::UnixStream::connect(&config.socket_path)?;
+    let data = base64::engine::general_purpose::STANDARD.encode("key: \"unterminated");
+    writeln!(socket, "{}", serde_json::json!({"cmd": "emit", "channel": "repo:delta", "data": data}))?;
+    let mut resp = String::new();
+    std::io::BufReader::new(&socket).read_line(&mut resp)?;
+    assert!(resp.contains("\"ok\""), "{}", resp);
+    emit("second.rs")?;
+
+    let mut child = hydra_mail()
+        .args(["subscribe", "--channel", "repo:delta", "--format", "json"])
+        .current_dir(&temp_dir)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+    child.kill()?;
+    let output = child.wait_with_output()?;
+
+    // One compact pulse per line; the bad message goes to stderr and the
+    // stream carries on past it
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let pulses: Vec<serde_json::Value> = stdout.lines().map(serde_json::from_str).collect::<Result<_, _>>()?;
+    let files: Vec<&str> = pulses.iter().filter_map(|p| p["data"]["file"].as_str()).collect();
+    assert_eq!(files, vec!["first.rs", "second.rs"]);
+    assert_eq!(pulses[0]["channel"], "repo:delta");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("key: \"unterminated"));
+
+    let once = hydra_mail()
+        .args(["subscribe", "--channel", "repo:delta", "--format", "json", "--pretty", "--once", "--last", "1"])
+        .current_dir(&temp_dir)
+        .output()?;
+    let stdout = String::from_utf8_lossy(&once.stdout);
+    assert!(stdout.lines().count() > 1, "{}", stdout);
+    let pulse: serde_json::Value = serde_json::from_str(&stdout)?;
+    assert_eq!(pulse["data"]["file"], "second.rs");
+
+    let _ = hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
+    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_history_dumps_buffer_and_exits() -> Result<()> {
+    let temp_dir = env::temp_dir().join(format!("hydra_test_dump_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)?;
+
+    hydra_mail()
+        .arg("init")
+        .arg("--daemon")
+        .current_dir(&temp_dir)
+        .output()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    let history = |args: &[&str]| -> Result<std::process::Output> {
+        Ok(hydra_mail()
+            .args(["history", "--channel", "repo:delta"])
+            .args(args)
+            .current_dir(&temp_dir)
+            .env_remove("HYDRA_AGENT_ID")
+            .output()?)
+    };
+
+    // An empty channel returns right away instead of waiting
+    let empty = history(&[])?;
+    assert!(empty.status.success());
+    assert!(String::from_utf8_lossy(&empty.stdout).contains("No messages in repo:delta"));
+
+    for file in ["first.rs", "second.rs", "third.rs"] {
+        let emit = hydra_mail()
+            .args(["emit", "--type", "delta", "--channel", "repo:delta", "--data"])
+            .arg(format!("{{\"file\":\"{}\"}}", file))
+            .current_dir(&temp_dir)
+            .output()?;
+        assert!(emit.status.success());
+    }
+
+    let all = history(&[])?;
+    assert!(all.status.success());
+    let stdout = String::from_utf8_lossy(&all.stdout);
+    assert!(["first.rs", "second.rs", "third.rs"].iter().all(|f| stdout.contains(f)), "{}", stdout);
+
+    let last = history(&["--last", "2", "--format", "json"])?;
+    let files: Vec<String> = String::from_utf8_lossy(&last.stdout)
+        .lines()
+        .map(|line| Ok(serde_json::from_str::<serde_json::Value>(line)?["data"]["file"].as_str().unwrap_or("").to_string()))
+        .collect::<Result<_>>()?;
+    assert_eq!(files, vec!["second.rs", "third.rs"]);
+
+    // Reading the history doesn't leave a subscriber behind
+    let channels = hydra_mail()
+        .args(["channels", "--json"])
+        .current_dir(&temp_dir)
+        .output()?;
+    let stats: serde_json::Value = serde_json::from_str(String::from_utf8_lossy(&channels.stdout).trim())?;
+    assert_eq!(stats["subscriber_count"], 0);
+
+    let _ = hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
+    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_tail_prints_history_then_follows() -> Result<()> {
+    let temp_dir = env::temp_dir().join(format!("hydra_test_tail_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)?;
+
+    hydra_mail()
+        .arg("init")
+        .arg("--daemon")
+        .current_dir(&temp_dir)
+        .output()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    let emit = |data: &str| -> Result<()> {
+        let emit = hydra_mail()
+            .args(["emit", "--type", "delta", "--channel", "repo:delta", "--data", data])
+            .current_dir(&temp_dir)
+            .output()?;
+        assert!(emit.status.success());
+        Ok(())
+    };
+    emit(r#"{"action":"updated","target":"old.rs"}"#)?;
+    emit(r#"{"action":"updated","target":"src/main.rs"}"#)?;
+
+    let tail = hydra_mail()
+        .args(["tail", "--channel", "repo:delta", "--last", "1"])
+        .current_dir(&temp_dir)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+    emit(r#"{"action":"fixed","impact":"null check","scope":{"module":"auth"}}"#)?;
+    // Terminal escapes in a message come out escaped
+    emit(r#"{"note":"\u001b[2Jgone","\u009b31m":"red"}"#)?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(300)).await;
+
+    // The daemon's shutdown ends the tail
+    let _ = hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
+    let output = tail.wait_with_output()?;
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // Not a terminal, so no colors; the time comes first
+    let lines: Vec<&str> = stdout.lines().map(|line| line.split_once(' ').map_or(line, |(_, rest)| rest)).collect();
+    assert_eq!(lines, vec![
+        "delta repo:delta action=updated target=src/main.rs",
+        r#"delta repo:delta action=fixed impact="null check" scope.module=auth"#,
+        r#"delta repo:delta note="\u001b[2Jgone" \u{9b}31m=red"#,
+    ], "{}", stdout);
+    assert!(!stdout.contains(['\x1b', '\u{9b}']), "{}", stdout);
+    assert!(stdout.lines().all(|line| line.as_bytes()[2] == b':' && line.as_bytes()[5] == b':'), "{}", stdout);
+
+    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_export_then_import() -> Result<()> {
+    let source = env::temp_dir().join(format!("hydra_test_export_{}", uuid::Uuid::new_v4()));
+    let target = env::temp_dir().join(format!("hydra_test_import_{}", uuid::Uuid::new_v4()));
+    for dir in [&source, &target] {
+        fs::create_dir_all(dir)?;
+        hydra_mail().args(["init", "--daemon"]).current_dir(dir).output()?;
+    }
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    for file in ["a.rs", "b.rs", "c.rs"] {
+        let emit = hydra_mail()
+            .args(["emit", "--type", "delta", "--channel", "repo:delta", "--data"])
+            .arg(format!("{{\"file\":\"{}\"}}", file))
+            .current_dir(&source)
+            .output()?;
+        assert!(emit.status.success());
+    }
+    let export_path = source.join("delta.ndjson");
+    let export = hydra_mail()
+        .args(["export", "--channel", "repo:delta", "--output"])
+        .arg(&export_path)
+        .current_dir(&source)
+        .output()?;
+    assert!(export.status.success(), "{}", String::from_utf8_lossy(&export.stderr));
+    assert!(String::from_utf8_lossy(&export.stdout).contains("Exported 3 message(s)"));
+    let exported: Vec<serde_json::Value> =
+        fs::read_to_string(&export_path)?.lines().map(serde_json::from_str).collect::<Result<_, _>>()?;
+    assert_eq!(exported.iter().map(|p| p["data"]["file"].as_str().unwrap()).collect::<Vec<_>>(), vec!["a.rs", "b.rs", "c.rs"]);
+
+    let import = |input: &std::path::Path, extra: &[&str]| -> Result<std::process::Output> {
+        Ok(hydra_mail()
+            .args(["import", "--channel", "repo:replayed", "--rate", "100/s", "--input"])
+            .arg(input)
+            .args(extra)
+            .current_dir(&target)
+            .output()?)
+    };
+    let history = || -> Result<Vec<serde_json::Value>> {
+        let history = hydra_mail()
+            .args(["history", "--channel", "repo:replayed", "--format", "json"])
+            .current_dir(&target)
+            .output()?;
+        Ok(String::from_utf8_lossy(&history.stdout).lines().map(serde_json::from_str).collect::<Result<_, _>>()?)
+    };
+
+    // One bad line and nothing goes out
+    let broken = target.join("broken.ndjson");
+    fs::write(&broken, format!("{}\nnot json\n", exported[0]))?;
+    let rejected = import(&broken, &[])?;
+    assert!(!rejected.status.success());
+    assert!(String::from_utf8_lossy(&rejected.stderr).contains("line 2"));
+    assert!(history()?.is_empty());
+
+    let imported = import(&export_path, &["--preserve-timestamps"])?;
+    assert!(imported.status.success(), "{}", String::from_utf8_lossy(&imported.stderr));
+    assert!(String::from_utf8_lossy(&imported.stdout).contains("Imported 3 message(s) to repo:replayed, skipped 0"));
+    let replayed = history()?;
+    assert_eq!(replayed.len(), 3);
+    for (pulse, original) in replayed.iter().zip(&exported) {
+        assert_eq!(pulse["channel"], "repo:replayed");
+        assert_eq!(pulse["data"], original["data"]);
+        assert_eq!(pulse["timestamp"], original["timestamp"]);
+    }
+
+    for dir in [&source, &target] {
+        let _ = hydra_mail().arg("stop").current_dir(dir).output()?;
+        let _ = fs::remove_dir_all(dir);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_purge_clears_history() -> Result<()> {
+    use std::io::Write;
+
+    let temp_dir = env::temp_dir().join(format!("hydra_test_purge_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)?;
+
+    hydra_mail()
+        .arg("init")
+        .arg("--daemon")
+        .current_dir(&temp_dir)
+        .output()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    for (channel, data) in [("team:alert", "token=hunter2"), ("team:alert", "oops"), ("repo:delta", "kept")] {
+        let emit = hydra_mail()
+            .args(["emit", "--type", "alert", "--channel", channel, "--data"])
+            .arg(format!("{{\"note\":\"{}\"}}", data))
+            .current_dir(&temp_dir)
+            .output()?;
+        assert!(emit.status.success());
+    }
+    let history = |channel: &str| -> Result<String> {
+        let output = hydra_mail()
+            .args(["history", "--channel", channel])
+            .current_dir(&temp_dir)
+            .output()?;
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+
+    // Anything but "y" at the prompt leaves the history alone
+    let mut declined = hydra_mail()
+        .args(["purge", "--channel", "team:alert"])
+        .current_dir(&temp_dir)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()?;
+    declined.stdin.take().unwrap().write_all(b"n\n")?;
+    assert!(String::from_utf8_lossy(&declined.wait_with_output()?.stdout).contains("Purge cancelled."));
+    assert!(history("team:alert")?.contains("hunter2"));
+
+    let mut live = hydra_mail()
+        .args(["subscribe", "--channel", "team:alert", "--no-history"])
+        .current_dir(&temp_dir)
+        .stdout(std::process::Stdio::piped())
+        .spawn()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+    let purge = hydra_mail()
+        .args(["purge", "--channel", "team:alert", "--yes"])
+        .current_dir(&temp_dir)
+        .output()?;
+    assert!(purge.status.success());
+    assert!(String::from_utf8_lossy(&purge.stdout).contains("team:alert: purged 2 message(s)"));
+    tokio::time::sleep(tokio::time::Duration::from_millis(300)).await;
+    live.kill()?;
+    let live = String::from_utf8_lossy(&live.wait_with_output()?.stdout).into_owned();
+
+    // The subscriber stays attached and hears about the purge, which is all
+    // the history holds now
+    assert!(live.contains("type: purge"), "{}", live);
+    let after = history("team:alert")?;
+    assert!(after.contains("type: purge") && !after.contains("hunter2"), "{}", after);
+    assert!(history("repo:delta")?.contains("kept"));
+
+    let all = hydra_mail()
+        .args(["purge", "--all", "--yes"])
+        .current_dir(&temp_dir)
+        .output()?;
+    let stdout = String::from_utf8_lossy(&all.stdout);
+    assert!(stdout.contains("repo:delta: purged 1 message(s)") && stdout.contains("team:alert: purged 1 message(s)"), "{}", stdout);
+    assert!(!history("repo:delta")?.contains("kept"));
+
+    // Nothing to purge, so no notice; patterns are refused
+    let unknown = hydra_mail()
+        .args(["purge", "--channel", "team:nobody", "--yes"])
+        .current_dir(&temp_dir)
+        .output()?;
+    assert!(String::from_utf8_lossy(&unknown.stdout).contains("Nothing to purge"));
+    assert!(!history("team:nobody")?.contains("type: purge"));
+    let pattern = hydra_mail()
+        .args(["purge", "--channel", "team:*", "--yes"])
+        .current_dir(&temp_dir)
+        .output()?;
+    assert!(!pattern.status.success());
+    assert!(String::from_utf8_lossy(&pattern.stderr).contains("Can't purge a pattern"));
+
+    let _ = hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
+    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_ping_exit_codes() -> Result<()> {
+    use hydra_mail::config::Config;
+
+    let temp_dir = env::temp_dir().join(format!("hydra_test_ping_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)?;
+    let ping = || -> Result<std::process::Output> {
+        Ok(hydra_mail()
+            .args(["ping", "--timeout", "1s"])
+            .current_dir(&temp_dir)
+            .output()?)
+    };
+
+    hydra_mail()
+        .arg("init")
+        .arg("--daemon")
+        .current_dir(&temp_dir)
+        .output()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    let up = ping()?;
+    assert_eq!(up.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&up.stdout).starts_with("ok: daemon"));
+
+    let _ = hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(300)).await;
+    assert_eq!(ping()?.status.code(), Some(1));
+
+    // A socket that accepts connections but never answers: a stuck daemon
+    let socket_path = Config::load(&temp_dir)?.socket_path;
+    let stuck = std::os::unix::net::UnixListener::bind(&socket_path)?;
+    assert_eq!(ping()?.status.code(), Some(2));
+    // A socket file nobody listens on any more
+    drop(stuck);
+    assert!(socket_path.exists());
+    assert_eq!(ping()?.status.code(), Some(2));
+
+    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_subscribe_agent_id_filters_targets() -> Result<()> {
+    let temp_dir = env::temp_dir().join(format!("hydra_test_target_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)?;
+
+    hydra_mail()
+        .arg("init")
+        .arg("--daemon")
+        .current_dir(&temp_dir)
+        .output()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    let emit = |file: &str, target: Option<&str>| -> Result<()> {
+        let mut cmd = hydra_mail();
+        cmd.args(["emit", "--type", "task", "--channel", "team:task", "--data"])
+            .arg(format!("{{\"file\":\"{}\"}}", file))
+            .current_dir(&temp_dir);
+        if let Some(target) = target {
+            cmd.args(["--target", target]);
+        }
+        assert!(cmd.output()?.status.success());
+        Ok(())
+    };
+    let subscribe = |agent: Option<&str>| -> Result<std::process::Child> {
+        let mut cmd = hydra_mail();
+        cmd.args(["subscribe", "--channel", "team:task", "--no-history"])
+            .current_dir(&temp_dir)
+            .env_remove("HYDRA_AGENT_ID")
+            .stdout(std::process::Stdio::piped());
+        if let Some(agent) = agent {
+            // The environment names the agent as well as the flag does
+            cmd.env("HYDRA_AGENT_ID", agent);
+        }
+        Ok(cmd.spawn()?)
+    };
+    let collect = |mut child: std::process::Child| -> Result<String> {
+        child.kill()?;
+        let output = child.wait_with_output()?;
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+
+    let agent1 = subscribe(Some("agent-1"))?;
+    let agent2 = subscribe(Some("agent-2"))?;
+    let monitor = subscribe(None)?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+    emit("for-agent-1.rs", Some("agent-1"))?;
+    emit("for-everyone.rs", None)?;
+    emit("for-agent-3.rs", Some("agent-3"))?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(300)).await;
+
+    let agent1 = collect(agent1)?;
+    assert!(agent1.contains("for-agent-1.rs") && agent1.contains("for-everyone.rs"), "{}", agent1);
+    assert!(!agent1.contains("for-agent-3.rs"), "{}", agent1);
+    let agent2 = collect(agent2)?;
+    assert!(agent2.contains("for-everyone.rs"), "{}", agent2);
+    assert!(!agent2.contains("for-agent-1.rs") && !agent2.contains("for-agent-3.rs"), "{}", agent2);
+    // A subscriber without an agent ID sees everything
+    let monitor = collect(monitor)?;
+    for file in ["for-agent-1.rs", "for-everyone.rs", "for-agent-3.rs"] {
+        assert!(monitor.contains(file), "{}", monitor);
+    }
+
+    // No agent-3 was listening, but the message waits in its history
+    let late = hydra_mail()
+        .args(["subscribe", "--channel", "team:task", "--agent-id", "agent-3"])
+        .current_dir(&temp_dir)
+        .stdout(std::process::Stdio::piped())
+        .spawn()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+    let late = collect(late)?;
+    assert!(late.contains("for-agent-3.rs") && late.contains("for-everyone.rs"), "{}", late);
+    assert!(!late.contains("for-agent-1.rs"), "{}", late);
+
+    let _ = hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
+    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_request_waits_for_matching_reply() -> Result<()> {
+    let temp_dir = env::temp_dir().join(format!("hydra_test_request_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)?;
+
+    hydra_mail()
+        .arg("init")
+        .arg("--daemon")
+        .current_dir(&temp_dir)
+        .output()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    let requester = hydra_mail()
+        .args(["request", "--channel", "team:question", "--data", r#"{"q":"port?"}"#])
+        .args(["--timeout", "10s", "--format", "json"])
+        .current_dir(&temp_dir)
+        .stdout(std::process::Stdio::piped())
+        .spawn()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    // The responder finds the correlation id on the request itself
+    let history = hydra_mail()
+        .args(["history", "--channel", "team:question", "--format", "json"])
+        .current_dir(&temp_dir)
+        .env_remove("HYDRA_AGENT_ID")
+        .output()?;
+    let question: serde_json::Value = serde_json::from_str(String::from_utf8_lossy(&history.stdout).trim())?;
+    assert_eq!(question["data"]["q"], "port?");
+    let id = question["metadata"]["correlation_id"].as_str().unwrap_or_default().to_string();
+    assert_eq!(question["metadata"]["reply_to"], format!("reply:{}", id));
+
+    let reply = |to: &str, data: &str| -> Result<()> {
+        let output = hydra_mail()
+            .args(["reply", "--to", to, "--channel", &format!("reply:{}", id), "--data", data])
+            .current_dir(&temp_dir)
+            .output()?;
+        assert!(output.status.success());
+        Ok(())
+    };
+    // Another request's reply on the same channel doesn't end the wait
+    reply("not-this-one", r#"{"port":1}"#)?;
+    reply(&id, r#"{"port":8080}"#)?;
+
+    let output = requester.wait_with_output()?;
+    assert!(output.status.success());
+    let answer: serde_json::Value = serde_json::from_str(String::from_utf8_lossy(&output.stdout).trim())?;
+    assert_eq!(answer["data"]["port"], 8080);
+    assert_eq!(answer["type"], "reply");
+    assert_eq!(answer["metadata"]["correlation_id"], id.as_str());
+
+    let _ = hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
+    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_request_times_out_and_emit_reply_to() -> Result<()> {
+    let temp_dir = env::temp_dir().join(format!("hydra_test_request_timeout_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)?;
+
+    hydra_mail()
+        .arg("init")
+        .arg("--daemon")
+        .current_dir(&temp_dir)
+        .output()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    let started = std::time::Instant::now();
+    let unanswered = hydra_mail()
+        .args(["request", "--channel", "team:question", "--data", "{}", "--timeout", "1s"])
+        .current_dir(&temp_dir)
+        .output()?;
+    assert_eq!(unanswered.status.code(), Some(1));
+    assert!(unanswered.stdout.is_empty());
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+
+    // emit --reply-to names the channel and prints the id to answer
+    let emit = hydra_mail()
+        .args(["emit", "--type", "ask", "--channel", "team:ask", "--data", "{}", "--reply-to", "team:answers"])
+        .current_dir(&temp_dir)
+        .output()?;
+    assert!(emit.status.success());
+    let stdout = String::from_utf8_lossy(&emit.stdout);
+    let id = stdout
+        .lines()
+        .find_map(|line| line.strip_prefix("Correlation id: "))
+        .unwrap_or_default();
+    assert!(!id.is_empty(), "{}", stdout);
+    let history = hydra_mail()
+        .args(["history", "--channel", "team:ask", "--format", "json"])
+        .current_dir(&temp_dir)
+        .env_remove("HYDRA_AGENT_ID")
+        .output()?;
+    let ask: serde_json::Value = serde_json::from_str(String::from_utf8_lossy(&history.stdout).trim())?;
+    assert_eq!(ask["metadata"]["correlation_id"], id);
+    assert_eq!(ask["metadata"]["reply_to"], "team:answers");
+
+    let _ = hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
+    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_subscribe_callback_per_message() -> Result<()> {
+    let temp_dir = env::temp_dir().join(format!("hydra_test_callback_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)?;
+
+    hydra_mail()
+        .arg("init")
+        .arg("--daemon")
+        .current_dir(&temp_dir)
+        .output()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    for file in ["first.rs", "second.rs"] {
+        hydra_mail()
+            .args(["emit", "--type", "delta", "--channel", "repo:delta", "--data"])
+            .arg(format!("{{\"file\":\"{}\"}}", file))
+            .current_dir(&temp_dir)
+            .output()?;
+    }
+
+    // Every message runs the script, which failing doesn't stop
+    let mut subscriber = hydra_mail()
+        .args(["subscribe", "--channel", "repo:*", "--format", "json", "--concurrency", "2"])
+        .args(["--callback", r#"cat >> pulses.txt; echo "$HYDRA_CHANNEL" >> channels.txt; exit 3"#])
+        .current_dir(&temp_dir)
+        .env_remove("HYDRA_AGENT_ID")
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(1000)).await;
+    subscriber.kill()?;
+    let output = subscriber.wait_with_output()?;
+
+    let mut files: Vec<String> = fs::read_to_string(temp_dir.join("pulses.txt"))?
+        .lines()
+        .map(|line| Ok(serde_json::from_str::<serde_json::Value>(line)?["data"]["file"].as_str().unwrap_or("").to_string()))
+        .collect::<Result<_>>()?;
+    files.sort();
+    assert_eq!(files, vec!["first.rs", "second.rs"]);
+    let channels = fs::read_to_string(temp_dir.join("channels.txt"))?;
+    assert_eq!(channels.lines().collect::<Vec<_>>(), vec!["repo:delta", "repo:delta"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("exit status: 3"));
+
+    // --once runs the script once and exits with its status
+    let once = hydra_mail()
+        .args(["subscribe", "--channel", "repo:delta", "--once", "--callback", "cat > once.txt; exit 7"])
+        .current_dir(&temp_dir)
+        .output()?;
+    assert_eq!(once.status.code(), Some(7));
+    assert!(fs::read_to_string(temp_dir.join("once.txt"))?.contains("first.rs"));
+
+    // A hung script is killed
+    let started = std::time::Instant::now();
+    let hung = hydra_mail()
+        .args(["subscribe", "--channel", "repo:delta", "--once", "--callback", "sleep 30", "--callback-timeout", "1s"])
+        .current_dir(&temp_dir)
+        .output()?;
+    assert_eq!(hung.status.code(), Some(1));
+    assert!(started.elapsed() < std::time::Duration::from_secs(10));
+
+    let _ = hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
+    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_subscribe_count_and_timeouts() -> Result<()> {
+    let temp_dir = env::temp_dir().join(format!("hydra_test_sub_timeout_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)?;
+
+    hydra_mail()
+        .arg("init")
+        .arg("--daemon")
+        .current_dir(&temp_dir)
+        .output()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    let subscribe = |args: &[&str]| -> Result<(std::process::Output, std::time::Duration)> {
+        let started = std::time::Instant::now();
+        let output = hydra_mail()
+            .args(["subscribe", "--channel", "repo:delta", "--format", "json"])
+            .args(args)
+            .current_dir(&temp_dir)
+            .env_remove("HYDRA_AGENT_ID")
+            .output()?;
+        Ok((output, started.elapsed()))
+    };
+    let lines = |output: &std::process::Output| String::from_utf8_lossy(&output.stdout).lines().count();
+
+    // Nothing ever emitted: --once gives up instead of hanging
+    let (empty, took) = subscribe(&["--once", "--timeout", "1s"])?;
+    assert_eq!(empty.status.code(), Some(124));
+    assert!(took < std::time::Duration::from_secs(5));
+
+    for file in ["first.rs", "second.rs", "third.rs"] {
+        hydra_mail()
+            .args(["emit", "--type", "delta", "--channel", "repo:delta", "--data"])
+            .arg(format!("{{\"file\":\"{}\"}}", file))
+            .current_dir(&temp_dir)
+            .output()?;
+    }
+
+    let (counted, _) = subscribe(&["--count", "2"])?;
+    assert!(counted.status.success());
+    assert_eq!(lines(&counted), 2);
+
+    // The history is delivered before either clock starts
+    let (idle, _) = subscribe(&["--idle-timeout", "1s"])?;
+    assert_eq!(idle.status.code(), Some(124));
+    assert_eq!(lines(&idle), 3);
+    let (capped, took) = subscribe(&["--max-duration", "1s", "--idle-timeout", "30s"])?;
+    assert_eq!(capped.status.code(), Some(124));
+    assert_eq!(lines(&capped), 3);
+    assert!(took < std::time::Duration::from_secs(10));
+
+    // Reaching the count first is a success
+    let (enough, _) = subscribe(&["--count", "3", "--timeout", "5s"])?;
+    assert!(enough.status.success());
+
+    let _ = hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
+    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_subscribe_and_history_filters() -> Result<()> {
+    let temp_dir = env::temp_dir().join(format!("hydra_test_filter_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)?;
+
+    hydra_mail()
+        .arg("init")
+        .arg("--daemon")
+        .current_dir(&temp_dir)
+        .output()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    for (r#type, data) in [
+        ("delta", r#"{"file":"src/auth.rs","kind":"edit"}"#),
+        ("status", r#"{"file":"src/auth.rs","kind":"edit"}"#),
+        ("delta", r#"{"file":"src/billing.rs","kind":"edit"}"#),
+        ("delta", r#"{"file":"src/auth.rs","kind":"delete"}"#),
+    ] {
+        hydra_mail()
+            .args(["emit", "--channel", "repo:delta", "--type", r#type, "--data", data])
+            .current_dir(&temp_dir)
+            .output()?;
+    }
+
+    let kinds = |output: &std::process::Output| -> Result<Vec<(String, String)>> {
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| {
+                let pulse: serde_json::Value = serde_json::from_str(line)?;
+                Ok((pulse["type"].as_str().unwrap_or("").to_string(), pulse["data"]["kind"].as_str().unwrap_or("").to_string()))
+            })
+            .collect()
+    };
+
+    // --match flags AND together, and --count only counts what passes
+    let subscribed = hydra_mail()
+        .args(["subscribe", "--channel", "repo:delta", "--format", "json", "--count", "2", "--timeout", "2s"])
+        .args(["--type", "delta", "--match", "data.file=src/auth.rs"])
+        .current_dir(&temp_dir)
+        .env_remove("HYDRA_AGENT_ID")
+        .output()?;
+    assert!(subscribed.status.success());
+    assert_eq!(kinds(&subscribed)?, vec![("delta".into(), "edit".into()), ("delta".into(), "delete".into())]);
+
+    let history = |args: &[&str]| -> Result<std::process::Output> {
+        Ok(hydra_mail()
+            .args(["history", "--channel", "repo:delta", "--format", "json"])
+            .args(args)
+            .current_dir(&temp_dir)
+            .env_remove("HYDRA_AGENT_ID")
+            .output()?)
+    };
+    let both = history(&["--match", "data.file=src/auth.rs", "--match", "data.kind=edit"])?;
+    assert_eq!(kinds(&both)?, vec![("delta".into(), "edit".into()), ("status".into(), "edit".into())]);
+    let billing = history(&["--contains", "billing"])?;
+    assert_eq!(kinds(&billing)?.len(), 1);
+    let none = history(&["--type", "alert"])?;
+    assert!(none.status.success());
+    assert!(none.stdout.is_empty());
+
+    let bad = history(&["--match", "data.file"])?;
+    assert!(!bad.status.success());
+
+    let _ = hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
+    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_idle_channels_are_dropped() -> Result<()> {
+    use hydra_mail::config::Config;
+
+    let temp_dir = env::temp_dir().join(format!("hydra_test_channel_ttl_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)?;
+
+    hydra_mail().arg("init").current_dir(&temp_dir).output()?;
+    let mut config = Config::load(&temp_dir)?;
+    config.limits.channel_ttl_secs = 1;
+    config.save(&temp_dir)?;
+    let mut daemon = hydra_mail()
+        .arg("start")
+        .current_dir(&temp_dir)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    let emit = |channel: &str| -> Result<()> {
+        let output = hydra_mail()
+            .args(["emit", "--type", "status", "--channel", channel, "--data", "{}"])
+            .current_dir(&temp_dir)
+            .output()?;
+        assert!(output.status.success());
+        Ok(())
+    };
+    let buffered = || -> Result<Vec<(String, u64)>> {
+        let output = hydra_mail().args(["channels", "--json"]).current_dir(&temp_dir).output()?;
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| {
+                let row: serde_json::Value = serde_json::from_str(line)?;
+                Ok((row["channel"].as_str().unwrap_or("").to_string(), row["replay_buffer_size"].as_u64().unwrap_or(0)))
+            })
+            .collect()
+    };
+
+    let mut subscriber = hydra_mail()
+        .args(["subscribe", "--channel", "team:watched"])
+        .current_dir(&temp_dir)
+        .stdout(std::process::Stdio::null())
+        .spawn()?;
+    emit("team:idle")?;
+    emit("team:watched")?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(300)).await;
+    assert_eq!(buffered()?, vec![("team:idle".into(), 1), ("team:watched".into(), 1)]);
+
+    // A subscriber keeps its channel; the idle one goes, history and all
+    tokio::time::sleep(tokio::time::Duration::from_millis(2500)).await;
+    assert_eq!(buffered()?, vec![("team:watched".into(), 1)]);
+
+    // and comes back on the next emit
+    emit("team:idle")?;
+    assert_eq!(buffered()?, vec![("team:idle".into(), 1), ("team:watched".into(), 1)]);
+
+    subscriber.kill()?;
+    let _ = subscriber.wait();
+    let _ = hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
+    let _ = daemon.wait();
+    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_agents_presence() -> Result<()> {
+    let temp_dir = env::temp_dir().join(format!("hydra_test_agents_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)?;
+
+    hydra_mail()
+        .arg("init")
+        .arg("--daemon")
+        .current_dir(&temp_dir)
+        .output()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    let agents = || -> Result<Vec<serde_json::Value>> {
+        let output = hydra_mail().args(["agents", "--json"]).current_dir(&temp_dir).output()?;
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).lines().map(|l| Ok(serde_json::from_str(l)?)).collect()
+    };
+    let presence = || -> Result<Vec<String>> {
+        let output = hydra_mail()
+            .args(["history", "--channel", "agent:presence", "--format", "json"])
+            .current_dir(&temp_dir)
+            .env_remove("HYDRA_AGENT_ID")
+            .output()?;
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| {
+                let pulse: serde_json::Value = serde_json::from_str(line)?;
+                Ok(format!("{} {}", pulse["data"]["agent"].as_str().unwrap_or(""), pulse["data"]["status"].as_str().unwrap_or("")))
+            })
+            .collect()
+    };
+    assert!(agents()?.is_empty());
+
+    // Nothing is ever emitted to it, so only the closed socket tells
+    let mut subscriber = hydra_mail()
+        .args(["subscribe", "--channel", "repo:delta", "--agent-id", "builder-1"])
+        .args(["--role", "builder", "--description", "auth refactor"])
+        .current_dir(&temp_dir)
+        .stdout(std::process::Stdio::null())
+        .spawn()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    let listed = agents()?;
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0]["agent_id"], "builder-1");
+    assert_eq!(listed[0]["role"], "builder");
+    assert_eq!(listed[0]["description"], "auth refactor");
+    assert_eq!(listed[0]["channels"], serde_json::json!(["repo:delta"]));
+    assert!(listed[0]["connected_at"].is_string());
+    let table = hydra_mail().arg("agents").current_dir(&temp_dir).output()?;
+    let stdout = String::from_utf8_lossy(&table.stdout);
+    assert!(stdout.lines().any(|l| l.starts_with("builder-1") && l.contains("repo:delta")), "{}", stdout);
+    assert_eq!(presence()?, vec!["builder-1 joined"]);
+
+    subscriber.kill()?;
+    let _ = subscriber.wait();
+    tokio::time::sleep(tokio::time::Duration::from_millis(300)).await;
+    assert!(agents()?.is_empty());
+    assert_eq!(presence()?, vec!["builder-1 joined", "builder-1 left"]);
+
+    let _ = hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
+    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stop_notifies_subscribers() -> Result<()> {
+    let temp_dir = env::temp_dir().join(format!("hydra_test_shutdown_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)?;
+
+    hydra_mail()
+        .arg("init")
+        .arg("--daemon")
+        .current_dir(&temp_dir)
+        .output()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+    let pid: u32 = fs::read_to_string(temp_dir.join(".hydra/daemon.pid"))?.trim().parse()?;
+
+    hydra_mail()
+        .args(["emit", "--type", "delta", "--channel", "repo:delta", "--data", r#"{"file":"a.rs"}"#])
+        .current_dir(&temp_dir)
+        .output()?;
+    let subscriber = hydra_mail()
+        .args(["subscribe", "--channel", "repo:delta", "--format", "json"])
+        .current_dir(&temp_dir)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
+    let pattern_subscriber = hydra_mail()
+        .args(["subscribe", "--channel", "repo:*"])
+        .current_dir(&temp_dir)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    let stop = hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
+    let stdout = String::from_utf8_lossy(&stop.stdout);
+    assert!(stop.status.success());
+    assert!(stdout.contains("terminated gracefully"), "{}", stdout);
+    // stop returns only once the daemon is gone
+    let ps = Command::new("ps").args(["-o", "stat=", "-p", &pid.to_string()]).output()?;
+    let stat = String::from_utf8_lossy(&ps.stdout);
+    assert!(stat.trim().is_empty() || stat.trim_start().starts_with('Z'), "daemon still running: {}", stat);
+
+    // Both saw the notice and exit cleanly, without printing it
+    let output = subscriber.wait_with_output()?;
+    assert!(output.status.success());
+    let messages: Vec<serde_json::Value> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0]["type"], "delta");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Daemon shut down after 1 message(s)"));
+    let output = pattern_subscriber.wait_with_output()?;
+    assert!(output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("daemon_shutdown"));
+
+    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stop_over_socket() -> Result<()> {
+    use hydra_mail::config::Config;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+    let temp_dir = env::temp_dir().join(format!("hydra_test_stop_rpc_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)?;
+
+    hydra_mail()
+        .arg("init")
+        .arg("--daemon")
+        .current_dir(&temp_dir)
+        .output()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+    let hydra_dir = temp_dir.join(".hydra");
+    let socket = Config::load(&temp_dir)?.socket_path;
+    let pid = fs::read_to_string(hydra_dir.join("daemon.pid"))?.trim().to_string();
+    assert_eq!(fs::read_to_string(hydra_dir.join("daemon.token"))?.len(), 32);
+
+    // Without the token the daemon stays up
+    let stream = tokio::net::UnixStream::connect(&socket).await?;
+    let (reader, mut writer) = stream.into_split();
+    writer.write_all(b"{\"cmd\":\"shutdown\",\"token\":\"guess\"}\n").await?;
+    let line = tokio::io::BufReader::new(reader).lines().next_line().await?.unwrap();
+    let resp: serde_json::Value = serde_json::from_str(&line)?;
+    assert_eq!(resp["status"], "error");
+
+    // A pid the signal can't reach, as from another pid namespace
+    fs::write(hydra_dir.join("daemon.pid"), "999999")?;
+    let stop = hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
+    let stdout = String::from_utf8_lossy(&stop.stdout);
+    assert!(stop.status.success());
+    assert!(stdout.contains(&format!("Daemon (PID: {}) terminated gracefully", pid)), "{}", stdout);
+    assert!(!socket.exists());
+    assert!(!hydra_dir.join("daemon.token").exists());
+
+    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stop_falls_back_on_stale_socket() -> Result<()> {
+    use hydra_mail::config::Config;
+
+    let temp_dir = env::temp_dir().join(format!("hydra_test_stop_stale_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)?;
+
+    hydra_mail()
+        .arg("init")
+        .arg("--daemon")
+        .current_dir(&temp_dir)
+        .output()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+    let hydra_dir = temp_dir.join(".hydra");
+    let socket = Config::load(&temp_dir)?.socket_path;
+    let pid = fs::read_to_string(hydra_dir.join("daemon.pid"))?.trim().to_string();
+
+    // Killed outright, the daemon leaves its socket behind with no one on it
+    Command::new("kill").args(["-KILL", &pid]).output()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+    assert!(socket.exists());
+
+    let stop = hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
+    assert!(stop.status.success(), "{}", String::from_utf8_lossy(&stop.stdout));
+    assert!(String::from_utf8_lossy(&stop.stderr).contains("falling back to SIGTERM"));
+    assert!(!socket.exists(), "socket left behind");
+    for file in ["daemon.pid", "daemon.token"] {
+        assert!(!hydra_dir.join(file).exists(), "{} left behind", file);
+    }
+
+    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_socket_dir_and_hydra_socket_override() -> Result<()> {
+    use hydra_mail::config::Config;
+
+    let temp_dir = env::temp_dir().join(format!("hydra_test_socket_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)?;
+    let runtime_dir = temp_dir.join("run");
+
+    hydra_mail()
+        .args(["init", "--daemon", "--socket-dir"])
+        .arg(&runtime_dir)
+        .current_dir(&temp_dir)
+        .output()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+    let config = Config::load(&temp_dir)?;
+    let socket = runtime_dir.canonicalize()?.join(format!("{}.sock", config.project_uuid));
+    assert_eq!(config.socket_path, socket);
+    assert!(socket.exists());
+    let status = hydra_mail().arg("status").current_dir(&temp_dir).output()?;
+    assert!(String::from_utf8_lossy(&status.stdout).contains("(from socket_dir in config.toml)"));
+    hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
+    assert!(!socket.exists());
+
+    // HYDRA_SOCKET moves it for the daemon and the clients alike
+    let custom = temp_dir.join("custom.sock");
+    let mut daemon = hydra_mail()
+        .arg("start")
+        .current_dir(&temp_dir)
+        .env("HYDRA_SOCKET", &custom)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+    assert!(custom.exists());
+    assert!(!socket.exists());
+    let ping = |hydra_socket: Option<&std::path::Path>| -> Result<bool> {
+        let mut command = hydra_mail();
+        command.arg("ping").current_dir(&temp_dir);
+        if let Some(path) = hydra_socket {
+            command.env("HYDRA_SOCKET", path);
+        }
+        Ok(command.output()?.status.success())
+    };
+    assert!(ping(Some(&custom))?);
+    assert!(!ping(None)?);
+    let status = hydra_mail().arg("status").current_dir(&temp_dir).env("HYDRA_SOCKET", &custom).output()?;
+    assert!(String::from_utf8_lossy(&status.stdout).contains("(from HYDRA_SOCKET)"));
+
+    let stop = hydra_mail().arg("stop").current_dir(&temp_dir).env("HYDRA_SOCKET", &custom).output()?;
+    assert!(stop.status.success());
+    let _ = daemon.wait();
+    assert!(!custom.exists());
+
+    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_hooks_run_on_matching_emits() -> Result<()> {
+    use hydra_mail::config::{Config, HookConfig};
+
+    let temp_dir = env::temp_dir().join(format!("hydra_test_hooks_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)?;
+
+    hydra_mail().arg("init").current_dir(&temp_dir).output()?;
+    let mut config = Config::load(&temp_dir)?;
+    let hook = |channel_pattern: &str, command: &str| HookConfig {
+        channel_pattern: channel_pattern.into(),
+        command: command.into(),
+        max_concurrent: 2,
+        timeout_secs: 5,
+    };
+    config.hooks = vec![hook("team:alert", "cat >> alerts.jsonl"), hook("team:*", "exit 1")];
+    config.save(&temp_dir)?;
+
+    let dry_run = hydra_mail().args(["hooks", "test", "team:alert"]).current_dir(&temp_dir).output()?;
+    let stdout = String::from_utf8_lossy(&dry_run.stdout);
+    assert!(stdout.starts_with("2 hook(s) would run for team:alert:"), "{}", stdout);
+    assert!(stdout.contains("team:* → exit 1 (2 at a time, timeout 5s)"), "{}", stdout);
+    let dry_run = hydra_mail().args(["hooks", "test", "repo:delta"]).current_dir(&temp_dir).output()?;
+    assert!(String::from_utf8_lossy(&dry_run.stdout).starts_with("No hooks would run for repo:delta"));
+    // A dry run runs nothing
+    assert!(!temp_dir.join("alerts.jsonl").exists());
+
+    let mut daemon = hydra_mail()
+        .arg("start")
+        .current_dir(&temp_dir)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    for (channel, message) in [("team:alert", "disk full"), ("repo:delta", "unrelated")] {
+        let emit = hydra_mail()
+            .args(["emit", "--type", "alert", "--channel", channel, "--data"])
+            .arg(format!("{{\"message\":\"{}\"}}", message))
+            .current_dir(&temp_dir)
+            .output()?;
+        // The failing hook doesn't fail the emit
+        assert!(emit.status.success());
+    }
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    let alerts = fs::read_to_string(temp_dir.join("alerts.jsonl"))?;
+    let lines: Vec<&str> = alerts.lines().collect();
+    assert_eq!(lines.len(), 1, "{}", alerts);
+    let pulse: serde_json::Value = serde_json::from_str(lines[0])?;
+    assert_eq!(pulse["channel"], "team:alert");
+    assert_eq!(pulse["data"]["message"], "disk full");
+
+    let status = hydra_mail().arg("status").current_dir(&temp_dir).output()?;
+    let stdout = String::from_utf8_lossy(&status.stdout);
+    assert!(stdout.contains("team:alert → cat >> alerts.jsonl - 1 runs, 0 failed"), "{}", stdout);
+    assert!(stdout.contains("team:* → exit 1 - 1 runs, 1 failed"), "{}", stdout);
+
+    let _ = hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
+    let _ = daemon.wait();
+    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_rate_limits_per_channel() -> Result<()> {
+    use base64::Engine;
+    use hydra_mail::config::Config;
+    use std::io::{BufRead, Write};
+
+    let temp_dir = env::temp_dir().join(format!("hydra_test_rate_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)?;
+
+    hydra_mail().arg("init").current_dir(&temp_dir).output()?;
+    let mut config = Config::load(&temp_dir)?;
+    config.limits.rate_limits.insert("team:*".into(), "2/s".parse()?);
+    config.save(&temp_dir)?;
+    let mut daemon = hydra_mail()
+        .arg("start")
+        .current_dir(&temp_dir)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    // One connection, so one set of windows
+    let socket = std::os::unix::net::UnixStream::connect(&config.socket_path)?;
+    let mut reader = std::io::BufReader::new(socket.try_clone()?);
+    let mut emit = |channel: &str| -> Result<serde_json::Value> {
+        let data = base64::engine::general_purpose::STANDARD.encode("type: status");
+        writeln!(&socket, "{}", serde_json::json!({"cmd": "emit", "channel": channel, "data": data}))?;
+        let mut resp = String::new();
+        reader.read_line(&mut resp)?;
+        Ok(serde_json::from_str(&resp)?)
+    };
+    assert_eq!(emit("team:alert")?["status"], "ok");
+    assert_eq!(emit("team:alert")?["status"], "ok");
+    let limited = emit("team:alert")?;
+    assert_eq!(limited["status"], "error");
+    assert!(limited["msg"].as_str().unwrap().contains("team:alert: 2/s"), "{}", limited);
+    let retry_after = limited["retry_after_ms"].as_u64().unwrap();
+    assert!(retry_after > 0 && retry_after <= 1000, "{}", limited);
+
+    // The chatty channel doesn't hold up the others
+    assert_eq!(emit("team:status")?["status"], "ok");
+    for _ in 0..10 {
+        assert_eq!(emit("repo:delta")?["status"], "ok");
+    }
+    tokio::time::sleep(tokio::time::Duration::from_millis(retry_after)).await;
+    assert_eq!(emit("team:alert")?["status"], "ok");
+
+    let channels = hydra_mail().args(["channels", "--json"]).current_dir(&temp_dir).output()?;
+    let rows: Vec<serde_json::Value> = String::from_utf8_lossy(&channels.stdout)
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    let rate_limited = |name: &str| rows.iter().find(|r| r["channel"] == name).map(|r| r["rate_limited"].clone());
+    assert_eq!(rate_limited("team:alert"), Some(serde_json::json!(1)));
+    assert_eq!(rate_limited("repo:delta"), Some(serde_json::json!(0)));
+
+    drop(socket);
+
+    // A batch shares one connection, so it runs into the limit; --retry waits
+    // it out instead of failing the line
+    let batch = |retry: bool| -> Result<String> {
+        let mut command = hydra_mail();
+        command.args(["emit", "--batch", "--type", "status", "--channel", "team:alert"]);
+        if retry {
+            command.arg("--retry");
+        }
+        let mut child = command
+            .current_dir(&temp_dir)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::null())
+            .spawn()?;
+        child.stdin.take().unwrap().write_all("{}\n".repeat(3).as_bytes())?;
+        Ok(String::from_utf8_lossy(&child.wait_with_output()?.stdout).into_owned())
+    };
+    let stdout = batch(false)?;
+    assert!(stdout.contains("line 3: failed: Rate limit"), "{}", stdout);
+    let stdout = batch(true)?;
+    for line in 1..=3 {
+        assert!(stdout.contains(&format!("line {}: ok (team:alert)", line)), "{}", stdout);
+    }
+    let channels = hydra_mail().args(["channels", "--json"]).current_dir(&temp_dir).output()?;
+    let alert: serde_json::Value = String::from_utf8_lossy(&channels.stdout)
+        .lines()
+        .map(serde_json::from_str::<serde_json::Value>)
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .find(|r| r["channel"] == "team:alert")
+        .unwrap();
+    // Once above, once without --retry and once before the retry
+    assert_eq!(alert["rate_limited"], 3);
+
+    let _ = hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
+    let _ = daemon.wait();
+    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_replay_ttl_withholds_old_messages() -> Result<()> {
+    use hydra_mail::config::Config;
+
+    let temp_dir = env::temp_dir().join(format!("hydra_test_replay_ttl_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)?;
+
+    hydra_mail().arg("init").current_dir(&temp_dir).output()?;
+    let mut config = Config::load(&temp_dir)?;
+    config.limits.replay_ttl_secs = 1;
+    config.limits.replay_ttls.insert("team:*".into(), 0);
+    config.save(&temp_dir)?;
+    let mut daemon = hydra_mail()
+        .arg("start")
+        .current_dir(&temp_dir)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    for channel in ["repo:delta", "team:status"] {
+        let emit = hydra_mail()
+            .args(["emit", "--channel", channel, "--type", "status", "--data", r#"{"n":1}"#])
+            .current_dir(&temp_dir)
+            .output()?;
+        assert!(emit.status.success());
+    }
+    let history = |channel: &str| -> Result<String> {
+        let out = hydra_mail().args(["history", "--channel", channel]).current_dir(&temp_dir).output()?;
+        Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+    };
+    assert!(!history("repo:delta")?.contains("No messages"));
+
+    tokio::time::sleep(tokio::time::Duration::from_millis(1500)).await;
+    assert!(history("repo:delta")?.contains("No messages in repo:delta"));
+    // team:* keeps its messages for good
+    assert!(!history("team:status")?.contains("No messages"));
+
+    let channels = hydra_mail().args(["channels", "--json"]).current_dir(&temp_dir).output()?;
+    let rows: Vec<serde_json::Value> = String::from_utf8_lossy(&channels.stdout)
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    let expired = |name: &str| rows.iter().find(|r| r["channel"] == name).map(|r| r["expired"].clone());
+    assert_eq!(expired("repo:delta"), Some(serde_json::json!(1)));
+    assert_eq!(expired("team:status"), Some(serde_json::json!(0)));
+
+    let _ = hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
+    let _ = daemon.wait();
+    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_slow_subscriber_gets_lag_notice() -> Result<()> {
+    use base64::Engine as _;
+    use hydra_mail::config::Config;
+    use std::io::{BufRead, Write};
+
+    let temp_dir = env::temp_dir().join(format!("hydra_test_lag_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)?;
+
+    hydra_mail().arg("init").arg("--daemon").current_dir(&temp_dir).output()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+    let config = Config::load(&temp_dir)?;
+
+    // A subscriber that stops reading
+    let mut subscriber = std::os::unix::net::UnixStream::connect(&config.socket_path)?;
+    writeln!(subscriber, "{}", serde_json::json!({"cmd": "subscribe", "channel": "repo:delta", "framed": true}))?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+
+    // Far more than the socket buffers and the channel capacity hold
+    let mut emitter = std::os::unix::net::UnixStream::connect(&config.socket_path)?;
+    let mut replies = std::io::BufReader::new(emitter.try_clone()?);
+    let padding = "x".repeat(1000);
+    for n in 0..3000 {
+        let data = base64::engine::general_purpose::STANDARD.encode(format!("n: {}\npad: {}", n, padding));
+        writeln!(emitter, "{}", serde_json::json!({"cmd": "emit", "channel": "repo:delta", "data": data}))?;
+        let mut resp = String::new();
+        replies.read_line(&mut resp)?;
+        assert!(resp.contains("\"ok\""), "{}", resp);
+    }
+
+    // Catching up, it is told what it missed and goes on with the rest
+    subscriber.set_read_timeout(Some(std::time::Duration::from_secs(5)))?;
+    let mut lines = std::io::BufReader::new(&subscriber).lines();
+    let notice = lines
+        .by_ref()
+        .map_while(|line| line.ok())
+        .find_map(|line| serde_json::from_str::<serde_json::Value>(&line).ok().filter(|v| v.is_object()))
+        .expect("no lag notice");
+    assert_eq!(notice["status"], "lagged");
+    assert_eq!(notice["channel"], "repo:delta");
+    let missed = notice["missed"].as_u64().unwrap();
+    let resume_seq = notice["resume_seq"].as_u64().unwrap();
+    assert!(missed > 0 && resume_seq > missed, "{}", notice);
+    let next: String = serde_json::from_str(&lines.next().unwrap()?)?;
+    // Sequence numbers start at 1, the emits above at 0
+    assert!(next.starts_with(&format!("n: {}\n", resume_seq - 1)), "{}", next);
+
+    let stats = hydra_mail().args(["channels", "--json"]).current_dir(&temp_dir).output()?;
+    let row: serde_json::Value = serde_json::from_str(String::from_utf8_lossy(&stats.stdout).trim())?;
+    assert_eq!(row["latest_seq"], 3000);
+
+    drop(lines);
+    drop(subscriber);
+    let _ = hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
+    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mail_client_round_trip() -> Result<()> {
+    use hydra_mail::client::{MailClient, Refused, SubscribeOptions};
+    use serde_json::{json, Value};
+
+    let temp_dir = env::temp_dir().join(format!("hydra_test_client_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)?;
+
+    hydra_mail().args(["init", "--daemon"]).current_dir(&temp_dir).output()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    let client = MailClient::connect(&temp_dir).await?;
+    let ack = client.emit("team:status", "status", json!({"n": 1}), Value::Null).await?;
+    assert_eq!((ack.channel.as_str(), ack.receivers, ack.scheduled), ("team:status", 0, false));
+
+    // History first, then live pulses, decoded
+    let mut stream = client.subscribe("team:*", SubscribeOptions::default()).await?;
+    let first = stream.next().await.expect("history pulse");
+    assert_eq!((first["channel"].as_str(), &first["data"]["n"]), (Some("team:status"), &json!(1)));
+    let ack = client.emit_blocking("team:alert", "alert", json!({"n": 2}), json!({"target": "agent-1"}))?;
+    assert_eq!(ack.channel, "team:alert");
+    let second = tokio::time::timeout(tokio::time::Duration::from_secs(5), stream.next()).await?.expect("live pulse");
+    assert_eq!((second["type"].as_str(), &second["metadata"]["target"]), (Some("alert"), &json!("agent-1")));
+
+    let history = client.history("team:*").await?;
+    assert_eq!(history.len(), 2);
+
+    // One connection for a run of requests; a refusal doesn't end it
+    let mut connection = client.connection().await?;
+    let pulse = hydra_mail::pulse::new_pulse("status", "team:status", json!({"n": 3}), Value::Null);
+    let ack = connection.call(&hydra_mail::client::EmitRequest::new("team:status", &pulse)?).await?;
+    assert_eq!(ack.channel, "team:status");
+    let pattern = hydra_mail::client::EmitRequest::new("team:*", &pulse)?;
+    assert!(connection.call(&pattern).await.unwrap_err().downcast_ref::<Refused>().is_some());
+    assert_eq!(connection.call(&hydra_mail::client::HistoryRequest { channel: "team:status".into(), ..Default::default() }).await?.messages.len(), 2);
+    drop(connection);
+    let third = tokio::time::timeout(tokio::time::Duration::from_secs(5), stream.next()).await?.expect("live pulse");
+    assert_eq!(third["data"]["n"], 3);
+
+    // The daemon's refusals are told apart from connection failures
+    let err = client.emit("team:*", "status", json!({}), Value::Null).await.unwrap_err();
+    assert!(err.downcast_ref::<Refused>().is_some_and(|r| r.msg.contains("pattern")), "{:#}", err);
+
+    // The stream ends when the daemon shuts down
+    let _ = hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
+    assert_eq!(tokio::time::timeout(tokio::time::Duration::from_secs(5), stream.next()).await?, None);
+    assert!(client.ping().await.unwrap_err().downcast_ref::<Refused>().is_none());
+    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_emit_batch_reports_each_line() -> Result<()> {
+    use std::io::Write;
+
+    let temp_dir = env::temp_dir().join(format!("hydra_test_batch_{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&temp_dir)?;
+
+    hydra_mail().args(["init", "--daemon"]).current_dir(&temp_dir).output()?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    let batch = |input: String| -> Result<std::process::Output> {
+        let mut child = hydra_mail()
+            .args(["emit", "--batch", "--channel", "repo:delta", "--type", "delta"])
+            .current_dir(&temp_dir)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()?;
+        child.stdin.take().unwrap().write_all(input.as_bytes())?;
+        Ok(child.wait_with_output()?)
+    };
+
+    let input = [
+        r#"{"file":"a.rs"}"#.to_string(),
+        String::new(),
+        r#"{"_channel":"team:status","_type":"status","ok":true}"#.to_string(),
+        "not json".to_string(),
+        format!(r#"{{"blob":"{}"}}"#, "x".repeat(20_000)),
+        r#"{"_channel":"repo:*"}"#.to_string(),
+        r#"{"file":"b.rs"}"#.to_string(),
+    ]
+    .join("\n");
+    let output = batch(input)?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success(), "{}", stdout);
+    assert!(stdout.contains("line 1: ok (repo:delta)"), "{}", stdout);
+    assert!(stdout.contains("line 3: ok (team:status)"), "{}", stdout);
+    assert!(stdout.contains("line 4: failed: Invalid JSON"), "{}", stdout);
+    // Too large on its own, without spoiling the rest of the batch
+    assert!(stdout.contains("line 5: failed: Message too large"), "{}", stdout);
+    assert!(stdout.contains("line 6: failed: Cannot emit to a channel pattern"), "{}", stdout);
+    assert!(stdout.contains("line 7: ok (repo:delta)"), "{}", stdout);
+    assert!(!stdout.contains("line 2:"), "{}", stdout);
+
+    let history = hydra_mail()
+        .args(["history", "--channel", "team:status", "--format", "json"])
+        .current_dir(&temp_dir)
+        .output()?;
+    let pulse: serde_json::Value = serde_json::from_str(String::from_utf8_lossy(&history.stdout).trim())?;
+    assert_eq!((pulse["type"].as_str(), &pulse["data"]), (Some("status"), &serde_json::json!({"ok": true})));
+    let history = hydra_mail().args(["history", "--channel", "repo:delta"]).current_dir(&temp_dir).output()?;
+    assert_eq!(String::from_utf8_lossy(&history.stdout).matches("type: delta").count(), 2);
+
+    let output = batch("{\"n\":1}\n{\"n\":2}\n".to_string())?;
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Emitted 2 message(s)"));
+
+    let _ = hydra_mail().arg("stop").current_dir(&temp_dir).output()?;
     let _ = fs::remove_dir_all(&temp_dir);
     Ok(())
 }
@@ -0,0 +1,94 @@
+//! Replay history surviving a daemon restart through `.hydra/messages.log`
+//!
+//! The message log and channel map are process-wide, so these run in their
+//! own test binary and restart the daemon the way `start` does: drop every
+//! channel, then restore from the log.
+
+use anyhow::Result;
+use hydra_mail::channels;
+use hydra_mail::message_log::MessageLog;
+use tempfile::TempDir;
+use uuid::Uuid;
+
+/// Stop logging and forget every channel, as a daemon exiting does
+async fn stop_daemon() {
+    channels::set_message_log_path(None);
+    channels::clear_all_channels().await;
+}
+
+#[tokio::test]
+async fn test_history_survives_restart() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let log_path = temp_dir.path().join("messages.log");
+    let project_uuid = Uuid::new_v4();
+
+    // First run: nothing to restore yet
+    assert_eq!(channels::restore_message_log(&log_path).await?, 0);
+    for i in 0..3 {
+        channels::emit_and_store(project_uuid, "repo:delta", format!("delta{}", i)).await;
+    }
+    channels::emit_and_store(project_uuid, "team:status", "status".to_string()).await;
+    stop_daemon().await;
+
+    // Second run: a new subscriber gets the history of the first
+    assert_eq!(channels::restore_message_log(&log_path).await?, 4);
+    let (_rx, history) = channels::subscribe_broadcast(project_uuid, "repo:delta").await;
+    assert_eq!(history, vec!["delta0", "delta1", "delta2"]);
+    let (_rx, history) = channels::subscribe_broadcast(project_uuid, "team:status").await;
+    assert_eq!(history, vec!["status"]);
+
+    // Replaying doesn't log the messages again; new emits are logged
+    channels::emit_and_store(project_uuid, "repo:delta", "delta3".to_string()).await;
+    let logged: Vec<String> = MessageLog::open(&log_path)?.replay()?.into_iter().map(|e| e.message).collect();
+    assert_eq!(logged, vec!["delta0", "delta1", "delta2", "status", "delta3"]);
+
+    // Third run: the replay fills each buffer up to its capacity only
+    for i in 4..150 {
+        channels::emit_and_store(project_uuid, "repo:delta", format!("delta{}", i)).await;
+    }
+    stop_daemon().await;
+    channels::restore_message_log(&log_path).await?;
+    let (_rx, history) = channels::subscribe_broadcast(project_uuid, "repo:delta").await;
+    assert_eq!(history.len(), hydra_mail::constants::REPLAY_BUFFER_CAPACITY);
+    assert_eq!(history.last().map(String::as_str), Some("delta149"));
+
+    // Compaction keeps what a restart would restore, and logging goes on
+    let size = std::fs::metadata(&log_path)?.len();
+    assert!(!channels::compact_message_log(100, size)?);
+    assert!(channels::compact_message_log(100, 0)?);
+    channels::emit_and_store(project_uuid, "repo:delta", "after".to_string()).await;
+    let entries = MessageLog::open(&log_path)?.replay()?;
+    assert_eq!(entries.len(), 100 + 1 + 1);
+    assert_eq!(entries.last().map(|e| e.message.as_str()), Some("after"));
+
+    // A purged channel stays empty across a restart; the others don't lose anything
+    assert_eq!(channels::purge(project_uuid, "team:status").await?, 1);
+    channels::emit_and_store(project_uuid, "repo:delta", "after purge".to_string()).await;
+    stop_daemon().await;
+    assert_eq!(channels::restore_message_log(&log_path).await?, 100 + 1 + 1);
+    let (_rx, history) = channels::subscribe_broadcast(project_uuid, "team:status").await;
+    assert!(history.is_empty());
+    let (_rx, history) = channels::subscribe_broadcast(project_uuid, "repo:delta").await;
+    assert_eq!(history.last().map(String::as_str), Some("after purge"));
+
+    // Replies only matter to a request waiting right now, so they aren't logged
+    channels::restore_message_log(&log_path).await?;
+    channels::emit_and_store(project_uuid, "reply:3f6c0b9e", "answer".to_string()).await;
+    let (_rx, history) = channels::subscribe_broadcast(project_uuid, "reply:3f6c0b9e").await;
+    assert_eq!(history, vec!["answer"]);
+    let logged = MessageLog::open(&log_path)?.replay()?;
+    assert!(logged.iter().all(|e| !e.channel.starts_with("reply:")));
+
+    // The log stamps a message with the time it was buffered at, so a purge
+    // cuts buffer and log at the same point
+    channels::emit_and_store(project_uuid, "team:status", "stamped".to_string()).await;
+    let stamped = MessageLog::open(&log_path)?.replay()?.pop().map(|e| e.timestamp).unwrap();
+    let since = |at| channels::HistoryFilter { last: None, since: Some(at) };
+    let (history, _) = channels::history(project_uuid, "team:status", None, &since(stamped)).await;
+    assert_eq!(history.len(), 1);
+    let after = stamped + chrono::Duration::nanoseconds(1);
+    assert!(channels::history(project_uuid, "team:status", None, &since(after)).await.0.is_empty());
+
+    stop_daemon().await;
+    Ok(())
+}
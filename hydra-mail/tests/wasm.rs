@@ -0,0 +1,63 @@
+//! Runs the pulse fixture vectors through the wasm exports.
+//!
+//! `scripts/build-wasm.sh test` (wasm-pack test --node --features wasm).
+//! Uses the same vectors as the native `pulse` tests so the two builds can't
+//! drift apart.
+#![cfg(all(target_arch = "wasm32", feature = "wasm"))]
+
+use hydra_mail::wasm::{decode_pulse, encode_pulse, evaluate_filter, parse_well_known, verify_pulse};
+use serde_json::Value;
+use wasm_bindgen_test::*;
+
+const FIXTURES: &str = include_str!("fixtures/pulses.json");
+
+fn vectors() -> Vec<Value> {
+    serde_json::from_str(FIXTURES).unwrap()
+}
+
+#[wasm_bindgen_test]
+fn test_decode_fixture_vectors() {
+    for vector in vectors() {
+        let decoded: Value = serde_json::from_str(&decode_pulse(vector["toon"].as_str().unwrap()).unwrap()).unwrap();
+        assert_eq!(decoded, vector["pulse"], "decode {}", vector["name"]);
+    }
+}
+
+#[wasm_bindgen_test]
+fn test_encode_fixture_vectors() {
+    for vector in vectors() {
+        let encoded = encode_pulse(&vector["pulse"].to_string()).unwrap();
+        assert_eq!(encoded, vector["toon"].as_str().unwrap(), "encode {}", vector["name"]);
+    }
+}
+
+#[wasm_bindgen_test]
+fn test_parse_well_known() {
+    assert_eq!(parse_well_known("session:spawned").unwrap(), r#"{"kind":"session","event":"spawned"}"#);
+    assert_eq!(parse_well_known("team:alert").unwrap(), r#"{"kind":"team_alert"}"#);
+    assert!(parse_well_known("custom:builds").is_none());
+}
+
+#[wasm_bindgen_test]
+fn test_verify_fixture_vectors() {
+    for vector in vectors() {
+        let toon = vector["toon"].as_str().unwrap();
+        let verified: Value = serde_json::from_str(&verify_pulse(toon, None).unwrap()).unwrap();
+        assert_eq!(verified, vector["pulse"], "verify {}", vector["name"]);
+        if let Some(key) = vector["key"].as_str() {
+            assert!(verify_pulse(toon, Some(key.to_string())).is_ok(), "signed {}", vector["name"]);
+            assert!(verify_pulse(toon, Some("other key".to_string())).is_err(), "wrong key {}", vector["name"]);
+        }
+    }
+    assert!(verify_pulse("id: q-1\ntype: delta", None).is_err());
+}
+
+#[wasm_bindgen_test]
+fn test_evaluate_filter() {
+    let toon = vectors()[0]["toon"].as_str().unwrap().to_string();
+    assert!(evaluate_filter("{}", &toon).unwrap());
+    assert!(evaluate_filter(r#"{"type": "delta", "match": ["data.target=src/auth.rs"]}"#, &toon).unwrap());
+    assert!(!evaluate_filter(r#"{"type": "status"}"#, &toon).unwrap());
+    assert!(!evaluate_filter(r#"{"contains": "billing"}"#, &toon).unwrap());
+    assert!(evaluate_filter(r#"{"match": ["data.target"]}"#, &toon).is_err());
+}
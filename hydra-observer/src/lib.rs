@@ -0,0 +1,5 @@
+//! Hydra Observer - HydraMail integration for Mascots
+//!
+//! Renderer-independent parts of the observer, used by the binary.
+
+pub mod surfaces;
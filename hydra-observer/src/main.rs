@@ -7,7 +7,9 @@ mod hydra_provider;
 
 use anyhow::Result;
 use clap::Parser;
-use tracing::info;
+use std::path::PathBuf;
+use hydra_observer::surfaces::{Appearance, OutputId, OutputInfo, Rect};
+use tracing::{debug, info};
 use tracing_subscriber::EnvFilter;
 
 #[derive(Parser, Debug)]
@@ -53,13 +55,44 @@ fn main() -> Result<()> {
     // Load mascots configuration
     let config = mascots::Config::load(cli.config.as_deref())?;
     info!(?config, "Loaded configuration");
+    let appearance = match cli.config.clone().or_else(default_config_path) {
+        Some(path) => Appearance::load(&path)?,
+        None => Appearance::default(),
+    };
+    info!(?appearance, "Loaded surface settings");
 
     // Detect platform
     let platform = detect_platform(cli.platform)?;
     info!(?platform, "Detected platform");
 
-    // Run the mascots application
-    mascots::run(config, platform)
+    // Run the mascots application. The renderer reports its outputs and the
+    // attachment target on monitor hotplug and on every picker-refresh /
+    // attachment-follow tick, and creates or destroys the layer surfaces the
+    // manager asks for; surfaces that stay keep their render state.
+    let mut surfaces = appearance.surface_manager::<()>();
+    mascots::run_with_output_sync(config, platform, move |monitors, attached| {
+        let outputs: Vec<OutputInfo> = monitors
+            .iter()
+            .map(|m| OutputInfo { id: OutputId(m.id), geometry: Rect::new(m.x, m.y, m.width, m.height) })
+            .collect();
+        let attached = attached.map(|w| Rect::new(w.x, w.y, w.width, w.height));
+        let changes = surfaces.sync(&outputs, attached.as_ref(), |_| ());
+        if !changes.is_empty() {
+            debug!(created = ?changes.created, destroyed = ?changes.destroyed.len(), surfaces = surfaces.len(), "Output surfaces changed");
+        }
+        mascots::OutputPlan {
+            create: changes.created.iter().map(|id| id.0).collect(),
+            destroy: changes.destroyed.iter().map(|(id, ())| id.0).collect(),
+        }
+    })
+}
+
+/// The config file mascots reads when `--config` isn't given
+fn default_config_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("mascots").join("config.toml"))
 }
 
 fn detect_platform(forced: Option<PlatformChoice>) -> Result<mascots::PlatformType> {
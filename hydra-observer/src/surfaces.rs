@@ -0,0 +1,351 @@
+//! Per-output overlay surface bookkeeping
+//!
+//! The renderer keeps one layer surface per monitor. With
+//! `appearance.confine_to_attached_output` set, only the output containing the
+//! attachment target keeps a surface while attached, so fullscreen apps on the
+//! other outputs can use direct scanout. Monitor hotplug needs the same
+//! create/destroy-at-runtime handling, so both go through [`SurfaceManager`].
+//!
+//! Both settings live in the `[appearance]` table of the config file:
+//!
+//! ```toml
+//! [appearance]
+//! multi_monitor = "all"               # or "primary"
+//! confine_to_attached_output = true
+//! ```
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Compositor-assigned output identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OutputId(pub u32);
+
+/// Rectangle in global compositor coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn new(x: i32, y: i32, width: u32, height: u32) -> Self {
+        Self { x, y, width, height }
+    }
+
+    fn right(&self) -> i64 {
+        self.x as i64 + self.width as i64
+    }
+
+    fn bottom(&self) -> i64 {
+        self.y as i64 + self.height as i64
+    }
+
+    /// Area shared with `other` (0 when they don't overlap)
+    pub fn overlap_area(&self, other: &Rect) -> u64 {
+        let w = self.right().min(other.right()) - (self.x as i64).max(other.x as i64);
+        let h = self.bottom().min(other.bottom()) - (self.y as i64).max(other.y as i64);
+        if w <= 0 || h <= 0 {
+            0
+        } else {
+            (w * h) as u64
+        }
+    }
+
+    pub fn contains_point(&self, x: i64, y: i64) -> bool {
+        x >= self.x as i64 && x < self.right() && y >= self.y as i64 && y < self.bottom()
+    }
+
+    fn center(&self) -> (i64, i64) {
+        (self.x as i64 + self.width as i64 / 2, self.y as i64 + self.height as i64 / 2)
+    }
+}
+
+/// A monitor as reported by the platform backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputInfo {
+    pub id: OutputId,
+    pub geometry: Rect,
+}
+
+/// Output that contains `target`.
+///
+/// A window spanning two monitors belongs to the one showing most of it; on a
+/// tie the one holding its center wins. `None` if it is on no output at all
+/// (minimized, or moved off-screen).
+pub fn output_containing(target: &Rect, outputs: &[OutputInfo]) -> Option<OutputId> {
+    let (cx, cy) = target.center();
+    outputs
+        .iter()
+        .map(|o| (o, o.geometry.overlap_area(target)))
+        .filter(|(_, area)| *area > 0)
+        .max_by_key(|(o, area)| (*area, o.geometry.contains_point(cx, cy)))
+        .map(|(o, _)| o.id)
+}
+
+/// Which outputs get a surface when confinement doesn't apply
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MultiMonitor {
+    /// One surface on every output
+    #[default]
+    All,
+    /// Only the first output the backend reports
+    Primary,
+}
+
+/// Outputs whose surfaces were created or destroyed by one [`SurfaceManager::sync`]
+#[derive(Debug)]
+pub struct SurfaceChanges<S> {
+    pub created: Vec<OutputId>,
+    /// Removed window states, handed back so the caller can tear them down
+    pub destroyed: Vec<(OutputId, S)>,
+}
+
+impl<S> SurfaceChanges<S> {
+    pub fn is_empty(&self) -> bool {
+        self.created.is_empty() && self.destroyed.is_empty()
+    }
+}
+
+/// Surface settings, the `[appearance]` table of the config file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(default)]
+pub struct Appearance {
+    pub multi_monitor: MultiMonitor,
+    /// While attached, keep only the surface on the target's output
+    pub confine_to_attached_output: bool,
+}
+
+impl Appearance {
+    /// Read from a config file; a missing file or table means the defaults.
+    /// Other tables are the renderer's and are ignored here.
+    pub fn load(path: &Path) -> Result<Self> {
+        #[derive(Deserialize)]
+        struct File {
+            #[serde(default)]
+            appearance: Appearance,
+        }
+
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let file: File = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        Ok(file.appearance)
+    }
+
+    pub fn surface_manager<S>(&self) -> SurfaceManager<S> {
+        SurfaceManager::new(self.multi_monitor, self.confine_to_attached_output)
+    }
+}
+
+/// Owns the per-output window state map.
+///
+/// `sync` is called on hotplug and from the periodic picker-refresh /
+/// attachment-follow tick. Surfaces that should stay are left untouched, so
+/// their render state survives outputs coming and going around them.
+pub struct SurfaceManager<S> {
+    multi_monitor: MultiMonitor,
+    confine_to_attached_output: bool,
+    surfaces: BTreeMap<OutputId, S>,
+}
+
+impl<S> SurfaceManager<S> {
+    pub fn new(multi_monitor: MultiMonitor, confine_to_attached_output: bool) -> Self {
+        Self {
+            multi_monitor,
+            confine_to_attached_output,
+            surfaces: BTreeMap::new(),
+        }
+    }
+
+    /// Outputs that should have a surface right now
+    pub fn desired_outputs(&self, outputs: &[OutputInfo], attached: Option<&Rect>) -> Vec<OutputId> {
+        if self.confine_to_attached_output {
+            if let Some(id) = attached.and_then(|target| output_containing(target, outputs)) {
+                return vec![id];
+            }
+        }
+        match self.multi_monitor {
+            MultiMonitor::All => outputs.iter().map(|o| o.id).collect(),
+            MultiMonitor::Primary => outputs.first().map(|o| o.id).into_iter().collect(),
+        }
+    }
+
+    /// Bring the surface set in line with `outputs` and the attachment target.
+    ///
+    /// `create` builds the window state for a newly needed output.
+    pub fn sync(
+        &mut self,
+        outputs: &[OutputInfo],
+        attached: Option<&Rect>,
+        mut create: impl FnMut(&OutputInfo) -> S,
+    ) -> SurfaceChanges<S> {
+        let desired = self.desired_outputs(outputs, attached);
+
+        let stale: Vec<OutputId> = self.surfaces
+            .keys()
+            .filter(|id| !desired.contains(id))
+            .copied()
+            .collect();
+        let destroyed = stale
+            .into_iter()
+            .filter_map(|id| self.surfaces.remove(&id).map(|state| (id, state)))
+            .collect();
+
+        let mut created = Vec::new();
+        for output in outputs.iter().filter(|o| desired.contains(&o.id)) {
+            if let Entry::Vacant(slot) = self.surfaces.entry(output.id) {
+                slot.insert(create(output));
+                created.push(output.id);
+            }
+        }
+
+        SurfaceChanges { created, destroyed }
+    }
+
+    pub fn get_mut(&mut self, id: OutputId) -> Option<&mut S> {
+        self.surfaces.get_mut(&id)
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&OutputId, &mut S)> {
+        self.surfaces.iter_mut()
+    }
+
+    pub fn outputs(&self) -> impl Iterator<Item = OutputId> + '_ {
+        self.surfaces.keys().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.surfaces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.surfaces.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Laptop at the origin, external monitor to its right
+    fn outputs() -> Vec<OutputInfo> {
+        vec![
+            OutputInfo { id: OutputId(1), geometry: Rect::new(0, 0, 1920, 1080) },
+            OutputInfo { id: OutputId(2), geometry: Rect::new(1920, 0, 2560, 1440) },
+        ]
+    }
+
+    #[test]
+    fn test_output_containing() {
+        let outputs = outputs();
+        assert_eq!(output_containing(&Rect::new(100, 100, 800, 600), &outputs), Some(OutputId(1)));
+        assert_eq!(output_containing(&Rect::new(2000, 200, 800, 600), &outputs), Some(OutputId(2)));
+        // Mostly on the external monitor
+        assert_eq!(output_containing(&Rect::new(1800, 0, 800, 600), &outputs), Some(OutputId(2)));
+        // Below the laptop panel but inside the taller external one
+        assert_eq!(output_containing(&Rect::new(1900, 1200, 100, 100), &outputs), Some(OutputId(2)));
+        assert_eq!(output_containing(&Rect::new(-900, -900, 100, 100), &outputs), None);
+    }
+
+    #[test]
+    fn test_output_containing_tie_uses_center() {
+        let outputs = vec![
+            OutputInfo { id: OutputId(1), geometry: Rect::new(0, 0, 1000, 1000) },
+            OutputInfo { id: OutputId(2), geometry: Rect::new(1000, 0, 1000, 1000) },
+        ];
+        // Equal halves; center (1000, 50) sits on the second output's edge
+        assert_eq!(output_containing(&Rect::new(900, 0, 200, 100), &outputs), Some(OutputId(2)));
+    }
+
+    #[test]
+    fn test_confined_follows_attachment() {
+        let outputs = outputs();
+        let mut manager = SurfaceManager::new(MultiMonitor::All, true);
+        let mut next = 0;
+        let mut create = |_: &OutputInfo| { next += 1; next };
+
+        // Unattached: configured multi-monitor behaviour
+        let changes = manager.sync(&outputs, None, &mut create);
+        assert_eq!(changes.created, vec![OutputId(1), OutputId(2)]);
+        assert_eq!(manager.len(), 2);
+
+        // Attached on the laptop: the external surface goes away, laptop state is kept
+        let laptop = Rect::new(100, 100, 800, 600);
+        let changes = manager.sync(&outputs, Some(&laptop), &mut create);
+        assert!(changes.created.is_empty());
+        assert_eq!(changes.destroyed, vec![(OutputId(2), 2)]);
+        assert_eq!(manager.get_mut(OutputId(1)), Some(&mut 1));
+
+        // Target moves to the external monitor
+        let external = Rect::new(2200, 100, 800, 600);
+        let changes = manager.sync(&outputs, Some(&external), &mut create);
+        assert_eq!(changes.created, vec![OutputId(2)]);
+        assert_eq!(changes.destroyed, vec![(OutputId(1), 1)]);
+
+        // Steady state is a no-op
+        assert!(manager.sync(&outputs, Some(&external), &mut create).is_empty());
+
+        // Detach: the laptop surface comes back, the external one survives
+        let changes = manager.sync(&outputs, None, &mut create);
+        assert_eq!(changes.created, vec![OutputId(1)]);
+        assert_eq!(manager.get_mut(OutputId(2)), Some(&mut 3));
+    }
+
+    #[test]
+    fn test_hotplug_keeps_surviving_surfaces() {
+        let mut outputs = outputs();
+        let mut manager = SurfaceManager::new(MultiMonitor::All, false);
+        manager.sync(&outputs, None, |o| o.id.0 * 10);
+
+        // Unplug the external monitor
+        outputs.pop();
+        let changes = manager.sync(&outputs, None, |o| o.id.0 * 100);
+        assert_eq!(changes.destroyed, vec![(OutputId(2), 20)]);
+        assert_eq!(manager.get_mut(OutputId(1)), Some(&mut 10));
+
+        // Plug in a different one
+        outputs.push(OutputInfo { id: OutputId(3), geometry: Rect::new(1920, 0, 1920, 1080) });
+        let changes = manager.sync(&outputs, None, |o| o.id.0 * 100);
+        assert_eq!(changes.created, vec![OutputId(3)]);
+        assert_eq!(manager.outputs().collect::<Vec<_>>(), vec![OutputId(1), OutputId(3)]);
+    }
+
+    #[test]
+    fn test_appearance_from_config_file() {
+        let dir = std::env::temp_dir().join(format!("hydra-observer-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        assert_eq!(Appearance::load(&path).unwrap(), Appearance::default());
+
+        std::fs::write(&path, "[appearance]\nmulti_monitor = \"primary\"\nconfine_to_attached_output = true\nscale = 2\n\n[behavior]\nidle = 5\n").unwrap();
+        let appearance = Appearance::load(&path).unwrap();
+        assert_eq!(appearance, Appearance { multi_monitor: MultiMonitor::Primary, confine_to_attached_output: true });
+
+        std::fs::write(&path, "[appearance]\nmulti_monitor = \"some\"\n").unwrap();
+        assert!(Appearance::load(&path).is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_not_confined_ignores_attachment() {
+        let outputs = outputs();
+        let manager: SurfaceManager<()> = SurfaceManager::new(MultiMonitor::Primary, false);
+        let laptop_only = manager.desired_outputs(&outputs, Some(&Rect::new(2200, 100, 10, 10)));
+        assert_eq!(laptop_only, vec![OutputId(1)]);
+
+        // Confined but the target is off-screen: fall back
+        let manager: SurfaceManager<()> = SurfaceManager::new(MultiMonitor::All, true);
+        let fallback = manager.desired_outputs(&outputs, Some(&Rect::new(-900, -900, 10, 10)));
+        assert_eq!(fallback, vec![OutputId(1), OutputId(2)]);
+    }
+}
@@ -0,0 +1,253 @@
+// ═══════════════════════════════════════════════════════════════════════════
+// Backends - Time source, tmux access and session records
+// ═══════════════════════════════════════════════════════════════════════════
+//
+// The orchestrator reads the clock, drives tmux and persists sessions only
+// through these traits. Production uses the system clock, the tmux binary and
+// `.hydra/orchestrator/sessions`; the simulator swaps in fakes so lifecycle
+// logic runs deterministically without real sessions.
+
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::session::{SessionId, SessionRecord};
+use crate::tmux;
+
+/// Source of "now" for session aging and health checks
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Clock that only moves when told to
+pub struct FakeClock {
+    start: Instant,
+    offset: Mutex<Duration>,
+}
+
+impl FakeClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            offset: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    /// Time since the clock was created
+    pub fn elapsed(&self) -> Duration {
+        *self.offset.lock().expect("clock lock poisoned")
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.offset.lock().expect("clock lock poisoned") += by;
+    }
+}
+
+impl Default for FakeClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Tmux
+// ─────────────────────────────────────────────────────────────────────────────
+
+/// The tmux operations session management needs
+pub trait TmuxBackend: Send + Sync {
+    fn new_session(&self, name: &str, working_dir: &Path) -> Result<()>;
+    fn kill_session(&self, name: &str) -> Result<()>;
+    fn send_keys(&self, name: &str, keys: &str) -> Result<()>;
+    fn session_exists(&self, name: &str) -> Result<bool>;
+}
+
+/// Runs the real tmux binary
+pub struct SystemTmux;
+
+impl TmuxBackend for SystemTmux {
+    fn new_session(&self, name: &str, working_dir: &Path) -> Result<()> {
+        tmux::new_session(name, working_dir)
+    }
+
+    fn kill_session(&self, name: &str) -> Result<()> {
+        tmux::kill_session(name)
+    }
+
+    fn send_keys(&self, name: &str, keys: &str) -> Result<()> {
+        tmux::send_keys(name, keys)
+    }
+
+    fn session_exists(&self, name: &str) -> Result<bool> {
+        tmux::session_exists(name)
+    }
+}
+
+/// In-memory tmux: sessions exist until killed or `exit`ed, keys are recorded
+#[derive(Default)]
+pub struct FakeTmux {
+    sessions: Mutex<BTreeMap<String, Vec<String>>>,
+}
+
+impl FakeTmux {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The session's shell went away on its own (crash, agent exit)
+    pub fn exit(&self, name: &str) -> bool {
+        self.sessions.lock().expect("tmux lock poisoned").remove(name).is_some()
+    }
+
+    /// Everything typed into a session so far
+    pub fn keys(&self, name: &str) -> Vec<String> {
+        self.sessions.lock().expect("tmux lock poisoned")
+            .get(name)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn sessions(&self) -> Vec<String> {
+        self.sessions.lock().expect("tmux lock poisoned").keys().cloned().collect()
+    }
+}
+
+impl TmuxBackend for FakeTmux {
+    fn new_session(&self, name: &str, _working_dir: &Path) -> Result<()> {
+        let mut sessions = self.sessions.lock().expect("tmux lock poisoned");
+        if sessions.contains_key(name) {
+            anyhow::bail!("tmux new-session failed: duplicate session: {}", name);
+        }
+        sessions.insert(name.to_string(), Vec::new());
+        Ok(())
+    }
+
+    fn kill_session(&self, name: &str) -> Result<()> {
+        self.exit(name);
+        Ok(())
+    }
+
+    fn send_keys(&self, name: &str, keys: &str) -> Result<()> {
+        self.sessions.lock().expect("tmux lock poisoned")
+            .get_mut(name)
+            .map(|sent| sent.push(keys.to_string()))
+            .with_context(|| format!("tmux send-keys failed: can't find session: {}", name))
+    }
+
+    fn session_exists(&self, name: &str) -> Result<bool> {
+        Ok(self.sessions.lock().expect("tmux lock poisoned").contains_key(name))
+    }
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Session records
+// ─────────────────────────────────────────────────────────────────────────────
+
+/// Where session records outlive the process (see `SessionStore` for the
+/// on-disk one)
+pub trait RecordStore: Send + Sync {
+    fn save(&self, record: &SessionRecord) -> Result<()>;
+    fn list(&self) -> Result<Vec<SessionRecord>>;
+    fn remove(&self, id: &SessionId) -> Result<()>;
+    /// Move the session's stored state to `to`, returning the one it
+    /// replaces (`from` if none was stored yet), or `None` if it already was
+    /// `to`. Compare and save are one step, so of several processes that
+    /// observe the same change only one gets it back.
+    fn transition(&self, id: &SessionId, from: &str, to: &str) -> Result<Option<String>>;
+}
+
+/// In-memory records, kept in id order
+#[derive(Default)]
+pub struct FakeStore {
+    records: Mutex<BTreeMap<String, SessionRecord>>,
+    states: Mutex<BTreeMap<String, String>>,
+}
+
+impl FakeStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ids of the records saved and not removed
+    pub fn ids(&self) -> Vec<String> {
+        self.records.lock().expect("store lock poisoned").keys().cloned().collect()
+    }
+
+    pub fn get(&self, id: &str) -> Option<SessionRecord> {
+        self.records.lock().expect("store lock poisoned").get(id).cloned()
+    }
+}
+
+impl RecordStore for FakeStore {
+    fn save(&self, record: &SessionRecord) -> Result<()> {
+        self.records.lock().expect("store lock poisoned").insert(record.id.clone(), record.clone());
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<SessionRecord>> {
+        Ok(self.records.lock().expect("store lock poisoned").values().cloned().collect())
+    }
+
+    fn remove(&self, id: &SessionId) -> Result<()> {
+        self.records.lock().expect("store lock poisoned").remove(&id.0);
+        self.states.lock().expect("store lock poisoned").remove(&id.0);
+        Ok(())
+    }
+
+    fn transition(&self, id: &SessionId, from: &str, to: &str) -> Result<Option<String>> {
+        let mut states = self.states.lock().expect("store lock poisoned");
+        let current = states.get(&id.0).map_or(from, String::as_str);
+        if current == to {
+            return Ok(None);
+        }
+        let previous = current.to_string();
+        states.insert(id.0.clone(), to.to_string());
+        Ok(Some(previous))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fake_clock_advances() {
+        let clock = FakeClock::new();
+        let t0 = clock.now();
+        assert_eq!(clock.now(), t0);
+        clock.advance(Duration::from_secs(90));
+        assert_eq!(clock.now() - t0, Duration::from_secs(90));
+        assert_eq!(clock.elapsed(), Duration::from_secs(90));
+    }
+
+    #[test]
+    fn test_fake_tmux_lifecycle() {
+        let tmux = FakeTmux::new();
+        tmux.new_session("a", Path::new("/tmp")).unwrap();
+        assert!(tmux.new_session("a", Path::new("/tmp")).is_err());
+        tmux.send_keys("a", "echo hi").unwrap();
+        assert_eq!(tmux.keys("a"), vec!["echo hi"]);
+        assert!(tmux.session_exists("a").unwrap());
+
+        assert!(tmux.exit("a"));
+        assert!(!tmux.session_exists("a").unwrap());
+        assert!(tmux.send_keys("a", "ls").is_err());
+        // Killing a session that is already gone is not an error, like tmux
+        tmux.kill_session("a").unwrap();
+    }
+}
@@ -6,6 +6,7 @@ use std::path::PathBuf;
 use std::time::Duration;
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use crate::limits::{ResourceLimits, SystemdCommands};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct HydralphConfig {
@@ -20,6 +21,14 @@ pub struct HydralphConfig {
 
     #[serde(default)]
     pub agent_flags: String,
+
+    /// Default resource limits for spawned sessions (`[limits]` table)
+    #[serde(default)]
+    pub limits: ResourceLimits,
+
+    /// Programs used for session scopes (`[systemd]` table)
+    #[serde(default)]
+    pub systemd: SystemdCommands,
 }
 
 fn default_max_iterations() -> u32 { 10 }
@@ -32,6 +41,8 @@ impl Default for HydralphConfig {
             max_duration_hours: default_max_duration_hours(),
             agent_cli: "claude".into(),
             agent_flags: "--dangerously-skip-permissions".into(),
+            limits: ResourceLimits::default(),
+            systemd: SystemdCommands::default(),
         }
     }
 }
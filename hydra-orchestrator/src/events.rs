@@ -0,0 +1,550 @@
+// ═══════════════════════════════════════════════════════════════════════════
+// Event Journal - Append-only record of orchestrator activity
+// ═══════════════════════════════════════════════════════════════════════════
+//
+// Every event the orchestrator publishes to hydra-mail is also appended to
+// `.hydra/orchestrator/events.jsonl`, one JSON object per line. Any process
+// can tail the file, which is how `hydra events --follow` sees activity from
+// other `hydra` invocations without talking to them.
+//
+// The field names of `Event` and `StreamEvent` are consumed by external
+// dashboards; treat them as a stable schema (see the snapshot tests below).
+//
+// There are no budget or maintenance categories: the orchestrator has no
+// budget warnings or maintenance mode to report. A session that runs out of
+// iterations shows up as a `session:state` change to blocked.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Every channel `Orchestrator::publish` is called with
+pub const CHANNELS: &[&str] = &[
+    "session:spawned",
+    "session:queued",
+    "session:state",
+    "session:paused",
+    "session:resumed",
+    "session:killed",
+    "session:unblocked",
+    "session:renamed",
+    "session:exited",
+    "session:completed",
+    "session:answered",
+    "session:injected",
+    "session:stuck",
+    "health:killed",
+    "merge:conflict-predicted",
+];
+
+/// Event categories accepted by `--types`: those of [`CHANNELS`], in order
+pub fn event_types() -> Vec<&'static str> {
+    let mut types = Vec::new();
+    for channel in CHANNELS {
+        let (event_type, _) = split_channel(channel);
+        if !types.contains(&event_type) {
+            types.push(event_type);
+        }
+    }
+    types
+}
+
+/// The category and name of the event for a channel like "session:spawned"
+fn split_channel(channel: &str) -> (&str, &str) {
+    let (event_type, event) = channel.split_once(':').unwrap_or((channel, ""));
+    // Injects and stuck detection get their own categories so dashboards
+    // can filter them independently of lifecycle events
+    let event_type = match event {
+        "injected" => "inject",
+        "stuck" => "health",
+        _ => event_type,
+    };
+    (event_type, event)
+}
+
+/// A single journal entry
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    /// Wall-clock time the event was recorded
+    pub ts: DateTime<Utc>,
+    /// Category, e.g. "session"
+    #[serde(rename = "type")]
+    pub event_type: String,
+    /// Name within the category, e.g. "spawned"
+    pub event: String,
+    /// Session the event concerns
+    pub session: String,
+    /// Event-specific details
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    pub data: serde_json::Value,
+}
+
+impl Event {
+    pub fn new(event_type: &str, event: &str, session: &str, data: serde_json::Value) -> Self {
+        Self {
+            ts: Utc::now(),
+            event_type: event_type.to_string(),
+            event: event.to_string(),
+            session: session.to_string(),
+            data,
+        }
+    }
+
+    /// Build from a mail channel name like "session:spawned"
+    pub fn from_channel(channel: &str, session: &str, data: serde_json::Value) -> Self {
+        let (event_type, event) = split_channel(channel);
+        Self::new(event_type, event, session, data)
+    }
+
+    /// Workflow trace this event belongs to, if any
+    pub fn trace_id(&self) -> Option<&str> {
+        self.data.get("trace_id").and_then(|t| t.as_str())
+    }
+}
+
+/// Add `trace_id` to an event payload unless it already has one
+pub(crate) fn with_trace(data: serde_json::Value, trace_id: Option<&str>) -> serde_json::Value {
+    let Some(trace_id) = trace_id else {
+        return data;
+    };
+    let mut data = match data {
+        serde_json::Value::Null => serde_json::json!({}),
+        serde_json::Value::Object(_) => data,
+        // Non-object payloads have nowhere to put the id
+        other => return other,
+    };
+    if data.get("trace_id").is_none() {
+        data["trace_id"] = serde_json::json!(trace_id);
+    }
+    data
+}
+
+/// An event as written to a consumer, numbered in output order
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StreamEvent {
+    pub seq: u64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+// ═══════════════════════════════════════════════════════════════════════════
+// Journal
+// ═══════════════════════════════════════════════════════════════════════════
+
+pub struct EventJournal {
+    path: PathBuf,
+}
+
+impl EventJournal {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append an event (single write so concurrent writers don't interleave)
+    pub fn append(&self, event: &Event) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let mut line = serde_json::to_string(event).context("Failed to serialize event")?;
+        line.push('\n');
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open {}", self.path.display()))?;
+        file.write_all(line.as_bytes())
+            .with_context(|| format!("Failed to append to {}", self.path.display()))?;
+        Ok(())
+    }
+
+    /// Current end of the journal in bytes
+    pub fn len(&self) -> u64 {
+        fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Read complete lines starting at `offset`. Returns the events and the
+    /// offset just past the last complete line; a partially written trailing
+    /// line is left for the next read.
+    pub fn read_from(&self, offset: u64) -> Result<(Vec<Event>, u64)> {
+        let mut file = match File::open(&self.path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((Vec::new(), 0)),
+            Err(e) => return Err(e).with_context(|| format!("Failed to open {}", self.path.display())),
+        };
+
+        // Journal was truncated or replaced: start over
+        let offset = if offset > file.metadata()?.len() { 0 } else { offset };
+
+        file.seek(SeekFrom::Start(offset))?;
+        let mut buf = String::new();
+        file.read_to_string(&mut buf)
+            .with_context(|| format!("Failed to read {}", self.path.display()))?;
+
+        let complete = match buf.rfind('\n') {
+            Some(idx) => &buf[..=idx],
+            None => return Ok((Vec::new(), offset)),
+        };
+
+        let mut events = Vec::new();
+        for line in complete.lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(line) {
+                Ok(event) => events.push(event),
+                Err(e) => warn!("skipping malformed event: {}", e),
+            }
+        }
+
+        Ok((events, offset + complete.len() as u64))
+    }
+}
+
+// ═══════════════════════════════════════════════════════════════════════════
+// Follower
+// ═══════════════════════════════════════════════════════════════════════════
+
+/// Position in the journal, persisted with `--cursor-file` so a restarted
+/// follower neither repeats nor skips events
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct EventCursor {
+    pub offset: u64,
+    pub seq: u64,
+}
+
+pub struct EventFollower {
+    journal: EventJournal,
+    cursor: EventCursor,
+    since: Option<DateTime<Utc>>,
+    types: Vec<String>,
+    trace: Option<String>,
+    cursor_file: Option<PathBuf>,
+}
+
+impl EventFollower {
+    /// Start following `journal`.
+    ///
+    /// A saved cursor wins over everything else. Otherwise `since` backfills
+    /// from the start of the journal, and with neither only new events are
+    /// returned.
+    pub fn new(
+        journal: EventJournal,
+        since: Option<DateTime<Utc>>,
+        types: Vec<String>,
+        cursor_file: Option<PathBuf>,
+    ) -> Result<Self> {
+        let known = event_types();
+        for t in &types {
+            if !known.contains(&t.as_str()) {
+                anyhow::bail!("Unknown event type '{}' (expected one of: {})", t, known.join(", "));
+            }
+        }
+
+        let saved = match &cursor_file {
+            Some(path) if path.exists() => {
+                let content = fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                Some(serde_json::from_str(&content)
+                    .with_context(|| format!("Failed to parse {}", path.display()))?)
+            }
+            _ => None,
+        };
+
+        let cursor = match saved {
+            Some(cursor) => cursor,
+            None if since.is_some() => EventCursor::default(),
+            None => EventCursor { offset: journal.len(), seq: 0 },
+        };
+
+        Ok(Self { journal, cursor, since, types, trace: None, cursor_file })
+    }
+
+    /// Only return events for one workflow trace
+    pub fn with_trace(mut self, trace: Option<String>) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    pub fn cursor(&self) -> &EventCursor {
+        &self.cursor
+    }
+
+    /// Return events appended since the last poll, numbered and filtered
+    pub fn poll(&mut self) -> Result<Vec<StreamEvent>> {
+        let (events, offset) = self.journal.read_from(self.cursor.offset)?;
+        if offset == self.cursor.offset {
+            return Ok(Vec::new());
+        }
+        // A truncated journal resets the offset but never the sequence
+        self.cursor.offset = offset;
+
+        let mut out = Vec::new();
+        for event in events {
+            if !self.matches(&event) {
+                continue;
+            }
+            self.cursor.seq += 1;
+            out.push(StreamEvent { seq: self.cursor.seq, event });
+        }
+
+        self.save_cursor()?;
+        Ok(out)
+    }
+
+    fn matches(&self, event: &Event) -> bool {
+        if let Some(since) = self.since {
+            if event.ts < since {
+                return false;
+            }
+        }
+        if let Some(trace) = &self.trace {
+            if event.trace_id() != Some(trace.as_str()) {
+                return false;
+            }
+        }
+        self.types.is_empty() || self.types.contains(&event.event_type)
+    }
+
+    fn save_cursor(&self) -> Result<()> {
+        let Some(path) = &self.cursor_file else {
+            return Ok(());
+        };
+        let content = serde_json::to_string(&self.cursor).context("Failed to serialize cursor")?;
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, content)
+            .with_context(|| format!("Failed to write {}", temp_path.display()))?;
+        fs::rename(&temp_path, path)
+            .with_context(|| format!("Failed to replace {}", path.display()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hydra-events-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    fn event(secs: i64, channel: &str, session: &str) -> Event {
+        Event { ts: at(secs), ..Event::from_channel(channel, session, serde_json::Value::Null) }
+    }
+
+    #[test]
+    fn test_event_schema_snapshot() {
+        let event = Event {
+            ts: at(0),
+            event_type: "session".into(),
+            event: "state".into(),
+            session: "abc12345".into(),
+            data: json!({"from": "starting", "to": "running"}),
+        };
+        let streamed = serde_json::to_string(&StreamEvent { seq: 7, event }).unwrap();
+        assert_eq!(
+            streamed,
+            r#"{"seq":7,"ts":"2023-11-14T22:13:20Z","type":"session","event":"state","session":"abc12345","data":{"from":"starting","to":"running"}}"#
+        );
+
+        let bare = Event { ts: at(0), ..Event::from_channel("session:injected", "abc12345", serde_json::Value::Null) };
+        let bare = serde_json::to_string(&bare).unwrap();
+        assert_eq!(
+            bare,
+            r#"{"ts":"2023-11-14T22:13:20Z","type":"inject","event":"injected","session":"abc12345"}"#
+        );
+
+        crate::timefmt::assert_machine_output(&streamed);
+        crate::timefmt::assert_machine_output(&bare);
+    }
+
+    #[test]
+    fn test_event_category_snapshots() {
+        assert_eq!(event_types(), vec!["session", "inject", "health", "merge"]);
+
+        let snapshot = |channel: &str, data: serde_json::Value| {
+            serde_json::to_string(&Event { ts: at(0), ..Event::from_channel(channel, "abc12345", data) }).unwrap()
+        };
+        assert_eq!(
+            snapshot("session:killed", json!({"reason": "user request"})),
+            r#"{"ts":"2023-11-14T22:13:20Z","type":"session","event":"killed","session":"abc12345","data":{"reason":"user request"}}"#
+        );
+        assert_eq!(
+            snapshot("session:injected", serde_json::Value::Null),
+            r#"{"ts":"2023-11-14T22:13:20Z","type":"inject","event":"injected","session":"abc12345"}"#
+        );
+        assert_eq!(
+            snapshot("session:stuck", serde_json::Value::Null),
+            r#"{"ts":"2023-11-14T22:13:20Z","type":"health","event":"stuck","session":"abc12345"}"#
+        );
+        assert_eq!(
+            snapshot("health:killed", json!({"reason": "stuck for 600s"})),
+            r#"{"ts":"2023-11-14T22:13:20Z","type":"health","event":"killed","session":"abc12345","data":{"reason":"stuck for 600s"}}"#
+        );
+        assert_eq!(
+            snapshot("merge:conflict-predicted", json!({"branch": "hydralph/abc12345", "target": "main", "files": ["src/lib.rs"]})),
+            r#"{"ts":"2023-11-14T22:13:20Z","type":"merge","event":"conflict-predicted","session":"abc12345","data":{"branch":"hydralph/abc12345","target":"main","files":["src/lib.rs"]}}"#
+        );
+    }
+
+    #[test]
+    fn test_merge_type_accepted() {
+        let dir = temp_dir();
+        let journal = EventJournal::new(dir.join("events.jsonl"));
+        journal.append(&event(0, "session:completed", "a")).unwrap();
+        journal.append(&event(1, "merge:conflict-predicted", "a")).unwrap();
+
+        let mut follower = EventFollower::new(journal, Some(at(0)), vec!["merge".into()], None).unwrap();
+        let out = follower.poll().unwrap();
+        assert_eq!(out.iter().map(|e| e.event.event.as_str()).collect::<Vec<_>>(), vec!["conflict-predicted"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_follow_live_only_by_default() {
+        let dir = temp_dir();
+        let journal = EventJournal::new(dir.join("events.jsonl"));
+        journal.append(&event(0, "session:spawned", "old")).unwrap();
+
+        let mut follower = EventFollower::new(EventJournal::new(journal.path().to_path_buf()), None, vec![], None).unwrap();
+        assert!(follower.poll().unwrap().is_empty());
+
+        journal.append(&event(1, "session:spawned", "a")).unwrap();
+        journal.append(&event(2, "session:paused", "a")).unwrap();
+        let out = follower.poll().unwrap();
+        assert_eq!(out.iter().map(|e| e.seq).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(out[0].event.event, "spawned");
+        assert_eq!(out[1].event.event, "paused");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_since_backfill_and_type_filter() {
+        let dir = temp_dir();
+        let journal = EventJournal::new(dir.join("events.jsonl"));
+        journal.append(&event(0, "session:spawned", "a")).unwrap();
+        journal.append(&event(100, "session:injected", "a")).unwrap();
+        journal.append(&event(200, "session:killed", "a")).unwrap();
+
+        let mut follower = EventFollower::new(
+            EventJournal::new(journal.path().to_path_buf()),
+            Some(at(50)),
+            vec!["session".into()],
+            None,
+        ).unwrap();
+        let out = follower.poll().unwrap();
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].seq, 1);
+        assert_eq!(out[0].event.event, "killed");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_unknown_type_rejected() {
+        let dir = temp_dir();
+        let journal = EventJournal::new(dir.join("events.jsonl"));
+        assert!(EventFollower::new(journal, None, vec!["bogus".into()], None).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_partial_line_waits_for_completion() {
+        let dir = temp_dir();
+        let path = dir.join("events.jsonl");
+        let journal = EventJournal::new(path.clone());
+        let mut follower = EventFollower::new(EventJournal::new(path.clone()), Some(at(0)), vec![], None).unwrap();
+
+        let line = serde_json::to_string(&event(1, "session:spawned", "a")).unwrap();
+        let (head, tail) = line.split_at(10);
+        fs::write(&path, head).unwrap();
+        assert!(follower.poll().unwrap().is_empty());
+
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        writeln!(file, "{}", tail).unwrap();
+        journal.append(&event(2, "session:killed", "a")).unwrap();
+
+        let out = follower.poll().unwrap();
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[1].seq, 2);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_cursor_file_resumes_without_repeats() {
+        let dir = temp_dir();
+        let path = dir.join("events.jsonl");
+        let cursor_path = dir.join("cursor.json");
+        let journal = EventJournal::new(path.clone());
+        journal.append(&event(0, "session:spawned", "a")).unwrap();
+
+        let mut first = EventFollower::new(
+            EventJournal::new(path.clone()), Some(at(0)), vec![], Some(cursor_path.clone()),
+        ).unwrap();
+        assert_eq!(first.poll().unwrap().len(), 1);
+        drop(first);
+
+        // Events written while no follower was running
+        journal.append(&event(1, "session:paused", "a")).unwrap();
+        journal.append(&event(2, "session:resumed", "a")).unwrap();
+
+        // `since` is ignored once a cursor exists
+        let mut second = EventFollower::new(
+            EventJournal::new(path.clone()), Some(at(0)), vec![], Some(cursor_path.clone()),
+        ).unwrap();
+        let out = second.poll().unwrap();
+        assert_eq!(out.iter().map(|e| (e.seq, e.event.event.as_str())).collect::<Vec<_>>(),
+                   vec![(2, "paused"), (3, "resumed")]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_with_trace() {
+        assert_eq!(with_trace(serde_json::Value::Null, None), serde_json::Value::Null);
+        assert_eq!(with_trace(serde_json::Value::Null, Some("t1")), json!({"trace_id": "t1"}));
+        assert_eq!(
+            with_trace(json!({"reason": "x", "trace_id": "t0"}), Some("t1")),
+            json!({"reason": "x", "trace_id": "t0"})
+        );
+    }
+
+    #[test]
+    fn test_trace_filter() {
+        let dir = temp_dir();
+        let journal = EventJournal::new(dir.join("events.jsonl"));
+        journal.append(&Event::new("session", "spawned", "a", json!({"trace_id": "t1"}))).unwrap();
+        journal.append(&Event::new("session", "spawned", "b", json!({"trace_id": "t2"}))).unwrap();
+        journal.append(&Event::new("health", "stuck", "a", json!({"trace_id": "t1"}))).unwrap();
+        journal.append(&Event::new("session", "exited", "c", serde_json::Value::Null)).unwrap();
+
+        let mut follower = EventFollower::new(journal, Some(at(0)), vec![], None)
+            .unwrap()
+            .with_trace(Some("t1".into()));
+        let events: Vec<String> = follower.poll().unwrap().into_iter().map(|e| e.event.event).collect();
+        assert_eq!(events, vec!["spawned", "stuck"]);
+        fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,451 @@
+// ═══════════════════════════════════════════════════════════════════════════
+// Inbox - Agent questions waiting on a human
+// ═══════════════════════════════════════════════════════════════════════════
+//
+// Agents ask on `team:question`. Nothing keeps listening there, so questions
+// are gathered after the fact from hydra-mail's message log, plus the SQLite
+// archive when the daemon keeps one (`sqlite` feature). A question stays open
+// until an `answer` pulse refers to its id or `hydra answer` recorded it in
+// `.hydra/orchestrator/answered.json`.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use hydra_mail::message_log::MessageLog;
+use hydra_mail::pulse::{decode_pulse, Answer, Question};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+/// A pulse from mail history, with the time the daemon recorded it
+#[derive(Debug, Clone)]
+pub struct RecordedPulse {
+    pub timestamp: DateTime<Utc>,
+    pub pulse: serde_json::Value,
+}
+
+/// Question and answer pulses hydra-mail kept for this project, oldest
+/// first. Empty when hydra-mail isn't initialized here.
+pub fn mail_history(root: &Path) -> Result<Vec<RecordedPulse>> {
+    if !root.join(".hydra/config.toml").exists() {
+        return Ok(Vec::new());
+    }
+    let config = hydra_mail::config::Config::load(root)?;
+
+    let mut pulses = Vec::new();
+    let log_path = root.join(".hydra/messages.log");
+    if log_path.exists() {
+        for entry in MessageLog::open(&log_path)?.replay()? {
+            if entry.project_uuid != config.project_uuid {
+                continue;
+            }
+            // Raw emits that aren't pulses can't be questions
+            if let Ok(pulse) = decode_pulse(&entry.message) {
+                pulses.push(RecordedPulse { timestamp: entry.timestamp, pulse });
+            }
+        }
+    }
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = config.archive.sqlite_path(root).filter(|p| p.exists()) {
+        pulses.extend(archived(&path)?);
+    }
+
+    pulses.retain(|p| is_question_or_answer(&p.pulse));
+    pulses.sort_by_key(|p| p.timestamp);
+    // The log and the archive record the same pulses
+    let mut seen = HashSet::new();
+    pulses.retain(|p| p.pulse["id"].as_str().is_none_or(|id| seen.insert(id.to_string())));
+    Ok(pulses)
+}
+
+fn is_question_or_answer(pulse: &serde_json::Value) -> bool {
+    Question::from_pulse(pulse).is_some() || Answer::from_pulse(pulse).is_some()
+}
+
+#[cfg(feature = "sqlite")]
+fn archived(path: &Path) -> Result<Vec<RecordedPulse>> {
+    let conn = hydra_mail::archive::open_read_only(path)?;
+    let rows = hydra_mail::archive::query_sql(
+        &conn,
+        "SELECT timestamp, payload FROM messages WHERE channel = 'team:question' OR type = 'answer' ORDER BY seq",
+    )?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            let timestamp = DateTime::parse_from_rfc3339(row.get("timestamp")?.as_str()?).ok()?;
+            let pulse = serde_json::from_str(row.get("payload")?.as_str()?).ok()?;
+            Some(RecordedPulse { timestamp: timestamp.with_timezone(&Utc), pulse })
+        })
+        .collect())
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Answered bookkeeping
+// ─────────────────────────────────────────────────────────────────────────────
+
+/// How `hydra answer` delivered an answer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "via", rename_all = "snake_case")]
+pub enum Delivery {
+    /// Answer pulse on the reply channel the question declared
+    Channel { channel: String },
+    /// Written to the asking session's inject.md
+    Inject { session: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AnsweredRecord {
+    pub answered_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub delivery: Delivery,
+}
+
+pub type Answered = BTreeMap<String, AnsweredRecord>;
+
+pub fn load_answered(path: &Path) -> Result<Answered> {
+    if !path.exists() {
+        return Ok(Answered::new());
+    }
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+pub fn save_answered(path: &Path, answered: &Answered) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    let content = serde_json::to_string_pretty(answered)
+        .context("Failed to serialize answered questions")?;
+    fs::write(path, content)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Listing
+// ─────────────────────────────────────────────────────────────────────────────
+
+/// An open question and the session that asked it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InboxEntry {
+    pub session: String,
+    pub asked_at: DateTime<Utc>,
+    pub question: Question,
+}
+
+impl InboxEntry {
+    pub fn short_id(&self) -> &str {
+        short_id(&self.question.id)
+    }
+}
+
+/// Questions asked by `sessions` (id and trace id) that have no answer pulse
+/// and aren't in `answered`, oldest first. A question belongs to a session
+/// through its `session` field or its trace id.
+pub fn unanswered(
+    history: &[RecordedPulse],
+    sessions: &[(String, Option<String>)],
+    answered: &Answered,
+) -> Vec<InboxEntry> {
+    let replied: HashSet<String> = history
+        .iter()
+        .filter_map(|p| Answer::from_pulse(&p.pulse))
+        .map(|a| a.question_id)
+        .collect();
+
+    let mut seen = HashSet::new();
+    history
+        .iter()
+        .filter_map(|p| {
+            let question = Question::from_pulse(&p.pulse)?;
+            let (session, _) = sessions.iter().find(|(id, trace)| {
+                question.session.as_deref() == Some(id.as_str())
+                    || (trace.is_some() && question.trace_id == *trace)
+            })?;
+            Some(InboxEntry { session: session.clone(), asked_at: p.timestamp, question })
+        })
+        .filter(|e| !replied.contains(&e.question.id) && !answered.contains_key(&e.question.id))
+        .filter(|e| seen.insert(e.question.id.clone()))
+        .collect()
+}
+
+/// The entry with id `id`, or the only one whose id starts with it
+pub fn find<'a>(entries: &'a [InboxEntry], id: &str) -> Result<&'a InboxEntry> {
+    if let Some(entry) = entries.iter().find(|e| e.question.id == id) {
+        return Ok(entry);
+    }
+    let matches: Vec<_> = entries.iter().filter(|e| !id.is_empty() && e.question.id.starts_with(id)).collect();
+    match matches.as_slice() {
+        [entry] => Ok(entry),
+        [] => bail!("No unanswered question matching '{}' (see: hydra inbox)", id),
+        _ => bail!("Question id '{}' is ambiguous, use more of it", id),
+    }
+}
+
+/// The question a block reason refers to, by full or short id. Ids are
+/// compared whole, so `q-1` isn't found in `q-12`.
+pub fn referenced_by<'a>(reason: &str, entries: &'a [InboxEntry]) -> Option<&'a InboxEntry> {
+    let words: Vec<&str> = reason
+        .split(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_'))
+        .filter(|word| !word.is_empty())
+        .collect();
+    entries.iter().find(|e| {
+        let id = e.question.id.as_str();
+        words.iter().any(|word| *word == id || *word == short_id(id))
+    })
+}
+
+/// First 8 characters of a question id, as `hydra inbox` shows it
+fn short_id(id: &str) -> &str {
+    id.char_indices().nth(8).map_or(id, |(i, _)| &id[..i])
+}
+
+/// inject.md content carrying an answer, for agents that only poll inject.md
+pub fn inject_text(question: &Question, answer: &str) -> String {
+    format!(
+        "Answer to your question {} (\"{}\"):\n\n{}\n",
+        question.id, question.question, answer
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FakeClock, FakeStore, FakeTmux, MailClient, Orchestrator, SessionConfig, SessionId};
+    use hydra_mail::message_log::LogEntry;
+    use hydra_mail::pulse::{encode_pulse, new_pulse};
+    use serde_json::json;
+    use std::io::{BufRead, BufReader, Write};
+    use std::sync::Arc;
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    fn recorded(secs: i64, pulse: serde_json::Value) -> RecordedPulse {
+        RecordedPulse { timestamp: at(secs), pulse }
+    }
+
+    fn question(id: &str, session: Option<&str>, trace_id: Option<&str>) -> Question {
+        Question {
+            id: id.into(),
+            question: format!("question {}", id),
+            from: None,
+            session: session.map(String::from),
+            trace_id: trace_id.map(String::from),
+            reply_channel: None,
+        }
+    }
+
+    fn ids(entries: &[InboxEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.question.id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_unanswered_filtering() {
+        let sessions = vec![("aaaa1111".to_string(), Some("trace-a".to_string())), ("bbbb2222".to_string(), None)];
+        let history = vec![
+            recorded(0, question("q-by-session", Some("bbbb2222"), None).to_pulse()),
+            recorded(1, question("q-by-trace", None, Some("trace-a")).to_pulse()),
+            recorded(2, question("q-other-project", Some("cccc3333"), Some("trace-c")).to_pulse()),
+            recorded(3, question("q-replied", Some("aaaa1111"), None).to_pulse()),
+            recorded(4, question("q-local", Some("aaaa1111"), None).to_pulse()),
+            recorded(5, Answer { question_id: "q-replied".into(), answer: "yes".into(), from: None }.to_pulse("team:answers", None)),
+            // Re-asked with the same id: listed once
+            recorded(6, question("q-by-session", Some("bbbb2222"), None).to_pulse()),
+            recorded(7, new_pulse("status", "team:status", json!({"session": "aaaa1111"}), serde_json::Value::Null)),
+        ];
+        let mut answered = Answered::new();
+        answered.insert(
+            "q-local".into(),
+            AnsweredRecord { answered_at: at(8), delivery: Delivery::Inject { session: "aaaa1111".into() } },
+        );
+
+        let entries = unanswered(&history, &sessions, &answered);
+        assert_eq!(ids(&entries), vec!["q-by-session", "q-by-trace"]);
+        assert_eq!(entries[0].session, "bbbb2222");
+        assert_eq!(entries[0].asked_at, at(0));
+        assert_eq!(entries[1].session, "aaaa1111");
+    }
+
+    #[test]
+    fn test_find_and_referenced_by() {
+        let entry = |id: &str| InboxEntry { session: "s".into(), asked_at: at(0), question: question(id, None, None) };
+        let entries = vec![entry("3f2a9c1e-aaaa"), entry("3f2a77d0-bbbb"), entry("q-1")];
+
+        assert_eq!(find(&entries, "q-1").unwrap().question.id, "q-1");
+        assert_eq!(find(&entries, "3f2a9").unwrap().question.id, "3f2a9c1e-aaaa");
+        assert!(find(&entries, "3f2a").unwrap_err().to_string().contains("ambiguous"));
+        assert!(find(&entries, "zzz").unwrap_err().to_string().contains("No unanswered question"));
+        assert!(find(&entries, "").is_err());
+
+        assert_eq!(short_id("3f2a9c1e-aaaa"), "3f2a9c1e");
+        assert_eq!(short_id("q-1"), "q-1");
+        let blocking = referenced_by("waiting on q:3f2a77d0 (which schema?)", &entries);
+        assert_eq!(blocking.map(|e| e.question.id.as_str()), Some("3f2a77d0-bbbb"));
+        assert_eq!(referenced_by("tests keep failing", &entries), None);
+        let full = referenced_by("see 3f2a9c1e-aaaa", &entries);
+        assert_eq!(full.map(|e| e.question.id.as_str()), Some("3f2a9c1e-aaaa"));
+        assert_eq!(referenced_by("waiting on q-1.", &entries).map(|e| e.question.id.as_str()), Some("q-1"));
+        assert_eq!(referenced_by("waiting on q-12", &entries), None);
+        assert_eq!(referenced_by("waiting on xq-1 and 3f2a77d01", &entries), None);
+    }
+
+    #[test]
+    fn test_answered_round_trip() {
+        let path = std::env::temp_dir().join(format!("hydra-answered-{}/answered.json", uuid::Uuid::new_v4()));
+        assert!(load_answered(&path).unwrap().is_empty());
+
+        let mut answered = Answered::new();
+        answered.insert(
+            "q-1".into(),
+            AnsweredRecord { answered_at: at(0), delivery: Delivery::Channel { channel: "team:answers".into() } },
+        );
+        save_answered(&path, &answered).unwrap();
+        assert_eq!(load_answered(&path).unwrap(), answered);
+        let raw: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(raw["q-1"], json!({"answered_at": "2023-11-14T22:13:20Z", "via": "channel", "channel": "team:answers"}));
+        crate::timefmt::assert_machine_output(&raw.to_string());
+        fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_mail_history_merges_archive() {
+        use hydra_mail::archive::{Archive, ArchiveRecord};
+
+        let root = std::env::temp_dir().join(format!("hydra-inbox-{}", uuid::Uuid::new_v4().simple()));
+        let mut config = hydra_mail::config::Config::init(&root).unwrap();
+        config.archive.sqlite = Some(".hydra/mail.db".into());
+        config.save(&root).unwrap();
+
+        let logged = question("q-both", Some("s"), None);
+        let archived_only = question("q-archived", Some("s"), None);
+        ask(&root, &logged);
+        let record = |q: &Question| ArchiveRecord {
+            channel: "team:question".into(),
+            timestamp: Utc::now(),
+            message: encode_pulse(&q.to_pulse()).unwrap(),
+        };
+        let mut archive = Archive::open(&root.join(".hydra/mail.db")).unwrap();
+        // Same pulse as the log line, so it's listed once
+        let logged_pulse = MessageLog::open(&root.join(".hydra/messages.log")).unwrap().replay().unwrap()[0].message.clone();
+        archive.insert(&[ArchiveRecord { message: logged_pulse, ..record(&logged) }, record(&archived_only)]).unwrap();
+        drop(archive);
+
+        let history = mail_history(&root).unwrap();
+        let questions: Vec<_> = history.iter().filter_map(|p| Question::from_pulse(&p.pulse)).map(|q| q.id).collect();
+        assert_eq!(questions, vec!["q-both", "q-archived"]);
+        fs::remove_dir_all(&root).ok();
+    }
+
+    // ─────────────────────────────────────────────────────────────────────
+    // Round trip through an orchestrator
+    // ─────────────────────────────────────────────────────────────────────
+
+    /// Project with hydra-mail initialized and one spawned session
+    fn project() -> (std::path::PathBuf, Orchestrator, SessionId) {
+        let root = std::env::temp_dir().join(format!("hydra-inbox-{}", uuid::Uuid::new_v4().simple()));
+        fs::create_dir_all(root.join(".hydra/ralph")).unwrap();
+        hydra_mail::config::Config::init(&root).unwrap();
+
+        let mut orch = Orchestrator::with_backends(&root, Arc::new(FakeClock::new()), Arc::new(FakeTmux::new()), Arc::new(FakeStore::new()));
+        let id = orch
+            .spawn(SessionConfig {
+                working_dir: root.clone(),
+                prd_path: root.join(".hydra/ralph/prd.json"),
+                ..SessionConfig::default()
+            })
+            .unwrap();
+        (root, orch, id)
+    }
+
+    /// What the daemon logs when an agent in `session` asks
+    fn ask(root: &Path, question: &Question) {
+        let config = hydra_mail::config::Config::load(root).unwrap();
+        let entry = LogEntry {
+            project_uuid: config.project_uuid,
+            channel: "team:question".into(),
+            message: encode_pulse(&question.to_pulse()).unwrap(),
+            timestamp: Utc::now(),
+            target: None,
+        };
+        let mut log = fs::OpenOptions::new().create(true).append(true).open(root.join(".hydra/messages.log")).unwrap();
+        std::io::Write::write_all(&mut log, format!("{}\n", serde_json::to_string(&entry).unwrap()).as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_answer_without_reply_channel_injects() {
+        let (root, mut orch, id) = project();
+        assert!(orch.inbox().unwrap().is_empty());
+
+        ask(&root, &question("q-inject", Some(&id.0), None));
+        let inbox = orch.inbox().unwrap();
+        assert_eq!(ids(&inbox), vec!["q-inject"]);
+        assert_eq!(inbox[0].session, id.0);
+
+        let (entry, delivery) = orch.answer("q-inj", "Use the staging database").unwrap();
+        assert_eq!(entry.question.id, "q-inject");
+        assert_eq!(delivery, Delivery::Inject { session: id.0.clone() });
+
+        let injected = fs::read_to_string(root.join(".hydra/ralph/inject.md")).unwrap();
+        assert!(injected.contains("q-inject") && injected.contains("Use the staging database"), "{}", injected);
+        let answered = load_answered(&root.join(".hydra/orchestrator/answered.json")).unwrap();
+        assert_eq!(answered["q-inject"].delivery, delivery);
+
+        // Answered questions leave the inbox and can't be answered twice
+        assert!(orch.inbox().unwrap().is_empty());
+        assert!(orch.answer("q-inject", "again").is_err());
+        let (events, _) = orch.journal().read_from(0).unwrap();
+        let last = events.last().unwrap();
+        assert_eq!((last.event_type.as_str(), last.event.as_str()), ("session", "answered"));
+        assert_eq!(last.data["question_id"], "q-inject");
+        fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn test_answer_with_reply_channel_emits_pulse() {
+        let (root, mut orch, id) = project();
+        let trace_id = orch.get_status(&id).unwrap().trace_id.clone().unwrap();
+        let mut asked = question("q-reply", None, Some(&trace_id));
+        asked.reply_channel = Some("team:answers".into());
+        ask(&root, &asked);
+
+        let config = hydra_mail::config::Config::load(&root).unwrap();
+        let listener = std::os::unix::net::UnixListener::bind(&config.socket_path).unwrap();
+        // Ack every emit like the daemon, handing the commands back
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut line = String::new();
+                BufReader::new(&stream).read_line(&mut line).unwrap();
+                let cmd: serde_json::Value = serde_json::from_str(&line).unwrap();
+                writeln!(stream, "{}", json!({"status": "ok", "channel": cmd["channel"], "receivers": 0})).unwrap();
+                if tx.send(cmd).is_err() {
+                    break;
+                }
+            }
+        });
+        orch.mail = Some(MailClient::locate(&root).unwrap());
+
+        let (_, delivery) = orch.answer("q-reply", "OIDC").unwrap();
+        assert_eq!(delivery, Delivery::Channel { channel: "team:answers".into() });
+        assert!(!root.join(".hydra/ralph/inject.md").exists());
+
+        // The emit command carrying the answer pulse
+        let cmd = rx.try_iter().find(|cmd| cmd["channel"] == "team:answers").unwrap();
+        use base64::Engine as _;
+        let toon = base64::engine::general_purpose::STANDARD.decode(cmd["data"].as_str().unwrap()).unwrap();
+        let pulse = decode_pulse(&String::from_utf8(toon).unwrap()).unwrap();
+        assert_eq!(pulse["metadata"]["trace_id"], trace_id.as_str());
+        let answer = Answer::from_pulse(&pulse).unwrap();
+        assert_eq!(answer, Answer { question_id: "q-reply".into(), answer: "OIDC".into(), from: Some("human".into()) });
+
+        assert!(orch.inbox().unwrap().is_empty());
+        fs::remove_file(&config.socket_path).ok();
+        fs::remove_dir_all(&root).ok();
+    }
+}
@@ -4,72 +4,159 @@
 
 mod tmux;
 mod session;
-mod mail;
 mod config;
 mod store;
+mod events;
+mod limits;
+mod shell;
+mod backend;
+mod simulate;
+mod timefmt;
+mod inbox;
+mod retry;
 
 pub use session::{SessionId, SessionConfig, SessionState, Session, SessionStatus};
-pub use mail::HydraMailClient;
+pub use hydra_mail::client::MailClient;
 pub use config::HydralphConfig;
 pub use store::find_project_root;
+pub use events::{event_types, Event, EventCursor, EventFollower, EventJournal, StreamEvent};
+use events::with_trace;
+pub use limits::{LimitMechanism, ResourceLimits, ResourceUsage, SystemdCommands};
+pub use backend::{Clock, FakeClock, FakeStore, FakeTmux, RecordStore, SystemClock, SystemTmux, TmuxBackend};
+pub use simulate::{Scenario, SimulationReport, Simulator};
+pub use inbox::{AnsweredRecord, Delivery, InboxEntry};
+pub use retry::{NotBlocked, RetryOutcome, DEFAULT_GUIDANCE};
+pub use hydra_mail::pulse::{Answer, Question};
+pub use timefmt::{compact_eta, human_duration, is_human_time, relative_time, relative_time_at};
 
 use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use anyhow::{Result, Context};
 use store::SessionStore;
 use serde::Deserialize;
+use tracing::{debug, info, warn};
 
 #[cfg(unix)]
 use std::os::unix::process::CommandExt;
 
+/// Working dir, worktree path, allocated port and branch chosen at spawn
+/// time, and whether the worktree was there already
+type WorkspaceSetup = (PathBuf, Option<PathBuf>, Option<u16>, Option<String>, bool);
+
+/// Names tried for a session's branch, `<branch>`, `<branch>-2`, ..., when
+/// the earlier ones are checked out in another worktree
+#[cfg(feature = "worktree")]
+const BRANCH_ATTEMPTS: u32 = 5;
+
+/// hydra-wt's environment overrides for a session: its `worktree_dir`, if
+/// it asks for one, in place of `HYDRA_WT_DIR`
+#[cfg(feature = "worktree")]
+fn wt_env(config: &SessionConfig) -> hydra_wt::config::EnvOverrides {
+    let mut env = hydra_wt::config::EnvOverrides::from_env();
+    if let Some(dir) = &config.worktree_dir {
+        env.worktrees_dir = Some(dir.to_string_lossy().to_string());
+    }
+    env
+}
+
 // ═══════════════════════════════════════════════════════════════════════════
 // Orchestrator
 // ═══════════════════════════════════════════════════════════════════════════
 
 pub struct Orchestrator {
     sessions: HashMap<String, Session>,
-    mail: Option<HydraMailClient>,
+    mail: Option<MailClient>,
     store: SessionStore,
+    records: Arc<dyn RecordStore>,
+    clock: Arc<dyn Clock>,
+    tmux: Arc<dyn TmuxBackend>,
+    systemd: SystemdCommands,
+    max_running: Option<usize>,
+    /// Queued sessions, first in line first
+    queue: Vec<SessionId>,
 }
 
 impl Orchestrator {
     pub fn new() -> Self {
         let root = find_project_root();
-        let store = SessionStore::new(root);
         let mut orch = Self {
             sessions: HashMap::new(),
             mail: None,
-            store,
+            store: SessionStore::new(root.clone()),
+            records: Arc::new(SessionStore::new(root)),
+            clock: Arc::new(SystemClock),
+            tmux: Arc::new(SystemTmux),
+            systemd: SystemdCommands::default(),
+            max_running: None,
+            queue: Vec::new(),
         };
         if let Err(e) = orch.load_sessions() {
-            eprintln!("Warning: Failed to load sessions: {}", e);
+            warn!("failed to load sessions: {}", e);
         }
         orch
     }
 
     /// Create orchestrator with hydra-mail integration
     pub fn with_mail(project_root: &Path) -> Result<Self> {
-        let mail = HydraMailClient::connect(project_root)
-            .ok();  // Graceful degradation if mail not available
-        let store = SessionStore::new(project_root.to_path_buf());
+        // Graceful degradation if mail not available
+        let mail = match MailClient::connect_blocking(project_root) {
+            Ok(mail) => Some(mail),
+            Err(e) => {
+                debug!("hydra-mail unavailable: {:#}", e);
+                None
+            }
+        };
         let mut orch = Self {
             sessions: HashMap::new(),
             mail,
-            store,
+            store: SessionStore::new(project_root.to_path_buf()),
+            records: Arc::new(SessionStore::new(project_root.to_path_buf())),
+            clock: Arc::new(SystemClock),
+            tmux: Arc::new(SystemTmux),
+            systemd: SystemdCommands::default(),
+            max_running: None,
+            queue: Vec::new(),
         };
         if let Err(e) = orch.load_sessions() {
-            eprintln!("Warning: Failed to load sessions: {}", e);
+            warn!("failed to load sessions: {}", e);
         }
         // Show mail status
         if orch.mail.is_some() {
-            eprintln!("✓ Connected to hydra-mail");
+            info!("✓ Connected to hydra-mail");
         } else {
-            eprintln!("Note: hydra-mail not available - running standalone");
+            info!("hydra-mail not available - running standalone");
         }
         Ok(orch)
     }
 
+    /// Create orchestrator over injected clock, tmux and record store,
+    /// without hydra-mail. Used by the simulator; the event journal and
+    /// worktrees still live under `project_root`.
+    pub fn with_backends(project_root: &Path, clock: Arc<dyn Clock>, tmux: Arc<dyn TmuxBackend>, records: Arc<dyn RecordStore>) -> Self {
+        let mut orch = Self {
+            sessions: HashMap::new(),
+            mail: None,
+            store: SessionStore::new(project_root.to_path_buf()),
+            records,
+            clock,
+            tmux,
+            systemd: SystemdCommands::default(),
+            max_running: None,
+            queue: Vec::new(),
+        };
+        if let Err(e) = orch.load_sessions() {
+            warn!("failed to load sessions: {}", e);
+        }
+        orch
+    }
+
+    /// Use these programs to start, stop and inspect session scopes
+    pub fn set_systemd_commands(&mut self, systemd: SystemdCommands) {
+        self.systemd = systemd;
+    }
+
     /// Check if hydra-mail is connected
     pub fn has_mail(&self) -> bool {
         self.mail.is_some()
@@ -79,30 +166,63 @@ impl Orchestrator {
     // Spawn
     // ─────────────────────────────────────────────────────────────────────────
 
+    /// Start a session, or queue it while one of `config.depends_on` hasn't
+    /// completed or [`Orchestrator::set_max_running`] sessions already run.
+    /// `tick()` starts queued sessions once they can go.
     pub fn spawn(&mut self, config: SessionConfig) -> Result<SessionId> {
         let id = SessionId::new();
-        let mut config = config;
+        config.resource_limits.validate()?;
+        if self.must_queue(&config)? {
+            self.enqueue(&id, config)?;
+        } else {
+            self.start(&id, config)?;
+        }
+        Ok(id)
+    }
+
+    /// How many sessions may run at once (`None`, the default, for no
+    /// limit). Paused, blocked and stuck sessions hold their slot; queued and
+    /// finished ones don't.
+    pub fn set_max_running(&mut self, max: Option<usize>) {
+        self.max_running = max;
+    }
+
+    fn start(&mut self, id: &SessionId, mut config: SessionConfig) -> Result<()> {
+        // Preflight: only probe systemd when there is something to enforce
+        let systemd = !config.resource_limits.is_empty() && limits::systemd_available(&self.systemd);
 
         // Worktree integration (if feature enabled)
-        let (working_dir, worktree_path, allocated_port, branch_name): (PathBuf, Option<PathBuf>, Option<u16>, Option<String>) = if config.use_worktree {
+        let (working_dir, worktree_path, allocated_port, branch_name, worktree_reused): WorkspaceSetup = if config.use_worktree {
             #[cfg(feature = "worktree")]
             {
                 // Try worktree creation, fallback to main dir if it fails
-                match self.try_create_worktree(&id, &config) {
+                match self.try_create_worktree(id, &config) {
                     Ok(result) => result,
+                    // Running from the main checkout would silently ignore the base ref or port
+                    Err(e) if config.base_ref.is_some() || config.requested_port.is_some() => {
+                        return Err(e.context("Failed to create worktree"))
+                    }
+                    // Every later spawn would hit it too; the range needs widening
+                    Err(e) if matches!(e.downcast_ref(), Some(hydra_wt::WtError::PortRangeExhausted { .. })) => {
+                        return Err(e.context("Failed to create worktree"))
+                    }
+                    // The branch is being worked on elsewhere; the main checkout may be that place
+                    Err(e) if matches!(e.downcast_ref(), Some(hydra_wt::WtError::BranchCheckedOut { .. })) => {
+                        return Err(e.context("Failed to create worktree"))
+                    }
                     Err(e) => {
-                        eprintln!("Warning: Worktree creation failed: {}. Using main directory.", e);
-                        (self.store.root().to_path_buf(), None, None, None)
+                        warn!("worktree creation failed: {}. Using main directory.", e);
+                        (self.store.root().to_path_buf(), None, None, None, false)
                     }
                 }
             }
             #[cfg(not(feature = "worktree"))]
             {
-                eprintln!("Warning: worktree feature not enabled, running in main directory");
-                (self.store.root().to_path_buf(), None, None, None)
+                warn!("worktree feature not enabled, running in main directory");
+                (self.store.root().to_path_buf(), None, None, None, false)
             }
         } else {
-            (self.store.root().to_path_buf(), None, None, None)
+            (self.store.root().to_path_buf(), None, None, None, false)
         };
 
         // Update config to reflect actual working directory and branch
@@ -115,39 +235,45 @@ impl Orchestrator {
         let ralph_dir = working_dir.join(".hydra/ralph");
         fs::create_dir_all(&ralph_dir)
             .context("Failed to create .hydra/ralph directory")?;
+        // A status left by an earlier session in this directory would be read
+        // as this one's
+        let _ = fs::remove_file(ralph_dir.join("status.json"));
 
         // Copy hydralph.sh and prompt.md if not present
         self.init_ralph_files(&ralph_dir, &config)?;
 
         // Create TMUX session
         let tmux_session = format!("hydralph-{}", id.0);
-        tmux::new_session(&tmux_session, &working_dir)?;
+        self.tmux.new_session(&tmux_session, &working_dir)?;
+
+        // Every emit made inside the session carries this trace id
+        let trace_id = uuid::Uuid::new_v4().simple().to_string();
 
         // Set environment and start hydralph
-        let port_env = if let Some(port) = allocated_port {
-            format!(" HYDRALPH_PORT='{}'", port)
-        } else {
-            String::new()
-        };
+        let mut env = vec![
+            ("HYDRALPH_SESSION_ID", id.0.clone()),
+            ("HYDRA_TRACE_ID", trace_id.clone()),
+            ("HYDRALPH_AGENT", config.agent_cli.clone()),
+            ("HYDRALPH_FLAGS", config.agent_flags.clone()),
+            ("HYDRALPH_MAX_ITERATIONS", config.max_iterations.to_string()),
+            ("HYDRALPH_PRD", ralph_dir.join("prd.json").display().to_string()),
+        ];
+        if let Some(port) = allocated_port {
+            env.push(("HYDRALPH_PORT", port.to_string()));
+        }
+        // hydra-wt run by the agent finds its worktree where it was made
+        if let (Some(dir), Some(_)) = (&config.worktree_dir, &worktree_path) {
+            env.push(("HYDRA_WT_DIR", dir.display().to_string()));
+        }
+        self.tmux.send_keys(&tmux_session, &shell::export(&env))?;
 
-        let env_cmd = format!(
-            "export HYDRALPH_SESSION_ID='{}' \
-             HYDRALPH_AGENT='{}' \
-             HYDRALPH_FLAGS='{}' \
-             HYDRALPH_MAX_ITERATIONS='{}' \
-             HYDRALPH_PRD='{}'{}",
-            id.0,
-            config.agent_cli,
-            config.agent_flags,
-            config.max_iterations,
-            ralph_dir.join("prd.json").display(),
-            port_env
-        );
-        tmux::send_keys(&tmux_session, &env_cmd)?;
-
-        // Start the loop
+        // Start the loop, inside a resource-limited scope if requested
         let script_path = ralph_dir.join("hydralph.sh").display().to_string();
-        tmux::send_keys(&tmux_session, &script_path)?;
+        let launch = limits::plan_launch(&config.resource_limits, &id.0, &script_path, systemd.then_some(&self.systemd));
+        for warning in &launch.warnings {
+            warn!("{}", warning);
+        }
+        self.tmux.send_keys(&tmux_session, &launch.command_line())?;
 
         // Track session
         let session = Session {
@@ -156,66 +282,254 @@ impl Orchestrator {
             state: SessionState::Starting,
             tmux_session,
             worktree_path,
+            worktree_reused,
             allocated_port,
-            started_at: std::time::Instant::now(),
-            last_activity: std::time::Instant::now(),
+            limit_mechanism: launch.mechanism,
+            scope_unit: launch.scope_unit,
+            trace_id: Some(trace_id),
+            started_at: self.clock.now(),
+            last_activity: self.clock.now(),
+            activity_marker: None,
         };
         self.sessions.insert(id.0.clone(), session);
 
         if let Some(session) = self.sessions.get(&id.0) {
             let record = session::SessionRecord::from_session(session);
-            self.store.save(&record)?;
+            self.records.save(&record)?;
         }
 
         // Emit to hydra-mail
-        if let Err(e) = self.emit("session:spawned", &id) {
-            eprintln!("Warning: Failed to emit to hydra-mail: {}", e);
+        let spawned = self.sessions.get(&id.0).map(|s| serde_json::json!({
+            "branch": s.config.branch_name,
+            "worktree": s.worktree_path,
+            "port": s.allocated_port,
+        })).unwrap_or_default();
+        if let Err(e) = self.publish("session:spawned", id, spawned) {
+            warn!("failed to emit to hydra-mail: {}", e);
         }
 
-        Ok(id)
+        Ok(())
+    }
+
+    // ─────────────────────────────────────────────────────────────────────────
+    // Queue
+    // ─────────────────────────────────────────────────────────────────────────
+
+    /// Whether a session can't start yet; errors if it never could
+    fn must_queue(&self, config: &SessionConfig) -> Result<bool> {
+        for dep in &config.depends_on {
+            match self.dependency(dep)? {
+                Dependency::Completed => {}
+                Dependency::Pending => return Ok(true),
+                Dependency::Failed(reason) => anyhow::bail!("Session {} won't complete: {}", dep, reason),
+            }
+        }
+        Ok(!self.has_free_slot())
+    }
+
+    fn dependency(&self, id: &SessionId) -> Result<Dependency> {
+        match self.sessions.get(&id.0).map(|s| &s.state) {
+            Some(SessionState::Completed { .. }) => Ok(Dependency::Completed),
+            Some(SessionState::Failed { reason }) => Ok(Dependency::Failed(reason.clone())),
+            Some(_) => Ok(Dependency::Pending),
+            // Gone: only the journal remembers how it ended
+            None => {
+                let (events, _) = self.journal().read_from(0)?;
+                let mut seen = false;
+                for event in events.iter().filter(|e| e.session == id.0) {
+                    if event.event_type == "session" && event.event == "completed" {
+                        return Ok(Dependency::Completed);
+                    }
+                    seen = true;
+                }
+                Ok(Dependency::Failed(if seen { "it ended without completing" } else { "no such session" }.into()))
+            }
+        }
+    }
+
+    fn has_free_slot(&self) -> bool {
+        self.max_running.is_none_or(|max| {
+            let running = self.sessions.values().filter(|s| matches!(s.state,
+                SessionState::Starting | SessionState::Running { .. } | SessionState::Paused
+                    | SessionState::Blocked { .. } | SessionState::Stuck { .. }
+            )).count();
+            running < max
+        })
+    }
+
+    fn enqueue(&mut self, id: &SessionId, config: SessionConfig) -> Result<()> {
+        let now = self.clock.now();
+        let depends_on: Vec<String> = config.depends_on.iter().map(|dep| dep.0.clone()).collect();
+        let session = Session {
+            id: id.clone(),
+            config,
+            state: SessionState::Queued,
+            tmux_session: format!("hydralph-{}", id.0),
+            worktree_path: None,
+            worktree_reused: false,
+            allocated_port: None,
+            limit_mechanism: None,
+            scope_unit: None,
+            trace_id: None,
+            started_at: now,
+            last_activity: now,
+            activity_marker: None,
+        };
+        self.records.save(&session::SessionRecord::from_session(&session))?;
+        self.sessions.insert(id.0.clone(), session);
+        self.queue.push(id.clone());
+        if let Err(e) = self.publish("session:queued", id, serde_json::json!({ "depends_on": depends_on })) {
+            warn!("failed to emit to hydra-mail: {}", e);
+        }
+        Ok(())
+    }
+
+    /// Start the queued sessions that can go, in queue order, while slots
+    /// are free. One whose dependency won't complete fails.
+    fn promote_queued(&mut self) -> Result<Vec<SessionId>> {
+        let mut promoted = vec![];
+        'queue: for id in self.queue.clone() {
+            let depends_on = match self.sessions.get(&id.0) {
+                Some(session) => session.config.depends_on.clone(),
+                None => continue,
+            };
+            for dep in &depends_on {
+                match self.dependency(dep)? {
+                    Dependency::Completed => {}
+                    Dependency::Pending => continue 'queue,
+                    Dependency::Failed(reason) => {
+                        self.fail_queued(&id, format!("Dependency {} won't complete: {}", dep, reason));
+                        continue 'queue;
+                    }
+                }
+            }
+            if !self.has_free_slot() {
+                continue;
+            }
+            let Some(session) = self.sessions.remove(&id.0) else { continue };
+            self.queue.retain(|queued| *queued != id);
+            match self.start(&id, session.config.clone()) {
+                Ok(()) => promoted.push(id),
+                Err(e) => {
+                    self.sessions.insert(id.0.clone(), session);
+                    self.fail_queued(&id, format!("Failed to start: {:#}", e));
+                }
+            }
+        }
+        Ok(promoted)
+    }
+
+    /// The next refresh drops it, as it has no tmux session
+    fn fail_queued(&mut self, id: &SessionId, reason: String) {
+        warn!("queued session {} failed: {}", id.0, reason);
+        self.queue.retain(|queued| queued != id);
+        if let Some(session) = self.sessions.get_mut(&id.0) {
+            session.state = SessionState::Failed { reason: reason.clone() };
+        }
+        match self.records.transition(id, "queued", "failed") {
+            Ok(Some(from)) => {
+                let change = serde_json::json!({ "from": from, "to": "failed", "reason": reason });
+                let _ = self.publish("session:state", id, change);
+            }
+            Ok(None) => {}
+            Err(e) => warn!("failed to save the state of {}: {}", id.0, e),
+        }
     }
 
     // ─────────────────────────────────────────────────────────────────────────
     // Worktree helper
     // ─────────────────────────────────────────────────────────────────────────
 
+    /// Create the session's worktree the way `hydra-wt create` does, with
+    /// artifacts, env files and hooks, rolled back if any of it fails. A
+    /// branch checked out in another worktree can't be checked out again:
+    /// that worktree is reused if no other session is running in it, and
+    /// otherwise the session gets a new branch started from it, `<branch>-2`
+    /// or the next name that is free.
     #[cfg(feature = "worktree")]
-    fn try_create_worktree(&self, id: &SessionId, config: &SessionConfig) -> Result<(PathBuf, Option<PathBuf>, Option<u16>, Option<String>)> {
-        let branch = config.branch_name.clone()
+    fn try_create_worktree(&self, id: &SessionId, config: &SessionConfig) -> Result<WorkspaceSetup> {
+        use hydra_wt::WtError;
+
+        let requested = config.branch_name.clone()
             .unwrap_or_else(|| format!("hydralph/{}", id.0));
 
+        let root = self.store.root();
+
         // Initialize port registry if needed
-        if let Err(e) = hydra_wt::ports::PortRegistry::init() {
-            eprintln!("Warning: Failed to init port registry: {}", e);
+        if let Err(e) = hydra_wt::ports::PortRegistry::init_at(root) {
+            warn!("failed to init port registry: {}", e);
         }
 
-        let wt_config = match hydra_wt::config::WtConfig::load() {
-            Ok(cfg) => cfg,
+        let wt_config = match hydra_wt::config::WtConfig::load_layered_with(root, &wt_env(config)) {
+            Ok(layered) => layered.config,
             Err(e) => {
-                eprintln!("Warning: Failed to load hydra-wt config: {}. Using defaults.", e);
+                warn!("failed to load hydra-wt config: {}. Using defaults.", e);
                 hydra_wt::config::WtConfig::default()
             }
         };
 
-        let mut registry = hydra_wt::ports::PortRegistry::load()
-            .unwrap_or_default();
-
-        // Allocate port
-        let port = registry.allocate(&branch, wt_config.ports.range_start, wt_config.ports.range_end)?;
+        let owner = format!("orchestrator:{}", id.0);
+        let mut branch = requested.clone();
+        let mut attempt = 1;
+        let created = loop {
+            let mut opts = hydra_wt::CreateOptions::new(&branch).owner(&owner);
+            // A retry starts its new branch where the requested one is
+            if let Some(base_ref) = config.base_ref.as_deref().or((branch != requested).then_some(requested.as_str())) {
+                opts = opts.base_ref(base_ref);
+            }
+            if let Some(port) = config.requested_port {
+                opts = opts.port(port);
+            }
+            let branch_config = wt_config.resolve_for(&branch)?;
+            let err = match hydra_wt::create_worktree(&branch_config, root, opts) {
+                Ok(created) => break created,
+                Err(e) => e,
+            };
+            let Some(WtError::BranchCheckedOut { path: checked_out_at, .. }) = err.downcast_ref() else {
+                return Err(err);
+            };
+            if branch == requested && self.can_reuse_worktree(checked_out_at) {
+                let port = hydra_wt::ports::PortRegistry::load_at(root)?.get(&branch);
+                if let Some(requested_port) = config.requested_port.filter(|p| Some(*p) != port) {
+                    anyhow::bail!(
+                        "'{}' is checked out at {} with port {}, not {}",
+                        branch, checked_out_at.display(),
+                        port.map(|p| p.to_string()).unwrap_or_else(|| "none".to_string()), requested_port
+                    );
+                }
+                info!("Reusing the worktree of '{}' at {}", branch, checked_out_at.display());
+                return Ok((checked_out_at.clone(), Some(checked_out_at.clone()), port, Some(branch), true));
+            }
 
-        // Create worktree (base path is project root)
-        let wt_base = PathBuf::from(&wt_config.worktrees.directory);
-        let wt_path = if wt_base.is_absolute() {
-            wt_base.join(&branch)
-        } else {
-            self.store.root().join(wt_base).join(&branch)
+            let mut next = None;
+            while next.is_none() && attempt < BRANCH_ATTEMPTS {
+                attempt += 1;
+                let candidate = format!("{}-{}", requested, attempt);
+                if !hydra_wt::worktree::branch_exists_in(root, &candidate)? {
+                    next = Some(candidate);
+                }
+            }
+            let Some(next) = next else {
+                return Err(err);
+            };
+            warn!("branch '{}' is checked out at {}, using '{}'", branch, checked_out_at.display(), next);
+            branch = next;
         };
-        hydra_wt::worktree::add(&wt_path, &branch)?;
 
-        // Save port allocation
-        registry.save()?;
+        // The session records the first port of the block
+        Ok((created.path.clone(), Some(created.path), created.port, Some(created.branch), false))
+    }
 
-        Ok((wt_path.clone(), Some(wt_path), Some(port), Some(branch)))
+    /// Whether a session may run in the worktree at `path`: not the main
+    /// checkout, and no other session runs there
+    #[cfg(feature = "worktree")]
+    fn can_reuse_worktree(&self, path: &Path) -> bool {
+        let same = |a: &Path, b: &Path| match (a.canonicalize(), b.canonicalize()) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => a == b,
+        };
+        !same(path, self.store.root())
+            && !self.sessions.values().any(|s| s.worktree_path.as_deref().is_some_and(|p| same(p, path)))
     }
 
     // ─────────────────────────────────────────────────────────────────────────
@@ -226,16 +540,26 @@ impl Orchestrator {
         let session = self.sessions.get_mut(&id.0)
             .ok_or_else(|| anyhow::anyhow!("Session not found: {}", id.0))?;
 
-        tmux::kill_session(&session.tmux_session)?;
+        // A queued session has nothing running yet
+        if matches!(session.state, SessionState::Queued) {
+            self.queue.retain(|queued| queued != id);
+        } else {
+            limits::stop_session_tree(&self.systemd, session.scope_unit.as_deref(), &session.tmux_session, self.tmux.as_ref())?;
+        }
         session.state = SessionState::Failed { reason: reason.to_string() };
 
         // Cleanup worktree if we created one
-        if let Some(wt_path) = session.worktree_path.take() {
+        if let Some(wt_path) = session.worktree_path.take().filter(|_| !session.worktree_reused) {
             #[cfg(feature = "worktree")]
             {
                 // Remove worktree
-                if let Err(e) = hydra_wt::worktree::remove(&wt_path, true) {
-                    eprintln!("Warning: Failed to remove worktree: {}", e);
+                if let Err(e) = hydra_wt::worktree::remove_in(self.store.root(), &wt_path) {
+                    warn!("failed to remove worktree: {}", e);
+                }
+                // `hydralph/<id>` leaves an empty `hydralph/` behind
+                if let Ok(layered) = hydra_wt::config::WtConfig::load_layered_with(self.store.root(), &wt_env(&session.config)) {
+                    let worktrees_dir = layered.config.worktree_dir_in(self.store.root());
+                    hydra_wt::worktree::remove_empty_parents(&wt_path, &worktrees_dir);
                 }
 
                 // Free allocated port
@@ -243,10 +567,10 @@ impl Orchestrator {
                     let branch_name = session.config.branch_name.clone()
                         .unwrap_or_else(|| format!("hydralph/{}", id.0));
                     let branch = branch_name.as_str();
-                    if let Ok(mut registry) = hydra_wt::ports::PortRegistry::load() {
+                    if let Ok(mut registry) = hydra_wt::ports::PortRegistry::load_at(self.store.root()) {
                         if let Ok(freed_port) = registry.free(branch) {
-                            eprintln!("Freed port {} for branch '{}'", freed_port, branch);
-                            let _ = registry.save();
+                            info!("Freed port {} for branch '{}'", freed_port, branch);
+                            let _ = registry.save_at(self.store.root());
                         }
                     }
                 }
@@ -255,11 +579,11 @@ impl Orchestrator {
             }
         }
 
-        self.sessions.remove(&id.0);
-        self.store.remove(id)?;
+        // Emit to hydra-mail (before forgetting the session, for its trace id)
+        let _ = self.publish("session:killed", id, serde_json::json!({ "reason": reason }));
 
-        // Emit to hydra-mail
-        let _ = self.emit("session:killed", id);
+        self.sessions.remove(&id.0);
+        self.records.remove(id)?;
 
         Ok(())
     }
@@ -290,9 +614,11 @@ impl Orchestrator {
         }
 
         // Send a key to wake up the session if it's waiting
-        tmux::send_keys(&session.tmux_session, "echo 'Resumed...'")?;
+        self.tmux.send_keys(&session.tmux_session, "echo 'Resumed...'")?;
 
         session.state = SessionState::Running { iteration: 0, stories: "unknown".into() };
+        // Time spent paused doesn't count towards stuck detection
+        session.last_activity = self.clock.now();
         let _ = self.emit("session:resumed", id);
         Ok(())
     }
@@ -308,24 +634,265 @@ impl Orchestrator {
         Ok(())
     }
 
+    /// Continue a blocked session: queue `guidance` (or [`DEFAULT_GUIDANCE`])
+    /// in inject.md, clear the blocked status and signal hydralph to go on,
+    /// with `extra_iterations` added to its budget. Fails with [`NotBlocked`]
+    /// before changing anything if the session isn't blocked; a later failure
+    /// names the steps that already happened.
+    pub fn retry_blocked(&mut self, id: &SessionId, guidance: Option<&str>, extra_iterations: u32) -> Result<RetryOutcome> {
+        if !self.refresh_state(id)? {
+            anyhow::bail!("Session not found or no longer running: {}", id.0);
+        }
+        let session = self.sessions.get(&id.0)
+            .ok_or_else(|| anyhow::anyhow!("Session not found: {}", id.0))?;
+        let iteration = match &session.state {
+            SessionState::Blocked { iteration, .. } => *iteration,
+            other => return Err(NotBlocked { session: id.0.clone(), state: other.name() }.into()),
+        };
+        let ralph_dir = session.config.working_dir.join(".hydra/ralph");
+        if !retry::script_supports_unblock(&ralph_dir) {
+            anyhow::bail!(
+                "Session {} runs a hydralph.sh that predates retry-blocked and has already exited. \
+                Inject guidance and spawn a new session instead",
+                id.0
+            );
+        }
+        let Some(max_iterations) = session.config.max_iterations.checked_add(extra_iterations) else {
+            anyhow::bail!(
+                "Session {} can't take {} more iterations on top of its {}",
+                id.0, extra_iterations, session.config.max_iterations
+            );
+        };
+        let guidance = guidance.unwrap_or(DEFAULT_GUIDANCE);
+
+        let mut steps = retry::Steps::default();
+        steps.run("append guidance to inject.md", retry::append_inject(&ralph_dir.join("inject.md"), guidance))?;
+        steps.run("clear the blocked status", retry::clear_blocked_status(&ralph_dir.join("status.json"), max_iterations))?;
+        steps.run("signal hydralph to continue", retry::signal_unblock(&ralph_dir, extra_iterations))?;
+
+        let now = self.clock.now();
+        let session = self.sessions.get_mut(&id.0)
+            .ok_or_else(|| anyhow::anyhow!("Session not found: {}", id.0))?;
+        session.config.max_iterations = max_iterations;
+        session.state = SessionState::Running {
+            iteration,
+            stories: read_status(&ralph_dir).and_then(|s| s.stories).unwrap_or_else(|| "unknown".into()),
+        };
+        // Time spent blocked doesn't count towards stuck detection
+        session.last_activity = now;
+        session.activity_marker = activity_marker(&ralph_dir);
+        let record = session::SessionRecord::from_session(session);
+        steps.run("save the session", self.records.save(&record))?;
+
+        let guidance_preview = retry::preview(guidance);
+        let unblocked = serde_json::json!({
+            "iteration": iteration,
+            "extra_iterations": extra_iterations,
+            "guidance": guidance_preview,
+        });
+        if let Err(e) = self.publish("session:unblocked", id, unblocked) {
+            warn!("failed to emit to hydra-mail: {}", e);
+        }
+        Ok(RetryOutcome { iteration, max_iterations, guidance_preview })
+    }
+
+    /// Rename the branch a worktree session runs on, moving its worktree and
+    /// port allocation along with it
+    pub fn rename(&mut self, id: &SessionId, new_branch: &str, keep_path: bool, force: bool) -> Result<()> {
+        let session = self.sessions.get(&id.0)
+            .ok_or_else(|| anyhow::anyhow!("Session not found: {}", id.0))?;
+
+        if session.worktree_path.is_none() {
+            anyhow::bail!("Session {} does not run in a worktree", id.0);
+        }
+
+        // Moving the directory out from under a live agent breaks its shell
+        if !keep_path && !force && self.tmux.session_exists(&session.tmux_session).unwrap_or(false) {
+            anyhow::bail!(
+                "Session {} is still running. Kill it first, rename with --keep-path, or pass --force",
+                id.0
+            );
+        }
+
+        #[cfg(feature = "worktree")]
+        {
+            let old_branch = session.config.branch_name.clone()
+                .unwrap_or_else(|| format!("hydralph/{}", id.0));
+
+            let wt_config = hydra_wt::config::WtConfig::load_layered_with(self.store.root(), &wt_env(&session.config))
+                .map(|layered| layered.config)
+                .unwrap_or_else(|_| hydra_wt::config::WtConfig::default());
+            let opts = hydra_wt::RenameOptions { keep_path, force };
+            let outcome = hydra_wt::rename::rename(&wt_config, self.store.root(), &old_branch, new_branch, opts)?;
+
+            let session = self.sessions.get_mut(&id.0)
+                .ok_or_else(|| anyhow::anyhow!("Session not found: {}", id.0))?;
+            session.config.branch_name = Some(outcome.new_branch.clone());
+            session.config.working_dir = outcome.new_path.clone();
+            session.worktree_path = Some(outcome.new_path.clone());
+
+            let record = session::SessionRecord::from_session(session);
+            if let Err(e) = self.records.save(&record) {
+                // Put the worktree back so the stored record still matches it
+                let back = hydra_wt::RenameOptions { keep_path, force: true };
+                if let Err(undo) = hydra_wt::rename::rename(&wt_config, self.store.root(), new_branch, &old_branch, back) {
+                    warn!("failed to roll back rename: {}", undo);
+                }
+                let session = self.sessions.get_mut(&id.0)
+                    .ok_or_else(|| anyhow::anyhow!("Session not found: {}", id.0))?;
+                session.config.branch_name = Some(old_branch);
+                session.config.working_dir = outcome.old_path.clone();
+                session.worktree_path = Some(outcome.old_path);
+                return Err(e);
+            }
+
+            let renamed = serde_json::json!({
+                "old_branch": outcome.old_branch,
+                "branch": outcome.new_branch,
+                "path": outcome.new_path,
+            });
+            if let Err(e) = self.publish("session:renamed", id, renamed) {
+                warn!("failed to emit to hydra-mail: {}", e);
+            }
+
+            Ok(())
+        }
+
+        #[cfg(not(feature = "worktree"))]
+        {
+            let _ = new_branch;
+            anyhow::bail!("Renaming requires the worktree feature")
+        }
+    }
+
+    /// Live usage of a session's systemd scope, if it has one
+    pub fn resource_usage(&self, id: &SessionId) -> Option<ResourceUsage> {
+        let unit = self.sessions.get(&id.0)?.scope_unit.as_deref()?;
+        limits::scope_usage(&self.systemd, unit)
+    }
+
+    /// Problems with the branch behind a worktree session (deleted branch,
+    /// upstream gone, rewritten history), as "label: description" lines
+    pub fn worktree_issues(&self, id: &SessionId) -> Vec<String> {
+        let Some(session) = self.sessions.get(&id.0) else {
+            return Vec::new();
+        };
+        if session.worktree_path.is_none() {
+            return Vec::new();
+        }
+
+        #[cfg(feature = "worktree")]
+        {
+            let branch = session.config.branch_name.clone()
+                .unwrap_or_else(|| format!("hydralph/{}", id.0));
+            match hydra_wt::health::check_branch(self.store.root(), &branch) {
+                Ok(issues) => issues.iter()
+                    .map(|i| format!("{}: {} — {}", i.label(), i.describe(), i.remediation(&branch)))
+                    .collect(),
+                Err(e) => vec![format!("check-failed: {}", e)],
+            }
+        }
+
+        #[cfg(not(feature = "worktree"))]
+        {
+            Vec::new()
+        }
+    }
+
+    /// Files that would conflict merging a worktree session's branch into
+    /// what the main checkout has checked out. When there are any, a
+    /// `merge:conflict-predicted` event lists them, so the conflict is known
+    /// before anyone attempts the merge.
+    pub fn predict_conflicts(&self, id: &SessionId) -> Result<Vec<String>> {
+        let Some(session) = self.sessions.get(&id.0) else {
+            return Ok(Vec::new());
+        };
+        if session.worktree_path.is_none() {
+            return Ok(Vec::new());
+        }
+
+        #[cfg(feature = "worktree")]
+        {
+            let root = self.store.root();
+            let branch = session.config.branch_name.clone()
+                .unwrap_or_else(|| format!("hydralph/{}", id.0));
+            let preview = hydra_wt::worktree::merge_preview(root, &branch)?;
+            if !preview.is_clean() {
+                let target = hydra_wt::worktree::get_current_branch(root)
+                    .unwrap_or_else(|_| hydra_wt::worktree::detect_main_branch_in(root));
+                let predicted = serde_json::json!({
+                    "branch": branch,
+                    "target": target,
+                    "files": preview.conflicts,
+                });
+                if let Err(e) = self.publish("merge:conflict-predicted", id, predicted) {
+                    warn!("failed to emit to hydra-mail: {}", e);
+                }
+            }
+            Ok(preview.conflicts)
+        }
+
+        #[cfg(not(feature = "worktree"))]
+        {
+            Ok(Vec::new())
+        }
+    }
+
+    /// What a worktree session's branch changed since it left the main
+    /// branch: per-file additions and deletions with their totals, as
+    /// `hydra-wt diff --json` prints them. `None` without a worktree.
+    pub fn change_summary(&self, id: &SessionId) -> Result<Option<serde_json::Value>> {
+        let Some(session) = self.sessions.get(&id.0) else {
+            return Ok(None);
+        };
+        if session.worktree_path.is_none() {
+            return Ok(None);
+        }
+
+        #[cfg(feature = "worktree")]
+        {
+            let root = self.store.root();
+            let branch = session.config.branch_name.clone()
+                .unwrap_or_else(|| format!("hydralph/{}", id.0));
+            let main = hydra_wt::worktree::detect_main_branch_in(root);
+            let base = hydra_wt::worktree::merge_base_in(root, &branch, &main)?;
+            let changes = hydra_wt::worktree::change_summary_in(root, &branch, &main)?;
+            Ok(Some(serde_json::json!({
+                "branch": branch,
+                "base": main,
+                "merge_base": base,
+                "additions": changes.insertions,
+                "deletions": changes.deletions,
+                "files": changes.files,
+            })))
+        }
+
+        #[cfg(not(feature = "worktree"))]
+        {
+            Ok(None)
+        }
+    }
+
     pub fn attach(&self, id: &SessionId) -> Result<()> {
         if let Some(session) = self.sessions.get(&id.0) {
             // This will replace current process with tmux attach
-            let _ = std::process::Command::new("tmux")
-                .args(["attach", "-t", &session.tmux_session])
-                .exec();
+            let mut cmd = std::process::Command::new("tmux");
+            cmd.args(["attach", "-t", &session.tmux_session]);
+            debug!("$ {}", hydra_log::command_line(&cmd));
+            let _ = cmd.exec();
         }
         Err(anyhow::anyhow!("Session not found: {}", id.0))
     }
 
     pub fn list(&mut self) -> Vec<SessionStatus> {
         if let Err(e) = self.refresh_all_states() {
-            eprintln!("Warning: Failed to refresh sessions: {}", e);
+            warn!("failed to refresh sessions: {}", e);
         }
+        let now = self.clock.now();
         self.sessions.values().map(|s| SessionStatus {
             id: s.id.0.clone(),
             state: format!("{:?}", s.state),
-            duration: s.started_at.elapsed(),
+            duration: now.saturating_duration_since(s.started_at),
             tmux: s.tmux_session.clone(),
         }).collect()
     }
@@ -335,19 +902,22 @@ impl Orchestrator {
             Ok(true) => {}
             Ok(false) => {
                 self.sessions.remove(&id.0);
-                let _ = self.store.remove(id);
+                let _ = self.records.remove(id);
                 return None;
             }
             Err(e) => {
-                eprintln!("Warning: Failed to refresh session {}: {}", id.0, e);
+                warn!("failed to refresh session {}: {}", id.0, e);
             }
         }
         self.sessions.get(&id.0)
     }
 
     fn load_sessions(&mut self) -> Result<()> {
-        let records = self.store.list()?;
+        let mut records = self.records.list()?;
         self.sessions.clear();
+        // Queued in the order they were spawned
+        records.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.id.cmp(&b.id)));
+        self.queue = records.iter().filter(|r| r.queued).map(|r| SessionId(r.id.clone())).collect();
         for record in records {
             let session = record.into_session();
             self.sessions.insert(session.id.0.clone(), session);
@@ -364,24 +934,81 @@ impl Orchestrator {
             }
         }
         for id in stale {
-            self.sessions.remove(&id);
-            let _ = self.store.remove(&SessionId(id));
+            let id = SessionId(id);
+            // Publish while the session is still known so the event keeps its trace id
+            let _ = self.publish("session:exited", &id, serde_json::Value::Null);
+            self.sessions.remove(&id.0);
+            let _ = self.records.remove(&id);
         }
         Ok(())
     }
 
     fn refresh_state(&mut self, id: &SessionId) -> Result<bool> {
+        let before = match self.sessions.get(&id.0) {
+            Some(session) => session.state.name(),
+            None => return Ok(false),
+        };
+        let alive = self.refresh_session(id)?;
+
+        if let Some(session) = self.sessions.get(&id.0) {
+            let after = session.state.name();
+            // Every process holding an orchestrator sees a new status.json;
+            // the one that moves the stored state reports the change. Only
+            // the process that found a session stuck knows it was, so that
+            // one reports it coming back.
+            let changed = if alive && after != before {
+                match self.records.transition(id, before, after) {
+                    Ok(_) if before == "stuck" => Some(before.to_string()),
+                    Ok(stored) => stored,
+                    Err(e) => {
+                        warn!("failed to save the state of {}: {}", id.0, e);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+            if let Some(from) = changed {
+                let change = serde_json::json!({ "from": from, "to": after });
+                let _ = self.publish("session:state", id, change);
+                // A finished branch is what gets merged next
+                if after == "completed" {
+                    let changes = self.change_summary(id).unwrap_or_else(|e| {
+                        warn!("failed to summarize changes: {}", e);
+                        None
+                    });
+                    let _ = self.publish("session:completed", id, serde_json::json!({ "changes": changes }));
+                    if let Err(e) = self.predict_conflicts(id) {
+                        warn!("failed to predict merge conflicts: {}", e);
+                    }
+                }
+            }
+        }
+        Ok(alive)
+    }
+
+    fn refresh_session(&mut self, id: &SessionId) -> Result<bool> {
         let session = match self.sessions.get_mut(&id.0) {
             Some(session) => session,
             None => return Ok(false),
         };
 
-        let session_exists = tmux::session_exists(&session.tmux_session).unwrap_or(false);
+        if matches!(session.state, SessionState::Queued) {
+            return Ok(true);
+        }
+        let session_exists = self.tmux.session_exists(&session.tmux_session).unwrap_or(false);
         if !session_exists {
             return Ok(false);
         }
 
         let ralph_dir = session.config.working_dir.join(".hydra/ralph");
+        let marker = activity_marker(&ralph_dir);
+        let active = marker != session.activity_marker;
+        if active {
+            session.activity_marker = marker;
+            session.last_activity = self.clock.now();
+        }
+
         let pause_path = ralph_dir.join(".pause");
         if pause_path.exists() {
             session.state = SessionState::Paused;
@@ -389,7 +1016,10 @@ impl Orchestrator {
         }
 
         if let Some(status) = read_status(&ralph_dir) {
-            session.state = map_status(status);
+            // A stuck session stays stuck until it shows activity again
+            if active || !matches!(session.state, SessionState::Stuck { .. }) {
+                session.state = map_status(status);
+            }
         } else if matches!(session.state, SessionState::Starting) {
             session.state = SessionState::Running {
                 iteration: 0,
@@ -439,19 +1069,127 @@ impl Orchestrator {
         Ok(())
     }
 
+    // ─────────────────────────────────────────────────────────────────────────
+    // Questions
+    // ─────────────────────────────────────────────────────────────────────────
+
+    /// Unanswered `team:question` pulses from this project's sessions, oldest first
+    pub fn inbox(&mut self) -> Result<Vec<InboxEntry>> {
+        if let Err(e) = self.refresh_all_states() {
+            warn!("failed to refresh sessions: {}", e);
+        }
+        let sessions: Vec<_> = self.sessions.values()
+            .map(|s| (s.id.0.clone(), s.trace_id.clone()))
+            .collect();
+        let history = inbox::mail_history(self.store.root())?;
+        let answered = inbox::load_answered(&self.store.answered_path())?;
+        Ok(inbox::unanswered(&history, &sessions, &answered))
+    }
+
+    /// Answer an open question, by full id or unique prefix. Goes out as an
+    /// answer pulse on the question's reply channel when it declared one and
+    /// hydra-mail is up, otherwise into the asking session's inject.md.
+    pub fn answer(&mut self, question_id: &str, text: &str) -> Result<(InboxEntry, Delivery)> {
+        let entries = self.inbox()?;
+        let entry = inbox::find(&entries, question_id)?.clone();
+        let session = SessionId(entry.session.clone());
+
+        let reply = match (&entry.question.reply_channel, &self.mail) {
+            (Some(channel), Some(mail)) => Some((channel, mail)),
+            (Some(channel), None) => {
+                warn!("hydra-mail not available, injecting the answer instead of sending it on {}", channel);
+                None
+            }
+            (None, _) => None,
+        };
+        let delivery = match reply {
+            Some((channel, mail)) => {
+                let answer = Answer {
+                    question_id: entry.question.id.clone(),
+                    answer: text.to_string(),
+                    from: Some("human".into()),
+                };
+                let trace_id = entry.question.trace_id.as_deref()
+                    .or_else(|| self.sessions.get(&session.0).and_then(|s| s.trace_id.as_deref()));
+                mail.emit_pulse_blocking(channel, &answer.to_pulse(channel, trace_id))?;
+                Delivery::Channel { channel: channel.clone() }
+            }
+            None => {
+                self.inject(&session, &inbox::inject_text(&entry.question, text))?;
+                Delivery::Inject { session: session.0.clone() }
+            }
+        };
+        debug!("answered {} via {:?}", entry.question.id, delivery);
+
+        let path = self.store.answered_path();
+        let mut answered = inbox::load_answered(&path)?;
+        answered.insert(entry.question.id.clone(), AnsweredRecord {
+            answered_at: chrono::Utc::now(),
+            delivery: delivery.clone(),
+        });
+        inbox::save_answered(&path, &answered)?;
+
+        let mut data = serde_json::to_value(&delivery)?;
+        data["question_id"] = serde_json::json!(entry.question.id);
+        let _ = self.publish("session:answered", &session, data);
+        Ok((entry, delivery))
+    }
+
+    /// The open question a blocked session's block reason refers to
+    pub fn waiting_on<'a>(&self, id: &SessionId, inbox: &'a [InboxEntry]) -> Option<&'a InboxEntry> {
+        match &self.sessions.get(&id.0)?.state {
+            SessionState::Blocked { reason, .. } => inbox::referenced_by(reason, inbox),
+            _ => None,
+        }
+    }
+
     // ─────────────────────────────────────────────────────────────────────────
     // Hydra-Mail Integration
     // ─────────────────────────────────────────────────────────────────────────
 
     /// Emit a message to hydra-mail (no-op if mail not available)
     pub fn emit(&self, channel: &str, id: &SessionId) -> Result<()> {
+        self.publish(channel, id, serde_json::Value::Null)
+    }
+
+    /// Record an event in the journal and emit it to hydra-mail with extra
+    /// fields merged into the payload
+    pub fn publish(&self, channel: &str, id: &SessionId, data: serde_json::Value) -> Result<()> {
+        // `--types` accepts the categories listed there
+        debug_assert!(events::CHANNELS.contains(&channel), "{} is missing from events::CHANNELS", channel);
+        let data = with_trace(data, self.sessions.get(&id.0).and_then(|s| s.trace_id.as_deref()));
+        let event = Event::from_channel(channel, &id.0, data.clone());
+        if let Err(e) = self.journal().append(&event) {
+            warn!("failed to record event: {}", e);
+        }
+
         if let Some(mail) = &self.mail {
-            let payload = serde_json::json!({ "session": id.0 }).to_string();
-            mail.emit(channel, &payload)?;
+            let mut payload = serde_json::json!({ "session": id.0 });
+            if let serde_json::Value::Object(fields) = data {
+                payload.as_object_mut().expect("payload is an object").extend(fields);
+            }
+            // A status pulse carrying the session's trace, else the one we're
+            // running under
+            let metadata = match payload["trace_id"].as_str() {
+                Some(trace_id) => serde_json::json!({ "trace_id": trace_id }),
+                None => serde_json::Value::Null,
+            };
+            if let Err(e) = mail.emit_blocking(channel, "status", payload, metadata) {
+                // Sending it again won't change the daemon's mind
+                if !e.is::<hydra_mail::client::Refused>() {
+                    return Err(e);
+                }
+                warn!("hydra-mail refused {}: {}", channel, e);
+            }
         }
         Ok(())
     }
 
+    /// Journal of everything this project's orchestrators have published
+    pub fn journal(&self) -> EventJournal {
+        EventJournal::new(self.store.events_path())
+    }
+
     /// Process incoming mail messages and update session states
     pub async fn process_mail(&mut self) -> Result<()> {
         // For now, this is a stub
@@ -464,14 +1202,29 @@ impl Orchestrator {
     // Health Monitoring
     // ─────────────────────────────────────────────────────────────────────────
 
+    /// One monitor pass: pick up status changes and exits, run the health
+    /// check, then start the queued sessions that can go. Returns the
+    /// sessions the health check killed.
+    pub fn tick(&mut self) -> Result<Vec<SessionId>> {
+        self.refresh_all_states()?;
+        let killed = self.health_check()?;
+        self.promote_queued()?;
+        Ok(killed)
+    }
+
     /// Check health of all sessions, enforcing duration limits and detecting stuck sessions
     pub fn health_check(&mut self) -> Result<Vec<SessionId>> {
-        let now = std::time::Instant::now();
+        let now = self.clock.now();
         let mut killed = vec![];
         let mut to_kill = vec![];
         let mut stuck_to_emit = vec![];
 
         for (id, session) in &mut self.sessions {
+            // Not started, so neither limited nor watched yet
+            if matches!(session.state, SessionState::Queued) {
+                continue;
+            }
+
             // Check duration limit
             if now.duration_since(session.started_at) > session.config.max_duration {
                 to_kill.push((SessionId(id.clone()), format!("Duration limit exceeded ({:?})", session.config.max_duration)));
@@ -479,7 +1232,7 @@ impl Orchestrator {
             }
 
             // Check if TMUX session still exists
-            let session_exists = tmux::session_exists(&session.tmux_session).unwrap_or(false);
+            let session_exists = self.tmux.session_exists(&session.tmux_session).unwrap_or(false);
             if !session_exists {
                 // Session ended - check if it was complete or failed
                 match &session.state {
@@ -495,9 +1248,11 @@ impl Orchestrator {
                 continue;
             }
 
-            // Check for stuck (no activity in 15 minutes)
+            // Check for stuck (no activity in 15 minutes). Paused and finished
+            // sessions are quiet on purpose.
             let stuck_threshold = std::time::Duration::from_secs(15 * 60);
-            if now.duration_since(session.last_activity) > stuck_threshold {
+            let can_stall = matches!(session.state, SessionState::Starting | SessionState::Running { .. } | SessionState::Stuck { .. });
+            if can_stall && now.duration_since(session.last_activity) > stuck_threshold {
                 let was_stuck = matches!(session.state, SessionState::Stuck { .. });
                 if !was_stuck {
                     let last_iter = match &session.state {
@@ -521,6 +1276,7 @@ impl Orchestrator {
 
         // Kill sessions that exceeded limits
         for (id, reason) in to_kill {
+            let _ = self.publish("health:killed", &id, serde_json::json!({ "reason": reason }));
             if self.kill(&id, &reason).is_ok() {
                 killed.push(id);
             }
@@ -530,13 +1286,22 @@ impl Orchestrator {
     }
 }
 
+/// How far a session another one depends on has got
+enum Dependency {
+    Completed,
+    Pending,
+    Failed(String),
+}
+
 #[derive(Debug, Deserialize)]
 struct RalphStatus {
     status: String,
     iteration: u32,
-    max: u32,
     #[serde(default)]
     stories: Option<String>,
+    /// The agent's `Reason:` line when blocked
+    #[serde(default)]
+    reason: Option<String>,
 }
 
 fn read_status(ralph_dir: &Path) -> Option<RalphStatus> {
@@ -545,6 +1310,22 @@ fn read_status(ralph_dir: &Path) -> Option<RalphStatus> {
     serde_json::from_str(&content).ok()
 }
 
+/// Fingerprint of the files an active loop keeps touching (status.json is
+/// rewritten every iteration, progress.txt is appended to). `None` before the
+/// loop has written either.
+fn activity_marker(ralph_dir: &Path) -> Option<u64> {
+    use std::hash::{Hash, Hasher};
+
+    let status = fs::read(ralph_dir.join("status.json")).ok();
+    let progress = fs::metadata(ralph_dir.join("progress.txt")).ok().map(|m| m.len());
+    if status.is_none() && progress.is_none() {
+        return None;
+    }
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    (status, progress).hash(&mut hasher);
+    Some(hasher.finish())
+}
+
 fn map_status(status: RalphStatus) -> SessionState {
     match status.status.as_str() {
         "running" => SessionState::Running {
@@ -556,7 +1337,7 @@ fn map_status(status: RalphStatus) -> SessionState {
         },
         "blocked" => SessionState::Blocked {
             iteration: status.iteration,
-            reason: "Agent signaled blocked".into(),
+            reason: status.reason.filter(|r| !r.is_empty()).unwrap_or_else(|| "Agent signaled blocked".into()),
         },
         "max-iterations" => SessionState::MaxIterations {
             iterations: status.iteration,
@@ -571,3 +1352,106 @@ impl Default for Orchestrator {
         Self::new()
     }
 }
+
+#[cfg(all(test, feature = "worktree"))]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn git(dir: &Path, args: &[&str]) {
+        let output = Command::new("git").arg("-C").arg(dir).args(args).output().unwrap();
+        assert!(output.status.success(), "git {:?} failed: {}", args, String::from_utf8_lossy(&output.stderr));
+    }
+
+    /// Scratch project whose worktrees go to `wts/`, with `feature`
+    /// already checked out in one made by hydra-wt
+    fn setup() -> (PathBuf, PathBuf) {
+        let root = std::env::temp_dir().join(format!("hydra-orch-wt-{}", uuid::Uuid::new_v4().simple()));
+        fs::create_dir_all(root.join(".hydra")).unwrap();
+        fs::write(root.join(".hydra/wt.local.toml"), "[worktrees]\ndirectory = \"wts\"\n").unwrap();
+        fs::write(root.join(".gitignore"), ".hydra/\nwts/\n").unwrap();
+        git(&root, &["init", "-q", "-b", "main"]);
+        git(&root, &["add", ".gitignore"]);
+        git(&root, &["-c", "user.email=test@example.com", "-c", "user.name=Test", "commit", "-q", "-m", "init"]);
+
+        hydra_wt::ports::PortRegistry::init_at(&root).unwrap();
+        let cfg = hydra_wt::config::WtConfig::load_layered_at(&root).unwrap().config;
+        let created = hydra_wt::create::create_opts(&cfg, &root, "feature", Default::default()).unwrap();
+        (root, created.path)
+    }
+
+    fn spawn_on(orch: &mut Orchestrator, root: &Path, branch: &str) -> SessionId {
+        orch.spawn(SessionConfig {
+            working_dir: root.to_path_buf(),
+            use_worktree: true,
+            branch_name: Some(branch.to_string()),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_spawn_reuses_checked_out_worktree() {
+        let (root, existing) = setup();
+        let port = hydra_wt::ports::PortRegistry::load_at(&root).unwrap().get("feature");
+        let mut orch = Orchestrator::with_backends(&root, Arc::new(FakeClock::new()), Arc::new(FakeTmux::new()), Arc::new(FakeStore::new()));
+
+        let first = spawn_on(&mut orch, &root, "feature");
+        let session = orch.get_status(&first).unwrap();
+        assert_eq!(session.worktree_path.as_deref(), Some(existing.as_path()));
+        assert_eq!(session.config.working_dir, existing);
+        assert!(session.worktree_reused);
+        assert_eq!(session.allocated_port, port);
+        assert_eq!(session.config.branch_name.as_deref(), Some("feature"));
+
+        // A session already runs there, so the next one gets its own branch
+        let second = spawn_on(&mut orch, &root, "feature");
+        let session = orch.get_status(&second).unwrap();
+        assert_eq!(session.config.branch_name.as_deref(), Some("feature-2"));
+        assert!(!session.worktree_reused);
+        let second_path = session.worktree_path.clone().unwrap();
+        assert_ne!(second_path, existing);
+        let registry = hydra_wt::ports::PortRegistry::load_at(&root).unwrap();
+        assert_eq!(registry.owner("feature-2"), format!("orchestrator:{}", second.0));
+        assert!(registry.is_owned_by("feature-2", "orchestrator"));
+
+        // Killing the reusing session leaves the worktree and its port alone
+        orch.kill(&first, "test").unwrap();
+        assert!(existing.exists());
+        assert_eq!(hydra_wt::ports::PortRegistry::load_at(&root).unwrap().get("feature"), port);
+        orch.kill(&second, "test").unwrap();
+        assert!(!second_path.exists());
+        fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn test_change_summary_follows_renames() {
+        let (root, existing) = setup();
+        let mut orch = Orchestrator::with_backends(&root, Arc::new(FakeClock::new()), Arc::new(FakeTmux::new()), Arc::new(FakeStore::new()));
+        let id = spawn_on(&mut orch, &root, "feature");
+        git(&existing, &["mv", ".gitignore", "ignore.txt"]);
+        fs::write(existing.join("ignore.txt"), ".hydra/\nwts/\ntarget/\n").unwrap();
+        git(&existing, &["-c", "user.email=test@example.com", "-c", "user.name=Test", "commit", "-qam", "rename"]);
+
+        let changes = orch.change_summary(&id).unwrap().unwrap();
+        assert_eq!((changes["branch"].as_str(), changes["base"].as_str()), (Some("feature"), Some("main")));
+        assert_eq!((changes["additions"].as_u64(), changes["deletions"].as_u64()), (Some(1), Some(0)));
+        assert_eq!(changes["files"][0]["path"], "ignore.txt");
+        assert_eq!(changes["files"][0]["old_path"], ".gitignore");
+        fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn test_spawn_never_runs_in_main_checkout_of_branch() {
+        let (root, _) = setup();
+        let mut orch = Orchestrator::with_backends(&root, Arc::new(FakeClock::new()), Arc::new(FakeTmux::new()), Arc::new(FakeStore::new()));
+
+        // `main` is checked out in the main checkout, which is never reused
+        let id = spawn_on(&mut orch, &root, "main");
+        let session = orch.get_status(&id).unwrap();
+        assert_eq!(session.config.branch_name.as_deref(), Some("main-2"));
+        assert_ne!(session.config.working_dir, root);
+        assert!(!session.worktree_reused);
+        fs::remove_dir_all(&root).ok();
+    }
+}
@@ -0,0 +1,418 @@
+// ═══════════════════════════════════════════════════════════════════════════
+// Per-Session Resource Limits
+// ═══════════════════════════════════════════════════════════════════════════
+//
+// With systemd, the hydralph script runs in its own transient user scope so
+// memory/CPU limits cover the whole agent process tree and kill can take the
+// tree down in one go. Without systemd only nice/ionice can be applied.
+
+use std::path::Path;
+use std::process::Command;
+use hydra_log::LoggedCommand;
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::backend::TmuxBackend;
+use crate::shell;
+
+/// Limits requested for a session (all optional)
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceLimits {
+    /// Scheduling priority, -20 (highest) to 19 (lowest)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nice: Option<i32>,
+    /// systemd MemoryMax (e.g. "4G", "50%", "infinity")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_max: Option<String>,
+    /// systemd CPUQuota (e.g. "200%" for two cores)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_quota: Option<String>,
+}
+
+impl ResourceLimits {
+    pub fn is_empty(&self) -> bool {
+        self.nice.is_none() && self.memory_max.is_none() && self.cpu_quota.is_none()
+    }
+
+    /// Limits that only a systemd scope can enforce
+    fn needs_systemd(&self) -> bool {
+        self.memory_max.is_some() || self.cpu_quota.is_some()
+    }
+
+    /// Fill unset fields from `defaults`
+    pub fn or(self, defaults: &ResourceLimits) -> Self {
+        Self {
+            nice: self.nice.or(defaults.nice),
+            memory_max: self.memory_max.or_else(|| defaults.memory_max.clone()),
+            cpu_quota: self.cpu_quota.or_else(|| defaults.cpu_quota.clone()),
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if let Some(nice) = self.nice {
+            if !(-20..=19).contains(&nice) {
+                bail!("nice must be between -20 and 19 (got {})", nice);
+            }
+        }
+        if let Some(mem) = &self.memory_max {
+            let digits = mem.strip_suffix(['K', 'M', 'G', 'T', '%']).unwrap_or(mem);
+            if mem != "infinity" && (digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit())) {
+                bail!("Invalid memory limit '{}'. Use e.g. 512M, 4G or 50%", mem);
+            }
+        }
+        if let Some(cpu) = &self.cpu_quota {
+            let digits = cpu.strip_suffix('%').unwrap_or_default();
+            if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+                bail!("Invalid CPU quota '{}'. Use a percentage, e.g. 50% or 200%", cpu);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How limits were applied to a session
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LimitMechanism {
+    /// Transient `systemd-run --user --scope` unit
+    Systemd,
+    /// nice/ionice prefix only
+    Nice,
+}
+
+impl std::fmt::Display for LimitMechanism {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitMechanism::Systemd => write!(f, "systemd scope"),
+            LimitMechanism::Nice => write!(f, "nice/ionice"),
+        }
+    }
+}
+
+/// Command line that starts hydralph, plus how limits end up applied
+#[derive(Debug, PartialEq, Eq)]
+pub struct LaunchPlan {
+    pub argv: Vec<String>,
+    pub mechanism: Option<LimitMechanism>,
+    pub scope_unit: Option<String>,
+    pub warnings: Vec<String>,
+}
+
+impl LaunchPlan {
+    /// Quoted command line for tmux send-keys
+    pub fn command_line(&self) -> String {
+        shell::join(&self.argv)
+    }
+}
+
+/// Programs that start and manage scopes (`[systemd]` in
+/// .hydra/ralph/config.toml), for systems where they aren't on PATH
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SystemdCommands {
+    /// Starts transient scopes
+    pub run: String,
+    /// Stops and inspects them
+    pub ctl: String,
+}
+
+impl Default for SystemdCommands {
+    fn default() -> Self {
+        Self { run: "systemd-run".into(), ctl: "systemctl".into() }
+    }
+}
+
+/// Scope unit name for a session
+pub fn scope_unit_name(session_id: &str) -> String {
+    format!("hydralph-{}.scope", session_id)
+}
+
+/// Preflight: can we start transient user scopes?
+pub fn systemd_available(systemd: &SystemdCommands) -> bool {
+    Command::new(&systemd.run)
+        .args(["--user", "--scope", "--quiet", "--", "true"])
+        .logged_output()
+        .map(|out| out.status.success())
+        .unwrap_or(false)
+}
+
+/// Decide how to launch `script` under `limits`
+pub fn plan_launch(limits: &ResourceLimits, session_id: &str, script: &str, systemd: Option<&SystemdCommands>) -> LaunchPlan {
+    let mut plan = LaunchPlan {
+        argv: vec![script.to_string()],
+        mechanism: None,
+        scope_unit: None,
+        warnings: Vec::new(),
+    };
+    if limits.is_empty() {
+        return plan;
+    }
+
+    if let Some(systemd) = systemd {
+        let unit = scope_unit_name(session_id);
+        let mut argv: Vec<String> = vec![
+            systemd.run.clone(),
+            "--user".into(),
+            "--scope".into(),
+            "--quiet".into(),
+            format!("--unit={}", unit),
+        ];
+        if let Some(mem) = &limits.memory_max {
+            argv.push("-p".into());
+            argv.push(format!("MemoryMax={}", mem));
+        }
+        if let Some(cpu) = &limits.cpu_quota {
+            argv.push("-p".into());
+            argv.push(format!("CPUQuota={}", cpu));
+        }
+        if let Some(nice) = limits.nice {
+            argv.push(format!("--nice={}", nice));
+        }
+        argv.push("--".into());
+        argv.push(script.to_string());
+
+        plan.argv = argv;
+        plan.mechanism = Some(LimitMechanism::Systemd);
+        plan.scope_unit = Some(unit);
+        return plan;
+    }
+
+    if limits.needs_systemd() {
+        plan.warnings.push(
+            "memory/CPU limits need systemd user scopes, which are unavailable; only nice is applied".into(),
+        );
+    }
+    if let Some(nice) = limits.nice {
+        let mut argv: Vec<String> = vec!["nice".into(), "-n".into(), nice.to_string()];
+        // Lowered CPU priority should lower I/O priority too
+        if nice > 0 {
+            argv.extend(["ionice".into(), "-c".into(), "2".into(), "-n".into(), "7".into()]);
+        }
+        argv.push(script.to_string());
+        plan.argv = argv;
+        plan.mechanism = Some(LimitMechanism::Nice);
+    }
+    plan
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Kill
+// ─────────────────────────────────────────────────────────────────────────────
+
+/// Which path took a session's processes down
+#[derive(Debug, PartialEq, Eq)]
+pub enum KillPath {
+    Scope,
+    Tmux,
+}
+
+/// Stop a transient scope; false if systemd refused or isn't there
+pub fn stop_scope(systemd: &SystemdCommands, unit: &str) -> bool {
+    Command::new(&systemd.ctl)
+        .args(["--user", "stop", unit])
+        .logged_output()
+        .map(|out| out.status.success())
+        .unwrap_or(false)
+}
+
+/// Stop a session's process tree, preferring its scope over tmux
+pub fn stop_session_tree(
+    systemd: &SystemdCommands,
+    scope_unit: Option<&str>,
+    tmux_session: &str,
+    tmux: &dyn TmuxBackend,
+) -> Result<KillPath> {
+    if let Some(unit) = scope_unit {
+        if stop_scope(systemd, unit) {
+            // The pane's shell lives outside the scope
+            let _ = tmux.kill_session(tmux_session);
+            return Ok(KillPath::Scope);
+        }
+        warn!("failed to stop {}, killing tmux session instead", unit);
+    }
+    tmux.kill_session(tmux_session)?;
+    Ok(KillPath::Tmux)
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Usage
+// ─────────────────────────────────────────────────────────────────────────────
+
+/// Current usage read from a scope's cgroup
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    pub memory_bytes: Option<u64>,
+    pub cpu_usec: Option<u64>,
+    pub tasks: Option<u64>,
+}
+
+/// Usage of a running scope, if systemd knows its cgroup
+pub fn scope_usage(systemd: &SystemdCommands, unit: &str) -> Option<ResourceUsage> {
+    let out = Command::new(&systemd.ctl)
+        .args(["--user", "show", "-p", "ControlGroup", "--value", unit])
+        .logged_output()
+        .ok()?;
+    let cgroup = String::from_utf8_lossy(&out.stdout).trim().to_string();
+    if !out.status.success() || cgroup.is_empty() {
+        return None;
+    }
+    read_cgroup_usage(&Path::new("/sys/fs/cgroup").join(cgroup.trim_start_matches('/')))
+}
+
+/// Read cgroup v2 accounting files from `dir`
+pub fn read_cgroup_usage(dir: &Path) -> Option<ResourceUsage> {
+    if !dir.is_dir() {
+        return None;
+    }
+    let read_u64 = |name: &str| -> Option<u64> {
+        std::fs::read_to_string(dir.join(name)).ok()?.trim().parse().ok()
+    };
+    let cpu_usec = std::fs::read_to_string(dir.join("cpu.stat")).ok().and_then(|stat| {
+        stat.lines()
+            .find_map(|line| line.strip_prefix("usage_usec "))
+            .and_then(|v| v.trim().parse().ok())
+    });
+    Some(ResourceUsage {
+        memory_bytes: read_u64("memory.current"),
+        cpu_usec,
+        tasks: read_u64("pids.current"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{FakeTmux, TmuxBackend};
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hydra-limits-{}-{}", name, uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Fake `systemd-run`/`systemctl` in `dir`. Each logs its arguments
+    /// to `<dir>/calls` and exits with `code`.
+    fn install_shims(dir: &Path, code: i32) -> SystemdCommands {
+        for name in ["systemd-run", "systemctl"] {
+            let path = dir.join(name);
+            fs::write(&path, format!(
+                "#!/bin/sh\necho \"{} $*\" >> \"{}\"\nexit {}\n",
+                name, dir.join("calls").display(), code
+            )).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        SystemdCommands {
+            run: dir.join("systemd-run").display().to_string(),
+            ctl: dir.join("systemctl").display().to_string(),
+        }
+    }
+
+    fn calls(dir: &Path) -> String {
+        fs::read_to_string(dir.join("calls")).unwrap_or_default()
+    }
+
+    fn limits(nice: Option<i32>, mem: Option<&str>, cpu: Option<&str>) -> ResourceLimits {
+        ResourceLimits {
+            nice,
+            memory_max: mem.map(String::from),
+            cpu_quota: cpu.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_systemd_run_command_line() {
+        let plan = plan_launch(&limits(Some(5), Some("4G"), Some("150%")), "abc123", "/w d/hydralph.sh", Some(&SystemdCommands::default()));
+        assert_eq!(plan.mechanism, Some(LimitMechanism::Systemd));
+        assert_eq!(plan.scope_unit.as_deref(), Some("hydralph-abc123.scope"));
+        assert_eq!(
+            plan.command_line(),
+            "systemd-run --user --scope --quiet --unit=hydralph-abc123.scope \
+             -p MemoryMax=4G -p CPUQuota=150% --nice=5 -- '/w d/hydralph.sh'"
+        );
+        assert!(plan.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_nice_fallback_command_line() {
+        let plan = plan_launch(&limits(Some(10), Some("4G"), None), "abc123", "/w/hydralph.sh", None);
+        assert_eq!(plan.mechanism, Some(LimitMechanism::Nice));
+        assert_eq!(plan.scope_unit, None);
+        assert_eq!(plan.command_line(), "nice -n 10 ionice -c 2 -n 7 /w/hydralph.sh");
+        assert_eq!(plan.warnings.len(), 1);
+
+        // Memory only, no systemd: nothing can be applied
+        let plan = plan_launch(&limits(None, Some("4G"), None), "abc123", "/w/hydralph.sh", None);
+        assert_eq!(plan.mechanism, None);
+        assert_eq!(plan.argv, vec!["/w/hydralph.sh"]);
+
+        // No limits: plain script regardless of systemd
+        let plan = plan_launch(&ResourceLimits::default(), "abc123", "/w/hydralph.sh", Some(&SystemdCommands::default()));
+        assert_eq!(plan.command_line(), "/w/hydralph.sh");
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(limits(Some(19), Some("512M"), Some("50%")).validate().is_ok());
+        assert!(limits(None, Some("infinity"), None).validate().is_ok());
+        assert!(limits(Some(20), None, None).validate().is_err());
+        assert!(limits(None, Some("lots"), None).validate().is_err());
+        assert!(limits(None, None, Some("2")).validate().is_err());
+    }
+
+    #[test]
+    fn test_preflight_detection() {
+        let ok = scratch_dir("preflight-ok");
+        assert!(systemd_available(&install_shims(&ok, 0)));
+        assert!(calls(&ok).contains("systemd-run --user --scope --quiet -- true"));
+
+        let broken = scratch_dir("preflight-broken");
+        assert!(!systemd_available(&install_shims(&broken, 1)));
+        assert!(!systemd_available(&SystemdCommands {
+            run: broken.join("missing").display().to_string(),
+            ..SystemdCommands::default()
+        }));
+
+        let _ = fs::remove_dir_all(ok);
+        let _ = fs::remove_dir_all(broken);
+    }
+
+    #[test]
+    fn test_kill_prefers_scope() {
+        let dir = scratch_dir("kill");
+        let tmux = FakeTmux::new();
+        tmux.new_session("hydralph-abc123", &dir).unwrap();
+        let path = stop_session_tree(&install_shims(&dir, 0), Some("hydralph-abc123.scope"), "hydralph-abc123", &tmux).unwrap();
+        assert_eq!(path, KillPath::Scope);
+        assert!(calls(&dir).contains("systemctl --user stop hydralph-abc123.scope"));
+        assert!(tmux.sessions().is_empty());
+
+        // A scope systemd refuses to stop falls back to tmux
+        let broken = scratch_dir("kill-broken");
+        tmux.new_session("hydralph-abc123", &dir).unwrap();
+        let path = stop_session_tree(&install_shims(&broken, 1), Some("hydralph-abc123.scope"), "hydralph-abc123", &tmux).unwrap();
+        assert_eq!(path, KillPath::Tmux);
+        assert!(tmux.sessions().is_empty());
+        let _ = fs::remove_dir_all(dir);
+        let _ = fs::remove_dir_all(broken);
+    }
+
+    #[test]
+    fn test_read_cgroup_usage() {
+        let dir = scratch_dir("cgroup");
+        assert_eq!(read_cgroup_usage(&dir.join("missing")), None);
+
+        fs::write(dir.join("memory.current"), "1048576\n").unwrap();
+        fs::write(dir.join("pids.current"), "7\n").unwrap();
+        fs::write(dir.join("cpu.stat"), "usage_usec 2500000\nuser_usec 2000000\n").unwrap();
+        assert_eq!(read_cgroup_usage(&dir), Some(ResourceUsage {
+            memory_bytes: Some(1048576),
+            cpu_usec: Some(2500000),
+            tasks: Some(7),
+        }));
+        let _ = fs::remove_dir_all(dir);
+    }
+}
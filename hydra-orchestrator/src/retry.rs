@@ -0,0 +1,260 @@
+// ═══════════════════════════════════════════════════════════════════════════
+// Retry Blocked - Unstick a session after a human dealt with its blocker
+// ═══════════════════════════════════════════════════════════════════════════
+//
+// After an agent signals BLOCKED, hydralph.sh writes status.json and waits for
+// a `.unblock` marker in the ralph dir. The marker holds the number of extra
+// iterations to add to its budget. Retrying queues guidance in inject.md,
+// rewrites status.json so the session no longer reads as blocked, and drops
+// the marker.
+
+use anyhow::{Context, Result};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+/// Guidance injected when `hydra retry-blocked` is given no message
+pub const DEFAULT_GUIDANCE: &str =
+    "The blocker has been addressed. Re-read inject.md and continue with your current story.";
+
+/// Returned (inside anyhow) when retrying a session that isn't blocked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotBlocked {
+    pub session: String,
+    pub state: &'static str,
+}
+
+impl std::fmt::Display for NotBlocked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Session {} is {}, not blocked", self.session, self.state)
+    }
+}
+
+impl std::error::Error for NotBlocked {}
+
+/// What a successful retry did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryOutcome {
+    /// Iteration the session was blocked in
+    pub iteration: u32,
+    /// Iteration budget from here on
+    pub max_iterations: u32,
+    /// First line of the injected guidance, as recorded in the event
+    pub guidance_preview: String,
+}
+
+/// Steps completed so far, so a failure can say what already happened
+#[derive(Debug, Default)]
+pub(crate) struct Steps {
+    done: Vec<&'static str>,
+}
+
+impl Steps {
+    pub(crate) fn run<T>(&mut self, step: &'static str, result: Result<T>) -> Result<T> {
+        match result {
+            Ok(value) => {
+                self.done.push(step);
+                Ok(value)
+            }
+            Err(e) if self.done.is_empty() => Err(e.context(format!("Failed to {} (nothing was changed)", step))),
+            Err(e) => Err(e.context(format!("Failed to {} (already done: {})", step, self.done.join(", ")))),
+        }
+    }
+}
+
+/// Whether the session's copy of hydralph.sh waits for `.unblock`. A missing
+/// script can't be checked and gets the benefit of the doubt.
+pub(crate) fn script_supports_unblock(ralph_dir: &Path) -> bool {
+    match fs::read_to_string(ralph_dir.join("hydralph.sh")) {
+        Ok(script) => script.contains(".unblock"),
+        Err(_) => true,
+    }
+}
+
+/// Add guidance to inject.md after whatever is already queued there
+pub(crate) fn append_inject(path: &Path, text: &str) -> Result<()> {
+    let queued = fs::read_to_string(path).unwrap_or_default();
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let separator = if queued.trim().is_empty() { "" } else { "\n---\n\n" };
+    writeln!(file, "{}{}", separator, text.trim_end())
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Rewrite a blocked status.json as running, keeping its iteration and stories
+pub(crate) fn clear_blocked_status(path: &Path, max_iterations: u32) -> Result<()> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut status: serde_json::Value = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    let fields = status.as_object_mut()
+        .with_context(|| format!("{} is not a JSON object", path.display()))?;
+    fields.insert("status".into(), "running".into());
+    fields.insert("max".into(), max_iterations.into());
+    fields.remove("reason");
+    fs::write(path, format!("{}\n", status))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Tell a waiting hydralph to continue, granting `extra_iterations`
+pub(crate) fn signal_unblock(ralph_dir: &Path, extra_iterations: u32) -> Result<()> {
+    let marker = ralph_dir.join(".unblock");
+    fs::write(&marker, extra_iterations.to_string())
+        .with_context(|| format!("Failed to write {}", marker.display()))
+}
+
+/// First line of the guidance, cut to fit an event payload
+pub(crate) fn preview(text: &str) -> String {
+    const MAX_CHARS: usize = 80;
+    let line = text.trim().lines().next().unwrap_or_default();
+    if line.chars().count() <= MAX_CHARS {
+        return line.to_string();
+    }
+    let cut: String = line.chars().take(MAX_CHARS - 1).collect();
+    format!("{}…", cut)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FakeClock, FakeTmux, Orchestrator, RecordStore, SessionConfig, SessionId, SessionState};
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    /// A spawned session whose loop has reported BLOCKED in `iteration` of `max`
+    fn blocked_project(iteration: u32, max: u32) -> (PathBuf, Orchestrator, SessionId) {
+        let root = std::env::temp_dir().join(format!("hydra-retry-{}", uuid::Uuid::new_v4().simple()));
+        fs::create_dir_all(root.join(".hydra/ralph")).unwrap();
+        let store = Arc::new(crate::store::SessionStore::new(root.clone()));
+        let mut orch = Orchestrator::with_backends(&root, Arc::new(FakeClock::new()), Arc::new(FakeTmux::new()), store);
+        let id = orch
+            .spawn(SessionConfig {
+                max_iterations: max,
+                working_dir: root.clone(),
+                prd_path: root.join(".hydra/ralph/prd.json"),
+                ..SessionConfig::default()
+            })
+            .unwrap();
+        let status = serde_json::json!({
+            "session": id.0, "status": "blocked", "iteration": iteration, "max": max,
+            "stories": "2/5", "reason": "need the staging credentials",
+        });
+        fs::write(root.join(".hydra/ralph/status.json"), status.to_string()).unwrap();
+        (root, orch, id)
+    }
+
+    fn ralph(root: &Path, file: &str) -> String {
+        fs::read_to_string(root.join(".hydra/ralph").join(file)).unwrap()
+    }
+
+    #[test]
+    fn test_retry_blocked_unblocks_session() {
+        let (root, mut orch, id) = blocked_project(3, 10);
+        fs::write(root.join(".hydra/ralph/inject.md"), "Earlier note\n").unwrap();
+        assert!(matches!(orch.get_status(&id).unwrap().state, SessionState::Blocked { .. }));
+
+        let outcome = orch.retry_blocked(&id, Some("Credentials are in .env.staging now"), 0).unwrap();
+        assert_eq!(outcome, RetryOutcome {
+            iteration: 3,
+            max_iterations: 10,
+            guidance_preview: "Credentials are in .env.staging now".into(),
+        });
+
+        // Guidance queued after what was already there
+        assert_eq!(ralph(&root, "inject.md"), "Earlier note\n\n---\n\nCredentials are in .env.staging now\n");
+        // Status no longer reads as blocked, and the loop is told to go on
+        let status: serde_json::Value = serde_json::from_str(&ralph(&root, "status.json")).unwrap();
+        assert_eq!(status["status"], "running");
+        assert_eq!(status["iteration"], 3);
+        assert!(status.get("reason").is_none());
+        assert_eq!(ralph(&root, ".unblock"), "0");
+        match &orch.get_status(&id).unwrap().state {
+            SessionState::Running { iteration, stories } => assert_eq!((*iteration, stories.as_str()), (3, "2/5")),
+            other => panic!("expected running, got {:?}", other),
+        }
+
+        let (events, _) = orch.journal().read_from(0).unwrap();
+        let last = events.last().unwrap();
+        assert_eq!((last.event_type.as_str(), last.event.as_str()), ("session", "unblocked"));
+        assert_eq!(last.data["guidance"], "Credentials are in .env.staging now");
+        assert_eq!(last.data["iteration"], 3);
+        fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn test_retry_blocked_at_limit_extends_budget() {
+        let (root, mut orch, id) = blocked_project(10, 10);
+
+        let outcome = orch.retry_blocked(&id, None, 5).unwrap();
+        assert_eq!(outcome.max_iterations, 15);
+        assert_eq!(ralph(&root, "inject.md"), format!("{}\n", DEFAULT_GUIDANCE));
+        assert_eq!(ralph(&root, ".unblock"), "5");
+        let status: serde_json::Value = serde_json::from_str(&ralph(&root, "status.json")).unwrap();
+        assert_eq!(status["max"], 15);
+
+        // The larger budget is persisted with the session
+        let records = crate::store::SessionStore::new(root.clone()).list().unwrap();
+        assert_eq!(records[0].max_iterations, 15);
+        fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn test_retry_refuses_budget_past_u32() {
+        let (root, mut orch, id) = blocked_project(10, 10);
+        let err = orch.retry_blocked(&id, None, u32::MAX).unwrap_err().to_string();
+        assert!(err.contains("can't take 4294967295 more iterations on top of its 10"), "{}", err);
+        assert!(!root.join(".hydra/ralph/inject.md").exists());
+        assert!(!root.join(".hydra/ralph/.unblock").exists());
+        fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn test_retry_refuses_session_that_is_not_blocked() {
+        let (root, mut orch, id) = blocked_project(3, 10);
+        let running = serde_json::json!({"session": id.0, "status": "running", "iteration": 4, "max": 10});
+        fs::write(root.join(".hydra/ralph/status.json"), running.to_string()).unwrap();
+
+        let err = orch.retry_blocked(&id, Some("go on"), 0).unwrap_err();
+        assert_eq!(err.downcast_ref::<NotBlocked>(), Some(&NotBlocked { session: id.0.clone(), state: "running" }));
+        // Nothing was touched
+        assert!(!root.join(".hydra/ralph/inject.md").exists());
+        assert!(!root.join(".hydra/ralph/.unblock").exists());
+        fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn test_retry_reports_completed_steps_on_failure() {
+        let (root, mut orch, id) = blocked_project(3, 10);
+        // A directory where the marker should go makes the last file step fail
+        fs::create_dir_all(root.join(".hydra/ralph/.unblock")).unwrap();
+
+        let err = format!("{:#}", orch.retry_blocked(&id, Some("go on"), 0).unwrap_err());
+        assert!(
+            err.starts_with("Failed to signal hydralph to continue (already done: append guidance to inject.md, clear the blocked status)"),
+            "{}",
+            err
+        );
+        fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn test_old_script_without_unblock_is_refused() {
+        let (root, mut orch, id) = blocked_project(3, 10);
+        fs::write(root.join(".hydra/ralph/hydralph.sh"), "#!/bin/bash\nexit 2\n").unwrap();
+        let err = orch.retry_blocked(&id, None, 0).unwrap_err().to_string();
+        assert!(err.contains("predates retry-blocked"), "{}", err);
+        assert!(!root.join(".hydra/ralph/inject.md").exists());
+        fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn test_preview() {
+        assert_eq!(preview("  one line\nsecond\n"), "one line");
+        let long = "x".repeat(100);
+        assert_eq!(preview(&long).chars().count(), 80);
+        assert!(preview(&long).ends_with('…'));
+    }
+}
@@ -5,6 +5,7 @@
 use std::path::PathBuf;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use serde::{Deserialize, Serialize};
+use crate::limits::{LimitMechanism, ResourceLimits};
 
 /// Unique session identifier (short UUID)
 #[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
@@ -16,6 +17,12 @@ impl SessionId {
     }
 }
 
+impl Default for SessionId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl std::fmt::Display for SessionId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.0)
@@ -33,6 +40,17 @@ pub struct SessionConfig {
     pub working_dir: PathBuf,
     pub use_worktree: bool,
     pub branch_name: Option<String>,
+    /// Ref a new worktree branch starts from, instead of the main checkout's HEAD
+    pub base_ref: Option<String>,
+    /// Port the worktree must get instead of the next free one
+    pub requested_port: Option<u16>,
+    /// Directory the worktree goes under instead of hydra-wt's
+    /// `worktrees.directory`, as `HYDRA_WT_DIR` would set it
+    pub worktree_dir: Option<PathBuf>,
+    pub resource_limits: ResourceLimits,
+    /// Sessions that must complete before this one starts; until then it is
+    /// queued
+    pub depends_on: Vec<SessionId>,
 }
 
 impl Default for SessionConfig {
@@ -46,6 +64,11 @@ impl Default for SessionConfig {
             working_dir: std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")),
             use_worktree: false,
             branch_name: None,
+            base_ref: None,
+            requested_port: None,
+            worktree_dir: None,
+            resource_limits: ResourceLimits::default(),
+            depends_on: Vec::new(),
         }
     }
 }
@@ -53,6 +76,8 @@ impl Default for SessionConfig {
 /// Current state of a session
 #[derive(Clone, Debug)]
 pub enum SessionState {
+    /// Waiting for its dependencies or a free slot; nothing runs yet
+    Queued,
     Starting,
     Running { iteration: u32, stories: String },
     Paused,
@@ -63,6 +88,23 @@ pub enum SessionState {
     Stuck { since: Instant, last_iteration: u32 },
 }
 
+impl SessionState {
+    /// Short lowercase name used in events
+    pub fn name(&self) -> &'static str {
+        match self {
+            SessionState::Queued => "queued",
+            SessionState::Starting => "starting",
+            SessionState::Running { .. } => "running",
+            SessionState::Paused => "paused",
+            SessionState::Completed { .. } => "completed",
+            SessionState::Blocked { .. } => "blocked",
+            SessionState::MaxIterations { .. } => "max-iterations",
+            SessionState::Failed { .. } => "failed",
+            SessionState::Stuck { .. } => "stuck",
+        }
+    }
+}
+
 /// A running hydralph session
 #[derive(Debug)]
 pub struct Session {
@@ -71,9 +113,14 @@ pub struct Session {
     pub state: SessionState,
     pub tmux_session: String,
     pub worktree_path: Option<PathBuf>,
+    pub worktree_reused: bool,  // Worktree was there before the session, so kill leaves it
     pub allocated_port: Option<u16>,  // Allocated by hydra-wt
+    pub limit_mechanism: Option<LimitMechanism>,
+    pub scope_unit: Option<String>,  // systemd scope wrapping the agent tree
+    pub trace_id: Option<String>,  // Exported as HYDRA_TRACE_ID; None for pre-tracing sessions
     pub started_at: Instant,
     pub last_activity: Instant,
+    pub activity_marker: Option<u64>,  // Fingerprint of status/progress files as of last_activity
 }
 
 /// Persistent session metadata stored on disk
@@ -89,8 +136,29 @@ pub struct SessionRecord {
     pub working_dir: PathBuf,
     pub use_worktree: bool,
     pub branch_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_ref: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requested_port: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub worktree_dir: Option<PathBuf>,
     pub worktree_path: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub worktree_reused: bool,
     pub allocated_port: Option<u16>,
+    #[serde(default, skip_serializing_if = "ResourceLimits::is_empty")]
+    pub resource_limits: ResourceLimits,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit_mechanism: Option<LimitMechanism>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope_unit: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<String>,
+    /// Not started yet (see [`SessionState::Queued`])
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub queued: bool,
     pub created_at: u64,
 }
 
@@ -107,12 +175,24 @@ impl SessionRecord {
             working_dir: session.config.working_dir.clone(),
             use_worktree: session.config.use_worktree,
             branch_name: session.config.branch_name.clone(),
+            base_ref: session.config.base_ref.clone(),
+            requested_port: session.config.requested_port,
+            worktree_dir: session.config.worktree_dir.clone(),
             worktree_path: session.worktree_path.clone(),
+            worktree_reused: session.worktree_reused,
             allocated_port: session.allocated_port,
+            resource_limits: session.config.resource_limits.clone(),
+            limit_mechanism: session.limit_mechanism,
+            scope_unit: session.scope_unit.clone(),
+            trace_id: session.trace_id.clone(),
+            depends_on: session.config.depends_on.iter().map(|id| id.0.clone()).collect(),
+            queued: matches!(session.state, SessionState::Queued),
+            // Derived from started_at so re-saving a record keeps its age
             created_at: SystemTime::now()
                 .duration_since(UNIX_EPOCH)
                 .unwrap_or_default()
-                .as_secs(),
+                .as_secs()
+                .saturating_sub(session.started_at.elapsed().as_secs()),
         }
     }
 
@@ -133,17 +213,27 @@ impl SessionRecord {
             working_dir: self.working_dir,
             use_worktree: self.use_worktree,
             branch_name: self.branch_name,
+            base_ref: self.base_ref,
+            requested_port: self.requested_port,
+            worktree_dir: self.worktree_dir,
+            resource_limits: self.resource_limits,
+            depends_on: self.depends_on.into_iter().map(SessionId).collect(),
         };
 
         Session {
             id: SessionId(self.id),
             config,
-            state: SessionState::Starting,
+            state: if self.queued { SessionState::Queued } else { SessionState::Starting },
             tmux_session: self.tmux_session,
             worktree_path: self.worktree_path,
+            worktree_reused: self.worktree_reused,
             allocated_port: self.allocated_port,
+            limit_mechanism: self.limit_mechanism,
+            scope_unit: self.scope_unit,
+            trace_id: self.trace_id,
             started_at,
             last_activity: started_at,
+            activity_marker: None,
         }
     }
 }
@@ -201,8 +291,18 @@ mod tests {
             working_dir: PathBuf::from("/tmp/test"),
             use_worktree: true,
             branch_name: Some("feature".to_string()),
+            base_ref: None,
+            requested_port: None,
+            worktree_dir: None,
             worktree_path: Some(PathBuf::from("/tmp/wt")),
+            worktree_reused: false,
             allocated_port: Some(3001),
+            resource_limits: ResourceLimits::default(),
+            limit_mechanism: None,
+            scope_unit: None,
+            trace_id: None,
+            depends_on: Vec::new(),
+            queued: false,
             created_at: 1234567890,
         };
 
@@ -225,6 +325,11 @@ mod tests {
             working_dir: PathBuf::from("/work"),
             use_worktree: false,
             branch_name: None,
+            base_ref: None,
+            requested_port: None,
+            worktree_dir: None,
+            resource_limits: ResourceLimits::default(),
+            depends_on: Vec::new(),
         };
 
         let session = Session {
@@ -233,9 +338,14 @@ mod tests {
             state: SessionState::Starting,
             tmux_session: "hydra-abc12345".to_string(),
             worktree_path: None,
+            worktree_reused: false,
             allocated_port: None,
+            limit_mechanism: None,
+            scope_unit: None,
+            trace_id: None,
             started_at: Instant::now(),
             last_activity: Instant::now(),
+            activity_marker: None,
         };
 
         let record = SessionRecord::from_session(&session);
@@ -247,6 +357,40 @@ mod tests {
         assert_eq!(restored.config.max_iterations, 3);
     }
 
+    #[test]
+    fn test_queued_session_record_roundtrip() {
+        let config = SessionConfig {
+            depends_on: vec![SessionId("a1b2c3d4".to_string())],
+            ..Default::default()
+        };
+        let session = Session {
+            id: SessionId("abc12345".to_string()),
+            config,
+            state: SessionState::Queued,
+            tmux_session: "hydralph-abc12345".to_string(),
+            worktree_path: None,
+            worktree_reused: false,
+            allocated_port: None,
+            limit_mechanism: None,
+            scope_unit: None,
+            trace_id: None,
+            started_at: Instant::now(),
+            last_activity: Instant::now(),
+            activity_marker: None,
+        };
+
+        let json = serde_json::to_string(&SessionRecord::from_session(&session)).unwrap();
+        assert!(json.contains(r#""depends_on":["a1b2c3d4"],"queued":true"#));
+        let restored = serde_json::from_str::<SessionRecord>(&json).unwrap().into_session();
+        assert!(matches!(restored.state, SessionState::Queued));
+        assert_eq!(restored.config.depends_on, vec![SessionId("a1b2c3d4".to_string())]);
+
+        // Records of started sessions don't mention either
+        let started = Session { state: SessionState::Starting, config: SessionConfig::default(), ..restored };
+        let json = serde_json::to_string(&SessionRecord::from_session(&started)).unwrap();
+        assert!(!json.contains("queued") && !json.contains("depends_on"));
+    }
+
     #[test]
     fn test_session_record_with_worktree() {
         let record = SessionRecord {
@@ -260,18 +404,71 @@ mod tests {
             working_dir: PathBuf::from("/tmp/test"),
             use_worktree: true,
             branch_name: Some("feature-branch".to_string()),
+            base_ref: None,
+            requested_port: None,
+            worktree_dir: Some(PathBuf::from("/runner/tmp")),
             worktree_path: Some(PathBuf::from("/tmp/test-wt")),
+            worktree_reused: false,
             allocated_port: Some(3005),
+            resource_limits: ResourceLimits::default(),
+            limit_mechanism: None,
+            scope_unit: None,
+            trace_id: None,
+            depends_on: Vec::new(),
+            queued: false,
             created_at: 1234567890,
         };
 
         let session = record.clone().into_session();
         assert_eq!(session.worktree_path, Some(PathBuf::from("/tmp/test-wt")));
+        assert_eq!(session.config.worktree_dir, Some(PathBuf::from("/runner/tmp")));
         assert_eq!(session.allocated_port, Some(3005));
         assert_eq!(session.config.branch_name, Some("feature-branch".to_string()));
         assert!(session.config.use_worktree);
     }
 
+    #[test]
+    fn test_session_record_resource_limits() {
+        let config = SessionConfig {
+            resource_limits: ResourceLimits {
+                nice: Some(10),
+                memory_max: Some("4G".to_string()),
+                cpu_quota: None,
+            },
+            ..Default::default()
+        };
+        let session = Session {
+            id: SessionId("abc12345".to_string()),
+            config,
+            state: SessionState::Starting,
+            tmux_session: "hydralph-abc12345".to_string(),
+            worktree_path: None,
+            worktree_reused: false,
+            allocated_port: None,
+            limit_mechanism: Some(LimitMechanism::Systemd),
+            scope_unit: Some("hydralph-abc12345.scope".to_string()),
+            trace_id: None,
+            started_at: Instant::now(),
+            last_activity: Instant::now(),
+            activity_marker: None,
+        };
+
+        let json = serde_json::to_string(&SessionRecord::from_session(&session)).unwrap();
+        assert!(json.contains(r#""limit_mechanism":"systemd""#));
+        let restored = serde_json::from_str::<SessionRecord>(&json).unwrap().into_session();
+        assert_eq!(restored.scope_unit.as_deref(), Some("hydralph-abc12345.scope"));
+        assert_eq!(restored.config.resource_limits.memory_max.as_deref(), Some("4G"));
+
+        // Records written before limits existed still load
+        let old = json
+            .replace(r#","resource_limits":{"nice":10,"memory_max":"4G"}"#, "")
+            .replace(r#","limit_mechanism":"systemd""#, "")
+            .replace(r#","scope_unit":"hydralph-abc12345.scope""#, "");
+        let restored = serde_json::from_str::<SessionRecord>(&old).unwrap().into_session();
+        assert_eq!(restored.scope_unit, None);
+        assert!(restored.config.resource_limits.is_empty());
+    }
+
     #[test]
     fn test_session_record_invalid_json() {
         let invalid_json = r#"{"id": "test", "invalid_field": true}"#;
@@ -290,6 +487,11 @@ mod tests {
             working_dir: PathBuf::from("/custom/dir"),
             use_worktree: true,
             branch_name: Some("custom-branch".to_string()),
+            base_ref: Some("origin/main".to_string()),
+            requested_port: None,
+            worktree_dir: None,
+            resource_limits: ResourceLimits::default(),
+            depends_on: vec![SessionId("a1b2c3d4".to_string())],
         };
 
         assert_eq!(config.max_iterations, 100);
@@ -307,7 +509,8 @@ mod tests {
 
     #[test]
     fn test_session_state_variants() {
-        let states = vec![
+        let states = [
+            SessionState::Queued,
             SessionState::Starting,
             SessionState::Running { iteration: 1, stories: "test".to_string() },
             SessionState::Paused,
@@ -319,6 +522,6 @@ mod tests {
         ];
 
         // Just verify all variants can be constructed
-        assert_eq!(states.len(), 8);
+        assert_eq!(states.len(), 9);
     }
 }
@@ -0,0 +1,56 @@
+// ═══════════════════════════════════════════════════════════════════════════
+// Shell Quoting
+// ═══════════════════════════════════════════════════════════════════════════
+//
+// Everything typed into a tmux pane is parsed by the user's shell, so every
+// command line built for send-keys goes through here.
+
+/// Quote a single word for POSIX sh
+pub fn quote(word: &str) -> String {
+    let safe = !word.is_empty()
+        && word.chars().all(|c| c.is_ascii_alphanumeric() || "_-./=:,+@%".contains(c));
+    if safe {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+/// Quote and join an argv into one command line
+pub fn join<S: AsRef<str>>(argv: &[S]) -> String {
+    argv.iter()
+        .map(|arg| quote(arg.as_ref()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// `export NAME=value ...` with every value quoted
+pub fn export(vars: &[(&str, String)]) -> String {
+    let assignments: Vec<String> = vars.iter()
+        .map(|(name, value)| format!("{}={}", name, quote(value)))
+        .collect();
+    format!("export {}", assignments.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("plain-word_1.sh"), "plain-word_1.sh");
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("two words"), "'two words'");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+        assert_eq!(quote("$(rm -rf /)"), "'$(rm -rf /)'");
+    }
+
+    #[test]
+    fn test_join_and_export() {
+        assert_eq!(join(&["nice", "-n", "10", "/a b/run.sh"]), "nice -n 10 '/a b/run.sh'");
+        assert_eq!(
+            export(&[("A", "1".to_string()), ("B", "x y".to_string())]),
+            "export A=1 B='x y'"
+        );
+    }
+}
@@ -0,0 +1,500 @@
+// ═══════════════════════════════════════════════════════════════════════════
+// Simulation - Deterministic runs of the monitor logic
+// ═══════════════════════════════════════════════════════════════════════════
+//
+// A scenario file lists timed steps (spawns, status.json writes, file
+// activity, tmux deaths) and the outcomes expected afterwards. The simulator
+// runs them against a real Orchestrator wired to a fake clock and fake tmux in
+// a scratch project directory, advancing the clock to each step and running
+// one monitor tick after it.
+//
+// ```toml
+// name = "duration kill"
+//
+// [[step]]
+// at = "0s"
+// action = "spawn"
+// session = "a"
+// max_duration = "2h"
+//
+// [[step]]
+// at = "2h1m"
+// action = "tick"
+//
+// [[expect]]
+// event = "health:killed"
+// session = "a"
+// at = "2h1m"
+// ```
+//
+// Sessions spawned without a worktree share the project's `.hydra/ralph`, so
+// `status` and `activity` steps apply to every session in the scenario. A
+// spawn can wait on earlier sessions (`after = ["a"]`) and a top-level
+// `max_running` caps how many run at once, which exercises the queue.
+// Records go to a `FakeStore`, checked against the expected states.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::backend::{FakeClock, FakeStore, FakeTmux};
+use crate::{Event, Orchestrator, SessionConfig, SessionId, SessionState};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Sessions allowed to run at once; more are queued
+    #[serde(default)]
+    pub max_running: Option<usize>,
+    #[serde(default, rename = "step")]
+    pub steps: Vec<Step>,
+    #[serde(default, rename = "expect")]
+    pub expectations: Vec<Expectation>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Step {
+    /// Time since the start of the run, e.g. "90m" or "1h30m"
+    pub at: String,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    /// Spawn a session, referred to as `session` in later steps
+    Spawn {
+        session: String,
+        #[serde(default)]
+        max_duration: Option<String>,
+        #[serde(default)]
+        max_iterations: Option<u32>,
+        /// Sessions that must complete first
+        #[serde(default)]
+        after: Vec<String>,
+    },
+    /// Write .hydra/ralph/status.json as hydralph would
+    Status {
+        status: String,
+        #[serde(default)]
+        iteration: u32,
+        #[serde(default)]
+        stories: Option<String>,
+    },
+    /// Append to progress.txt
+    Activity,
+    Pause { session: String },
+    Resume { session: String },
+    Kill {
+        session: String,
+        #[serde(default = "default_reason")]
+        reason: String,
+    },
+    /// Continue a blocked session with `retry_blocked`
+    Retry {
+        session: String,
+        #[serde(default)]
+        guidance: Option<String>,
+        #[serde(default)]
+        extra_iterations: u32,
+    },
+    /// The session's tmux shell exits on its own
+    TmuxExit { session: String },
+    /// Only advance the clock and run the monitor
+    Tick,
+}
+
+fn default_reason() -> String {
+    "scenario".to_string()
+}
+
+/// One check run after the last step.
+///
+/// With `state`, checks the session's final state name (`running`, `stuck`,
+/// ... or `gone` once the orchestrator dropped it), and that the store holds
+/// its record exactly while it isn't gone. With `event`, counts
+/// journal events named `type:event` (e.g. `health:killed`), optionally only
+/// those for `session` and fired by the step at `at`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Expectation {
+    #[serde(default)]
+    pub session: Option<String>,
+    #[serde(default)]
+    pub state: Option<String>,
+    #[serde(default)]
+    pub event: Option<String>,
+    /// Exact number of matching events (default: at least one)
+    #[serde(default)]
+    pub count: Option<usize>,
+    #[serde(default)]
+    pub at: Option<String>,
+}
+
+impl Scenario {
+    pub fn parse(content: &str) -> Result<Self> {
+        let scenario: Self = toml::from_str(content).context("Failed to parse scenario")?;
+        for expect in &scenario.expectations {
+            match (&expect.state, &expect.event) {
+                (Some(_), None) if expect.session.is_none() => bail!("A state expectation needs a session"),
+                (Some(_), None) | (None, Some(_)) => {}
+                _ => bail!("Each expectation needs exactly one of state or event"),
+            }
+        }
+        Ok(scenario)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(&content).with_context(|| format!("Invalid scenario {}", path.display()))
+    }
+}
+
+/// What happened during a run and which expectations failed
+#[derive(Debug)]
+pub struct SimulationReport {
+    pub name: String,
+    /// One line per step and per event fired
+    pub log: Vec<String>,
+    pub failures: Vec<String>,
+}
+
+impl SimulationReport {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+// ═══════════════════════════════════════════════════════════════════════════
+// Simulator
+// ═══════════════════════════════════════════════════════════════════════════
+
+pub struct Simulator {
+    root: PathBuf,
+    clock: Arc<FakeClock>,
+    tmux: Arc<FakeTmux>,
+    store: Arc<FakeStore>,
+    orch: Orchestrator,
+    aliases: HashMap<String, SessionId>,
+    /// Journal events with the step time they fired at
+    fired: Vec<(Duration, Event)>,
+    journal_offset: u64,
+    log: Vec<String>,
+}
+
+impl Simulator {
+    /// Fresh orchestrator over a scratch project directory
+    pub fn new() -> Result<Self> {
+        let root = std::env::temp_dir().join(format!("hydra-sim-{}", uuid::Uuid::new_v4().simple()));
+        fs::create_dir_all(root.join(".hydra"))
+            .with_context(|| format!("Failed to create {}", root.display()))?;
+
+        let clock = Arc::new(FakeClock::new());
+        let tmux = Arc::new(FakeTmux::new());
+        let store = Arc::new(FakeStore::new());
+        let orch = Orchestrator::with_backends(&root, clock.clone(), tmux.clone(), store.clone());
+        Ok(Self {
+            root,
+            clock,
+            tmux,
+            store,
+            orch,
+            aliases: HashMap::new(),
+            fired: Vec::new(),
+            journal_offset: 0,
+            log: Vec::new(),
+        })
+    }
+
+    /// Run every step, then check the expectations
+    pub fn run(scenario: &Scenario) -> Result<SimulationReport> {
+        let mut sim = Self::new()?;
+        sim.orch.set_max_running(scenario.max_running);
+        for (n, step) in scenario.steps.iter().enumerate() {
+            sim.step(step).with_context(|| format!("Step {} (at {})", n + 1, step.at))?;
+        }
+
+        let mut failures = Vec::new();
+        for expect in &scenario.expectations {
+            if let Err(e) = sim.check(expect) {
+                failures.push(e.to_string());
+            }
+        }
+
+        Ok(SimulationReport {
+            name: scenario.name.clone(),
+            log: std::mem::take(&mut sim.log),
+            failures,
+        })
+    }
+
+    pub fn orchestrator(&mut self) -> &mut Orchestrator {
+        &mut self.orch
+    }
+
+    /// Advance to the step's time, apply it, then run one monitor tick
+    pub fn step(&mut self, step: &Step) -> Result<()> {
+        let at = parse_duration(&step.at)?;
+        let now = self.clock.elapsed();
+        if at < now {
+            bail!("Steps must be in time order ({} is before {})", step.at, format_offset(now));
+        }
+        self.clock.advance(at - now);
+
+        let ralph_dir = self.root.join(".hydra/ralph");
+        let line = match &step.action {
+            Action::Spawn { session, max_duration, max_iterations, after } => {
+                if self.aliases.contains_key(session) {
+                    bail!("Session '{}' already spawned", session);
+                }
+                let mut config = SessionConfig {
+                    working_dir: self.root.clone(),
+                    prd_path: ralph_dir.join("prd.json"),
+                    ..SessionConfig::default()
+                };
+                if let Some(max) = max_duration {
+                    config.max_duration = parse_duration(max)?;
+                }
+                if let Some(max) = max_iterations {
+                    config.max_iterations = *max;
+                }
+                config.depends_on = after.iter().map(|dep| self.alias(dep)).collect::<Result<_>>()?;
+                let id = self.orch.spawn(config)?;
+                let queued = self.orch.sessions.get(&id.0).is_some_and(|s| matches!(s.state, SessionState::Queued));
+                let line = format!("spawn {} -> {}{}", session, id, if queued { " (queued)" } else { "" });
+                self.aliases.insert(session.clone(), id);
+                line
+            }
+            Action::Status { status, iteration, stories } => {
+                fs::create_dir_all(&ralph_dir)?;
+                let content = serde_json::json!({
+                    "status": status,
+                    "iteration": iteration,
+                    "max": 10,
+                    "stories": stories,
+                });
+                fs::write(ralph_dir.join("status.json"), content.to_string())?;
+                format!("status {} (iteration {})", status, iteration)
+            }
+            Action::Activity => {
+                fs::create_dir_all(&ralph_dir)?;
+                let mut progress = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(ralph_dir.join("progress.txt"))?;
+                writeln!(progress, "progress at {}", step.at)?;
+                "activity".to_string()
+            }
+            Action::Pause { session } => {
+                self.orch.pause(&self.alias(session)?)?;
+                format!("pause {}", session)
+            }
+            Action::Resume { session } => {
+                self.orch.resume(&self.alias(session)?)?;
+                format!("resume {}", session)
+            }
+            Action::Kill { session, reason } => {
+                self.orch.kill(&self.alias(session)?, reason)?;
+                format!("kill {} ({})", session, reason)
+            }
+            Action::Retry { session, guidance, extra_iterations } => {
+                let outcome = self.orch.retry_blocked(&self.alias(session)?, guidance.as_deref(), *extra_iterations)?;
+                format!("retry {} (budget {})", session, outcome.max_iterations)
+            }
+            Action::TmuxExit { session } => {
+                let id = self.alias(session)?;
+                self.tmux.exit(&format!("hydralph-{}", id.0));
+                format!("tmux exit {}", session)
+            }
+            Action::Tick => "tick".to_string(),
+        };
+        self.log.push(format!("[{:>7}] {}", format_offset(at), line));
+
+        self.orch.tick()?;
+        self.collect_events(at)
+    }
+
+    fn alias(&self, session: &str) -> Result<SessionId> {
+        self.aliases.get(session)
+            .cloned()
+            .with_context(|| format!("Unknown session '{}' (spawn it first)", session))
+    }
+
+    fn collect_events(&mut self, at: Duration) -> Result<()> {
+        let (events, offset) = self.orch.journal().read_from(self.journal_offset)?;
+        self.journal_offset = offset;
+        for event in events {
+            let name = self.aliases.iter()
+                .find(|(_, id)| id.0 == event.session)
+                .map(|(alias, _)| alias.as_str())
+                .unwrap_or(event.session.as_str());
+            self.log.push(format!("[{:>7}]   {}:{} {}", format_offset(at), event.event_type, event.event, name));
+            self.fired.push((at, event));
+        }
+        Ok(())
+    }
+
+    fn check(&self, expect: &Expectation) -> Result<()> {
+        let session = expect.session.as_deref().map(|s| self.alias(s)).transpose()?;
+
+        if let Some(state) = &expect.state {
+            let id = session.expect("validated in Scenario::parse");
+            let alias = expect.session.as_deref().unwrap_or_default();
+            let actual = self.orch.sessions.get(&id.0).map(|s| s.state.name()).unwrap_or("gone");
+            if actual != state {
+                bail!("{}: expected state {}, got {}", alias, state, actual);
+            }
+            match (actual, self.store.get(&id.0)) {
+                ("gone", Some(_)) => bail!("{}: gone but its record is still stored", alias),
+                ("gone", None) => {}
+                (_, None) => bail!("{}: {} but its record is not stored", alias, actual),
+                (_, Some(record)) if record.queued != (actual == "queued") => {
+                    bail!("{}: {} but its record says queued = {}", alias, actual, record.queued)
+                }
+                _ => {}
+            }
+            return Ok(());
+        }
+
+        let name = expect.event.as_deref().expect("validated in Scenario::parse");
+        let at = expect.at.as_deref().map(parse_duration).transpose()?;
+        let matched = self.fired.iter()
+            .filter(|(_, e)| format!("{}:{}", e.event_type, e.event) == name)
+            .filter(|(_, e)| session.as_ref().is_none_or(|id| id.0 == e.session))
+            .filter(|(when, _)| at.is_none_or(|at| *when == at))
+            .count();
+
+        let ok = match expect.count {
+            Some(count) => matched == count,
+            None => matched > 0,
+        };
+        if !ok {
+            let wanted = expect.count.map_or("at least 1".to_string(), |c| c.to_string());
+            let mut what = name.to_string();
+            if let Some(s) = &expect.session {
+                what.push_str(&format!(" for {}", s));
+            }
+            if let Some(at) = &expect.at {
+                what.push_str(&format!(" at {}", at));
+            }
+            bail!("{}: expected {}, got {}", what, wanted, matched);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Simulator {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+/// Parse "1h30m", "90m", "45s" or "0s"
+fn parse_duration(s: &str) -> Result<Duration> {
+    let mut total = 0u64;
+    let mut current = String::new();
+    for ch in s.trim().chars() {
+        if ch.is_ascii_digit() {
+            current.push(ch);
+            continue;
+        }
+        let unit = match ch {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => bail!("Invalid duration '{}'", s),
+        };
+        let value: u64 = current.parse().with_context(|| format!("Invalid duration '{}'", s))?;
+        total = value.checked_mul(unit)
+            .and_then(|secs| total.checked_add(secs))
+            .with_context(|| format!("Duration '{}' is too long", s))?;
+        current.clear();
+    }
+    if !current.is_empty() {
+        bail!("Invalid duration '{}' (missing unit)", s);
+    }
+    Ok(Duration::from_secs(total))
+}
+
+fn format_offset(d: Duration) -> String {
+    let secs = d.as_secs();
+    match (secs / 3600, (secs % 3600) / 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, 0) => format!("{}m", m),
+        (0, m, s) => format!("{}m{}s", m, s),
+        (h, 0, 0) => format!("{}h", h),
+        (h, m, _) => format!("{}h{}m", h, m),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("0s").unwrap(), Duration::ZERO);
+        assert_eq!(parse_duration("1h30m").unwrap(), Duration::from_secs(5400));
+        assert_eq!(parse_duration("2h1m5s").unwrap(), Duration::from_secs(7265));
+        assert!(parse_duration("10").is_err());
+        assert!(parse_duration("5d").is_err());
+        assert!(parse_duration("99999999999999999999h").is_err());
+        assert!(parse_duration("9999999999999999h").unwrap_err().to_string().contains("too long"));
+        assert!(parse_duration("18446744073709551615s1s").unwrap_err().to_string().contains("too long"));
+    }
+
+    #[test]
+    fn test_failed_expectations_are_reported() {
+        let scenario = Scenario::parse(r#"
+            name = "misfire"
+
+            [[step]]
+            at = "0s"
+            action = "spawn"
+            session = "a"
+
+            [[expect]]
+            session = "a"
+            state = "completed"
+
+            [[expect]]
+            event = "session:spawned"
+            count = 2
+        "#).unwrap();
+
+        let report = Simulator::run(&scenario).unwrap();
+        assert_eq!(report.failures, vec![
+            "a: expected state completed, got running",
+            "session:spawned: expected 2, got 1",
+        ]);
+    }
+
+    #[test]
+    fn test_invalid_scenarios() {
+        let both = "name = 'x'\n[[expect]]\nsession = 'a'\nstate = 'gone'\nevent = 'session:killed'\n";
+        assert!(Scenario::parse(both).is_err());
+        let no_session = "name = 'x'\n[[expect]]\nstate = 'gone'\n";
+        assert!(Scenario::parse(no_session).is_err());
+
+        let backwards = Scenario::parse(r#"
+            name = "backwards"
+            [[step]]
+            at = "10m"
+            action = "tick"
+            [[step]]
+            at = "5m"
+            action = "tick"
+        "#).unwrap();
+        assert!(Simulator::run(&backwards).is_err());
+    }
+}
@@ -3,10 +3,13 @@
 // ═══════════════════════════════════════════════════════════════════════════
 
 use anyhow::{Context, Result};
-use std::fs;
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use hydra_log::LoggedCommand;
 
+use crate::backend::RecordStore;
 use crate::session::{SessionId, SessionRecord};
 
 pub struct SessionStore {
@@ -33,20 +36,26 @@ impl SessionStore {
         Ok(())
     }
 
+    pub fn events_path(&self) -> PathBuf {
+        self.root.join(".hydra/orchestrator/events.jsonl")
+    }
+
+    /// Questions answered through `hydra answer`, keyed by question id
+    pub fn answered_path(&self) -> PathBuf {
+        self.root.join(".hydra/orchestrator/answered.json")
+    }
+
     pub fn record_path(&self, id: &SessionId) -> PathBuf {
         self.dir().join(format!("{}.json", id.0))
     }
 
-    pub fn save(&self, record: &SessionRecord) -> Result<()> {
-        self.ensure_dir()?;
-        let path = self.record_path(&SessionId(record.id.clone()));
-        let content = serde_json::to_string_pretty(record)
-            .context("Failed to serialize session record")?;
-        fs::write(&path, content)
-            .with_context(|| format!("Failed to write {}", path.display()))?;
-        Ok(())
+    /// Last state a `hydra` process saw the session in, kept apart from the
+    /// record so saving a record never rolls it back
+    fn state_path(&self, id: &SessionId) -> PathBuf {
+        self.dir().join(format!("{}.state", id.0))
     }
 
+    #[cfg(test)]
     pub fn load(&self, id: &SessionId) -> Result<Option<SessionRecord>> {
         let path = self.record_path(id);
         if !path.exists() {
@@ -58,8 +67,20 @@ impl SessionStore {
             .with_context(|| format!("Failed to parse {}", path.display()))?;
         Ok(Some(record))
     }
+}
+
+impl RecordStore for SessionStore {
+    fn save(&self, record: &SessionRecord) -> Result<()> {
+        self.ensure_dir()?;
+        let path = self.record_path(&SessionId(record.id.clone()));
+        let content = serde_json::to_string_pretty(record)
+            .context("Failed to serialize session record")?;
+        fs::write(&path, content)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
 
-    pub fn list(&self) -> Result<Vec<SessionRecord>> {
+    fn list(&self) -> Result<Vec<SessionRecord>> {
         let dir = self.dir();
         if !dir.exists() {
             return Ok(vec![]);
@@ -82,14 +103,46 @@ impl SessionStore {
         Ok(records)
     }
 
-    pub fn remove(&self, id: &SessionId) -> Result<()> {
-        let path = self.record_path(id);
-        if path.exists() {
-            fs::remove_file(&path)
-                .with_context(|| format!("Failed to remove {}", path.display()))?;
+    fn remove(&self, id: &SessionId) -> Result<()> {
+        for path in [self.record_path(id), self.state_path(id)] {
+            if path.exists() {
+                fs::remove_file(&path)
+                    .with_context(|| format!("Failed to remove {}", path.display()))?;
+            }
         }
         Ok(())
     }
+
+    fn transition(&self, id: &SessionId, from: &str, to: &str) -> Result<Option<String>> {
+        self.ensure_dir()?;
+        let path = self.state_path(id);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        // Released when the file is closed
+        file.lock().with_context(|| format!("Failed to lock {}", path.display()))?;
+
+        let mut current = String::new();
+        file.read_to_string(&mut current)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let current = match current.trim() {
+            "" => from,
+            stored => stored,
+        };
+        if current == to {
+            return Ok(None);
+        }
+        let previous = current.to_string();
+        file.set_len(0)
+            .and_then(|()| file.seek(SeekFrom::Start(0)))
+            .and_then(|_| file.write_all(to.as_bytes()))
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(Some(previous))
+    }
 }
 
 pub fn find_project_root() -> PathBuf {
@@ -127,7 +180,7 @@ pub fn find_project_root() -> PathBuf {
 fn git_root(cwd: &Path) -> Option<PathBuf> {
     let output = Command::new("git")
         .args(["-C", &cwd.to_string_lossy(), "rev-parse", "--show-toplevel"])
-        .output()
+        .logged_output()
         .ok()?;
     if !output.status.success() {
         return None;
@@ -143,6 +196,9 @@ fn git_root(cwd: &Path) -> Option<PathBuf> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::limits::ResourceLimits;
+    use crate::{FakeClock, FakeTmux, Orchestrator, SessionConfig};
+    use std::sync::Arc;
 
     #[test]
     fn test_session_store_paths() {
@@ -178,12 +234,24 @@ mod tests {
             working_dir: PathBuf::from("/work"),
             use_worktree: false,
             branch_name: None,
+            base_ref: None,
+            requested_port: None,
+            worktree_dir: None,
             worktree_path: None,
+            worktree_reused: false,
             allocated_port: None,
+            resource_limits: ResourceLimits::default(),
+            limit_mechanism: None,
+            scope_unit: None,
+            trace_id: None,
+            depends_on: Vec::new(),
+            queued: false,
             created_at: 1234567890,
         };
 
         store.save(&record).unwrap();
+        let saved = std::fs::read_to_string(store.record_path(&SessionId("test123".to_string()))).unwrap();
+        crate::timefmt::assert_machine_output(&saved);
 
         let loaded = store.load(&SessionId("test123".to_string())).unwrap();
         assert!(loaded.is_some());
@@ -228,8 +296,18 @@ mod tests {
                 working_dir: PathBuf::from("/work"),
                 use_worktree: false,
                 branch_name: None,
+                base_ref: None,
+                requested_port: None,
+                worktree_dir: None,
                 worktree_path: None,
+                worktree_reused: false,
                 allocated_port: None,
+                resource_limits: ResourceLimits::default(),
+                limit_mechanism: None,
+                scope_unit: None,
+                trace_id: None,
+                depends_on: Vec::new(),
+                queued: false,
                 created_at: 1234567890,
             };
             store.save(&record).unwrap();
@@ -260,8 +338,18 @@ mod tests {
             working_dir: PathBuf::from("/work"),
             use_worktree: false,
             branch_name: None,
+            base_ref: None,
+            requested_port: None,
+            worktree_dir: None,
             worktree_path: None,
+            worktree_reused: false,
             allocated_port: None,
+            resource_limits: ResourceLimits::default(),
+            limit_mechanism: None,
+            scope_unit: None,
+            trace_id: None,
+            depends_on: Vec::new(),
+            queued: false,
             created_at: 1234567890,
         };
 
@@ -298,4 +386,69 @@ mod tests {
 
         std::fs::remove_dir_all(&temp_dir).ok();
     }
+
+    #[test]
+    fn test_transition_compares_and_saves() {
+        let temp_dir = std::env::temp_dir().join(format!("hydra-test-{}", uuid::Uuid::new_v4()));
+        let store = SessionStore::new(temp_dir.clone());
+        let id = SessionId("abc12345".into());
+
+        // Nothing stored yet: `from` stands in for it
+        assert_eq!(store.transition(&id, "starting", "running").unwrap().as_deref(), Some("starting"));
+        assert_eq!(store.transition(&id, "starting", "running").unwrap(), None);
+        assert_eq!(store.transition(&id, "running", "completed").unwrap().as_deref(), Some("running"));
+        // A stale `from` doesn't matter once a state is stored
+        assert_eq!(store.transition(&id, "starting", "completed").unwrap(), None);
+
+        store.remove(&id).unwrap();
+        assert!(!store.state_path(&id).exists());
+        std::fs::remove_dir_all(&temp_dir).ok();
+    }
+
+    #[test]
+    fn test_each_transition_journaled_once_across_processes() {
+        let root = std::env::temp_dir().join(format!("hydra-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(root.join(".hydra/ralph")).unwrap();
+        let tmux = Arc::new(FakeTmux::new());
+        let orchestrator = || Orchestrator::with_backends(
+            &root, Arc::new(FakeClock::new()), tmux.clone(), Arc::new(SessionStore::new(root.clone())),
+        );
+        let mut spawner = orchestrator();
+        let id = spawner
+            .spawn(SessionConfig {
+                working_dir: root.clone(),
+                prd_path: root.join(".hydra/ralph/prd.json"),
+                ..SessionConfig::default()
+            })
+            .unwrap();
+
+        // Two followers, and a `hydra ls` started after the change
+        let mut first = orchestrator();
+        let mut second = orchestrator();
+        let status = serde_json::json!({"session": id.0, "status": "running", "iteration": 1, "max": 10});
+        std::fs::write(root.join(".hydra/ralph/status.json"), status.to_string()).unwrap();
+        first.list();
+        second.list();
+        spawner.list();
+        orchestrator().list();
+
+        let status = serde_json::json!({"session": id.0, "status": "complete", "iteration": 2, "max": 10});
+        std::fs::write(root.join(".hydra/ralph/status.json"), status.to_string()).unwrap();
+        second.list();
+        first.list();
+        orchestrator().list();
+
+        let (events, _) = spawner.journal().read_from(0).unwrap();
+        let reported: Vec<_> = events
+            .iter()
+            .filter(|e| e.event_type == "session" && e.event != "spawned")
+            .map(|e| (e.event.as_str(), e.data["from"].as_str(), e.data["to"].as_str()))
+            .collect();
+        assert_eq!(reported, vec![
+            ("state", Some("starting"), Some("running")),
+            ("state", Some("running"), Some("completed")),
+            ("completed", None, None),
+        ]);
+        std::fs::remove_dir_all(&root).ok();
+    }
 }
@@ -0,0 +1,255 @@
+// ═══════════════════════════════════════════════════════════════════════════
+// Time Formatting - Durations and timestamps for humans
+// ═══════════════════════════════════════════════════════════════════════════
+//
+// Every human-facing surface (ls, status, ...) formats time through these
+// helpers so the strings stay the same everywhere and between releases.
+// Machine outputs (events, stored records) carry raw seconds or RFC 3339 and
+// must never contain these strings; the event snapshot tests check that.
+
+use chrono::{DateTime, Local, TimeZone};
+use std::time::{Duration, SystemTime};
+
+const MINUTE: u64 = 60;
+const HOUR: u64 = 60 * MINUTE;
+const DAY: u64 = 24 * HOUR;
+
+/// Past this, `relative_time` names the day instead of counting hours
+const RELATIVE_HOURS_LIMIT: i64 = 12;
+
+/// Past this many days, `relative_time` shows an absolute date
+const RELATIVE_DAYS_LIMIT: i64 = 7;
+
+/// The two most significant units, e.g. "45s", "3m 12s", "2h 3m", "4d 1h".
+/// Lower units are truncated, never rounded up, so a value doesn't show the
+/// next unit before it gets there.
+pub fn human_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    let (major, major_unit, minor, minor_unit) = if secs < MINUTE {
+        return format!("{}s", secs);
+    } else if secs < HOUR {
+        (secs / MINUTE, "m", secs % MINUTE, "s")
+    } else if secs < DAY {
+        (secs / HOUR, "h", (secs % HOUR) / MINUTE, "m")
+    } else {
+        (secs / DAY, "d", (secs % DAY) / HOUR, "h")
+    };
+    if minor == 0 {
+        format!("{}{}", major, major_unit)
+    } else {
+        format!("{}{} {}{}", major, major_unit, minor, minor_unit)
+    }
+}
+
+/// Short remaining-time estimate without spaces, e.g. "<1m", "12m", "1h30m".
+/// Rounds up at the precision shown, so the estimate is never too short.
+pub fn compact_eta(d: Duration) -> String {
+    let secs = d.as_secs() + u64::from(d.subsec_nanos() > 0);
+    if secs < MINUTE {
+        return "<1m".to_string();
+    }
+    let step = if secs <= DAY { MINUTE } else { HOUR };
+    human_duration(Duration::from_secs(secs.div_ceil(step) * step)).replace(' ', "")
+}
+
+/// How long ago `t` was, in the local time zone: "just now", "5m ago",
+/// "3h ago", then "yesterday 14:02", "Mon 09:15" and finally "2025-10-01"
+/// once it's more than a week back.
+pub fn relative_time(t: SystemTime) -> String {
+    relative_time_at(DateTime::<Local>::from(t), Local::now())
+}
+
+/// `relative_time` against an explicit "now"; `t` and `now` share a zone
+pub fn relative_time_at<Tz: TimeZone>(t: DateTime<Tz>, now: DateTime<Tz>) -> String
+where
+    Tz::Offset: std::fmt::Display,
+{
+    let ago = now.clone().signed_duration_since(t.clone());
+    // Clock skew between machines can put a record slightly in the future
+    if ago.num_seconds() < MINUTE as i64 {
+        return "just now".to_string();
+    }
+    if ago.num_hours() < 1 {
+        return format!("{}m ago", ago.num_minutes());
+    }
+
+    let days_back = (now.date_naive() - t.date_naive()).num_days();
+    if ago.num_hours() < RELATIVE_HOURS_LIMIT && days_back == 0 {
+        return format!("{}h ago", ago.num_hours());
+    }
+    match days_back {
+        0 => format!("today {}", t.format("%H:%M")),
+        1 => format!("yesterday {}", t.format("%H:%M")),
+        d if d < RELATIVE_DAYS_LIMIT => t.format("%a %H:%M").to_string(),
+        _ => t.format("%Y-%m-%d").to_string(),
+    }
+}
+
+/// Whether `s` reads like the output of one of the helpers above. Used to
+/// keep human strings out of machine outputs.
+pub fn is_human_time(s: &str) -> bool {
+    let s = s.trim();
+    if s == "just now" || s == "<1m" || s.ends_with(" ago") || s.starts_with("yesterday ") || s.starts_with("today ") {
+        return true;
+    }
+    // "3m 12s", "1h30m", "2d"
+    let compact: String = s.split(' ').collect();
+    !compact.is_empty() && {
+        let mut rest = compact.as_str();
+        let mut parts = 0;
+        while !rest.is_empty() {
+            let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            let unit = rest[digits..].chars().next();
+            if digits == 0 || !matches!(unit, Some('s' | 'm' | 'h' | 'd')) {
+                return false;
+            }
+            rest = &rest[digits + 1..];
+            parts += 1;
+        }
+        parts <= 2
+    }
+}
+
+/// Fail if any string in a serialized machine output reads like a human time
+#[cfg(test)]
+pub(crate) fn assert_machine_output(json: &str) {
+    fn walk(value: &serde_json::Value, path: &str) {
+        match value {
+            serde_json::Value::String(s) => {
+                assert!(!is_human_time(s), "human-formatted time {:?} at {} in machine output", s, path)
+            }
+            serde_json::Value::Array(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    walk(item, &format!("{}[{}]", path, i));
+                }
+            }
+            serde_json::Value::Object(fields) => {
+                for (key, field) in fields {
+                    walk(field, &format!("{}.{}", path, key));
+                }
+            }
+            _ => {}
+        }
+    }
+    let value: serde_json::Value = serde_json::from_str(json).expect("machine output is JSON");
+    walk(&value, "$");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::FixedOffset;
+    use proptest::prelude::*;
+
+    fn secs(n: u64) -> String {
+        human_duration(Duration::from_secs(n))
+    }
+
+    #[test]
+    fn test_human_duration_boundaries() {
+        assert_eq!(secs(0), "0s");
+        assert_eq!(secs(59), "59s");
+        assert_eq!(secs(60), "1m");
+        assert_eq!(secs(61), "1m 1s");
+        assert_eq!(secs(3599), "59m 59s");
+        assert_eq!(secs(3600), "1h");
+        assert_eq!(secs(2 * 3600 + 3 * 60 + 59), "2h 3m");
+        assert_eq!(secs(DAY - 1), "23h 59m");
+        assert_eq!(secs(DAY), "1d");
+        assert_eq!(secs(20 * DAY + 5 * HOUR + 59 * 60), "20d 5h");
+        // What `hydra status` used to print as "started 1759841 seconds ago"
+        assert_eq!(secs(1_759_841), "20d 8h");
+    }
+
+    #[test]
+    fn test_compact_eta() {
+        assert_eq!(compact_eta(Duration::ZERO), "<1m");
+        assert_eq!(compact_eta(Duration::from_millis(59_500)), "1m");
+        assert_eq!(compact_eta(Duration::from_secs(61)), "2m");
+        assert_eq!(compact_eta(Duration::from_secs(90 * 60)), "1h30m");
+        assert_eq!(compact_eta(Duration::from_secs(DAY - 30)), "1d");
+        assert_eq!(compact_eta(Duration::from_secs(DAY + 1)), "1d1h");
+    }
+
+    fn zone() -> FixedOffset {
+        FixedOffset::east_opt(2 * 3600).unwrap()
+    }
+
+    fn local(y: i32, mo: u32, d: u32, h: u32, mi: u32) -> DateTime<FixedOffset> {
+        zone().with_ymd_and_hms(y, mo, d, h, mi, 0).unwrap()
+    }
+
+    #[test]
+    fn test_relative_time() {
+        let now = local(2026, 10, 16, 15, 30);
+        let rel = |t| relative_time_at(t, now);
+        assert_eq!(rel(now), "just now");
+        assert_eq!(rel(local(2026, 10, 16, 15, 31)), "just now");
+        assert_eq!(rel(local(2026, 10, 16, 15, 25)), "5m ago");
+        assert_eq!(rel(local(2026, 10, 16, 12, 0)), "3h ago");
+        assert_eq!(rel(local(2026, 10, 16, 1, 10)), "today 01:10");
+        assert_eq!(rel(local(2026, 10, 15, 14, 2)), "yesterday 14:02");
+        assert_eq!(rel(local(2026, 10, 12, 9, 15)), "Mon 09:15");
+        assert_eq!(rel(local(2026, 10, 1, 9, 15)), "2026-10-01");
+
+        // Shortly after midnight, last night is "yesterday", not "2h ago"
+        let after_midnight = local(2026, 10, 16, 0, 30);
+        assert_eq!(relative_time_at(local(2026, 10, 15, 22, 0), after_midnight), "yesterday 22:00");
+    }
+
+    #[test]
+    fn test_is_human_time() {
+        for human in ["59s", "3m 12s", "2h", "1h30m", "<1m", "5m ago", "yesterday 14:02", "just now"] {
+            assert!(is_human_time(human), "{}", human);
+        }
+        for machine in ["2023-11-14T22:13:20Z", "abc12345", "running", "3600", "session", "1h2m3s", "m"] {
+            assert!(!is_human_time(machine), "{}", machine);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "human-formatted time \"3h ago\" at $.data.started")]
+    fn test_assert_machine_output_catches_human_times() {
+        assert_machine_output(r#"{"ts":"2023-11-14T22:13:20Z","data":{"started":"3h ago"}}"#);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_units_switch_at_boundaries(n in 0u64..(30 * DAY)) {
+            let s = secs(n);
+            let major = s.split(' ').next().unwrap();
+            let expected = match n {
+                n if n < MINUTE => format!("{}s", n),
+                n if n < HOUR => format!("{}m", n / MINUTE),
+                n if n < DAY => format!("{}h", n / HOUR),
+                n => format!("{}d", n / DAY),
+            };
+            prop_assert_eq!(major, expected);
+            prop_assert!(s.split(' ').count() <= 2);
+            prop_assert!(is_human_time(&s));
+        }
+
+        #[test]
+        fn prop_eta_never_under_estimates(n in MINUTE..(30 * DAY)) {
+            let eta = compact_eta(Duration::from_secs(n));
+            prop_assert!(parse_compact(&eta) >= n, "{} for {}s", eta, n);
+            prop_assert!(!eta.contains(' '));
+        }
+    }
+
+    fn parse_compact(s: &str) -> u64 {
+        let mut total = 0;
+        let mut current = 0;
+        for ch in s.chars() {
+            match ch {
+                '0'..='9' => current = current * 10 + ch.to_digit(10).unwrap() as u64,
+                's' => { total += current; current = 0 }
+                'm' => { total += current * MINUTE; current = 0 }
+                'h' => { total += current * HOUR; current = 0 }
+                'd' => { total += current * DAY; current = 0 }
+                _ => panic!("unexpected {}", ch),
+            }
+        }
+        total
+    }
+}
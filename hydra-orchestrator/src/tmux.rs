@@ -3,13 +3,14 @@
 // ═══════════════════════════════════════════════════════════════════════════
 
 use std::process::Command;
+use hydra_log::LoggedCommand;
 use anyhow::{Result, Context};
 
 /// Create a new TMUX session
 pub fn new_session(name: &str, working_dir: &std::path::Path) -> Result<()> {
     let output = Command::new("tmux")
         .args(["new-session", "-d", "-s", name, "-c", &working_dir.display().to_string()])
-        .output()
+        .logged_output()
         .context("Failed to create tmux session. Is tmux installed?")?;
 
     if !output.status.success() {
@@ -24,7 +25,7 @@ pub fn new_session(name: &str, working_dir: &std::path::Path) -> Result<()> {
 pub fn kill_session(name: &str) -> Result<()> {
     let output = Command::new("tmux")
         .args(["kill-session", "-t", name])
-        .output()?;
+        .logged_output()?;
 
     // Don't error if session doesn't exist
     if !output.status.success() {
@@ -41,7 +42,7 @@ pub fn kill_session(name: &str) -> Result<()> {
 pub fn send_keys(name: &str, keys: &str) -> Result<()> {
     let output = Command::new("tmux")
         .args(["send-keys", "-t", name, keys, "C-m"])
-        .output()
+        .logged_output()
         .context("Failed to send keys to tmux session")?;
 
     if !output.status.success() {
@@ -56,7 +57,7 @@ pub fn send_keys(name: &str, keys: &str) -> Result<()> {
 pub fn session_exists(name: &str) -> Result<bool> {
     let output = Command::new("tmux")
         .args(["list-sessions", "-F", "#{session_name}"])
-        .output()?;
+        .logged_output()?;
 
     if !output.status.success() {
         // No server running = no sessions
@@ -66,18 +67,3 @@ pub fn session_exists(name: &str) -> Result<bool> {
     let stdout = String::from_utf8_lossy(&output.stdout);
     Ok(stdout.lines().any(|line| line == name))
 }
-
-/// List all active TMUX sessions
-pub fn list_sessions() -> Result<Vec<String>> {
-    let output = Command::new("tmux")
-        .args(["list-sessions", "-F", "#{session_name}"])
-        .output();
-
-    match output {
-        Ok(out) if out.status.success() => {
-            let stdout = String::from_utf8_lossy(&out.stdout);
-            Ok(stdout.lines().map(|s| s.to_string()).collect())
-        }
-        _ => Ok(vec![]), // No server or error = no sessions
-    }
-}
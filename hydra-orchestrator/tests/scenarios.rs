@@ -0,0 +1,27 @@
+//! Runs every scenario in tests/scenarios through the simulator.
+
+use hydra_orchestrator::{Scenario, Simulator};
+use std::path::Path;
+
+#[test]
+fn test_scenarios() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/scenarios");
+    let mut paths: Vec<_> = std::fs::read_dir(&dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .collect();
+    paths.sort();
+    assert!(paths.len() >= 5, "expected the scenario suite in {}", dir.display());
+
+    let mut failed = Vec::new();
+    for path in &paths {
+        let scenario = Scenario::load(path).unwrap();
+        let report = Simulator::run(&scenario)
+            .unwrap_or_else(|e| panic!("{} did not run: {:#}", path.display(), e));
+        if !report.passed() {
+            failed.push(format!("{}:\n  {}\n  log:\n    {}",
+                report.name, report.failures.join("\n  "), report.log.join("\n    ")));
+        }
+    }
+    assert!(failed.is_empty(), "failing scenarios:\n{}", failed.join("\n"));
+}
@@ -0,0 +1,99 @@
+//! Worktree ages, for spotting abandoned worktrees
+//!
+//! The registry records when each worktree was created; `list` shows how
+//! long ago that was, and `list`/`clean --older-than 7d` pick out the old
+//! ones.
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Duration, Utc};
+
+/// Parse an age like `7d`, `12h`, `2w` or `1d12h`. Units are `m`, `h`, `d`
+/// and `w`; an age too large to represent is an error.
+pub fn parse(s: &str) -> Result<Duration> {
+    let mut total = Duration::zero();
+    let mut current = String::new();
+    for ch in s.trim().chars() {
+        if ch.is_ascii_digit() {
+            current.push(ch);
+            continue;
+        }
+        let value: i64 = current.parse().with_context(|| format!("Invalid age '{}'", s))?;
+        let part = match ch {
+            'm' => Duration::try_minutes(value),
+            'h' => Duration::try_hours(value),
+            'd' => Duration::try_days(value),
+            'w' => Duration::try_weeks(value),
+            _ => bail!("Invalid age '{}': units are m, h, d and w", s),
+        };
+        total = part
+            .and_then(|part| total.checked_add(&part))
+            .with_context(|| format!("Invalid age '{}': too large", s))?;
+        current.clear();
+    }
+    if !current.is_empty() {
+        bail!("Invalid age '{}' (missing unit, e.g. 7d)", s);
+    }
+    if total.is_zero() {
+        bail!("Invalid age '{}'", s);
+    }
+    Ok(total)
+}
+
+/// `"3d"`, `"5h"`, `"12m"` or `"<1m"`: the largest whole unit of `age`
+pub fn describe(age: Duration) -> String {
+    if age.num_weeks() >= 2 {
+        format!("{}w", age.num_weeks())
+    } else if age.num_days() >= 1 {
+        format!("{}d", age.num_days())
+    } else if age.num_hours() >= 1 {
+        format!("{}h", age.num_hours())
+    } else if age.num_minutes() >= 1 {
+        format!("{}m", age.num_minutes())
+    } else {
+        "<1m".to_string()
+    }
+}
+
+/// Whether something created at `created` is older than `age` at `now`.
+/// An unknown creation time is never old, so entries from before creation
+/// times were recorded are left alone.
+pub fn is_older(created: Option<DateTime<Utc>>, age: Duration, now: DateTime<Utc>) -> bool {
+    created.is_some_and(|created| now - created > age)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("7d").unwrap(), Duration::days(7));
+        assert_eq!(parse("1d12h").unwrap(), Duration::hours(36));
+        assert_eq!(parse("2w").unwrap(), Duration::days(14));
+        assert_eq!(parse(" 90m ").unwrap(), Duration::minutes(90));
+        for bad in ["", "7", "d", "7x", "0d", "-1d", "7 d"] {
+            assert!(parse(bad).is_err(), "{:?} parsed", bad);
+        }
+        for huge in ["9223372036854775807w", "15250284452471w", "15250284452w15250284452w", "99999999999999999999d"] {
+            assert!(parse(huge).unwrap_err().to_string().starts_with("Invalid age"), "{:?} parsed", huge);
+        }
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(describe(Duration::seconds(30)), "<1m");
+        assert_eq!(describe(Duration::minutes(59)), "59m");
+        assert_eq!(describe(Duration::hours(23)), "23h");
+        assert_eq!(describe(Duration::days(9)), "9d");
+        assert_eq!(describe(Duration::days(30)), "4w");
+    }
+
+    #[test]
+    fn test_is_older() {
+        let now: DateTime<Utc> = "2026-03-10T00:00:00Z".parse().unwrap();
+        let created = "2026-03-01T00:00:00Z".parse().ok();
+        assert!(is_older(created, Duration::days(7), now));
+        assert!(!is_older(created, Duration::days(10), now));
+        assert!(!is_older(None, Duration::days(7), now));
+    }
+}
@@ -0,0 +1,300 @@
+//! Archives of branches before they are deleted
+//!
+//! `hydra-wt archive`, `remove --archive` and `merge --cleanup --archive`
+//! write what a branch has beyond the main branch to a `git bundle` in
+//! `.hydra/archives/`, next to a JSON sidecar with the branch, its head and
+//! its port, so nothing they delete is gone for good. `hydra-wt restore`
+//! fetches a bundle back into the repository. Only the newest
+//! `archive.keep` archives are kept.
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+use crate::config::WtConfig;
+use crate::ports::PortRegistry;
+use crate::{health, worktree};
+
+/// What the sidecar of an archive records
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveInfo {
+    pub branch: String,
+    /// Commit the branch pointed at when it was archived
+    pub head: String,
+    /// Branch the bundle is relative to; restoring needs its commits
+    pub base: String,
+    pub port: Option<u16>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// An archive written by [`create`]
+#[derive(Debug)]
+pub struct Archive {
+    pub bundle: PathBuf,
+    pub info: ArchiveInfo,
+    /// Older archives removed to stay within `archive.keep`
+    pub pruned: Vec<PathBuf>,
+}
+
+/// Where archives of the repository at `repo_root` are kept
+pub fn dir(repo_root: &Path) -> PathBuf {
+    repo_root.join(".hydra/archives")
+}
+
+/// The JSON sidecar next to `bundle`
+pub fn sidecar_path(bundle: &Path) -> PathBuf {
+    bundle.with_extension("json")
+}
+
+/// `<name>-<date>.bundle` for `branch` archived at `at`, where the name is
+/// the branch with `/` and anything else unsafe in a file name
+/// percent-encoded, so no two branches share a bundle
+pub fn bundle_path(repo_root: &Path, branch: &str, at: DateTime<Utc>) -> PathBuf {
+    dir(repo_root).join(format!("{}-{}.bundle", file_name(branch), at.format("%Y%m%d-%H%M%S")))
+}
+
+/// `branch` with every byte but ASCII letters, digits, `.`, `-` and `_`
+/// as `%XX`: `feature/a` is `feature%2Fa`, apart from `feature-a`
+fn file_name(branch: &str) -> String {
+    let mut name = String::with_capacity(branch.len());
+    for byte in branch.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'.' | b'-' | b'_' => name.push(char::from(byte)),
+            _ => name.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    name
+}
+
+/// Bundle the commits `branch` has beyond the main branch, record its head
+/// and port next to the bundle, and prune archives beyond `archive.keep`
+///
+/// A branch with nothing beyond the main branch, e.g. one just merged, gets
+/// a bundle of its head commit alone, so it can still be restored.
+pub fn create(cfg: &WtConfig, repo_root: &Path, branch: &str) -> Result<Archive> {
+    let Some(head) = health::rev_parse(repo_root, &format!("refs/heads/{}", branch))? else {
+        bail!("Branch '{}' does not exist", branch);
+    };
+    let base = worktree::detect_main_branch_in(repo_root);
+    let ahead = worktree::commits_ahead_in(repo_root, branch, &base)?;
+    let range = format!("{}..{}", base, branch);
+    let revs: Vec<&str> = if ahead.is_empty() { vec!["-1", branch] } else { vec![&range] };
+
+    let info = ArchiveInfo {
+        branch: branch.to_string(),
+        head,
+        base,
+        port: PortRegistry::load_at(repo_root)?.get(branch),
+        created_at: Utc::now(),
+    };
+    let bundle = bundle_path(repo_root, branch, info.created_at);
+    worktree::bundle_in(repo_root, &revs, &bundle)?;
+
+    let sidecar = sidecar_path(&bundle);
+    let content = serde_json::to_string_pretty(&info).context("Failed to serialize archive info")?;
+    std::fs::write(&sidecar, content)
+        .with_context(|| format!("Failed to write {}", sidecar.display()))?;
+    debug!("wrote {}", sidecar.display());
+
+    let pruned = prune(repo_root, cfg.archive.keep)?;
+    Ok(Archive { bundle, info, pruned })
+}
+
+/// The sidecar of `bundle`, `None` for a bundle written without one
+pub fn load_info(bundle: &Path) -> Result<Option<ArchiveInfo>> {
+    let path = sidecar_path(bundle);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_str(&content)
+        .map(Some)
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Every bundle in the archives directory with when it was archived, newest
+/// first. Bundles without a sidecar go by their modification time.
+pub fn list(repo_root: &Path) -> Result<Vec<(PathBuf, DateTime<Utc>)>> {
+    let dir = dir(repo_root);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut archives = Vec::new();
+    for entry in std::fs::read_dir(&dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let path = entry.with_context(|| format!("Failed to read {}", dir.display()))?.path();
+        if path.extension().is_none_or(|ext| ext != "bundle") {
+            continue;
+        }
+        let created_at = match load_info(&path)? {
+            Some(info) => info.created_at,
+            None => std::fs::metadata(&path)
+                .and_then(|meta| meta.modified())
+                .map(DateTime::<Utc>::from)
+                .with_context(|| format!("Failed to read {}", path.display()))?,
+        };
+        archives.push((path, created_at));
+    }
+    archives.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| b.0.cmp(&a.0)));
+    Ok(archives)
+}
+
+/// Remove all but the `keep` newest archives, returning the bundles removed.
+/// `keep` 0 keeps everything.
+pub fn prune(repo_root: &Path, keep: usize) -> Result<Vec<PathBuf>> {
+    if keep == 0 {
+        return Ok(Vec::new());
+    }
+    let mut pruned = Vec::new();
+    for (bundle, _) in list(repo_root)?.into_iter().skip(keep) {
+        std::fs::remove_file(&bundle)
+            .with_context(|| format!("Failed to remove {}", bundle.display()))?;
+        let sidecar = sidecar_path(&bundle);
+        if sidecar.exists() {
+            std::fs::remove_file(&sidecar)
+                .with_context(|| format!("Failed to remove {}", sidecar.display()))?;
+        }
+        info!("Pruned archive {}", bundle.display());
+        pruned.push(bundle);
+    }
+    Ok(pruned)
+}
+
+/// A branch brought back by [`restore`]
+#[derive(Debug)]
+pub struct Restored {
+    pub branch: String,
+    pub head: String,
+    /// The sidecar, if the bundle has one
+    pub info: Option<ArchiveInfo>,
+}
+
+/// Create the branch archived in `bundle` in the repository at `repo_root`
+///
+/// The branch comes from the sidecar, or from the bundle itself if there
+/// is none. Fails if a branch of that name already exists.
+pub fn restore(repo_root: &Path, bundle: &Path) -> Result<Restored> {
+    if !bundle.exists() {
+        bail!("Archive {} not found", bundle.display());
+    }
+    let info = load_info(bundle)?;
+    let heads = worktree::bundle_heads_in(repo_root, bundle)?;
+    let branch = match &info {
+        Some(info) => info.branch.clone(),
+        None => match heads.iter().find_map(|(name, _)| name.strip_prefix("refs/heads/")) {
+            Some(branch) => branch.to_string(),
+            None => bail!("{} contains no branch", bundle.display()),
+        },
+    };
+    let Some((_, head)) = heads.iter().find(|(name, _)| *name == format!("refs/heads/{}", branch)) else {
+        bail!("{} does not contain branch '{}'", bundle.display(), branch);
+    };
+
+    if worktree::branch_exists_in(repo_root, &format!("refs/heads/{}", branch))? {
+        bail!("Branch '{}' already exists; rename or delete it before restoring", branch);
+    }
+    worktree::fetch_bundle_in(repo_root, bundle, &branch)?;
+    Ok(Restored { branch, head: head.clone(), info })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::{self, git};
+
+    /// Scratch repo whose `feature/login` has two commits beyond `main`
+    fn setup() -> PathBuf {
+        let repo = scratch::repo("archive");
+        scratch::commit_all(&repo, "init");
+        git(&repo, &["checkout", "-q", "-b", "feature/login"]);
+        scratch::commit_all(&repo, "login form");
+        scratch::commit_all(&repo, "login api");
+        git(&repo, &["checkout", "-q", "main"]);
+        repo
+    }
+
+    #[test]
+    fn test_archive_and_restore() {
+        let repo = setup();
+        let cfg = WtConfig::default();
+        let head = health::rev_parse(&repo, "feature/login").unwrap().unwrap();
+
+        let archive = create(&cfg, &repo, "feature/login").unwrap();
+        let name = archive.bundle.file_name().unwrap().to_string_lossy().to_string();
+        assert!(name.starts_with("feature%2Flogin-") && name.ends_with(".bundle"), "{}", name);
+        assert_eq!(load_info(&archive.bundle).unwrap(), Some(archive.info.clone()));
+        assert_eq!(archive.info.head, head);
+        assert_eq!(archive.info.base, "main");
+        assert!(archive.pruned.is_empty());
+
+        assert!(restore(&repo, &archive.bundle).is_err(), "restored over an existing branch");
+        git(&repo, &["branch", "-D", "feature/login"]);
+        let restored = restore(&repo, &archive.bundle).unwrap();
+        assert_eq!((restored.branch.as_str(), restored.head.as_str()), ("feature/login", head.as_str()));
+        assert_eq!(health::rev_parse(&repo, "feature/login").unwrap(), Some(head));
+
+        // Without a sidecar the branch is read from the bundle
+        std::fs::remove_file(sidecar_path(&archive.bundle)).unwrap();
+        git(&repo, &["branch", "-D", "feature/login"]);
+        assert_eq!(restore(&repo, &archive.bundle).unwrap().branch, "feature/login");
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_archive_merged_branch() {
+        let repo = setup();
+        git(&repo, &["merge", "-q", "--ff-only", "feature/login"]);
+
+        let archive = create(&WtConfig::default(), &repo, "feature/login").unwrap();
+        git(&repo, &["bundle", "verify", "-q", &archive.bundle.to_string_lossy()]);
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_bundle_paths_never_collide() {
+        let repo = Path::new("/src/repo");
+        let at: DateTime<Utc> = "2026-03-01T12:00:00Z".parse().unwrap();
+        let names: Vec<PathBuf> = ["feature/a", "feature-a", "feature_a", "Feature/A", "fix/ü"]
+            .iter()
+            .map(|branch| bundle_path(repo, branch, at).strip_prefix(dir(repo)).unwrap().to_path_buf())
+            .collect();
+        assert_eq!(names, [
+            "feature%2Fa-20260301-120000.bundle",
+            "feature-a-20260301-120000.bundle",
+            "feature_a-20260301-120000.bundle",
+            "Feature%2FA-20260301-120000.bundle",
+            "fix%2F%C3%BC-20260301-120000.bundle",
+        ].map(PathBuf::from));
+    }
+
+    #[test]
+    fn test_prune_keeps_newest() {
+        let repo = setup();
+        std::fs::create_dir_all(dir(&repo)).unwrap();
+        let now = Utc::now();
+        for days in [3, 1, 2] {
+            let at = now - chrono::Duration::days(days);
+            let bundle = bundle_path(&repo, &format!("b{}", days), at);
+            std::fs::write(&bundle, "").unwrap();
+            let info = ArchiveInfo {
+                branch: format!("b{}", days),
+                head: String::new(),
+                base: "main".to_string(),
+                port: None,
+                created_at: at,
+            };
+            std::fs::write(sidecar_path(&bundle), serde_json::to_string(&info).unwrap()).unwrap();
+        }
+
+        assert!(prune(&repo, 0).unwrap().is_empty());
+        let pruned = prune(&repo, 2).unwrap();
+        assert_eq!(pruned.len(), 1);
+        assert!(pruned[0].file_name().unwrap().to_string_lossy().starts_with("b3-"));
+        assert!(!sidecar_path(&pruned[0]).exists());
+        let left: Vec<String> = list(&repo).unwrap().iter().map(|(p, _)| p.file_name().unwrap().to_string_lossy()[..2].to_string()).collect();
+        assert_eq!(left, vec!["b1", "b2"]);
+        scratch::remove(&repo);
+    }
+}
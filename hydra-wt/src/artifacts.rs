@@ -1,11 +1,18 @@
 //! Artifact handling for worktrees
 //!
-//! Provides symlink and copy operations to bring artifacts from the repo
-//! root into new worktrees.
+//! Provides symlink, copy and hardlink operations to bring artifacts from
+//! the repo root into new worktrees, and a refresh that applies entries
+//! added to the config after a worktree was created.
 
 use anyhow::{Context, Result};
+use hydra_log::LoggedCommand;
+use std::io::ErrorKind;
 use std::path::Path;
 use std::process::Command;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::config::ArtifactsConfig;
 
 /// Symlink an artifact from repo root to worktree
 ///
@@ -17,8 +24,8 @@ pub fn symlink_artifact(repo_root: &Path, wt_path: &Path, artifact: &str) -> Res
 
     // Check source exists
     if !source.exists() {
-        eprintln!(
-            "Warning: artifact source '{}' not found, skipping symlink",
+        warn!(
+            "artifact source '{}' not found, skipping symlink",
             source.display()
         );
         return Ok(());
@@ -26,8 +33,8 @@ pub fn symlink_artifact(repo_root: &Path, wt_path: &Path, artifact: &str) -> Res
 
     // Check target doesn't already exist
     if target.exists() || target.is_symlink() {
-        eprintln!(
-            "Warning: artifact target '{}' already exists, skipping symlink",
+        warn!(
+            "artifact target '{}' already exists, skipping symlink",
             target.display()
         );
         return Ok(());
@@ -54,14 +61,18 @@ pub fn symlink_artifact(repo_root: &Path, wt_path: &Path, artifact: &str) -> Res
         .with_context(|| format!("Failed to symlink {} -> {}", target.display(), source.display()))?;
     }
 
-    println!("  Symlinked: {} -> {}", artifact, source.display());
+    info!("  Symlinked: {} -> {}", artifact, source.display());
     Ok(())
 }
 
 /// Copy an artifact from repo root to worktree
 ///
-/// Copies `repo_root/artifact` to `wt_path/artifact`.
-/// Uses `cp -a --reflink=auto` for copy-on-write optimization.
+/// Copies `repo_root/artifact` to `wt_path/artifact`. On Linux this uses
+/// `cp -a --reflink=auto` for copy-on-write when `cp` is available; anywhere
+/// else, or if `cp` is missing or fails, the tree is copied natively, keeping
+/// permissions, modification times and symlinks. The native copy goes
+/// through `std::fs::copy`, which clones the file where the filesystem
+/// supports it (btrfs and XFS on Linux, APFS on macOS).
 /// Warns and skips if source doesn't exist or target already exists.
 pub fn copy_artifact(repo_root: &Path, wt_path: &Path, artifact: &str) -> Result<()> {
     let source = repo_root.join(artifact);
@@ -69,8 +80,8 @@ pub fn copy_artifact(repo_root: &Path, wt_path: &Path, artifact: &str) -> Result
 
     // Check source exists
     if !source.exists() {
-        eprintln!(
-            "Warning: artifact source '{}' not found, skipping copy",
+        warn!(
+            "artifact source '{}' not found, skipping copy",
             source.display()
         );
         return Ok(());
@@ -78,8 +89,8 @@ pub fn copy_artifact(repo_root: &Path, wt_path: &Path, artifact: &str) -> Result
 
     // Check target doesn't already exist
     if target.exists() {
-        eprintln!(
-            "Warning: artifact target '{}' already exists, skipping copy",
+        warn!(
+            "artifact target '{}' already exists, skipping copy",
             target.display()
         );
         return Ok(());
@@ -91,19 +102,512 @@ pub fn copy_artifact(repo_root: &Path, wt_path: &Path, artifact: &str) -> Result
             .with_context(|| format!("Failed to create parent dirs for {}", target.display()))?;
     }
 
-    // Use cp for robust copying with COW support
-    let output = Command::new("cp")
+    if cfg!(target_os = "linux") && copy_with_cp(&source, &target, artifact)? {
+        info!("  Copied: {}", artifact);
+        return Ok(());
+    }
+
+    let mut progress = CopyProgress::new(artifact);
+    copy_tree(&source, &target, &mut progress)?;
+    info!("  Copied: {} ({} files)", artifact, progress.files);
+    Ok(())
+}
+
+/// Copy with `cp -a --reflink=auto`. `false` when `cp` isn't there or
+/// failed, after clearing whatever it left at `target`.
+fn copy_with_cp(source: &Path, target: &Path, artifact: &str) -> Result<bool> {
+    let output = match Command::new("cp")
         .args(["-a", "--reflink=auto"])
-        .arg(&source)
-        .arg(&target)
-        .output()
-        .with_context(|| format!("Failed to run cp for {}", artifact))?;
+        .arg(source)
+        .arg(target)
+        .logged_output()
+    {
+        Ok(output) => output,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e).with_context(|| format!("Failed to run cp for {}", artifact)),
+    };
+
+    if output.status.success() {
+        return Ok(true);
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    warn!("cp failed for {} ({}), copying it natively", artifact, stderr.trim());
+    let cleared = match std::fs::symlink_metadata(target) {
+        Ok(meta) if meta.is_dir() => std::fs::remove_dir_all(target),
+        Ok(_) => std::fs::remove_file(target),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    };
+    cleared.with_context(|| format!("Failed to remove partial copy at {}", target.display()))?;
+    Ok(false)
+}
+
+/// How often a long native copy reports how far it got
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Files copied so far, logged every [`PROGRESS_INTERVAL`] so copying a
+/// large tree doesn't look like a hang
+struct CopyProgress<'a> {
+    artifact: &'a str,
+    files: usize,
+    bytes: u64,
+    last_report: Instant,
+}
+
+impl<'a> CopyProgress<'a> {
+    fn new(artifact: &'a str) -> Self {
+        Self { artifact, files: 0, bytes: 0, last_report: Instant::now() }
+    }
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        anyhow::bail!("cp failed for {}: {}", artifact, stderr.trim());
+    fn copied(&mut self, bytes: u64) {
+        self.files += 1;
+        self.bytes += bytes;
+        if self.last_report.elapsed() >= PROGRESS_INTERVAL {
+            info!(
+                "  Copying {}: {} files, {} MB so far",
+                self.artifact,
+                self.files,
+                self.bytes / (1024 * 1024)
+            );
+            self.last_report = Instant::now();
+        }
     }
+}
+
+/// Mirror `source` at `target`, recreating symlinks as symlinks with the
+/// same target and keeping permissions and modification times
+fn copy_tree(source: &Path, target: &Path, progress: &mut CopyProgress) -> Result<()> {
+    let meta = std::fs::symlink_metadata(source)
+        .with_context(|| format!("Failed to read {}", source.display()))?;
 
-    println!("  Copied: {}", artifact);
+    if meta.file_type().is_symlink() {
+        let link = std::fs::read_link(source)
+            .with_context(|| format!("Failed to read symlink {}", source.display()))?;
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&link, target)
+            .with_context(|| format!("Failed to symlink {} -> {}", target.display(), link.display()))?;
+        #[cfg(windows)]
+        {
+            if source.is_dir() {
+                std::os::windows::fs::symlink_dir(&link, target)
+            } else {
+                std::os::windows::fs::symlink_file(&link, target)
+            }
+            .with_context(|| format!("Failed to symlink {} -> {}", target.display(), link.display()))?;
+        }
+        return Ok(());
+    }
+
+    if meta.is_dir() {
+        std::fs::create_dir(target)
+            .with_context(|| format!("Failed to create {}", target.display()))?;
+        let entries = std::fs::read_dir(source)
+            .with_context(|| format!("Failed to read {}", source.display()))?;
+        for entry in entries {
+            let entry = entry.with_context(|| format!("Failed to read {}", source.display()))?;
+            copy_tree(&entry.path(), &target.join(entry.file_name()), progress)?;
+        }
+        // Set last, so a read-only directory can still be filled
+        std::fs::set_permissions(target, meta.permissions())
+            .with_context(|| format!("Failed to set permissions on {}", target.display()))?;
+        return Ok(());
+    }
+
+    // Copies the permissions along with the contents
+    let bytes = std::fs::copy(source, target)
+        .with_context(|| format!("Failed to copy {} to {}", source.display(), target.display()))?;
+    // Build tools compare mtimes; fresh ones would make them rebuild
+    if let Err(e) = meta.modified().and_then(|modified| set_modified(target, modified)) {
+        warn!("could not keep the modification time of {}: {}", target.display(), e);
+    }
+    progress.copied(bytes);
     Ok(())
 }
+
+/// Set the modification time of the file at `path`, which may be read-only
+fn set_modified(path: &Path, modified: std::time::SystemTime) -> std::io::Result<()> {
+    let mut options = std::fs::File::options();
+    // The owner may set times without write access on unix; Windows needs
+    // the attribute right, which a read-only file still grants
+    options.read(true);
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+        const FILE_WRITE_ATTRIBUTES: u32 = 0x100;
+        options.access_mode(FILE_WRITE_ATTRIBUTES);
+    }
+    options.open(path)?.set_modified(modified)
+}
+
+/// How the files of a hardlinked artifact ended up in the worktree
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LinkStats {
+    pub linked: usize,
+    pub copied: usize,
+}
+
+impl std::ops::AddAssign for LinkStats {
+    fn add_assign(&mut self, other: Self) {
+        self.linked += other.linked;
+        self.copied += other.copied;
+    }
+}
+
+/// Hardlink an artifact from repo root to worktree
+///
+/// Recreates the directory tree of `repo_root/artifact` under
+/// `wt_path/artifact` and hardlinks each file into it, so a huge build cache
+/// costs no disk space and the build tool still sees a real directory.
+/// Symlinks inside the tree are skipped rather than followed. If the
+/// worktree is on another filesystem, falls back to copying with a warning.
+/// Warns and skips if source doesn't exist or target already exists.
+pub fn hardlink_artifact(repo_root: &Path, wt_path: &Path, artifact: &str) -> Result<LinkStats> {
+    let source = repo_root.join(artifact);
+    let target = wt_path.join(artifact);
+    let mut stats = LinkStats::default();
+
+    // Check source exists
+    if !source.exists() {
+        warn!(
+            "artifact source '{}' not found, skipping hardlink",
+            source.display()
+        );
+        return Ok(stats);
+    }
+
+    // Check target doesn't already exist
+    if target.exists() || target.is_symlink() {
+        warn!(
+            "artifact target '{}' already exists, skipping hardlink",
+            target.display()
+        );
+        return Ok(stats);
+    }
+
+    // Create parent directories if needed
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create parent dirs for {}", target.display()))?;
+    }
+
+    let mut cross_device = false;
+    hardlink_tree(&source, &target, &mut cross_device, &mut stats)?;
+
+    if cross_device {
+        info!("  Hardlinked: {} ({} linked, {} copied)", artifact, stats.linked, stats.copied);
+    } else {
+        info!("  Hardlinked: {} ({} files)", artifact, stats.linked);
+    }
+    Ok(stats)
+}
+
+/// Mirror `source` at `target`, linking files until a link crosses devices
+/// and copying from then on
+fn hardlink_tree(source: &Path, target: &Path, cross_device: &mut bool, stats: &mut LinkStats) -> Result<()> {
+    let meta = std::fs::symlink_metadata(source)
+        .with_context(|| format!("Failed to read {}", source.display()))?;
+
+    if meta.file_type().is_symlink() {
+        warn!("skipping symlink '{}' in hardlinked artifact", source.display());
+        return Ok(());
+    }
+
+    if meta.is_dir() {
+        std::fs::create_dir(target)
+            .with_context(|| format!("Failed to create {}", target.display()))?;
+        let entries = std::fs::read_dir(source)
+            .with_context(|| format!("Failed to read {}", source.display()))?;
+        for entry in entries {
+            let entry = entry.with_context(|| format!("Failed to read {}", source.display()))?;
+            hardlink_tree(&entry.path(), &target.join(entry.file_name()), cross_device, stats)?;
+        }
+        // Set last, so a read-only directory can still be filled
+        std::fs::set_permissions(target, meta.permissions())
+            .with_context(|| format!("Failed to set permissions on {}", target.display()))?;
+        return Ok(());
+    }
+
+    if !*cross_device {
+        match std::fs::hard_link(source, target) {
+            Ok(()) => {
+                stats.linked += 1;
+                return Ok(());
+            }
+            Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+                warn!(
+                    "'{}' is on another filesystem than '{}', copying instead of hardlinking",
+                    target.display(),
+                    source.display()
+                );
+                *cross_device = true;
+            }
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("Failed to hardlink {} -> {}", target.display(), source.display())
+                });
+            }
+        }
+    }
+
+    std::fs::copy(source, target)
+        .with_context(|| format!("Failed to copy {} to {}", source.display(), target.display()))?;
+    stats.copied += 1;
+    Ok(())
+}
+
+/// The `[artifacts]` list an entry comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArtifactKind {
+    Symlink,
+    Copy,
+    Hardlink,
+}
+
+impl ArtifactKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Symlink => "symlink",
+            Self::Copy => "copy",
+            Self::Hardlink => "hardlink",
+        }
+    }
+}
+
+/// What a refresh did with one artifact entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefreshAction {
+    /// The target was missing and has been set up
+    Created,
+    /// The target already exists
+    Skipped,
+    /// A broken symlink at the target was replaced
+    Repaired,
+    /// The target is a broken symlink; left alone without `fix`, or because
+    /// the source is gone too
+    Broken,
+    /// Nothing to set up: the source doesn't exist in the repo root
+    SourceMissing,
+}
+
+impl RefreshAction {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Self::Created => "created",
+            Self::Skipped => "skipped",
+            Self::Repaired => "repaired",
+            Self::Broken => "broken symlink",
+            Self::SourceMissing => "source missing",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefreshEntry {
+    pub artifact: String,
+    pub kind: ArtifactKind,
+    pub action: RefreshAction,
+}
+
+/// Outcome of [`refresh`] for one worktree
+#[derive(Debug, Default)]
+pub struct RefreshReport {
+    pub entries: Vec<RefreshEntry>,
+}
+
+impl RefreshReport {
+    pub fn count(&self, action: RefreshAction) -> usize {
+        self.entries.iter().filter(|e| e.action == action).count()
+    }
+}
+
+/// Apply every configured artifact that is missing from the worktree at
+/// `wt_path`
+///
+/// Targets that already exist are skipped, as during create. A target that
+/// is a symlink to a path that no longer exists (the source was moved, or
+/// the repo root was) is reported as broken, and with `fix` is removed and
+/// set up again.
+pub fn refresh(cfg: &ArtifactsConfig, repo_root: &Path, wt_path: &Path, fix: bool) -> Result<RefreshReport> {
+    let lists = [
+        (ArtifactKind::Symlink, &cfg.symlink),
+        (ArtifactKind::Copy, &cfg.copy),
+        (ArtifactKind::Hardlink, &cfg.hardlink),
+    ];
+
+    let mut report = RefreshReport::default();
+    for (kind, artifacts) in lists {
+        for artifact in artifacts {
+            let source = repo_root.join(artifact);
+            let target = wt_path.join(artifact);
+            let broken = target.is_symlink() && !target.exists();
+
+            let action = if target.exists() {
+                RefreshAction::Skipped
+            } else if !source.exists() {
+                if broken { RefreshAction::Broken } else { RefreshAction::SourceMissing }
+            } else if broken && !fix {
+                RefreshAction::Broken
+            } else {
+                if broken {
+                    std::fs::remove_file(&target)
+                        .with_context(|| format!("Failed to remove broken symlink {}", target.display()))?;
+                }
+                match kind {
+                    ArtifactKind::Symlink => symlink_artifact(repo_root, wt_path, artifact)?,
+                    ArtifactKind::Copy => copy_artifact(repo_root, wt_path, artifact)?,
+                    ArtifactKind::Hardlink => {
+                        hardlink_artifact(repo_root, wt_path, artifact)?;
+                    }
+                }
+                if broken { RefreshAction::Repaired } else { RefreshAction::Created }
+            };
+            report.entries.push(RefreshEntry { artifact: artifact.clone(), kind, action });
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+
+    #[test]
+    fn test_hardlink_artifact_links_files_and_skips_symlinks() {
+        let root = std::env::temp_dir().join(format!("hydra-wt-artifacts-{}", uuid::Uuid::new_v4()));
+        let repo = root.join("repo");
+        let wt = root.join("wt");
+        std::fs::create_dir_all(repo.join("target/debug/deps")).unwrap();
+        std::fs::create_dir_all(&wt).unwrap();
+        std::fs::write(repo.join("target/CACHEDIR.TAG"), "tag").unwrap();
+        std::fs::write(repo.join("target/debug/deps/libfoo.rlib"), "rlib").unwrap();
+        std::os::unix::fs::symlink(repo.join("target/debug"), repo.join("target/latest")).unwrap();
+
+        let stats = hardlink_artifact(&repo, &wt, "target").unwrap();
+        assert_eq!(stats, LinkStats { linked: 2, copied: 0 });
+
+        let linked = wt.join("target/debug/deps/libfoo.rlib");
+        assert_eq!(std::fs::read_to_string(&linked).unwrap(), "rlib");
+        let ino = |p: &Path| std::fs::metadata(p).unwrap().ino();
+        assert_eq!(ino(&linked), ino(&repo.join("target/debug/deps/libfoo.rlib")));
+        assert!(!wt.join("target/latest").exists() && !wt.join("target/latest").is_symlink());
+
+        // A second run leaves the existing target alone
+        assert_eq!(hardlink_artifact(&repo, &wt, "target").unwrap(), LinkStats::default());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_hardlink_artifact_fills_read_only_directories() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = std::env::temp_dir().join(format!("hydra-wt-artifacts-{}", uuid::Uuid::new_v4()));
+        let repo = root.join("repo");
+        let wt = root.join("wt");
+        let module = repo.join("pkg/mod/example.com/lib@v1.0.0");
+        std::fs::create_dir_all(module.join("internal")).unwrap();
+        std::fs::create_dir_all(&wt).unwrap();
+        std::fs::write(module.join("lib.go"), "package lib").unwrap();
+        std::fs::write(module.join("internal/util.go"), "package internal").unwrap();
+        // Go's module cache is read-only all the way down
+        for dir in [module.join("internal"), module.clone()] {
+            std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o555)).unwrap();
+        }
+
+        let stats = hardlink_artifact(&repo, &wt, "pkg").unwrap();
+        assert_eq!(stats, LinkStats { linked: 2, copied: 0 });
+
+        let linked = wt.join("pkg/mod/example.com/lib@v1.0.0");
+        assert_eq!(std::fs::read_to_string(linked.join("internal/util.go")).unwrap(), "package internal");
+        let mode = |p: &Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&linked), 0o555);
+        assert_eq!(mode(&linked.join("internal")), 0o555);
+
+        for dir in [&module, &module.join("internal"), &linked, &linked.join("internal")] {
+            std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_copy_tree_keeps_symlinks_permissions_and_mtimes() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = std::env::temp_dir().join(format!("hydra-wt-artifacts-{}", uuid::Uuid::new_v4()));
+        let source = root.join("repo/vendor");
+        let target = root.join("wt/vendor");
+        std::fs::create_dir_all(source.join("lib/nested")).unwrap();
+        std::fs::create_dir_all(root.join("wt")).unwrap();
+        std::fs::write(source.join("lib/nested/data.txt"), "data").unwrap();
+        std::fs::write(source.join("lib/run.sh"), "#!/bin/sh").unwrap();
+        std::fs::set_permissions(source.join("lib/run.sh"), std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::fs::set_permissions(source.join("lib/nested/data.txt"), std::fs::Permissions::from_mode(0o444)).unwrap();
+        std::os::unix::fs::symlink("nested/data.txt", source.join("lib/current")).unwrap();
+        let old = std::time::SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        set_modified(&source.join("lib/nested/data.txt"), old).unwrap();
+
+        let mut progress = CopyProgress::new("vendor");
+        copy_tree(&source, &target, &mut progress).unwrap();
+        assert_eq!(progress.files, 2);
+
+        let copied = target.join("lib/nested/data.txt");
+        assert_eq!(std::fs::read_to_string(&copied).unwrap(), "data");
+        // Copied, not linked
+        let ino = |p: &Path| std::fs::metadata(p).unwrap().ino();
+        assert_ne!(ino(&copied), ino(&source.join("lib/nested/data.txt")));
+        // The symlink is recreated with its relative target, so it resolves inside the copy
+        assert_eq!(std::fs::read_link(target.join("lib/current")).unwrap(), Path::new("nested/data.txt"));
+        assert_eq!(std::fs::read_to_string(target.join("lib/current")).unwrap(), "data");
+
+        let mode = |p: &Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&target.join("lib/run.sh")), 0o755);
+        assert_eq!(mode(&copied), 0o444);
+        assert_eq!(std::fs::metadata(&copied).unwrap().modified().unwrap(), old);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_refresh_applies_missing_and_repairs_broken_symlinks() {
+        let root = std::env::temp_dir().join(format!("hydra-wt-artifacts-{}", uuid::Uuid::new_v4()));
+        let repo = root.join("repo");
+        let wt = root.join("wt");
+        std::fs::create_dir_all(repo.join("node_modules")).unwrap();
+        std::fs::create_dir_all(&wt).unwrap();
+        std::fs::write(repo.join("config.local.json"), "{}").unwrap();
+        std::fs::write(wt.join("config.local.json"), "{\"edited\": true}").unwrap();
+        // Left behind by a repo that has since moved
+        std::os::unix::fs::symlink(root.join("old-repo/node_modules"), wt.join("node_modules")).unwrap();
+
+        let cfg = ArtifactsConfig {
+            symlink: vec!["node_modules".to_string(), "vendor".to_string()],
+            copy: vec!["config.local.json".to_string()],
+            hardlink: vec![],
+        };
+        let actions = |report: &RefreshReport| -> Vec<(String, RefreshAction)> {
+            report.entries.iter().map(|e| (e.artifact.clone(), e.action)).collect()
+        };
+
+        let report = refresh(&cfg, &repo, &wt, false).unwrap();
+        assert_eq!(actions(&report), vec![
+            ("node_modules".to_string(), RefreshAction::Broken),
+            ("vendor".to_string(), RefreshAction::SourceMissing),
+            ("config.local.json".to_string(), RefreshAction::Skipped),
+        ]);
+        assert!(!wt.join("node_modules").exists());
+
+        let report = refresh(&cfg, &repo, &wt, true).unwrap();
+        assert_eq!(report.count(RefreshAction::Repaired), 1);
+        assert_eq!(std::fs::read_link(wt.join("node_modules")).unwrap(), repo.join("node_modules"));
+        // Existing targets are never overwritten
+        assert_eq!(std::fs::read_to_string(wt.join("config.local.json")).unwrap(), "{\"edited\": true}");
+
+        // An entry added to the config later is created
+        std::fs::create_dir_all(repo.join("vendor")).unwrap();
+        let report = refresh(&cfg, &repo, &wt, false).unwrap();
+        assert_eq!(report.count(RefreshAction::Created), 1);
+        assert_eq!(report.count(RefreshAction::Skipped), 2);
+        assert!(wt.join("vendor").is_symlink());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
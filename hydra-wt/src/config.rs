@@ -1,6 +1,107 @@
 use anyhow::{Context, Result, bail};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::IpAddr;
+use hydra_log::LoggedCommand;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{debug, info};
+
+use crate::ports::PortRange;
+use crate::{probe, template};
+
+/// Committed template locations, relative to the repo root, in lookup order
+pub const TEMPLATE_PATHS: [&str; 2] = ["hydra-wt.toml", ".config/hydra-wt.toml"];
+
+/// Machine-local overrides (gitignored with the rest of `.hydra/`)
+pub const LOCAL_CONFIG_PATH: &str = ".hydra/wt.local.toml";
+
+/// Single-file config written before layering; read as the local layer
+/// until the first save
+const LEGACY_CONFIG_PATH: &str = ".hydra/wt.toml";
+
+/// Overrides `worktrees.directory`, e.g. to put CI worktrees under `$RUNNER_TEMP`
+pub const DIR_ENV: &str = "HYDRA_WT_DIR";
+/// Overrides where the port registry is kept
+pub const REGISTRY_ENV: &str = "HYDRA_WT_REGISTRY";
+
+/// Where a config value came from, lowest precedence first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConfigLayer {
+    Default,
+    Template,
+    Local,
+    /// `HYDRA_WT_DIR`
+    Env,
+}
+
+impl fmt::Display for ConfigLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigLayer::Default => write!(f, "default"),
+            ConfigLayer::Template => write!(f, "template"),
+            ConfigLayer::Local => write!(f, "local"),
+            ConfigLayer::Env => write!(f, "env"),
+        }
+    }
+}
+
+/// Settings taken from the environment, which win over every config layer
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EnvOverrides {
+    /// `HYDRA_WT_DIR`, replacing `worktrees.directory`
+    pub worktrees_dir: Option<String>,
+    /// `HYDRA_WT_REGISTRY`, replacing `.hydra/wt-ports.json`; relative to
+    /// the repository root unless absolute
+    pub registry: Option<PathBuf>,
+}
+
+impl EnvOverrides {
+    /// The overrides set in this process's environment; empty values are
+    /// ignored
+    pub fn from_env() -> Self {
+        let var = |name| std::env::var(name).ok().filter(|v: &String| !v.trim().is_empty());
+        Self { worktrees_dir: var(DIR_ENV), registry: var(REGISTRY_ENV).map(PathBuf::from) }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.worktrees_dir.is_none() && self.registry.is_none()
+    }
+
+    /// `(variable, value)` of each override that is set, for `status`
+    pub fn describe(&self) -> Vec<(&'static str, String)> {
+        let mut set = Vec::new();
+        if let Some(dir) = &self.worktrees_dir {
+            set.push((DIR_ENV, dir.clone()));
+        }
+        if let Some(registry) = &self.registry {
+            set.push((REGISTRY_ENV, registry.display().to_string()));
+        }
+        set
+    }
+
+    /// The layer these put over the config files
+    fn layer(&self) -> toml::Table {
+        let mut layer = toml::Table::new();
+        if let Some(dir) = &self.worktrees_dir {
+            let mut worktrees = toml::Table::new();
+            worktrees.insert("directory".to_string(), toml::Value::String(dir.clone()));
+            layer.insert("worktrees".to_string(), toml::Value::Table(worktrees));
+        }
+        layer
+    }
+}
+
+/// Effective config plus the layer each value came from
+#[derive(Debug)]
+pub struct LayeredConfig {
+    pub config: WtConfig,
+    /// Dotted key (e.g. `ports.range_start`) to the layer that set it
+    pub origins: BTreeMap<String, ConfigLayer>,
+    pub template_path: Option<PathBuf>,
+    pub local_path: Option<PathBuf>,
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct WtConfig {
@@ -11,6 +112,196 @@ pub struct WtConfig {
     pub artifacts: ArtifactsConfig,
     #[serde(default)]
     pub hooks: HooksConfig,
+    #[serde(default)]
+    pub template: TemplateConfig,
+    #[serde(default)]
+    pub hydra: HydraConfig,
+    #[serde(default)]
+    pub archive: ArchiveConfig,
+    #[serde(default)]
+    pub merge: MergeConfig,
+    #[serde(default)]
+    pub open: OpenConfig,
+    /// Settings for branches matching a pattern; the first match applies
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub overrides: Vec<BranchOverride>,
+}
+
+/// `[[overrides]]`: settings layered over the rest of the config for the
+/// branches `pattern` matches. Each section may set any subset of its keys.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BranchOverride {
+    /// Glob against the branch name, e.g. `hydralph/*`
+    pub pattern: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ports: Option<toml::Table>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env: Option<toml::Table>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hooks: Option<toml::Table>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifacts: Option<toml::Table>,
+    /// Only `sparse`; where worktrees go can't differ by branch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub worktrees: Option<toml::Table>,
+}
+
+impl BranchOverride {
+    pub fn matches(&self, branch: &str) -> Result<bool> {
+        let glob = globset::Glob::new(&self.pattern)
+            .with_context(|| format!("Invalid overrides pattern '{}'", self.pattern))?;
+        Ok(glob.compile_matcher().is_match(branch))
+    }
+
+    /// The sections this override sets, as a config layer
+    fn layer(&self) -> toml::Table {
+        let sections = [
+            ("ports", &self.ports),
+            ("env", &self.env),
+            ("hooks", &self.hooks),
+            ("artifacts", &self.artifacts),
+            ("worktrees", &self.worktrees),
+        ];
+        sections
+            .into_iter()
+            .filter_map(|(name, section)| Some((name.to_string(), toml::Value::Table(section.clone()?))))
+            .collect()
+    }
+}
+
+/// The config as it applies to one branch: [`WtConfig`] with the first
+/// matching override applied. Derefs to the resolved [`WtConfig`].
+#[derive(Debug)]
+pub struct EffectiveConfig {
+    pub config: WtConfig,
+    /// Pattern of the override that applied, if any
+    pub pattern: Option<String>,
+}
+
+impl std::ops::Deref for EffectiveConfig {
+    type Target = WtConfig;
+
+    fn deref(&self) -> &WtConfig {
+        &self.config
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HydraConfig {
+    /// Emit events to hydra-mail; off skips the daemon check and the
+    /// pending queue entirely
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for HydraConfig {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MergeConfig {
+    /// Run in each source worktree by `merge --check`; a source only
+    /// merges if it exits 0
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check_command: Option<String>,
+    /// Seconds the check may run before it is killed and the source
+    /// counts as failed; 0 waits forever
+    #[serde(default = "default_check_timeout")]
+    pub check_timeout_secs: u64,
+    /// Message of merge and squash commits, with `{source}`, `{target}`
+    /// and `{count}` (commits merged) filled in; git's own when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_template: Option<String>,
+    /// Sign merge and squash commits with the configured signing key
+    #[serde(default)]
+    pub sign: bool,
+    /// Push the target to `remote` after each merge that lands;
+    /// `merge --no-push` skips it once
+    #[serde(default)]
+    pub push: bool,
+    /// Remote `push` and `merge --push` push the target to
+    #[serde(default = "default_remote")]
+    pub remote: String,
+}
+
+fn default_check_timeout() -> u64 {
+    1800
+}
+
+fn default_remote() -> String {
+    "origin".to_string()
+}
+
+impl Default for MergeConfig {
+    fn default() -> Self {
+        Self {
+            check_command: None,
+            check_timeout_secs: default_check_timeout(),
+            message_template: None,
+            sign: false,
+            push: false,
+            remote: default_remote(),
+        }
+    }
+}
+
+impl MergeConfig {
+    pub fn check_timeout(&self) -> Option<Duration> {
+        (self.check_timeout_secs > 0).then(|| Duration::from_secs(self.check_timeout_secs))
+    }
+
+    /// `template` (a `message_template` or `merge --message`) for merging
+    /// `count` commits of `source` into `target`
+    pub fn render_message(template: &str, source: &str, target: &str, count: usize) -> String {
+        template
+            .replace("{source}", source)
+            .replace("{target}", target)
+            .replace("{count}", &count.to_string())
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct OpenConfig {
+    /// Shell command `open --editor` runs with the worktree path, e.g.
+    /// `code`; `$VISUAL` or `$EDITOR` when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub editor: Option<String>,
+}
+
+impl OpenConfig {
+    /// `editor`, falling back to `$VISUAL` and then `$EDITOR`
+    pub fn editor_command(&self) -> Option<String> {
+        self.editor
+            .clone()
+            .or_else(|| std::env::var("VISUAL").ok())
+            .or_else(|| std::env::var("EDITOR").ok())
+            .filter(|e| !e.trim().is_empty())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArchiveConfig {
+    /// Archives kept in `.hydra/archives`, oldest pruned first; 0 keeps
+    /// them all
+    #[serde(default = "default_archive_keep")]
+    pub keep: usize,
+}
+
+fn default_archive_keep() -> usize {
+    20
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        Self { keep: default_archive_keep() }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Default)]
@@ -19,29 +310,184 @@ pub struct ArtifactsConfig {
     pub symlink: Vec<String>,
     #[serde(default)]
     pub copy: Vec<String>,
+    /// Directories recreated in the worktree with every file hardlinked
+    #[serde(default)]
+    pub hardlink: Vec<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct HooksConfig {
     #[serde(default)]
     pub post_create: Vec<String>,
+    /// Seconds a single hook may run before it is killed; 0 waits forever
+    #[serde(default = "default_hook_timeout")]
+    pub timeout_secs: u64,
+}
+
+fn default_hook_timeout() -> u64 {
+    600
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        Self { post_create: Vec::new(), timeout_secs: default_hook_timeout() }
+    }
+}
+
+impl HooksConfig {
+    pub fn timeout(&self) -> Option<Duration> {
+        (self.timeout_secs > 0).then(|| Duration::from_secs(self.timeout_secs))
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PortsConfig {
     pub range_start: u16,
     pub range_end: u16,
+    /// Consecutive ports reserved per worktree, exposed to templates as
+    /// `PORT`, `PORT_2`, `PORT_3`, ...
+    #[serde(default = "default_port_count")]
+    pub count: u16,
+    /// Addresses a port must be free on before it is allocated
+    #[serde(default = "default_check_hosts")]
+    pub check_hosts: Vec<String>,
+    /// Address the worktree's services listen on; used by `status --check`
+    /// and exposed to templates as `bind_host`
+    #[serde(default = "default_bind_host")]
+    pub bind_host: String,
+    /// Ports that are never allocated because something outside hydra
+    /// owns them, as ports or `"START-END"` ranges
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reserved: Vec<PortRange>,
+    /// When the range is full, free the ports of worktrees whose directory
+    /// and branch are both gone, then try once more
+    #[serde(default)]
+    pub auto_recycle: bool,
+}
+
+fn default_port_count() -> u16 {
+    1
+}
+
+fn default_check_hosts() -> Vec<String> {
+    vec!["127.0.0.1".to_string(), "::1".to_string()]
+}
+
+fn default_bind_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+impl PortsConfig {
+    pub fn check_addrs(&self) -> Result<Vec<IpAddr>> {
+        probe::parse_hosts(&self.check_hosts).context("Invalid ports.check_hosts")
+    }
+
+    pub fn bind_addr(&self) -> Result<IpAddr> {
+        probe::parse_host(&self.bind_host).context("Invalid ports.bind_host")
+    }
+
+    pub fn is_reserved(&self, port: u16) -> bool {
+        self.reserved.iter().any(|range| range.contains(port))
+    }
+
+    /// Reserved ports inside `range_start`-`range_end`
+    pub fn reserved_in_range(&self) -> std::collections::BTreeSet<u16> {
+        self.reserved
+            .iter()
+            .flat_map(PortRange::ports)
+            .filter(|p| (self.range_start..=self.range_end).contains(p))
+            .collect()
+    }
+
+    /// Hosts `status --check` connects to: the bind address, then the
+    /// check hosts
+    pub fn probe_addrs(&self) -> Result<Vec<IpAddr>> {
+        let mut addrs = vec![probe::connect_host(self.bind_addr()?)];
+        for addr in self.check_addrs()? {
+            if !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+        }
+        Ok(addrs)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct EnvConfig {
     pub template: String,
     pub output: String,
+    /// Further templates rendered into the worktree after the one above
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<TemplateFile>,
+}
+
+/// A template in the repo root and the file it renders to in the worktree
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TemplateFile {
+    pub template: String,
+    pub output: String,
+}
+
+impl EnvConfig {
+    /// Every template/output pair, `template`/`output` first
+    pub fn pairs(&self) -> impl Iterator<Item = (&str, &str)> {
+        std::iter::once((self.template.as_str(), self.output.as_str()))
+            .chain(self.files.iter().map(|f| (f.template.as_str(), f.output.as_str())))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct TemplateConfig {
+    /// Extra template variables; values may themselves use the built-in
+    /// variables, e.g. `db = "app_{{ branch_slug }}"`
+    #[serde(default)]
+    pub vars: BTreeMap<String, String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct WorktreesConfig {
     pub directory: String,
+    /// Directory name of each worktree under `directory`, from the
+    /// placeholders `{repo}`, `{branch}` and `{branch_slug}`
+    #[serde(default = "default_name_template")]
+    pub name_template: String,
+    /// Directories a new worktree checks out, as a cone-mode sparse
+    /// checkout; empty checks out everything
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sparse: Vec<String>,
+}
+
+fn default_name_template() -> String {
+    "{branch}".to_string()
+}
+
+/// Placeholders `worktrees.name_template` may use
+const NAME_PLACEHOLDERS: [&str; 3] = ["repo", "branch", "branch_slug"];
+
+impl WorktreesConfig {
+    /// Fail on unknown placeholders, or a template that would give every
+    /// branch the same directory
+    pub fn check_name_template(&self) -> Result<()> {
+        let template = &self.name_template;
+        let mut rest = template.as_str();
+        while let Some(start) = rest.find('{') {
+            let Some(end) = rest[start..].find('}') else {
+                bail!("worktrees.name_template '{}' has an unclosed '{{'", template);
+            };
+            let name = &rest[start + 1..start + end];
+            if !NAME_PLACEHOLDERS.contains(&name) {
+                bail!(
+                    "worktrees.name_template '{}' uses unknown placeholder {{{}}}; expected {{repo}}, {{branch}} or {{branch_slug}}",
+                    template, name
+                );
+            }
+            rest = &rest[start + end + 1..];
+        }
+        if !template.contains("{branch}") && !template.contains("{branch_slug}") {
+            bail!("worktrees.name_template '{}' must contain {{branch}} or {{branch_slug}}", template);
+        }
+        Ok(())
+    }
 }
 
 impl Default for WtConfig {
@@ -50,78 +496,397 @@ impl Default for WtConfig {
             ports: PortsConfig {
                 range_start: 3001,
                 range_end: 3099,
+                count: default_port_count(),
+                check_hosts: default_check_hosts(),
+                bind_host: default_bind_host(),
+                reserved: Vec::new(),
+                auto_recycle: false,
             },
             env: EnvConfig {
                 template: ".env.template".to_string(),
                 output: ".env.local".to_string(),
+                files: Vec::new(),
             },
             worktrees: WorktreesConfig {
                 directory: "../".to_string(),
+                name_template: default_name_template(),
+                sparse: Vec::new(),
             },
             artifacts: ArtifactsConfig::default(),
             hooks: HooksConfig::default(),
+            template: TemplateConfig::default(),
+            hydra: HydraConfig::default(),
+            archive: ArchiveConfig::default(),
+            merge: MergeConfig::default(),
+            open: OpenConfig::default(),
+            overrides: Vec::new(),
         }
     }
 }
 
 impl WtConfig {
+    /// Local layer, the only file hydra-wt writes, relative to the repo root
     pub fn config_path() -> PathBuf {
-        PathBuf::from(".hydra/wt.toml")
+        PathBuf::from(LOCAL_CONFIG_PATH)
+    }
+
+    /// Committed template under `root`, if the repo has one
+    pub fn template_path_at(root: &Path) -> Option<PathBuf> {
+        TEMPLATE_PATHS.iter()
+            .map(|p| root.join(p))
+            .find(|p| p.is_file())
     }
 
+    /// Local layer under `root`, falling back to the pre-layering file
+    fn local_path_at(root: &Path) -> Option<PathBuf> {
+        [LOCAL_CONFIG_PATH, LEGACY_CONFIG_PATH].iter()
+            .map(|p| root.join(p))
+            .find(|p| p.is_file())
+    }
+
+    /// Load the config of the repository the current directory is in,
+    /// wherever in it (or in which of its worktrees) that is
     pub fn load() -> Result<Self> {
-        let path = Self::config_path();
-        if !path.exists() {
-            bail!("Config not found at {}. Run 'hydra-wt init' first.", path.display());
+        Ok(Self::load_layered_at(&project_root()?)?.config)
+    }
+
+    /// Merge defaults < committed template < local overrides < environment
+    pub fn load_layered_at(root: &Path) -> Result<LayeredConfig> {
+        Self::load_layered_with(root, &EnvOverrides::from_env())
+    }
+
+    /// [`load_layered_at`](Self::load_layered_at) with `env` in place of
+    /// the process environment
+    pub fn load_layered_with(root: &Path, env: &EnvOverrides) -> Result<LayeredConfig> {
+        let template_path = Self::template_path_at(root);
+        let local_path = Self::local_path_at(root);
+        if template_path.is_none() && local_path.is_none() {
+            bail!(
+                "Config not found at {} or {}. Run 'hydra-wt init' first.",
+                LOCAL_CONFIG_PATH, TEMPLATE_PATHS[0]
+            );
+        }
+
+        let mut merged = to_table(&WtConfig::default())?;
+        let mut origins = BTreeMap::new();
+        record_origins(&merged, "", ConfigLayer::Default, &mut origins);
+
+        if let Some(path) = &template_path {
+            let template = read_table(path)?;
+            check_shareable(&template, path)?;
+            debug!("config: template layer from {}", path.display());
+            merge_layer(&mut merged, &template, "", ConfigLayer::Template, &mut origins);
         }
-        let content = std::fs::read_to_string(&path)
-            .with_context(|| format!("Failed to read {}", path.display()))?;
-        let config: WtConfig = toml::from_str(&content)
-            .with_context(|| format!("Failed to parse {}", path.display()))?;
-        Ok(config)
+        if let Some(path) = &local_path {
+            debug!("config: local layer from {}", path.display());
+            merge_layer(&mut merged, &read_table(path)?, "", ConfigLayer::Local, &mut origins);
+        }
+        if !env.is_empty() {
+            debug!("config: environment layer {:?}", env.describe());
+            merge_layer(&mut merged, &env.layer(), "", ConfigLayer::Env, &mut origins);
+        }
+
+        let config: WtConfig = toml::Value::Table(merged).try_into()
+            .context("Invalid merged hydra-wt config")?;
+        config.worktrees.check_name_template()?;
+        config.check_overrides()?;
+        Ok(LayeredConfig { config, origins, template_path, local_path })
     }
 
     pub fn save(&self) -> Result<()> {
-        let path = Self::config_path();
-        let content = toml::to_string_pretty(self)
+        self.save_at(&project_root()?)
+    }
+
+    /// Write the values that differ from defaults + template to the local layer.
+    /// The committed template is never written.
+    pub fn save_at(&self, root: &Path) -> Result<()> {
+        let mut base = to_table(&WtConfig::default())?;
+        if let Some(path) = Self::template_path_at(root) {
+            merge_layer(&mut base, &read_table(&path)?, "", ConfigLayer::Template, &mut BTreeMap::new());
+        }
+        let mut current = to_table(self)?;
+        // A directory from HYDRA_WT_DIR is not the config's to keep
+        if EnvOverrides::from_env().worktrees_dir.as_deref() == Some(self.worktrees.directory.as_str()) {
+            let from_files = match Self::load_layered_with(root, &EnvOverrides::default()) {
+                Ok(layered) => layered.config.worktrees.directory,
+                Err(_) => WtConfig::default().worktrees.directory,
+            };
+            if let Some(toml::Value::Table(worktrees)) = current.get_mut("worktrees") {
+                worktrees.insert("directory".to_string(), toml::Value::String(from_files));
+            }
+        }
+        let overrides = diff_tables(&base, &current);
+
+        let path = root.join(LOCAL_CONFIG_PATH);
+        let content = toml::to_string_pretty(&overrides)
             .context("Failed to serialize config")?;
         std::fs::write(&path, content)
             .with_context(|| format!("Failed to write {}", path.display()))?;
+        debug!("wrote {}", path.display());
         Ok(())
     }
 
     pub fn init() -> Result<()> {
-        let hydra_dir = Path::new(".hydra");
-        if !hydra_dir.exists() {
+        let root = project_root()?;
+        if !root.join(".hydra").exists() {
             bail!(".hydra/ directory not found. Run 'hydra-mail init' first.");
         }
 
-        let config_path = Self::config_path();
-        if config_path.exists() {
-            bail!("Config already exists at {}", config_path.display());
+        // A committed template means the repo is already configured
+        if Self::template_path_at(&root).is_some() {
+            return Self::clone_setup_at(&root);
         }
 
-        let config = WtConfig::default();
-        config.save()?;
-        println!("Created {}", config_path.display());
+        if let Some(existing) = Self::local_path_at(&root) {
+            bail!("Config already exists at {}", display_path(&existing).display());
+        }
+
+        // No template: write the full defaults locally so they're easy to edit
+        let config_path = root.join(Self::config_path());
+        let content = toml::to_string_pretty(&WtConfig::default())
+            .context("Failed to serialize config")?;
+        std::fs::write(&config_path, content)
+            .with_context(|| format!("Failed to write {}", config_path.display()))?;
+        info!("Created {}", display_path(&config_path).display());
+        Ok(())
+    }
+
+    /// Set up a fresh clone from the committed template: validate it and
+    /// create an empty local override file.
+    pub fn clone_setup_at(root: &Path) -> Result<()> {
+        let Some(template) = Self::template_path_at(root) else {
+            bail!("No committed template found (looked for {})", TEMPLATE_PATHS.join(", "));
+        };
+        if !root.join(".hydra").exists() {
+            bail!(".hydra/ directory not found. Run 'hydra-mail init' first.");
+        }
+
+        // Fails early if the template is invalid or not shareable
+        Self::load_layered_at(root)?;
+        info!("Using template {}", display_path(&template).display());
+
+        let local = root.join(LOCAL_CONFIG_PATH);
+        if local.exists() {
+            info!("Keeping existing {}", display_path(&local).display());
+        } else {
+            std::fs::write(&local, "# Machine-local overrides for hydra-wt; see hydra-wt config show --origin\n")
+                .with_context(|| format!("Failed to write {}", local.display()))?;
+            info!("Created {}", display_path(&local).display());
+        }
         Ok(())
     }
 
+    /// The config for `branch`: this one with the first `[[overrides]]`
+    /// entry whose pattern matches the branch layered on top
+    pub fn resolve_for(&self, branch: &str) -> Result<EffectiveConfig> {
+        let mut matched = None;
+        for entry in &self.overrides {
+            if entry.matches(branch)? {
+                debug!("config: overrides for '{}' apply to {}", entry.pattern, branch);
+                matched = Some(entry);
+                break;
+            }
+        }
+        Ok(EffectiveConfig {
+            config: self.with_override(matched)?,
+            pattern: matched.map(|entry| entry.pattern.clone()),
+        })
+    }
+
+    fn with_override(&self, entry: Option<&BranchOverride>) -> Result<WtConfig> {
+        let mut table = to_table(self)?;
+        let Some(entry) = entry else {
+            return toml::Value::Table(table).try_into().context("Invalid hydra-wt config");
+        };
+        merge_layer(&mut table, &entry.layer(), "", ConfigLayer::Local, &mut BTreeMap::new());
+        toml::Value::Table(table)
+            .try_into()
+            .with_context(|| format!("Invalid overrides entry for '{}'", entry.pattern))
+    }
+
+    /// Every override has a valid pattern and yields a valid config
+    fn check_overrides(&self) -> Result<()> {
+        for entry in &self.overrides {
+            entry.matches("")?;
+            if let Some(key) = entry.worktrees.iter().flat_map(|w| w.keys()).find(|k| *k != "sparse") {
+                bail!("overrides entry for '{}' sets worktrees.{}; only worktrees.sparse can differ by branch", entry.pattern, key);
+            }
+            self.with_override(Some(entry))?;
+        }
+        Ok(())
+    }
+
+    /// `worktrees.directory` as configured
     pub fn worktree_dir(&self) -> PathBuf {
         PathBuf::from(&self.worktrees.directory)
     }
 
+    /// `worktrees.directory` resolved against `repo_root`
+    pub fn worktree_dir_in(&self, repo_root: &Path) -> PathBuf {
+        normalize(&repo_root.join(self.worktree_dir()))
+    }
+
+    /// Directory name of the worktree of `branch` in the repository at
+    /// `repo_root`, rendered from `worktrees.name_template`
+    pub fn worktree_name(&self, repo_root: &Path, branch: &str) -> String {
+        let repo = repo_root.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+        self.worktrees
+            .name_template
+            .replace("{repo}", &repo)
+            .replace("{branch_slug}", &template::branch_slug(branch))
+            .replace("{branch}", branch)
+    }
+
+    /// Where a new worktree of `branch` goes, as configured; a relative
+    /// directory is relative to the repo root. `{repo}` in the name
+    /// template is the name of the repository of the current directory.
+    #[deprecated(note = "use `worktree_path_in`, which takes the repo root instead of asking git")]
     pub fn worktree_path(&self, branch: &str) -> PathBuf {
-        self.worktree_dir().join(branch)
+        let repo_root = get_repo_root().unwrap_or_default();
+        self.worktree_dir().join(self.worktree_name(&repo_root, branch))
+    }
+
+    /// Where a new worktree of `branch` goes; a relative directory is
+    /// relative to the repo root. Existing worktrees are found through the
+    /// path the port registry recorded for them.
+    pub fn worktree_path_in(&self, repo_root: &Path, branch: &str) -> PathBuf {
+        self.worktree_dir_in(repo_root).join(self.worktree_name(repo_root, branch))
+    }
+
+    /// Where worktrees went before `name_template` existed and the registry
+    /// recorded paths: the branch name under `worktrees.directory`
+    pub fn legacy_worktree_path_in(&self, repo_root: &Path, branch: &str) -> PathBuf {
+        normalize(&self.worktree_dir_in(repo_root).join(branch))
+    }
+}
+
+/// `path` with `.` and `..` components folded away lexically, so
+/// `/src/repo/../feature` reads as `/src/feature`
+fn normalize(path: &Path) -> PathBuf {
+    use std::path::Component;
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if matches!(out.components().next_back(), Some(Component::Normal(_))) => {
+                out.pop();
+            }
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+// ─────────────────────────────────────────────────────────────────────────────
+// Layer merging
+// ─────────────────────────────────────────────────────────────────────────────
+
+fn to_table(config: &WtConfig) -> Result<toml::Table> {
+    toml::Table::try_from(config).context("Failed to serialize config")
+}
+
+fn read_table(path: &Path) -> Result<toml::Table> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    toml::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+fn join_key(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", prefix, key)
+    }
+}
+
+fn record_origins(table: &toml::Table, prefix: &str, layer: ConfigLayer, origins: &mut BTreeMap<String, ConfigLayer>) {
+    for (key, value) in table {
+        let path = join_key(prefix, key);
+        match value {
+            toml::Value::Table(inner) => record_origins(inner, &path, layer, origins),
+            _ => {
+                origins.insert(path, layer);
+            }
+        }
+    }
+}
+
+/// Overlay `layer` onto `base`; tables merge key by key, everything else
+/// (including arrays) is replaced whole.
+fn merge_layer(
+    base: &mut toml::Table,
+    layer: &toml::Table,
+    prefix: &str,
+    origin: ConfigLayer,
+    origins: &mut BTreeMap<String, ConfigLayer>,
+) {
+    for (key, value) in layer {
+        let path = join_key(prefix, key);
+        match (base.get_mut(key), value) {
+            (Some(toml::Value::Table(existing)), toml::Value::Table(inner)) => {
+                merge_layer(existing, inner, &path, origin, origins);
+            }
+            _ => {
+                base.insert(key.clone(), value.clone());
+                match value {
+                    toml::Value::Table(inner) => record_origins(inner, &path, origin, origins),
+                    _ => {
+                        origins.insert(path, origin);
+                    }
+                }
+            }
+        }
     }
 }
 
+/// Entries of `new` that differ from `base`
+fn diff_tables(base: &toml::Table, new: &toml::Table) -> toml::Table {
+    let mut out = toml::Table::new();
+    for (key, value) in new {
+        match (base.get(key), value) {
+            (Some(toml::Value::Table(old)), toml::Value::Table(inner)) => {
+                let nested = diff_tables(old, inner);
+                if !nested.is_empty() {
+                    out.insert(key.clone(), toml::Value::Table(nested));
+                }
+            }
+            (Some(old), _) if old == value => {}
+            _ => {
+                out.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    out
+}
+
+/// The template is shared across machines, so absolute paths don't belong there
+fn check_shareable(template: &toml::Table, path: &Path) -> Result<()> {
+    let dir = template.get("worktrees")
+        .and_then(|w| w.get("directory"))
+        .and_then(|d| d.as_str());
+    if let Some(dir) = dir {
+        if Path::new(dir).is_absolute() {
+            bail!(
+                "{} sets worktrees.directory to the machine-specific path '{}'. \
+                 Use a relative path or move it to {}",
+                path.display(), dir, LOCAL_CONFIG_PATH
+            );
+        }
+    }
+    Ok(())
+}
+
 pub fn get_project_uuid() -> Result<String> {
-    let hydra_config_path = Path::new(".hydra/config.toml");
+    get_project_uuid_at(&project_root()?)
+}
+
+pub fn get_project_uuid_at(root: &Path) -> Result<String> {
+    let hydra_config_path = root.join(".hydra/config.toml");
     if !hydra_config_path.exists() {
         bail!(".hydra/config.toml not found. Run 'hydra-mail init' first.");
     }
-    let content = std::fs::read_to_string(hydra_config_path)
+    let content = std::fs::read_to_string(&hydra_config_path)
         .context("Failed to read .hydra/config.toml")?;
 
     #[derive(Deserialize)]
@@ -134,19 +899,121 @@ pub fn get_project_uuid() -> Result<String> {
     Ok(config.project_uuid)
 }
 
+/// The hydra-mail daemon's socket for the project at `root`, as the
+/// hydra-mail client finds it, or `.hydra/hydra.sock` if `.hydra/config.toml`
+/// can't be read
+pub fn hydra_socket_at(root: &Path) -> PathBuf {
+    hydra_mail::client::MailClient::locate(root)
+        .map(|client| client.socket().to_path_buf())
+        .unwrap_or_else(|_| root.join(".hydra/hydra.sock"))
+}
+
+/// Root of the main checkout of the repository the current directory is in.
+///
+/// From a subdirectory this is the top level; from a linked worktree it is
+/// the checkout the worktree was added from, which is where `.hydra/` lives.
+/// A bare repository has no main checkout; its root is the directory
+/// holding it when git finds the repository from there too (a `.bare`
+/// directory next to a `.git` file pointing at it), otherwise the bare
+/// repository itself, e.g. `project.git`.
 pub fn get_repo_root() -> Result<PathBuf> {
+    repo_root_of(&std::env::current_dir().context("Failed to read current directory")?)
+}
+
+/// [`get_repo_root`] for a repository containing `dir`
+pub fn repo_root_of(dir: &Path) -> Result<PathBuf> {
+    let git = |args: &[&str]| -> Result<Option<PathBuf>> {
+        let output = std::process::Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(args)
+            .logged_output()
+            .context("Failed to run git rev-parse")?;
+        if !output.status.success() {
+            return Ok(None);
+        }
+        let path = String::from_utf8(output.stdout)
+            .context("Invalid UTF-8 in git output")?;
+        Ok(Some(dir.join(path.trim())))
+    };
+
+    // The common dir is the main checkout's .git, shared by all its worktrees
+    let Some(common_dir) = git(&["rev-parse", "--git-common-dir"])? else {
+        bail!("Not in a git repository");
+    };
+    let common_dir = common_dir.canonicalize()
+        .with_context(|| format!("Failed to resolve {}", common_dir.display()))?;
+    if common_dir.file_name().is_some_and(|name| name == ".git") {
+        if let Some(root) = common_dir.parent() {
+            return Ok(root.to_path_buf());
+        }
+    }
+
+    if is_bare(&common_dir)? {
+        if let Some(parent) = common_dir.parent() {
+            let from_parent = common_dir_of(parent)?;
+            if from_parent.is_some_and(|dir| dir == common_dir) {
+                return Ok(parent.to_path_buf());
+            }
+        }
+        return Ok(common_dir);
+    }
+
+    // Submodules keep their git dir elsewhere
+    git(&["rev-parse", "--show-toplevel"])?.context("Not in a git repository")
+}
+
+/// Whether the repository with git dir `git_dir` is bare
+fn is_bare(git_dir: &Path) -> Result<bool> {
     let output = std::process::Command::new("git")
-        .args(["rev-parse", "--show-toplevel"])
-        .output()
+        .arg("--git-dir")
+        .arg(git_dir)
+        .args(["rev-parse", "--is-bare-repository"])
+        .logged_output()
         .context("Failed to run git rev-parse")?;
+    Ok(output.status.success() && String::from_utf8_lossy(&output.stdout).trim() == "true")
+}
 
+/// The git common dir git finds from `dir`, if `dir` is in a repository
+fn common_dir_of(dir: &Path) -> Result<Option<PathBuf>> {
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["rev-parse", "--git-common-dir"])
+        .logged_output()
+        .context("Failed to run git rev-parse")?;
     if !output.status.success() {
-        bail!("Not in a git repository");
+        return Ok(None);
     }
+    let common_dir = dir.join(String::from_utf8_lossy(&output.stdout).trim());
+    Ok(common_dir.canonicalize().ok())
+}
+
+/// Whether the repository at `root` (as from [`get_repo_root`]) is bare,
+/// so every branch is worked on in a linked worktree
+pub fn is_bare_at(root: &Path) -> bool {
+    common_dir_of(root)
+        .ok()
+        .flatten()
+        .is_some_and(|common_dir| is_bare(&common_dir).unwrap_or(false))
+}
+
+/// Directory holding `.hydra/`: the repo root inside a git repository,
+/// otherwise the current directory
+pub fn project_root() -> Result<PathBuf> {
+    match get_repo_root() {
+        Ok(root) => Ok(root),
+        Err(_) => std::env::current_dir().context("Failed to read current directory"),
+    }
+}
 
-    let path = String::from_utf8(output.stdout)
-        .context("Invalid UTF-8 in git output")?;
-    Ok(PathBuf::from(path.trim()))
+/// `path` relative to the current directory when it is below it, for
+/// messages
+pub fn display_path(path: &Path) -> PathBuf {
+    std::env::current_dir()
+        .ok()
+        .and_then(|cwd| path.strip_prefix(cwd).ok().map(Path::to_path_buf))
+        .unwrap_or_else(|| path.to_path_buf())
 }
 
 #[cfg(test)]
@@ -164,10 +1031,93 @@ mod tests {
     }
 
     #[test]
-    fn test_worktree_path() {
+    fn test_worktree_path_in() {
         let config = WtConfig::default();
-        let path = config.worktree_path("feature-branch");
-        assert_eq!(path, PathBuf::from("../feature-branch"));
+        let root = Path::new("/src/repo");
+        assert_eq!(config.worktree_path_in(root, "feature-x"), PathBuf::from("/src/feature-x"));
+
+        let nested = WtConfig {
+            worktrees: WorktreesConfig { directory: "./wts".to_string(), ..WtConfig::default().worktrees },
+            ..WtConfig::default()
+        };
+        assert_eq!(nested.worktree_path_in(root, "a/b"), PathBuf::from("/src/repo/wts/a/b"));
+        #[allow(deprecated)]
+        let unresolved = nested.worktree_path("a/b");
+        assert_eq!(unresolved, PathBuf::from("./wts/a/b"));
+    }
+
+    #[test]
+    fn test_worktree_name_template() {
+        let mut config = WtConfig::default();
+        config.worktrees.name_template = "{repo}-{branch_slug}".to_string();
+        let root = Path::new("/src/repo");
+        assert_eq!(config.worktree_path_in(root, "feature/login/oauth"), PathBuf::from("/src/repo-feature-login-oauth"));
+        assert_eq!(config.legacy_worktree_path_in(root, "feature/login"), PathBuf::from("/src/feature/login"));
+        config.worktrees.check_name_template().unwrap();
+
+        for bad in ["{repo}", "{repo}-{name}", "{branch"] {
+            config.worktrees.name_template = bad.to_string();
+            assert!(config.worktrees.check_name_template().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_repo_root_from_subdirectory_and_worktree() {
+        let git = |dir: &Path, args: &[&str]| {
+            let status = std::process::Command::new("git").arg("-C").arg(dir).args(args).output().unwrap().status;
+            assert!(status.success(), "git {:?} failed", args);
+        };
+        let temp_dir = std::env::temp_dir().join(format!("hydra-test-{}", uuid::Uuid::new_v4()));
+        let repo = temp_dir.join("repo");
+        std::fs::create_dir_all(repo.join("src/deep")).unwrap();
+        git(&repo, &["init", "-q", "-b", "main"]);
+        git(&repo, &["-c", "user.email=test@example.com", "-c", "user.name=Test", "commit", "-q", "--allow-empty", "-m", "init"]);
+        git(&repo, &["worktree", "add", "-q", "-b", "feature", "../feature"]);
+        std::fs::create_dir_all(temp_dir.join("feature/sub")).unwrap();
+
+        let root = repo.canonicalize().unwrap();
+        assert_eq!(repo_root_of(&repo).unwrap(), root);
+        assert_eq!(repo_root_of(&repo.join("src/deep")).unwrap(), root);
+        assert_eq!(repo_root_of(&temp_dir.join("feature")).unwrap(), root);
+        assert_eq!(repo_root_of(&temp_dir.join("feature/sub")).unwrap(), root);
+        assert!(repo_root_of(&temp_dir).is_err());
+
+        std::fs::remove_dir_all(&temp_dir).ok();
+    }
+
+    #[test]
+    fn test_repo_root_of_bare_repo() {
+        let git = |dir: &Path, args: &[&str]| {
+            let status = std::process::Command::new("git").arg("-C").arg(dir).args(args).output().unwrap().status;
+            assert!(status.success(), "git {:?} failed", args);
+        };
+        let temp_dir = std::env::temp_dir().join(format!("hydra-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(temp_dir.join("seed")).unwrap();
+        git(&temp_dir.join("seed"), &["init", "-q", "-b", "main"]);
+        git(&temp_dir.join("seed"), &["-c", "user.email=test@example.com", "-c", "user.name=Test", "commit", "-q", "--allow-empty", "-m", "init"]);
+
+        // `project.git`: the bare repository is the root
+        git(&temp_dir, &["clone", "-q", "--bare", "seed", "project.git"]);
+        let bare = temp_dir.join("project.git");
+        git(&bare, &["worktree", "add", "-q", "-b", "feature", "../feature"]);
+        let root = bare.canonicalize().unwrap();
+        assert_eq!(repo_root_of(&bare).unwrap(), root);
+        assert_eq!(repo_root_of(&temp_dir.join("feature")).unwrap(), root);
+        assert!(is_bare_at(&root));
+
+        // `project/.bare` with `project/.git` pointing at it: the directory holding both
+        let project = temp_dir.join("project");
+        std::fs::create_dir_all(&project).unwrap();
+        git(&project, &["clone", "-q", "--bare", "../seed", ".bare"]);
+        std::fs::write(project.join(".git"), "gitdir: ./.bare\n").unwrap();
+        git(&project, &["worktree", "add", "-q", "-b", "feature", "feature"]);
+        let root = project.canonicalize().unwrap();
+        assert_eq!(repo_root_of(&project).unwrap(), root);
+        assert_eq!(repo_root_of(&project.join("feature")).unwrap(), root);
+        assert!(is_bare_at(&root));
+        assert!(!is_bare_at(&temp_dir.join("seed")));
+
+        std::fs::remove_dir_all(&temp_dir).ok();
     }
 
     #[test]
@@ -206,6 +1156,70 @@ mod tests {
         assert_eq!(config.env.template, ".env.example");
         assert_eq!(config.env.output, ".env");
         assert_eq!(config.worktrees.directory, "../worktrees/");
+        // Configs written before probing existed get both loopback families
+        assert_eq!(config.ports.check_hosts, vec!["127.0.0.1", "::1"]);
+        assert_eq!(config.ports.bind_host, "127.0.0.1");
+        assert_eq!(config.ports.count, 1);
+        assert_eq!(config.env.pairs().collect::<Vec<_>>(), vec![(".env.example", ".env")]);
+        assert!(config.template.vars.is_empty());
+    }
+
+    #[test]
+    fn test_multiple_templates_and_vars() {
+        let toml_str = r#"
+            [ports]
+            range_start = 4000
+            range_end = 4100
+
+            [env]
+            template = ".env.template"
+            output = ".env.local"
+            files = [
+                { template = "docker-compose.override.yml.tera", output = "docker-compose.override.yml" },
+            ]
+
+            [worktrees]
+            directory = "../"
+
+            [template.vars]
+            db_name = "app_{{ branch_slug }}"
+        "#;
+        let config: WtConfig = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            config.env.pairs().collect::<Vec<_>>(),
+            vec![
+                (".env.template", ".env.local"),
+                ("docker-compose.override.yml.tera", "docker-compose.override.yml"),
+            ]
+        );
+        assert_eq!(config.template.vars["db_name"], "app_{{ branch_slug }}");
+
+        // Round-trips through the file hydra-wt writes
+        let reparsed: WtConfig = toml::from_str(&toml::to_string_pretty(&config).unwrap()).unwrap();
+        assert_eq!(reparsed.env.files, config.env.files);
+        assert_eq!(reparsed.template.vars, config.template.vars);
+    }
+
+    #[test]
+    fn test_probe_addrs() {
+        let mut ports = WtConfig::default().ports;
+        ports.bind_host = "::".to_string();
+        let addrs: Vec<String> = ports.probe_addrs().unwrap().iter().map(|a| a.to_string()).collect();
+        assert_eq!(addrs, vec!["::1", "127.0.0.1"]);
+
+        ports.check_hosts = vec!["localhost".to_string()];
+        let err = ports.probe_addrs().unwrap_err();
+        assert!(format!("{:#}", err).contains("ports.check_hosts"));
+    }
+
+    #[test]
+    fn test_merge_message_template() {
+        let merge: MergeConfig = toml::from_str("message_template = \"chore(merge): {source} into {target} ({count})\"\nsign = true\n").unwrap();
+        let template = merge.message_template.as_deref().unwrap();
+        assert_eq!(MergeConfig::render_message(template, "feature/auth", "main", 3), "chore(merge): feature/auth into main (3)");
+        assert!(merge.sign);
+        assert_eq!(merge.check_timeout_secs, 1800);
+        assert!(!WtConfig::default().merge.sign);
     }
 
     #[test]
@@ -213,12 +1227,14 @@ mod tests {
         let artifacts = ArtifactsConfig::default();
         assert_eq!(artifacts.symlink.len(), 0);
         assert_eq!(artifacts.copy.len(), 0);
+        assert_eq!(artifacts.hardlink.len(), 0);
     }
 
     #[test]
     fn test_hooks_config_default() {
         let hooks = HooksConfig::default();
         assert_eq!(hooks.post_create.len(), 0);
+        assert_eq!(hooks.timeout(), Some(Duration::from_secs(600)));
     }
 
     #[test]
@@ -263,6 +1279,34 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_reserved_ports() {
+        let toml_str = r#"
+            [ports]
+            range_start = 3001
+            range_end = 3099
+            reserved = [3005, "3050-3060", 4000]
+
+            [env]
+            template = ".env.template"
+            output = ".env.local"
+
+            [worktrees]
+            directory = "../"
+        "#;
+        let config: WtConfig = toml::from_str(toml_str).unwrap();
+        assert!(config.ports.is_reserved(3005));
+        assert!(config.ports.is_reserved(3055));
+        assert!(!config.ports.is_reserved(3006));
+        // 4000 is outside the range and doesn't count
+        assert_eq!(config.ports.reserved_in_range().len(), 12);
+
+        // A typo fails the load instead of reserving nothing
+        let typo = toml_str.replace("\"3050-3060\"", "\"3050-306O\"");
+        let err = toml::from_str::<WtConfig>(&typo).unwrap_err().to_string();
+        assert!(err.contains("invalid port range '3050-306O'"), "{}", err);
+    }
+
     #[test]
     fn test_config_with_missing_required_fields() {
         let toml_str = r#"
@@ -273,4 +1317,242 @@ mod tests {
         let result: Result<WtConfig, _> = toml::from_str(toml_str);
         assert!(result.is_err());
     }
+
+    fn layered_root() -> PathBuf {
+        let root = std::env::temp_dir().join(format!("hydra-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(root.join(".hydra")).unwrap();
+        root
+    }
+
+    #[test]
+    fn test_layer_precedence() {
+        let root = layered_root();
+        std::fs::write(root.join("hydra-wt.toml"), r#"
+            [ports]
+            range_start = 4000
+            range_end = 4100
+
+            [artifacts]
+            symlink = ["node_modules"]
+        "#).unwrap();
+        std::fs::write(root.join(LOCAL_CONFIG_PATH), r#"
+            [ports]
+            range_end = 4010
+        "#).unwrap();
+
+        let layered = WtConfig::load_layered_at(&root).unwrap();
+        assert_eq!(layered.config.ports.range_start, 4000);
+        assert_eq!(layered.config.ports.range_end, 4010);
+        assert_eq!(layered.config.artifacts.symlink, vec!["node_modules"]);
+        assert_eq!(layered.config.env.template, ".env.template");
+
+        assert_eq!(layered.origins["ports.range_start"], ConfigLayer::Template);
+        assert_eq!(layered.origins["ports.range_end"], ConfigLayer::Local);
+        assert_eq!(layered.origins["env.template"], ConfigLayer::Default);
+        assert_eq!(layered.origins["artifacts.symlink"], ConfigLayer::Template);
+
+        std::fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn test_env_overrides_directory() {
+        let root = layered_root();
+        std::fs::write(root.join("hydra-wt.toml"), "[worktrees]\ndirectory = \"../wts\"\n").unwrap();
+        let env = EnvOverrides { worktrees_dir: Some("/runner/tmp/wts".to_string()), registry: None };
+
+        // env > template > default
+        let layered = WtConfig::load_layered_with(&root, &env).unwrap();
+        assert_eq!(layered.config.worktrees.directory, "/runner/tmp/wts");
+        assert_eq!(layered.origins["worktrees.directory"], ConfigLayer::Env);
+        // An absolute path is fine here, unlike in the template
+        assert_eq!(layered.config.worktree_dir_in(&root), PathBuf::from("/runner/tmp/wts"));
+
+        // env > local
+        std::fs::write(root.join(LOCAL_CONFIG_PATH), "[worktrees]\ndirectory = \"../local\"\n").unwrap();
+        let layered = WtConfig::load_layered_with(&root, &env).unwrap();
+        assert_eq!(layered.config.worktrees.directory, "/runner/tmp/wts");
+
+        let layered = WtConfig::load_layered_with(&root, &EnvOverrides::default()).unwrap();
+        assert_eq!(layered.config.worktrees.directory, "../local");
+        assert_eq!(layered.origins["worktrees.directory"], ConfigLayer::Local);
+        std::fs::remove_file(root.join(LOCAL_CONFIG_PATH)).unwrap();
+        let layered = WtConfig::load_layered_with(&root, &EnvOverrides::default()).unwrap();
+        assert_eq!(layered.origins["worktrees.directory"], ConfigLayer::Template);
+
+        assert_eq!(env.describe(), vec![(DIR_ENV, "/runner/tmp/wts".to_string())]);
+        assert!(EnvOverrides::default().is_empty());
+        std::fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn test_missing_layers() {
+        let root = layered_root();
+        let err = WtConfig::load_layered_at(&root).unwrap_err();
+        assert!(err.to_string().contains("Run 'hydra-wt init' first"));
+
+        // Template only (fresh clone)
+        std::fs::create_dir_all(root.join(".config")).unwrap();
+        std::fs::write(root.join(".config/hydra-wt.toml"), "[worktrees]\ndirectory = \"../wt/\"\n").unwrap();
+        let layered = WtConfig::load_layered_at(&root).unwrap();
+        assert_eq!(layered.config.worktrees.directory, "../wt/");
+        assert_eq!(layered.local_path, None);
+
+        // Legacy single-file config acts as the local layer
+        std::fs::write(root.join(".hydra/wt.toml"), "[ports]\nrange_start = 5000\nrange_end = 5100\n").unwrap();
+        let layered = WtConfig::load_layered_at(&root).unwrap();
+        assert_eq!(layered.config.ports.range_start, 5000);
+        assert_eq!(layered.origins["ports.range_start"], ConfigLayer::Local);
+
+        std::fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn test_save_never_touches_template() {
+        let root = layered_root();
+        let template = "[ports]\nrange_start = 4000\nrange_end = 4100\n";
+        std::fs::write(root.join("hydra-wt.toml"), template).unwrap();
+
+        let mut config = WtConfig::load_layered_at(&root).unwrap().config;
+        config.ports.range_end = 4050;
+        config.hooks.post_create = vec!["npm install".to_string()];
+        config.save_at(&root).unwrap();
+
+        assert_eq!(std::fs::read_to_string(root.join("hydra-wt.toml")).unwrap(), template);
+
+        // Only the differences land in the local layer
+        let local: toml::Table = toml::from_str(
+            &std::fs::read_to_string(root.join(LOCAL_CONFIG_PATH)).unwrap()
+        ).unwrap();
+        assert_eq!(local["ports"].as_table().unwrap().len(), 1);
+        assert_eq!(local["ports"]["range_end"].as_integer(), Some(4050));
+        assert!(local.get("env").is_none());
+
+        let reloaded = WtConfig::load_layered_at(&root).unwrap();
+        assert_eq!(reloaded.config.ports.range_start, 4000);
+        assert_eq!(reloaded.config.ports.range_end, 4050);
+        assert_eq!(reloaded.origins["hooks.post_create"], ConfigLayer::Local);
+
+        std::fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn test_overrides_first_match_wins() {
+        let config: WtConfig = toml::from_str(r#"
+            [ports]
+            range_start = 3001
+            range_end = 3099
+
+            [env]
+            template = ".env.template"
+            output = ".env.local"
+
+            [worktrees]
+            directory = "../"
+
+            [hooks]
+            post_create = ["npm install"]
+
+            [[overrides]]
+            pattern = "hydralph/review-*"
+            hooks = { post_create = ["npm ci"] }
+
+            [[overrides]]
+            pattern = "hydralph/*"
+            ports = { range_start = 3100, range_end = 3199 }
+            hooks = { post_create = [] }
+        "#).unwrap();
+        config.check_overrides().unwrap();
+
+        // Only the first match applies, not a merge of all that match
+        let review = config.resolve_for("hydralph/review-42").unwrap();
+        assert_eq!(review.pattern.as_deref(), Some("hydralph/review-*"));
+        assert_eq!(review.hooks.post_create, vec!["npm ci"]);
+        assert_eq!(review.ports.range_start, 3001);
+
+        let agent = config.resolve_for("hydralph/abc/def").unwrap();
+        assert_eq!(agent.pattern.as_deref(), Some("hydralph/*"));
+        assert!(agent.hooks.post_create.is_empty());
+
+        let human = config.resolve_for("feature/login").unwrap();
+        assert_eq!(human.pattern, None);
+        assert_eq!(human.ports.range_start, 3001);
+        assert_eq!(human.hooks.post_create, vec!["npm install"]);
+    }
+
+    #[test]
+    fn test_override_port_range_only() {
+        let root = layered_root();
+        std::fs::write(root.join("hydra-wt.toml"), r#"
+            [ports]
+            range_start = 4000
+            range_end = 4100
+            count = 2
+
+            [artifacts]
+            symlink = ["node_modules"]
+
+            [hooks]
+            post_create = ["npm install"]
+            timeout_secs = 60
+
+            [[overrides]]
+            pattern = "hydralph/*"
+            ports = { range_start = 4200, range_end = 4299 }
+        "#).unwrap();
+
+        let config = WtConfig::load_layered_at(&root).unwrap().config;
+        let agent = config.resolve_for("hydralph/1").unwrap();
+        assert_eq!((agent.ports.range_start, agent.ports.range_end), (4200, 4299));
+        // Everything else is inherited, other `[ports]` keys included
+        assert_eq!(agent.ports.count, 2);
+        assert_eq!(agent.artifacts.symlink, vec!["node_modules"]);
+        assert_eq!(agent.hooks.post_create, vec!["npm install"]);
+        assert_eq!(agent.hooks.timeout_secs, 60);
+        assert_eq!(agent.env.output, ".env.local");
+
+        // Overrides are checked when the config is loaded
+        std::fs::write(root.join(LOCAL_CONFIG_PATH), "[[overrides]]\npattern = \"a/*\"\nports = { range_start = \"x\" }\n").unwrap();
+        let err = WtConfig::load_layered_at(&root).unwrap_err();
+        assert!(format!("{:#}", err).contains("Invalid overrides entry for 'a/*'"), "{:#}", err);
+        std::fs::write(root.join(LOCAL_CONFIG_PATH), "[[overrides]]\npattern = \"a/*\"\nworktrees = { directory = \"x\" }\n").unwrap();
+        assert!(WtConfig::load_layered_at(&root).unwrap_err().to_string().contains("only worktrees.sparse"));
+        std::fs::write(root.join(LOCAL_CONFIG_PATH), "[[overrides]]\npattern = \"a/*\"\nworktrees = { sparse = [\"web\"] }\n").unwrap();
+        let config = WtConfig::load_layered_at(&root).unwrap().config;
+        assert_eq!(config.resolve_for("a/1").unwrap().worktrees.sparse, vec!["web"]);
+        assert!(config.resolve_for("b").unwrap().worktrees.sparse.is_empty());
+
+        std::fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn test_template_rejects_machine_paths() {
+        let root = layered_root();
+        std::fs::write(root.join("hydra-wt.toml"), "[worktrees]\ndirectory = \"/home/me/wt\"\n").unwrap();
+        let err = WtConfig::load_layered_at(&root).unwrap_err();
+        assert!(err.to_string().contains("machine-specific"));
+
+        // Fine as a local override
+        std::fs::write(root.join("hydra-wt.toml"), "").unwrap();
+        std::fs::write(root.join(LOCAL_CONFIG_PATH), "[worktrees]\ndirectory = \"/home/me/wt\"\n").unwrap();
+        assert_eq!(WtConfig::load_layered_at(&root).unwrap().config.worktrees.directory, "/home/me/wt");
+
+        std::fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn test_clone_setup() {
+        let root = layered_root();
+        assert!(WtConfig::clone_setup_at(&root).is_err());
+
+        std::fs::write(root.join("hydra-wt.toml"), "[ports]\nrange_start = 4000\nrange_end = 4100\n").unwrap();
+        WtConfig::clone_setup_at(&root).unwrap();
+        assert!(root.join(LOCAL_CONFIG_PATH).exists());
+
+        // Existing overrides survive a second run
+        std::fs::write(root.join(LOCAL_CONFIG_PATH), "[ports]\nrange_end = 4001\n").unwrap();
+        WtConfig::clone_setup_at(&root).unwrap();
+        assert_eq!(WtConfig::load_layered_at(&root).unwrap().config.ports.range_end, 4001);
+
+        std::fs::remove_dir_all(&root).ok();
+    }
 }
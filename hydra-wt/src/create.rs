@@ -0,0 +1,952 @@
+//! Timed, interruptible worktree creation
+//!
+//! Creating a worktree allocates a port, registers and checks out the
+//! worktree, then sets up artifacts, the env file and post-create hooks.
+//! On a large repository the checkout alone can take minutes, so progress
+//! is reported as it happens and each phase is timed. If anything fails,
+//! including the caller asking to stop or a hook failing or timing out,
+//! everything done so far is rolled back so no half-registered worktree is
+//! left behind.
+
+use anyhow::{Context, Result, bail};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::artifacts::LinkStats;
+use crate::config::{self, WtConfig};
+use crate::error::WtError;
+use crate::hooks::{HookContext, HookStatus};
+use crate::metrics::CreateMetrics;
+use crate::ports::{PortBlock, PortRegistry};
+use crate::worktree::{self, CheckoutProgress};
+use crate::{artifacts, health, hooks, hydra, template};
+
+/// The timed phases of a create, in the order they run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CreatePhase {
+    WorktreeAdd,
+    Artifacts,
+    Templates,
+    Hooks,
+}
+
+impl CreatePhase {
+    pub const ALL: [CreatePhase; 4] = [Self::WorktreeAdd, Self::Artifacts, Self::Templates, Self::Hooks];
+
+    /// Key in `.hydra/wt-metrics.toml`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::WorktreeAdd => "worktree_add",
+            Self::Artifacts => "artifacts",
+            Self::Templates => "templates",
+            Self::Hooks => "hooks",
+        }
+    }
+}
+
+/// Progress of a create, reported as it happens
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CreateEvent {
+    /// A phase is about to start; `estimate` is how long it usually takes here
+    PhaseStarted { phase: CreatePhase, estimate: Option<Duration> },
+    /// git's checkout progress during [`CreatePhase::WorktreeAdd`]
+    Checkout { progress: CheckoutProgress, elapsed: Duration },
+    PhaseFinished { phase: CreatePhase, elapsed: Duration },
+}
+
+/// Options for [`create_opts`] and [`create_opts_with`], or built up from
+/// [`CreateOptions::new`] for [`create_worktree`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CreateOptions<'a> {
+    /// Branch [`create_worktree`] creates the worktree for; the other entry
+    /// points take it as an argument and ignore this
+    pub branch: &'a str,
+    /// Start a new branch at this ref instead of HEAD; the branch must not
+    /// exist yet
+    pub base_ref: Option<&'a str>,
+    /// Claim the port block starting at exactly this port instead of the
+    /// next free one
+    pub port: Option<u16>,
+    /// Free ports the registry already holds for the branch instead of failing
+    pub force: bool,
+    /// Allocate no ports, for a worktree that never runs a server
+    pub no_port: bool,
+    /// Leave out the `[artifacts]` symlinks, copies and hardlinks
+    pub skip_artifacts: bool,
+    /// Render no env files
+    pub skip_templates: bool,
+    /// Run no post-create hooks
+    pub skip_hooks: bool,
+    /// Tool recorded as the creator of the worktree, e.g. `cli` or
+    /// `orchestrator:<session-id>`; none is recorded without it
+    pub owner: Option<&'a str>,
+}
+
+impl<'a> CreateOptions<'a> {
+    /// A create of `branch` with every phase and the next free ports
+    pub fn new(branch: &'a str) -> Self {
+        Self { branch, ..Self::default() }
+    }
+
+    /// Start the new branch at `base_ref` instead of HEAD
+    pub fn base_ref(mut self, base_ref: &'a str) -> Self {
+        self.base_ref = Some(base_ref);
+        self
+    }
+
+    /// Claim the port block starting at exactly `port`
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self.no_port = false;
+        self
+    }
+
+    /// Allocate no ports
+    pub fn no_port(mut self) -> Self {
+        self.port = None;
+        self.no_port = true;
+        self
+    }
+
+    /// Free ports the registry already holds for the branch
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    pub fn run_hooks(mut self, run: bool) -> Self {
+        self.skip_hooks = !run;
+        self
+    }
+
+    pub fn apply_artifacts(mut self, apply: bool) -> Self {
+        self.skip_artifacts = !apply;
+        self
+    }
+
+    pub fn render_templates(mut self, render: bool) -> Self {
+        self.skip_templates = !render;
+        self
+    }
+
+    /// Record `owner` as the creator of the worktree
+    pub fn owner(mut self, owner: &'a str) -> Self {
+        self.owner = Some(owner);
+        self
+    }
+
+    /// Whether a create with these options runs `phase`
+    pub fn runs(&self, phase: CreatePhase) -> bool {
+        match phase {
+            CreatePhase::WorktreeAdd => true,
+            CreatePhase::Artifacts => !self.skip_artifacts,
+            CreatePhase::Templates => !self.skip_templates,
+            CreatePhase::Hooks => !self.skip_hooks,
+        }
+    }
+}
+
+/// What a successful create set up and how long it took
+#[derive(Debug, Clone)]
+pub struct CreateOutcome {
+    pub branch: String,
+    pub path: PathBuf,
+    /// First port of `ports`; 0 when created with [`CreateOptions::no_port`]
+    pub port: u16,
+    /// A block of the single port 0 when created with
+    /// [`CreateOptions::no_port`]; `allocated` tells the two apart
+    pub ports: PortBlock,
+    /// The ports allocated, `None` when created with [`CreateOptions::no_port`]
+    pub allocated: Option<PortBlock>,
+    /// Files of `artifacts.hardlink` entries that were linked or copied
+    pub hardlinked: LinkStats,
+    /// Duration of every phase, in order
+    pub timings: Vec<(CreatePhase, Duration)>,
+}
+
+impl CreateOutcome {
+    fn new(
+        branch: &str,
+        path: PathBuf,
+        allocated: Option<PortBlock>,
+        hardlinked: LinkStats,
+        timings: Vec<(CreatePhase, Duration)>,
+    ) -> Self {
+        let ports = allocated.unwrap_or(PortBlock::single(0));
+        Self { branch: branch.to_string(), path, port: ports.start, ports, allocated, hardlinked, timings }
+    }
+
+    pub fn total(&self) -> Duration {
+        self.timings.iter().map(|(_, d)| *d).sum()
+    }
+}
+
+/// A worktree made by [`create_worktree`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CreatedWorktree {
+    pub branch: String,
+    pub path: PathBuf,
+    /// First port of `ports`, `None` without ports
+    pub port: Option<u16>,
+    pub ports: Option<PortBlock>,
+}
+
+impl From<CreateOutcome> for CreatedWorktree {
+    fn from(outcome: CreateOutcome) -> Self {
+        Self {
+            branch: outcome.branch,
+            path: outcome.path,
+            port: outcome.allocated.map(|ports| ports.start),
+            ports: outcome.allocated,
+        }
+    }
+}
+
+/// Create a managed worktree for `opts.branch` as [`create_opts`] does,
+/// rolling it back on failure. The options are built up from
+/// [`CreateOptions::new`], as in `CreateOptions::new("feature/login").run_hooks(false)`.
+pub fn create_worktree(cfg: &WtConfig, repo_root: &Path, opts: CreateOptions) -> Result<CreatedWorktree> {
+    if opts.branch.is_empty() {
+        bail!("No branch given to create a worktree for");
+    }
+    create_opts(cfg, repo_root, opts.branch, opts).map(CreatedWorktree::from)
+}
+
+/// Create a managed worktree for `branch`, creating the branch if needed.
+///
+/// `repo_root` is the main checkout; the registry, templates, artifacts and
+/// relative worktree directory are resolved against it.
+pub fn create(cfg: &WtConfig, repo_root: &Path, branch: &str) -> Result<CreateOutcome> {
+    create_with(cfg, repo_root, branch, |_| Ok(()))
+}
+
+/// Same as [`create`], reporting progress to `on_event`. An error from
+/// `on_event` stops the create (killing git mid-checkout) and rolls it back;
+/// this is how an interrupt is honoured.
+pub fn create_with<F>(cfg: &WtConfig, repo_root: &Path, branch: &str, on_event: F) -> Result<CreateOutcome>
+where
+    F: FnMut(CreateEvent) -> Result<()>,
+{
+    create_from_with(cfg, repo_root, branch, None, on_event)
+}
+
+/// Same as [`create_with`], starting a new branch at `base_ref` instead of
+/// HEAD. With a base ref the branch must not exist yet.
+pub fn create_from_with<F>(
+    cfg: &WtConfig,
+    repo_root: &Path,
+    branch: &str,
+    base_ref: Option<&str>,
+    on_event: F,
+) -> Result<CreateOutcome>
+where
+    F: FnMut(CreateEvent) -> Result<()>,
+{
+    create_opts_with(cfg, repo_root, branch, CreateOptions { base_ref, ..CreateOptions::default() }, on_event)
+}
+
+/// Same as [`create`], with the base ref, port and phases given in `opts`.
+/// This is the entry point for tools that create worktrees on their own,
+/// like the orchestrator.
+pub fn create_opts(cfg: &WtConfig, repo_root: &Path, branch: &str, opts: CreateOptions) -> Result<CreateOutcome> {
+    create_opts_with(cfg, repo_root, branch, opts, |_| Ok(()))
+}
+
+/// Same as [`create_with`], with the base ref, port and phases given in `opts`
+pub fn create_opts_with<F>(
+    cfg: &WtConfig,
+    repo_root: &Path,
+    branch: &str,
+    opts: CreateOptions,
+    mut on_event: F,
+) -> Result<CreateOutcome>
+where
+    F: FnMut(CreateEvent) -> Result<()>,
+{
+    let base_ref = opts.base_ref;
+    if opts.no_port && opts.port.is_some() {
+        bail!("A worktree without ports cannot be given a port");
+    }
+    if !worktree::is_valid_branch_name(branch) {
+        bail!("'{}' is not a valid branch name", branch);
+    }
+    // Checked again by the checkout, but fail before anything is allocated
+    if let Some(base_ref) = base_ref {
+        worktree::check_base_ref_in(repo_root, branch, base_ref)?;
+    }
+    // git allows a branch in only one worktree. Checked before the registry,
+    // which usually has an entry for that worktree, so callers can tell.
+    if let Some(path) = worktree::get_worktree_path_in(repo_root, branch)? {
+        return Err(WtError::BranchCheckedOut { branch: branch.to_string(), path }.into());
+    }
+    let wt_path = cfg.worktree_path_in(repo_root, branch);
+    // Rollback deletes the directory, so never start from one that exists
+    if wt_path.exists() {
+        if worktree::exists(&wt_path) {
+            return Err(WtError::WorktreeExists { path: wt_path }.into());
+        }
+        bail!("{} already exists", wt_path.display());
+    }
+    let mut registry = PortRegistry::load_at(repo_root)?;
+    if registry.is_managed(branch) {
+        let existing = registry.block(branch);
+        match (existing, opts.port) {
+            _ if opts.force => {
+                registry.unregister(branch);
+                match existing {
+                    Some(existing) => warn!("freeing port {} previously allocated to '{}'", existing, branch),
+                    None => warn!("dropping the registry entry previously held by '{}'", branch),
+                }
+            }
+            (Some(existing), Some(port)) if port != existing.start => bail!(
+                "'{}' already has port {} allocated; pass --force to free it and take {}",
+                branch, existing, port
+            ),
+            _ => bail!("Port registry already has an entry for '{}'", branch),
+        }
+    }
+    let created_branch = !worktree::branch_exists_in(repo_root, branch)?;
+
+    // Allocate ports that aren't reserved and nothing is already listening
+    // on, on any check host
+    let ports = if opts.no_port {
+        registry.add_portless(branch);
+        None
+    } else {
+        Some(match opts.port {
+            Some(port) => registry.allocate_specific(branch, port, &cfg.ports)?,
+            None => registry.allocate_recycling(branch, cfg, repo_root)?,
+        })
+    };
+    let port = ports.map(|p| p.start);
+    registry.set_path(branch, &wt_path);
+    registry.set_created(branch, chrono::Utc::now());
+    if let Some(owner) = opts.owner {
+        registry.set_owner(branch, owner);
+    }
+    registry.save_at(repo_root)?;
+
+    let mut metrics = CreateMetrics::load_at(repo_root).unwrap_or_else(|e| {
+        warn!("ignoring unreadable create metrics: {:#}", e);
+        CreateMetrics::default()
+    });
+    let mut timings = Vec::new();
+    let mut hardlinked = LinkStats::default();
+
+    let result = (|| -> Result<()> {
+        for phase in CreatePhase::ALL.into_iter().filter(|&p| opts.runs(p)) {
+            on_event(CreateEvent::PhaseStarted { phase, estimate: metrics.estimate(phase) })?;
+            let started = Instant::now();
+            match phase {
+                CreatePhase::WorktreeAdd => {
+                    worktree::add_with_progress(repo_root, &wt_path, branch, base_ref, &cfg.worktrees.sparse, |progress| {
+                        on_event(CreateEvent::Checkout { progress, elapsed: started.elapsed() })
+                    })?;
+                    // Remember where the branch started so later rewrites can be detected
+                    if let Err(e) = health::record_head(repo_root, branch) {
+                        warn!("failed to record branch head: {}", e);
+                    }
+                }
+                _ => set_up(cfg, repo_root, &wt_path, branch, ports, phase, &mut hardlinked)?,
+            }
+            let elapsed = started.elapsed();
+            timings.push((phase, elapsed));
+            on_event(CreateEvent::PhaseFinished { phase, elapsed })?;
+        }
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        let undone = rollback(repo_root, &wt_path, branch, created_branch);
+        worktree::remove_empty_parents(&wt_path, &cfg.worktree_dir_in(repo_root));
+        return match undone {
+            Ok(()) => Err(e.context(format!("Create of '{}' failed, changes rolled back", branch))),
+            Err(undo_err) => Err(e.context(format!(
+                "Create of '{}' failed and rollback was incomplete ({:#})",
+                branch, undo_err
+            ))),
+        };
+    }
+
+    for (phase, elapsed) in &timings {
+        metrics.record(*phase, *elapsed);
+    }
+    if let Err(e) = metrics.save_at(repo_root) {
+        warn!("failed to save create metrics: {:#}", e);
+    }
+
+    hydra::emit_worktree_created(cfg, repo_root, branch, port, &wt_path.to_string_lossy())?;
+    hydra::emit_inventory(cfg, repo_root)?;
+
+    Ok(CreateOutcome::new(branch, wt_path, ports, hardlinked, timings))
+}
+
+/// Bring the existing worktree of `branch` under management, as if
+/// [`create`] had made it: record its path, creation time and head,
+/// allocate ports (exactly `port` if given), then set up artifacts, the env
+/// file and post-create hooks, reporting each phase to `on_event`. For
+/// worktrees made with plain `git worktree add`, or left without a registry
+/// entry by a create that crashed. Fails before changing anything if
+/// `branch` is already managed or has no worktree besides the main
+/// checkout. On a later failure the registry entry and head record are
+/// undone, but the worktree is never removed: artifacts, env files and hook
+/// output from the phases that ran stay in it. No owner is recorded; see
+/// [`adopt_as`].
+pub fn adopt_with<F>(
+    cfg: &WtConfig,
+    repo_root: &Path,
+    branch: &str,
+    port: Option<u16>,
+    on_event: F,
+) -> Result<CreateOutcome>
+where
+    F: FnMut(CreateEvent) -> Result<()>,
+{
+    adopt_as(cfg, repo_root, branch, port, None, on_event)
+}
+
+/// [`adopt_with`], recording `owner` as the creator of the worktree like
+/// [`CreateOptions::owner`] does
+pub fn adopt_as<F>(
+    cfg: &WtConfig,
+    repo_root: &Path,
+    branch: &str,
+    port: Option<u16>,
+    owner: Option<&str>,
+    mut on_event: F,
+) -> Result<CreateOutcome>
+where
+    F: FnMut(CreateEvent) -> Result<()>,
+{
+    let Some(wt_path) = worktree::get_worktree_path_in(repo_root, branch)? else {
+        bail!("'{}' is not checked out in any worktree; create one with: hydra-wt create {}", branch, branch);
+    };
+    if same_path(&wt_path, repo_root) {
+        bail!("'{}' is checked out in the main checkout, which cannot be adopted", branch);
+    }
+    if !wt_path.exists() {
+        bail!("Worktree of '{}' at {} no longer exists; run hydra-wt doctor --fix", branch, wt_path.display());
+    }
+    let mut registry = PortRegistry::load_at(repo_root)?;
+    if registry.is_managed(branch) {
+        match registry.block(branch) {
+            Some(existing) => bail!("'{}' is already managed (port {})", branch, existing),
+            None => bail!("'{}' is already managed (no port)", branch),
+        }
+    }
+
+    let ports = match port {
+        Some(port) => registry.allocate_specific(branch, port, &cfg.ports)?,
+        None => registry.allocate_recycling(branch, cfg, repo_root)?,
+    };
+    registry.set_path(branch, &wt_path);
+    registry.set_created(branch, chrono::Utc::now());
+    if let Some(owner) = owner {
+        registry.set_owner(branch, owner);
+    }
+    if let Err(e) = registry.record_head(repo_root, branch) {
+        warn!("failed to record branch head: {}", e);
+    }
+    registry.save_at(repo_root)?;
+
+    let mut metrics = CreateMetrics::load_at(repo_root).unwrap_or_else(|e| {
+        warn!("ignoring unreadable create metrics: {:#}", e);
+        CreateMetrics::default()
+    });
+    let mut timings = Vec::new();
+    let mut hardlinked = LinkStats::default();
+
+    let result = (|| -> Result<()> {
+        for phase in CreatePhase::ALL.into_iter().filter(|&p| p != CreatePhase::WorktreeAdd) {
+            on_event(CreateEvent::PhaseStarted { phase, estimate: metrics.estimate(phase) })?;
+            let started = Instant::now();
+            set_up(cfg, repo_root, &wt_path, branch, Some(ports), phase, &mut hardlinked)?;
+            let elapsed = started.elapsed();
+            timings.push((phase, elapsed));
+            on_event(CreateEvent::PhaseFinished { phase, elapsed })?;
+        }
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        let undo = (|| -> Result<()> {
+            let mut registry = PortRegistry::load_at(repo_root)?;
+            registry.free(branch)?;
+            registry.save_at(repo_root)?;
+            Ok(())
+        })();
+        return match undo {
+            Ok(()) => Err(e.context(format!("Adopt of '{}' failed, its ports were freed", branch))),
+            Err(undo_err) => Err(e.context(format!(
+                "Adopt of '{}' failed and its ports could not be freed ({:#})",
+                branch, undo_err
+            ))),
+        };
+    }
+
+    for (phase, elapsed) in &timings {
+        metrics.record(*phase, *elapsed);
+    }
+    if let Err(e) = metrics.save_at(repo_root) {
+        warn!("failed to save create metrics: {:#}", e);
+    }
+
+    hydra::emit_worktree_created(cfg, repo_root, branch, Some(ports.start), &wt_path.to_string_lossy())?;
+    hydra::emit_inventory(cfg, repo_root)?;
+
+    Ok(CreateOutcome::new(branch, wt_path, Some(ports), hardlinked, timings))
+}
+
+/// Run one of the setup phases that follow the checkout in the worktree of
+/// `branch` at `wt_path`
+fn set_up(
+    cfg: &WtConfig,
+    repo_root: &Path,
+    wt_path: &Path,
+    branch: &str,
+    ports: Option<PortBlock>,
+    phase: CreatePhase,
+    hardlinked: &mut LinkStats,
+) -> Result<()> {
+    match phase {
+        CreatePhase::WorktreeAdd => {}
+        CreatePhase::Artifacts => {
+            for artifact in &cfg.artifacts.symlink {
+                artifacts::symlink_artifact(repo_root, wt_path, artifact)?;
+            }
+            for artifact in &cfg.artifacts.copy {
+                artifacts::copy_artifact(repo_root, wt_path, artifact)?;
+            }
+            for artifact in &cfg.artifacts.hardlink {
+                *hardlinked += artifacts::hardlink_artifact(repo_root, wt_path, artifact)?;
+            }
+        }
+        CreatePhase::Templates => render_env(cfg, repo_root, wt_path, branch, ports)?,
+        CreatePhase::Hooks => {
+            let ctx = HookContext {
+                branch,
+                ports,
+                worktree: wt_path,
+                repo_root,
+                project_uuid: config::get_project_uuid_at(repo_root).ok(),
+            };
+            let runs = hooks::run_post_create(&ctx, &cfg.hooks.post_create, cfg.hooks.timeout())?;
+            // Only the last hook run can have failed; the rest were skipped
+            if let Some(run) = runs.last() {
+                match run.status {
+                    HookStatus::Succeeded => {}
+                    HookStatus::Failed(status) => bail!("Hook '{}' failed ({})", run.command, status),
+                    HookStatus::TimedOut(after) => {
+                        bail!("Hook '{}' timed out after {}s", run.command, after.as_secs());
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Undo a partial create of `branch` at `wt_path`: force-remove the
+/// worktree and whatever of its directory exists, delete the branch if the
+/// create made it, and drop its port and head record. Each step is skipped
+/// when there is nothing to undo, so this is safe at any point of a create.
+pub fn rollback(repo_root: &Path, wt_path: &Path, branch: &str, created_branch: bool) -> Result<()> {
+    let mut errors = Vec::new();
+
+    let registered = worktree::list_in(repo_root)
+        .map(|wts| wts.iter().any(|wt| same_path(Path::new(&wt.path), wt_path)))
+        .unwrap_or(false);
+    if registered || wt_path.exists() {
+        if let Err(e) = worktree::remove_in(repo_root, wt_path) {
+            errors.push(format!("worktree: {}", e));
+        }
+    }
+    if wt_path.exists() {
+        if let Err(e) = std::fs::remove_dir_all(wt_path) {
+            errors.push(format!("directory {}: {}", wt_path.display(), e));
+        }
+    }
+
+    if created_branch && worktree::branch_exists_in(repo_root, branch).unwrap_or(false) {
+        if let Err(e) = worktree::delete_branch_in(repo_root, branch, true) {
+            errors.push(format!("branch: {}", e));
+        }
+    }
+
+    let registry = PortRegistry::load_at(repo_root).and_then(|mut registry| {
+        if registry.is_managed(branch) {
+            registry.unregister(branch);
+            registry.save_at(repo_root)?;
+        }
+        Ok(())
+    });
+    if let Err(e) = registry {
+        errors.push(format!("port registry: {}", e));
+    }
+
+    if !errors.is_empty() {
+        bail!("{}", errors.join("; "));
+    }
+    Ok(())
+}
+
+/// `a` and `b` are the same directory, even when spelled differently
+pub(crate) fn same_path(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Render the env templates into the new worktree
+fn render_env(cfg: &WtConfig, repo_root: &Path, wt_path: &Path, branch: &str, ports: Option<PortBlock>) -> Result<()> {
+    let ctx = template::TemplateContext::for_worktree(cfg, repo_root, branch, ports)?;
+    template::render_all(&cfg.env, repo_root, wt_path, &ctx)
+        .context("Failed to render env files")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ArtifactsConfig, EnvConfig, HooksConfig, PortsConfig, TemplateConfig, TemplateFile, WorktreesConfig};
+    use crate::scratch::{self, git};
+
+    /// Scratch repo with an env template, a copied and a hardlinked artifact
+    fn setup() -> (PathBuf, WtConfig) {
+        let repo = scratch::repo("create");
+        let root = repo.parent().unwrap();
+        std::fs::write(repo.join(".gitignore"), ".hydra/\ncache/\nbuild/\n").unwrap();
+        std::fs::write(repo.join(".env.template"), "PORT={{ port }}\n").unwrap();
+        for i in 0..50 {
+            std::fs::write(repo.join(format!("file{}.txt", i)), i.to_string()).unwrap();
+        }
+        scratch::commit_all(&repo, "init");
+        std::fs::create_dir_all(repo.join("cache")).unwrap();
+        std::fs::write(repo.join("cache/data"), "warm").unwrap();
+        std::fs::create_dir_all(repo.join("build/obj")).unwrap();
+        std::fs::write(repo.join("build/obj/main.o"), "obj").unwrap();
+
+        let cfg = WtConfig {
+            ports: PortsConfig { range_start: 3101, range_end: 3150, ..WtConfig::default().ports },
+            env: EnvConfig {
+                template: ".env.template".to_string(),
+                output: ".env.local".to_string(),
+                files: Vec::new(),
+            },
+            worktrees: WorktreesConfig { directory: root.join("wts").to_string_lossy().to_string(), ..WtConfig::default().worktrees },
+            artifacts: ArtifactsConfig {
+                copy: vec!["cache".to_string()],
+                hardlink: vec!["build".to_string()],
+                ..ArtifactsConfig::default()
+            },
+            hooks: HooksConfig::default(),
+            template: TemplateConfig::default(),
+            ..WtConfig::default()
+        };
+        (repo, cfg)
+    }
+
+    /// A rendered file without the header naming its template
+    fn rendered(path: &Path) -> String {
+        let content = std::fs::read_to_string(path).unwrap();
+        match content.split_once('\n') {
+            Some((header, rest)) if header.starts_with("# Rendered by hydra-wt") => rest.to_string(),
+            _ => content,
+        }
+    }
+
+    #[test]
+    fn test_create_times_phases_and_records_metrics() {
+        let (repo, cfg) = setup();
+
+        let mut events = Vec::new();
+        let outcome = create_with(&cfg, &repo, "feature", |event| {
+            events.push(event);
+            Ok(())
+        })
+        .unwrap();
+
+        assert!(worktree::exists(&outcome.path));
+        assert!(outcome.path.join("file49.txt").exists());
+        assert!(outcome.path.join("cache/data").exists());
+        assert!(outcome.path.join("build/obj/main.o").exists());
+        assert_eq!(outcome.hardlinked, LinkStats { linked: 1, copied: 0 });
+        let env = rendered(&outcome.path.join(".env.local"));
+        assert_eq!(env, format!("PORT={}\n", outcome.port));
+        assert_eq!(PortRegistry::load_at(&repo).unwrap().get("feature"), Some(outcome.port));
+        assert!(PortRegistry::load_at(&repo).unwrap().head("feature").is_some());
+
+        let phases: Vec<_> = outcome.timings.iter().map(|(p, _)| *p).collect();
+        assert_eq!(phases, CreatePhase::ALL);
+        assert!(matches!(
+            events.first(),
+            Some(CreateEvent::PhaseStarted { phase: CreatePhase::WorktreeAdd, estimate: None })
+        ));
+        assert!(matches!(events.last(), Some(CreateEvent::PhaseFinished { phase: CreatePhase::Hooks, .. })));
+
+        // The next create knows how long the checkout took
+        let metrics = CreateMetrics::load_at(&repo).unwrap();
+        assert_eq!(metrics.phases["worktree_add"].runs, 1);
+        let outcome2 = create_with(&cfg, &repo, "feature-2", |event| {
+            if let CreateEvent::PhaseStarted { phase: CreatePhase::WorktreeAdd, estimate } = event {
+                assert_eq!(estimate, metrics.estimate(CreatePhase::WorktreeAdd));
+                assert!(estimate.is_some());
+            }
+            Ok(())
+        })
+        .unwrap();
+        assert_ne!(outcome2.port, outcome.port);
+        assert_eq!(CreateMetrics::load_at(&repo).unwrap().phases["templates"].runs, 2);
+
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_create_allocates_port_block() {
+        let (repo, mut cfg) = setup();
+        cfg.ports.count = 3;
+        std::fs::write(repo.join(".env.template"), "WEB={{ PORT }}\nAPI={{ PORT_2 }}\nSTORYBOOK={{ PORT_3 }}\n").unwrap();
+
+        let outcome = create(&cfg, &repo, "feature").unwrap();
+        assert_eq!(outcome.ports.count, 3);
+        let p = outcome.port;
+        let env = rendered(&outcome.path.join(".env.local"));
+        assert_eq!(env, format!("WEB={}\nAPI={}\nSTORYBOOK={}\n", p, p + 1, p + 2));
+        assert_eq!(PortRegistry::load_at(&repo).unwrap().block("feature"), outcome.allocated);
+
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_create_renders_every_template_with_vars() {
+        let (repo, mut cfg) = setup();
+        std::fs::write(repo.join("compose.tera"), "name: {{ branch_slug }}\ndb: {{ db_name }}\nmissing: {{ FOO }}\n").unwrap();
+        cfg.env.files.push(TemplateFile {
+            template: "compose.tera".to_string(),
+            output: "docker/docker-compose.override.yml".to_string(),
+        });
+        cfg.template.vars.insert("db_name".to_string(), "app_{{ branch_slug | replace(from=\"-\", to=\"_\") }}".to_string());
+
+        let outcome = create(&cfg, &repo, "feature/Auth").unwrap();
+        let env = rendered(&outcome.path.join(".env.local"));
+        assert_eq!(env, format!("PORT={}\n", outcome.port));
+        let compose = rendered(&outcome.path.join("docker/docker-compose.override.yml"));
+        assert_eq!(compose, "name: feature-auth\ndb: app_feature_auth\nmissing: {{ FOO }}\n");
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_create_with_specific_port() {
+        let (repo, cfg) = setup();
+        let mut registry = PortRegistry::default();
+        registry.allocations.insert("feature".to_string(), 3101.into());
+        registry.save_at(&repo).unwrap();
+
+        let opts = CreateOptions { port: Some(3120), ..CreateOptions::default() };
+        let err = create_opts_with(&cfg, &repo, "feature", opts, |_| Ok(())).unwrap_err();
+        assert!(err.to_string().contains("already has port 3101 allocated; pass --force"), "{}", err);
+        let err = create_opts_with(&cfg, &repo, "other", CreateOptions { port: Some(3101), ..opts }, |_| Ok(())).unwrap_err();
+        assert!(err.to_string().contains("Port 3101 is already allocated to 'feature'"), "{}", err);
+
+        let outcome = create_opts_with(&cfg, &repo, "feature", CreateOptions { force: true, ..opts }, |_| Ok(())).unwrap();
+        assert_eq!(outcome.port, 3120);
+        assert_eq!(rendered(&outcome.path.join(".env.local")), "PORT=3120\n");
+        let registry = PortRegistry::load_at(&repo).unwrap();
+        assert_eq!(registry.allocations.len(), 1);
+        assert_eq!(registry.get("feature"), Some(3120));
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_create_without_port() {
+        let (repo, cfg) = setup();
+        std::fs::write(repo.join("names.tera"), "NAME={{ worktree }}\nURL={{ port_url }}\n").unwrap();
+        let mut cfg = cfg;
+        cfg.env.files.push(TemplateFile { template: "names.tera".to_string(), output: "names.env".to_string() });
+
+        let opts = CreateOptions { no_port: true, ..CreateOptions::default() };
+        let outcome = create_opts_with(&cfg, &repo, "docs", opts, |_| Ok(())).unwrap();
+        assert_eq!((outcome.port, outcome.allocated), (0, None));
+        // `.env.template` only sets the port; the other one renders it empty
+        assert!(!outcome.path.join(".env.local").exists());
+        assert_eq!(rendered(&outcome.path.join("names.env")), "NAME=docs\nURL=\n");
+
+        let registry = PortRegistry::load_at(&repo).unwrap();
+        assert!(registry.is_managed("docs") && registry.get("docs").is_none());
+        let err = adopt_with(&cfg, &repo, "docs", None, |_| Ok(())).unwrap_err();
+        assert!(err.to_string().contains("already managed (no port)"), "{}", err);
+
+        // Rolling back a portless create drops its entry
+        let err = create_opts_with(&cfg, &repo, "scratch", opts, |_| bail!("Interrupted")).unwrap_err();
+        assert!(format!("{:#}", err).contains("rolled back"), "{:#}", err);
+        assert!(!PortRegistry::load_at(&repo).unwrap().is_managed("scratch"));
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_create_skips_phases() {
+        let (repo, cfg) = setup();
+
+        let opts = CreateOptions { skip_artifacts: true, skip_templates: true, ..CreateOptions::default() };
+        let outcome = create_opts(&cfg, &repo, "bare", opts).unwrap();
+        assert!(outcome.path.join("file0.txt").exists());
+        assert!(!outcome.path.join("cache").exists() && !outcome.path.join(".env.local").exists());
+        let phases: Vec<_> = outcome.timings.iter().map(|(p, _)| *p).collect();
+        assert_eq!(phases, [CreatePhase::WorktreeAdd, CreatePhase::Hooks]);
+
+        // Callers can tell why a create was refused
+        git(&repo, &["branch", "taken"]);
+        git(&repo, &["worktree", "add", "-q", &repo.parent().unwrap().join("elsewhere").to_string_lossy(), "taken"]);
+        let err = create_opts(&cfg, &repo, "taken", CreateOptions::default()).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(crate::WtError::BranchCheckedOut { .. })), "{:#}", err);
+        assert!(!PortRegistry::load_at(&repo).unwrap().is_managed("taken"));
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_create_worktree_builder() {
+        let (repo, cfg) = setup();
+        let base = worktree::get_head_commit(&repo).unwrap();
+        git(&repo, &["commit", "-q", "--allow-empty", "-m", "later"]);
+
+        let opts = CreateOptions::new("feature").base_ref(&base).port(3130).apply_artifacts(false).owner("tests");
+        assert!(opts.runs(CreatePhase::Templates) && !opts.runs(CreatePhase::Artifacts));
+        let created = create_worktree(&cfg, &repo, opts).unwrap();
+        assert_eq!((created.branch.as_str(), created.port), ("feature", Some(3130)));
+        assert_eq!(worktree::get_head_commit(&created.path).unwrap(), base);
+        assert!(created.path.join(".env.local").exists() && !created.path.join("cache").exists());
+
+        let created = create_worktree(&cfg, &repo, CreateOptions::new("docs").no_port()).unwrap();
+        assert_eq!((created.port, created.ports), (None, None));
+        let err = create_worktree(&cfg, &repo, CreateOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("No branch given"), "{}", err);
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_create_from_base_ref() {
+        let (repo, cfg) = setup();
+        let base = worktree::get_head_commit(&repo).unwrap();
+        git(&repo, &["commit", "-q", "--allow-empty", "-m", "later"]);
+        git(&repo, &["branch", "existing"]);
+
+        let outcome = create_from_with(&cfg, &repo, "feature", Some(&base), |_| Ok(())).unwrap();
+        assert_eq!(worktree::get_head_commit(&outcome.path).unwrap(), base);
+
+        // Existing branches and unknown refs are refused before anything is allocated
+        let err = create_from_with(&cfg, &repo, "existing", Some("main"), |_| Ok(())).unwrap_err();
+        assert!(err.to_string().contains("Branch 'existing' already exists"), "{}", err);
+        let err = create_from_with(&cfg, &repo, "other", Some("no-such-ref"), |_| Ok(())).unwrap_err();
+        assert!(err.to_string().contains("does not resolve to a commit"), "{}", err);
+        let registry = PortRegistry::load_at(&repo).unwrap();
+        assert_eq!((registry.get("existing"), registry.get("other")), (None, None));
+        assert!(!worktree::branch_exists_in(&repo, "other").unwrap());
+
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_create_rolls_back_when_interrupted() {
+        let (repo, cfg) = setup();
+        git(&repo, &["branch", "existing"]);
+
+        for (branch, stop_at) in [("feature", CreatePhase::Templates), ("existing", CreatePhase::Hooks)] {
+            let err = create_with(&cfg, &repo, branch, |event| match event {
+                CreateEvent::PhaseStarted { phase, .. } if phase == stop_at => bail!("Interrupted"),
+                _ => Ok(()),
+            })
+            .unwrap_err();
+            assert!(format!("{:#}", err).contains("rolled back"), "{:#}", err);
+
+            let wt_path = cfg.worktree_path_in(&repo, branch);
+            assert!(!wt_path.exists());
+            assert!(worktree::get_worktree_path_in(&repo, branch).unwrap().is_none());
+            assert_eq!(PortRegistry::load_at(&repo).unwrap().get(branch), None);
+            assert!(PortRegistry::load_at(&repo).unwrap().head(branch).is_none());
+        }
+        // Only the branch the create made is deleted
+        assert!(!worktree::branch_exists_in(&repo, "feature").unwrap());
+        assert!(worktree::branch_exists_in(&repo, "existing").unwrap());
+        // Failed creates don't count towards the estimate
+        assert_eq!(CreateMetrics::load_at(&repo).unwrap(), CreateMetrics::default());
+
+        // The same branch can be created afterwards
+        create(&cfg, &repo, "feature").unwrap();
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_hook_failure_and_timeout_roll_back() {
+        let (repo, mut cfg) = setup();
+        cfg.hooks.post_create = vec!["test -n \"$HYDRA_WT_PORT\"".to_string(), "exit 1".to_string(), "true".to_string()];
+        let err = create(&cfg, &repo, "failing").unwrap_err();
+        assert!(format!("{:#}", err).contains("Hook 'exit 1' failed (exit status: 1)"), "{:#}", err);
+        assert!(!cfg.worktree_path_in(&repo, "failing").exists());
+        assert_eq!(PortRegistry::load_at(&repo).unwrap().get("failing"), None);
+
+        cfg.hooks.post_create = vec!["sleep 30".to_string()];
+        cfg.hooks.timeout_secs = 1;
+        let err = create(&cfg, &repo, "hanging").unwrap_err();
+        assert!(format!("{:#}", err).contains("timed out after 1s"), "{:#}", err);
+        assert!(!cfg.worktree_path_in(&repo, "hanging").exists());
+        assert_eq!(PortRegistry::load_at(&repo).unwrap().get("hanging"), None);
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_rollback_after_partial_checkout() {
+        let (repo, cfg) = setup();
+        let wt_path = cfg.worktree_path_in(&repo, "feature");
+
+        // Stopped at the first progress line: registered, partly checked out.
+        // git may print no progress for a checkout this small; then it's complete.
+        let _ = worktree::add_with_progress(&repo, &wt_path, "feature", None, &[], |_| bail!("Interrupted"));
+        let mut registry = PortRegistry::default();
+        registry.allocations.insert("feature".to_string(), 3101.into());
+        registry.save_at(&repo).unwrap();
+        assert!(wt_path.exists());
+
+        rollback(&repo, &wt_path, "feature", true).unwrap();
+        assert!(!wt_path.exists());
+        assert!(worktree::get_worktree_path_in(&repo, "feature").unwrap().is_none());
+        assert!(!worktree::branch_exists_in(&repo, "feature").unwrap());
+        assert_eq!(PortRegistry::load_at(&repo).unwrap().get("feature"), None);
+
+        // Nothing left to undo is fine too
+        rollback(&repo, &wt_path, "feature", true).unwrap();
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_adopt_sets_up_existing_worktree() {
+        let (repo, cfg) = setup();
+        let wt_path = cfg.worktree_path_in(&repo, "manual");
+        git(&repo, &["worktree", "add", "-q", "-b", "manual", &wt_path.to_string_lossy()]);
+
+        let outcome = adopt_as(&cfg, &repo, "manual", Some(3110), Some("cli"), |_| Ok(())).unwrap();
+        assert_eq!(outcome.port, 3110);
+        assert_eq!(PortRegistry::load_at(&repo).unwrap().owner("manual"), "cli");
+        assert!(same_path(&outcome.path, &wt_path));
+        assert!(wt_path.join("cache/data").exists());
+        assert_eq!(rendered(&wt_path.join(".env.local")), "PORT=3110\n");
+        assert_eq!(PortRegistry::load_at(&repo).unwrap().get("manual"), Some(3110));
+        assert!(PortRegistry::load_at(&repo).unwrap().head("manual").is_some());
+        let phases: Vec<_> = outcome.timings.iter().map(|(p, _)| *p).collect();
+        assert_eq!(phases, [CreatePhase::Artifacts, CreatePhase::Templates, CreatePhase::Hooks]);
+
+        let err = adopt_with(&cfg, &repo, "manual", None, |_| Ok(())).unwrap_err();
+        assert!(err.to_string().contains("already managed"), "{}", err);
+        assert!(adopt_with(&cfg, &repo, "main", None, |_| Ok(())).is_err());
+        assert!(adopt_with(&cfg, &repo, "nowhere", None, |_| Ok(())).is_err());
+
+        // A failed setup frees the ports but keeps the worktree
+        let other = cfg.worktree_path_in(&repo, "other");
+        git(&repo, &["worktree", "add", "-q", "-b", "other", &other.to_string_lossy()]);
+        assert!(adopt_with(&cfg, &repo, "other", None, |_| bail!("Interrupted")).is_err());
+        assert_eq!(PortRegistry::load_at(&repo).unwrap().get("other"), None);
+        assert!(worktree::exists(&other));
+        scratch::remove(&repo);
+    }
+}
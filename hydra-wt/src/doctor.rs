@@ -0,0 +1,342 @@
+//! Consistency check of the port registry, git and the filesystem
+//!
+//! The registry, `git worktree list` and the worktree directories drift
+//! apart through manual deletions, interrupted creates and branches renamed
+//! outside hydra-wt. The doctor cross-checks all three and reports every
+//! inconsistency, and can apply the repairs that can't lose anything.
+
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::config::{self, WtConfig};
+use crate::ports::{PortBlock, PortRegistry};
+use crate::worktree;
+
+/// One inconsistency between the registry, git and the filesystem
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Finding {
+    /// A registry entry with no worktree checked out on its branch; `ports`
+    /// is `None` for a worktree created without ports
+    OrphanedAllocation { branch: String, ports: Option<PortBlock>, path: PathBuf },
+    /// A worktree git knows about whose directory is gone
+    MissingDirectory { branch: Option<String>, path: PathBuf },
+    /// A worktree git knows about that has no registry entry
+    UnmanagedWorktree { branch: Option<String>, path: PathBuf },
+    /// A registry entry with ports outside `[ports]` range
+    PortOutOfRange { branch: String, ports: PortBlock },
+    /// A port allocated to more than one branch
+    DuplicatePort { port: u16, branches: Vec<String> },
+    /// A registry entry holding ports listed in `ports.reserved`
+    ReservedPort { branch: String, ports: PortBlock, reserved: Vec<u16> },
+}
+
+impl Finding {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Finding::OrphanedAllocation { .. } => "orphaned allocation",
+            Finding::MissingDirectory { .. } => "missing directory",
+            Finding::UnmanagedWorktree { .. } => "unmanaged worktree",
+            Finding::PortOutOfRange { .. } => "port out of range",
+            Finding::DuplicatePort { .. } => "duplicate port",
+            Finding::ReservedPort { .. } => "reserved port",
+        }
+    }
+
+    pub fn describe(&self, cfg: &WtConfig) -> String {
+        match self {
+            Finding::OrphanedAllocation { branch, ports: Some(ports), path } => {
+                format!("'{}' holds port {} but has no worktree (expected at {})", branch, ports, path.display())
+            }
+            Finding::OrphanedAllocation { branch, ports: None, path } => {
+                format!("'{}' is registered but has no worktree (expected at {})", branch, path.display())
+            }
+            Finding::MissingDirectory { branch, path } => {
+                format!("git lists {} at {}, which no longer exists", describe_branch(branch), path.display())
+            }
+            Finding::UnmanagedWorktree { branch, path } => {
+                format!("{} at {} has no port allocation", describe_branch(branch), path.display())
+            }
+            Finding::PortOutOfRange { branch, ports } => format!(
+                "'{}' holds port {}, outside {}-{}",
+                branch, ports, cfg.ports.range_start, cfg.ports.range_end
+            ),
+            Finding::DuplicatePort { port, branches } => {
+                format!("port {} is allocated to {}", port, branches.join(", "))
+            }
+            Finding::ReservedPort { branch, ports, reserved } => {
+                let reserved: Vec<String> = reserved.iter().map(u16::to_string).collect();
+                format!("'{}' holds port {}, which includes reserved {}", branch, ports, reserved.join(", "))
+            }
+        }
+    }
+
+    /// How to resolve the finding by hand
+    pub fn remediation(&self) -> String {
+        match self {
+            Finding::OrphanedAllocation { branch, .. } => {
+                format!("hydra-wt doctor --fix, or hydra-wt create {} to recreate it", branch)
+            }
+            Finding::MissingDirectory { .. } => "hydra-wt doctor --fix (runs git worktree prune)".to_string(),
+            Finding::UnmanagedWorktree { branch: Some(branch), .. } => {
+                format!("hydra-wt adopt {}, or hydra-wt doctor --fix to only allocate its ports", branch)
+            }
+            Finding::UnmanagedWorktree { branch: None, path } => {
+                format!("check out a branch in {} so it can be adopted", path.display())
+            }
+            Finding::PortOutOfRange { branch, .. } | Finding::ReservedPort { branch, .. } => reallocate(branch),
+            Finding::DuplicatePort { branches, .. } => reallocate(branches.last().map_or("<branch>", String::as_str)),
+        }
+    }
+
+    /// Whether `--fix` can repair this finding. Adopting a worktree still
+    /// needs confirmation.
+    pub fn is_fixable(&self) -> bool {
+        matches!(
+            self,
+            Finding::OrphanedAllocation { .. }
+                | Finding::MissingDirectory { .. }
+                | Finding::UnmanagedWorktree { branch: Some(_), .. }
+        )
+    }
+}
+
+fn reallocate(branch: &str) -> String {
+    format!("hydra-wt remove {0} && hydra-wt create {0} to reallocate its ports", branch)
+}
+
+fn describe_branch(branch: &Option<String>) -> String {
+    match branch {
+        Some(branch) => format!("worktree '{}'", branch),
+        None => "detached worktree".to_string(),
+    }
+}
+
+/// Cross-check the registry of the repository at `repo_root` against git
+/// and the filesystem
+pub fn diagnose(cfg: &WtConfig, repo_root: &Path) -> Result<Vec<Finding>> {
+    let registry = PortRegistry::load_at(repo_root)?;
+    // The first entry is the main checkout, which is never managed; a bare
+    // repository has none, and its own entry isn't listed
+    let main_checkouts = usize::from(!config::is_bare_at(repo_root));
+    let worktrees: Vec<_> = worktree::list_in(repo_root)?.into_iter().skip(main_checkouts).collect();
+
+    let mut allocations: Vec<(&String, &PortBlock)> = registry.blocks().collect();
+    allocations.sort();
+
+    let mut findings = Vec::new();
+    for wt in &worktrees {
+        let path = PathBuf::from(&wt.path);
+        if !path.exists() {
+            findings.push(Finding::MissingDirectory { branch: wt.branch.clone(), path });
+        }
+    }
+
+    for branch in registry.branches() {
+        let live = worktrees
+            .iter()
+            .any(|wt| wt.branch.as_deref() == Some(branch.as_str()) && Path::new(&wt.path).exists());
+        if !live {
+            let path = worktrees
+                .iter()
+                .find(|wt| wt.branch.as_deref() == Some(branch.as_str()))
+                .map(|wt| PathBuf::from(&wt.path))
+                .unwrap_or_else(|| registry.worktree_path(cfg, repo_root, branch));
+            findings.push(Finding::OrphanedAllocation { branch: branch.clone(), ports: registry.block(branch), path });
+        }
+    }
+
+    for wt in &worktrees {
+        let path = PathBuf::from(&wt.path);
+        let managed = wt.branch.as_ref().is_some_and(|b| registry.is_managed(b));
+        if !managed && path.exists() {
+            findings.push(Finding::UnmanagedWorktree { branch: wt.branch.clone(), path });
+        }
+    }
+
+    // `[[overrides]]` can give a branch a range and reserved ports of its own
+    let mut port_configs = BTreeMap::new();
+    for &(branch, _) in &allocations {
+        port_configs.insert(branch, cfg.resolve_for(branch)?.config.ports);
+    }
+
+    for &(branch, &ports) in &allocations {
+        let range = &port_configs[branch];
+        if ports.start < range.range_start || ports.end() > range.range_end {
+            findings.push(Finding::PortOutOfRange { branch: branch.clone(), ports });
+        }
+    }
+
+    for &(branch, &ports) in &allocations {
+        let reserved: Vec<u16> = ports.ports().filter(|&p| port_configs[branch].is_reserved(p)).collect();
+        if !reserved.is_empty() {
+            findings.push(Finding::ReservedPort { branch: branch.clone(), ports, reserved });
+        }
+    }
+
+    let mut owners: BTreeMap<u16, Vec<String>> = BTreeMap::new();
+    for &(branch, ports) in &allocations {
+        for port in ports.ports() {
+            owners.entry(port).or_default().push(branch.clone());
+        }
+    }
+    for (port, branches) in owners {
+        if branches.len() > 1 {
+            findings.push(Finding::DuplicatePort { port, branches });
+        }
+    }
+
+    Ok(findings)
+}
+
+/// Apply the safe repairs for `findings`: free orphaned allocations, let git
+/// forget missing worktrees, and allocate ports for the unmanaged worktrees
+/// `adopt` agrees to. Returns the findings that were repaired.
+pub fn fix(
+    cfg: &WtConfig,
+    repo_root: &Path,
+    findings: &[Finding],
+    mut adopt: impl FnMut(&str, &Path) -> bool,
+) -> Result<Vec<Finding>> {
+    let mut registry = PortRegistry::load_at(repo_root)?;
+    let mut fixed = Vec::new();
+
+    if findings.iter().any(|f| matches!(f, Finding::MissingDirectory { .. })) {
+        worktree::prune_in(repo_root)?;
+        fixed.extend(findings.iter().filter(|f| matches!(f, Finding::MissingDirectory { .. })).cloned());
+    }
+
+    for finding in findings {
+        if let Finding::OrphanedAllocation { branch, .. } = finding {
+            registry.unregister(branch);
+            fixed.push(finding.clone());
+        }
+    }
+    registry.save_at(repo_root)?;
+
+    for finding in findings {
+        let Finding::UnmanagedWorktree { branch: Some(branch), path } = finding else {
+            continue;
+        };
+        if !adopt(branch, path) {
+            continue;
+        }
+        registry.allocate_for(branch, &cfg.ports)?;
+        registry.set_path(branch, path);
+        registry.set_created(branch, chrono::Utc::now());
+        registry.record_head(repo_root, branch)?;
+        registry.save_at(repo_root)?;
+        fixed.push(finding.clone());
+    }
+
+    Ok(fixed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::WorktreesConfig;
+    use crate::scratch::{self, git};
+
+    /// Scratch repo with worktrees `live` (3001), `gone` (3002, directory
+    /// deleted) and `manual` (no allocation), plus registry entries `never`
+    /// holding 3001-3002 and `far` holding 4000 outside the range; 3002 is
+    /// reserved
+    fn setup() -> (PathBuf, WtConfig) {
+        let repo = scratch::repo("doctor");
+        let root = repo.parent().unwrap();
+        scratch::commit_all(&repo, "init");
+
+        let mut cfg = WtConfig {
+            worktrees: WorktreesConfig {
+                directory: root.join("wts").to_string_lossy().to_string(),
+                ..WtConfig::default().worktrees
+            },
+            ..WtConfig::default()
+        };
+        cfg.ports.reserved = vec!["3002".parse().unwrap()];
+
+        let mut registry = PortRegistry::default();
+        for branch in ["live", "gone", "manual"] {
+            git(&repo, &["worktree", "add", "-q", "-b", branch, &cfg.worktree_path_in(&repo, branch).to_string_lossy()]);
+        }
+        registry.allocations.insert("live".to_string(), 3001.into());
+        registry.allocations.insert("gone".to_string(), 3002.into());
+        registry.allocations.insert("never".to_string(), PortBlock { start: 3001, count: 2 });
+        registry.allocations.insert("far".to_string(), 4000.into());
+        registry.save_at(&repo).unwrap();
+
+        std::fs::remove_dir_all(cfg.worktree_path_in(&repo, "gone")).unwrap();
+        (repo, cfg)
+    }
+
+    #[test]
+    fn test_diagnose_reports_every_kind() {
+        let (repo, cfg) = setup();
+        let wts = cfg.worktree_dir().canonicalize().unwrap();
+
+        let findings = diagnose(&cfg, &repo).unwrap();
+        let labels: Vec<&str> = findings.iter().map(Finding::label).collect();
+        assert_eq!(labels, vec![
+            "missing directory",
+            "orphaned allocation",
+            "orphaned allocation",
+            "orphaned allocation",
+            "unmanaged worktree",
+            "port out of range",
+            "reserved port",
+            "reserved port",
+            "duplicate port",
+            "duplicate port",
+        ]);
+        assert_eq!(findings[0], Finding::MissingDirectory { branch: Some("gone".into()), path: wts.join("gone") });
+        assert_eq!(findings[4], Finding::UnmanagedWorktree { branch: Some("manual".into()), path: wts.join("manual") });
+        assert_eq!(findings[6], Finding::ReservedPort { branch: "gone".into(), ports: 3002.into(), reserved: vec![3002] });
+        assert_eq!(findings[8], Finding::DuplicatePort { port: 3001, branches: vec!["live".into(), "never".into()] });
+        assert_eq!(findings[9], Finding::DuplicatePort { port: 3002, branches: vec!["gone".into(), "never".into()] });
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_port_checks_follow_overrides() {
+        let (repo, mut cfg) = setup();
+        cfg.overrides = vec![
+            toml::from_str("pattern = \"far\"\nports = { range_start = 4000, range_end = 4099 }").unwrap(),
+            toml::from_str("pattern = \"gone\"\nports = { reserved = [] }").unwrap(),
+        ];
+
+        let labels: Vec<&str> = diagnose(&cfg, &repo).unwrap().iter().map(Finding::label).collect();
+        assert!(!labels.contains(&"port out of range"), "{:?}", labels);
+        // Only `never` still holds the reserved 3002
+        assert_eq!(labels.iter().filter(|&&label| label == "reserved port").count(), 1, "{:?}", labels);
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_fix_applies_safe_repairs() {
+        let (repo, cfg) = setup();
+
+        let findings = diagnose(&cfg, &repo).unwrap();
+        let mut asked = Vec::new();
+        let fixed = fix(&cfg, &repo, &findings, |branch, _| {
+            asked.push(branch.to_string());
+            false
+        })
+        .unwrap();
+        assert_eq!(asked, vec!["manual"]);
+        assert_eq!(fixed.len(), 4);
+
+        let registry = PortRegistry::load_at(&repo).unwrap();
+        assert_eq!(registry.block("live"), Some(3001.into()));
+        assert_eq!(registry.allocations.len(), 1);
+        assert!(!worktree::list_in(&repo).unwrap().iter().any(|wt| wt.branch.as_deref() == Some("gone")));
+
+        // Declined adoption is still reported; accepting it leaves nothing
+        let remaining = diagnose(&cfg, &repo).unwrap();
+        assert_eq!(remaining.iter().map(Finding::label).collect::<Vec<_>>(), vec!["unmanaged worktree"]);
+        fix(&cfg, &repo, &remaining, |_, _| true).unwrap();
+        assert!(diagnose(&cfg, &repo).unwrap().is_empty());
+        assert!(PortRegistry::load_at(&repo).unwrap().block("manual").is_some());
+        scratch::remove(&repo);
+    }
+}
@@ -0,0 +1,79 @@
+//! Errors of the worktree and port APIs
+//!
+//! [`worktree`](crate::worktree) and [`ports`](crate::ports) return
+//! [`WtError`] so callers like the orchestrator can tell the failures they
+//! can do something about from the rest. Anything without a variant of its
+//! own is carried as [`WtError::Other`]. `WtError` converts into
+//! `anyhow::Error`, so code built on anyhow keeps using `?`.
+
+use std::path::PathBuf;
+
+/// A failure of the worktree or port APIs
+#[derive(Debug, thiserror::Error)]
+pub enum WtError {
+    /// No run of `count` consecutive free ports is left in `start..=end`.
+    /// `holders` describes the oldest allocations, e.g. `feature (3001, 12d)`.
+    #[error("No {} in range {start}-{end}{}", free_ports(.count), held_by(.holders))]
+    PortRangeExhausted { start: u16, end: u16, count: u16, holders: Vec<String> },
+    /// The branch is already checked out in the worktree at `path`, and git
+    /// allows a branch in only one worktree
+    #[error("Branch '{branch}' is already checked out at {}", .path.display())]
+    BranchCheckedOut { branch: String, path: PathBuf },
+    /// Something already exists where the worktree would go
+    #[error("Worktree already exists at {}", .path.display())]
+    WorktreeExists { path: PathBuf },
+    /// git could not sign a commit: no signing key is configured, or gpg
+    /// or ssh-keygen can't use it. `stderr` is what git printed.
+    #[error(
+        "Could not sign the commit: no usable signing key. Set user.signingkey \
+        (with gpg.format = ssh for an SSH key) or turn off merge.sign"
+    )]
+    SigningFailed { stderr: String },
+    /// A git command exited unsuccessfully; `command` is e.g. `git log`
+    #[error("{command} failed: {stderr}")]
+    GitFailed { command: String, stderr: String },
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+pub type Result<T, E = WtError> = std::result::Result<T, E>;
+
+impl WtError {
+    /// `command` failed with `stderr` as its output
+    pub(crate) fn git(command: impl Into<String>, stderr: &[u8]) -> Self {
+        WtError::GitFailed {
+            command: command.into(),
+            stderr: String::from_utf8_lossy(stderr).trim().to_string(),
+        }
+    }
+
+    /// Signing a commit failed with `stderr` as git's output
+    pub(crate) fn signing(stderr: &[u8]) -> Self {
+        WtError::SigningFailed { stderr: String::from_utf8_lossy(stderr).trim().to_string() }
+    }
+}
+
+fn held_by(holders: &[String]) -> String {
+    if holders.is_empty() {
+        return String::new();
+    }
+    format!(
+        "; held longest by {}. Free ports with hydra-wt clean, prune or remove",
+        holders.join(", ")
+    )
+}
+
+fn free_ports(count: &u16) -> String {
+    match count {
+        1 => "free ports".to_string(),
+        n => format!("{} consecutive free ports", n),
+    }
+}
+
+/// `anyhow::bail!` for functions returning [`WtError`]
+macro_rules! bail {
+    ($($arg:tt)*) => {
+        return Err($crate::error::WtError::Other(anyhow::anyhow!($($arg)*)))
+    };
+}
+pub(crate) use bail;
@@ -0,0 +1,164 @@
+//! Shell exports of a worktree's settings
+//!
+//! `hydra-wt env <branch>` and `create --print-env` print the port, branch
+//! and path of a worktree plus its `[template.vars]`, for
+//! `eval "$(hydra-wt env <branch>)"`. The values come from the port registry
+//! and the config resolved for the branch, never from the rendered env
+//! files, so they are there for worktrees without ports too.
+
+use anyhow::{Result, bail};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::config::WtConfig;
+use crate::ports::PortRegistry;
+use crate::template::TemplateContext;
+
+/// How [`render`] writes the variables
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellFormat {
+    /// `export NAME='value'` for sh, bash and zsh
+    Posix,
+    /// `set -gx NAME 'value'`
+    Fish,
+    /// One JSON object
+    Json,
+}
+
+/// Variables hydra-wt sets itself, here or for hooks; `HYDRA_WT_PORT_<n>`
+/// too. No template variable may export under one of these names.
+const BUILT_IN: &[&str] = &[
+    "HYDRA_WT_BRANCH",
+    "HYDRA_WT_PATH",
+    "HYDRA_WT_PORT",
+    "HYDRA_WT_REPO_ROOT",
+    "HYDRA_WT_PROJECT_UUID",
+];
+
+/// The variables of the worktree of `branch`, in print order:
+/// `HYDRA_WT_BRANCH`, `HYDRA_WT_PATH`, `HYDRA_WT_PORT` (with `_2`, `_3`, ...
+/// for a larger block; none without ports), then each template variable
+/// as `HYDRA_WT_<NAME>`, e.g. `HYDRA_WT_BRANCH_SLUG`. Fails if a template
+/// variable would shadow a built-in one or another template variable.
+pub fn for_worktree(cfg: &WtConfig, repo_root: &Path, branch: &str) -> Result<Vec<(String, String)>> {
+    let registry = PortRegistry::load_at(repo_root)?;
+    if !registry.is_managed(branch) {
+        bail!("No worktree for '{}' is managed by hydra-wt", branch);
+    }
+    let path = registry.worktree_path(cfg, repo_root, branch);
+    let ctx = TemplateContext::for_worktree(cfg, repo_root, branch, registry.block(branch))?;
+
+    let mut vars = vec![
+        ("HYDRA_WT_BRANCH".to_string(), branch.to_string()),
+        ("HYDRA_WT_PATH".to_string(), path.to_string_lossy().to_string()),
+    ];
+    for (i, port) in ctx.ports.iter().enumerate() {
+        let name = if i == 0 { "HYDRA_WT_PORT".to_string() } else { format!("HYDRA_WT_PORT_{}", i + 1) };
+        vars.push((name, port.to_string()));
+    }
+    vars.extend(template_exports(&ctx.vars)?);
+    Ok(vars)
+}
+
+/// `HYDRA_WT_<NAME>` for each template variable, its name upper-cased with
+/// anything but ASCII letters and digits as `_`, so `api-url` exports as
+/// `HYDRA_WT_API_URL`
+fn template_exports(template_vars: &BTreeMap<String, String>) -> Result<Vec<(String, String)>> {
+    let mut exports: Vec<(String, String)> = Vec::new();
+    let mut sources: BTreeMap<String, &str> = BTreeMap::new();
+    for (name, value) in template_vars {
+        let sanitized: String = name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect();
+        let export = format!("HYDRA_WT_{}", sanitized);
+        if is_built_in(&export) {
+            bail!("template.vars.{} would export as {}, which hydra-wt sets itself; rename it", name, export);
+        }
+        if let Some(other) = sources.insert(export.clone(), name) {
+            bail!("template.vars.{} and template.vars.{} would both export as {}; rename one", other, name, export);
+        }
+        exports.push((export, value.clone()));
+    }
+    Ok(exports)
+}
+
+fn is_built_in(name: &str) -> bool {
+    BUILT_IN.contains(&name)
+        || name
+            .strip_prefix("HYDRA_WT_PORT_")
+            .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// `vars` as `format`, one assignment per line
+pub fn render(vars: &[(String, String)], format: ShellFormat) -> String {
+    match format {
+        ShellFormat::Posix => vars
+            .iter()
+            .map(|(name, value)| format!("export {}={}\n", name, posix_quote(value)))
+            .collect(),
+        ShellFormat::Fish => vars
+            .iter()
+            .map(|(name, value)| format!("set -gx {} {}\n", name, fish_quote(value)))
+            .collect(),
+        ShellFormat::Json => {
+            let map: BTreeMap<&str, &str> = vars.iter().map(|(n, v)| (n.as_str(), v.as_str())).collect();
+            let mut json = serde_json::to_string_pretty(&map).unwrap_or_default();
+            json.push('\n');
+            json
+        }
+    }
+}
+
+/// Single-quoted for sh; a `'` ends the quote, is escaped, and reopens it
+fn posix_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Single-quoted for fish, where `\` and `'` are escaped inside the quotes
+fn fish_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let vars = vec![
+            ("HYDRA_WT_BRANCH".to_string(), "feature/it's".to_string()),
+            ("HYDRA_WT_PORT".to_string(), "3004".to_string()),
+        ];
+        assert_eq!(
+            render(&vars, ShellFormat::Posix),
+            "export HYDRA_WT_BRANCH='feature/it'\\''s'\nexport HYDRA_WT_PORT='3004'\n"
+        );
+        assert_eq!(
+            render(&vars, ShellFormat::Fish),
+            "set -gx HYDRA_WT_BRANCH 'feature/it\\'s'\nset -gx HYDRA_WT_PORT '3004'\n"
+        );
+        let json: serde_json::Value = serde_json::from_str(&render(&vars, ShellFormat::Json)).unwrap();
+        assert_eq!(json, serde_json::json!({ "HYDRA_WT_BRANCH": "feature/it's", "HYDRA_WT_PORT": "3004" }));
+        assert_eq!(fish_quote("a\\b"), "'a\\\\b'");
+    }
+
+    #[test]
+    fn test_template_exports_are_sanitized() {
+        let vars = |names: &[&str]| -> BTreeMap<String, String> {
+            names.iter().map(|name| (name.to_string(), format!("{} value", name))).collect()
+        };
+        let exports = template_exports(&vars(&["api-url", "branch_slug", "db.name", "$(rm)"])).unwrap();
+        let names: Vec<&str> = exports.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["HYDRA_WT___RM_", "HYDRA_WT_API_URL", "HYDRA_WT_BRANCH_SLUG", "HYDRA_WT_DB_NAME"]);
+        assert_eq!(exports[1].1, "api-url value");
+
+        for built_in in ["port", "Path", "port_2", "repo-root"] {
+            let err = template_exports(&vars(&[built_in])).unwrap_err().to_string();
+            assert!(err.contains("hydra-wt sets itself"), "{}", err);
+        }
+        assert!(template_exports(&vars(&["port_url", "port_"])).is_ok());
+        let err = template_exports(&vars(&["api-url", "api_url"])).unwrap_err().to_string();
+        assert!(err.contains("template.vars.api-url and template.vars.api_url would both export as HYDRA_WT_API_URL"), "{}", err);
+    }
+}
@@ -0,0 +1,277 @@
+//! Detection of zombie worktrees
+//!
+//! A managed worktree can outlive its branch: the branch gets deleted, its
+//! upstream disappears after a squash-merge, or the branch is force-moved to
+//! unrelated history. None of this shows up in `git status` inside the
+//! worktree, so we check for it explicitly.
+
+use anyhow::{Context, Result};
+use hydra_log::LoggedCommand;
+use std::path::Path;
+use std::process::Command;
+
+use crate::ports::PortRegistry;
+
+/// Record the current head of `branch` in the port registry of the
+/// repository at `repo`, for callers that just created or synced its
+/// worktree. Only managed branches keep a head.
+pub fn record_head(repo: &Path, branch: &str) -> Result<()> {
+    let mut registry = PortRegistry::load_at(repo)?;
+    registry.record_head(repo, branch)?;
+    registry.save_at(repo)?;
+    Ok(())
+}
+
+/// Something wrong with the branch behind a managed worktree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WorktreeIssue {
+    /// The local branch no longer exists
+    BranchDeleted,
+    /// The branch tracks an upstream that has been deleted
+    UpstreamGone { upstream: String },
+    /// The recorded head is no longer part of the branch's history
+    HistoryRewritten { recorded: String, current: String },
+}
+
+impl WorktreeIssue {
+    /// Short, stable label for tables and machine output
+    pub fn label(&self) -> &'static str {
+        match self {
+            WorktreeIssue::BranchDeleted => "branch-deleted",
+            WorktreeIssue::UpstreamGone { .. } => "upstream-gone",
+            WorktreeIssue::HistoryRewritten { .. } => "history-rewritten",
+        }
+    }
+
+    /// One-line description
+    pub fn describe(&self) -> String {
+        match self {
+            WorktreeIssue::BranchDeleted => "local branch no longer exists".to_string(),
+            WorktreeIssue::UpstreamGone { upstream } => {
+                format!("upstream '{}' has been deleted", upstream)
+            }
+            WorktreeIssue::HistoryRewritten { recorded, current } => format!(
+                "branch moved from {} to {} without keeping the old head",
+                short(recorded),
+                short(current)
+            ),
+        }
+    }
+
+    /// Suggested way out
+    pub fn remediation(&self, branch: &str) -> String {
+        match self {
+            WorktreeIssue::BranchDeleted => format!(
+                "remove the worktree (hydra-wt remove -f {b}) or recreate the branch inside it (git checkout -b {b})",
+                b = branch
+            ),
+            WorktreeIssue::UpstreamGone { .. } => format!(
+                "if the branch was merged, remove it (hydra-wt remove {b}); otherwise clear the upstream (hydra-wt verify --fix-upstream-gone)",
+                b = branch
+            ),
+            WorktreeIssue::HistoryRewritten { recorded, .. } => format!(
+                "rebase any local work onto the new head, or re-point the branch (git branch -f {} {})",
+                branch,
+                short(recorded)
+            ),
+        }
+    }
+}
+
+/// Check a managed branch for zombie conditions, against the head
+/// `registry` recorded for it
+pub fn check(repo: &Path, branch: &str, registry: &PortRegistry) -> Result<Vec<WorktreeIssue>> {
+    let current = match rev_parse(repo, &format!("refs/heads/{}", branch))? {
+        Some(head) => head,
+        None => return Ok(vec![WorktreeIssue::BranchDeleted]),
+    };
+
+    let mut issues = Vec::new();
+
+    if let Some(upstream) = gone_upstream(repo, branch)? {
+        issues.push(WorktreeIssue::UpstreamGone { upstream });
+    }
+
+    if let Some(recorded) = registry.head(branch) {
+        if recorded != current && !is_ancestor(repo, recorded, &current) {
+            issues.push(WorktreeIssue::HistoryRewritten {
+                recorded: recorded.to_string(),
+                current,
+            });
+        }
+    }
+
+    Ok(issues)
+}
+
+/// Remove the upstream setting of a branch whose upstream is gone
+pub fn clear_upstream(repo: &Path, branch: &str) -> Result<()> {
+    let output = Command::new("git")
+        .args(["-C", &repo.to_string_lossy(), "branch", "--unset-upstream", branch])
+        .logged_output()
+        .context("Failed to run git branch --unset-upstream")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("git branch --unset-upstream failed: {}", stderr.trim());
+    }
+
+    Ok(())
+}
+
+/// Resolve a ref to a commit hash, `None` if it doesn't exist
+pub(crate) fn rev_parse(repo: &Path, rev: &str) -> Result<Option<String>> {
+    let output = Command::new("git")
+        .args(["-C", &repo.to_string_lossy(), "rev-parse", "--verify", "--quiet", rev])
+        .logged_output()
+        .context("Failed to run git rev-parse")?;
+
+    if !output.status.success() {
+        return Ok(None);
+    }
+    Ok(Some(String::from_utf8_lossy(&output.stdout).trim().to_string()))
+}
+
+/// Upstream name if the branch tracks one that no longer exists
+fn gone_upstream(repo: &Path, branch: &str) -> Result<Option<String>> {
+    let output = Command::new("git")
+        .args([
+            "-C",
+            &repo.to_string_lossy(),
+            "for-each-ref",
+            "--format=%(upstream:short)|%(upstream:track)",
+            &format!("refs/heads/{}", branch),
+        ])
+        .logged_output()
+        .context("Failed to run git for-each-ref")?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let line = stdout.lines().next().unwrap_or("");
+    match line.split_once('|') {
+        Some((upstream, track)) if track.trim() == "[gone]" => Ok(Some(upstream.to_string())),
+        _ => Ok(None),
+    }
+}
+
+fn is_ancestor(repo: &Path, ancestor: &str, descendant: &str) -> bool {
+    // An unknown commit (e.g. garbage collected) counts as not an ancestor
+    Command::new("git")
+        .args(["-C", &repo.to_string_lossy(), "merge-base", "--is-ancestor", ancestor, descendant])
+        .logged_output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+fn short(hash: &str) -> &str {
+    &hash[..7.min(hash.len())]
+}
+
+/// Convenience wrapper: check a branch using the repo's recorded heads
+pub fn check_branch(repo: &Path, branch: &str) -> Result<Vec<WorktreeIssue>> {
+    let registry = PortRegistry::load_at(repo)?;
+    check(repo, branch, &registry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::{self, git};
+    use std::path::PathBuf;
+
+    /// Repo with a local bare origin and a `feature` worktree tracking it
+    fn setup() -> (PathBuf, PathBuf) {
+        let repo = scratch::repo("health");
+        let root = repo.parent().unwrap();
+        let origin = root.join("origin.git");
+
+        git(root, &["init", "-q", "--bare", "-b", "main", &origin.to_string_lossy()]);
+        scratch::commit_all(&repo, "init");
+        git(&repo, &["remote", "add", "origin", &origin.to_string_lossy()]);
+        git(&repo, &["push", "-q", "-u", "origin", "main"]);
+
+        let wt = root.join("wts").join("feature");
+        git(&repo, &["worktree", "add", "-q", "-b", "feature", &wt.to_string_lossy()]);
+        std::fs::write(wt.join("a.txt"), "a").unwrap();
+        git(&wt, &["add", "."]);
+        git(&wt, &["commit", "-q", "-m", "feature work"]);
+        git(&wt, &["push", "-q", "-u", "origin", "feature"]);
+
+        let mut registry = PortRegistry::default();
+        registry.add_portless("feature");
+        registry.set_path("feature", &wt);
+        registry.save_at(&repo).unwrap();
+        record_head(&repo, "feature").unwrap();
+        (repo, wt)
+    }
+
+    #[test]
+    fn test_healthy_worktree() {
+        let (repo, wt) = setup();
+
+        // Normal progress on top of the recorded head is fine
+        std::fs::write(wt.join("b.txt"), "b").unwrap();
+        git(&wt, &["add", "."]);
+        git(&wt, &["commit", "-q", "-m", "more work"]);
+
+        assert!(check_branch(&repo, "feature").unwrap().is_empty());
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_branch_deleted() {
+        let (repo, _wt) = setup();
+        git(&repo, &["update-ref", "-d", "refs/heads/feature"]);
+
+        assert_eq!(check_branch(&repo, "feature").unwrap(), vec![WorktreeIssue::BranchDeleted]);
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_upstream_gone() {
+        let (repo, _wt) = setup();
+        git(&repo, &["push", "-q", "origin", "--delete", "feature"]);
+        git(&repo, &["fetch", "-q", "--prune", "origin"]);
+
+        let issues = check_branch(&repo, "feature").unwrap();
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].label(), "upstream-gone");
+
+        clear_upstream(&repo, "feature").unwrap();
+        assert!(check_branch(&repo, "feature").unwrap().is_empty());
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_history_rewritten() {
+        let (repo, wt) = setup();
+        let recorded = PortRegistry::load_at(&repo).unwrap().head("feature").unwrap().to_string();
+
+        git(&wt, &["reset", "-q", "--hard", "HEAD~1"]);
+        std::fs::write(wt.join("c.txt"), "c").unwrap();
+        git(&wt, &["add", "."]);
+        git(&wt, &["commit", "-q", "-m", "rewritten"]);
+        let current = git(&wt, &["rev-parse", "HEAD"]);
+
+        assert_eq!(
+            check_branch(&repo, "feature").unwrap(),
+            vec![WorktreeIssue::HistoryRewritten { recorded, current }]
+        );
+
+        // Re-recording accepts the new history
+        record_head(&repo, "feature").unwrap();
+        assert!(check_branch(&repo, "feature").unwrap().is_empty());
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_unrecorded_branch_skips_rewrite_check() {
+        let (repo, wt) = setup();
+        let mut registry = PortRegistry::load_at(&repo).unwrap();
+        registry.heads.remove("feature");
+        registry.save_at(&repo).unwrap();
+        git(&wt, &["reset", "-q", "--hard", "HEAD~1"]);
+
+        assert!(check_branch(&repo, "feature").unwrap().is_empty());
+        scratch::remove(&repo);
+    }
+}
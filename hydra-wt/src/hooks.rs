@@ -1,47 +1,450 @@
 //! Hook execution for worktrees
 //!
 //! Provides post-create hook execution for running setup commands
-//! in newly created worktrees.
+//! in newly created worktrees. Each hook runs with a timeout and the
+//! worktree's branch, ports and paths in `HYDRA_WT_*` variables, and its
+//! output is streamed to the console and appended to
+//! `<worktree>/.hydra/hooks.log` so a hung or failed hook can be diagnosed.
+//! `merge --check` runs its check command the same way.
+//!
+//! A hook runs in a process group of its own, so a timeout can kill
+//! everything it started; that also keeps the terminal's Ctrl+C from
+//! reaching it, so a Ctrl+C handler passes it on with [`interrupt`].
+
+use anyhow::{Context, Result};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+use crate::ports::PortBlock;
+
+/// How long an interrupted hook gets to exit before it is killed
+const INTERRUPT_GRACE: Duration = Duration::from_secs(2);
+
+/// Hooks running now by process group, each with the flag [`interrupt`]
+/// sets for it
+static RUNNING: LazyLock<Mutex<BTreeMap<u32, Arc<AtomicBool>>>> = LazyLock::new(Default::default);
+
+/// How a single hook ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookStatus {
+    Succeeded,
+    /// The hook exited unsuccessfully
+    Failed(ExitStatus),
+    /// The hook was still running after the timeout and was killed
+    TimedOut(Duration),
+}
+
+impl fmt::Display for HookStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HookStatus::Succeeded => write!(f, "exit status: 0"),
+            HookStatus::Failed(status) => write!(f, "{}", status),
+            HookStatus::TimedOut(after) => write!(f, "timed out after {}s", after.as_secs()),
+        }
+    }
+}
 
-use anyhow::Result;
-use std::path::Path;
-use std::process::Command;
+/// The worktree a hook runs for
+#[derive(Debug, Clone)]
+pub struct HookContext<'a> {
+    pub branch: &'a str,
+    /// `None` for a worktree created without ports
+    pub ports: Option<PortBlock>,
+    pub worktree: &'a Path,
+    pub repo_root: &'a Path,
+    /// hydra-mail project, `None` if the repository has no `.hydra/config.toml`
+    pub project_uuid: Option<String>,
+}
 
-/// Run post-create hooks in the worktree directory
+impl HookContext<'_> {
+    /// `HYDRA_WT_BRANCH`, `HYDRA_WT_PATH`, `HYDRA_WT_REPO_ROOT`,
+    /// `HYDRA_WT_PORT` (with `_2`, `_3`, ... for a larger block) and
+    /// `HYDRA_WT_PROJECT_UUID`; the port and project variables are left out
+    /// when there are none
+    pub fn env(&self) -> Vec<(String, String)> {
+        let mut env = vec![
+            ("HYDRA_WT_BRANCH".to_string(), self.branch.to_string()),
+            ("HYDRA_WT_PATH".to_string(), self.worktree.to_string_lossy().to_string()),
+            ("HYDRA_WT_REPO_ROOT".to_string(), self.repo_root.to_string_lossy().to_string()),
+        ];
+        for (i, port) in self.ports.iter().flat_map(|block| block.ports()).enumerate() {
+            let name = if i == 0 { "HYDRA_WT_PORT".to_string() } else { format!("HYDRA_WT_PORT_{}", i + 1) };
+            env.push((name, port.to_string()));
+        }
+        if let Some(uuid) = &self.project_uuid {
+            env.push(("HYDRA_WT_PROJECT_UUID".to_string(), uuid.clone()));
+        }
+        env
+    }
+}
+
+/// A hook that was run and how it ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HookRun {
+    pub command: String,
+    pub status: HookStatus,
+}
+
+/// Where hook output of the worktree at `wt_path` is logged
+pub fn log_path(wt_path: &Path) -> PathBuf {
+    wt_path.join(".hydra").join("hooks.log")
+}
+
+/// Run post-create hooks in the worktree of `ctx`
 ///
-/// Executes each command via `sh -c` in the worktree directory.
-/// Hook failures warn but don't fail - the worktree is already created.
-pub fn run_post_create(wt_path: &Path, commands: &[String]) -> Result<()> {
+/// Executes each command via `sh -c` in the worktree directory with the
+/// variables of [`HookContext::env`], one after another. The first hook
+/// that fails or times out stops the rest; a timed-out hook is killed
+/// along with everything it started. Returns how each hook that ran ended,
+/// so only the last can have failed; an error means a hook could not be
+/// started at all or was stopped by [`interrupt`].
+pub fn run_post_create(ctx: &HookContext, commands: &[String], timeout: Option<Duration>) -> Result<Vec<HookRun>> {
+    let mut runs = Vec::new();
     if commands.is_empty() {
-        return Ok(());
+        return Ok(runs);
     }
 
-    println!("Running post-create hooks...");
+    info!("Running post-create hooks...");
+
+    let wt_path = ctx.worktree;
+    let env = ctx.env();
+    let log_path = log_path(wt_path);
+    if let Some(parent) = log_path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .with_context(|| format!("Failed to open {}", log_path.display()))?;
+    let log = Arc::new(Mutex::new(log));
 
     for cmd in commands {
-        println!("  Running: {}", cmd);
-
-        let output = Command::new("sh")
-            .args(["-c", cmd])
-            .current_dir(wt_path)
-            .output();
-
-        match output {
-            Ok(result) => {
-                if !result.status.success() {
-                    let stderr = String::from_utf8_lossy(&result.stderr);
-                    eprintln!(
-                        "Warning: hook '{}' failed: {}",
-                        cmd,
-                        stderr.trim()
-                    );
-                }
+        info!("  Running: {}", cmd);
+        let status = run_hook(wt_path, cmd, &env, timeout, &log)?;
+        info!("  {}: {}", cmd, status);
+        runs.push(HookRun { command: cmd.clone(), status });
+        if status != HookStatus::Succeeded {
+            break;
+        }
+    }
+
+    Ok(runs)
+}
+
+/// Run `cmd` via `sh -c` in the worktree at `wt_path` with `env` set, as a
+/// hook is run, writing its output to a fresh `log_path`
+pub fn run_check(
+    wt_path: &Path,
+    cmd: &str,
+    env: &[(String, String)],
+    timeout: Option<Duration>,
+    log_path: &Path,
+) -> Result<HookStatus> {
+    if let Some(parent) = log_path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    let log = File::create(log_path)
+        .with_context(|| format!("Failed to create {}", log_path.display()))?;
+    run_hook(wt_path, cmd, env, timeout, &Arc::new(Mutex::new(log)))
+}
+
+/// Pass a Ctrl+C on to the hooks running now: each gets SIGINT, and is
+/// killed along with everything it started if it hasn't exited after a
+/// grace period. Their runs fail with an interrupted error. For a Ctrl+C
+/// handler.
+pub fn interrupt() {
+    let running: Vec<u32> = RUNNING.lock().unwrap().keys().copied().collect();
+    for group in running {
+        interrupt_group(group);
+    }
+}
+
+fn interrupt_group(group: u32) {
+    if let Some(flag) = RUNNING.lock().unwrap().get(&group) {
+        flag.store(true, Ordering::SeqCst);
+        signal_group(group, "-INT");
+    }
+}
+
+/// Takes a running hook out of [`RUNNING`] when its run ends
+struct Registered(u32);
+
+impl Drop for Registered {
+    fn drop(&mut self) {
+        RUNNING.lock().unwrap().remove(&self.0);
+    }
+}
+
+/// Run one hook, copying its output to the console and `log`
+fn run_hook(
+    wt_path: &Path,
+    cmd: &str,
+    env: &[(String, String)],
+    timeout: Option<Duration>,
+    log: &Arc<Mutex<File>>,
+) -> Result<HookStatus> {
+    write_log(log, &format!("$ {}", cmd));
+
+    let mut command = Command::new("sh");
+    command
+        .args(["-c", cmd])
+        .envs(env.iter().map(|(k, v)| (k, v)))
+        .current_dir(wt_path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    // Its own process group, so a timeout can kill whatever the hook started
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    debug!("$ {}", hydra_log::command_line(&command));
+
+    let started = Instant::now();
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("Failed to run hook '{}'", cmd))?;
+    let interrupted = Arc::new(AtomicBool::new(false));
+    RUNNING.lock().unwrap().insert(child.id(), interrupted.clone());
+    let _registered = Registered(child.id());
+
+    // Each stream reports on `done` when it reaches EOF
+    let (done_tx, done_rx) = mpsc::channel();
+    let echo = tracing::enabled!(tracing::Level::INFO);
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+    for stream in [Box::new(stdout) as Box<dyn Read + Send>, Box::new(stderr)] {
+        let log = log.clone();
+        let done = done_tx.clone();
+        std::thread::spawn(move || {
+            forward(stream, &log, echo);
+            let _ = done.send(());
+        });
+    }
+    drop(done_tx);
+
+    // Wait for the hook to exit and its output to be drained. Something it
+    // left running in the background can hold the pipes open, so the
+    // timeout covers both.
+    let mut open_streams = 2;
+    let mut status = None;
+    loop {
+        if status.is_none() {
+            status = child.try_wait().context("Failed to wait for hook")?;
+        }
+        if let Some(status) = status {
+            if open_streams == 0 {
+                debug!("hook '{}' -> {} in {}ms", cmd, status, started.elapsed().as_millis());
+                let outcome = if status.success() { HookStatus::Succeeded } else { HookStatus::Failed(status) };
+                write_log(log, &format!("# {}\n", status));
+                return Ok(outcome);
+            }
+        }
+        if interrupted.load(Ordering::SeqCst) {
+            stop_interrupted(&mut child);
+            // With the whole group gone, what it wrote last comes through
+            while open_streams > 0 && done_rx.recv_timeout(Duration::from_secs(1)).is_ok() {
+                open_streams -= 1;
             }
-            Err(e) => {
-                eprintln!("Warning: failed to run hook '{}': {}", cmd, e);
+            write_log(log, "# interrupted\n");
+            anyhow::bail!("Hook '{}' interrupted", cmd);
+        }
+        if let Some(timeout) = timeout {
+            if started.elapsed() >= timeout {
+                kill_group(&mut child);
+                write_log(log, &format!("# timed out after {}s, killed\n", timeout.as_secs()));
+                return Ok(HookStatus::TimedOut(timeout));
             }
         }
+        match done_rx.recv_timeout(Duration::from_millis(50)) {
+            Ok(()) => open_streams -= 1,
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => open_streams = 0,
+        }
+    }
+}
+
+/// Copy lines from `stream` to the log, and to the console unless quiet
+fn forward(stream: Box<dyn Read + Send>, log: &Mutex<File>, echo: bool) {
+    let mut reader = BufReader::new(stream);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {}
+        }
+        let text = String::from_utf8_lossy(&line);
+        let text = text.trim_end_matches(['\n', '\r']);
+        if echo {
+            eprintln!("    {}", text);
+        }
+        write_log(log, text);
+    }
+}
+
+fn write_log(log: &Mutex<File>, line: &str) {
+    if let Ok(mut file) = log.lock() {
+        let _ = writeln!(file, "{}", line);
+    }
+}
+
+/// Give a hook sent SIGINT the grace period to exit, then kill its group
+fn stop_interrupted(child: &mut Child) {
+    let deadline = Instant::now() + INTERRUPT_GRACE;
+    while Instant::now() < deadline {
+        if let Ok(Some(_)) = child.try_wait() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    // Whatever it left running in the background goes too
+    kill_group(child);
+}
+
+/// Kill the hook and everything in its process group
+fn kill_group(child: &mut Child) {
+    signal_group(child.id(), "-KILL");
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+fn signal_group(group: u32, signal: &str) {
+    #[cfg(unix)]
+    {
+        let _ = Command::new("kill")
+            .args([signal, "--", &format!("-{}", group)])
+            .stderr(Stdio::null())
+            .status();
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn scratch() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hydra-wt-hooks-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn context(wt: &Path) -> HookContext<'_> {
+        HookContext { branch: "feature/db", ports: None, worktree: wt, repo_root: wt, project_uuid: None }
     }
 
-    Ok(())
+    #[test]
+    fn test_hooks_log_output_and_stop_at_first_failure() {
+        let wt = scratch();
+        let commands = vec![
+            "echo first".to_string(),
+            "echo out; echo err >&2; exit 3".to_string(),
+            "touch never-run".to_string(),
+        ];
+
+        let runs = run_post_create(&context(&wt), &commands, Some(Duration::from_secs(30))).unwrap();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].status, HookStatus::Succeeded);
+        assert!(matches!(runs[1].status, HookStatus::Failed(s) if s.code() == Some(3)));
+        assert!(!wt.join("never-run").exists());
+
+        let log = std::fs::read_to_string(log_path(&wt)).unwrap();
+        assert!(log.starts_with("$ echo first\nfirst\n# exit status: 0\n"), "{}", log);
+        assert!(log.contains("out\n") && log.contains("err\n") && log.contains("# exit status: 3\n"), "{}", log);
+        let _ = std::fs::remove_dir_all(&wt);
+    }
+
+    #[test]
+    fn test_hooks_see_context() {
+        let wt = scratch();
+        let ctx = HookContext {
+            ports: Some(PortBlock { start: 3004, count: 2 }),
+            project_uuid: Some("0b6e".to_string()),
+            ..context(&wt)
+        };
+        let commands = vec!["echo $HYDRA_WT_BRANCH $HYDRA_WT_PORT $HYDRA_WT_PORT_2 $HYDRA_WT_PROJECT_UUID > env.txt".to_string()];
+
+        run_post_create(&ctx, &commands, None).unwrap();
+        assert_eq!(std::fs::read_to_string(wt.join("env.txt")).unwrap(), "feature/db 3004 3005 0b6e\n");
+        let env = context(&wt).env();
+        assert!(!env.iter().any(|(name, _)| name == "HYDRA_WT_PORT" || name == "HYDRA_WT_PROJECT_UUID"), "{:?}", env);
+        let _ = std::fs::remove_dir_all(&wt);
+    }
+
+    #[test]
+    fn test_hook_timeout_kills_process_group() {
+        let wt = scratch();
+        // The background sleep holds the output pipe open after the shell is killed
+        let commands = vec![
+            "sleep 30 & echo started; wait".to_string(),
+            "touch never-run".to_string(),
+        ];
+
+        let started = Instant::now();
+        let runs = run_post_create(&context(&wt), &commands, Some(Duration::from_millis(300))).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(runs, vec![HookRun {
+            command: commands[0].clone(),
+            status: HookStatus::TimedOut(Duration::from_millis(300)),
+        }]);
+        assert!(!wt.join("never-run").exists());
+        let log = std::fs::read_to_string(log_path(&wt)).unwrap();
+        assert!(log.contains("started\n") && log.contains("timed out"), "{}", log);
+        let _ = std::fs::remove_dir_all(&wt);
+    }
+
+    #[test]
+    fn test_interrupt_reaches_the_hook_and_stops_waiting() {
+        let wt = scratch();
+        let commands = vec![
+            "echo $$ > group; trap 'echo got INT; exit 3' INT; sleep 30 & wait".to_string(),
+            "touch never-run".to_string(),
+        ];
+        // Only this test's hook, as the others run alongside
+        let group = wt.join("group");
+        let interrupter = std::thread::spawn(move || {
+            for _ in 0..200 {
+                if let Some(group) = std::fs::read_to_string(&group).ok().and_then(|g| g.trim().parse().ok()) {
+                    interrupt_group(group);
+                    return;
+                }
+                std::thread::sleep(Duration::from_millis(20));
+            }
+        });
+
+        let started = Instant::now();
+        let err = run_post_create(&context(&wt), &commands, None).unwrap_err().to_string();
+        interrupter.join().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(err.contains("interrupted"), "{}", err);
+        assert!(!wt.join("never-run").exists());
+        let log = std::fs::read_to_string(log_path(&wt)).unwrap();
+        assert!(log.contains("got INT\n") && log.contains("# interrupted"), "{}", log);
+        let _ = std::fs::remove_dir_all(&wt);
+    }
+
+    #[test]
+    fn test_check_sees_env_and_replaces_log() {
+        let wt = scratch();
+        let log = wt.join("checks/feature.log");
+        std::fs::create_dir_all(log.parent().unwrap()).unwrap();
+        std::fs::write(&log, "previous run\n").unwrap();
+
+        let env = vec![("PORT".to_string(), "3004".to_string())];
+        let status = run_check(&wt, "echo port=$PORT; exit 1", &env, None, &log).unwrap();
+        assert!(matches!(status, HookStatus::Failed(s) if s.code() == Some(1)));
+        let output = std::fs::read_to_string(&log).unwrap();
+        assert!(output.contains("port=3004\n") && !output.contains("previous run"), "{}", output);
+        let _ = std::fs::remove_dir_all(&wt);
+    }
 }
@@ -1,14 +1,43 @@
-use anyhow::{Context, Result};
-use serde::Serialize;
+//! Events for hydra-mail
+//!
+//! Events are emitted through the hydra-mail client. When the daemon isn't
+//! running they are appended to `.hydra/wt-events.pending` instead, and
+//! replayed once it is back, so a create never waits on hydra-mail.
+//!
+//! Besides the events of single changes, every change to the set of
+//! worktrees publishes the whole inventory on `wt:inventory`, so a
+//! subscriber finds a current snapshot in the replay buffer.
+//!
+//! Registry changes and merge progress go to `sys:registry`, merge problems
+//! that need someone to act to `merge:` channels, and worktree changes to
+//! `repo:delta`. A project whose `[channel_policy]` lists its own
+//! namespaces needs `sys`, `merge`, `wt` and `repo` among them.
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Utc};
+use hydra_mail::client::{MailClient, Refused};
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
 use std::io::Write;
-use std::process::{Command, Stdio};
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+use crate::config::{self, WtConfig};
+use crate::ports::PortRegistry;
+use crate::{watch, worktree};
+
+/// Largest inventory, as JSON, that is published whole. hydra-mail refuses
+/// pulses over its `MAX_MESSAGE_SIZE` of 10 KiB; the TOON it sends is
+/// shorter than this JSON, and the rest is room for the pulse around it.
+pub const INVENTORY_MAX_BYTES: usize = 8 * 1024;
 
 #[derive(Serialize)]
 pub struct WorktreeCreatedEvent {
     #[serde(rename = "type")]
     pub event_type: &'static str,
     pub worktree: String,
-    pub port: u16,
+    /// `null` for a worktree without ports
+    pub port: Option<u16>,
     pub path: String,
 }
 
@@ -19,6 +48,15 @@ pub struct WorktreeRemovedEvent {
     pub worktree: String,
 }
 
+#[derive(Serialize)]
+pub struct WorktreeRenamedEvent {
+    #[serde(rename = "type")]
+    pub event_type: &'static str,
+    pub old_worktree: String,
+    pub worktree: String,
+    pub path: String,
+}
+
 #[derive(Serialize)]
 pub struct MergeStartedEvent {
     #[serde(rename = "type")]
@@ -35,6 +73,8 @@ pub struct MergeCompletedEvent {
     pub source: String,
     pub target: String,
     pub merge_commit: String,
+    /// Subject of the commit the target ends up at
+    pub subject: String,
 }
 
 #[derive(Serialize)]
@@ -47,27 +87,153 @@ pub struct MergeConflictEvent {
     pub conflicted_files: Vec<String>,
 }
 
-pub fn emit_merge_started(source: &str, target: &str, commits: usize) -> Result<()> {
+/// A merge landed but pushing its target afterwards failed
+#[derive(Serialize)]
+pub struct MergePushFailedEvent {
+    #[serde(rename = "type")]
+    pub event_type: &'static str,
+    pub source: String,
+    pub target: String,
+    pub remote: String,
+    /// Commit the target is at locally, still unpushed
+    pub commit: String,
+    pub error: String,
+}
+
+/// Paths named in a [`WorktreeDeltaEvent`]; a larger batch is cut down to
+/// these and `truncated`, to stay within a hydra-mail message
+pub const DELTA_PATHS_SHOWN: usize = 100;
+
+/// A batch of changes `hydra-wt watch` saw in a worktree, in the
+/// `action`/`target`/`summary` shape agents emit on `repo:delta`
+#[derive(Debug, Serialize)]
+pub struct WorktreeDeltaEvent {
+    #[serde(rename = "type")]
+    pub event_type: &'static str,
+    pub action: &'static str,
+    /// The branch of the worktree
+    pub target: String,
+    /// e.g. `3 files changed, +12 -4`
+    pub summary: String,
+    pub paths: Vec<String>,
+    /// Changed paths, including any left out of `paths`
+    pub files: usize,
+    pub added: usize,
+    pub removed: usize,
+    pub truncated: bool,
+}
+
+/// One managed worktree in an [`InventoryEvent`]
+#[derive(Debug, Serialize)]
+pub struct InventoryEntry {
+    pub branch: String,
+    /// `null` for a worktree without ports
+    pub port: Option<u16>,
+    pub path: String,
+    pub exists: bool,
+    /// `null` when the registry didn't record it
+    pub created_at: Option<DateTime<Utc>>,
+    /// Seconds since `created_at` when the inventory was taken
+    pub age_secs: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InventoryEvent {
+    #[serde(rename = "type")]
+    pub event_type: &'static str,
+    /// Managed worktrees, including any left out of `worktrees`
+    pub total: usize,
+    /// `worktrees` stops short of `total` to stay under [`INVENTORY_MAX_BYTES`]
+    pub truncated: bool,
+    /// Sorted by branch
+    pub worktrees: Vec<InventoryEntry>,
+}
+
+/// Every worktree in the registry of the project at `root`, cut to fit
+/// [`INVENTORY_MAX_BYTES`]
+pub fn inventory(cfg: &WtConfig, root: &Path) -> Result<InventoryEvent> {
+    let registry = PortRegistry::load_at(root)?;
+    let now = Utc::now();
+    let branches = registry.branches();
+    let mut event = InventoryEvent {
+        event_type: "worktree_inventory",
+        total: branches.len(),
+        truncated: true,
+        worktrees: Vec::new(),
+    };
+
+    // The size with `truncated: true`, then each entry and its comma
+    let mut size = serde_json::to_string(&event).context("Failed to serialize inventory")?.len();
+    for branch in branches {
+        let path = registry.worktree_path(cfg, root, branch);
+        let created_at = registry.created_at(branch);
+        let entry = InventoryEntry {
+            branch: branch.clone(),
+            port: registry.get(branch),
+            exists: worktree::exists(&path),
+            path: path.to_string_lossy().to_string(),
+            created_at,
+            age_secs: created_at.map(|at| (now - at).num_seconds()),
+        };
+        size += serde_json::to_string(&entry).context("Failed to serialize inventory")?.len() + 1;
+        if size > INVENTORY_MAX_BYTES {
+            break;
+        }
+        event.worktrees.push(entry);
+    }
+    event.truncated = event.worktrees.len() < event.total;
+    Ok(event)
+}
+
+/// Publish the [`inventory`] of the project at `root` on `wt:inventory`
+pub fn emit_inventory(cfg: &WtConfig, root: &Path) -> Result<InventoryEvent> {
+    let event = inventory(cfg, root)?;
+    emit(cfg, root, "wt:inventory", "status", &event)?;
+    Ok(event)
+}
+
+pub fn emit_merge_started(cfg: &WtConfig, root: &Path, source: &str, target: &str, commits: usize) -> Result<()> {
     let event = MergeStartedEvent {
         event_type: "merge_started",
         source: source.to_string(),
         target: target.to_string(),
         commits,
     };
-    emit("sys:registry", "status", &event)
+    emit(cfg, root, "sys:registry", "status", &event)
 }
 
-pub fn emit_merge_completed(source: &str, target: &str, merge_commit: &str) -> Result<()> {
+pub fn emit_merge_completed(cfg: &WtConfig, root: &Path, source: &str, target: &str, merge_commit: &str, subject: &str) -> Result<()> {
     let event = MergeCompletedEvent {
         event_type: "merge_completed",
         source: source.to_string(),
         target: target.to_string(),
         merge_commit: merge_commit.to_string(),
+        subject: subject.to_string(),
+    };
+    emit(cfg, root, "sys:registry", "status", &event)
+}
+
+pub fn emit_merge_push_failed(
+    cfg: &WtConfig,
+    root: &Path,
+    source: &str,
+    target: &str,
+    remote: &str,
+    commit: &str,
+    error: &str,
+) -> Result<()> {
+    let event = MergePushFailedEvent {
+        event_type: "merge_push_failed",
+        source: source.to_string(),
+        target: target.to_string(),
+        remote: remote.to_string(),
+        commit: commit.to_string(),
+        error: error.to_string(),
     };
-    emit("sys:registry", "status", &event)
+    emit(cfg, root, "merge:push-failed", "alert", &event)
 }
 
-pub fn emit_merge_conflict(source: &str, target: &str, target_worktree: &str, files: &[String]) -> Result<()> {
+pub fn emit_merge_conflict(cfg: &WtConfig, root: &Path, source: &str, target: &str, target_worktree: &str, files: &[String]) -> Result<()> {
     let event = MergeConflictEvent {
         event_type: "merge_conflict",
         source: source.to_string(),
@@ -75,60 +241,259 @@ pub fn emit_merge_conflict(source: &str, target: &str, target_worktree: &str, fi
         target_worktree: target_worktree.to_string(),
         conflicted_files: files.to_vec(),
     };
-    emit("sys:registry", "status", &event)
+    emit(cfg, root, "sys:registry", "status", &event)
+}
+
+pub fn emit_worktree_delta(cfg: &WtConfig, root: &Path, branch: &str, delta: &watch::Delta) -> Result<()> {
+    let files = delta.paths.len();
+    let event = WorktreeDeltaEvent {
+        event_type: "worktree_delta",
+        action: "updated",
+        target: branch.to_string(),
+        summary: format!(
+            "{} file{} changed, +{} -{}",
+            files,
+            if files == 1 { "" } else { "s" },
+            delta.added,
+            delta.removed
+        ),
+        paths: delta.paths.iter().take(DELTA_PATHS_SHOWN).cloned().collect(),
+        files,
+        added: delta.added,
+        removed: delta.removed,
+        truncated: files > DELTA_PATHS_SHOWN,
+    };
+    emit(cfg, root, "repo:delta", "delta", &event)
 }
 
-pub fn emit_worktree_created(worktree: &str, port: u16, path: &str) -> Result<()> {
+pub fn emit_worktree_created(cfg: &WtConfig, root: &Path, worktree: &str, port: Option<u16>, path: &str) -> Result<()> {
     let event = WorktreeCreatedEvent {
         event_type: "worktree_created",
         worktree: worktree.to_string(),
         port,
         path: path.to_string(),
     };
-    emit("sys:registry", "status", &event)
+    emit(cfg, root, "sys:registry", "status", &event)
 }
 
-pub fn emit_worktree_removed(worktree: &str) -> Result<()> {
+pub fn emit_worktree_removed(cfg: &WtConfig, root: &Path, worktree: &str) -> Result<()> {
     let event = WorktreeRemovedEvent {
         event_type: "worktree_removed",
         worktree: worktree.to_string(),
     };
-    emit("sys:registry", "status", &event)
+    emit(cfg, root, "sys:registry", "status", &event)
 }
 
-fn emit<T: Serialize>(channel: &str, msg_type: &str, data: &T) -> Result<()> {
-    let json = serde_json::to_string(data).context("Failed to serialize event")?;
+pub fn emit_worktree_renamed(cfg: &WtConfig, root: &Path, old: &str, new: &str, path: &str) -> Result<()> {
+    let event = WorktreeRenamedEvent {
+        event_type: "worktree_renamed",
+        old_worktree: old.to_string(),
+        worktree: new.to_string(),
+        path: path.to_string(),
+    };
+    emit(cfg, root, "sys:registry", "status", &event)
+}
 
-    // Check if hydra-mail is available
-    let which = Command::new("which")
-        .arg("hydra-mail")
-        .output();
+/// Where events go while the daemon is unreachable, one JSON object per line
+pub fn pending_path(root: &Path) -> PathBuf {
+    root.join(".hydra/wt-events.pending")
+}
+
+/// An event waiting in the pending queue
+#[derive(Debug, Serialize, Deserialize)]
+struct PendingEvent {
+    channel: String,
+    #[serde(rename = "type")]
+    msg_type: String,
+    data: serde_json::Value,
+}
+
+/// Whether the hydra-mail daemon of the project at `root` accepts
+/// connections. A missing socket or refused connection fails immediately,
+/// so this never waits on a timeout.
+pub fn daemon_reachable(root: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        std::os::unix::net::UnixStream::connect(config::hydra_socket_at(root)).is_ok()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = root;
+        false
+    }
+}
 
-    if which.is_err() || !which.unwrap().status.success() {
-        eprintln!("Warning: hydra-mail not found, skipping event emission");
+/// Emit an event of the project at `root`, or queue it in
+/// [`pending_path`] when the daemon isn't running
+fn emit<T: Serialize>(cfg: &WtConfig, root: &Path, channel: &str, msg_type: &str, data: &T) -> Result<()> {
+    if !cfg.hydra.enabled {
         return Ok(());
     }
+    let event = PendingEvent {
+        channel: channel.to_string(),
+        msg_type: msg_type.to_string(),
+        data: serde_json::to_value(data).context("Failed to serialize event")?,
+    };
 
-    let mut child = Command::new("hydra-mail")
-        .args(["emit", "--channel", channel, "--type", msg_type, "--data", "@-"])
-        .stdin(Stdio::piped())
-        .stdout(Stdio::null())
-        .stderr(Stdio::piped())
-        .spawn()
-        .context("Failed to spawn hydra-mail")?;
-
-    if let Some(mut stdin) = child.stdin.take() {
-        stdin
-            .write_all(json.as_bytes())
-            .context("Failed to write to hydra-mail stdin")?;
+    if daemon_reachable(root) {
+        match send(root, &event) {
+            Ok(()) => return Ok(()),
+            Err(e) => debug!("hydra-mail emit failed: {:#}", e),
+        }
+    } else {
+        debug!("hydra-mail daemon not running at {}", config::hydra_socket_at(root).display());
     }
 
-    let output = child.wait_with_output().context("Failed to wait for hydra-mail")?;
+    if let Err(e) = queue(root, &event) {
+        warn!("failed to queue hydra-mail event: {:#}", e);
+    }
+    Ok(())
+}
+
+fn queue(root: &Path, event: &PendingEvent) -> Result<()> {
+    let path = pending_path(root);
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    writeln!(file, "{}", serde_json::to_string(event)?)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    debug!("queued {} event in {}", event.msg_type, path.display());
+    Ok(())
+}
+
+/// What [`flush`] did
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct FlushOutcome {
+    pub sent: usize,
+    /// Events still queued because the daemon went away mid-flush
+    pub remaining: usize,
+}
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        eprintln!("Warning: hydra-mail emit failed: {}", stderr.trim());
+/// Replay the pending queue of the project at `root` to the daemon, in
+/// order. If the daemon goes away, that event and the ones after it stay
+/// queued.
+pub fn flush(cfg: &WtConfig, root: &Path) -> Result<FlushOutcome> {
+    let path = pending_path(root);
+    if !cfg.hydra.enabled || !path.exists() {
+        return Ok(FlushOutcome::default());
     }
+    if !daemon_reachable(root) {
+        bail!("hydra-mail daemon is not running at {}", config::hydra_socket_at(root).display());
+    }
+
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let lines: Vec<&str> = content.lines().filter(|line| !line.trim().is_empty()).collect();
+
+    let mut outcome = FlushOutcome::default();
+    for (i, line) in lines.iter().enumerate() {
+        let event: PendingEvent = match serde_json::from_str(line) {
+            Ok(event) => event,
+            Err(e) => {
+                warn!("dropping unreadable queued event: {}", e);
+                continue;
+            }
+        };
+        if let Err(e) = send(root, &event) {
+            debug!("hydra-mail emit failed: {:#}", e);
+            let rest = lines[i..].join("\n") + "\n";
+            std::fs::write(&path, rest).with_context(|| format!("Failed to write {}", path.display()))?;
+            outcome.remaining = lines.len() - i;
+            return Ok(outcome);
+        }
+        outcome.sent += 1;
+    }
+
+    std::fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+    Ok(outcome)
+}
 
+/// Send one event to the daemon. Inside an orchestrated session the
+/// client puts HYDRA_TRACE_ID in the pulse metadata. An event the daemon
+/// refuses is dropped with a warning, since sending it again won't change
+/// that; only losing the daemon is an error.
+fn send(root: &Path, event: &PendingEvent) -> Result<()> {
+    // The project's .hydra/ is at the repo root, not in a linked worktree
+    let client = MailClient::locate(root)?;
+    match client.emit_blocking(&event.channel, &event.msg_type, event.data.clone(), serde_json::Value::Null) {
+        Ok(ack) => debug!("hydra-mail emit {} -> {} receiver(s)", ack.channel, ack.receivers),
+        Err(e) if e.is::<Refused>() => warn!("hydra-mail emit failed: {}", e),
+        Err(e) => return Err(e.context("hydra-mail emit failed")),
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch() -> PathBuf {
+        let root = std::env::temp_dir().join(format!("hydra-wt-events-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(root.join(".hydra")).unwrap();
+        root
+    }
+
+    #[test]
+    fn test_events_queue_while_daemon_is_down() {
+        let root = scratch();
+        let mut cfg = WtConfig::default();
+        assert!(!daemon_reachable(&root));
+
+        emit_worktree_created(&cfg, &root, "feature", Some(3001), "/wts/feature").unwrap();
+        emit_worktree_removed(&cfg, &root, "feature").unwrap();
+        let queued = std::fs::read_to_string(pending_path(&root)).unwrap();
+        let events: Vec<serde_json::Value> = queued.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["channel"], "sys:registry");
+        assert_eq!(events[0]["type"], "status");
+        assert_eq!(events[0]["data"]["type"], "worktree_created");
+        assert_eq!(events[0]["data"]["port"], 3001);
+        assert_eq!(events[1]["data"]["type"], "worktree_removed");
+
+        // Still down: flushing fails and keeps the queue
+        assert!(flush(&cfg, &root).unwrap_err().to_string().contains("not running"));
+        assert_eq!(std::fs::read_to_string(pending_path(&root)).unwrap(), queued);
+
+        // Disabled: nothing is queued or flushed
+        cfg.hydra.enabled = false;
+        emit_worktree_removed(&cfg, &root, "other").unwrap();
+        assert_eq!(std::fs::read_to_string(pending_path(&root)).unwrap(), queued);
+        assert_eq!(flush(&cfg, &root).unwrap(), FlushOutcome::default());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_inventory_truncates_to_fit() {
+        let root = scratch();
+        let cfg = WtConfig::default();
+        let mut registry = PortRegistry::default();
+        for i in 0..500u16 {
+            registry.allocations.insert(format!("agent/task-{:03}", i), (3001 + i).into());
+        }
+        registry.set_created("agent/task-000", Utc::now() - chrono::Duration::hours(1));
+        registry.save_at(&root).unwrap();
+
+        let event = emit_inventory(&cfg, &root).unwrap();
+        assert_eq!(event.total, 500);
+        assert!(event.truncated && !event.worktrees.is_empty() && event.worktrees.len() < 500);
+        assert!(serde_json::to_string(&event).unwrap().len() <= INVENTORY_MAX_BYTES);
+        assert_eq!(event.worktrees[0].branch, "agent/task-000");
+        assert!(event.worktrees[0].age_secs.unwrap() >= 3600);
+        assert!(!event.worktrees[0].exists);
+
+        let queued = std::fs::read_to_string(pending_path(&root)).unwrap();
+        let queued: serde_json::Value = serde_json::from_str(queued.lines().next().unwrap()).unwrap();
+        assert_eq!((queued["channel"].as_str(), queued["data"]["type"].as_str()), (Some("wt:inventory"), Some("worktree_inventory")));
+
+        // A handful of worktrees fit whole
+        let mut registry = PortRegistry::default();
+        registry.allocations.insert("feature".to_string(), 3001.into());
+        registry.save_at(&root).unwrap();
+        let event = inventory(&cfg, &root).unwrap();
+        assert!(!event.truncated);
+        assert_eq!((event.total, event.worktrees[0].port), (1, Some(3001)));
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
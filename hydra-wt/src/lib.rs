@@ -3,21 +3,50 @@
 //! This library provides worktree creation, removal, and port allocation
 //! for use by other tools in the hydra ecosystem.
 
+pub mod age;
+pub mod archive;
 pub mod artifacts;
 pub mod config;
+pub mod create;
+pub mod doctor;
+pub mod error;
+pub mod exports;
+pub mod health;
 pub mod hooks;
 pub mod hydra;
+pub mod merge_queue;
+pub mod metrics;
+pub mod open;
 pub mod ports;
+pub mod probe;
+pub mod prune;
+pub mod rename;
 pub mod template;
+pub mod watch;
 pub mod worktree;
 
+#[cfg(test)]
+mod scratch;
+
 // Re-export main types
-pub use config::WtConfig;
-pub use ports::PortRegistry;
+pub use archive::{Archive, ArchiveInfo};
+pub use artifacts::{ArtifactKind, LinkStats, RefreshAction, RefreshReport};
+pub use config::{EffectiveConfig, WtConfig};
+pub use create::{create_worktree, CreateEvent, CreateOptions, CreateOutcome, CreatePhase, CreatedWorktree};
+pub use doctor::Finding;
+pub use error::WtError;
+pub use merge_queue::MergeQueue;
+pub use ports::{PortBlock, PortRegistry};
+pub use prune::{PrunePlan, StaleEntry, StaleReason};
+pub use rename::{RenameOptions, RenameOutcome, RenameStep};
 pub use worktree::{
-    WorktreeInfo, MergeResult, CommitInfo,
-    add, remove, exists, list,
-    merge, merge_abort, commits_ahead, merge_base, can_merge,
-    has_uncommitted_changes, is_merge_in_progress,
+    WorktreeInfo, WorktreeStatus, WorkState, MergeResult, MergeMode, MergeOptions, RebaseResult, CommitInfo, Divergence, MergePreview, CheckoutProgress,
+    DiffFormat, FileChange, ChangeSummary,
+    add, add_from, remove, exists, list,
+    merge, merge_abort, commits_ahead, divergence, merge_base, can_merge, merge_preview,
+    fetch, rebase, rebase_abort, is_rebase_in_progress, collect_status, detect_main_branch,
+    has_uncommitted_changes, is_merge_in_progress, work_state,
     get_current_branch, get_head_commit, branch_exists, get_worktree_path,
+    branch_exists_in, get_worktree_path_in, list_in, is_valid_branch_name,
+    ExecResult, exec_in_all,
 };
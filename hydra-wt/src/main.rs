@@ -1,20 +1,20 @@
-mod artifacts;
-mod config;
-mod hooks;
-mod hydra;
-mod ports;
-mod template;
-mod worktree;
-
 use anyhow::Result;
 use clap::{Parser, Subcommand};
-use std::path::PathBuf;
+use hydra_wt::{age, archive, artifacts, config, create, doctor, exports, health, hooks, hydra, metrics, open, ports, probe, prune, rename, template, watch, worktree, CreateEvent, CreatePhase, LinkStats, MergeQueue, RefreshAction};
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tracing::{error, info, warn};
 
 #[derive(Parser)]
 #[command(name = "hydra-wt")]
 #[command(about = "Worktree management for the Hydra ecosystem")]
 #[command(version)]
 struct Cli {
+    #[command(flatten)]
+    verbosity: hydra_log::VerbosityArgs,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -24,20 +24,173 @@ enum Commands {
     /// Initialize hydra-wt configuration
     Init,
 
+    /// Configure a fresh clone from the committed hydra-wt.toml template
+    CloneSetup,
+
+    /// Inspect the effective configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+
     /// Create a new worktree with port allocation
     Create {
         /// Branch name (creates new branch if doesn't exist)
         branch: String,
+        /// Start the new branch at this ref instead of HEAD
+        #[arg(long, value_name = "REF")]
+        from: Option<String>,
+        /// Claim exactly this port (the first of the block) instead of the next free one
+        #[arg(long)]
+        port: Option<u16>,
+        /// Free ports the registry still holds for the branch
+        #[arg(short, long)]
+        force: bool,
+        /// Allocate no ports, for a worktree that never runs a server
+        #[arg(long, conflicts_with = "port")]
+        no_port: bool,
+        /// Print the worktree's variables as `env` does instead of the summary
+        #[arg(long)]
+        print_env: bool,
+        /// Record this as the tool that created the worktree, for `list` and
+        /// `clean --owner`
+        #[arg(long, default_value = "cli")]
+        owner: String,
+    },
+
+    /// Print a worktree's port, branch, path and template variables as shell exports
+    Env {
+        /// Branch of the worktree
+        branch: String,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = EnvFormat::Sh)]
+        format: EnvFormat,
+    },
+
+    /// Render the env templates of existing worktrees again, after a template changed
+    Render {
+        /// Branch of the worktree
+        #[arg(required_unless_present = "all", conflicts_with = "all")]
+        branch: Option<String>,
+        /// Render every managed worktree
+        #[arg(long)]
+        all: bool,
+        /// Only show what would change
+        #[arg(long)]
+        diff: bool,
+    },
+
+    /// Jump into a worktree: a tmux window inside tmux, otherwise a shell
+    Open {
+        /// Branch of the worktree
+        branch: String,
+        /// Open the worktree in open.editor ($VISUAL or $EDITOR if unset) instead
+        #[arg(long, visible_alias = "code")]
+        editor: bool,
+    },
+
+    /// Manage an existing worktree made with plain git worktree add
+    Adopt {
+        /// Branch checked out in the worktree
+        branch: String,
+        /// Claim exactly this port (the first of the block) instead of the next free one
+        #[arg(long)]
+        port: Option<u16>,
+        /// Record this as the tool that created the worktree, as on `create`
+        #[arg(long, default_value = "cli")]
+        owner: String,
+    },
+
+    /// Manage artifacts of existing worktrees
+    Artifacts {
+        #[command(subcommand)]
+        action: ArtifactsAction,
+    },
+
+    /// Manage the sparse checkout of a worktree
+    Sparse {
+        #[command(subcommand)]
+        action: SparseAction,
     },
 
     /// List all managed worktrees
-    List,
+    List {
+        /// Print a JSON array instead of the table
+        #[arg(long)]
+        json: bool,
+        /// Only worktrees created longer ago than this, e.g. 7d or 12h
+        #[arg(long, value_name = "AGE", value_parser = parse_age)]
+        older_than: Option<chrono::Duration>,
+        /// Only worktrees created by this tool, e.g. cli or orchestrator
+        #[arg(long)]
+        owner: Option<String>,
+    },
 
     /// Remove a worktree and free its port
     Remove {
         /// Branch name to remove
         branch: String,
-        /// Force removal even with untracked/modified files
+        /// Force removal even with untracked/modified files or commits that
+        /// exist nowhere else
+        #[arg(short, long)]
+        force: bool,
+        /// Archive the branch to .hydra/archives first, as `archive` does
+        #[arg(long)]
+        archive: bool,
+    },
+
+    /// Save a branch's commits beyond the main branch to a bundle in .hydra/archives
+    Archive {
+        /// Branch to archive
+        branch: String,
+    },
+
+    /// Bring back a branch from an archive bundle
+    Restore {
+        /// Bundle written by `archive`, `remove --archive` or `merge --archive`
+        bundle: PathBuf,
+        /// Also create a worktree for the restored branch
+        #[arg(long)]
+        worktree: bool,
+    },
+
+    /// Drop registry entries whose worktree directory or branch is gone
+    Prune {
+        /// Show what would be removed without changing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Also remove worktrees that still exist but whose branch was deleted
+        #[arg(short, long)]
+        force: bool,
+    },
+
+    /// Drop registry entries whose worktree directory was deleted by hand
+    Clean {
+        /// Show what would be cleaned without changing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Skip confirmation prompt
+        #[arg(short, long)]
+        yes: bool,
+        /// Only worktrees created longer ago than this, e.g. 7d or 12h
+        #[arg(long, value_name = "AGE", value_parser = parse_age)]
+        older_than: Option<chrono::Duration>,
+        /// Only worktrees created by this tool, e.g. orchestrator; entries
+        /// without a recorded owner are never cleaned by owner
+        #[arg(long)]
+        owner: Option<String>,
+    },
+
+    /// Rename a worktree's branch, moving its directory and port with it
+    Rename {
+        /// Current branch name
+        old: String,
+        /// New branch name
+        new: String,
+        /// Leave the worktree directory where it is
+        #[arg(long)]
+        keep_path: bool,
+        /// Rename even with uncommitted changes or a merge in progress
         #[arg(short, long)]
         force: bool,
     },
@@ -46,15 +199,56 @@ enum Commands {
     Status {
         /// Specific branch to show (optional)
         branch: Option<String>,
+        /// Probe allocated ports and report which address families are listening
+        #[arg(long)]
+        check: bool,
+        /// Print JSON instead of text
+        #[arg(long, conflicts_with = "check")]
+        json: bool,
+    },
+
+    /// Check managed worktrees for deleted, orphaned or rewritten branches
+    Verify {
+        /// Clear the upstream setting of branches whose upstream was deleted
+        #[arg(long)]
+        fix_upstream_gone: bool,
+    },
+
+    /// Cross-check the port registry, git worktrees and directories
+    Doctor {
+        /// Free orphaned ports, prune missing worktrees, and offer to adopt unmanaged ones
+        #[arg(long)]
+        fix: bool,
+    },
+
+    /// Show what a branch changed since it left the main branch
+    Diff {
+        /// Branch to diff; it needs no worktree
+        branch: String,
+        /// Print the whole patch instead of the stat
+        #[arg(long, conflicts_with_all = ["name_only", "json", "summary"])]
+        full: bool,
+        /// Print only the changed paths
+        #[arg(long, conflicts_with_all = ["json", "summary"])]
+        name_only: bool,
+        /// Print per-file additions and deletions as JSON
+        #[arg(long, conflicts_with = "summary")]
+        json: bool,
+        /// Print per-file additions and deletions, renames and a total
+        #[arg(long)]
+        summary: bool,
     },
 
-    /// Merge a worktree branch into another
+    /// Merge worktree branches into another, one at a time
     Merge {
-        /// Source branch to merge from
-        source: String,
+        /// Source branch to merge from, then the target branch; with --into,
+        /// every branch is a source
+        #[arg(value_name = "BRANCH", required_unless_present = "continue_")]
+        branches: Vec<String>,
 
-        /// Target branch to merge into
-        target: String,
+        /// Target branch to merge every source into, in order
+        #[arg(long, value_name = "TARGET")]
+        into: Option<String>,
 
         /// Skip confirmation prompt
         #[arg(short, long)]
@@ -64,6 +258,10 @@ enum Commands {
         #[arg(long)]
         no_ff: bool,
 
+        /// Combine the source's commits into a single commit on the target
+        #[arg(long, conflicts_with = "no_ff")]
+        squash: bool,
+
         /// Preview without merging
         #[arg(long)]
         dry_run: bool,
@@ -71,241 +269,1071 @@ enum Commands {
         /// Remove source worktree after successful merge
         #[arg(long)]
         cleanup: bool,
+
+        /// With --cleanup, also delete the merged source branch
+        #[arg(long, requires = "cleanup")]
+        delete_branch: bool,
+
+        /// With --cleanup, archive each source to .hydra/archives before
+        /// removing it
+        #[arg(long, requires = "cleanup")]
+        archive: bool,
+
+        /// Run merge.check_command in each source's worktree first, and
+        /// stop at the first source whose check fails
+        #[arg(long)]
+        check: bool,
+
+        /// Message of the merge or squash commit, in place of
+        /// merge.message_template; {source}, {target} and {count} are
+        /// filled in
+        #[arg(short, long)]
+        message: Option<String>,
+
+        /// Push the target to merge.remote after each merge that lands
+        #[arg(long, conflicts_with = "no_push")]
+        push: bool,
+
+        /// Don't push the target, even with merge.push set
+        #[arg(long)]
+        no_push: bool,
+
+        /// Finish a merge that stopped at a conflict once the resolution is
+        /// committed, then merge the sources left after it
+        #[arg(
+            long = "continue",
+            conflicts_with_all = ["branches", "into", "no_ff", "squash", "dry_run", "cleanup", "check", "message", "push", "no_push"]
+        )]
+        continue_: bool,
+    },
+
+    /// Rebase worktree branches onto the main branch
+    Sync {
+        /// Branch to sync
+        #[arg(required_unless_present = "all", conflicts_with = "all")]
+        branch: Option<String>,
+        /// Sync every managed worktree
+        #[arg(long)]
+        all: bool,
+        /// Merge main into the branch instead of rebasing it
+        #[arg(long)]
+        merge: bool,
+    },
+
+    /// Run a command in every managed worktree, e.g. `hydra-wt exec -- cargo check`
+    Exec {
+        /// Only in the worktree of this branch
+        #[arg(long)]
+        branch: Option<String>,
+        /// Worktrees to run in at once
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+        parallel: u16,
+        /// Program and arguments, after `--`; run without a shell
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
     },
 
-    /// Abort an in-progress merge
+    /// Abort an in-progress merge or rebase
     MergeAbort {
-        /// Branch with in-progress merge
+        /// Branch with in-progress merge or rebase
+        branch: String,
+    },
+
+    /// Manage hydra-mail events queued while the daemon was down
+    Events {
+        #[command(subcommand)]
+        action: EventsAction,
+    },
+
+    /// Publish the worktree inventory to hydra-mail on wt:inventory
+    Publish,
+
+    /// Publish what changes in a worktree to hydra-mail on repo:delta,
+    /// every 2s in which something changed, until Ctrl-C or the worktree
+    /// is removed. With -q nothing is printed, the deltas are only emitted
+    Watch {
+        /// Branch of the worktree to watch
+        branch: String,
+    },
+}
+
+/// Shell syntax of `env` output
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum EnvFormat {
+    /// `export` lines for sh, bash and zsh
+    Sh,
+    /// `set -gx` lines for fish
+    Fish,
+    Json,
+}
+
+#[derive(Subcommand)]
+enum EventsAction {
+    /// Send the queued events to the hydra-mail daemon
+    Flush,
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print the merged config (defaults < template < local)
+    Show {
+        /// Show which layer each value came from
+        #[arg(long)]
+        origin: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ArtifactsAction {
+    /// Apply configured artifacts that are missing from existing worktrees
+    Refresh {
+        /// Worktree to refresh (all managed worktrees if omitted)
+        branch: Option<String>,
+        /// Recreate symlinks whose source no longer exists at the old path
+        #[arg(long)]
+        fix: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum SparseAction {
+    /// Check out more directories in a sparse worktree
+    Add {
+        /// Branch of the worktree
         branch: String,
+        /// Directories to add, relative to the repo root
+        #[arg(required = true)]
+        paths: Vec<String>,
     },
 }
 
 fn main() {
     let cli = Cli::parse();
+    hydra_log::init(cli.verbosity.level());
+
+    if !matches!(cli.command, Commands::Events { .. }) {
+        flush_pending_events();
+    }
 
     let result = match cli.command {
         Commands::Init => cmd_init(),
-        Commands::Create { branch } => cmd_create(&branch),
-        Commands::List => cmd_list(),
-        Commands::Remove { branch, force } => cmd_remove(&branch, force),
-        Commands::Status { branch } => cmd_status(branch.as_deref()),
+        Commands::CloneSetup => cmd_clone_setup(),
+        Commands::Config { action: ConfigAction::Show { origin } } => cmd_config_show(origin),
+        Commands::Create { branch, from, port, force, no_port, print_env, owner } => {
+            let opts = create::CreateOptions {
+                base_ref: from.as_deref(),
+                port,
+                force,
+                no_port,
+                owner: Some(&owner),
+                ..Default::default()
+            };
+            cmd_create(&branch, opts, print_env)
+        }
+        Commands::Env { branch, format } => cmd_env(&branch, format),
+        Commands::Render { branch, all, diff } => cmd_render(branch.as_deref(), all, diff),
+        Commands::Open { branch, editor } => cmd_open(&branch, editor),
+        Commands::Adopt { branch, port, owner } => cmd_adopt(&branch, port, &owner),
+        Commands::Artifacts { action: ArtifactsAction::Refresh { branch, fix } } => {
+            cmd_artifacts_refresh(branch.as_deref(), fix)
+        }
+        Commands::Sparse { action: SparseAction::Add { branch, paths } } => cmd_sparse_add(&branch, &paths),
+        Commands::List { json, older_than, owner } => cmd_list(json, older_than, owner.as_deref()),
+        Commands::Remove { branch, force, archive } => cmd_remove(&branch, force, archive),
+        Commands::Archive { branch } => cmd_archive(&branch),
+        Commands::Restore { bundle, worktree } => cmd_restore(&bundle, worktree),
+        Commands::Prune { dry_run, force } => cmd_prune(dry_run, force),
+        Commands::Clean { dry_run, yes, older_than, owner } => cmd_clean(dry_run, yes, older_than, owner.as_deref()),
+        Commands::Rename {
+            old,
+            new,
+            keep_path,
+            force,
+        } => cmd_rename(&old, &new, keep_path, force),
+        Commands::Status { branch, check, json } => cmd_status(branch.as_deref(), check, json),
+        Commands::Verify { fix_upstream_gone } => cmd_verify(fix_upstream_gone),
+        Commands::Doctor { fix } => cmd_doctor(fix),
+        Commands::Diff { branch, full, name_only, json, summary } => {
+            cmd_diff(&branch, full, name_only, json, summary)
+        }
+        Commands::Merge { continue_: true, .. } => cmd_merge_continue(),
         Commands::Merge {
-            source,
-            target,
+            branches,
+            into,
             force,
             no_ff,
+            squash,
             dry_run,
             cleanup,
-        } => cmd_merge(&source, &target, force, no_ff, dry_run, cleanup),
+            delete_branch,
+            archive,
+            check,
+            message,
+            push,
+            no_push,
+            continue_: false,
+        } => {
+            let mode = if squash {
+                worktree::MergeMode::Squash
+            } else if no_ff {
+                worktree::MergeMode::NoFastForward
+            } else {
+                worktree::MergeMode::FastForward
+            };
+            merge_branches(branches, into)
+                .and_then(|(sources, target)| {
+                    let cleanup = MergeCleanup { remove: cleanup, delete_branch, archive };
+                    // Neither flag leaves it to merge.push
+                    let push = (push || no_push).then_some(push);
+                    cmd_merge(&sources, &target, force, MergeStrategy { mode, message, push }, dry_run, cleanup, check)
+                })
+        }
+        Commands::Sync { branch, all, merge } => cmd_sync(branch.as_deref(), all, merge),
+        Commands::Exec { branch, parallel, command } => cmd_exec(branch.as_deref(), usize::from(parallel), &command),
         Commands::MergeAbort { branch } => cmd_merge_abort(&branch),
+        Commands::Events { action: EventsAction::Flush } => cmd_events_flush(),
+        Commands::Publish => cmd_publish(),
+        Commands::Watch { branch } => cmd_watch(&branch, cli.verbosity.quiet),
     };
 
     if let Err(e) = result {
-        eprintln!("Error: {}", e);
+        error!("{}", e);
         std::process::exit(1);
     }
 }
 
-fn cmd_init() -> Result<()> {
-    config::WtConfig::init()?;
-    ports::PortRegistry::init()?;
-    println!("hydra-wt initialized successfully");
-    Ok(())
+/// Replay events queued while the hydra-mail daemon was down, if it is back.
+/// Never fails the command that is about to run.
+fn flush_pending_events() {
+    let Ok(repo_root) = config::get_repo_root() else { return };
+    if !hydra::pending_path(&repo_root).exists() || !hydra::daemon_reachable(&repo_root) {
+        return;
+    }
+    let Ok(cfg) = config::WtConfig::load() else { return };
+    match hydra::flush(&cfg, &repo_root) {
+        Ok(outcome) if outcome.sent > 0 => info!("Sent {} queued hydra-mail event(s)", outcome.sent),
+        Ok(_) => {}
+        Err(e) => warn!("failed to send queued hydra-mail events: {:#}", e),
+    }
 }
 
-fn cmd_create(branch: &str) -> Result<()> {
+fn cmd_events_flush() -> Result<()> {
     let cfg = config::WtConfig::load()?;
-    let mut registry = ports::PortRegistry::load()?;
+    let repo_root = config::get_repo_root()?;
 
-    // Check if worktree already exists
-    let wt_path = cfg.worktree_path(branch);
-    if worktree::exists(&wt_path) {
-        anyhow::bail!("Worktree already exists at {}", wt_path.display());
+    if !cfg.hydra.enabled {
+        println!("hydra-mail events are disabled (hydra.enabled = false)");
+        return Ok(());
+    }
+    if !hydra::pending_path(&repo_root).exists() {
+        println!("No queued events");
+        return Ok(());
     }
 
-    // Allocate port
-    let port = registry.allocate(branch, cfg.ports.range_start, cfg.ports.range_end)?;
-    println!("Allocated port {} for {}", port, branch);
-
-    // Create worktree
-    println!("Creating worktree at {}...", wt_path.display());
-    if let Err(e) = worktree::add(&wt_path, branch) {
-        // Rollback port allocation on failure
-        registry.allocations.remove(branch);
-        registry.save()?;
-        return Err(e);
+    let outcome = hydra::flush(&cfg, &repo_root)?;
+    println!("Sent {} queued event(s)", outcome.sent);
+    if outcome.remaining > 0 {
+        anyhow::bail!("{} event(s) still queued; the daemon stopped responding", outcome.remaining);
     }
+    Ok(())
+}
+
+fn cmd_publish() -> Result<()> {
+    let cfg = config::WtConfig::load()?;
+    let repo_root = config::get_repo_root()?;
 
-    // Save port allocation
-    registry.save()?;
+    if !cfg.hydra.enabled {
+        println!("hydra-mail events are disabled (hydra.enabled = false)");
+        return Ok(());
+    }
+    let event = hydra::emit_inventory(&cfg, &repo_root)?;
+    let what = if event.truncated {
+        format!("{} of {} worktree(s), truncated to fit a message", event.worktrees.len(), event.total)
+    } else {
+        format!("{} worktree(s)", event.total)
+    };
+    if hydra::daemon_reachable(&repo_root) {
+        println!("Published inventory of {} on wt:inventory", what);
+    } else {
+        println!("Queued inventory of {}; the hydra-mail daemon is not running", what);
+    }
+    Ok(())
+}
 
-    // Handle artifacts
+fn cmd_watch(branch: &str, quiet: bool) -> Result<()> {
+    let cfg = config::WtConfig::load()?.resolve_for(branch)?;
     let repo_root = config::get_repo_root()?;
-    if !cfg.artifacts.symlink.is_empty() || !cfg.artifacts.copy.is_empty() {
-        println!("Setting up artifacts...");
+    let registry = ports::PortRegistry::load_at(&repo_root)?;
+    if !registry.is_managed(branch) {
+        anyhow::bail!("No worktree for '{}' is managed by hydra-wt; create one with: hydra-wt create {}", branch, branch);
+    }
+    let wt_path = registry.worktree_path(&cfg, &repo_root, branch);
+    if !worktree::exists(&wt_path) {
+        anyhow::bail!("Worktree of '{}' missing at {}", branch, wt_path.display());
     }
-    for artifact in &cfg.artifacts.symlink {
-        artifacts::symlink_artifact(&repo_root, &wt_path, artifact)?;
+    if !cfg.hydra.enabled {
+        warn!("hydra-mail events are disabled (hydra.enabled = false); changes are only printed");
     }
-    for artifact in &cfg.artifacts.copy {
-        artifacts::copy_artifact(&repo_root, &wt_path, artifact)?;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let flag = stop.clone();
+    ctrlc::set_handler(move || flag.store(true, Ordering::SeqCst))?;
+
+    info!("Watching {} for changes (Ctrl-C to stop)", wt_path.display());
+    let end = watch::watch(&wt_path, watch::BATCH_INTERVAL, &stop, |delta| {
+        hydra::emit_worktree_delta(&cfg, &repo_root, branch, delta)?;
+        if !quiet {
+            let shown = delta.paths.iter().take(5).cloned().collect::<Vec<_>>().join(", ");
+            let more = delta.paths.len().saturating_sub(5);
+            println!(
+                "{} file(s) changed, +{} -{}: {}{}",
+                delta.paths.len(),
+                delta.added,
+                delta.removed,
+                shown,
+                if more > 0 { format!(" and {} more", more) } else { String::new() }
+            );
+        }
+        Ok(())
+    })?;
+    match end {
+        watch::WatchEnd::Stopped => info!("Stopped watching '{}'", branch),
+        watch::WatchEnd::WorktreeRemoved => info!("Worktree of '{}' was removed; stopped watching", branch),
     }
+    Ok(())
+}
+
+fn cmd_init() -> Result<()> {
+    config::WtConfig::init()?;
+    ports::PortRegistry::init()?;
+    println!("hydra-wt initialized successfully");
+    Ok(())
+}
+
+fn cmd_clone_setup() -> Result<()> {
+    config::WtConfig::clone_setup_at(&config::project_root()?)?;
+    ports::PortRegistry::init()?;
+    println!("hydra-wt configured from template");
+    Ok(())
+}
 
-    // Render template if exists
-    let template_path = PathBuf::from(&cfg.env.template);
-    let output_path = wt_path.join(&cfg.env.output);
+fn cmd_config_show(origin: bool) -> Result<()> {
+    let layered = config::WtConfig::load_layered_at(&config::project_root()?)?;
 
-    let project_uuid = config::get_project_uuid().unwrap_or_else(|_| "unknown".to_string());
-    let repo_root = config::get_repo_root()
-        .map(|p| p.to_string_lossy().to_string())
-        .unwrap_or_else(|_| ".".to_string());
+    if !origin {
+        print!("{}", toml::to_string_pretty(&layered.config)?);
+        return Ok(());
+    }
 
-    let ctx = template::TemplateContext {
-        port,
-        worktree: branch.to_string(),
-        project_uuid,
-        repo_root,
+    let describe = |path: &Option<PathBuf>| {
+        path.as_ref().map(|p| p.display().to_string()).unwrap_or_else(|| "(none)".to_string())
     };
+    println!("# template: {}", describe(&layered.template_path));
+    println!("# local:    {}", describe(&layered.local_path));
 
-    template::render(&template_path, &output_path, &ctx)?;
+    let values = toml::Table::try_from(&layered.config)?;
+    for (key, layer) in &layered.origins {
+        if let Some(value) = lookup(&values, key) {
+            println!("{:<28} = {:<24} # {}", key, value.to_string(), layer);
+        }
+    }
+    Ok(())
+}
 
-    if template_path.exists() {
-        println!("Created {}", output_path.display());
+/// Value at a dotted key path
+fn lookup<'a>(table: &'a toml::Table, key: &str) -> Option<&'a toml::Value> {
+    let mut parts = key.split('.');
+    let mut current = table.get(parts.next()?)?;
+    for part in parts {
+        current = current.as_table()?.get(part)?;
     }
+    Some(current)
+}
+
+fn cmd_create(branch: &str, opts: create::CreateOptions, print_env: bool) -> Result<()> {
+    let cfg = config::WtConfig::load()?.resolve_for(branch)?;
+    let repo_root = config::get_repo_root()?;
 
-    // Run post-create hooks
-    hooks::run_post_create(&wt_path, &cfg.hooks.post_create)?;
+    // Ctrl+C also reaches git (same process group) and is passed on to a
+    // running hook; either way the next event fails and the create rolls
+    // back before we exit
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let flag = interrupted.clone();
+    ctrlc::set_handler(move || {
+        flag.store(true, Ordering::SeqCst);
+        // Hooks run in a process group of their own, out of its reach
+        hooks::interrupt();
+    })?;
+
+    let has_artifacts = !cfg.artifacts.symlink.is_empty()
+        || !cfg.artifacts.copy.is_empty()
+        || !cfg.artifacts.hardlink.is_empty();
+    let mut progress = ProgressLine::new();
+    let result = create::create_opts_with(&cfg, &repo_root, branch, opts, |event| {
+        if interrupted.load(Ordering::SeqCst) {
+            progress.finish();
+            anyhow::bail!("Interrupted");
+        }
+        match event {
+            CreateEvent::PhaseStarted { phase: CreatePhase::WorktreeAdd, estimate } => {
+                let usually = estimate
+                    .map(|e| format!(" (checkout usually takes {} here)", metrics::approx(e)))
+                    .unwrap_or_default();
+                info!("Creating worktree at {}...{}", cfg.worktree_path_in(&repo_root, branch).display(), usually);
+            }
+            CreateEvent::PhaseStarted { phase: CreatePhase::Artifacts, .. } if has_artifacts => {
+                info!("Setting up artifacts...");
+            }
+            CreateEvent::Checkout { progress: p, elapsed } => {
+                let left = metrics::remaining(elapsed, p.done, p.total)
+                    .map(|d| format!(", {} left", metrics::approx(d)))
+                    .unwrap_or_default();
+                progress.show(&format!("  Checking out files: {}% ({}/{}){}", p.percent(), p.done, p.total, left));
+            }
+            CreateEvent::PhaseFinished { phase: CreatePhase::WorktreeAdd, .. } => progress.finish(),
+            CreateEvent::PhaseFinished { phase: CreatePhase::Templates, .. } => {
+                // Without a port, templates that only set ports are skipped
+                for (_, output) in cfg.env.pairs() {
+                    let output = cfg.worktree_path_in(&repo_root, branch).join(output);
+                    if output.exists() {
+                        info!("Created {}", output.display());
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    });
+
+    let outcome = match result {
+        Ok(outcome) => outcome,
+        Err(e) => {
+            match e.downcast_ref() {
+                Some(hydra_wt::WtError::BranchCheckedOut { path, .. }) => error!(
+                    "'{}' is already checked out at {} — use `hydra-wt open {}` or pick another branch",
+                    branch, path.display(), branch
+                ),
+                _ => error!("{:#}", e),
+            }
+            std::process::exit(if interrupted.load(Ordering::SeqCst) { 130 } else { 1 });
+        }
+    };
 
-    // Emit to Hydra
-    hydra::emit_worktree_created(branch, port, &wt_path.to_string_lossy())?;
+    let phases: Vec<String> = outcome
+        .timings
+        .iter()
+        .map(|(phase, elapsed)| format!("{} {:.1}s", phase.name(), elapsed.as_secs_f64()))
+        .collect();
+    info!("Took {:.1}s ({})", outcome.total().as_secs_f64(), phases.join(", "));
+
+    // Nothing else goes to stdout, so it can be eval'd
+    if print_env {
+        info!("Worktree '{}' created at {}", branch, outcome.path.display());
+        let vars = exports::for_worktree(&cfg, &repo_root, branch)?;
+        print!("{}", exports::render(&vars, exports::ShellFormat::Posix));
+        return Ok(());
+    }
 
     println!("\nWorktree '{}' created successfully", branch);
-    println!("  Path: {}", wt_path.display());
-    println!("  Port: {}", port);
+    println!("  Path: {}", outcome.path.display());
+    match outcome.allocated {
+        Some(ports) => println!("  Port: {}", ports),
+        None => println!("  Port: none"),
+    }
+    if !cfg.artifacts.hardlink.is_empty() {
+        let LinkStats { linked, copied } = outcome.hardlinked;
+        println!("  Hardlinked artifacts: {} file(s) linked, {} copied", linked, copied);
+    }
 
     Ok(())
 }
 
-fn cmd_list() -> Result<()> {
-    let cfg = config::WtConfig::load()?;
-    let registry = ports::PortRegistry::load()?;
+fn cmd_env(branch: &str, format: EnvFormat) -> Result<()> {
+    let cfg = config::WtConfig::load()?.resolve_for(branch)?;
+    let repo_root = config::get_repo_root()?;
+    let vars = exports::for_worktree(&cfg, &repo_root, branch)?;
+    let format = match format {
+        EnvFormat::Sh => exports::ShellFormat::Posix,
+        EnvFormat::Fish => exports::ShellFormat::Fish,
+        EnvFormat::Json => exports::ShellFormat::Json,
+    };
+    print!("{}", exports::render(&vars, format));
+    Ok(())
+}
 
-    if registry.allocations.is_empty() {
+fn cmd_render(branch: Option<&str>, all: bool, diff: bool) -> Result<()> {
+    let repo_root = config::get_repo_root()?;
+    let registry = ports::PortRegistry::load_at(&repo_root)?;
+    let branches: Vec<String> = if all {
+        registry.branches().into_iter().cloned().collect()
+    } else {
+        let branch = branch.unwrap_or_default();
+        if !registry.is_managed(branch) {
+            anyhow::bail!("No worktree for '{}' is managed by hydra-wt; create one with: hydra-wt create {}", branch, branch);
+        }
+        vec![branch.to_string()]
+    };
+    if branches.is_empty() {
         println!("No worktrees managed by hydra-wt");
         return Ok(());
     }
 
-    // Detect main branch
-    let main_branch = detect_main_branch();
-
-    println!(
-        "{:<20} {:<6} {:<25} {:<10} {:<20}",
-        "BRANCH", "PORT", "PATH", "STATUS", "COMMITS AHEAD"
-    );
-    println!("{}", "-".repeat(85));
-
-    for (branch, port) in registry.list() {
-        let wt_path = cfg.worktree_path(branch);
-        let status = if worktree::exists(&wt_path) {
-            "exists"
-        } else {
-            "missing"
-        };
-
-        // Get commits ahead of main (if not the main branch itself)
-        let commits_info = if *branch != main_branch {
-            match worktree::commits_ahead(branch, &main_branch) {
-                Ok(commits) if commits.is_empty() => "up to date".to_string(),
-                Ok(commits) => {
-                    // Check if can merge without conflicts
-                    let can_merge_status = if worktree::exists(&wt_path) {
-                        match worktree::can_merge(&wt_path, &main_branch) {
-                            Ok(true) => "",
-                            Ok(false) => " (conflicts)",
-                            Err(_) => "",
-                        }
-                    } else {
-                        ""
-                    };
-                    format!("{}{}", commits.len(), can_merge_status)
+    let mut problems = 0;
+    for branch in &branches {
+        let cfg = config::WtConfig::load()?.resolve_for(branch)?;
+        let wt_path = registry.worktree_path(&cfg, &repo_root, branch);
+        if !worktree::exists(&wt_path) {
+            println!("✗ {}: worktree missing at {}", branch, wt_path.display());
+            problems += 1;
+            continue;
+        }
+        // Ports stay as registered; only the templates and config changed
+        let ctx = template::TemplateContext::for_worktree(&cfg, &repo_root, branch, registry.block(branch))?;
+        let rerenders = template::rerender_all(&cfg.env, &repo_root, &wt_path, &ctx)?;
+        let changed: Vec<_> = rerenders.iter().filter(|r| r.is_changed()).collect();
+        if changed.is_empty() {
+            println!("✓ {}: up to date", branch);
+            continue;
+        }
+        for rerender in changed {
+            let output = rerender.output.strip_prefix(&wt_path).unwrap_or(&rerender.output);
+            if diff {
+                println!("{}: {}", branch, output.display());
+                for line in rerender.diff() {
+                    println!("  {}", line);
                 }
-                Err(_) => "-".to_string(),
+                continue;
             }
-        } else {
-            "-".to_string()
-        };
+            match rerender.write()? {
+                Some(backup) => println!(
+                    "✓ {}: re-rendered {}, previous kept in {}",
+                    branch,
+                    output.display(),
+                    backup.file_name().unwrap_or_default().to_string_lossy()
+                ),
+                None => println!("✓ {}: rendered {}", branch, output.display()),
+            }
+        }
+    }
 
-        println!(
-            "{:<20} {:<6} {:<25} {:<10} {:<20}",
+    if problems > 0 {
+        anyhow::bail!("{} worktree(s) could not be rendered", problems);
+    }
+    Ok(())
+}
+
+fn cmd_open(branch: &str, editor: bool) -> Result<()> {
+    let cfg = config::WtConfig::load()?.resolve_for(branch)?;
+    let repo_root = config::get_repo_root()?;
+    let registry = ports::PortRegistry::load_at(&repo_root)?;
+    if !registry.is_managed(branch) {
+        anyhow::bail!("No worktree for '{}' is managed by hydra-wt; create one with: hydra-wt create {}", branch, branch);
+    }
+    let wt_path = registry.worktree_path(&cfg, &repo_root, branch);
+    if !worktree::exists(&wt_path) {
+        anyhow::bail!(
+            "Worktree of '{}' missing at {}; create it with: hydra-wt create {}",
             branch,
-            port,
             wt_path.display(),
-            status,
-            commits_info
+            branch
         );
     }
+    let vars = exports::for_worktree(&cfg, &repo_root, branch)?;
 
-    Ok(())
+    if editor {
+        let Some(command) = cfg.open.editor_command() else {
+            anyhow::bail!("No editor configured; set open.editor in hydra-wt.toml, e.g. editor = \"code\"");
+        };
+        return open::editor(&command, &wt_path, &vars);
+    }
+    if open::in_tmux() {
+        if open::tmux_window(branch, &wt_path, &vars)? {
+            println!("Opened tmux window '{}' in {}", branch, wt_path.display());
+        } else {
+            println!("Switched to tmux window '{}'", branch);
+        }
+        return Ok(());
+    }
+    info!("Starting a shell in {} (exit it to come back)", wt_path.display());
+    open::shell(&wt_path, &vars)
 }
 
-fn detect_main_branch() -> String {
-    // Try common main branch names
-    for branch in &["main", "master"] {
-        if worktree::branch_exists(branch).unwrap_or(false) {
-            return branch.to_string();
+fn cmd_adopt(branch: &str, port: Option<u16>, owner: &str) -> Result<()> {
+    let cfg = config::WtConfig::load()?.resolve_for(branch)?;
+    let repo_root = config::get_repo_root()?;
+
+    let has_artifacts = !cfg.artifacts.symlink.is_empty()
+        || !cfg.artifacts.copy.is_empty()
+        || !cfg.artifacts.hardlink.is_empty();
+    let outcome = create::adopt_as(&cfg, &repo_root, branch, port, Some(owner), |event| {
+        if let CreateEvent::PhaseStarted { phase: CreatePhase::Artifacts, .. } = event {
+            if has_artifacts {
+                info!("Setting up artifacts...");
+            }
         }
+        Ok(())
+    })?;
+
+    println!("Worktree '{}' adopted", branch);
+    println!("  Path: {}", outcome.path.display());
+    if let Some(ports) = outcome.allocated {
+        println!("  Port: {}", ports);
+    }
+    if !cfg.artifacts.hardlink.is_empty() {
+        let LinkStats { linked, copied } = outcome.hardlinked;
+        println!("  Hardlinked artifacts: {} file(s) linked, {} copied", linked, copied);
     }
-    // Fallback
-    "main".to_string()
-}
 
-fn cmd_remove(branch: &str, force: bool) -> Result<()> {
-    let cfg = config::WtConfig::load()?;
-    let mut registry = ports::PortRegistry::load()?;
+    Ok(())
+}
 
-    let wt_path = cfg.worktree_path(branch);
+/// A status line redrawn in place on an interactive stderr; silent otherwise
+/// or when `-q` hides progress
+struct ProgressLine {
+    enabled: bool,
+    shown: bool,
+}
 
-    // Remove worktree
-    if worktree::exists(&wt_path) {
-        println!("Removing worktree at {}...", wt_path.display());
-        worktree::remove(&wt_path, force)?;
-    } else {
-        println!("Worktree not found at {}, cleaning up registry...", wt_path.display());
+impl ProgressLine {
+    fn new() -> Self {
+        let enabled = std::io::stderr().is_terminal() && tracing::enabled!(tracing::Level::INFO);
+        Self { enabled, shown: false }
     }
 
-    // Free port
-    match registry.free(branch) {
-        Ok(port) => {
-            println!("Freed port {}", port);
-            registry.save()?;
-        }
-        Err(_) => {
-            println!("No port allocation found for {}", branch);
+    fn show(&mut self, line: &str) {
+        if self.enabled {
+            let mut stderr = std::io::stderr().lock();
+            let _ = write!(stderr, "\r\x1b[2K{}", line);
+            let _ = stderr.flush();
+            self.shown = true;
         }
     }
 
-    // Emit to Hydra
-    hydra::emit_worktree_removed(branch)?;
-
-    println!("Worktree '{}' removed", branch);
-
-    Ok(())
+    fn finish(&mut self) {
+        if self.shown {
+            eprintln!();
+            self.shown = false;
+        }
+    }
 }
 
-fn cmd_status(branch: Option<&str>) -> Result<()> {
+fn cmd_artifacts_refresh(branch: Option<&str>, fix: bool) -> Result<()> {
     let cfg = config::WtConfig::load()?;
-    let registry = ports::PortRegistry::load()?;
+    let repo_root = config::get_repo_root()?;
+
+    let artifacts = &cfg.artifacts;
+    if artifacts.symlink.is_empty() && artifacts.copy.is_empty() && artifacts.hardlink.is_empty() {
+        println!("No artifacts configured");
+        return Ok(());
+    }
+
+    let registry = ports::PortRegistry::load_at(&repo_root)?;
+    let all = branch.is_none();
+    let branches: Vec<String> = match branch {
+        Some(branch) => vec![branch.to_string()],
+        None => registry.branches().into_iter().cloned().collect(),
+    };
+    if branches.is_empty() {
+        println!("No worktrees managed by hydra-wt");
+        return Ok(());
+    }
+
+    let mut broken = 0;
+    for branch in &branches {
+        let wt_path = match worktree::get_worktree_path_in(&repo_root, branch)? {
+            Some(path) => path,
+            None if all => {
+                println!("{}: worktree missing at {}", branch, registry.worktree_path(&cfg, &repo_root, branch).display());
+                continue;
+            }
+            None => anyhow::bail!("Branch '{}' is not checked out in any worktree", branch),
+        };
+
+        let report = artifacts::refresh(artifacts, &repo_root, &wt_path, fix)?;
+        let counts: Vec<String> = [RefreshAction::Created, RefreshAction::Skipped, RefreshAction::Repaired]
+            .iter()
+            .map(|action| format!("{} {}", report.count(*action), action.describe()))
+            .collect();
+        println!("{}: {}", branch, counts.join(", "));
+        for entry in report.entries.iter().filter(|e| e.action != RefreshAction::Skipped) {
+            println!("  {:<15} {} {}", entry.action.describe(), entry.kind.name(), entry.artifact);
+        }
+        broken += report.count(RefreshAction::Broken);
+    }
+
+    if broken > 0 && !fix {
+        println!("    Pass --fix to recreate broken symlinks");
+    }
+    Ok(())
+}
+
+fn cmd_sparse_add(branch: &str, paths: &[String]) -> Result<()> {
+    let cfg = config::WtConfig::load()?;
+    let repo_root = config::get_repo_root()?;
+    let registry = ports::PortRegistry::load_at(&repo_root)?;
+    let wt_path = registry.worktree_path(&cfg, &repo_root, branch);
+    if !worktree::exists(&wt_path) {
+        anyhow::bail!("No worktree for '{}' at {}", branch, wt_path.display());
+    }
+    if worktree::sparse_paths_in(&wt_path)?.is_none() {
+        anyhow::bail!("'{}' is not a sparse worktree; it already checks out everything", branch);
+    }
+
+    info!("Adding {} to the sparse checkout of '{}'...", paths.join(", "), branch);
+    worktree::sparse_add_in(&wt_path, paths)?;
+    let paths = worktree::sparse_paths_in(&wt_path)?.unwrap_or_default();
+    println!("'{}' now checks out: {}", branch, paths.join(", "));
+    Ok(())
+}
+
+fn cmd_list(json: bool, older_than: Option<chrono::Duration>, owner: Option<&str>) -> Result<()> {
+    let cfg = config::WtConfig::load()?;
+    let repo_root = config::get_repo_root()?;
+    let registry = ports::PortRegistry::load_at(&repo_root)?;
+    let mut statuses = worktree::collect_status(&cfg, &repo_root, &registry)?;
+    let now = chrono::Utc::now();
+    if let Some(older_than) = older_than {
+        statuses.retain(|st| age::is_older(st.created_at, older_than, now));
+    }
+    if let Some(owner) = owner {
+        statuses.retain(|st| ports::owner_matches(&st.owner, owner));
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&statuses)?);
+        return Ok(());
+    }
+
+    if statuses.is_empty() {
+        match (older_than, owner) {
+            (Some(older_than), _) => println!("No worktrees older than {}", age::describe(older_than)),
+            (None, Some(owner)) => println!("No worktrees created by {}", owner),
+            (None, None) => println!("No worktrees managed by hydra-wt"),
+        }
+        return Ok(());
+    }
+
+    println!(
+        "{:<20} {:<11} {:<5} {:<13} {:<25} {:<17} {:<20} {:<7}",
+        "BRANCH", "PORT", "AGE", "OWNER", "PATH", "STATUS", "COMMITS AHEAD", "BEHIND"
+    );
+    println!("{}", "-".repeat(125));
+
+    for st in &statuses {
+        let ports = registry.block(&st.branch).map(|b| b.to_string()).unwrap_or_else(|| "-".to_string());
+        let mut status = if !st.exists {
+            "missing".to_string()
+        } else if st.issues.is_empty() {
+            "exists".to_string()
+        } else {
+            st.issues.join(",")
+        };
+        if st.locked.is_some() {
+            status.push_str(" LOCKED");
+        }
+        let commits_info = match st.commits_ahead {
+            None => "-".to_string(),
+            Some(0) => "up to date".to_string(),
+            Some(n) if st.conflicts => format!("{} ({} conflicts)", n, st.conflict_files.len()),
+            Some(n) => n.to_string(),
+        };
+        let behind = st.commits_behind.map(|n| n.to_string()).unwrap_or_else(|| "-".to_string());
+        let age = st.created_at.map(|at| age::describe(now - at)).unwrap_or_else(|| "-".to_string());
+
+        // The tool, without the session of an `orchestrator:<id>` owner
+        let owner = st.owner.split(':').next().unwrap_or_default();
+
+        println!(
+            "{:<20} {:<11} {:<5} {:<13} {:<25} {:<17} {:<20} {:<7}",
+            st.branch,
+            ports,
+            age,
+            owner,
+            config::display_path(&st.path).display(),
+            status,
+            commits_info,
+            behind
+        );
+        if st.conflicts {
+            println!("    conflicts with main in: {}", first_paths(&st.conflict_files));
+        }
+        match st.locked.as_deref() {
+            Some("") | None => {}
+            Some(reason) => println!("    locked: {}", reason),
+        }
+    }
+
+    Ok(())
+}
+
+fn cmd_remove(branch: &str, force: bool, archive: bool) -> Result<()> {
+    let cfg = config::WtConfig::load()?.resolve_for(branch)?;
+    let repo_root = config::get_repo_root()?;
+    let mut registry = ports::PortRegistry::load_at(&repo_root)?;
+
+    let wt_path = registry.worktree_path(&cfg, &repo_root, branch);
+
+    // A locked worktree is one someone asked git to keep, e.g. on a drive
+    // that isn't always mounted
+    let locked = worktree::list_in(&repo_root)?
+        .into_iter()
+        .find(|wt| Path::new(&wt.path) == wt_path)
+        .and_then(|wt| wt.locked);
+    if let Some(reason) = &locked {
+        let why = if reason.is_empty() { String::new() } else { format!(" ({})", reason) };
+        if !force {
+            anyhow::bail!(
+                "'{}' is locked{}; unlock it with `git worktree unlock {}`, or pass --force to remove it anyway",
+                branch,
+                why,
+                wt_path.display()
+            );
+        }
+        warn!("removing locked worktree '{}'{}", branch, why);
+    }
+
+    let main = worktree::detect_main_branch_in(&repo_root);
+    if branch != main && worktree::branch_exists_in(&repo_root, &format!("refs/heads/{}", branch))? {
+        let unique = worktree::unique_commits_in(&repo_root, branch, &main)?;
+        if archive {
+            print_archived(&archive::create(&cfg, &repo_root, branch)?);
+        } else if !unique.is_empty() {
+            let what = match worktree::upstream_in(&repo_root, branch)? {
+                Some(upstream) => format!("not on {} or {}", main, upstream),
+                None => format!("not on {} and never pushed", main),
+            };
+            if !force {
+                println!("'{}' has {} commit(s) {}:", branch, unique.len(), what);
+                for commit in &unique {
+                    println!("  {} {}", &commit.hash[..7.min(commit.hash.len())], commit.message);
+                }
+                anyhow::bail!(
+                    "Refusing to remove '{}'; pass --archive to keep a bundle of it, or --force to remove it anyway",
+                    branch
+                );
+            }
+            warn!("removing '{}' with {} commit(s) {}", branch, unique.len(), what);
+        }
+    }
+
+    // Remove worktree
+    if worktree::exists(&wt_path) {
+        info!("Removing worktree at {}...", wt_path.display());
+        if locked.is_some() {
+            // Only a second --force gets git past the lock
+            worktree::remove_in(&repo_root, &wt_path)?;
+        } else {
+            worktree::remove(&wt_path, force)?;
+        }
+        worktree::remove_empty_parents(&wt_path, &cfg.worktree_dir_in(&repo_root));
+    } else {
+        info!("Worktree not found at {}, cleaning up registry...", wt_path.display());
+    }
+
+    // Free port
+    if registry.is_managed(branch) {
+        if let Some(port) = registry.unregister(branch) {
+            info!("Freed port {}", port);
+        }
+        registry.save_at(&repo_root)?;
+    } else {
+        warn!("no port allocation found for {}", branch);
+    }
+
+    // Emit to Hydra
+    hydra::emit_worktree_removed(&cfg, &repo_root, branch)?;
+    hydra::emit_inventory(&cfg, &repo_root)?;
+
+    println!("Worktree '{}' removed", branch);
+
+    Ok(())
+}
+
+fn cmd_archive(branch: &str) -> Result<()> {
+    let cfg = config::WtConfig::load()?;
+    let repo_root = config::get_repo_root()?;
+    print_archived(&archive::create(&cfg, &repo_root, branch)?);
+    Ok(())
+}
+
+fn print_archived(archive: &archive::Archive) {
+    println!("Archived '{}' ({}) to {}", archive.info.branch, &archive.info.head[..7.min(archive.info.head.len())], archive.bundle.display());
+    println!("    Restore with: hydra-wt restore {}", archive.bundle.display());
+    if !archive.pruned.is_empty() {
+        println!("    Pruned {} older archive(s)", archive.pruned.len());
+    }
+}
+
+fn cmd_restore(bundle: &Path, with_worktree: bool) -> Result<()> {
+    let repo_root = config::get_repo_root()?;
+    // A bare file name is looked up in the archives directory
+    let in_archives = archive::dir(&repo_root).join(bundle);
+    let bundle = if !bundle.exists() && in_archives.exists() { in_archives.as_path() } else { bundle };
+
+    let restored = archive::restore(&repo_root, bundle)?;
+    println!("Restored branch '{}' at {}", restored.branch, &restored.head[..7.min(restored.head.len())]);
+    if with_worktree {
+        cmd_create(&restored.branch, create::CreateOptions::default(), false)
+    } else {
+        println!("    Create its worktree with: hydra-wt create {}", restored.branch);
+        Ok(())
+    }
+}
+
+fn cmd_prune(dry_run: bool, force: bool) -> Result<()> {
+    let cfg = config::WtConfig::load()?;
+    let repo_root = config::get_repo_root()?;
+
+    let plan = prune::plan(&cfg, &repo_root, force)?;
+    if plan.is_empty() {
+        println!("Nothing to prune");
+        return Ok(());
+    }
+
+    if !dry_run {
+        prune::apply(&cfg, &repo_root, &plan)?;
+    }
+
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    for entry in &plan.remove {
+        println!("{} '{}' ({}): {}", verb, entry.branch, stale_ports(entry), entry.reason.describe(&entry.path));
+    }
+    for entry in &plan.skipped {
+        println!("Kept '{}' ({}): {}", entry.branch, stale_ports(entry), entry.reason.describe(&entry.path));
+    }
+    if !plan.skipped.is_empty() {
+        println!("    Pass --force to remove these worktrees too");
+    }
+
+    if !dry_run {
+        for entry in &plan.remove {
+            hydra::emit_worktree_removed(&cfg, &repo_root, &entry.branch)?;
+        }
+        hydra::emit_inventory(&cfg, &repo_root)?;
+    }
+    Ok(())
+}
+
+fn cmd_clean(dry_run: bool, yes: bool, older_than: Option<chrono::Duration>, owner: Option<&str>) -> Result<()> {
+    let cfg = config::WtConfig::load()?;
+    let repo_root = config::get_repo_root()?;
+    if owner == Some(ports::UNKNOWN_OWNER) {
+        anyhow::bail!("Entries without a recorded owner are never cleaned by owner; run clean without --owner");
+    }
+
+    let mut plan = prune::plan_missing(&cfg, &repo_root)?;
+    let registry = ports::PortRegistry::load_at(&repo_root)?;
+    if let Some(older_than) = older_than {
+        let now = chrono::Utc::now();
+        plan.remove.retain(|entry| age::is_older(registry.created_at(&entry.branch), older_than, now));
+    }
+    if let Some(owner) = owner {
+        plan.remove.retain(|entry| registry.is_owned_by(&entry.branch, owner));
+    }
+    if plan.is_empty() {
+        println!("Nothing to clean");
+        return Ok(());
+    }
+
+    let verb = if dry_run { "Would clean" } else { "Missing" };
+    for entry in &plan.remove {
+        println!("{} '{}' ({}): {}", verb, entry.branch, stale_ports(entry), entry.reason.describe(&entry.path));
+    }
+    if dry_run {
+        return Ok(());
+    }
+    let entries = if plan.remove.len() == 1 { "entry" } else { "entries" };
+
+    // An unmounted disk or a stale network share makes every worktree look
+    // deleted, so ask before dropping their entries
+    if !yes {
+        if plan.remove.len() == registry.branches().len() {
+            warn!("every managed worktree is missing; check that their filesystem is mounted");
+        }
+        print!("Drop {} registry {} and free their ports? [y/N] ", plan.remove.len(), entries);
+        std::io::Write::flush(&mut std::io::stdout())?;
+
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+        if !input.trim().eq_ignore_ascii_case("y") {
+            println!("Clean cancelled.");
+            return Ok(());
+        }
+    }
+
+    prune::apply(&cfg, &repo_root, &plan)?;
+    for entry in &plan.remove {
+        hydra::emit_worktree_removed(&cfg, &repo_root, &entry.branch)?;
+    }
+    hydra::emit_inventory(&cfg, &repo_root)?;
+    println!("Cleaned {} registry {}", plan.remove.len(), entries);
+    Ok(())
+}
+
+/// `--older-than` value
+fn parse_age(s: &str) -> Result<chrono::Duration, String> {
+    age::parse(s).map_err(|e| e.to_string())
+}
+
+/// `port 3001-3003`, or `no port` for a worktree created without one
+fn stale_ports(entry: &prune::StaleEntry) -> String {
+    match entry.ports {
+        Some(ports) => format!("port {}", ports),
+        None => "no port".to_string(),
+    }
+}
+
+fn cmd_rename(old: &str, new: &str, keep_path: bool, force: bool) -> Result<()> {
+    let cfg = config::WtConfig::load()?;
+    let repo_root = config::get_repo_root()?;
+
+    let opts = rename::RenameOptions { keep_path, force };
+    let outcome = rename::rename(&cfg, &repo_root, old, new, opts)?;
+
+    println!("Renamed '{}' to '{}'", outcome.old_branch, outcome.new_branch);
+    if outcome.new_path != outcome.old_path {
+        println!("  Path: {} -> {}", outcome.old_path.display(), outcome.new_path.display());
+    } else {
+        println!("  Path: {}", outcome.new_path.display());
+    }
+    if let Some(port) = outcome.port {
+        println!("  Port: {}", port);
+    }
+
+    Ok(())
+}
+
+fn cmd_status(branch: Option<&str>, check: bool, json: bool) -> Result<()> {
+    let cfg = config::WtConfig::load()?;
+    let repo_root = config::get_repo_root()?;
+    let registry = ports::PortRegistry::load_at(&repo_root)?;
+    if json {
+        return print_status_json(&cfg, &repo_root, &registry, branch);
+    }
+    let probe_hosts = if check { cfg.ports.probe_addrs()? } else { Vec::new() };
 
     match branch {
         Some(b) => {
             // Show specific branch
-            let wt_path = cfg.worktree_path(b);
-            let port = registry.get(b);
+            let wt_path = registry.worktree_path(&cfg, &repo_root, b);
+            let ports = registry.block(b);
 
             println!("Branch: {}", b);
             println!("  Path: {}", wt_path.display());
-            println!("  Port: {}", port.map(|p| p.to_string()).unwrap_or_else(|| "not allocated".to_string()));
+            println!("  Port: {}", ports.map(|p| p.to_string()).unwrap_or_else(|| "not allocated".to_string()));
             println!("  Exists: {}", worktree::exists(&wt_path));
+            match (check, ports) {
+                (true, Some(ports)) if ports.count == 1 => {
+                    println!("  Listening: {}", describe_probes(ports.start, &probe_hosts));
+                }
+                (true, Some(ports)) => {
+                    println!("  Listening:");
+                    for port in ports.ports() {
+                        println!("    :{} {}", port, describe_probes(port, &probe_hosts));
+                    }
+                }
+                _ => {}
+            }
 
             // Show git info if exists
             if worktree::exists(&wt_path) {
@@ -314,51 +1342,231 @@ fn cmd_status(branch: Option<&str>) -> Result<()> {
                     println!("  HEAD: {}", &wt.head[..8.min(wt.head.len())]);
                     if let Some(ref branch) = wt.branch {
                         println!("  Branch: {}", branch);
+                    } else if wt.detached {
+                        println!("  Branch: none (detached HEAD)");
+                    }
+                    match wt.locked.as_deref() {
+                        Some("") => println!("  LOCKED"),
+                        Some(reason) => println!("  LOCKED: {}", reason),
+                        None => {}
                     }
                 }
+                match worktree::sparse_paths_in(&wt_path)? {
+                    Some(paths) => println!("  Sparse: {}", paths.join(", ")),
+                    None => println!("  Sparse: no"),
+                }
+                let state = worktree::work_state(&wt_path);
+                match state.uncommitted {
+                    Some(true) => println!("  Uncommitted changes: yes"),
+                    Some(false) => println!("  Uncommitted changes: no"),
+                    None => println!("  Uncommitted changes: unknown (git status failed)"),
+                }
+                if state.merge_in_progress {
+                    println!("  MERGE IN PROGRESS (finish or run: hydra-wt merge-abort {})", b);
+                }
+                if state.rebase_in_progress {
+                    println!("  REBASE IN PROGRESS (finish or run: hydra-wt merge-abort {})", b);
+                }
+
+                let issues = health::check_branch(&repo_root, b)?;
+                for issue in &issues {
+                    println!("  ⚠️  {}: {}", issue.label(), issue.describe());
+                    println!("     Fix: {}", issue.remediation(b));
+                }
             }
         }
         None => {
             // Summary
-            let total = registry.allocations.len();
-            let existing = registry
-                .allocations
-                .keys()
-                .filter(|b| worktree::exists(&cfg.worktree_path(b)))
-                .count();
+            let branches = registry.branches();
+            let total = branches.len();
+            let states: Vec<(&String, worktree::WorkState)> = branches
+                .iter()
+                .filter_map(|b| {
+                    let path = registry.worktree_path(&cfg, &repo_root, b);
+                    worktree::exists(&path).then(|| (*b, worktree::work_state(&path)))
+                })
+                .collect();
+            let existing = states.len();
 
             println!("hydra-wt status");
             println!("  Total managed: {}", total);
             println!("  Existing: {}", existing);
             println!("  Missing: {}", total - existing);
+            let flagged = |flag: fn(&worktree::WorkState) -> bool| -> Vec<&str> {
+                states.iter().filter(|(_, state)| flag(state)).map(|(b, _)| b.as_str()).collect()
+            };
+            for (label, branches) in [
+                ("Uncommitted changes", flagged(|s| s.uncommitted == Some(true))),
+                ("Merge in progress", flagged(|s| s.merge_in_progress)),
+                ("Rebase in progress", flagged(|s| s.rebase_in_progress)),
+            ] {
+                if !branches.is_empty() {
+                    println!("  {}: {} ({})", label, branches.len(), branches.join(", "));
+                }
+            }
+            let locked = worktree::list_in(&repo_root)?
+                .iter()
+                .filter(|wt| wt.locked.is_some() && wt.branch.as_ref().is_some_and(|b| registry.is_managed(b)))
+                .count();
+            if locked > 0 {
+                println!("  Locked: {}", locked);
+            }
+            if !registry.portless.is_empty() {
+                println!("  Without ports: {}", registry.portless.len());
+            }
             println!("  Port range: {}-{}", cfg.ports.range_start, cfg.ports.range_end);
-            println!("  Ports used: {}", total);
-            println!("  Ports free: {}", (cfg.ports.range_end - cfg.ports.range_start + 1) as usize - total);
+            println!("  Ports used: {}", registry.ports_used());
+            if !cfg.ports.reserved.is_empty() {
+                let reserved: Vec<String> = cfg.ports.reserved.iter().map(|r| r.to_string()).collect();
+                println!("  Reserved: {}", reserved.join(", "));
+            }
+            println!("  Ports free: {}", ports_free(&cfg.ports, &registry));
+            let from_env = config::EnvOverrides::from_env().describe();
+            if !from_env.is_empty() {
+                let set: Vec<String> = from_env.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+                println!("  From environment: {}", set.join(", "));
+            }
+
+            if check {
+                let mut allocations: Vec<_> = registry.blocks().collect();
+                allocations.sort_by_key(|(_, ports)| **ports);
+                println!();
+                for (b, ports) in allocations {
+                    for port in ports.ports() {
+                        println!("  {} :{} {}", b, port, describe_probes(port, &probe_hosts));
+                    }
+                }
+            }
         }
     }
 
     Ok(())
 }
 
+/// `status --json`: one worktree, or a summary with every managed worktree
+fn print_status_json(
+    cfg: &config::WtConfig,
+    repo_root: &Path,
+    registry: &ports::PortRegistry,
+    branch: Option<&str>,
+) -> Result<()> {
+    let value = match branch {
+        Some(b) => serde_json::to_value(worktree::branch_status(cfg, repo_root, registry, b)?)?,
+        None => {
+            let worktrees = worktree::collect_status(cfg, repo_root, registry)?;
+            let existing = worktrees.iter().filter(|w| w.exists).count();
+            let count = |flag: fn(&worktree::WorktreeStatus) -> bool| worktrees.iter().filter(|w| flag(w)).count();
+            serde_json::json!({
+                "total": worktrees.len(),
+                "existing": existing,
+                "missing": worktrees.len() - existing,
+                "uncommitted": count(|w| w.uncommitted == Some(true)),
+                "merge_in_progress": count(|w| w.merge_in_progress),
+                "rebase_in_progress": count(|w| w.rebase_in_progress),
+                "portless": registry.portless.len(),
+                "port_range": [cfg.ports.range_start, cfg.ports.range_end],
+                "ports_used": registry.ports_used(),
+                "ports_reserved": cfg.ports.reserved,
+                "ports_free": ports_free(&cfg.ports, registry),
+                "from_env": config::EnvOverrides::from_env().describe().into_iter().collect::<std::collections::BTreeMap<_, _>>(),
+                "worktrees": worktrees,
+            })
+        }
+    };
+    println!("{}", serde_json::to_string_pretty(&value)?);
+    Ok(())
+}
+
+/// Ports in range that are neither allocated nor reserved
+fn ports_free(cfg: &config::PortsConfig, registry: &ports::PortRegistry) -> usize {
+    let mut taken = cfg.reserved_in_range();
+    taken.extend(registry.blocks().flat_map(|(_, block)| block.ports()));
+    let range = usize::from(cfg.range_end - cfg.range_start) + 1;
+    range.saturating_sub(taken.iter().filter(|p| (cfg.range_start..=cfg.range_end).contains(*p)).count())
+}
+
+/// The first few of `paths`, e.g. `a.rs, b.rs, c.rs and 4 more`
+fn first_paths(paths: &[String]) -> String {
+    const SHOWN: usize = 3;
+    let shown = paths.iter().take(SHOWN).cloned().collect::<Vec<_>>().join(", ");
+    if paths.len() > SHOWN {
+        format!("{} and {} more", shown, paths.len() - SHOWN)
+    } else {
+        shown
+    }
+}
+
+/// e.g. `127.0.0.1 (IPv4) listening, ::1 (IPv6) closed`
+fn describe_probes(port: u16, hosts: &[std::net::IpAddr]) -> String {
+    probe::probe_all(port, hosts, &probe::Timeouts::default())
+        .iter()
+        .map(|p| format!("{} ({}) {}", p.host, p.family, p.result))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Split the branches given to `merge` into sources and target: every
+/// branch is a source with `--into`, otherwise the last one is the target
+fn merge_branches(mut branches: Vec<String>, into: Option<String>) -> Result<(Vec<String>, String)> {
+    if let Some(target) = into {
+        return Ok((branches, target));
+    }
+    match branches.len() {
+        2 => {
+            let target = branches.pop().expect("two branches");
+            Ok((branches, target))
+        }
+        1 => anyhow::bail!("Missing the target branch; give it after the source or with --into"),
+        _ => anyhow::bail!("Merging several branches needs the target given with --into"),
+    }
+}
+
+/// What `merge --cleanup` does with each source once it has landed
+struct MergeCleanup {
+    remove: bool,
+    delete_branch: bool,
+    archive: bool,
+}
+
+/// How each source lands on the target, and the message of the commit
+/// that lands it
+struct MergeStrategy {
+    mode: worktree::MergeMode,
+    message: Option<String>,
+    /// `--push` or `--no-push`; `merge.push` decides without either
+    push: Option<bool>,
+}
+
 fn cmd_merge(
-    source: &str,
+    sources: &[String],
     target: &str,
     force: bool,
-    no_ff: bool,
+    strategy: MergeStrategy,
     dry_run: bool,
-    cleanup: bool,
+    cleanup: MergeCleanup,
+    check: bool,
 ) -> Result<()> {
     let cfg = config::WtConfig::load()?;
-    let registry = ports::PortRegistry::load()?;
+    let repo_root = config::get_repo_root()?;
+    let MergeStrategy { mode, message, push } = strategy;
 
-    // Validate: cannot merge branch into itself
-    if source == target {
-        anyhow::bail!("Cannot merge branch '{}' into itself", source);
+    if check && cfg.merge.check_command.is_none() {
+        anyhow::bail!("--check needs a command to run; set merge.check_command in the config");
     }
 
-    // Validate source branch exists
-    if !worktree::branch_exists(source)? {
-        anyhow::bail!("Source branch '{}' does not exist", source);
+    for (i, source) in sources.iter().enumerate() {
+        // Validate: cannot merge branch into itself
+        if source == target {
+            anyhow::bail!("Cannot merge branch '{}' into itself", source);
+        }
+        if sources[..i].contains(source) {
+            anyhow::bail!("Source branch '{}' is given more than once", source);
+        }
+
+        // Validate source branch exists
+        if !worktree::branch_exists(source)? {
+            anyhow::bail!("Source branch '{}' does not exist", source);
+        }
     }
 
     // Validate target branch exists
@@ -366,25 +1574,7 @@ fn cmd_merge(
         anyhow::bail!("Target branch '{}' does not exist", target);
     }
 
-    // Get target worktree path (could be main repo or a worktree)
-    let target_path = match worktree::get_worktree_path(target)? {
-        Some(path) => path,
-        None => {
-            // Check if target is the current branch in the main repo
-            let repo_root = config::get_repo_root()?;
-            let current = worktree::get_current_branch(&repo_root)?;
-            if current == target {
-                repo_root
-            } else {
-                anyhow::bail!(
-                    "Target branch '{}' is not checked out in any worktree. \
-                    Create a worktree first with: hydra-wt create {}",
-                    target,
-                    target
-                );
-            }
-        }
-    };
+    let target_path = target_worktree(&repo_root, target)?;
 
     // Check for uncommitted changes in target
     if worktree::has_uncommitted_changes(&target_path)? {
@@ -407,40 +1597,98 @@ fn cmd_merge(
         );
     }
 
-    // Get commits ahead
-    let commits = worktree::commits_ahead(source, target)?;
+    // Get commits ahead, and how far each source has fallen behind
+    let mut divergences = Vec::new();
+    for source in sources {
+        divergences.push((source, worktree::divergence_in(&repo_root, source, target)?));
+    }
+    let total: usize = divergences.iter().map(|(_, d)| d.ahead.len()).sum();
 
-    if commits.is_empty() {
+    if total == 0 {
         println!("Already up to date. Nothing to merge.");
         return Ok(());
     }
 
     // Show preview
-    println!("Merge preview: {} → {}", source, target);
-    println!("{} commit(s) to merge:\n", commits.len());
-    for commit in &commits {
-        println!(
-            "  {} {}",
-            &commit.hash[..7.min(commit.hash.len())],
-            commit.message
-        );
+    if let [(source, divergence)] = divergences.as_slice() {
+        println!("Merge preview: {} → {}", source, target);
+        if mode == worktree::MergeMode::Squash {
+            println!("{} commit(s) to squash into one:\n", total);
+        } else {
+            println!("{} commit(s) to merge:\n", total);
+        }
+        for commit in &divergence.ahead {
+            println!("  {}", commit_line(commit));
+        }
+        println!();
+        println!("{}", worktree::change_summary_in(&repo_root, source, target)?.describe());
+        println!();
+        if divergence.behind > 0 {
+            println!("{} is {} commit(s) behind {}", source, divergence.behind, target);
+            if target == worktree::detect_main_branch() {
+                println!("    Run hydra-wt sync {} first to resolve conflicts in the worktree", source);
+            }
+            println!();
+        }
+    } else {
+        println!("Merge preview: {} → {}", sources.join(", "), target);
+        if mode == worktree::MergeMode::Squash {
+            println!("{} commit(s) to squash into one per source:\n", total);
+        } else {
+            println!("{} commit(s) to merge:\n", total);
+        }
+        for (source, divergence) in &divergences {
+            if divergence.ahead.is_empty() {
+                println!("  {}: up to date", source);
+                continue;
+            }
+            let behind = if divergence.behind > 0 {
+                format!(", {} behind {}", divergence.behind, target)
+            } else {
+                String::new()
+            };
+            let changes = worktree::change_summary_in(&repo_root, source, target)?;
+            println!("  {}: {} commit(s), {}{}", source, divergence.ahead.len(), changes.describe(), behind);
+            for commit in &divergence.ahead {
+                println!("    {}", commit_line(commit));
+            }
+        }
+        println!();
     }
-    println!();
 
     if dry_run {
-        // Check if merge would have conflicts
-        let can_merge = worktree::can_merge(&target_path, source)?;
-        if can_merge {
-            println!("✓ Merge can proceed without conflicts");
-        } else {
-            println!("⚠️  Merge would have conflicts");
+        let several = divergences.len() > 1;
+        for (source, divergence) in &divergences {
+            if divergence.ahead.is_empty() {
+                continue;
+            }
+            let preview = worktree::merge_preview(&target_path, source)?;
+            let label = if several { format!("{}: ", source) } else { String::new() };
+            if preview.is_clean() {
+                println!("✓ {}Merge can proceed without conflicts", label);
+            } else {
+                println!(
+                    "⚠️  {}Merge would have conflicts in {} file(s): {}",
+                    label,
+                    preview.conflicts.len(),
+                    first_paths(&preview.conflicts)
+                );
+            }
+        }
+        if several {
+            println!("    Each source is checked against {} as it is now, not after the ones before it", target);
         }
         return Ok(());
     }
 
     // Confirm unless --force
     if !force {
-        print!("Proceed with merge? [y/N] ");
+        let pending = divergences.iter().filter(|(_, d)| !d.ahead.is_empty()).count();
+        if pending > 1 {
+            print!("Proceed with {} merges? [y/N] ", pending);
+        } else {
+            print!("Proceed with merge? [y/N] ");
+        }
         std::io::Write::flush(&mut std::io::stdout())?;
 
         let mut input = String::new();
@@ -451,84 +1699,662 @@ fn cmd_merge(
         }
     }
 
-    // Emit merge started event
-    hydra::emit_merge_started(source, target, commits.len())?;
+    if let Some(stale) = MergeQueue::load_at(&repo_root)? {
+        warn!(
+            "discarding the stopped merge into '{}' that still had {} to merge",
+            stale.target,
+            if stale.remaining.is_empty() { "nothing".to_string() } else { stale.remaining.join(", ") }
+        );
+        MergeQueue::clear_at(&repo_root)?;
+    }
 
-    // Perform the merge
-    println!("Merging {} into {}...", source, target);
-    let result = worktree::merge(&target_path, source, no_ff)?;
+    let queue = MergeQueue {
+        target: target.to_string(),
+        conflicted: String::new(),
+        landed: Vec::new(),
+        remaining: divergences
+            .iter()
+            .filter(|(_, d)| !d.ahead.is_empty())
+            .map(|(source, _)| source.to_string())
+            .collect(),
+        mode,
+        cleanup: cleanup.remove,
+        delete_branch: cleanup.delete_branch,
+        archive: cleanup.archive,
+        check,
+        message,
+        push: push.unwrap_or(cfg.merge.push),
+    };
+    run_merges(&cfg, &repo_root, &target_path, queue)
+}
 
-    match result {
-        worktree::MergeResult::Success { merge_commit } => {
-            println!(
-                "✓ Merge successful (commit: {})",
-                &merge_commit[..7.min(merge_commit.len())]
-            );
-            hydra::emit_merge_completed(source, target, &merge_commit)?;
+/// Finish the merge that stopped at a conflict, then merge what was left
+fn cmd_merge_continue() -> Result<()> {
+    let cfg = config::WtConfig::load()?;
+    let repo_root = config::get_repo_root()?;
+
+    let Some(mut queue) = MergeQueue::load_at(&repo_root)? else {
+        anyhow::bail!("No stopped merge to continue");
+    };
+    let target = queue.target.clone();
+    let source = std::mem::take(&mut queue.conflicted);
+    let target_path = target_worktree(&repo_root, &target)?;
+
+    if worktree::is_merge_in_progress(&target_path) {
+        anyhow::bail!(
+            "The merge of '{}' into '{}' is not committed yet.\n\
+            Resolve the conflicts, then: cd {} && git add . && git commit",
+            source,
+            target,
+            target_path.display()
+        );
+    }
+    if worktree::has_uncommitted_changes(&target_path)? {
+        anyhow::bail!(
+            "Target worktree has uncommitted changes. \
+            Commit or stash changes first:\n  cd {} && git status",
+            target_path.display()
+        );
+    }
+    // A squash leaves the source's commits off the target, so there is
+    // nothing to check it against
+    if queue.mode != worktree::MergeMode::Squash && !worktree::commits_ahead_in(&repo_root, &source, &target)?.is_empty() {
+        let rest: Vec<&str> = std::iter::once(source.as_str()).chain(queue.remaining.iter().map(String::as_str)).collect();
+        anyhow::bail!(
+            "'{}' is not merged into '{}'. Merge it yourself, or start over with: hydra-wt merge {} --into {}",
+            source,
+            target,
+            rest.join(" "),
+            target
+        );
+    }
+
+    let head = worktree::get_head_commit(&target_path)?;
+    let subject = worktree::commit_subject_in(&target_path, &head)?;
+    println!("✓ {}: Merge committed ({} {})", source, &head[..7.min(head.len())], subject);
+    finish_merge(&cfg, &repo_root, &target_path, &queue, &source, &head, &subject)?;
+    queue.landed.push(source);
+    MergeQueue::clear_at(&repo_root)?;
+
+    run_merges(&cfg, &repo_root, &target_path, queue)
+}
+
+/// Where `target` is checked out: its worktree, or the main checkout
+fn target_worktree(repo_root: &Path, target: &str) -> Result<PathBuf> {
+    if let Some(path) = worktree::get_worktree_path_in(repo_root, target)? {
+        return Ok(path);
+    }
+    // A bare repository has no main checkout to merge in
+    if config::is_bare_at(repo_root) {
+        anyhow::bail!(
+            "Target branch '{}' is not checked out in any worktree, and a bare repository has no \
+            main checkout to merge in. Create a worktree first with: hydra-wt create {}",
+            target,
+            target
+        );
+    }
+    // Check if target is the current branch in the main repo
+    let current = worktree::get_current_branch(repo_root)?;
+    if current == target {
+        Ok(repo_root.to_path_buf())
+    } else {
+        anyhow::bail!(
+            "Target branch '{}' is not checked out in any worktree. \
+            Create a worktree first with: hydra-wt create {}",
+            target,
+            target
+        );
+    }
+}
+
+/// `abc1234 message (author, date)`
+fn commit_line(commit: &worktree::CommitInfo) -> String {
+    format!(
+        "{} {} ({}, {})",
+        &commit.hash[..7.min(commit.hash.len())],
+        commit.message,
+        commit.author,
+        &commit.date[..10.min(commit.date.len())]
+    )
+}
+
+/// Merge the remaining sources of `queue` into its target one at a time.
+/// At the first conflict the merge is left in progress and, if there is
+/// anything left for `merge --continue` to do, the queue is saved.
+fn run_merges(cfg: &config::WtConfig, repo_root: &Path, target_path: &Path, mut queue: MergeQueue) -> Result<()> {
+    let target = queue.target.clone();
+    let several = queue.landed.len() + queue.remaining.len() > 1;
+    // Checks run in a process group of their own, out of reach of the
+    // terminal's Ctrl+C; pass it on so an interrupted check stops
+    if queue.check {
+        ctrlc::set_handler(hooks::interrupt)?;
+    }
+
+    while !queue.remaining.is_empty() {
+        let source = queue.remaining.remove(0);
+        let label = if several { format!("{}: ", source) } else { String::new() };
+        let commits = worktree::commits_ahead_in(repo_root, &source, &target)?;
+        if commits.is_empty() {
+            println!("{}Already up to date. Nothing to merge.", label);
+            continue;
         }
-        worktree::MergeResult::FastForward { new_head } => {
-            println!(
-                "✓ Fast-forward merge (head: {})",
-                &new_head[..7.min(new_head.len())]
-            );
-            hydra::emit_merge_completed(source, target, &new_head)?;
+
+        if queue.check && !check_source(cfg, repo_root, &source, &label)? {
+            if several {
+                let rest: Vec<&str> = std::iter::once(source.as_str()).chain(queue.remaining.iter().map(String::as_str)).collect();
+                println!("\nMerged: {}", if queue.landed.is_empty() { "none".to_string() } else { queue.landed.join(", ") });
+                println!("Not merged: {}", rest.join(", "));
+            }
+            anyhow::bail!("Check failed for '{}', not merging it", source);
         }
-        worktree::MergeResult::Conflict { files } => {
-            println!("\n⚠️  Merge conflict in {} file(s):", files.len());
-            for file in &files {
-                println!("  - {}", file);
+
+        // Emit merge started event
+        hydra::emit_merge_started(cfg, repo_root, &source, &target, commits.len())?;
+
+        // Perform the merge
+        info!("Merging {} into {}...", source, target);
+        let opts = worktree::MergeOptions {
+            message: queue
+                .message
+                .as_deref()
+                .or(cfg.merge.message_template.as_deref())
+                .map(|template| config::MergeConfig::render_message(template, &source, &target, commits.len())),
+            sign: cfg.merge.sign,
+        };
+        let (head, subject) = match worktree::merge_with(target_path, &source, queue.mode, &opts)? {
+            worktree::MergeResult::Success { merge_commit, subject } => {
+                println!(
+                    "✓ {}Merge successful (commit: {} {})",
+                    label,
+                    &merge_commit[..7.min(merge_commit.len())],
+                    subject
+                );
+                (merge_commit, subject)
+            }
+            worktree::MergeResult::FastForward { new_head, subject } => {
+                println!(
+                    "✓ {}Fast-forward merge (head: {})",
+                    label,
+                    &new_head[..7.min(new_head.len())]
+                );
+                (new_head, subject)
+            }
+            worktree::MergeResult::Squashed { commit, subject, squashed } => {
+                println!(
+                    "✓ {}Squashed {} commit(s) into {} {}",
+                    label,
+                    squashed,
+                    &commit[..7.min(commit.len())],
+                    subject
+                );
+                (commit, subject)
             }
-            println!("\nResolve conflicts in: {}", target_path.display());
-            println!("Then run: cd {} && git add . && git commit", target_path.display());
-            println!("Or abort: hydra-wt merge-abort {}", target);
+            worktree::MergeResult::Conflict { files } => {
+                println!("\n⚠️  {}Merge conflict in {} file(s):", label, files.len());
+                for file in &files {
+                    println!("  - {}", file);
+                }
+                println!("\nResolve conflicts in: {}", target_path.display());
+                println!("Then run: cd {} && git add . && git commit", target_path.display());
+                println!("Or abort: hydra-wt merge-abort {}", target);
 
-            hydra::emit_merge_conflict(source, target, &target_path.to_string_lossy(), &files)?;
-            return Ok(());
+                hydra::emit_merge_conflict(cfg, repo_root, &source, &target, &target_path.to_string_lossy(), &files)?;
+
+                if several {
+                    let list = |branches: &[String]| {
+                        if branches.is_empty() { "none".to_string() } else { branches.join(", ") }
+                    };
+                    println!("\nMerged: {}", list(&queue.landed));
+                    println!("Stopped at: {}", source);
+                    println!("Remaining: {}", list(&queue.remaining));
+                }
+                if several || queue.cleanup {
+                    println!("After committing, finish with: hydra-wt merge --continue");
+                    queue.conflicted = source;
+                    queue.save_at(repo_root)?;
+                }
+                return Ok(());
+            }
+            worktree::MergeResult::NothingToMerge => {
+                println!("{}Already up to date. Nothing to merge.", label);
+                continue;
+            }
+        };
+
+        finish_merge(cfg, repo_root, target_path, &queue, &source, &head, &subject)?;
+        queue.landed.push(source);
+    }
+
+    if several && !queue.landed.is_empty() {
+        println!("✓ Merged {} into {}", queue.landed.join(", "), target);
+    }
+    Ok(())
+}
+
+/// Run `merge.check_command` in the worktree of `source` with its ports and
+/// template variables in the environment; whether it passed
+fn check_source(cfg: &config::WtConfig, repo_root: &Path, source: &str, label: &str) -> Result<bool> {
+    let Some(command) = cfg.merge.check_command.as_deref() else {
+        anyhow::bail!("No merge.check_command to check '{}' with", source);
+    };
+    let cfg = cfg.resolve_for(source)?;
+    let registry = ports::PortRegistry::load_at(repo_root)?;
+    let wt_path = match registry.worktree_path(&cfg, repo_root, source) {
+        path if worktree::exists(&path) => path,
+        _ => match worktree::get_worktree_path_in(repo_root, source)? {
+            Some(path) => path,
+            None => anyhow::bail!("'{}' has no worktree to run the check in", source),
+        },
+    };
+
+    let mut env = if registry.is_managed(source) { exports::for_worktree(&cfg, repo_root, source)? } else { Vec::new() };
+    // What dev servers and test setups usually read
+    if let Some(port) = registry.get(source) {
+        env.push(("PORT".to_string(), port.to_string()));
+    }
+
+    let log = repo_root.join(".hydra/merge-checks").join(format!("{}.log", source));
+    info!("Checking {}: {}", source, command);
+    match hooks::run_check(&wt_path, command, &env, cfg.merge.check_timeout(), &log)? {
+        hooks::HookStatus::Succeeded => {
+            println!("✓ {}Check passed", label);
+            Ok(true)
         }
-        worktree::MergeResult::NothingToMerge => {
-            println!("Already up to date. Nothing to merge.");
-            return Ok(());
+        hooks::HookStatus::Failed(status) => {
+            println!("✗ {}Check failed ({}), output in {}", label, status, log.display());
+            Ok(false)
+        }
+        hooks::HookStatus::TimedOut(timeout) => {
+            println!("✗ {}Check timed out after {}s, output in {}", label, timeout.as_secs(), log.display());
+            Ok(false)
         }
     }
+}
+
+/// Everything after `source` has landed on the target at `head`: announce
+/// it, record both heads, and clean up the source if the queue asks to
+fn finish_merge(
+    cfg: &config::WtConfig,
+    repo_root: &Path,
+    target_path: &Path,
+    queue: &MergeQueue,
+    source: &str,
+    head: &str,
+    subject: &str,
+) -> Result<()> {
+    hydra::emit_merge_completed(cfg, repo_root, source, &queue.target, head, subject)?;
+    record_synced_heads(repo_root, source, &queue.target);
+    if queue.push {
+        push_target(cfg, repo_root, target_path, source, &queue.target, head)?;
+    }
 
     // Cleanup if requested
-    if cleanup {
-        println!("\nCleaning up source worktree...");
-        let source_wt_path = cfg.worktree_path(source);
-
-        if worktree::exists(&source_wt_path) {
-            worktree::remove(&source_wt_path, true)?;
-
-            // Free port if allocated
-            let mut registry = registry;
-            if let Ok(port) = registry.free(source) {
-                registry.save()?;
-                println!("Removed worktree '{}' and freed port {}", source, port);
-            } else {
-                println!("Removed worktree '{}'", source);
-            }
+    if !queue.cleanup {
+        return Ok(());
+    }
+    if queue.archive {
+        print_archived(&archive::create(cfg, repo_root, source)?);
+    }
+    info!("Cleaning up source worktree...");
+    let mut registry = ports::PortRegistry::load_at(repo_root)?;
+    let source_wt_path = registry.worktree_path(cfg, repo_root, source);
+
+    if worktree::exists(&source_wt_path) {
+        worktree::remove(&source_wt_path, true)?;
+        worktree::remove_empty_parents(&source_wt_path, &cfg.worktree_dir_in(repo_root));
+
+        // Free port if allocated
+        let managed = registry.is_managed(source);
+        match registry.unregister(source) {
+            Some(port) => info!("Removed worktree '{}' and freed port {}", source, port),
+            None => info!("Removed worktree '{}'", source),
+        }
+        if managed {
+            registry.save_at(repo_root)?;
+        }
 
-            hydra::emit_worktree_removed(source)?;
+        hydra::emit_worktree_removed(cfg, repo_root, source)?;
+        hydra::emit_inventory(cfg, repo_root)?;
+    } else {
+        warn!("source worktree '{}' not found (may not be managed by hydra-wt)", source);
+    }
+
+    if queue.delete_branch {
+        if let Some(path) = worktree::get_worktree_path_in(repo_root, source)? {
+            warn!("not deleting branch '{}', it is still checked out at {}", source, path.display());
         } else {
-            println!("Source worktree '{}' not found (may not be managed by hydra-wt)", source);
+            // Run from the target so `git branch -d` checks against what was merged into
+            worktree::delete_branch_in(target_path, source, false)?;
+            println!("✓ Deleted branch '{}'", source);
+        }
+    }
+    Ok(())
+}
+
+/// Push `target` to `merge.remote` now that `source` landed on it at
+/// `head`. The merge stands either way, so a failed push is reported and
+/// announced on `merge:push-failed` rather than failing the merge.
+fn push_target(cfg: &config::WtConfig, repo_root: &Path, target_path: &Path, source: &str, target: &str, head: &str) -> Result<()> {
+    let remote = &cfg.merge.remote;
+    info!("Pushing {} to {}...", target, remote);
+    match worktree::push_in(target_path, remote, target) {
+        Ok(()) => println!("✓ Pushed {} to {}", target, remote),
+        Err(e) => {
+            println!("✗ Push of {} to {} failed, the merge is kept: {}", target, remote, e);
+            println!("    Push it yourself with: cd {} && git push {} {}", target_path.display(), remote, target);
+            hydra::emit_merge_push_failed(cfg, repo_root, source, target, remote, head, &e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+/// After a merge both branches are in a known-good state; record their heads
+fn record_synced_heads(repo_root: &Path, source: &str, target: &str) {
+    let registry = ports::PortRegistry::load_at(repo_root).unwrap_or_default();
+    for branch in [source, target] {
+        if registry.is_managed(branch) {
+            if let Err(e) = health::record_head(repo_root, branch) {
+                warn!("failed to record head of {}: {}", branch, e);
+            }
+        }
+    }
+}
+
+fn cmd_diff(branch: &str, full: bool, name_only: bool, json: bool, summary: bool) -> Result<()> {
+    let repo_root = config::get_repo_root()?;
+    if !worktree::branch_exists_in(&repo_root, branch)? {
+        anyhow::bail!("Branch '{}' does not exist", branch);
+    }
+    let main = worktree::detect_main_branch_in(&repo_root);
+    // What the branch did, not what main did since it branched off
+    let base = worktree::merge_base_in(&repo_root, branch, &main)?;
+
+    if json {
+        let changes = worktree::change_summary_in(&repo_root, branch, &main)?;
+        let summary = serde_json::json!({
+            "branch": branch,
+            "base": main,
+            "merge_base": base,
+            "additions": changes.insertions,
+            "deletions": changes.deletions,
+            "files": changes.files,
+        });
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+        return Ok(());
+    }
+
+    if summary {
+        let changes = worktree::change_summary_in(&repo_root, branch, &main)?;
+        if changes.files.is_empty() {
+            info!("'{}' has no changes since it left {}", branch, main);
+            return Ok(());
+        }
+        let count = |n: Option<usize>| n.map_or("-".to_string(), |n| n.to_string());
+        for file in &changes.files {
+            let path = match &file.old_path {
+                Some(old) => format!("{} → {}", old, file.path),
+                None => file.path.clone(),
+            };
+            println!("  +{:<6} -{:<6} {}", count(file.additions), count(file.deletions), path);
         }
+        println!("{}", changes.describe());
+        return Ok(());
     }
 
+    let format = if full {
+        worktree::DiffFormat::Patch
+    } else if name_only {
+        worktree::DiffFormat::NameOnly
+    } else {
+        worktree::DiffFormat::Stat
+    };
+    let color = !name_only && std::io::stdout().is_terminal();
+    let diff = worktree::diff_in(&repo_root, &base, branch, format, color)?;
+    if diff.is_empty() {
+        info!("'{}' has no changes since it left {}", branch, main);
+    }
+    print!("{}", diff);
+    Ok(())
+}
+
+fn cmd_verify(fix_upstream_gone: bool) -> Result<()> {
+    let cfg = config::WtConfig::load()?;
+    let repo_root = config::get_repo_root()?;
+    let registry = ports::PortRegistry::load_at(&repo_root)?;
+
+    let branches = registry.branches();
+
+    let mut problems = 0;
+    for branch in branches {
+        let wt_path = registry.worktree_path(&cfg, &repo_root, branch);
+        if !worktree::exists(&wt_path) {
+            println!("✗ {}: worktree missing at {}", branch, wt_path.display());
+            println!("    Fix: hydra-wt remove {} to free its port", branch);
+            problems += 1;
+            continue;
+        }
+
+        let issues = health::check(&repo_root, branch, &registry)?;
+        if issues.is_empty() {
+            println!("✓ {}", branch);
+            continue;
+        }
+
+        for issue in &issues {
+            if fix_upstream_gone && matches!(issue, health::WorktreeIssue::UpstreamGone { .. }) {
+                health::clear_upstream(&repo_root, branch)?;
+                println!("✓ {}: cleared stale upstream", branch);
+                continue;
+            }
+            println!("✗ {}: {} ({})", branch, issue.label(), issue.describe());
+            println!("    Fix: {}", issue.remediation(branch));
+            problems += 1;
+        }
+    }
+
+    if problems > 0 {
+        anyhow::bail!("{} problem(s) found", problems);
+    }
+    Ok(())
+}
+
+fn cmd_doctor(fix: bool) -> Result<()> {
+    let cfg = config::WtConfig::load()?;
+    let repo_root = config::get_repo_root()?;
+
+    let findings = doctor::diagnose(&cfg, &repo_root)?;
+    if findings.is_empty() {
+        println!("✓ Registry, git worktrees and directories agree");
+        return Ok(());
+    }
+
+    let fixed = if fix {
+        doctor::fix(&cfg, &repo_root, &findings, |branch, path| {
+            print!("Adopt worktree '{}' at {} and allocate ports for it? [y/N] ", branch, path.display());
+            let _ = std::io::stdout().flush();
+            let mut input = String::new();
+            std::io::stdin().read_line(&mut input).is_ok() && input.trim().eq_ignore_ascii_case("y")
+        })?
+    } else {
+        Vec::new()
+    };
+
+    let mut problems = 0;
+    for finding in &findings {
+        if fixed.contains(finding) {
+            println!("✓ fixed {}: {}", finding.label(), finding.describe(&cfg));
+            continue;
+        }
+        println!("✗ {}: {}", finding.label(), finding.describe(&cfg));
+        println!("    Fix: {}", finding.remediation());
+        problems += 1;
+    }
+
+    if problems > 0 {
+        anyhow::bail!("{} problem(s) found", problems);
+    }
+    Ok(())
+}
+
+/// Outcome of syncing one branch
+enum SyncOutcome {
+    Updated(String),
+    UpToDate,
+    Conflict(Vec<String>),
+}
+
+fn cmd_sync(branch: Option<&str>, all: bool, merge: bool) -> Result<()> {
+    let cfg = config::WtConfig::load()?;
+    let repo_root = config::get_repo_root()?;
+    let registry = ports::PortRegistry::load_at(&repo_root)?;
+    let main_branch = worktree::detect_main_branch();
+
+    info!("Fetching remotes...");
+    worktree::fetch(&repo_root)?;
+    let upstream = format!("{}@{{upstream}}", main_branch);
+    if let Ok(behind) = worktree::commits_ahead(&upstream, &main_branch) {
+        if !behind.is_empty() {
+            warn!(
+                "{} is {} commit(s) behind its upstream; update it first to sync onto the latest",
+                main_branch,
+                behind.len()
+            );
+        }
+    }
+
+    let branches: Vec<String> = if all {
+        registry.branches().into_iter().filter(|b| **b != main_branch).cloned().collect()
+    } else {
+        vec![branch.unwrap_or_default().to_string()]
+    };
+    if branches.is_empty() {
+        println!("No worktrees managed by hydra-wt");
+        return Ok(());
+    }
+
+    let verb = if merge { "merged" } else { "rebased onto" };
+    let mut problems = 0;
+    for branch in &branches {
+        let wt_path = match worktree::get_worktree_path(branch)? {
+            Some(path) => path,
+            None if all => {
+                println!("✗ {}: worktree missing at {}", branch, registry.worktree_path(&cfg, &repo_root, branch).display());
+                problems += 1;
+                continue;
+            }
+            None => anyhow::bail!("Branch '{}' is not checked out in any worktree", branch),
+        };
+
+        match sync_branch(&wt_path, &main_branch, merge) {
+            Ok(SyncOutcome::Updated(head)) => {
+                println!("✓ {}: {} {} (head: {})", branch, verb, main_branch, &head[..7.min(head.len())]);
+                // A rebase rewrites the branch; the new head is the one to watch from now on
+                if let Err(e) = health::record_head(&repo_root, branch) {
+                    warn!("failed to record head of {}: {}", branch, e);
+                }
+            }
+            Ok(SyncOutcome::UpToDate) => println!("✓ {}: already up to date with {}", branch, main_branch),
+            Ok(SyncOutcome::Conflict(files)) => {
+                println!("✗ {}: conflict in {} file(s):", branch, files.len());
+                for file in &files {
+                    println!("  - {}", file);
+                }
+                let next = if merge { "git add . && git commit" } else { "git add . && git rebase --continue" };
+                println!("    Resolve in {}, then: {}", wt_path.display(), next);
+                println!("    Or abort: hydra-wt merge-abort {}", branch);
+                problems += 1;
+            }
+            Err(e) if all => {
+                println!("✗ {}: {}", branch, e);
+                problems += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    if problems > 0 {
+        anyhow::bail!("{} branch(es) not synced", problems);
+    }
+    Ok(())
+}
+
+fn sync_branch(wt_path: &Path, main_branch: &str, merge: bool) -> Result<SyncOutcome> {
+    if !merge {
+        return Ok(match worktree::rebase(wt_path, main_branch)? {
+            worktree::RebaseResult::Rebased { new_head } => SyncOutcome::Updated(new_head),
+            worktree::RebaseResult::UpToDate => SyncOutcome::UpToDate,
+            worktree::RebaseResult::Conflict { files } => SyncOutcome::Conflict(files),
+        });
+    }
+
+    if worktree::is_merge_in_progress(wt_path) || worktree::is_rebase_in_progress(wt_path) {
+        anyhow::bail!("A merge or rebase is already in progress in {}", wt_path.display());
+    }
+    if worktree::has_uncommitted_changes(wt_path)? {
+        anyhow::bail!("Worktree {} has uncommitted changes. Commit or stash them first", wt_path.display());
+    }
+    Ok(match worktree::merge(wt_path, main_branch, worktree::MergeMode::FastForward)? {
+        worktree::MergeResult::Success { merge_commit: head, .. }
+        | worktree::MergeResult::FastForward { new_head: head, .. }
+        | worktree::MergeResult::Squashed { commit: head, .. } => SyncOutcome::Updated(head),
+        worktree::MergeResult::NothingToMerge => SyncOutcome::UpToDate,
+        worktree::MergeResult::Conflict { files } => SyncOutcome::Conflict(files),
+    })
+}
+
+fn cmd_exec(branch: Option<&str>, parallel: usize, command: &[String]) -> Result<()> {
+    let cfg = config::WtConfig::load()?;
+    let repo_root = config::get_repo_root()?;
+    let registry = ports::PortRegistry::load_at(&repo_root)?;
+
+    // Each run's output in one piece, as it finishes
+    let print = std::sync::Mutex::new(());
+    let results = worktree::exec_in_all(&cfg, &repo_root, &registry, branch, command, parallel, |result| {
+        let _guard = print.lock().unwrap();
+        let mark = if result.success() { "✓" } else { "✗" };
+        println!("{} {} (exit {})", mark, result.branch, result.describe());
+        print!("{}", result.stdout);
+        eprint!("{}", result.stderr);
+        if let Err(e) = &result.status {
+            eprintln!("{}", e);
+        }
+        let _ = std::io::stdout().flush();
+    })?;
+    if results.is_empty() {
+        println!("No worktrees managed by hydra-wt");
+        return Ok(());
+    }
+
+    println!();
+    println!("{:<30} {:<6}", "BRANCH", "EXIT");
+    println!("{}", "-".repeat(37));
+    for result in &results {
+        println!("{:<30} {:<6}", result.branch, result.describe());
+    }
+
+    let failed = results.iter().filter(|result| !result.success()).count();
+    if failed > 0 {
+        anyhow::bail!("{} of {} worktree(s) failed", failed, results.len());
+    }
     Ok(())
 }
 
 fn cmd_merge_abort(branch: &str) -> Result<()> {
-    let _cfg = config::WtConfig::load()?;
+    let cfg = config::WtConfig::load()?;
+    let repo_root = config::get_repo_root()?;
 
-    // Find the worktree for this branch
+    // Find the worktree for this branch. A rebase detaches HEAD, so git no
+    // longer lists the branch; fall back to where hydra-wt put it.
+    let registry = ports::PortRegistry::load_at(&repo_root)?;
+    let managed_path = registry.worktree_path(&cfg, &repo_root, branch);
     let target_path = match worktree::get_worktree_path(branch)? {
         Some(path) => path,
+        None if worktree::is_rebase_in_progress(&managed_path) => managed_path,
         None => {
             // Check if it's the current branch in main repo
-            let repo_root = config::get_repo_root()?;
             let current = worktree::get_current_branch(&repo_root)?;
             if current == branch {
-                repo_root
+                repo_root.clone()
             } else {
                 anyhow::bail!(
                     "Branch '{}' is not checked out in any worktree",
@@ -538,14 +2364,30 @@ fn cmd_merge_abort(branch: &str) -> Result<()> {
         }
     };
 
+    if worktree::is_rebase_in_progress(&target_path) {
+        worktree::rebase_abort(&target_path)?;
+        println!("Rebase aborted in '{}'", branch);
+        return Ok(());
+    }
+
+    // A stopped multi-source merge into this branch ends with the abort
+    let queue = MergeQueue::load_at(&repo_root)?.filter(|q| q.target == branch);
+
     // Check if merge is in progress
-    if !worktree::is_merge_in_progress(&target_path) {
-        anyhow::bail!("No merge in progress in '{}'", branch);
+    if worktree::is_merge_in_progress(&target_path) {
+        // Abort the merge
+        worktree::merge_abort(&target_path)?;
+        println!("Merge aborted in '{}'", branch);
+    } else if queue.is_none() {
+        anyhow::bail!("No merge or rebase in progress in '{}'", branch);
     }
 
-    // Abort the merge
-    worktree::merge_abort(&target_path)?;
-    println!("Merge aborted in '{}'", branch);
+    if let Some(queue) = queue {
+        MergeQueue::clear_at(&repo_root)?;
+        if !queue.remaining.is_empty() {
+            println!("Dropped the queued merge(s) of {} into '{}'", queue.remaining.join(", "), branch);
+        }
+    }
 
     Ok(())
 }
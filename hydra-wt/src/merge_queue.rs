@@ -0,0 +1,83 @@
+//! Sequential merges of several sources into one target
+//!
+//! `hydra-wt merge a b c --into main` merges the sources one at a time. When
+//! one of them conflicts the run stops there, leaving the merge in progress
+//! for the user to resolve, and what is left to do is saved in
+//! `.hydra/wt-merge.json` so `merge --continue` can pick up after the
+//! resolution is committed.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+use crate::worktree::MergeMode;
+
+/// A multi-source merge stopped by a conflict
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MergeQueue {
+    pub target: String,
+    /// The source whose merge conflicted, finished by the user's commit
+    pub conflicted: String,
+    /// Sources merged before the conflict
+    pub landed: Vec<String>,
+    /// Sources not merged yet, in order
+    pub remaining: Vec<String>,
+    pub mode: MergeMode,
+    /// Remove each merged source's worktree
+    pub cleanup: bool,
+    /// With `cleanup`, also delete each merged source branch
+    pub delete_branch: bool,
+    /// With `cleanup`, archive each merged source before removing it
+    #[serde(default)]
+    pub archive: bool,
+    /// Run `merge.check_command` in each source's worktree before merging it
+    #[serde(default)]
+    pub check: bool,
+    /// `merge --message`, used for each source in place of
+    /// `merge.message_template`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Push the target to `merge.remote` after each source lands
+    #[serde(default)]
+    pub push: bool,
+}
+
+impl MergeQueue {
+    pub fn path() -> PathBuf {
+        PathBuf::from(".hydra/wt-merge.json")
+    }
+
+    /// The stopped merge of the repository at `root`, if there is one
+    pub fn load_at(root: &Path) -> Result<Option<Self>> {
+        let path = root.join(Self::path());
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&content)
+            .map(Some)
+            .with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    pub fn save_at(&self, root: &Path) -> Result<()> {
+        let path = root.join(Self::path());
+        let content = serde_json::to_string_pretty(self)
+            .context("Failed to serialize merge queue")?;
+        std::fs::write(&path, content)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        debug!("wrote {}", path.display());
+        Ok(())
+    }
+
+    /// Forget the stopped merge of the repository at `root`, if any
+    pub fn clear_at(root: &Path) -> Result<()> {
+        let path = root.join(Self::path());
+        match std::fs::remove_file(&path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e).with_context(|| format!("Failed to remove {}", path.display())),
+        }
+    }
+}
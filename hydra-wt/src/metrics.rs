@@ -0,0 +1,174 @@
+//! Creation timing history
+//!
+//! Checking out a huge repository can take minutes with nothing on screen.
+//! Every successful create records how long each phase took in
+//! `.hydra/wt-metrics.toml`, so the next one can say up front how long it
+//! will probably take.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::debug;
+
+use crate::create::CreatePhase;
+
+/// Samples kept per phase; older runs say little about the repo today
+pub const MAX_SAMPLES: usize = 10;
+
+/// Recent durations of one create phase
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PhaseHistory {
+    /// Creates that ran this phase, ever
+    pub runs: u64,
+    /// The last [`MAX_SAMPLES`] durations, oldest first
+    pub recent_ms: Vec<u64>,
+}
+
+impl PhaseHistory {
+    pub fn record(&mut self, elapsed: Duration) {
+        self.runs += 1;
+        self.recent_ms.push(elapsed.as_millis() as u64);
+        if self.recent_ms.len() > MAX_SAMPLES {
+            self.recent_ms.remove(0);
+        }
+    }
+
+    /// Median of the recent samples, so one cold-cache run doesn't skew it
+    pub fn estimate(&self) -> Option<Duration> {
+        if self.recent_ms.is_empty() {
+            return None;
+        }
+        let mut sorted = self.recent_ms.clone();
+        sorted.sort_unstable();
+        let mid = sorted.len() / 2;
+        let median = if sorted.len().is_multiple_of(2) {
+            (sorted[mid - 1] + sorted[mid]) / 2
+        } else {
+            sorted[mid]
+        };
+        Some(Duration::from_millis(median))
+    }
+}
+
+/// Per-phase history of this repository's creates (`.hydra/wt-metrics.toml`)
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CreateMetrics {
+    #[serde(flatten)]
+    pub phases: BTreeMap<String, PhaseHistory>,
+}
+
+impl CreateMetrics {
+    pub fn path() -> PathBuf {
+        PathBuf::from(".hydra/wt-metrics.toml")
+    }
+
+    pub fn load_at(root: &Path) -> Result<Self> {
+        let path = root.join(Self::path());
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    pub fn save_at(&self, root: &Path) -> Result<()> {
+        let path = root.join(Self::path());
+        let content = toml::to_string_pretty(self)
+            .context("Failed to serialize create metrics")?;
+        std::fs::write(&path, content)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        debug!("wrote {}", path.display());
+        Ok(())
+    }
+
+    pub fn record(&mut self, phase: CreatePhase, elapsed: Duration) {
+        self.phases.entry(phase.name().to_string()).or_default().record(elapsed);
+    }
+
+    pub fn estimate(&self, phase: CreatePhase) -> Option<Duration> {
+        self.phases.get(phase.name()).and_then(PhaseHistory::estimate)
+    }
+}
+
+/// Rough duration for an estimate: "~45s", "~3m"
+pub fn approx(d: Duration) -> String {
+    let secs = d.as_secs_f64().round() as u64;
+    if secs < 90 {
+        format!("~{}s", secs.max(1))
+    } else {
+        format!("~{}m", (secs + 30) / 60)
+    }
+}
+
+/// Time left in a phase from how far it got in `elapsed`
+pub fn remaining(elapsed: Duration, done: u64, total: u64) -> Option<Duration> {
+    if done == 0 || total == 0 || done > total {
+        return None;
+    }
+    Some(elapsed.mul_f64((total - done) as f64 / done as f64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(n: u64) -> Duration {
+        Duration::from_secs(n)
+    }
+
+    #[test]
+    fn test_estimate_is_median_of_recent_samples() {
+        let mut history = PhaseHistory::default();
+        assert_eq!(history.estimate(), None);
+
+        for s in [80, 82, 400] {
+            history.record(secs(s));
+        }
+        assert_eq!(history.estimate(), Some(secs(82)));
+        history.record(secs(78));
+        assert_eq!(history.estimate(), Some(secs(81)));
+
+        // Only the last MAX_SAMPLES count
+        for _ in 0..MAX_SAMPLES {
+            history.record(secs(5));
+        }
+        assert_eq!(history.runs, 4 + MAX_SAMPLES as u64);
+        assert_eq!(history.recent_ms.len(), MAX_SAMPLES);
+        assert_eq!(history.estimate(), Some(secs(5)));
+    }
+
+    #[test]
+    fn test_metrics_persist_per_phase() {
+        let root = std::env::temp_dir().join(format!("hydra-wt-metrics-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(root.join(".hydra")).unwrap();
+        assert_eq!(CreateMetrics::load_at(&root).unwrap(), CreateMetrics::default());
+
+        let mut metrics = CreateMetrics::default();
+        metrics.record(CreatePhase::WorktreeAdd, Duration::from_millis(81_250));
+        metrics.record(CreatePhase::Artifacts, Duration::from_millis(1_500));
+        metrics.save_at(&root).unwrap();
+
+        let content = std::fs::read_to_string(root.join(CreateMetrics::path())).unwrap();
+        assert!(content.contains("[worktree_add]"), "{}", content);
+        let loaded = CreateMetrics::load_at(&root).unwrap();
+        assert_eq!(loaded, metrics);
+        assert_eq!(loaded.estimate(CreatePhase::WorktreeAdd), Some(Duration::from_millis(81_250)));
+        assert_eq!(loaded.estimate(CreatePhase::Hooks), None);
+
+        std::fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn test_eta_helpers() {
+        assert_eq!(approx(Duration::from_millis(300)), "~1s");
+        assert_eq!(approx(secs(80)), "~80s");
+        assert_eq!(approx(secs(150)), "~3m");
+        assert_eq!(remaining(secs(30), 50_000, 200_000), Some(secs(90)));
+        assert_eq!(remaining(secs(30), 0, 200_000), None);
+        assert_eq!(remaining(secs(30), 200_000, 200_000), Some(Duration::ZERO));
+    }
+}
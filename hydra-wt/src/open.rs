@@ -0,0 +1,100 @@
+//! Jumping into a worktree
+//!
+//! `hydra-wt open <branch>` switches to a tmux window for the worktree when
+//! run inside tmux, starting one in the worktree directory if there is
+//! none yet, and otherwise starts `$SHELL` there as a subshell. `--editor`
+//! hands the worktree to `open.editor` instead, in place of this process. Either way the `hydra-wt env`
+//! variables of the worktree are set.
+
+use anyhow::{Context, Result, bail};
+use std::path::Path;
+use std::process::Command;
+use tracing::debug;
+
+use hydra_log::LoggedCommand;
+
+/// Whether this process runs inside a tmux session
+pub fn in_tmux() -> bool {
+    std::env::var_os("TMUX").is_some_and(|v| !v.is_empty())
+}
+
+/// Switch to the tmux window named `name`, or open one in `dir` with `env`
+/// set if the current session has none. Returns whether a window was opened.
+pub fn tmux_window(name: &str, dir: &Path, env: &[(String, String)]) -> Result<bool> {
+    let output = Command::new("tmux")
+        .args(["list-windows", "-F", "#{window_id} #{window_name}"])
+        .logged_output()
+        .context("Failed to run tmux. Is tmux installed?")?;
+    if !output.status.success() {
+        bail!("tmux list-windows failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    let windows = String::from_utf8_lossy(&output.stdout);
+    let existing = windows
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .find(|(_, window)| *window == name)
+        .map(|(id, _)| id.to_string());
+
+    let mut command = Command::new("tmux");
+    match &existing {
+        Some(id) => {
+            debug!("tmux window {} is already open as {}", name, id);
+            command.args(["select-window", "-t", id]);
+        }
+        None => {
+            command.args(["new-window", "-n", name, "-c"]).arg(dir);
+            for (key, value) in env {
+                command.arg("-e").arg(format!("{}={}", key, value));
+            }
+        }
+    }
+    let output = command.logged_output().context("Failed to run tmux")?;
+    if !output.status.success() {
+        bail!("tmux failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(existing.is_none())
+}
+
+/// Start `$SHELL` (`sh` if unset) in `dir` with `env` set as a subshell,
+/// returning once it exits. How the shell exits is up to whoever uses it,
+/// so its status isn't an error.
+pub fn shell(dir: &Path, env: &[(String, String)]) -> Result<()> {
+    let shell = std::env::var("SHELL").ok().filter(|s| !s.is_empty()).unwrap_or_else(|| "sh".to_string());
+    let mut command = Command::new(&shell);
+    command.current_dir(dir).envs(env.iter().map(|(k, v)| (k, v)));
+    debug!("$ {}", hydra_log::command_line(&command));
+    let status = command.status().with_context(|| format!("Failed to run {}", shell))?;
+    debug!("{} exited with {}", shell, status);
+    Ok(())
+}
+
+/// Run `editor` with `dir` as its last argument. `editor` is a shell
+/// command, so `code -n` works as well as `code`.
+pub fn editor(editor: &str, dir: &Path, env: &[(String, String)]) -> Result<()> {
+    let mut command = Command::new("sh");
+    command
+        .args(["-c", &format!("{} \"$1\"", editor), "sh"])
+        .arg(dir)
+        .current_dir(dir)
+        .envs(env.iter().map(|(k, v)| (k, v)));
+    exec(command, editor)
+}
+
+/// Replace this process with `command` where that is possible, otherwise
+/// wait for it
+fn exec(mut command: Command, name: &str) -> Result<()> {
+    debug!("$ {}", hydra_log::command_line(&command));
+    #[cfg(unix)]
+    {
+        let err = std::os::unix::process::CommandExt::exec(&mut command);
+        Err(err).with_context(|| format!("Failed to run {}", name))
+    }
+    #[cfg(not(unix))]
+    {
+        let status = command.status().with_context(|| format!("Failed to run {}", name))?;
+        if !status.success() {
+            bail!("{} exited with {}", name, status);
+        }
+        Ok(())
+    }
+}
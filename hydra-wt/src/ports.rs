@@ -1,12 +1,293 @@
-use anyhow::{Context, Result, bail};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+use crate::config::{self, PortsConfig, WtConfig};
+use crate::error::{bail, Result, WtError};
+use crate::{age, health, probe, worktree};
+
+/// Allocations named in the error for a full port range
+const HOLDERS_SHOWN: usize = 5;
+
+/// Owner of an entry written before owners were recorded
+pub const UNKNOWN_OWNER: &str = "unknown";
+
+/// Whether `owner` is `filter` or one of its sessions: `orchestrator`
+/// matches `orchestrator:4f2a`
+pub fn owner_matches(owner: &str, filter: &str) -> bool {
+    owner == filter || owner.strip_prefix(filter).is_some_and(|rest| rest.starts_with(':'))
+}
+
+/// Consecutive ports reserved for one worktree
+///
+/// Stored as a bare number when the block is a single port, so registries
+/// written before blocks existed read unchanged, and as the list of its
+/// ports otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "StoredPorts", into = "StoredPorts")]
+pub struct PortBlock {
+    pub start: u16,
+    pub count: u16,
+}
+
+impl PortBlock {
+    pub fn single(port: u16) -> Self {
+        Self { start: port, count: 1 }
+    }
+
+    /// Last port of the block
+    pub fn end(&self) -> u16 {
+        self.start + (self.count - 1)
+    }
+
+    pub fn ports(&self) -> impl Iterator<Item = u16> {
+        self.start..=self.end()
+    }
+
+    pub fn contains(&self, port: u16) -> bool {
+        (self.start..=self.end()).contains(&port)
+    }
+}
+
+impl From<u16> for PortBlock {
+    fn from(port: u16) -> Self {
+        Self::single(port)
+    }
+}
+
+/// `3001`, or `3001-3003` for a block of three
+impl fmt::Display for PortBlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.count == 1 {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end())
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum StoredPorts {
+    Single(u16),
+    Block(Vec<u16>),
+}
+
+impl TryFrom<StoredPorts> for PortBlock {
+    type Error = String;
+
+    fn try_from(stored: StoredPorts) -> Result<Self, Self::Error> {
+        let ports = match stored {
+            StoredPorts::Single(port) => return Ok(Self::single(port)),
+            StoredPorts::Block(ports) => ports,
+        };
+        let Some(&start) = ports.first() else {
+            return Err("empty port block".to_string());
+        };
+        let consecutive = ports.iter().enumerate().all(|(i, &p)| u32::from(p) == u32::from(start) + i as u32);
+        if !consecutive {
+            return Err(format!("port block {:?} is not consecutive", ports));
+        }
+        Ok(Self { start, count: ports.len() as u16 })
+    }
+}
+
+impl From<PortBlock> for StoredPorts {
+    fn from(block: PortBlock) -> Self {
+        if block.count == 1 {
+            StoredPorts::Single(block.start)
+        } else {
+            StoredPorts::Block(block.ports().collect())
+        }
+    }
+}
+
+/// Inclusive range of ports, written in config as a bare port (`3005`) or
+/// a `"START-END"` string (`"3050-3060"`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "StoredRange", into = "StoredRange")]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl PortRange {
+    pub fn contains(&self, port: u16) -> bool {
+        (self.start..=self.end).contains(&port)
+    }
+
+    pub fn ports(&self) -> impl Iterator<Item = u16> {
+        self.start..=self.end
+    }
+}
+
+impl std::str::FromStr for PortRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let port = |part: &str| match part.trim().parse::<u16>() {
+            Ok(0) | Err(_) => Err(format!("invalid port range '{}': expected PORT or START-END with ports 1-65535", s)),
+            Ok(port) => Ok(port),
+        };
+        let (start, end) = match s.split_once('-') {
+            Some((start, end)) => (port(start)?, port(end)?),
+            None => {
+                let port = port(s)?;
+                (port, port)
+            }
+        };
+        if start > end {
+            return Err(format!("invalid port range '{}': start is after end", s));
+        }
+        Ok(Self { start, end })
+    }
+}
+
+/// `3005`, or `3050-3060`
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum StoredRange {
+    Single(u16),
+    Range(String),
+}
+
+impl TryFrom<StoredRange> for PortRange {
+    type Error = String;
+
+    fn try_from(stored: StoredRange) -> Result<Self, Self::Error> {
+        match stored {
+            StoredRange::Single(port) => port.to_string().parse(),
+            StoredRange::Range(range) => range.parse(),
+        }
+    }
+}
+
+impl From<PortRange> for StoredRange {
+    fn from(range: PortRange) -> Self {
+        if range.start == range.end {
+            StoredRange::Single(range.start)
+        } else {
+            StoredRange::Range(range.to_string())
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(from = "StoredRegistry", into = "StoredRegistry")]
 pub struct PortRegistry {
-    #[serde(flatten)]
-    pub allocations: HashMap<String, u16>,
+    pub allocations: HashMap<String, PortBlock>,
+    /// Managed worktrees created with `--no-port`
+    pub portless: BTreeSet<String>,
+    /// Where each branch's worktree was created. Entries written before
+    /// paths were recorded have none; see [`PortRegistry::worktree_path`].
+    pub paths: HashMap<String, PathBuf>,
+    /// When each branch's worktree was created or adopted. Entries written
+    /// before creation times were recorded have none.
+    pub created: HashMap<String, DateTime<Utc>>,
+    /// Which tool created each branch's worktree, e.g. `cli` or
+    /// `orchestrator:<session-id>`. Entries written before owners were
+    /// recorded have none.
+    pub owners: HashMap<String, String>,
+    /// Each branch's head as of its creation or last sync, which
+    /// [`health::check`] tells a rewritten history by. Entries written
+    /// before heads were kept here have none.
+    pub heads: HashMap<String, String>,
+}
+
+/// On disk, branch to `PORT`, `[PORTS]` or
+/// `{"ports": ..., "path": ..., "created_at": ..., "owner": ..., "head": ...}`,
+/// where `ports` is `null` for a portless worktree and `created_at`, `owner`
+/// and `head` are left out when unknown
+type StoredRegistry = BTreeMap<String, StoredEntry>;
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum StoredEntry {
+    Ports(PortBlock),
+    WithPath {
+        ports: Option<PortBlock>,
+        path: Option<PathBuf>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        created_at: Option<DateTime<Utc>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        owner: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        head: Option<String>,
+    },
+}
+
+impl From<StoredRegistry> for PortRegistry {
+    fn from(stored: StoredRegistry) -> Self {
+        let mut registry = PortRegistry::default();
+        for (branch, entry) in stored {
+            let ports = match entry {
+                StoredEntry::Ports(ports) => Some(ports),
+                StoredEntry::WithPath { ports, path, created_at, owner, head } => {
+                    if let Some(path) = path {
+                        registry.paths.insert(branch.clone(), path);
+                    }
+                    if let Some(created_at) = created_at {
+                        registry.created.insert(branch.clone(), created_at);
+                    }
+                    if let Some(owner) = owner {
+                        registry.owners.insert(branch.clone(), owner);
+                    }
+                    if let Some(head) = head {
+                        registry.heads.insert(branch.clone(), head);
+                    }
+                    ports
+                }
+            };
+            match ports {
+                Some(ports) => {
+                    registry.allocations.insert(branch, ports);
+                }
+                None => {
+                    registry.portless.insert(branch);
+                }
+            }
+        }
+        registry
+    }
+}
+
+impl From<PortRegistry> for StoredRegistry {
+    fn from(mut registry: PortRegistry) -> Self {
+        let entries: Vec<(String, Option<PortBlock>)> = registry
+            .allocations
+            .into_iter()
+            .map(|(branch, ports)| (branch, Some(ports)))
+            .chain(registry.portless.into_iter().map(|branch| (branch, None)))
+            .collect();
+        entries
+            .into_iter()
+            .map(|(branch, ports)| {
+                let path = registry.paths.remove(&branch);
+                let created_at = registry.created.remove(&branch);
+                let owner = registry.owners.remove(&branch);
+                let head = registry.heads.remove(&branch);
+                let entry = match (ports, path, created_at, owner, head) {
+                    (Some(ports), None, None, None, None) => StoredEntry::Ports(ports),
+                    (ports, path, created_at, owner, head) => StoredEntry::WithPath { ports, path, created_at, owner, head },
+                };
+                (branch, entry)
+            })
+            .collect()
+    }
 }
 
 impl PortRegistry {
@@ -14,8 +295,24 @@ impl PortRegistry {
         PathBuf::from(".hydra/wt-ports.json")
     }
 
+    /// Where the registry of the repository at `root` is kept:
+    /// `HYDRA_WT_REGISTRY` if set, relative to `root` unless absolute,
+    /// otherwise [`path`](Self::path)
+    pub fn path_at(root: &Path) -> PathBuf {
+        match config::EnvOverrides::from_env().registry {
+            Some(path) => root.join(path),
+            None => root.join(Self::path()),
+        }
+    }
+
+    /// Load the registry of the repository the current directory is in
     pub fn load() -> Result<Self> {
-        let path = Self::path();
+        Self::load_at(&config::project_root()?)
+    }
+
+    /// Load the registry belonging to the repository at `root`
+    pub fn load_at(root: &Path) -> Result<Self> {
+        let path = Self::path_at(root);
         if !path.exists() {
             return Ok(Self::default());
         }
@@ -27,54 +324,319 @@ impl PortRegistry {
     }
 
     pub fn save(&self) -> Result<()> {
-        let path = Self::path();
+        self.save_at(&config::project_root()?)
+    }
+
+    /// Save the registry belonging to the repository at `root`
+    pub fn save_at(&self, root: &Path) -> Result<()> {
+        let path = Self::path_at(root);
         let content = serde_json::to_string_pretty(self)
             .context("Failed to serialize port registry")?;
         std::fs::write(&path, content)
             .with_context(|| format!("Failed to write {}", path.display()))?;
+        debug!("wrote {}", path.display());
         Ok(())
     }
 
     pub fn allocate(&mut self, branch: &str, range_start: u16, range_end: u16) -> Result<u16> {
-        if let Some(&port) = self.allocations.get(branch) {
-            bail!("Branch '{}' already has port {} allocated", branch, port);
+        self.allocate_where(branch, range_start, range_end, |_| true)
+    }
+
+    /// Allocate the first unregistered port in range that `available` accepts,
+    /// e.g. one nothing else is already listening on
+    pub fn allocate_where(
+        &mut self,
+        branch: &str,
+        range_start: u16,
+        range_end: u16,
+        available: impl Fn(u16) -> bool,
+    ) -> Result<u16> {
+        self.allocate_block_where(branch, 1, range_start, range_end, available)
+            .map(|block| block.start)
+    }
+
+    /// Reserve `count` consecutive ports for `branch`
+    pub fn allocate_block(&mut self, branch: &str, count: u16, range_start: u16, range_end: u16) -> Result<PortBlock> {
+        self.allocate_block_where(branch, count, range_start, range_end, |_| true)
+    }
+
+    /// Reserve the first run of `count` consecutive unregistered ports in
+    /// range that `available` accepts
+    pub fn allocate_block_where(
+        &mut self,
+        branch: &str,
+        count: u16,
+        range_start: u16,
+        range_end: u16,
+        available: impl Fn(u16) -> bool,
+    ) -> Result<PortBlock> {
+        if let Some(block) = self.allocations.get(branch) {
+            bail!("Branch '{}' already has port {} allocated", branch, block);
+        }
+        if count == 0 {
+            bail!("Port block size must be at least 1");
         }
 
         let used_ports: std::collections::HashSet<u16> =
-            self.allocations.values().copied().collect();
+            self.allocations.values().flat_map(|block| block.ports()).collect();
 
-        for port in range_start..=range_end {
-            if !used_ports.contains(&port) {
-                self.allocations.insert(branch.to_string(), port);
-                return Ok(port);
+        let mut start = u32::from(range_start);
+        while start + u32::from(count) - 1 <= u32::from(range_end) {
+            let block = PortBlock { start: start as u16, count };
+            match block.ports().find(|p| used_ports.contains(p) || !available(*p)) {
+                // Resume past the port that broke this run
+                Some(taken) => start = u32::from(taken) + 1,
+                None => {
+                    self.allocations.insert(branch.to_string(), block);
+                    return Ok(block);
+                }
             }
         }
 
-        bail!("No free ports in range {}-{}", range_start, range_end);
+        Err(WtError::PortRangeExhausted {
+            start: range_start,
+            end: range_end,
+            count,
+            holders: self.oldest_holders(range_start, range_end),
+        })
     }
 
-    pub fn free(&mut self, branch: &str) -> Result<u16> {
+    /// Up to [`HOLDERS_SHOWN`] allocations in `range_start..=range_end`,
+    /// oldest first, as `feature (3001, 12d)`; entries without a creation
+    /// time count as oldest
+    fn oldest_holders(&self, range_start: u16, range_end: u16) -> Vec<String> {
+        let mut holders: Vec<(&String, &PortBlock)> = self
+            .allocations
+            .iter()
+            .filter(|(_, block)| block.start <= range_end && block.end() >= range_start)
+            .collect();
+        holders.sort_by_key(|(branch, block)| (self.created.get(*branch), block.start));
+        let now = Utc::now();
+        let mut shown: Vec<String> = holders
+            .iter()
+            .take(HOLDERS_SHOWN)
+            .map(|(branch, block)| match self.created.get(*branch) {
+                Some(created) => format!("{} ({}, {})", branch, block, age::describe(now - *created)),
+                None => format!("{} ({})", branch, block),
+            })
+            .collect();
+        if holders.len() > HOLDERS_SHOWN {
+            shown.push(format!("and {} more", holders.len() - HOLDERS_SHOWN));
+        }
+        shown
+    }
+
+    /// Free the ports of worktrees whose directory and branch are both
+    /// gone, returning what was freed. A directory that is merely missing,
+    /// e.g. on an unmounted drive, keeps its ports while the branch exists.
+    pub fn recycle_missing(&mut self, cfg: &WtConfig, repo_root: &Path) -> Result<Vec<(String, PortBlock)>> {
+        let mut branches: Vec<String> = self.allocations.keys().cloned().collect();
+        branches.sort();
+        let mut recycled = Vec::new();
+        for branch in branches {
+            let path = self.worktree_path(cfg, repo_root, &branch);
+            if worktree::exists(&path) || worktree::branch_exists_in(repo_root, &branch)? {
+                continue;
+            }
+            let block = self.free(&branch)?;
+            info!(
+                "Recycled port {} of '{}': its worktree at {} and its branch are gone",
+                block, branch, path.display()
+            );
+            recycled.push((branch, block));
+        }
+        Ok(recycled)
+    }
+
+    /// [`allocate_for`](Self::allocate_for), but with `ports.auto_recycle`
+    /// a full range is retried once after [`recycle_missing`](Self::recycle_missing)
+    pub fn allocate_recycling(&mut self, branch: &str, cfg: &WtConfig, repo_root: &Path) -> Result<PortBlock> {
+        match self.allocate_for(branch, &cfg.ports) {
+            Err(WtError::PortRangeExhausted { .. }) if cfg.ports.auto_recycle => {
+                if self.recycle_missing(cfg, repo_root)?.is_empty() {
+                    debug!("nothing to recycle in the port registry");
+                }
+                self.allocate_for(branch, &cfg.ports)
+            }
+            result => result,
+        }
+    }
+
+    /// Reserve a block for `branch` as `[ports]` configures it, skipping
+    /// reserved ports and ports something is already listening on
+    pub fn allocate_for(&mut self, branch: &str, cfg: &PortsConfig) -> Result<PortBlock> {
+        let check_hosts = cfg.check_addrs()?;
+        self.allocate_block_where(branch, cfg.count, cfg.range_start, cfg.range_end, |p| {
+            !cfg.is_reserved(p) && probe::is_free(p, &check_hosts)
+        })
+    }
+
+    /// Reserve the block starting at exactly `port` for `branch`, sized as
+    /// `[ports]` configures. Fails if any port of it is outside the range,
+    /// reserved, allocated to another branch, or bound on a check host.
+    pub fn allocate_specific(&mut self, branch: &str, port: u16, cfg: &PortsConfig) -> Result<PortBlock> {
+        if let Some(block) = self.allocations.get(branch) {
+            bail!("Branch '{}' already has port {} allocated", branch, block);
+        }
+        if cfg.count == 0 {
+            bail!("Port block size must be at least 1");
+        }
+        let Some(end) = port.checked_add(cfg.count - 1) else {
+            bail!("Port {} with a block of {} runs past 65535", port, cfg.count);
+        };
+        let block = PortBlock { start: port, count: cfg.count };
+        if port < cfg.range_start || end > cfg.range_end {
+            bail!("Port {} is outside the configured range {}-{}", block, cfg.range_start, cfg.range_end);
+        }
+
+        let check_hosts = cfg.check_addrs()?;
+        for p in block.ports() {
+            if cfg.is_reserved(p) {
+                bail!("Port {} is reserved in ports.reserved", p);
+            }
+            if let Some((owner, _)) = self.allocations.iter().find(|(_, b)| b.contains(p)) {
+                bail!("Port {} is already allocated to '{}'", p, owner);
+            }
+            if let Some(host) = check_hosts.iter().find(|&&h| probe::bind_check(h, p) == probe::BindCheck::InUse) {
+                bail!("Port {} is already in use on {}", p, host);
+            }
+        }
+
+        self.allocations.insert(branch.to_string(), block);
+        Ok(block)
+    }
+
+    /// Free every port allocated to `branch`
+    pub fn free(&mut self, branch: &str) -> Result<PortBlock> {
+        self.paths.remove(branch);
+        self.created.remove(branch);
+        self.owners.remove(branch);
+        self.heads.remove(branch);
+        self.portless.remove(branch);
         self.allocations
             .remove(branch)
-            .ok_or_else(|| anyhow::anyhow!("No port allocated for branch '{}'", branch))
+            .ok_or_else(|| anyhow::anyhow!("No port allocated for branch '{}'", branch).into())
     }
 
+    /// First port allocated to `branch`
     pub fn get(&self, branch: &str) -> Option<u16> {
+        self.allocations.get(branch).map(|block| block.start)
+    }
+
+    pub fn block(&self, branch: &str) -> Option<PortBlock> {
         self.allocations.get(branch).copied()
     }
 
+    /// Register `branch` without allocating it any ports
+    pub fn add_portless(&mut self, branch: &str) {
+        self.portless.insert(branch.to_string());
+    }
+
+    /// Whether `branch` has an entry, with or without ports
+    pub fn is_managed(&self, branch: &str) -> bool {
+        self.allocations.contains_key(branch) || self.portless.contains(branch)
+    }
+
+    /// Every managed branch, sorted
+    pub fn branches(&self) -> Vec<&String> {
+        let mut branches: Vec<&String> = self.allocations.keys().chain(&self.portless).collect();
+        branches.sort();
+        branches
+    }
+
+    /// Drop the entry of `branch`, whatever it holds, returning its ports
+    pub fn unregister(&mut self, branch: &str) -> Option<PortBlock> {
+        self.paths.remove(branch);
+        self.created.remove(branch);
+        self.owners.remove(branch);
+        self.heads.remove(branch);
+        self.portless.remove(branch);
+        self.allocations.remove(branch)
+    }
+
+    /// Record where the worktree of `branch` lives
+    pub fn set_path(&mut self, branch: &str, path: &Path) {
+        self.paths.insert(branch.to_string(), path.to_path_buf());
+    }
+
+    /// Record when the worktree of `branch` was created
+    pub fn set_created(&mut self, branch: &str, at: DateTime<Utc>) {
+        self.created.insert(branch.to_string(), at);
+    }
+
+    /// When the worktree of `branch` was created, if that was recorded
+    pub fn created_at(&self, branch: &str) -> Option<DateTime<Utc>> {
+        self.created.get(branch).copied()
+    }
+
+    /// Record which tool created the worktree of `branch`
+    pub fn set_owner(&mut self, branch: &str, owner: &str) {
+        self.owners.insert(branch.to_string(), owner.to_string());
+    }
+
+    /// Which tool created the worktree of `branch`, [`UNKNOWN_OWNER`] if
+    /// that wasn't recorded
+    pub fn owner(&self, branch: &str) -> &str {
+        self.owners.get(branch).map_or(UNKNOWN_OWNER, String::as_str)
+    }
+
+    /// Whether `branch` was recorded as created by `owner`, see
+    /// [`owner_matches`]. Entries without a recorded owner match no
+    /// owner, not even `unknown`, so nothing filtered by owner touches them.
+    pub fn is_owned_by(&self, branch: &str, owner: &str) -> bool {
+        self.owners.get(branch).is_some_and(|recorded| owner_matches(recorded, owner))
+    }
+
+    /// Record the current head of `branch` in the repository at `repo`
+    pub fn record_head(&mut self, repo: &Path, branch: &str) -> Result<()> {
+        let head = health::rev_parse(repo, &format!("refs/heads/{}", branch))?
+            .ok_or_else(|| anyhow::anyhow!("Branch '{}' does not exist", branch))?;
+        self.heads.insert(branch.to_string(), head);
+        Ok(())
+    }
+
+    /// Head of `branch` as of its creation or last sync, if recorded
+    pub fn head(&self, branch: &str) -> Option<&str> {
+        self.heads.get(branch).map(String::as_str)
+    }
+
+    /// Where the worktree of `branch` in the repository at `repo_root` is:
+    /// the path recorded when it was created, or where worktrees went before
+    /// paths were recorded
+    pub fn worktree_path(&self, cfg: &WtConfig, repo_root: &Path, branch: &str) -> PathBuf {
+        match self.paths.get(branch) {
+            Some(path) => path.clone(),
+            None => cfg.legacy_worktree_path_in(repo_root, branch),
+        }
+    }
+
+    /// Branches with the first port of their block
     pub fn list(&self) -> impl Iterator<Item = (&String, &u16)> {
+        self.allocations.iter().map(|(branch, block)| (branch, &block.start))
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = (&String, &PortBlock)> {
         self.allocations.iter()
     }
 
+    /// Number of ports allocated across all branches
+    pub fn ports_used(&self) -> usize {
+        self.allocations.values().map(|block| usize::from(block.count)).sum()
+    }
+
     pub fn init() -> Result<()> {
-        let path = Self::path();
+        Self::init_at(&config::project_root()?)
+    }
+
+    /// Create an empty registry for the repository at `root` unless it has one
+    pub fn init_at(root: &Path) -> Result<()> {
+        let path = Self::path_at(root);
         if path.exists() {
             return Ok(());
         }
         let registry = Self::default();
-        registry.save()?;
-        println!("Created {}", path.display());
+        registry.save_at(root)?;
+        info!("Created {}", config::display_path(&path).display());
         Ok(())
     }
 }
@@ -82,6 +644,7 @@ impl PortRegistry {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::scratch::{self, git};
 
     #[test]
     fn test_allocate_port() {
@@ -116,8 +679,9 @@ mod tests {
         registry.allocate("feature-a", 3000, 3001).unwrap();
         registry.allocate("feature-b", 3000, 3001).unwrap();
         let result = registry.allocate("feature-c", 3000, 3001);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("No free ports"));
+        assert!(matches!(result, Err(WtError::PortRangeExhausted { start: 3000, end: 3001, count: 1, .. })));
+        let message = result.unwrap_err().to_string();
+        assert!(message.starts_with("No free ports in range 3000-3001; held longest by feature-a (3000), feature-b (3001)"), "{}", message);
     }
 
     #[test]
@@ -125,7 +689,7 @@ mod tests {
         let mut registry = PortRegistry::default();
         registry.allocate("feature-a", 3000, 3010).unwrap();
         let freed = registry.free("feature-a").unwrap();
-        assert_eq!(freed, 3000);
+        assert_eq!(freed, PortBlock::single(3000));
         assert_eq!(registry.get("feature-a"), None);
     }
 
@@ -228,4 +792,305 @@ mod tests {
         assert!(result.is_err());
         assert!(result.unwrap_err().to_string().contains("No port allocated"));
     }
+
+    #[test]
+    fn test_allocate_where_skips_unavailable() {
+        let mut registry = PortRegistry::default();
+        registry.allocate("feature-a", 3000, 3010).unwrap();
+
+        // 3001 is taken by something outside the registry
+        let port = registry.allocate_where("feature-b", 3000, 3010, |p| p != 3001).unwrap();
+        assert_eq!(port, 3002);
+
+        let result = registry.allocate_where("feature-c", 3000, 3010, |_| false);
+        assert!(result.unwrap_err().to_string().contains("No free ports"));
+        assert_eq!(registry.get("feature-c"), None);
+    }
+
+    #[test]
+    fn test_allocate_block() {
+        let mut registry = PortRegistry::default();
+        registry.allocate("feature-a", 3000, 3010).unwrap();
+        let block = registry.allocate_block("feature-b", 3, 3000, 3010).unwrap();
+        assert_eq!(block, PortBlock { start: 3001, count: 3 });
+        assert_eq!(block.to_string(), "3001-3003");
+        assert_eq!(registry.get("feature-b"), Some(3001));
+        assert_eq!(registry.ports_used(), 4);
+
+        // Single allocations skip every port of the block
+        assert_eq!(registry.allocate("feature-c", 3000, 3010).unwrap(), 3004);
+    }
+
+    #[test]
+    fn test_allocate_block_skips_short_gaps() {
+        let mut registry = PortRegistry::default();
+        // 3000-3001 is only two ports wide before 3002, which is in use elsewhere
+        let block = registry
+            .allocate_block_where("feature-a", 3, 3000, 3010, |p| p != 3002)
+            .unwrap();
+        assert_eq!(block.start, 3003);
+
+        let result = registry.allocate_block("feature-b", 3, 3006, 3007);
+        assert!(result.unwrap_err().to_string().contains("No 3 consecutive free ports"));
+        assert!(registry.allocate_block("feature-c", 0, 3000, 3010).is_err());
+    }
+
+    #[test]
+    fn test_free_block_frees_all_ports() {
+        let mut registry = PortRegistry::default();
+        registry.allocate_block("feature-a", 3, 3000, 3010).unwrap();
+        assert_eq!(registry.free("feature-a").unwrap(), PortBlock { start: 3000, count: 3 });
+        assert_eq!(registry.allocate_block("feature-b", 3, 3000, 3010).unwrap().start, 3000);
+    }
+
+    #[test]
+    fn test_block_serialization() {
+        let mut registry = PortRegistry::default();
+        registry.allocate("single", 3000, 3010).unwrap();
+        registry.allocate_block("multi", 3, 3000, 3010).unwrap();
+
+        let json: serde_json::Value = serde_json::to_value(&registry).unwrap();
+        assert_eq!(json, serde_json::json!({"single": 3000, "multi": [3001, 3002, 3003]}));
+
+        let back: PortRegistry = serde_json::from_value(json).unwrap();
+        assert_eq!(back.block("multi"), Some(PortBlock { start: 3001, count: 3 }));
+        assert!(serde_json::from_str::<PortRegistry>(r#"{"a": [3000, 3002]}"#).is_err());
+        assert!(serde_json::from_str::<PortRegistry>(r#"{"a": []}"#).is_err());
+    }
+
+    #[test]
+    fn test_registry_paths() {
+        let old: PortRegistry = serde_json::from_str(r#"{"feature/x": 3001, "new": {"ports": [3002, 3003], "path": "/wts/repo-new"}}"#).unwrap();
+        assert_eq!(old.block("new"), Some(PortBlock { start: 3002, count: 2 }));
+
+        let cfg = WtConfig::default();
+        let root = Path::new("/src/repo");
+        assert_eq!(old.worktree_path(&cfg, root, "new"), PathBuf::from("/wts/repo-new"));
+        assert_eq!(old.worktree_path(&cfg, root, "feature/x"), PathBuf::from("/src/feature/x"));
+
+        let json = serde_json::to_value(&old).unwrap();
+        assert_eq!(json, serde_json::json!({"feature/x": 3001, "new": {"ports": [3002, 3003], "path": "/wts/repo-new"}}));
+
+        let mut registry = old;
+        registry.free("new").unwrap();
+        assert!(registry.paths.is_empty());
+
+        registry.add_portless("docs");
+        registry.set_path("docs", Path::new("/wts/docs"));
+        let json = serde_json::to_value(&registry).unwrap();
+        assert_eq!(json, serde_json::json!({"feature/x": 3001, "docs": {"ports": null, "path": "/wts/docs"}}));
+        let back: PortRegistry = serde_json::from_value(json).unwrap();
+        assert!(back.is_managed("docs") && back.block("docs").is_none());
+        assert_eq!(back.branches(), vec!["docs", "feature/x"]);
+        assert_eq!(back.worktree_path(&cfg, root, "docs"), PathBuf::from("/wts/docs"));
+
+        let mut back = back;
+        assert_eq!(back.unregister("docs"), None);
+        assert_eq!(back.unregister("feature/x"), Some(3001.into()));
+        assert!(back.branches().is_empty() && back.paths.is_empty());
+    }
+
+    #[test]
+    fn test_registry_created_at() {
+        // Written before creation times were recorded
+        let legacy = r#"{"old": 3001, "docs": {"ports": null, "path": "/wts/docs"}}"#;
+        let mut registry: PortRegistry = serde_json::from_str(legacy).unwrap();
+        assert_eq!((registry.created_at("old"), registry.created_at("docs")), (None, None));
+
+        let at: DateTime<Utc> = "2026-03-01T12:00:00Z".parse().unwrap();
+        registry.allocate("new", 3002, 3010).unwrap();
+        registry.set_created("new", at);
+        registry.set_created("docs", at);
+        let json = serde_json::to_value(&registry).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "old": 3001,
+                "new": {"ports": 3002, "path": null, "created_at": "2026-03-01T12:00:00Z"},
+                "docs": {"ports": null, "path": "/wts/docs", "created_at": "2026-03-01T12:00:00Z"},
+            })
+        );
+
+        let mut back: PortRegistry = serde_json::from_value(json).unwrap();
+        assert_eq!(back.created_at("new"), Some(at));
+        assert_eq!(back.created_at("docs"), Some(at));
+        assert_eq!(back.created_at("old"), None);
+        assert_eq!(back.get("new"), Some(3002));
+        back.free("new").unwrap();
+        back.unregister("docs");
+        assert!(back.created.is_empty());
+    }
+
+    #[test]
+    fn test_registry_owner() {
+        // Written before owners were recorded
+        let legacy = r#"{"old": 3001, "docs": {"ports": null, "path": "/wts/docs"}}"#;
+        let mut registry: PortRegistry = serde_json::from_str(legacy).unwrap();
+        assert_eq!(registry.owner("old"), UNKNOWN_OWNER);
+        assert!(!registry.is_owned_by("old", "unknown"));
+
+        registry.allocate("agent", 3002, 3010).unwrap();
+        registry.set_owner("agent", "orchestrator:4f2a");
+        registry.set_owner("docs", "cli");
+        let json = serde_json::to_value(&registry).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "old": 3001,
+                "agent": {"ports": 3002, "path": null, "owner": "orchestrator:4f2a"},
+                "docs": {"ports": null, "path": "/wts/docs", "owner": "cli"},
+            })
+        );
+
+        let mut back: PortRegistry = serde_json::from_value(json).unwrap();
+        assert!(back.is_owned_by("agent", "orchestrator"));
+        assert!(back.is_owned_by("agent", "orchestrator:4f2a"));
+        assert!(!back.is_owned_by("agent", "orch"));
+        assert!(!back.is_owned_by("docs", "orchestrator"));
+        assert!(owner_matches("cli", "cli") && !owner_matches("clippy", "cli"));
+        back.free("agent").unwrap();
+        back.unregister("docs");
+        assert!(back.owners.is_empty());
+    }
+
+    #[test]
+    fn test_registry_head() {
+        let repo = scratch::repo("ports");
+        scratch::commit_all(&repo, "init");
+        git(&repo, &["branch", "docs"]);
+        let main = git(&repo, &["rev-parse", "main"]);
+
+        // Written before heads were recorded
+        let legacy = r#"{"old": 3001, "docs": {"ports": null, "path": "/wts/docs"}}"#;
+        let mut registry: PortRegistry = serde_json::from_str(legacy).unwrap();
+        assert_eq!((registry.head("old"), registry.head("docs")), (None, None));
+
+        registry.record_head(&repo, "docs").unwrap();
+        assert!(registry.record_head(&repo, "no-such-branch").is_err());
+        let json = serde_json::to_value(&registry).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "old": 3001,
+                "docs": {"ports": null, "path": "/wts/docs", "head": main},
+            })
+        );
+
+        // A head goes with the rest of the entry
+        let mut back: PortRegistry = serde_json::from_value(json).unwrap();
+        assert_eq!(back.head("docs"), Some(main.as_str()));
+        back.heads.insert("old".to_string(), main.clone());
+        back.free("old").unwrap();
+        back.unregister("docs");
+        assert!(back.heads.is_empty());
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_allocate_recycling() {
+        let repo = scratch::repo("ports");
+        scratch::commit_all(&repo, "init");
+        git(&repo, &["branch", "unmounted"]);
+        let mut cfg = WtConfig::default();
+        cfg.ports = PortsConfig { range_start: 3000, range_end: 3001, check_hosts: Vec::new(), ..cfg.ports };
+
+        // Neither directory exists, but only `gone` lost its branch too
+        let mut registry = PortRegistry::default();
+        for branch in ["gone", "unmounted"] {
+            registry.allocate_for(branch, &cfg.ports).unwrap();
+            registry.set_path(branch, &repo.join("wts").join(branch));
+        }
+        let err = registry.allocate_recycling("new", &cfg, &repo).unwrap_err();
+        assert!(err.to_string().contains("held longest by gone (3000), unmounted (3001)"), "{}", err);
+        assert_eq!(registry.ports_used(), 2);
+
+        cfg.ports.auto_recycle = true;
+        assert_eq!(registry.allocate_recycling("new", &cfg, &repo).unwrap(), PortBlock::single(3000));
+        assert!(!registry.is_managed("gone"));
+        assert_eq!(registry.get("unmounted"), Some(3001));
+        git(&repo, &["branch", "new"]);
+        assert!(matches!(
+            registry.allocate_recycling("newer", &cfg, &repo),
+            Err(WtError::PortRangeExhausted { .. })
+        ));
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_parse_port_range() {
+        assert_eq!("3005".parse::<PortRange>().unwrap(), PortRange { start: 3005, end: 3005 });
+        assert_eq!("3050-3060".parse::<PortRange>().unwrap(), PortRange { start: 3050, end: 3060 });
+        assert_eq!(" 3050 - 3060 ".parse::<PortRange>().unwrap(), PortRange { start: 3050, end: 3060 });
+
+        for bad in ["", "-", "30x5", "3050-", "-3060", "3060-3050", "3050-3060-3070", "0", "70000", "3050..3060"] {
+            assert!(bad.parse::<PortRange>().is_err(), "{:?} parsed", bad);
+        }
+        let err = "3060-3050".parse::<PortRange>().unwrap_err();
+        assert!(err.contains("start is after end"), "{}", err);
+    }
+
+    #[test]
+    fn test_port_range_serialization() {
+        let ranges: Vec<PortRange> = serde_json::from_str(r#"[3005, "3050-3060", "3010"]"#).unwrap();
+        assert_eq!(ranges[0], PortRange { start: 3005, end: 3005 });
+        assert_eq!(ranges[2], PortRange { start: 3010, end: 3010 });
+        assert_eq!(serde_json::to_string(&ranges).unwrap(), r#"[3005,"3050-3060",3010]"#);
+        assert!(serde_json::from_str::<Vec<PortRange>>(r#"["3050-30x0"]"#).is_err());
+    }
+
+    #[test]
+    fn test_allocate_for_skips_reserved() {
+        let mut cfg = crate::config::WtConfig::default().ports;
+        cfg.range_start = 3000;
+        cfg.range_end = 3010;
+        cfg.count = 2;
+        cfg.check_hosts = Vec::new();
+        cfg.reserved = vec!["3001".parse().unwrap(), "3003-3004".parse().unwrap()];
+
+        let mut registry = PortRegistry::default();
+        assert_eq!(registry.allocate_for("a", &cfg).unwrap(), PortBlock { start: 3005, count: 2 });
+        cfg.count = 1;
+        assert_eq!(registry.allocate_for("b", &cfg).unwrap(), PortBlock::single(3000));
+        assert_eq!(registry.allocate_for("c", &cfg).unwrap(), PortBlock::single(3002));
+        assert_eq!(registry.allocate_for("d", &cfg).unwrap(), PortBlock::single(3007));
+    }
+
+    #[test]
+    fn test_allocate_specific() {
+        let mut cfg = crate::config::WtConfig::default().ports;
+        cfg.range_start = 3000;
+        cfg.range_end = 3010;
+        cfg.check_hosts = Vec::new();
+        cfg.reserved = vec!["3005".parse().unwrap()];
+
+        let mut registry = PortRegistry::default();
+        assert_eq!(registry.allocate_specific("a", 3007, &cfg).unwrap(), PortBlock::single(3007));
+        // Sequential allocation goes around it
+        assert_eq!(registry.allocate_for("b", &cfg).unwrap(), PortBlock::single(3000));
+
+        let err = |registry: &mut PortRegistry, branch, port, cfg: &PortsConfig| {
+            registry.allocate_specific(branch, port, cfg).unwrap_err().to_string()
+        };
+        assert!(err(&mut registry, "c", 3007, &cfg).contains("already allocated to 'a'"));
+        assert!(err(&mut registry, "c", 3011, &cfg).contains("outside the configured range 3000-3010"));
+        assert!(err(&mut registry, "c", 3005, &cfg).contains("reserved"));
+        assert!(err(&mut registry, "a", 3008, &cfg).contains("already has port 3007"));
+        cfg.count = 2;
+        assert!(err(&mut registry, "c", 3006, &cfg).contains("Port 3007 is already allocated"));
+        assert!(err(&mut registry, "c", 3010, &cfg).contains("3010-3011 is outside"));
+        assert_eq!(registry.allocations.len(), 2);
+    }
+
+    #[test]
+    fn test_allocate_specific_refuses_bound_port() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut cfg = crate::config::WtConfig::default().ports;
+        cfg.range_start = port;
+        cfg.range_end = port;
+        cfg.check_hosts = vec!["127.0.0.1".to_string()];
+
+        let err = PortRegistry::default().allocate_specific("a", port, &cfg).unwrap_err().to_string();
+        assert_eq!(err, format!("Port {} is already in use on 127.0.0.1", port));
+    }
 }
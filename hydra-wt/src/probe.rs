@@ -0,0 +1,263 @@
+//! Loopback port probing.
+//!
+//! Dev servers differ in which address family they bind: some take 127.0.0.1,
+//! some ::1, some a dual-stack [::]. Allocation and `status --check` therefore
+//! look at every configured host and report per family. Hosts whose family
+//! the machine doesn't support (IPv6 disabled, v4-only containers) are
+//! reported as unavailable instead of failing the check.
+
+use anyhow::{bail, Result};
+use std::fmt;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressFamily {
+    V4,
+    V6,
+}
+
+impl AddressFamily {
+    pub fn of(host: IpAddr) -> Self {
+        match host {
+            IpAddr::V4(_) => Self::V4,
+            IpAddr::V6(_) => Self::V6,
+        }
+    }
+
+    fn loopback(self) -> IpAddr {
+        match self {
+            Self::V4 => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            Self::V6 => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        }
+    }
+
+    /// Whether this machine can bind the family's loopback address at all
+    pub fn is_supported(self) -> bool {
+        TcpListener::bind(SocketAddr::new(self.loopback(), 0)).is_ok()
+    }
+}
+
+impl fmt::Display for AddressFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::V4 => write!(f, "IPv4"),
+            Self::V6 => write!(f, "IPv6"),
+        }
+    }
+}
+
+/// Connect timeout per address family
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    pub v4: Duration,
+    pub v6: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            v4: Duration::from_millis(250),
+            v6: Duration::from_millis(250),
+        }
+    }
+}
+
+impl Timeouts {
+    pub fn for_family(&self, family: AddressFamily) -> Duration {
+        match family {
+            AddressFamily::V4 => self.v4,
+            AddressFamily::V6 => self.v6,
+        }
+    }
+}
+
+/// Outcome of trying to bind a port on one host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindCheck {
+    Free,
+    InUse,
+    /// The host's address family isn't available on this machine
+    Unsupported,
+}
+
+/// Outcome of connecting to a port on one host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbeResult {
+    Listening,
+    Closed,
+    /// The host's address family isn't available, or the connect timed out
+    Unreachable,
+}
+
+impl fmt::Display for ProbeResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Listening => write!(f, "listening"),
+            Self::Closed => write!(f, "closed"),
+            Self::Unreachable => write!(f, "unreachable"),
+        }
+    }
+}
+
+/// Probe result for one host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HostProbe {
+    pub host: IpAddr,
+    pub family: AddressFamily,
+    pub result: ProbeResult,
+}
+
+/// Parse a configured host; must be an IP literal
+pub fn parse_host(host: &str) -> Result<IpAddr> {
+    // Accept the bracketed form people copy out of URLs
+    let trimmed = host.trim().trim_start_matches('[').trim_end_matches(']');
+    match trimmed.parse() {
+        Ok(ip) => Ok(ip),
+        Err(_) => bail!("Invalid host '{}': expected an IP address such as 127.0.0.1 or ::1", host),
+    }
+}
+
+pub fn parse_hosts(hosts: &[String]) -> Result<Vec<IpAddr>> {
+    hosts.iter().map(|h| parse_host(h)).collect()
+}
+
+/// Address to connect to for a bind address: a wildcard bind is reached
+/// through the loopback address of the same family
+pub fn connect_host(host: IpAddr) -> IpAddr {
+    if host.is_unspecified() {
+        AddressFamily::of(host).loopback()
+    } else {
+        host
+    }
+}
+
+/// `http://<host>:<port>`, bracketing IPv6 literals
+pub fn port_url(host: &str, port: u16) -> String {
+    match parse_host(host).map(connect_host) {
+        Ok(IpAddr::V6(ip)) => format!("http://[{}]:{}", ip, port),
+        Ok(IpAddr::V4(ip)) => format!("http://{}:{}", ip, port),
+        Err(_) => format!("http://{}:{}", host, port),
+    }
+}
+
+pub fn bind_check(host: IpAddr, port: u16) -> BindCheck {
+    match TcpListener::bind(SocketAddr::new(host, port)) {
+        Ok(_) => BindCheck::Free,
+        Err(_) if !AddressFamily::of(host).is_supported() => BindCheck::Unsupported,
+        Err(_) => BindCheck::InUse,
+    }
+}
+
+/// A port is free when no host reports it in use. Hosts of an unsupported
+/// family don't count against it.
+pub fn is_free(port: u16, hosts: &[IpAddr]) -> bool {
+    hosts.iter().all(|&host| bind_check(host, port) != BindCheck::InUse)
+}
+
+pub fn probe(host: IpAddr, port: u16, timeouts: &Timeouts) -> HostProbe {
+    let family = AddressFamily::of(host);
+    let addr = SocketAddr::new(connect_host(host), port);
+    let result = match TcpStream::connect_timeout(&addr, timeouts.for_family(family)) {
+        Ok(_) => ProbeResult::Listening,
+        Err(e) if e.kind() == ErrorKind::ConnectionRefused => ProbeResult::Closed,
+        Err(_) => ProbeResult::Unreachable,
+    };
+    HostProbe { host, family, result }
+}
+
+pub fn probe_all(port: u16, hosts: &[IpAddr], timeouts: &Timeouts) -> Vec<HostProbe> {
+    hosts.iter().map(|&host| probe(host, port, timeouts)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V4: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    const V6: IpAddr = IpAddr::V6(Ipv6Addr::LOCALHOST);
+
+    fn results(port: u16) -> Vec<ProbeResult> {
+        probe_all(port, &[V4, V6], &Timeouts::default())
+            .into_iter()
+            .map(|p| p.result)
+            .collect()
+    }
+
+    #[test]
+    fn test_v4_only_listener() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        assert_eq!(bind_check(V4, port), BindCheck::InUse);
+        assert!(!is_free(port, &[V4, V6]));
+        if !AddressFamily::V6.is_supported() {
+            eprintln!("IPv6 unavailable, skipping IPv6 half");
+            return;
+        }
+        assert!(is_free(port, &[V6]));
+        assert_eq!(results(port), vec![ProbeResult::Listening, ProbeResult::Closed]);
+    }
+
+    #[test]
+    fn test_v6_only_listener() {
+        if !AddressFamily::V6.is_supported() {
+            eprintln!("IPv6 unavailable, skipping");
+            return;
+        }
+        let listener = TcpListener::bind((Ipv6Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // A 127.0.0.1-only check would hand this port out
+        assert!(is_free(port, &[V4]));
+        assert!(!is_free(port, &[V4, V6]));
+        assert_eq!(results(port), vec![ProbeResult::Closed, ProbeResult::Listening]);
+    }
+
+    #[test]
+    fn test_dual_stack_listener() {
+        if !AddressFamily::V6.is_supported() {
+            eprintln!("IPv6 unavailable, skipping");
+            return;
+        }
+        let listener = TcpListener::bind((Ipv6Addr::UNSPECIFIED, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        if probe(V4, port, &Timeouts::default()).result != ProbeResult::Listening {
+            eprintln!("[::] is v6-only here (bindv6only=1), skipping");
+            return;
+        }
+
+        assert_eq!(bind_check(V4, port), BindCheck::InUse);
+        assert_eq!(bind_check(V6, port), BindCheck::InUse);
+        assert_eq!(results(port), vec![ProbeResult::Listening, ProbeResult::Listening]);
+    }
+
+    #[test]
+    fn test_free_port_on_both_families() {
+        let port = {
+            let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        assert!(is_free(port, &[V4, V6]));
+        assert_eq!(probe(V4, port, &Timeouts::default()).result, ProbeResult::Closed);
+    }
+
+    #[test]
+    fn test_parse_hosts() {
+        let hosts = parse_hosts(&["127.0.0.1".into(), "::1".into(), "[::1]".into()]).unwrap();
+        assert_eq!(hosts, vec![V4, V6, V6]);
+        assert!(parse_host("localhost").is_err());
+        assert_eq!(connect_host("0.0.0.0".parse().unwrap()), V4);
+        assert_eq!(connect_host("::".parse().unwrap()), V6);
+    }
+
+    #[test]
+    fn test_port_url() {
+        assert_eq!(port_url("127.0.0.1", 3001), "http://127.0.0.1:3001");
+        assert_eq!(port_url("::1", 3001), "http://[::1]:3001");
+        assert_eq!(port_url("0.0.0.0", 3001), "http://127.0.0.1:3001");
+        assert_eq!(port_url("::", 3001), "http://[::1]:3001");
+        assert_eq!(port_url("dev.local", 3001), "http://dev.local:3001");
+    }
+}
@@ -0,0 +1,195 @@
+//! Cleanup of registry entries left behind by deleted worktrees
+//!
+//! Worktree directories get `rm -rf`'d by hand and branches get deleted on
+//! the remote, but the port registry only shrinks through `hydra-wt remove`.
+//! Pruning cross-references the registry with `git worktree list` and the
+//! local branches, drops entries that no longer point at anything, and frees
+//! their ports.
+
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+use crate::config::WtConfig;
+use crate::ports::{PortBlock, PortRegistry};
+use crate::worktree;
+
+/// Why a registry entry is stale
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StaleReason {
+    /// The worktree directory is gone (the branch may or may not still exist)
+    WorktreeMissing,
+    /// The branch is gone but its worktree directory is still on disk
+    BranchDeleted,
+}
+
+impl StaleReason {
+    pub fn describe(&self, path: &Path) -> String {
+        match self {
+            StaleReason::WorktreeMissing => format!("worktree missing at {}", path.display()),
+            StaleReason::BranchDeleted => format!("branch deleted, worktree still at {}", path.display()),
+        }
+    }
+}
+
+/// A registry entry that no longer matches a live worktree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaleEntry {
+    pub branch: String,
+    /// `None` for a worktree created without ports
+    pub ports: Option<PortBlock>,
+    pub path: PathBuf,
+    pub reason: StaleReason,
+}
+
+/// What a prune will do, computed before anything is touched
+#[derive(Debug, Default)]
+pub struct PrunePlan {
+    /// Entries to drop from the registry
+    pub remove: Vec<StaleEntry>,
+    /// Stale entries left alone because their worktree still exists and
+    /// `force` was not given
+    pub skipped: Vec<StaleEntry>,
+}
+
+impl PrunePlan {
+    pub fn is_empty(&self) -> bool {
+        self.remove.is_empty() && self.skipped.is_empty()
+    }
+}
+
+/// Work out which registry entries of the repository at `repo_root` are stale.
+///
+/// An entry whose worktree directory is missing is always pruned. One whose
+/// branch is gone but whose directory is still there is only pruned with
+/// `force`, since that deletes the directory along with whatever is in it.
+pub fn plan(cfg: &WtConfig, repo_root: &Path, force: bool) -> Result<PrunePlan> {
+    let registry = PortRegistry::load_at(repo_root)?;
+    let worktrees = worktree::list_in(repo_root)?;
+
+    let mut plan = PrunePlan::default();
+    for branch in registry.branches() {
+        let path = worktrees
+            .iter()
+            .find(|wt| wt.branch.as_deref() == Some(branch.as_str()))
+            .map(|wt| PathBuf::from(&wt.path))
+            .unwrap_or_else(|| registry.worktree_path(cfg, repo_root, branch));
+
+        let reason = if !worktree::exists(&path) {
+            StaleReason::WorktreeMissing
+        } else if !worktree::branch_exists_in(repo_root, &format!("refs/heads/{}", branch))? {
+            StaleReason::BranchDeleted
+        } else {
+            continue;
+        };
+
+        let entry = StaleEntry { branch: branch.clone(), ports: registry.block(branch), path, reason };
+        if reason == StaleReason::BranchDeleted && !force {
+            plan.skipped.push(entry);
+        } else {
+            plan.remove.push(entry);
+        }
+    }
+    Ok(plan)
+}
+
+/// The entries `hydra-wt clean` drops: only those whose worktree is gone,
+/// since nothing on disk is lost by dropping them
+pub fn plan_missing(cfg: &WtConfig, repo_root: &Path) -> Result<PrunePlan> {
+    let mut plan = plan(cfg, repo_root, false)?;
+    plan.skipped.clear();
+    Ok(plan)
+}
+
+/// Carry out `plan`: remove the worktrees that still exist, let git forget
+/// the missing ones, and free their ports
+pub fn apply(cfg: &WtConfig, repo_root: &Path, plan: &PrunePlan) -> Result<()> {
+    let mut registry = PortRegistry::load_at(repo_root)?;
+    for entry in &plan.remove {
+        // A leftover directory that is no longer a worktree is left alone
+        if worktree::exists(&entry.path) {
+            worktree::remove_in(repo_root, &entry.path)?;
+        }
+        worktree::remove_empty_parents(&entry.path, &cfg.worktree_dir_in(repo_root));
+        registry.unregister(&entry.branch);
+    }
+    worktree::prune_in(repo_root)?;
+    registry.save_at(repo_root)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::WorktreesConfig;
+    use crate::scratch::{self, git};
+
+    /// Scratch repo with managed worktrees `live` (3001), `gone-dir` (3002,
+    /// directory deleted) and `gone-branch` (3003, branch deleted)
+    fn setup() -> (PathBuf, WtConfig) {
+        let repo = scratch::repo("prune");
+        let root = repo.parent().unwrap();
+        scratch::commit_all(&repo, "init");
+
+        let cfg = WtConfig {
+            worktrees: WorktreesConfig {
+                directory: root.join("wts").to_string_lossy().to_string(),
+                ..WtConfig::default().worktrees
+            },
+            ..WtConfig::default()
+        };
+
+        let mut registry = PortRegistry::default();
+        for (branch, port) in [("live", 3001), ("gone-dir", 3002), ("gone-branch", 3003)] {
+            git(&repo, &["worktree", "add", "-q", "-b", branch, &cfg.worktree_path_in(&repo, branch).to_string_lossy()]);
+            registry.allocations.insert(branch.to_string(), port.into());
+        }
+        registry.save_at(&repo).unwrap();
+
+        std::fs::remove_dir_all(cfg.worktree_path_in(&repo, "gone-dir")).unwrap();
+        // `git branch -D` refuses while the branch is checked out, so delete the ref directly
+        git(&repo, &["update-ref", "-d", "refs/heads/gone-branch"]);
+
+        (repo, cfg)
+    }
+
+    fn branches(entries: &[StaleEntry]) -> Vec<(&str, StaleReason)> {
+        entries.iter().map(|e| (e.branch.as_str(), e.reason)).collect()
+    }
+
+    #[test]
+    fn test_prune_skips_existing_worktree_without_force() {
+        let (repo, cfg) = setup();
+
+        let plan = plan(&cfg, &repo, false).unwrap();
+        assert_eq!(branches(&plan.remove), vec![("gone-dir", StaleReason::WorktreeMissing)]);
+        assert_eq!(branches(&plan.skipped), vec![("gone-branch", StaleReason::BranchDeleted)]);
+
+        apply(&cfg, &repo, &plan).unwrap();
+        let registry = PortRegistry::load_at(&repo).unwrap();
+        assert_eq!(registry.get("gone-dir"), None);
+        assert_eq!(registry.get("gone-branch"), Some(3003));
+        assert_eq!(registry.get("live"), Some(3001));
+        assert!(cfg.worktree_path_in(&repo, "gone-branch").exists());
+        // git no longer lists the deleted directory
+        let listed = worktree::list_in(&repo).unwrap();
+        assert!(!listed.iter().any(|wt| wt.branch.as_deref() == Some("gone-dir")));
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_prune_force_removes_orphaned_worktree() {
+        let (repo, cfg) = setup();
+
+        let plan = plan(&cfg, &repo, true).unwrap();
+        assert!(plan.skipped.is_empty());
+        assert_eq!(plan.remove.len(), 2);
+        apply(&cfg, &repo, &plan).unwrap();
+
+        let registry = PortRegistry::load_at(&repo).unwrap();
+        assert_eq!(registry.list().collect::<Vec<_>>(), vec![(&"live".to_string(), &3001)]);
+        assert!(!cfg.worktree_path_in(&repo, "gone-branch").exists());
+        assert!(cfg.worktree_path_in(&repo, "live").exists());
+        assert!(super::plan(&cfg, &repo, true).unwrap().is_empty());
+        scratch::remove(&repo);
+    }
+}
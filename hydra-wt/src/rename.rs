@@ -0,0 +1,390 @@
+//! Transactional branch rename for managed worktrees
+//!
+//! Renaming touches four places that have to agree with each other: the git
+//! branch, the worktree directory, the port registry and the rendered env
+//! file. Each step is recorded as it completes so that a failure part-way
+//! through can undo the earlier ones in reverse order.
+
+use anyhow::{Context, Result, bail};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::config::WtConfig;
+use crate::create::same_path;
+use crate::ports::{PortBlock, PortRegistry};
+use crate::{hydra, template, worktree};
+
+/// Options for [`rename`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RenameOptions {
+    /// Rename the branch but leave the worktree directory where it is
+    pub keep_path: bool,
+    /// Rename even if the worktree has uncommitted changes or a merge in progress
+    pub force: bool,
+}
+
+/// The individual steps of a rename, in the order they are applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenameStep {
+    Branch,
+    MoveWorktree,
+    Registry,
+    EnvFile,
+}
+
+/// What a successful rename changed
+#[derive(Debug, Clone)]
+pub struct RenameOutcome {
+    pub old_branch: String,
+    pub new_branch: String,
+    pub old_path: PathBuf,
+    pub new_path: PathBuf,
+    pub port: Option<PortBlock>,
+}
+
+/// Rename the branch checked out in a managed worktree.
+///
+/// `repo_root` is the main checkout; the registry, env template and relative
+/// worktree directory are resolved against it.
+pub fn rename(
+    cfg: &WtConfig,
+    repo_root: &Path,
+    old: &str,
+    new: &str,
+    opts: RenameOptions,
+) -> Result<RenameOutcome> {
+    rename_with_hook(cfg, repo_root, old, new, opts, |_| Ok(()))
+}
+
+/// Same as [`rename`], but calls `before_step` ahead of every step. An error
+/// from the hook is treated like a failure of that step.
+pub fn rename_with_hook<F>(
+    cfg: &WtConfig,
+    repo_root: &Path,
+    old: &str,
+    new: &str,
+    opts: RenameOptions,
+    mut before_step: F,
+) -> Result<RenameOutcome>
+where
+    F: FnMut(RenameStep) -> Result<()>,
+{
+    if old == new {
+        bail!("'{}' is already the branch name", old);
+    }
+    if !worktree::is_valid_branch_name(new) {
+        bail!("'{}' is not a valid branch name", new);
+    }
+    if !worktree::branch_exists_in(repo_root, old)? {
+        bail!("Branch '{}' does not exist", old);
+    }
+    if worktree::branch_exists_in(repo_root, new)? {
+        bail!("Branch '{}' already exists", new);
+    }
+
+    let old_path = worktree::get_worktree_path_in(repo_root, old)?
+        .ok_or_else(|| anyhow::anyhow!("Branch '{}' is not checked out in any worktree", old))?;
+
+    let mut registry = PortRegistry::load_at(repo_root)?;
+    if registry.is_managed(new) {
+        bail!("Port registry already has an entry for '{}'", new);
+    }
+    let managed = registry.is_managed(old);
+    let port = registry.block(old);
+    let recorded_path = registry.paths.get(old).cloned();
+
+    if !opts.force {
+        if worktree::is_merge_in_progress(&old_path) {
+            bail!(
+                "A merge is in progress in {}. Finish or abort it first, or pass --force",
+                old_path.display()
+            );
+        }
+        if worktree::has_uncommitted_changes(&old_path)? {
+            bail!(
+                "Worktree {} has uncommitted changes. Commit or stash them first, or pass --force",
+                old_path.display()
+            );
+        }
+    }
+
+    // Only a worktree where the naming template put it moves along; one
+    // adopted from elsewhere stays where its owner chose to keep it
+    let follows_template = same_path(&old_path, &cfg.worktree_path_in(repo_root, old));
+    if !follows_template && !opts.keep_path {
+        info!("{} is not where hydra-wt names worktrees, leaving it in place", old_path.display());
+    }
+    let new_path = if opts.keep_path || !follows_template {
+        old_path.clone()
+    } else {
+        cfg.worktree_path_in(repo_root, new)
+    };
+    let moves = new_path != old_path;
+    if moves && new_path.exists() {
+        bail!("{} already exists", new_path.display());
+    }
+
+    let mut done: Vec<RenameStep> = Vec::new();
+    let result = (|| -> Result<()> {
+        before_step(RenameStep::Branch)?;
+        worktree::rename_branch(&old_path, old, new)?;
+        done.push(RenameStep::Branch);
+
+        if moves {
+            before_step(RenameStep::MoveWorktree)?;
+            worktree::move_worktree(&old_path, &new_path)?;
+            worktree::remove_empty_parents(&old_path, &cfg.worktree_dir_in(repo_root));
+            done.push(RenameStep::MoveWorktree);
+        }
+
+        if managed {
+            before_step(RenameStep::Registry)?;
+            move_entry(&mut registry, old, new);
+            registry.set_path(new, &new_path);
+            registry.save_at(repo_root)?;
+            done.push(RenameStep::Registry);
+
+            before_step(RenameStep::EnvFile)?;
+            render_env(cfg, repo_root, &new_path, new, port)?;
+            done.push(RenameStep::EnvFile);
+        }
+
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        let mut rollback_errors = Vec::new();
+        for step in done.iter().rev() {
+            let undo = match step {
+                RenameStep::Branch => worktree::rename_branch(&old_path, new, old).map_err(Into::into),
+                RenameStep::MoveWorktree => worktree::move_worktree(&new_path, &old_path).map_err(Into::into),
+                RenameStep::Registry => (|| -> Result<()> {
+                    let mut registry = PortRegistry::load_at(repo_root)?;
+                    if registry.is_managed(new) {
+                        move_entry(&mut registry, new, old);
+                        if let Some(path) = &recorded_path {
+                            registry.set_path(old, path);
+                        }
+                    }
+                    registry.save_at(repo_root)?;
+                    Ok(())
+                })(),
+                RenameStep::EnvFile => render_env(cfg, repo_root, &old_path, old, port),
+            };
+            if let Err(undo_err) = undo {
+                rollback_errors.push(format!("{:?}: {}", step, undo_err));
+            }
+        }
+
+        if rollback_errors.is_empty() {
+            return Err(e.context(format!("Rename of '{}' failed, changes rolled back", old)));
+        }
+        return Err(e.context(format!(
+            "Rename of '{}' failed and rollback was incomplete ({})",
+            old,
+            rollback_errors.join("; ")
+        )));
+    }
+
+    hydra::emit_worktree_renamed(cfg, repo_root, old, new, &new_path.to_string_lossy())?;
+    hydra::emit_inventory(cfg, repo_root)?;
+
+    Ok(RenameOutcome {
+        old_branch: old.to_string(),
+        new_branch: new.to_string(),
+        old_path,
+        new_path,
+        port,
+    })
+}
+
+/// Move the registry entry of `from`, with or without ports, to `to`. The
+/// recorded path is dropped; the caller records the new one.
+fn move_entry(registry: &mut PortRegistry, from: &str, to: &str) {
+    if let Some(created) = registry.created_at(from) {
+        registry.set_created(to, created);
+    }
+    if let Some(owner) = registry.owners.get(from).cloned() {
+        registry.set_owner(to, &owner);
+    }
+    if let Some(head) = registry.heads.get(from).cloned() {
+        registry.heads.insert(to.to_string(), head);
+    }
+    match registry.unregister(from) {
+        Some(ports) => {
+            registry.allocations.insert(to.to_string(), ports);
+        }
+        None => registry.add_portless(to),
+    }
+}
+
+/// Re-render the env templates so they carry the new worktree name
+fn render_env(cfg: &WtConfig, repo_root: &Path, wt_path: &Path, branch: &str, ports: Option<PortBlock>) -> Result<()> {
+    let ctx = template::TemplateContext::for_worktree(cfg, repo_root, branch, ports)?;
+    template::render_all(&cfg.env, repo_root, wt_path, &ctx)
+        .context("Failed to re-render env files")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ArtifactsConfig, EnvConfig, HooksConfig, PortsConfig, TemplateConfig, WorktreesConfig};
+    use crate::scratch::{self, git};
+
+    /// Scratch repo with one managed worktree `feature` on port 3001
+    fn setup() -> (PathBuf, WtConfig) {
+        let repo = scratch::repo("rename");
+        let root = repo.parent().unwrap();
+        std::fs::write(repo.join(".env.template"), "PORT={{ port }}\nWORKTREE={{ worktree }}\n").unwrap();
+        scratch::commit_all(&repo, "init");
+
+        let cfg = WtConfig {
+            ports: PortsConfig { range_start: 3001, range_end: 3010, ..WtConfig::default().ports },
+            env: EnvConfig {
+                template: ".env.template".to_string(),
+                output: ".env.local".to_string(),
+                files: Vec::new(),
+            },
+            worktrees: WorktreesConfig {
+                directory: root.join("wts").to_string_lossy().to_string(),
+                ..WtConfig::default().worktrees
+            },
+            artifacts: ArtifactsConfig::default(),
+            hooks: HooksConfig::default(),
+            template: TemplateConfig::default(),
+            ..WtConfig::default()
+        };
+
+        let wt_path = cfg.worktree_path_in(&repo, "feature");
+        git(&repo, &["worktree", "add", "-q", "-b", "feature", &wt_path.to_string_lossy()]);
+
+        let mut registry = PortRegistry::default();
+        registry.allocations.insert("feature".to_string(), 3001.into());
+        registry.record_head(&repo, "feature").unwrap();
+        registry.save_at(&repo).unwrap();
+
+        (repo, cfg)
+    }
+
+
+    #[test]
+    fn test_rename_moves_branch_worktree_and_port() {
+        let (repo, cfg) = setup();
+
+        let outcome = rename(&cfg, &repo, "feature", "feature-2", RenameOptions::default()).unwrap();
+
+        assert_eq!(outcome.port, Some(PortBlock::single(3001)));
+        assert_eq!(outcome.new_path, cfg.worktree_path_in(&repo, "feature-2"));
+        assert!(!worktree::branch_exists_in(&repo, "feature").unwrap());
+        assert!(worktree::branch_exists_in(&repo, "feature-2").unwrap());
+        assert!(!cfg.worktree_path_in(&repo, "feature").exists());
+        assert!(worktree::exists(&cfg.worktree_path_in(&repo, "feature-2")));
+
+        let registry = PortRegistry::load_at(&repo).unwrap();
+        assert_eq!(registry.get("feature"), None);
+        assert_eq!(registry.get("feature-2"), Some(3001));
+
+        assert_eq!(registry.head("feature"), None);
+        assert!(registry.head("feature-2").is_some());
+
+        let env = std::fs::read_to_string(outcome.new_path.join(".env.local")).unwrap();
+        assert!(env.contains("WORKTREE=feature-2"));
+        assert!(env.contains("PORT=3001"));
+
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_rename_keep_path() {
+        let (repo, cfg) = setup();
+        let old_path = cfg.worktree_path_in(&repo, "feature");
+
+        let opts = RenameOptions { keep_path: true, force: false };
+        let outcome = rename(&cfg, &repo, "feature", "feature-2", opts).unwrap();
+
+        assert_eq!(outcome.new_path, outcome.old_path);
+        assert!(old_path.exists());
+        assert!(!cfg.worktree_path_in(&repo, "feature-2").exists());
+        assert_eq!(
+            worktree::get_worktree_path_in(&repo, "feature-2").unwrap().map(|p| p.canonicalize().unwrap()),
+            Some(old_path.canonicalize().unwrap())
+        );
+        assert_eq!(PortRegistry::load_at(&repo).unwrap().get("feature-2"), Some(3001));
+
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_rename_leaves_adopted_worktree_in_place() {
+        let (repo, cfg) = setup();
+        let custom = repo.parent().unwrap().join("elsewhere");
+        git(&repo, &["worktree", "add", "-q", "-b", "adopted", &custom.to_string_lossy()]);
+        let mut registry = PortRegistry::load_at(&repo).unwrap();
+        registry.allocations.insert("adopted".to_string(), 3002.into());
+        registry.set_path("adopted", &custom);
+        registry.save_at(&repo).unwrap();
+
+        let outcome = rename(&cfg, &repo, "adopted", "adopted-2", RenameOptions::default()).unwrap();
+        assert_eq!(outcome.new_path, outcome.old_path);
+        assert!(worktree::exists(&custom));
+        assert!(!cfg.worktree_path_in(&repo, "adopted-2").exists());
+        let registry = PortRegistry::load_at(&repo).unwrap();
+        assert_eq!(registry.get("adopted-2"), Some(3002));
+        assert!(same_path(&registry.worktree_path(&cfg, &repo, "adopted-2"), &custom));
+
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_rename_rolls_back_on_failure() {
+        let (repo, cfg) = setup();
+
+        let err = rename_with_hook(&cfg, &repo, "feature", "feature-2", RenameOptions::default(), |step| {
+            if step == RenameStep::EnvFile {
+                bail!("injected failure");
+            }
+            Ok(())
+        })
+        .unwrap_err();
+        assert!(format!("{:#}", err).contains("rolled back"));
+
+        assert!(worktree::branch_exists_in(&repo, "feature").unwrap());
+        assert!(!worktree::branch_exists_in(&repo, "feature-2").unwrap());
+        assert!(worktree::exists(&cfg.worktree_path_in(&repo, "feature")));
+        assert!(!cfg.worktree_path_in(&repo, "feature-2").exists());
+
+        let registry = PortRegistry::load_at(&repo).unwrap();
+        assert_eq!(registry.get("feature"), Some(3001));
+        assert_eq!(registry.get("feature-2"), None);
+        assert!(registry.head("feature").is_some());
+
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_rename_refuses_dirty_worktree_unless_forced() {
+        let (repo, cfg) = setup();
+        std::fs::write(cfg.worktree_path_in(&repo, "feature").join("scratch.txt"), "wip").unwrap();
+
+        let err = rename(&cfg, &repo, "feature", "feature-2", RenameOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("uncommitted changes"));
+        assert!(worktree::branch_exists_in(&repo, "feature").unwrap());
+
+        let opts = RenameOptions { keep_path: false, force: true };
+        rename(&cfg, &repo, "feature", "feature-2", opts).unwrap();
+        assert!(cfg.worktree_path_in(&repo, "feature-2").join("scratch.txt").exists());
+
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_rename_rejects_invalid_name() {
+        let (repo, cfg) = setup();
+
+        let err = rename(&cfg, &repo, "feature", "bad..name", RenameOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("not a valid branch name"));
+
+        scratch::remove(&repo);
+    }
+}
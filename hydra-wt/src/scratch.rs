@@ -0,0 +1,65 @@
+//! Throwaway git repositories for the unit tests
+//!
+//! The integration tests have `tests/support` for this. Each repository
+//! lives one level down in a directory of its own under the system temp
+//! dir, so worktrees and anything else a test puts next to it go away with
+//! [`remove`].
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Run git in `dir` without the user's or system's config, returning its
+/// trimmed stdout
+pub(crate) fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env_remove("GIT_DIR")
+        .env_remove("GIT_WORK_TREE")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "git {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+/// A repository on `main` with an empty `.hydra/` and no commits yet, at
+/// `<temp>/hydra-wt-<name>-<uuid>/repo`
+pub(crate) fn repo(name: &str) -> PathBuf {
+    let repo = std::env::temp_dir()
+        .join(format!("hydra-wt-{}-{}", name, uuid::Uuid::new_v4()))
+        .join("repo");
+    std::fs::create_dir_all(repo.join(".hydra")).unwrap();
+    git(&repo, &["init", "-q", "-b", "main"]);
+    // hydra-wt runs git itself, with the user's config; the repository's
+    // settings win over it, so its commits are never signed or hooked
+    for (key, value) in [
+        ("user.name", "Test"),
+        ("user.email", "test@example.com"),
+        ("commit.gpgsign", "false"),
+        ("core.hooksPath", "/dev/null"),
+    ] {
+        git(&repo, &["config", key, value]);
+    }
+    std::fs::write(repo.join(".git/info/exclude"), ".hydra/\n").unwrap();
+    repo
+}
+
+/// Stage everything in the checkout at `dir` and commit it, even if
+/// nothing changed
+pub(crate) fn commit_all(dir: &Path, message: &str) {
+    git(dir, &["add", "-A"]);
+    git(dir, &["commit", "-q", "--allow-empty", "-m", message]);
+}
+
+/// Remove the directory of a [`repo`], worktrees next to it included
+pub(crate) fn remove(repo: &Path) {
+    let _ = std::fs::remove_dir_all(repo.parent().unwrap());
+}
@@ -1,42 +1,524 @@
 use anyhow::{Context, Result};
-use std::path::Path;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
 use tera::Tera;
+use tracing::{debug, warn};
+
+use crate::config::{self, EnvConfig, WtConfig};
+use crate::ports::PortBlock;
+use crate::probe;
 
 pub struct TemplateContext {
-    pub port: u16,
+    /// `None` for a worktree created with `--no-port`, rendered as empty
+    pub port: Option<u16>,
+    /// Every port of the worktree's block, starting with `port`; exposed as
+    /// `PORT`, `PORT_2`, `PORT_3`, ...
+    pub ports: Vec<u16>,
     pub worktree: String,
     pub project_uuid: String,
     pub repo_root: String,
+    /// `ports.bind_host`
+    pub bind_host: String,
+    /// `http://<bind_host>:<port>`, IPv6 hosts bracketed; empty without a port
+    pub port_url: String,
+    /// Further variables: `branch_slug` and the rendered `[template.vars]`
+    pub vars: BTreeMap<String, String>,
+}
+
+impl TemplateContext {
+    /// Context for the worktree of `branch` holding `ports`, with the
+    /// `[template.vars]` of `cfg` rendered against the built-in variables
+    pub fn for_worktree(cfg: &WtConfig, repo_root: &Path, branch: &str, ports: Option<PortBlock>) -> Result<Self> {
+        let mut ctx = TemplateContext {
+            port: ports.map(|p| p.start),
+            ports: ports.map(|p| p.ports().collect()).unwrap_or_default(),
+            worktree: branch.to_string(),
+            project_uuid: config::get_project_uuid_at(repo_root).unwrap_or_else(|_| "unknown".to_string()),
+            repo_root: repo_root.to_string_lossy().to_string(),
+            bind_host: cfg.ports.bind_host.clone(),
+            port_url: ports.map(|p| probe::port_url(&cfg.ports.bind_host, p.start)).unwrap_or_default(),
+            vars: BTreeMap::from([("branch_slug".to_string(), branch_slug(branch))]),
+        };
+
+        let builtins = ctx.to_tera();
+        for (name, value) in &cfg.template.vars {
+            let rendered = Tera::one_off(value, &builtins, false)
+                .with_context(|| format!("Failed to render template.vars.{}", name))?;
+            ctx.vars.insert(name.clone(), rendered);
+        }
+        Ok(ctx)
+    }
+
+    fn to_tera(&self) -> tera::Context {
+        let mut context = tera::Context::new();
+        match self.port {
+            Some(port) => context.insert("port", &port),
+            None => {
+                context.insert("port", "");
+                context.insert("PORT", "");
+            }
+        }
+        for (i, port) in self.ports.iter().enumerate() {
+            let name = if i == 0 { "PORT".to_string() } else { format!("PORT_{}", i + 1) };
+            context.insert(name, port);
+        }
+        context.insert("worktree", &self.worktree);
+        context.insert("project_uuid", &self.project_uuid);
+        context.insert("repo_root", &self.repo_root);
+        context.insert("bind_host", &self.bind_host);
+        context.insert("port_url", &self.port_url);
+        for (name, value) in &self.vars {
+            context.insert(name, value);
+        }
+        context
+    }
+}
+
+/// `branch` lowercased with every run of characters other than ASCII
+/// letters and digits replaced by a single `-`, so `feature/Auth_v2`
+/// becomes `feature-auth-v2`. Safe as a compose project or database name.
+pub fn branch_slug(branch: &str) -> String {
+    let mut slug = String::with_capacity(branch.len());
+    for c in branch.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+/// Start of the comment a rendered file opens with, naming its template
+/// and the hash of the template it was rendered from
+const HEADER_PREFIX: &str = "# Rendered by hydra-wt from ";
+
+/// First 16 hex digits of the SHA-256 of a template's content
+pub fn template_hash(content: &str) -> String {
+    Sha256::digest(content.as_bytes()).iter().take(8).map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The [`template_hash`] the file at `output_path` was rendered with, from
+/// its header; `None` for a file without one, like those rendered before
+/// the header existed or JSON and XML ones, which can't have `#` comments
+pub fn rendered_hash(output_path: &Path) -> Option<String> {
+    let content = std::fs::read_to_string(output_path).ok()?;
+    let mut lines = content.lines();
+    let mut first = lines.next()?;
+    if first.starts_with("#!") {
+        first = lines.next()?;
+    }
+    let header = first.strip_prefix(HEADER_PREFIX)?;
+    let hash = header.rsplit_once("sha256:")?.1;
+    Some(hash.trim_end_matches(')').to_string())
+}
+
+/// Where the header goes in `rendered`, the content of `output_path`:
+/// after a shebang, which has to stay the first line, otherwise at the
+/// start. `None` for JSON and XML, which have no `#` comments.
+fn header_offset(output_path: &Path, rendered: &str) -> Option<usize> {
+    let is_json_or_xml = output_path.extension().is_some_and(|ext| ext == "json" || ext == "xml");
+    if is_json_or_xml || rendered.starts_with("<?xml") {
+        return None;
+    }
+    if rendered.starts_with("#!") {
+        return Some(rendered.find('\n').map_or(rendered.len(), |end| end + 1));
+    }
+    Some(0)
+}
+
+/// Render `template_path` into `output_path`, returning whether a file was
+/// written. Without a port, a template whose every variable is a port one
+/// would only produce empty values and is skipped.
+pub fn render(template_path: &Path, output_path: &Path, ctx: &TemplateContext) -> Result<bool> {
+    let Some(rendered) = render_to_string(template_path, output_path, ctx)? else {
+        return Ok(false);
+    };
+
+    if let Some(parent) = output_path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    std::fs::write(output_path, rendered)
+        .with_context(|| format!("Failed to write {}", output_path.display()))?;
+    debug!("wrote {}", output_path.display());
+
+    Ok(true)
 }
 
-pub fn render(template_path: &Path, output_path: &Path, ctx: &TemplateContext) -> Result<()> {
+/// What [`render`] would write to `output_path`, header included, or
+/// `None` where it would write nothing
+fn render_to_string(template_path: &Path, output_path: &Path, ctx: &TemplateContext) -> Result<Option<String>> {
     if !template_path.exists() {
-        eprintln!(
-            "Warning: Template {} not found, skipping env generation",
+        warn!(
+            "template {} not found, skipping env generation",
             template_path.display()
         );
-        return Ok(());
+        return Ok(None);
     }
 
     let template_content = std::fs::read_to_string(template_path)
         .with_context(|| format!("Failed to read template {}", template_path.display()))?;
 
+    if ctx.port.is_none() && only_port_placeholders(&template_content) {
+        debug!("{} only sets ports, skipping it for a worktree without one", template_path.display());
+        return Ok(None);
+    }
+
     let mut tera = Tera::default();
     tera.add_raw_template("env", &template_content)
         .context("Failed to parse template")?;
 
-    let mut context = tera::Context::new();
-    context.insert("port", &ctx.port);
-    context.insert("worktree", &ctx.worktree);
-    context.insert("project_uuid", &ctx.project_uuid);
-    context.insert("repo_root", &ctx.repo_root);
+    let mut context = ctx.to_tera();
+    let unknown = unknown_placeholders(&template_content, &context);
+    if !unknown.is_empty() {
+        warn!(
+            "template {} uses unknown variable(s) {}, leaving them unrendered",
+            template_path.display(),
+            unknown.join(", ")
+        );
+        for name in &unknown {
+            context.insert(name, &format!("{{{{ {} }}}}", name));
+        }
+    }
 
     let rendered = tera
         .render("env", &context)
         .context("Failed to render template")?;
 
-    std::fs::write(output_path, rendered)
-        .with_context(|| format!("Failed to write {}", output_path.display()))?;
+    let Some(offset) = header_offset(output_path, &rendered) else {
+        return Ok(Some(rendered));
+    };
+    let (before, after) = rendered.split_at(offset);
+    // A shebang without a newline of its own still ends its line
+    let before = if before.is_empty() || before.ends_with('\n') { before.to_string() } else { format!("{}\n", before) };
+    let name = template_path.file_name().unwrap_or_default().to_string_lossy();
+    Ok(Some(format!(
+        "{}{}{} (template sha256:{})\n{}",
+        before,
+        HEADER_PREFIX,
+        name,
+        template_hash(&template_content),
+        after
+    )))
+}
+
+/// Render every template pair of `env` from `repo_root` into `wt_path`,
+/// returning the files written. A missing `env.template` is skipped
+/// quietly since every config has one; a missing `env.files` entry warns.
+pub fn render_all(env: &EnvConfig, repo_root: &Path, wt_path: &Path, ctx: &TemplateContext) -> Result<Vec<PathBuf>> {
+    let mut written = Vec::new();
+    for (i, (template, output)) in env.pairs().enumerate() {
+        let template_path = repo_root.join(template);
+        if i == 0 && !template_path.exists() {
+            continue;
+        }
+        let output_path = wt_path.join(output);
+        if render(&template_path, &output_path, ctx).with_context(|| format!("Failed to render {}", template))? {
+            written.push(output_path);
+        }
+    }
+    Ok(written)
+}
+
+/// One env file of an existing worktree rendered again, not yet written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rerender {
+    pub output: PathBuf,
+    /// What the file holds now, `None` if it doesn't exist
+    pub previous: Option<String>,
+    pub rendered: String,
+}
 
-    Ok(())
+impl Rerender {
+    pub fn is_changed(&self) -> bool {
+        self.previous.as_deref() != Some(self.rendered.as_str())
+    }
+
+    /// Where [`Rerender::write`] keeps the previous content:
+    /// `.env.local.bak` for `.env.local`
+    pub fn backup_path(&self) -> PathBuf {
+        let mut name = self.output.file_name().unwrap_or_default().to_os_string();
+        name.push(".bak");
+        self.output.with_file_name(name)
+    }
+
+    /// Write the rendered content, first copying the previous content to
+    /// [`Rerender::backup_path`]. Returns the backup, `None` when there was
+    /// no previous file.
+    pub fn write(&self) -> Result<Option<PathBuf>> {
+        let backup = match &self.previous {
+            Some(previous) => {
+                let backup = self.backup_path();
+                std::fs::write(&backup, previous)
+                    .with_context(|| format!("Failed to write {}", backup.display()))?;
+                Some(backup)
+            }
+            None => None,
+        };
+        if let Some(parent) = self.output.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        std::fs::write(&self.output, &self.rendered)
+            .with_context(|| format!("Failed to write {}", self.output.display()))?;
+        Ok(backup)
+    }
+
+    /// The lines that differ between the previous and the rendered content,
+    /// `-` for removed and `+` for added ones, in file order
+    pub fn diff(&self) -> Vec<String> {
+        line_diff(self.previous.as_deref().unwrap_or_default(), &self.rendered)
+    }
+}
+
+/// Render every template pair of `env` again for the existing worktree at
+/// `wt_path`, without writing anything; pairs [`render_all`] would skip are
+/// left out
+pub fn rerender_all(env: &EnvConfig, repo_root: &Path, wt_path: &Path, ctx: &TemplateContext) -> Result<Vec<Rerender>> {
+    let mut rerenders = Vec::new();
+    for (i, (template, output)) in env.pairs().enumerate() {
+        let template_path = repo_root.join(template);
+        if i == 0 && !template_path.exists() {
+            continue;
+        }
+        let output = wt_path.join(output);
+        let rendered = render_to_string(&template_path, &output, ctx).with_context(|| format!("Failed to render {}", template))?;
+        if let Some(rendered) = rendered {
+            let previous = std::fs::read_to_string(&output).ok();
+            rerenders.push(Rerender { output, previous, rendered });
+        }
+    }
+    Ok(rerenders)
+}
+
+/// Changed lines between `old` and `new` by their longest common
+/// subsequence; env files are short enough for the quadratic table
+fn line_diff(old: &str, new: &str) -> Vec<String> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    // common[i][j]: length of the LCS of old[i..] and new[j..]
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+            lines.push(format!("-{}", old[i]));
+            i += 1;
+        } else {
+            lines.push(format!("+{}", new[j]));
+            j += 1;
+        }
+    }
+    lines
+}
+
+/// Variables that `{{ ... }}` expressions in `template` start with but that
+/// neither `context` nor the template itself (`set`, `for`) defines, and
+/// that have no `default`
+fn unknown_placeholders(template: &str, context: &tera::Context) -> Vec<String> {
+    let local = local_names(template);
+    let mut unknown = BTreeSet::new();
+    for expr in delimited(template, "{{", "}}") {
+        let name = leading_ident(expr);
+        if name.is_empty() || matches!(name, "true" | "false" | "loop" | "__tera_context") {
+            continue;
+        }
+        // `default` is how a template says a variable is optional
+        if expr.contains("default(") {
+            continue;
+        }
+        if !context.contains_key(name) && !local.contains(name) {
+            unknown.insert(name.to_string());
+        }
+    }
+    unknown.into_iter().collect()
+}
+
+/// Whether `template` has `{{ ... }}` expressions and every one of them is
+/// about the worktree's ports (`port`, `PORT`, `PORT_<n>`, `port_url`)
+fn only_port_placeholders(template: &str) -> bool {
+    let exprs = delimited(template, "{{", "}}");
+    !exprs.is_empty()
+        && exprs.iter().all(|expr| {
+            let name = leading_ident(expr);
+            matches!(name, "port" | "PORT" | "port_url")
+                || name.strip_prefix("PORT_").is_some_and(|n| n.parse::<u32>().is_ok())
+        })
+}
+
+/// Names bound by `{% set %}` and `{% for %}` tags
+fn local_names(template: &str) -> BTreeSet<&str> {
+    let mut names = BTreeSet::new();
+    for tag in delimited(template, "{%", "%}") {
+        let mut words = tag.split_whitespace();
+        match words.next() {
+            Some("set") | Some("set_global") => {
+                if let Some(rest) = words.next() {
+                    names.insert(leading_ident(rest));
+                }
+            }
+            Some("for") => {
+                for word in words.take_while(|w| *w != "in") {
+                    for name in word.split(',') {
+                        if !name.is_empty() {
+                            names.insert(leading_ident(name));
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    names
+}
+
+/// Contents of every `open ... close` span, whitespace-control dashes trimmed
+fn delimited<'a>(text: &'a str, open: &str, close: &str) -> Vec<&'a str> {
+    let mut spans = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(open) {
+        let after = &rest[start + open.len()..];
+        let Some(end) = after.find(close) else { break };
+        spans.push(after[..end].trim().trim_matches('-').trim());
+        rest = &after[end + close.len()..];
+    }
+    spans
+}
+
+fn leading_ident(expr: &str) -> &str {
+    let starts_ident = expr.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_');
+    if !starts_ident {
+        return "";
+    }
+    let end = expr
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(expr.len());
+    &expr[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_branch_slug() {
+        assert_eq!(branch_slug("feature/Auth_v2"), "feature-auth-v2");
+        assert_eq!(branch_slug("fix//double--dash/"), "fix-double-dash");
+        assert_eq!(branch_slug("main"), "main");
+    }
+
+    #[test]
+    fn test_unknown_placeholders() {
+        let mut context = tera::Context::new();
+        context.insert("port", &3001);
+        let template = "PORT={{ port }}\n{{- DB_HOST | default(value='x') }}\n\
+            {% set name = 'a' %}{{ name }}{% for k, v in items %}{{ k }}{{ v }}{% endfor %}{{ \"lit\" }}{{ FOO }}";
+        assert_eq!(unknown_placeholders(template, &context), vec!["FOO"]);
+    }
+
+    #[test]
+    fn test_portless_rendering() {
+        assert!(only_port_placeholders("PORT={{ port }}\nAPI={{ PORT_2 }}\nURL={{ port_url }}\n"));
+        assert!(!only_port_placeholders("PORT={{ port }}\nNAME={{ worktree }}\n"));
+        assert!(!only_port_placeholders("STATIC=1\n"));
+
+        let dir = std::env::temp_dir().join(format!("hydra-wt-template-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let ctx = TemplateContext::for_worktree(&WtConfig::default(), &dir, "docs", None).unwrap();
+        std::fs::write(dir.join("ports.template"), "PORT={{ port }}\n").unwrap();
+        assert!(!render(&dir.join("ports.template"), &dir.join("ports.env"), &ctx).unwrap());
+        assert!(!dir.join("ports.env").exists());
+
+        std::fs::write(dir.join("mixed.template"), "PORT={{ PORT }}\nURL={{ port_url }}\nNAME={{ worktree }}\n").unwrap();
+        assert!(render(&dir.join("mixed.template"), &dir.join("mixed.env"), &ctx).unwrap());
+        let rendered = std::fs::read_to_string(dir.join("mixed.env")).unwrap();
+        assert_eq!(rendered.split_once('\n').unwrap().1, "PORT=\nURL=\nNAME=docs\n");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_header_and_rerender() {
+        let dir = std::env::temp_dir().join(format!("hydra-wt-template-{}", uuid::Uuid::new_v4()));
+        let wt = dir.join("wt");
+        std::fs::create_dir_all(&wt).unwrap();
+        let ctx = TemplateContext::for_worktree(&WtConfig::default(), &dir, "feature", Some(PortBlock::single(3001))).unwrap();
+        let env = WtConfig::default().env;
+        std::fs::write(dir.join(&env.template), "PORT={{ port }}\n").unwrap();
+        assert!(render(&dir.join(&env.template), &wt.join(&env.output), &ctx).unwrap());
+        let output = wt.join(&env.output);
+        assert_eq!(rendered_hash(&output), Some(template_hash("PORT={{ port }}\n")));
+        assert!(std::fs::read_to_string(&output).unwrap().ends_with("\nPORT=3001\n"));
+
+        // Nothing to do until the template changes
+        let rerenders = rerender_all(&env, &dir, &wt, &ctx).unwrap();
+        assert_eq!(rerenders.len(), 1);
+        assert!(!rerenders[0].is_changed());
+
+        std::fs::write(dir.join(&env.template), "PORT={{ port }}\nNAME={{ worktree }}\n").unwrap();
+        let rerender = rerender_all(&env, &dir, &wt, &ctx).unwrap().remove(0);
+        assert!(rerender.is_changed());
+        let diff = rerender.diff();
+        assert_eq!(diff.len(), 3, "{:?}", diff);
+        assert!(diff[0].starts_with("-# Rendered by hydra-wt") && diff[1].starts_with("+# Rendered by hydra-wt"));
+        assert_eq!(diff[2], "+NAME=feature");
+
+        let previous = std::fs::read_to_string(&output).unwrap();
+        let backup = rerender.write().unwrap().unwrap();
+        assert_eq!(backup, wt.join(".env.local.bak"));
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), previous);
+        assert_eq!(rendered_hash(&output), Some(template_hash("PORT={{ port }}\nNAME={{ worktree }}\n")));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_header_follows_a_shebang_and_skips_xml() {
+        let dir = std::env::temp_dir().join(format!("hydra-wt-template-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let ctx = TemplateContext::for_worktree(&WtConfig::default(), &dir, "docs", None).unwrap();
+        let script = "#!/bin/sh\necho {{ worktree }}\n";
+        std::fs::write(dir.join("run.template"), script).unwrap();
+        assert!(render(&dir.join("run.template"), &dir.join("run.sh"), &ctx).unwrap());
+        let rendered = std::fs::read_to_string(dir.join("run.sh")).unwrap();
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines[0], "#!/bin/sh");
+        assert!(lines[1].starts_with(HEADER_PREFIX), "{}", rendered);
+        assert_eq!(lines[2], "echo docs");
+        assert_eq!(rendered_hash(&dir.join("run.sh")), Some(template_hash(script)));
+
+        // XML, by extension or declaration, has no `#` comments
+        let xml = "<?xml version=\"1.0\"?>\n<name>{{ worktree }}</name>\n";
+        std::fs::write(dir.join("app.template"), xml).unwrap();
+        for output in ["app.xml", "app.config"] {
+            assert!(render(&dir.join("app.template"), &dir.join(output), &ctx).unwrap());
+            let rendered = std::fs::read_to_string(dir.join(output)).unwrap();
+            assert_eq!(rendered, "<?xml version=\"1.0\"?>\n<name>docs</name>\n");
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_json_outputs_have_no_header() {
+        let dir = std::env::temp_dir().join(format!("hydra-wt-template-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let ctx = TemplateContext::for_worktree(&WtConfig::default(), &dir, "docs", None).unwrap();
+        std::fs::write(dir.join("settings.template"), "{\"name\": \"{{ worktree }}\"}\n").unwrap();
+        assert!(render(&dir.join("settings.template"), &dir.join("settings.json"), &ctx).unwrap());
+        assert_eq!(std::fs::read_to_string(dir.join("settings.json")).unwrap(), "{\"name\": \"docs\"}\n");
+        assert_eq!(rendered_hash(&dir.join("settings.json")), None);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }
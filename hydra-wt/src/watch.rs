@@ -0,0 +1,333 @@
+//! Watching a worktree for changes
+//!
+//! `hydra-wt watch <branch>` reports what changes in a worktree as
+//! `repo:delta` pulses on hydra-mail, one per [`BATCH_INTERVAL`] in which
+//! anything changed. File events come from notify, debounced into batches;
+//! a batch only tells [`watch`] to look, and what changed is worked out by
+//! comparing `git status` before and after, which already leaves out what
+//! `.gitignore` ignores. The worktree's git dir is watched as well, for
+//! HEAD moving on a commit or checkout.
+
+use anyhow::{Context, Result};
+use notify_debouncer_mini::notify::RecursiveMode;
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, TrySendError};
+use std::time::{Duration, SystemTime};
+
+use hydra_log::LoggedCommand;
+use tracing::debug;
+
+use crate::error::WtError;
+use crate::{health, worktree};
+
+/// How long changes are collected before they are reported together
+pub const BATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How often [`watch`] checks whether it was stopped while it waits
+const STOP_POLL: Duration = Duration::from_millis(100);
+
+/// What one changed file looked like when a [`Snapshot`] was taken
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FileState {
+    /// The two status letters of `git status --porcelain`
+    status: String,
+    modified: Option<SystemTime>,
+    len: Option<u64>,
+}
+
+/// The uncommitted state of a worktree: its HEAD and every file git
+/// reports as changed or untracked
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    head: Option<String>,
+    files: BTreeMap<String, FileState>,
+}
+
+/// Changes seen in one batch
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Delta {
+    /// Paths that changed, relative to the worktree, sorted
+    pub paths: Vec<String>,
+    /// Lines added and removed in `paths` against HEAD, from
+    /// `git diff --numstat`; untracked text files count as added
+    pub added: usize,
+    pub removed: usize,
+}
+
+/// Why [`watch`] returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchEnd {
+    Stopped,
+    WorktreeRemoved,
+}
+
+/// Take a [`Snapshot`] of the worktree at `path`
+pub fn snapshot(path: &Path) -> Result<Snapshot> {
+    let output = Command::new("git")
+        .args(["-C", &path.to_string_lossy(), "status", "--porcelain", "-z", "--untracked-files=all"])
+        .logged_output()?;
+    if !output.status.success() {
+        return Err(WtError::git("git status", &output.stderr).into());
+    }
+    let mut files = BTreeMap::new();
+    for (status, file) in parse_status(&String::from_utf8_lossy(&output.stdout)) {
+        let meta = std::fs::symlink_metadata(path.join(&file)).ok();
+        let state = FileState {
+            status,
+            modified: meta.as_ref().and_then(|m| m.modified().ok()),
+            len: meta.as_ref().map(|m| m.len()),
+        };
+        files.insert(file, state);
+    }
+    Ok(Snapshot { head: health::rev_parse(path, "HEAD")?, files })
+}
+
+/// `git status --porcelain -z` output: `XY PATH\0` per file, followed by
+/// `ORIG\0` for a rename or copy
+fn parse_status(status: &str) -> Vec<(String, String)> {
+    let mut entries = Vec::new();
+    let mut fields = status.split('\0');
+    while let Some(record) = fields.next() {
+        if record.len() < 4 {
+            continue;
+        }
+        let (code, file) = record.split_at(2);
+        if code.starts_with(['R', 'C']) {
+            fields.next();
+        }
+        entries.push((code.to_string(), file[1..].to_string()));
+    }
+    entries
+}
+
+/// Paths of the worktree at `path` that changed between `before` and
+/// `after`: files whose status, size or modification time differ, plus
+/// the files of any commits HEAD moved across
+pub fn changed_paths(path: &Path, before: &Snapshot, after: &Snapshot) -> Result<Vec<String>> {
+    let mut paths = BTreeSet::new();
+    for (file, state) in &after.files {
+        if before.files.get(file) != Some(state) {
+            paths.insert(file.clone());
+        }
+    }
+    for file in before.files.keys() {
+        if !after.files.contains_key(file) {
+            paths.insert(file.clone());
+        }
+    }
+    if let (Some(old), Some(new)) = (&before.head, &after.head) {
+        if old != new {
+            let names = worktree::diff_in(path, old, new, worktree::DiffFormat::NameOnly, false)?;
+            paths.extend(names.lines().filter(|l| !l.is_empty()).map(str::to_string));
+        }
+    }
+    Ok(paths.into_iter().collect())
+}
+
+/// The [`Delta`] of `paths` in the worktree at `path`
+pub fn delta(path: &Path, snapshot: &Snapshot, paths: Vec<String>) -> Result<Delta> {
+    let wanted: BTreeSet<&str> = paths.iter().map(String::as_str).collect();
+    let (mut added, mut removed) = (0, 0);
+    if snapshot.head.is_some() {
+        for change in worktree::diff_worktree_files_in(path)? {
+            if wanted.contains(change.path.as_str()) {
+                added += change.additions.unwrap_or(0);
+                removed += change.deletions.unwrap_or(0);
+            }
+        }
+    }
+    for (file, state) in &snapshot.files {
+        if state.status == "??" && wanted.contains(file.as_str()) {
+            added += text_lines(&path.join(file)).unwrap_or(0);
+        }
+    }
+    Ok(Delta { paths, added, removed })
+}
+
+/// Lines of the file at `path`, `None` for a binary or unreadable one
+fn text_lines(path: &Path) -> Option<usize> {
+    let content = std::fs::read(path).ok()?;
+    if content.contains(&0) {
+        return None;
+    }
+    Some(content.split(|b| *b == b'\n').filter(|line| !line.is_empty()).count())
+}
+
+/// The git dir of the worktree at `path`, absolute
+fn git_dir(path: &Path) -> Result<PathBuf> {
+    let output = Command::new("git")
+        .args(["-C", &path.to_string_lossy(), "rev-parse", "--absolute-git-dir"])
+        .logged_output()?;
+    if !output.status.success() {
+        return Err(WtError::git("git rev-parse --absolute-git-dir", &output.stderr).into());
+    }
+    Ok(PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()))
+}
+
+/// Whether an event for `file` may mean the worktree changed. Of the git
+/// dir only HEAD and its reflog count, which a commit or checkout touches;
+/// the rest, the index above all, changes whenever git status runs.
+fn is_relevant(file: &Path, git_dir: &Path) -> bool {
+    match file.strip_prefix(git_dir) {
+        Ok(inside) => inside == Path::new("HEAD") || inside == Path::new("logs/HEAD"),
+        Err(_) => true,
+    }
+}
+
+/// Watch the worktree at `path` and call `on_delta` for each batch of
+/// events, `interval` long, in which something changed, until `stop` is
+/// set or the worktree is removed
+pub fn watch(
+    path: &Path,
+    interval: Duration,
+    stop: &AtomicBool,
+    mut on_delta: impl FnMut(&Delta) -> Result<()>,
+) -> Result<WatchEnd> {
+    let git_dir = git_dir(path)?;
+    // Room for one batch: batches arriving while one waits only add to
+    // what the next snapshot finds, so they are dropped, not queued
+    let (tx, rx) = mpsc::sync_channel::<notify_debouncer_mini::notify::Result<()>>(1);
+    let mut debouncer = {
+        let git_dir = git_dir.clone();
+        new_debouncer(interval, move |events: DebounceEventResult| {
+            let batch = match events {
+                Ok(events) if events.iter().any(|e| is_relevant(&e.path, &git_dir)) => Ok(()),
+                Ok(_) => return,
+                Err(e) => Err(e),
+            };
+            if let Err(TrySendError::Full(_)) = tx.try_send(batch) {
+                debug!("A change batch is already waiting; coalescing");
+            }
+        })
+        .context("Failed to start watching files")?
+    };
+    debouncer
+        .watcher()
+        .watch(path, RecursiveMode::Recursive)
+        .with_context(|| format!("Failed to watch {}", path.display()))?;
+    // A linked worktree's git dir lives in the main repository
+    if !git_dir.starts_with(path) {
+        debouncer
+            .watcher()
+            .watch(&git_dir, RecursiveMode::Recursive)
+            .with_context(|| format!("Failed to watch {}", git_dir.display()))?;
+    }
+
+    let mut before = snapshot(path)?;
+    loop {
+        if stop.load(Ordering::SeqCst) {
+            return Ok(WatchEnd::Stopped);
+        }
+        if !worktree::exists(path) {
+            return Ok(WatchEnd::WorktreeRemoved);
+        }
+        match rx.recv_timeout(STOP_POLL) {
+            Ok(Ok(())) => {}
+            // Watching a worktree that is going away fails
+            Ok(Err(_)) if !worktree::exists(path) => return Ok(WatchEnd::WorktreeRemoved),
+            Ok(Err(e)) => return Err(e).context("Failed to watch files"),
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => anyhow::bail!("File watcher stopped unexpectedly"),
+        }
+        let after = match snapshot(path) {
+            Ok(after) => after,
+            // Removed between the check and git status
+            Err(_) if !worktree::exists(path) => return Ok(WatchEnd::WorktreeRemoved),
+            Err(e) => return Err(e),
+        };
+        let paths = changed_paths(path, &before, &after)?;
+        if !paths.is_empty() {
+            on_delta(&delta(path, &after, paths)?)?;
+        }
+        before = after;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::{self, git};
+    use std::path::PathBuf;
+
+    /// Scratch repo with a committed three-line `a.txt` that ignores `target/`
+    fn setup() -> PathBuf {
+        let repo = scratch::repo("watch");
+        std::fs::write(repo.join(".gitignore"), "target/\n").unwrap();
+        std::fs::write(repo.join("a.txt"), "one\ntwo\nthree\n").unwrap();
+        scratch::commit_all(&repo, "init");
+        repo
+    }
+
+    #[test]
+    fn test_parse_status() {
+        let entries = parse_status(" M a.txt\0R  new.rs\0old.rs\0?? dir/b c.txt\0");
+        let files: Vec<&str> = entries.iter().map(|(_, f)| f.as_str()).collect();
+        assert_eq!(files, vec!["a.txt", "new.rs", "dir/b c.txt"]);
+        assert_eq!(entries[2].0, "??");
+    }
+
+    #[test]
+    fn test_only_head_counts_in_the_git_dir() {
+        let git_dir = Path::new("/repo/.git/worktrees/feature");
+        assert!(is_relevant(Path::new("/wt/src/main.rs"), git_dir));
+        assert!(is_relevant(&git_dir.join("HEAD"), git_dir));
+        assert!(is_relevant(&git_dir.join("logs/HEAD"), git_dir));
+        assert!(!is_relevant(&git_dir.join("index"), git_dir));
+        assert!(!is_relevant(&git_dir.join("index.lock"), git_dir));
+    }
+
+    #[test]
+    fn test_changed_paths_and_delta() {
+        let repo = setup();
+        let before = snapshot(&repo).unwrap();
+        assert!(changed_paths(&repo, &before, &snapshot(&repo).unwrap()).unwrap().is_empty());
+
+        std::fs::write(repo.join("a.txt"), "one\n2\nthree\nfour\n").unwrap();
+        std::fs::create_dir_all(repo.join("src")).unwrap();
+        std::fs::write(repo.join("src/new.rs"), "fn main() {}\n").unwrap();
+        std::fs::create_dir_all(repo.join("target")).unwrap();
+        std::fs::write(repo.join("target/out.o"), "ignored").unwrap();
+        let after = snapshot(&repo).unwrap();
+        let paths = changed_paths(&repo, &before, &after).unwrap();
+        assert_eq!(paths, vec!["a.txt", "src/new.rs"]);
+        let delta = delta(&repo, &after, paths).unwrap();
+        assert_eq!((delta.added, delta.removed), (3, 1));
+
+        // Committing moves HEAD; the committed file is reported once more
+        git(&repo, &["commit", "-q", "-am", "edit a"]);
+        let committed = snapshot(&repo).unwrap();
+        assert_eq!(changed_paths(&repo, &after, &committed).unwrap(), vec!["a.txt"]);
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_watch_ends() {
+        let repo = setup();
+        let stop = AtomicBool::new(true);
+        assert_eq!(watch(&repo, Duration::from_millis(50), &stop, |_| Ok(())).unwrap(), WatchEnd::Stopped);
+
+        let writer = {
+            let repo = repo.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(100));
+                std::fs::write(repo.join("b.txt"), "b\n").unwrap();
+            })
+        };
+        let stop = AtomicBool::new(false);
+        let mut deltas = Vec::new();
+        let end = watch(&repo, Duration::from_millis(50), &stop, |delta| {
+            deltas.push(delta.clone());
+            std::fs::remove_dir_all(&repo).unwrap();
+            Ok(())
+        })
+        .unwrap();
+        writer.join().unwrap();
+        assert_eq!(end, WatchEnd::WorktreeRemoved);
+        assert_eq!(deltas, vec![Delta { paths: vec!["b.txt".to_string()], added: 1, removed: 0 }]);
+        scratch::remove(&repo);
+    }
+}
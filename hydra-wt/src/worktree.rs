@@ -1,14 +1,25 @@
-use anyhow::{Context, Result, bail};
-use std::path::Path;
+use anyhow::Context;
+use hydra_log::LoggedCommand;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use tracing::debug;
+
+use crate::config::WtConfig;
+use crate::error::{bail, Result, WtError};
+use crate::health;
+use crate::ports::PortRegistry;
 
 /// Result of a merge operation
 #[derive(Debug)]
 pub enum MergeResult {
     /// Merge completed with a merge commit
-    Success { merge_commit: String },
+    Success { merge_commit: String, subject: String },
     /// Fast-forward merge (no merge commit needed)
-    FastForward { new_head: String },
+    FastForward { new_head: String, subject: String },
+    /// Source changes landed as a single new commit on the target
+    Squashed { commit: String, subject: String, squashed: usize },
     /// Merge has conflicts that need resolution
     Conflict { files: Vec<String> },
     /// Nothing to merge (already up to date)
@@ -21,19 +32,87 @@ pub struct CommitInfo {
     pub hash: String,
     pub message: String,
     pub author: String,
+    /// Author date, ISO 8601
     pub date: String,
+    /// Author date, seconds since the Unix epoch
+    pub timestamp: i64,
+}
+
+/// How two branches have moved apart since their merge base
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    /// Commits the source has that the target doesn't, newest first
+    pub ahead: Vec<CommitInfo>,
+    /// Number of commits the target has that the source doesn't
+    pub behind: usize,
+}
+
+/// How [`merge`] combines the source branch into the target
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeMode {
+    /// Fast-forward when possible, merge commit otherwise
+    #[default]
+    FastForward,
+    /// Always create a merge commit
+    NoFastForward,
+    /// Stage the combined changes and commit them as one new commit
+    Squash,
+}
+
+/// How [`merge_with`] writes the commit it makes, if it makes one
+#[derive(Debug, Clone, Default)]
+pub struct MergeOptions {
+    /// Message of the merge or squash commit, instead of git's or
+    /// [`squash_message`]
+    pub message: Option<String>,
+    /// Sign the commit (`-S`) with the configured signing key
+    pub sign: bool,
 }
 
 /// Get commits that source has but target doesn't
 pub fn commits_ahead(source: &str, target: &str) -> Result<Vec<CommitInfo>> {
+    commits_ahead_in(Path::new("."), source, target)
+}
+
+/// [`commits_ahead`] in the repository at `repo`, newest first
+pub fn commits_ahead_in(repo: &Path, source: &str, target: &str) -> Result<Vec<CommitInfo>> {
+    log_in(repo, &[&format!("{}..{}", target, source)])
+}
+
+/// Commits of `branch` that exist nowhere else: not on `main`, and not
+/// pushed to the branch's upstream if it has one. Newest first.
+pub fn unique_commits_in(repo: &Path, branch: &str, main: &str) -> Result<Vec<CommitInfo>> {
+    let mut revs = vec![branch.to_string(), "--not".to_string(), main.to_string()];
+    if let Some(upstream) = upstream_in(repo, branch)? {
+        revs.push(upstream);
+    }
+    let revs: Vec<&str> = revs.iter().map(String::as_str).collect();
+    log_in(repo, &revs)
+}
+
+/// The upstream `branch` tracks, if it has one that still exists
+pub fn upstream_in(repo: &Path, branch: &str) -> Result<Option<String>> {
     let output = Command::new("git")
-        .args(["log", &format!("{}..{}", target, source), "--format=%H|%s|%an|%ai"])
-        .output()
+        .args(["-C", &repo.to_string_lossy(), "rev-parse", "--abbrev-ref", "--symbolic-full-name", &format!("{}@{{upstream}}", branch)])
+        .logged_output()
+        .context("Failed to run git rev-parse")?;
+    let upstream = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Ok((output.status.success() && !upstream.is_empty()).then_some(upstream))
+}
+
+/// `git log` of `revs`, newest first
+fn log_in(repo: &Path, revs: &[&str]) -> Result<Vec<CommitInfo>> {
+    // Fields are separated by 0x1f so subjects may contain anything
+    let output = Command::new("git")
+        .args(["-C", &repo.to_string_lossy(), "log"])
+        .args(revs)
+        .arg("--format=%H%x1f%an%x1f%ai%x1f%at%x1f%s")
+        .logged_output()
         .context("Failed to run git log")?;
 
     if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        bail!("git log failed: {}", stderr.trim());
+        return Err(WtError::git("git log", &output.stderr));
     }
 
     let stdout = String::from_utf8_lossy(&output.stdout);
@@ -41,13 +120,14 @@ pub fn commits_ahead(source: &str, target: &str) -> Result<Vec<CommitInfo>> {
         .lines()
         .filter(|line| !line.is_empty())
         .filter_map(|line| {
-            let parts: Vec<&str> = line.splitn(4, '|').collect();
-            if parts.len() >= 4 {
+            let parts: Vec<&str> = line.splitn(5, '\x1f').collect();
+            if parts.len() >= 5 {
                 Some(CommitInfo {
                     hash: parts[0].to_string(),
-                    message: parts[1].to_string(),
-                    author: parts[2].to_string(),
-                    date: parts[3].to_string(),
+                    author: parts[1].to_string(),
+                    date: parts[2].to_string(),
+                    timestamp: parts[3].parse().unwrap_or_default(),
+                    message: parts[4].to_string(),
                 })
             } else {
                 None
@@ -58,69 +138,380 @@ pub fn commits_ahead(source: &str, target: &str) -> Result<Vec<CommitInfo>> {
     Ok(commits)
 }
 
+/// Write the commits `revs` selects to a `git bundle` at `path`, e.g.
+/// `main..feature` for what `feature` has that `main` doesn't
+pub fn bundle_in(repo: &Path, revs: &[&str], path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(["bundle", "create"])
+        .arg(path)
+        .args(revs)
+        .logged_output()
+        .context("Failed to run git bundle")?;
+    if !output.status.success() {
+        return Err(WtError::git("git bundle create", &output.stderr));
+    }
+    Ok(())
+}
+
+/// The refs in the bundle at `path`, as `(ref, commit)` pairs
+pub fn bundle_heads_in(repo: &Path, path: &Path) -> Result<Vec<(String, String)>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(["bundle", "list-heads"])
+        .arg(path)
+        .logged_output()
+        .context("Failed to run git bundle")?;
+    if !output.status.success() {
+        return Err(WtError::git("git bundle list-heads", &output.stderr));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(commit, name)| (name.to_string(), commit.to_string()))
+        .collect())
+}
+
+/// Create `branch` from the branch of the same name in the bundle at `path`
+pub fn fetch_bundle_in(repo: &Path, path: &Path, branch: &str) -> Result<()> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .arg("fetch")
+        .arg(path)
+        .arg(format!("refs/heads/{0}:refs/heads/{0}", branch))
+        .logged_output()
+        .context("Failed to run git fetch")?;
+    if !output.status.success() {
+        return Err(WtError::git("git fetch", &output.stderr));
+    }
+    Ok(())
+}
+
+/// Commits `source` is ahead of `target`, and how many it is behind
+pub fn divergence(source: &str, target: &str) -> Result<Divergence> {
+    divergence_in(Path::new("."), source, target)
+}
+
+/// [`divergence`] in the repository at `repo`
+pub fn divergence_in(repo: &Path, source: &str, target: &str) -> Result<Divergence> {
+    let output = Command::new("git")
+        .args(["-C", &repo.to_string_lossy(), "rev-list", "--left-right", "--count", &format!("{}...{}", target, source)])
+        .logged_output()
+        .context("Failed to run git rev-list")?;
+
+    if !output.status.success() {
+        return Err(WtError::git("git rev-list", &output.stderr));
+    }
+
+    // `<only in target>\t<only in source>`
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let behind = stdout
+        .split_whitespace()
+        .next()
+        .and_then(|count| count.parse().ok())
+        .with_context(|| format!("Unexpected git rev-list output: {}", stdout.trim()))?;
+
+    Ok(Divergence { ahead: commits_ahead_in(repo, source, target)?, behind })
+}
+
 /// Get the merge base (common ancestor) of two branches
 pub fn merge_base(source: &str, target: &str) -> Result<String> {
+    merge_base_in(Path::new("."), source, target)
+}
+
+/// [`merge_base`] in the repository at `repo`
+pub fn merge_base_in(repo: &Path, source: &str, target: &str) -> Result<String> {
     let output = Command::new("git")
-        .args(["merge-base", source, target])
-        .output()
+        .args(["-C", &repo.to_string_lossy(), "merge-base", source, target])
+        .logged_output()
         .context("Failed to run git merge-base")?;
 
     if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        bail!("git merge-base failed: {}", stderr.trim());
+        return Err(WtError::git("git merge-base", &output.stderr));
     }
 
     Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
 }
 
+/// How [`diff_in`] prints a diff
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffFormat {
+    /// `git diff --stat`
+    Stat,
+    /// The whole patch
+    Patch,
+    /// Changed paths, one per line
+    NameOnly,
+}
+
+/// Lines one file changed, from `git diff --numstat`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileChange {
+    /// Path after the change; the new path of a rename
+    pub path: String,
+    /// Path before a rename, `None` for any other change
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_path: Option<String>,
+    /// `None` for a binary file
+    pub additions: Option<usize>,
+    pub deletions: Option<usize>,
+}
+
+/// Diff between the commits `from` and `to` of the repository at `repo`,
+/// as git prints it in `format`. Works on refs alone, so `to` doesn't need
+/// a worktree.
+pub fn diff_in(repo: &Path, from: &str, to: &str, format: DiffFormat, color: bool) -> Result<String> {
+    let mut cmd = Command::new("git");
+    cmd.args(["-C", &repo.to_string_lossy(), "diff"])
+        .arg(if color { "--color=always" } else { "--no-color" });
+    match format {
+        DiffFormat::Stat => cmd.arg("--stat"),
+        DiffFormat::Patch => &mut cmd,
+        DiffFormat::NameOnly => cmd.arg("--name-only"),
+    };
+    let output = cmd.args([from, to, "--"]).logged_output().context("Failed to run git diff")?;
+    if !output.status.success() {
+        return Err(WtError::git("git diff", &output.stderr));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Per-file additions and deletions between the commits `from` and `to`
+pub fn diff_files_in(repo: &Path, from: &str, to: &str) -> Result<Vec<FileChange>> {
+    let output = Command::new("git")
+        .args(["-C", &repo.to_string_lossy(), "diff", "--numstat", "-z", from, to, "--"])
+        .logged_output()
+        .context("Failed to run git diff")?;
+    if !output.status.success() {
+        return Err(WtError::git("git diff --numstat", &output.stderr));
+    }
+    Ok(parse_numstat(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// What a branch changed since it left its base: every file with its lines,
+/// and the lines in total
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ChangeSummary {
+    pub files: Vec<FileChange>,
+    /// Lines added across all files; binary files count none
+    pub insertions: usize,
+    pub deletions: usize,
+}
+
+impl ChangeSummary {
+    fn from_files(files: Vec<FileChange>) -> Self {
+        Self {
+            insertions: files.iter().filter_map(|f| f.additions).sum(),
+            deletions: files.iter().filter_map(|f| f.deletions).sum(),
+            files,
+        }
+    }
+
+    /// `14 files changed, +820 -113`
+    pub fn describe(&self) -> String {
+        let plural = if self.files.len() == 1 { "" } else { "s" };
+        format!("{} file{} changed, +{} -{}", self.files.len(), plural, self.insertions, self.deletions)
+    }
+}
+
+/// What `branch` changed since it left `base`, `git diff <base>...<branch>`
+/// with renames detected
+pub fn change_summary(branch: &str, base: &str) -> Result<ChangeSummary> {
+    change_summary_in(Path::new("."), branch, base)
+}
+
+/// [`change_summary`] in the repository at `repo`
+pub fn change_summary_in(repo: &Path, branch: &str, base: &str) -> Result<ChangeSummary> {
+    let range = format!("{}...{}", base, branch);
+    let output = Command::new("git")
+        .args(["-C", &repo.to_string_lossy(), "diff", "--numstat", "-z", "-M", &range, "--"])
+        .logged_output()
+        .context("Failed to run git diff")?;
+    if !output.status.success() {
+        return Err(WtError::git("git diff --numstat", &output.stderr));
+    }
+    Ok(ChangeSummary::from_files(parse_numstat(&String::from_utf8_lossy(&output.stdout))))
+}
+
+/// Per-file additions and deletions of the uncommitted changes to tracked
+/// files in the worktree at `path`, against its HEAD
+pub fn diff_worktree_files_in(path: &Path) -> Result<Vec<FileChange>> {
+    let output = Command::new("git")
+        .args(["-C", &path.to_string_lossy(), "diff", "--numstat", "-z", "HEAD", "--"])
+        .logged_output()
+        .context("Failed to run git diff")?;
+    if !output.status.success() {
+        return Err(WtError::git("git diff --numstat", &output.stderr));
+    }
+    Ok(parse_numstat(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// `git diff --numstat -z` output: `ADDED\tDELETED\tPATH\0` per file, or
+/// `ADDED\tDELETED\t\0OLD\0NEW\0` for a rename. Binary files count `-`.
+fn parse_numstat(numstat: &str) -> Vec<FileChange> {
+    let mut changes = Vec::new();
+    let mut fields = numstat.split('\0');
+    while let Some(record) = fields.next() {
+        let mut parts = record.splitn(3, '\t');
+        let (Some(added), Some(deleted), Some(path)) = (parts.next(), parts.next(), parts.next()) else {
+            continue;
+        };
+        let (path, old_path) = if path.is_empty() {
+            // A rename: the old path, then the new one
+            let old = fields.next().unwrap_or_default();
+            (fields.next().unwrap_or_default(), Some(old.to_string()))
+        } else {
+            (path, None)
+        };
+        changes.push(FileChange {
+            path: path.to_string(),
+            old_path,
+            additions: added.parse().ok(),
+            deletions: deleted.parse().ok(),
+        });
+    }
+    changes
+}
+
+/// What merging a branch would do, predicted without committing anything
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergePreview {
+    /// Paths that would conflict, sorted
+    pub conflicts: Vec<String>,
+}
+
+impl MergePreview {
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
 /// Check if a merge would have conflicts (dry-run)
 pub fn can_merge(target_path: &Path, source: &str) -> Result<bool> {
-    // Try a merge with --no-commit to see if it would succeed
+    Ok(merge_preview(target_path, source)?.is_clean())
+}
+
+/// Predict merging `source` into the branch checked out at `target_path`.
+///
+/// Uses `git merge-tree --write-tree`, which touches neither the index nor
+/// the worktree. Gits older than 2.38 don't have it; there a trial merge
+/// is made in the target worktree and aborted again.
+pub fn merge_preview(target_path: &Path, source: &str) -> Result<MergePreview> {
+    let output = Command::new("git")
+        .args(["-C", &target_path.to_string_lossy(), "merge-tree", "--write-tree", "--name-only", "--no-messages", "HEAD", source])
+        .logged_output()
+        .context("Failed to run git merge-tree")?;
+
+    // 0 is a clean merge and 1 a conflicted one; anything else is either a
+    // real error or a git that doesn't know --write-tree, which the trial
+    // merge tells apart
+    match output.status.code() {
+        Some(0) => Ok(MergePreview::default()),
+        // Without a tree on stdout git refused to merge at all, e.g. an unknown ref
+        Some(1) if output.stdout.is_empty() => Err(WtError::git("git merge-tree", &output.stderr)),
+        Some(1) => {
+            // The tree OID, then one conflicted path per line
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let conflicts: BTreeSet<String> = stdout
+                .lines()
+                .skip(1)
+                .take_while(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect();
+            Ok(MergePreview { conflicts: conflicts.into_iter().collect() })
+        }
+        _ => {
+            debug!("git merge-tree --write-tree unavailable, falling back to a trial merge");
+            trial_merge(target_path, source)
+        }
+    }
+}
+
+/// [`merge_preview`] by merging with `--no-commit` and aborting
+fn trial_merge(target_path: &Path, source: &str) -> Result<MergePreview> {
     let output = Command::new("git")
         .args(["-C", &target_path.to_string_lossy(), "merge", "--no-commit", "--no-ff", source])
-        .output()
+        .logged_output()
         .context("Failed to run git merge --no-commit")?;
 
+    let conflicts = if output.status.success() { Ok(Vec::new()) } else { get_conflict_files(target_path) };
+
     // Abort the merge attempt regardless of outcome
     let _ = Command::new("git")
         .args(["-C", &target_path.to_string_lossy(), "merge", "--abort"])
-        .output();
+        .logged_output();
 
-    Ok(output.status.success())
+    let mut conflicts = conflicts?;
+    if !output.status.success() && conflicts.is_empty() {
+        return Err(WtError::git("git merge --no-commit", &output.stderr));
+    }
+    conflicts.sort();
+    Ok(MergePreview { conflicts })
 }
 
 /// Perform a merge
-pub fn merge(target_path: &Path, source: &str, no_ff: bool) -> Result<MergeResult> {
+pub fn merge(target_path: &Path, source: &str, mode: MergeMode) -> Result<MergeResult> {
+    merge_with(target_path, source, mode, &MergeOptions::default())
+}
+
+/// [`merge`] with the commit message and signing of `opts`. A commit that
+/// can't be signed fails with [`WtError::SigningFailed`], and the merge is
+/// aborted rather than left in progress.
+pub fn merge_with(target_path: &Path, source: &str, mode: MergeMode, opts: &MergeOptions) -> Result<MergeResult> {
     // Check if already up to date
-    let commits = commits_ahead(source, &get_current_branch(target_path)?)?;
+    let commits = commits_ahead_in(target_path, source, &get_current_branch(target_path)?)?;
     if commits.is_empty() {
         return Ok(MergeResult::NothingToMerge);
     }
 
-    let mut args = vec!["-C", &target_path.to_string_lossy().into_owned()];
     let path_str = target_path.to_string_lossy().into_owned();
-    args = vec!["-C", &path_str, "merge"];
-    if no_ff {
-        args.push("--no-ff");
+    let mut args = vec!["-C", &path_str, "merge"];
+    match mode {
+        MergeMode::FastForward => {}
+        MergeMode::NoFastForward => args.push("--no-ff"),
+        MergeMode::Squash => args.push("--squash"),
+    }
+    // A squash commits separately, in commit_squash
+    if mode != MergeMode::Squash {
+        if let Some(message) = &opts.message {
+            args.extend(["-m", message]);
+        }
+        if opts.sign {
+            args.push("-S");
+        }
     }
     args.push(source);
 
     let output = Command::new("git")
         .args(&args)
-        .output()
+        .logged_output()
         .context("Failed to run git merge")?;
 
+    if output.status.success() && mode == MergeMode::Squash {
+        return commit_squash(target_path, source, &commits, opts);
+    }
+
     if output.status.success() {
         let stdout = String::from_utf8_lossy(&output.stdout);
 
+        let (head, subject) = head_and_subject(target_path)?;
         // Check if it was a fast-forward
         if stdout.contains("Fast-forward") {
-            let head = get_head_commit(target_path)?;
-            return Ok(MergeResult::FastForward { new_head: head });
+            return Ok(MergeResult::FastForward { new_head: head, subject });
         }
+        return Ok(MergeResult::Success { merge_commit: head, subject });
+    }
 
-        let head = get_head_commit(target_path)?;
-        return Ok(MergeResult::Success { merge_commit: head });
+    if opts.sign && is_signing_failure(&output.stderr) {
+        merge_abort(target_path)?;
+        return Err(WtError::signing(&output.stderr));
     }
 
     // Check for conflicts
@@ -130,35 +521,227 @@ pub fn merge(target_path: &Path, source: &str, no_ff: bool) -> Result<MergeResul
     }
 
     // Some other error
-    let stderr = String::from_utf8_lossy(&output.stderr);
-    bail!("git merge failed: {}", stderr.trim());
+    Err(WtError::git("git merge", &output.stderr))
 }
 
-/// Abort an in-progress merge
+/// Commit what `git merge --squash` staged, summarizing the squashed commits
+fn commit_squash(target_path: &Path, source: &str, commits: &[CommitInfo], opts: &MergeOptions) -> Result<MergeResult> {
+    let staged = Command::new("git")
+        .args(["-C", &target_path.to_string_lossy(), "diff", "--cached", "--quiet"])
+        .logged_output()
+        .context("Failed to run git diff --cached")?;
+    if staged.status.success() {
+        // The target already has the source's changes; don't leave the squash pending
+        if let Some(squash_msg) = git_path(target_path, "SQUASH_MSG") {
+            let _ = std::fs::remove_file(squash_msg);
+        }
+        return Ok(MergeResult::NothingToMerge);
+    }
+
+    let message = opts.message.clone().unwrap_or_else(|| squash_message(source, commits));
+    let mut command = Command::new("git");
+    command.args(["-C", &target_path.to_string_lossy(), "commit", "-q", "-m", &message]);
+    if opts.sign {
+        command.arg("-S");
+    }
+    let output = command.logged_output().context("Failed to run git commit")?;
+    if !output.status.success() {
+        if opts.sign && is_signing_failure(&output.stderr) {
+            merge_abort(target_path)?;
+            return Err(WtError::signing(&output.stderr));
+        }
+        return Err(WtError::git("git commit of squashed changes", &output.stderr));
+    }
+
+    let (commit, subject) = head_and_subject(target_path)?;
+    Ok(MergeResult::Squashed { commit, subject, squashed: commits.len() })
+}
+
+/// Whether git failed because it could not sign a commit, with gpg or
+/// with an SSH key
+fn is_signing_failure(stderr: &[u8]) -> bool {
+    let stderr = String::from_utf8_lossy(stderr).to_lowercase();
+    ["failed to sign", "gpg failed", "no secret key", "couldn't load public key", "ssh-keygen"]
+        .iter()
+        .any(|needle| stderr.contains(needle))
+}
+
+/// Subject line of commit `rev` in the repository at `repo`
+pub fn commit_subject_in(repo: &Path, rev: &str) -> Result<String> {
+    let output = Command::new("git")
+        .args(["-C", &repo.to_string_lossy(), "log", "-1", "--format=%s", rev, "--"])
+        .logged_output()
+        .context("Failed to run git log")?;
+    if !output.status.success() {
+        return Err(WtError::git("git log", &output.stderr));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Commit hash and subject line of HEAD in the checkout at `path`
+fn head_and_subject(path: &Path) -> Result<(String, String)> {
+    let output = Command::new("git")
+        .args(["-C", &path.to_string_lossy(), "log", "-1", "--format=%H%n%s", "HEAD", "--"])
+        .logged_output()
+        .context("Failed to run git log")?;
+    if !output.status.success() {
+        return Err(WtError::git("git log", &output.stderr));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (head, subject) = stdout.trim_end().split_once('\n').unwrap_or((stdout.trim_end(), ""));
+    Ok((head.to_string(), subject.to_string()))
+}
+
+/// Message for a squash of `commits` (newest first, as from [`commits_ahead`])
+pub fn squash_message(source: &str, commits: &[CommitInfo]) -> String {
+    let plural = if commits.len() == 1 { "" } else { "s" };
+    let mut message = format!("Squash merge branch '{}' ({} commit{})\n\n", source, commits.len(), plural);
+    for commit in commits.iter().rev() {
+        message.push_str(&format!("* {}\n", commit.message));
+    }
+    message
+}
+
+/// Push `branch` from the repository at `repo` to `remote`
+pub fn push_in(repo: &Path, remote: &str, branch: &str) -> Result<()> {
+    let output = Command::new("git")
+        .args(["-C", &repo.to_string_lossy(), "push", "--quiet", remote, branch])
+        .logged_output()
+        .context("Failed to run git push")?;
+    if !output.status.success() {
+        return Err(WtError::git(format!("git push {} {}", remote, branch), &output.stderr));
+    }
+    Ok(())
+}
+
+/// Abort an in-progress merge, including a squash merge stopped by conflicts
 pub fn merge_abort(target_path: &Path) -> Result<()> {
+    if !has_merge_head(target_path) && is_squash_in_progress(target_path) {
+        // A squash records no MERGE_HEAD, so `git merge --abort` refuses it
+        let output = Command::new("git")
+            .args(["-C", &target_path.to_string_lossy(), "reset", "--merge"])
+            .logged_output()
+            .context("Failed to run git reset --merge")?;
+        if !output.status.success() {
+            return Err(WtError::git("git reset --merge", &output.stderr));
+        }
+        if let Some(squash_msg) = git_path(target_path, "SQUASH_MSG") {
+            let _ = std::fs::remove_file(squash_msg);
+        }
+        return Ok(());
+    }
+
     let output = Command::new("git")
         .args(["-C", &target_path.to_string_lossy(), "merge", "--abort"])
-        .output()
+        .logged_output()
         .context("Failed to run git merge --abort")?;
 
     if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        bail!("git merge --abort failed: {}", stderr.trim());
+        return Err(WtError::git("git merge --abort", &output.stderr));
+    }
+
+    Ok(())
+}
+
+/// Result of a rebase
+#[derive(Debug)]
+pub enum RebaseResult {
+    /// The branch was replayed on top of `onto`
+    Rebased { new_head: String },
+    /// The branch already contains `onto`
+    UpToDate,
+    /// The rebase stopped on conflicts and is left in progress
+    Conflict { files: Vec<String> },
+}
+
+/// Fetch all remotes of the repository at `repo`. A repository without
+/// remotes has nothing to fetch.
+pub fn fetch(repo: &Path) -> Result<()> {
+    let remotes = Command::new("git")
+        .args(["-C", &repo.to_string_lossy(), "remote"])
+        .logged_output()
+        .context("Failed to run git remote")?;
+    if String::from_utf8_lossy(&remotes.stdout).trim().is_empty() {
+        debug!("no remotes to fetch");
+        return Ok(());
+    }
+
+    let output = Command::new("git")
+        .args(["-C", &repo.to_string_lossy(), "fetch", "--all", "--prune", "--quiet"])
+        .logged_output()
+        .context("Failed to run git fetch")?;
+    if !output.status.success() {
+        return Err(WtError::git("git fetch", &output.stderr));
+    }
+    Ok(())
+}
+
+/// Rebase the branch checked out at `worktree_path` onto `onto`. Refuses to
+/// start with uncommitted changes or another merge or rebase in progress; a
+/// rebase that hits conflicts is left in progress for the user to resolve.
+pub fn rebase(worktree_path: &Path, onto: &str) -> Result<RebaseResult> {
+    if is_rebase_in_progress(worktree_path) || is_merge_in_progress(worktree_path) {
+        bail!("A merge or rebase is already in progress in {}", worktree_path.display());
+    }
+    if has_uncommitted_changes(worktree_path)? {
+        bail!(
+            "Worktree {} has uncommitted changes. Commit or stash them first",
+            worktree_path.display()
+        );
+    }
+
+    let before = get_head_commit(worktree_path)?;
+    let output = Command::new("git")
+        .args(["-C", &worktree_path.to_string_lossy(), "rebase", onto])
+        .logged_output()
+        .context("Failed to run git rebase")?;
+
+    if output.status.success() {
+        let head = get_head_commit(worktree_path)?;
+        if head == before {
+            return Ok(RebaseResult::UpToDate);
+        }
+        return Ok(RebaseResult::Rebased { new_head: head });
+    }
+
+    let conflict_files = get_conflict_files(worktree_path)?;
+    if !conflict_files.is_empty() {
+        return Ok(RebaseResult::Conflict { files: conflict_files });
+    }
+
+    Err(WtError::git("git rebase", &output.stderr))
+}
+
+/// Abort an in-progress rebase, restoring the branch as it was
+pub fn rebase_abort(worktree_path: &Path) -> Result<()> {
+    let output = Command::new("git")
+        .args(["-C", &worktree_path.to_string_lossy(), "rebase", "--abort"])
+        .logged_output()
+        .context("Failed to run git rebase --abort")?;
+
+    if !output.status.success() {
+        return Err(WtError::git("git rebase --abort", &output.stderr));
     }
 
     Ok(())
 }
 
+/// Check if a rebase is in progress (HEAD is detached while it runs)
+pub fn is_rebase_in_progress(path: &Path) -> bool {
+    ["rebase-merge", "rebase-apply"]
+        .iter()
+        .any(|dir| git_path(path, dir).is_some_and(|p| p.exists()))
+}
+
 /// Check if worktree has uncommitted changes
 pub fn has_uncommitted_changes(path: &Path) -> Result<bool> {
     let output = Command::new("git")
         .args(["-C", &path.to_string_lossy(), "status", "--porcelain"])
-        .output()
+        .logged_output()
         .context("Failed to run git status")?;
 
     if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        bail!("git status failed: {}", stderr.trim());
+        return Err(WtError::git("git status", &output.stderr));
     }
 
     let stdout = String::from_utf8_lossy(&output.stdout);
@@ -169,7 +752,7 @@ pub fn has_uncommitted_changes(path: &Path) -> Result<bool> {
 pub fn get_conflict_files(path: &Path) -> Result<Vec<String>> {
     let output = Command::new("git")
         .args(["-C", &path.to_string_lossy(), "status", "--porcelain"])
-        .output()
+        .logged_output()
         .context("Failed to run git status")?;
 
     let stdout = String::from_utf8_lossy(&output.stdout);
@@ -185,34 +768,61 @@ pub fn get_conflict_files(path: &Path) -> Result<Vec<String>> {
     Ok(conflicts)
 }
 
-/// Check if a merge is in progress
+/// Check if a merge is in progress, including a squash merge stopped by conflicts
 pub fn is_merge_in_progress(path: &Path) -> bool {
-    path.join(".git").join("MERGE_HEAD").exists() ||
-    path.join(".git").is_file() && {
-        // For worktrees, .git is a file pointing to the actual git dir
-        if let Ok(content) = std::fs::read_to_string(path.join(".git")) {
-            if let Some(git_dir) = content.strip_prefix("gitdir: ") {
-                let git_dir = git_dir.trim();
-                Path::new(git_dir).join("MERGE_HEAD").exists()
-            } else {
-                false
-            }
-        } else {
-            false
-        }
+    has_merge_head(path) || is_squash_in_progress(path)
+}
+
+fn has_merge_head(path: &Path) -> bool {
+    git_path(path, "MERGE_HEAD").is_some_and(|p| p.exists())
+}
+
+/// `git merge --squash` leaves SQUASH_MSG until the squash is committed
+fn is_squash_in_progress(path: &Path) -> bool {
+    git_path(path, "SQUASH_MSG").is_some_and(|p| p.exists())
+}
+
+/// What is going on in a worktree that automation should not walk into
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WorkState {
+    /// `None` when git status failed
+    pub uncommitted: Option<bool>,
+    pub merge_in_progress: bool,
+    pub rebase_in_progress: bool,
+}
+
+/// [`WorkState`] of the worktree at `path`. A failing `git status` leaves
+/// `uncommitted` unknown instead of failing, so a worktree stopped halfway
+/// through a rebase still gets reported.
+pub fn work_state(path: &Path) -> WorkState {
+    WorkState {
+        uncommitted: has_uncommitted_changes(path).ok(),
+        merge_in_progress: is_merge_in_progress(path),
+        rebase_in_progress: is_rebase_in_progress(path),
+    }
+}
+
+/// A file in the git dir of the checkout at `path`
+fn git_path(path: &Path, name: &str) -> Option<PathBuf> {
+    let dot_git = path.join(".git");
+    if dot_git.is_dir() {
+        return Some(dot_git.join(name));
     }
+    // For worktrees, .git is a file pointing to the actual git dir
+    let content = std::fs::read_to_string(&dot_git).ok()?;
+    let git_dir = content.strip_prefix("gitdir: ")?.trim();
+    Some(path.join(git_dir).join(name))
 }
 
 /// Get the current branch name for a worktree
 pub fn get_current_branch(path: &Path) -> Result<String> {
     let output = Command::new("git")
         .args(["-C", &path.to_string_lossy(), "rev-parse", "--abbrev-ref", "HEAD"])
-        .output()
+        .logged_output()
         .context("Failed to run git rev-parse")?;
 
     if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        bail!("git rev-parse failed: {}", stderr.trim());
+        return Err(WtError::git("git rev-parse", &output.stderr));
     }
 
     Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
@@ -222,12 +832,11 @@ pub fn get_current_branch(path: &Path) -> Result<String> {
 pub fn get_head_commit(path: &Path) -> Result<String> {
     let output = Command::new("git")
         .args(["-C", &path.to_string_lossy(), "rev-parse", "HEAD"])
-        .output()
+        .logged_output()
         .context("Failed to run git rev-parse HEAD")?;
 
     if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        bail!("git rev-parse HEAD failed: {}", stderr.trim());
+        return Err(WtError::git("git rev-parse HEAD", &output.stderr));
     }
 
     Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
@@ -235,17 +844,79 @@ pub fn get_head_commit(path: &Path) -> Result<String> {
 
 /// Check if a branch exists
 pub fn branch_exists(branch: &str) -> Result<bool> {
+    branch_exists_in(Path::new("."), branch)
+}
+
+/// Check if a branch exists in the repository at `repo`
+pub fn branch_exists_in(repo: &Path, branch: &str) -> Result<bool> {
     let output = Command::new("git")
-        .args(["rev-parse", "--verify", branch])
-        .output()
+        .args(["-C", &repo.to_string_lossy(), "rev-parse", "--verify", branch])
+        .logged_output()
         .context("Failed to check if branch exists")?;
 
     Ok(output.status.success())
 }
 
+/// Check that a name is acceptable as a branch name (`git check-ref-format --branch`)
+pub fn is_valid_branch_name(name: &str) -> bool {
+    if name.is_empty() || name.starts_with('-') {
+        return false;
+    }
+    Command::new("git")
+        .args(["check-ref-format", "--branch", name])
+        .logged_output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+/// Rename a branch from inside the worktree (or repo) at `path`
+pub fn rename_branch(path: &Path, old: &str, new: &str) -> Result<()> {
+    let output = Command::new("git")
+        .args(["-C", &path.to_string_lossy(), "branch", "-m", old, new])
+        .logged_output()
+        .context("Failed to run git branch -m")?;
+
+    if !output.status.success() {
+        return Err(WtError::git("git branch -m", &output.stderr));
+    }
+
+    Ok(())
+}
+
+/// Move a linked worktree to a new location (`git worktree move`)
+pub fn move_worktree(from: &Path, to: &Path) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+
+    let output = Command::new("git")
+        .args([
+            "-C",
+            &from.to_string_lossy(),
+            "worktree",
+            "move",
+            &from.to_string_lossy(),
+            &to.to_string_lossy(),
+        ])
+        .logged_output()
+        .context("Failed to run git worktree move")?;
+
+    if !output.status.success() {
+        return Err(WtError::git("git worktree move", &output.stderr));
+    }
+
+    Ok(())
+}
+
 /// Get worktree path for a branch (if it exists as a worktree)
 pub fn get_worktree_path(branch: &str) -> Result<Option<std::path::PathBuf>> {
-    let worktrees = list()?;
+    get_worktree_path_in(Path::new("."), branch)
+}
+
+/// Get worktree path for a branch in the repository at `repo`
+pub fn get_worktree_path_in(repo: &Path, branch: &str) -> Result<Option<std::path::PathBuf>> {
+    let worktrees = list_in(repo)?;
     for wt in worktrees {
         if wt.branch.as_deref() == Some(branch) {
             return Ok(Some(std::path::PathBuf::from(wt.path)));
@@ -255,33 +926,359 @@ pub fn get_worktree_path(branch: &str) -> Result<Option<std::path::PathBuf>> {
 }
 
 pub fn add(path: &Path, branch: &str) -> Result<()> {
-    // Check if branch exists
-    let branch_exists = Command::new("git")
-        .args(["rev-parse", "--verify", branch])
-        .output()
-        .context("Failed to check if branch exists")?
-        .status
-        .success();
-
-    let output = if branch_exists {
+    add_in(Path::new("."), path, branch)
+}
+
+/// Add a worktree to the repository at `repo`; a relative `path` is relative to `repo`
+pub fn add_in(repo: &Path, path: &Path, branch: &str) -> Result<()> {
+    check_add_in(repo, path, branch)?;
+    let repo_str = repo.to_string_lossy();
+    let output = if branch_exists_in(repo, branch)? {
         // Check out existing branch
         Command::new("git")
-            .args(["worktree", "add", &path.to_string_lossy(), branch])
-            .output()
+            .args(["-C", &repo_str, "worktree", "add", &path.to_string_lossy(), branch])
+            .logged_output()
             .context("Failed to run git worktree add")?
     } else {
         // Create new branch
         Command::new("git")
-            .args(["worktree", "add", "-b", branch, &path.to_string_lossy()])
-            .output()
+            .args(["-C", &repo_str, "worktree", "add", "-b", branch, &path.to_string_lossy()])
+            .logged_output()
             .context("Failed to run git worktree add -b")?
     };
 
     if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        bail!("git worktree add failed: {}", stderr.trim());
+        return Err(WtError::git("git worktree add", &output.stderr));
+    }
+
+    Ok(())
+}
+
+/// Like [`add`], checking out only the `sparse` directories
+pub fn add_sparse(path: &Path, branch: &str, sparse: &[String]) -> Result<()> {
+    add_sparse_in(Path::new("."), path, branch, sparse)
+}
+
+/// [`add_in`] as a cone-mode sparse checkout of the `sparse` directories,
+/// set up before any file is written, as `worktrees.sparse` asks for; a
+/// full checkout when `sparse` is empty. A failure after git registered the
+/// worktree leaves it for the caller to remove, as with [`add_with_progress`].
+pub fn add_sparse_in(repo: &Path, path: &Path, branch: &str, sparse: &[String]) -> Result<()> {
+    if sparse.is_empty() {
+        return add_in(repo, path, branch);
+    }
+    add_with_progress(repo, path, branch, None, sparse, |_| Ok(()))
+}
+
+/// Refuse what `git worktree add` would: a `path` that exists and isn't an
+/// empty directory, or a `branch` checked out in another worktree
+fn check_add_in(repo: &Path, path: &Path, branch: &str) -> Result<()> {
+    // A relative path is relative to the repository, like git's
+    let wt_path = repo.join(path);
+    let empty_dir = wt_path.read_dir().is_ok_and(|mut entries| entries.next().is_none());
+    if wt_path.exists() && !empty_dir {
+        return Err(WtError::WorktreeExists { path: wt_path });
+    }
+    if let Some(checked_out) = get_worktree_path_in(repo, branch)? {
+        return Err(WtError::BranchCheckedOut { branch: branch.to_string(), path: checked_out });
+    }
+    Ok(())
+}
+
+/// Add a worktree on a new `branch` started at `base_ref` instead of HEAD
+pub fn add_from(path: &Path, branch: &str, base_ref: &str) -> Result<()> {
+    add_from_in(Path::new("."), path, branch, base_ref)
+}
+
+/// [`add_from`] for the repository at `repo`
+pub fn add_from_in(repo: &Path, path: &Path, branch: &str, base_ref: &str) -> Result<()> {
+    let commit = check_base_ref_in(repo, branch, base_ref)?;
+    check_add_in(repo, path, branch)?;
+    let output = Command::new("git")
+        .args(["-C", &repo.to_string_lossy(), "worktree", "add", "-b", branch, &path.to_string_lossy(), &commit])
+        .logged_output()
+        .context("Failed to run git worktree add -b")?;
+
+    if !output.status.success() {
+        return Err(WtError::git("git worktree add", &output.stderr));
+    }
+
+    Ok(())
+}
+
+/// Resolve the commit a new `branch` would start from. Fails if `branch`
+/// already exists, since a start point can't apply to it, or if `base_ref`
+/// doesn't name a commit.
+pub fn check_base_ref_in(repo: &Path, branch: &str, base_ref: &str) -> Result<String> {
+    if branch_exists_in(repo, branch)? {
+        bail!(
+            "Branch '{}' already exists; a base ref ('{}') only applies when creating a new branch",
+            branch, base_ref
+        );
+    }
+    if base_ref.starts_with('-') {
+        bail!("'{}' is not a valid base ref", base_ref);
+    }
+    let output = Command::new("git")
+        .args(["-C", &repo.to_string_lossy(), "rev-parse", "--verify", "--quiet"])
+        .arg(format!("{}^{{commit}}", base_ref))
+        .logged_output()
+        .context("Failed to run git rev-parse")?;
+    if !output.status.success() {
+        bail!("Base ref '{}' does not resolve to a commit", base_ref);
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// How far git's checkout has got, from "Updating files:  45% (90000/200000)"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CheckoutProgress {
+    pub done: u64,
+    pub total: u64,
+}
+
+impl CheckoutProgress {
+    pub fn percent(&self) -> u64 {
+        (self.done * 100).checked_div(self.total).unwrap_or(100)
+    }
+}
+
+/// Parse one `--progress` line of `git checkout`
+pub fn parse_checkout_progress(line: &str) -> Option<CheckoutProgress> {
+    let rest = line.trim().strip_prefix("Updating files:")?;
+    let counts = rest.split_once('(')?.1.split_once(')')?.0;
+    let (done, total) = counts.split_once('/')?;
+    Some(CheckoutProgress {
+        done: done.trim().parse().ok()?,
+        total: total.trim().parse().ok()?,
+    })
+}
+
+/// Like [`add_in`], but fills the checkout with git's progress output
+/// reported to `on_progress` as it arrives. The worktree is registered with
+/// `--no-checkout` first, so a failure (or an error from `on_progress`,
+/// which stops git) can leave a registered, partly checked out worktree
+/// behind for the caller to remove.
+///
+/// A new branch starts at `base_ref` when given (see [`check_base_ref_in`]),
+/// at HEAD otherwise. With `sparse` paths the worktree is a cone-mode sparse
+/// checkout of just those directories, set up before any file is written.
+pub fn add_with_progress<F>(
+    repo: &Path,
+    path: &Path,
+    branch: &str,
+    base_ref: Option<&str>,
+    sparse: &[String],
+    mut on_progress: F,
+) -> Result<()>
+where
+    F: FnMut(CheckoutProgress) -> anyhow::Result<()>,
+{
+    use std::io::Read;
+    use std::process::Stdio;
+
+    let repo_str = repo.to_string_lossy();
+    let path_str = path.to_string_lossy();
+    let start = base_ref.map(|r| check_base_ref_in(repo, branch, r)).transpose()?;
+    check_add_in(repo, path, branch)?;
+    let mut args = vec!["-C", &repo_str, "worktree", "add", "--no-checkout"];
+    if let Some(start) = &start {
+        args.extend(["-b", branch, &*path_str, start]);
+    } else if branch_exists_in(repo, branch)? {
+        args.extend([&*path_str, branch]);
+    } else {
+        args.extend(["-b", branch, &*path_str]);
+    }
+    let output = Command::new("git")
+        .args(&args)
+        .logged_output()
+        .context("Failed to run git worktree add")?;
+    if !output.status.success() {
+        return Err(WtError::git("git worktree add", &output.stderr));
+    }
+
+    // A relative path is relative to the repository, like git's
+    let wt_path = repo.join(path);
+    if !sparse.is_empty() {
+        sparse_set_in(&wt_path, sparse)?;
+    }
+    let mut cmd = Command::new("git");
+    cmd.arg("-C")
+        .arg(&wt_path)
+        .args(["checkout", "--progress", "--force", "HEAD"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+    debug!("$ {}", hydra_log::command_line(&cmd));
+    let mut child = cmd.spawn().context("Failed to run git checkout")?;
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+
+    // Progress lines end in \r, everything else in \n
+    let mut pending = Vec::new();
+    let mut messages = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        let n = match stderr.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(anyhow::Error::new(e).context("Failed to read git checkout output").into()),
+        };
+        pending.extend_from_slice(&chunk[..n]);
+        while let Some(end) = pending.iter().position(|&b| b == b'\r' || b == b'\n') {
+            let line: Vec<u8> = pending.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            match parse_checkout_progress(&line) {
+                Some(progress) => {
+                    if let Err(e) = on_progress(progress) {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                        return Err(e.into());
+                    }
+                }
+                None if !line.trim().is_empty() => messages.push(line.trim().to_string()),
+                None => {}
+            }
+        }
     }
 
+    let status = child.wait().context("Failed to wait for git checkout")?;
+    debug!("git checkout -> {}", status);
+    if !status.success() {
+        return Err(WtError::GitFailed {
+            command: "git checkout in new worktree".to_string(),
+            stderr: messages.join("; "),
+        });
+    }
+    Ok(())
+}
+
+/// Make the worktree at `wt_path` a cone-mode sparse checkout of `paths`
+/// (`git sparse-checkout init --cone` and `set` in one)
+pub fn sparse_set_in(wt_path: &Path, paths: &[String]) -> Result<()> {
+    sparse_checkout_in(wt_path, "set", paths)
+}
+
+/// Add `paths` to the sparse checkout of the worktree at `wt_path`
+pub fn sparse_add_in(wt_path: &Path, paths: &[String]) -> Result<()> {
+    sparse_checkout_in(wt_path, "add", paths)
+}
+
+fn sparse_checkout_in(wt_path: &Path, action: &str, paths: &[String]) -> Result<()> {
+    let mut args = vec!["sparse-checkout", action];
+    if action == "set" {
+        args.push("--cone");
+    }
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(wt_path)
+        .args(args)
+        .arg("--")
+        .args(paths)
+        .logged_output()
+        .context("Failed to run git sparse-checkout")?;
+    if !output.status.success() {
+        return Err(WtError::git(format!("git sparse-checkout {}", action), &output.stderr));
+    }
+    Ok(())
+}
+
+/// The directories the worktree at `wt_path` checks out, `None` if it
+/// isn't a sparse checkout
+pub fn sparse_paths_in(wt_path: &Path) -> Result<Option<Vec<String>>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(wt_path)
+        .args(["config", "--bool", "core.sparseCheckout"])
+        .logged_output()
+        .context("Failed to run git config")?;
+    if String::from_utf8_lossy(&output.stdout).trim() != "true" {
+        return Ok(None);
+    }
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(wt_path)
+        .args(["sparse-checkout", "list"])
+        .logged_output()
+        .context("Failed to run git sparse-checkout")?;
+    if !output.status.success() {
+        return Err(WtError::git("git sparse-checkout list", &output.stderr));
+    }
+    Ok(Some(String::from_utf8_lossy(&output.stdout).lines().map(str::to_string).collect()))
+}
+
+/// Force-remove a worktree of the repository at `repo`, then prune what
+/// git still has registered for it
+pub fn remove_in(repo: &Path, path: &Path) -> Result<()> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(["worktree", "remove", "--force", "--force"])
+        .arg(path)
+        .logged_output()
+        .context("Failed to run git worktree remove")?;
+    if !output.status.success() && path.exists() {
+        return Err(WtError::git("git worktree remove", &output.stderr));
+    }
+    prune_in(repo)
+}
+
+/// Remove the directories between `path` and `root` that are left empty,
+/// such as `feature/` once the worktree of `feature/login` is gone. Nothing
+/// at or above `root` is touched.
+pub fn remove_empty_parents(path: &Path, root: &Path) {
+    let mut dir = path.parent();
+    while let Some(parent) = dir {
+        if parent == root || !parent.starts_with(root) {
+            break;
+        }
+        // Fails, and stops the walk, once a directory isn't empty
+        if std::fs::remove_dir(parent).is_err() {
+            break;
+        }
+        debug!("removed empty directory {}", parent.display());
+        dir = parent.parent();
+    }
+}
+
+/// Drop git's bookkeeping for worktrees whose directory no longer exists
+pub fn prune_in(repo: &Path) -> Result<()> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(["worktree", "prune"])
+        .logged_output()
+        .context("Failed to run git worktree prune")?;
+    if !output.status.success() {
+        return Err(WtError::git("git worktree prune", &output.stderr));
+    }
+    Ok(())
+}
+
+pub fn delete_branch(branch: &str, force: bool) -> Result<()> {
+    delete_branch_in(Path::new("."), branch, force)
+}
+
+/// Delete a local branch of the repository at `repo`. Without `force` git
+/// refuses a branch that isn't merged into the HEAD of `repo`.
+pub fn delete_branch_in(repo: &Path, branch: &str, force: bool) -> Result<()> {
+    let flag = if force { "-D" } else { "-d" };
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(["branch", flag, branch])
+        .logged_output()
+        .with_context(|| format!("Failed to run git branch {}", flag))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !force && stderr.contains("not fully merged") {
+            bail!(
+                "Branch '{}' is not fully merged; delete it anyway with: git branch -D {}",
+                branch,
+                branch
+            );
+        }
+        return Err(WtError::git(format!("git branch {}", flag), &output.stderr));
+    }
     Ok(())
 }
 
@@ -295,12 +1292,11 @@ pub fn remove(path: &Path, force: bool) -> Result<()> {
 
     let output = Command::new("git")
         .args(&args)
-        .output()
+        .logged_output()
         .context("Failed to run git worktree remove")?;
 
     if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        bail!("git worktree remove failed: {}", stderr.trim());
+        return Err(WtError::git("git worktree remove", &output.stderr));
     }
 
     Ok(())
@@ -319,56 +1315,716 @@ pub fn exists(path: &Path) -> bool {
 #[derive(Debug)]
 pub struct WorktreeInfo {
     pub path: String,
+    /// `None` for a detached HEAD
     pub branch: Option<String>,
     pub head: String,
+    /// HEAD is detached rather than on a branch
+    pub detached: bool,
+    /// Why the worktree is locked, empty if no reason was given; `None` when
+    /// it isn't locked. git refuses to remove a locked worktree without
+    /// `--force` twice.
+    pub locked: Option<String>,
+    /// Why git would prune the worktree, e.g. its directory is gone
+    pub prunable: Option<String>,
 }
 
 pub fn list() -> Result<Vec<WorktreeInfo>> {
+    list_in(Path::new("."))
+}
+
+/// List worktrees of the repository at `repo`
+pub fn list_in(repo: &Path) -> Result<Vec<WorktreeInfo>> {
     let output = Command::new("git")
-        .args(["worktree", "list", "--porcelain"])
-        .output()
+        .args(["-C", &repo.to_string_lossy(), "worktree", "list", "--porcelain"])
+        .logged_output()
         .context("Failed to run git worktree list")?;
 
     if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        bail!("git worktree list failed: {}", stderr.trim());
+        return Err(WtError::git("git worktree list", &output.stderr));
     }
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let mut worktrees = Vec::new();
-    let mut current_path: Option<String> = None;
-    let mut current_head: Option<String> = None;
-    let mut current_branch: Option<String> = None;
-
-    for line in stdout.lines() {
-        if line.starts_with("worktree ") {
-            // Save previous worktree if any
-            if let (Some(path), Some(head)) = (current_path.take(), current_head.take()) {
-                worktrees.push(WorktreeInfo {
-                    path,
-                    head,
-                    branch: current_branch.take(),
-                });
+    Ok(parse_worktree_list(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Entries of `git worktree list --porcelain`, which are separated by blank
+/// lines. Entries without a HEAD, like that of a bare repository, are skipped.
+fn parse_worktree_list(porcelain: &str) -> Vec<WorktreeInfo> {
+    porcelain.split("\n\n").filter_map(parse_worktree_entry).collect()
+}
+
+fn parse_worktree_entry(entry: &str) -> Option<WorktreeInfo> {
+    let mut path = None;
+    let mut head = None;
+    let mut branch = None;
+    let mut detached = false;
+    let mut locked = None;
+    let mut prunable = None;
+
+    for line in entry.lines() {
+        // `locked` and `prunable` may come without a reason
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        match key {
+            "worktree" => path = Some(value.to_string()),
+            "HEAD" => head = Some(value.to_string()),
+            "branch" => branch = Some(value.strip_prefix("refs/heads/").unwrap_or(value).to_string()),
+            "detached" => detached = true,
+            "locked" => locked = Some(value.to_string()),
+            "prunable" => prunable = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    Some(WorktreeInfo { path: path?, head: head?, branch, detached, locked, prunable })
+}
+
+/// What `list` and `status` report about one managed worktree
+#[derive(Debug, Clone, Serialize)]
+pub struct WorktreeStatus {
+    pub branch: String,
+    /// First allocated port, if any
+    pub port: Option<u16>,
+    /// Every port of the branch's block
+    pub ports: Vec<u16>,
+    pub path: PathBuf,
+    pub exists: bool,
+    /// Commit checked out in the worktree
+    pub head: Option<String>,
+    /// Lock reason, empty if none was given; `None` when not locked
+    pub locked: Option<String>,
+    /// When the worktree was created, if the registry recorded it
+    pub created_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Which tool created the worktree, `unknown` if the registry didn't
+    /// record it
+    pub owner: String,
+    /// Directories a sparse worktree checks out; `None` for a full one
+    pub sparse: Option<Vec<String>>,
+    /// Commits ahead of the main branch; `None` for the main branch itself
+    /// or when git can't tell
+    pub commits_ahead: Option<usize>,
+    /// Commits the main branch has that this branch doesn't; `None` like
+    /// `commits_ahead`
+    pub commits_behind: Option<usize>,
+    /// Merging the main branch into the worktree would conflict
+    pub conflicts: bool,
+    /// Files that merge would conflict in
+    pub conflict_files: Vec<String>,
+    /// Labels of branch problems found by `verify`, e.g. `upstream-gone`
+    pub issues: Vec<&'static str>,
+    /// The worktree has uncommitted changes; `None` when it is missing or
+    /// git can't tell
+    pub uncommitted: Option<bool>,
+    /// A merge or squash merge is stopped in the worktree
+    pub merge_in_progress: bool,
+    /// A rebase is stopped in the worktree
+    pub rebase_in_progress: bool,
+}
+
+/// Status of every branch in `registry` of the repository at `repo_root`,
+/// sorted by branch
+pub fn collect_status(cfg: &WtConfig, repo_root: &Path, registry: &PortRegistry) -> Result<Vec<WorktreeStatus>> {
+    let main_branch = detect_main_branch();
+    let worktrees = list_in(repo_root)?;
+    Ok(registry
+        .branches()
+        .into_iter()
+        .map(|branch| status_of(cfg, repo_root, registry, &worktrees, &main_branch, branch))
+        .collect())
+}
+
+/// Status of one branch, managed or not
+pub fn branch_status(cfg: &WtConfig, repo_root: &Path, registry: &PortRegistry, branch: &str) -> Result<WorktreeStatus> {
+    let worktrees = list_in(repo_root)?;
+    Ok(status_of(cfg, repo_root, registry, &worktrees, &detect_main_branch(), branch))
+}
+
+fn status_of(
+    cfg: &WtConfig,
+    repo_root: &Path,
+    registry: &PortRegistry,
+    worktrees: &[WorktreeInfo],
+    main_branch: &str,
+    branch: &str,
+) -> WorktreeStatus {
+    let path = registry.worktree_path(cfg, repo_root, branch);
+    let exists = exists(&path);
+    let block = registry.block(branch);
+    let locked = worktrees
+        .iter()
+        .find(|wt| wt.branch.as_deref() == Some(branch) || Path::new(&wt.path) == path)
+        .and_then(|wt| wt.locked.clone());
+
+    let mut commits_ahead = None;
+    let mut commits_behind = None;
+    let mut conflict_files = Vec::new();
+    if branch != main_branch {
+        if let Ok(divergence) = divergence_in(repo_root, branch, main_branch) {
+            commits_ahead = Some(divergence.ahead.len());
+            commits_behind = Some(divergence.behind);
+            // Check if can merge without conflicts
+            if !divergence.ahead.is_empty() && exists {
+                if let Ok(preview) = merge_preview(&path, main_branch) {
+                    conflict_files = preview.conflicts;
+                }
             }
-            current_path = Some(line.strip_prefix("worktree ").unwrap().to_string());
-        } else if line.starts_with("HEAD ") {
-            current_head = Some(line.strip_prefix("HEAD ").unwrap().to_string());
-        } else if line.starts_with("branch ") {
-            let branch = line.strip_prefix("branch refs/heads/").unwrap_or(
-                line.strip_prefix("branch ").unwrap()
-            );
-            current_branch = Some(branch.to_string());
         }
     }
+    let state = if exists { work_state(&path) } else { WorkState::default() };
+
+    WorktreeStatus {
+        branch: branch.to_string(),
+        port: block.map(|b| b.start),
+        ports: block.map(|b| b.ports().collect()).unwrap_or_default(),
+        head: if exists { get_head_commit(&path).ok() } else { None },
+        locked,
+        created_at: registry.created_at(branch),
+        owner: registry.owner(branch).to_string(),
+        sparse: if exists { sparse_paths_in(&path).ok().flatten() } else { None },
+        issues: if exists {
+            health::check(repo_root, branch, registry)
+                .map(|issues| issues.iter().map(|i| i.label()).collect())
+                .unwrap_or_default()
+        } else {
+            Vec::new()
+        },
+        path,
+        exists,
+        commits_ahead,
+        commits_behind,
+        conflicts: !conflict_files.is_empty(),
+        conflict_files,
+        uncommitted: state.uncommitted,
+        merge_in_progress: state.merge_in_progress,
+        rebase_in_progress: state.rebase_in_progress,
+    }
+}
+
+/// `main` or `master`, whichever exists (`main` if neither does)
+pub fn detect_main_branch() -> String {
+    detect_main_branch_in(Path::new("."))
+}
+
+/// [`detect_main_branch`] of the repository at `repo`
+pub fn detect_main_branch_in(repo: &Path) -> String {
+    // Try common main branch names
+    for branch in &["main", "master"] {
+        if branch_exists_in(repo, branch).unwrap_or(false) {
+            return branch.to_string();
+        }
+    }
+    // Fallback
+    "main".to_string()
+}
 
-    // Save last worktree
-    if let (Some(path), Some(head)) = (current_path, current_head) {
-        worktrees.push(WorktreeInfo {
-            path,
-            head,
-            branch: current_branch,
+/// How a command run by [`exec_in_all`] ended in one worktree
+#[derive(Debug, Clone)]
+pub struct ExecResult {
+    pub branch: String,
+    pub path: PathBuf,
+    /// `Err` with the reason when the command could not be started
+    pub status: std::result::Result<std::process::ExitStatus, String>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl ExecResult {
+    pub fn success(&self) -> bool {
+        self.status.as_ref().is_ok_and(|status| status.success())
+    }
+
+    /// The exit code, or how the command ended without one
+    pub fn describe(&self) -> String {
+        match &self.status {
+            Ok(status) => match status.code() {
+                Some(code) => code.to_string(),
+                None => status.to_string(),
+            },
+            Err(_) => "not started".to_string(),
+        }
+    }
+}
+
+/// Run `command` (a program and its arguments, no shell) in every managed
+/// worktree of the repository at `repo_root` whose directory exists, or
+/// only in the one of `branch`, at most `parallel` at a time. Each run has
+/// the worktree as its working directory and the variables of
+/// [`HookContext::env`](crate::hooks::HookContext::env), such as
+/// `HYDRA_WT_BRANCH` and `HYDRA_WT_PORT`; its output is captured.
+/// `on_done` sees each result as its command exits. Returns the results
+/// sorted by branch; a command that fails is a result, not an error.
+pub fn exec_in_all(
+    cfg: &WtConfig,
+    repo_root: &Path,
+    registry: &PortRegistry,
+    branch: Option<&str>,
+    command: &[String],
+    parallel: usize,
+    on_done: impl Fn(&ExecResult) + Sync,
+) -> Result<Vec<ExecResult>> {
+    let Some((program, args)) = command.split_first() else {
+        bail!("No command to run");
+    };
+    if let Some(branch) = branch {
+        if !registry.is_managed(branch) {
+            bail!("No worktree for '{}' is managed by hydra-wt", branch);
+        }
+        let path = registry.worktree_path(cfg, repo_root, branch);
+        if !exists(&path) {
+            bail!("The worktree of '{}' is missing at {}", branch, path.display());
+        }
+    }
+    let targets: Vec<(String, PathBuf)> = registry
+        .branches()
+        .into_iter()
+        .filter(|managed| branch.is_none_or(|branch| *managed == branch))
+        .map(|managed| (managed.clone(), registry.worktree_path(cfg, repo_root, managed)))
+        .filter(|(_, path)| exists(path))
+        .collect();
+    let project_uuid = crate::config::get_project_uuid_at(repo_root).ok();
+
+    let next = std::sync::atomic::AtomicUsize::new(0);
+    let results = std::sync::Mutex::new(Vec::with_capacity(targets.len()));
+    std::thread::scope(|scope| {
+        for _ in 0..parallel.clamp(1, targets.len().max(1)) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                let Some((branch, path)) = targets.get(i) else { break };
+                let ctx = crate::hooks::HookContext {
+                    branch,
+                    ports: registry.block(branch),
+                    worktree: path,
+                    repo_root,
+                    project_uuid: project_uuid.clone(),
+                };
+                let output = Command::new(program)
+                    .args(args)
+                    .envs(ctx.env())
+                    .current_dir(path)
+                    .stdin(std::process::Stdio::null())
+                    .logged_output();
+                let result = match output {
+                    Ok(output) => ExecResult {
+                        branch: branch.clone(),
+                        path: path.clone(),
+                        status: Ok(output.status),
+                        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+                        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+                    },
+                    Err(e) => ExecResult {
+                        branch: branch.clone(),
+                        path: path.clone(),
+                        status: Err(format!("Failed to run {}: {}", program, e)),
+                        stdout: String::new(),
+                        stderr: String::new(),
+                    },
+                };
+                on_done(&result);
+                results.lock().unwrap().push(result);
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by(|a, b| a.branch.cmp(&b.branch));
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::{self, git};
+    use hydra_log::Verbosity;
+
+    fn add_logged(verbosity: Verbosity) -> String {
+        let repo = scratch::repo("worktree");
+        scratch::commit_all(&repo, "init");
+        let (subscriber, buffer) = hydra_log::capture(verbosity);
+        tracing::subscriber::with_default(subscriber, || {
+            add_in(&repo, &repo.join("wt-feature"), "feature").unwrap();
         });
+        assert!(branch_exists_in(&repo, "feature").unwrap());
+        scratch::remove(&repo);
+        buffer.contents()
+    }
+
+    /// Branch `feature` with three commits on top of main, in its own worktree
+    fn repo_with_feature_commits() -> std::path::PathBuf {
+        let repo = scratch::repo("worktree");
+        scratch::commit_all(&repo, "init");
+        std::fs::write(repo.join(".git/info/exclude"), ".hydra/\nwt-*\n").unwrap();
+        let wt = repo.join("wt-feature");
+        add_in(&repo, &wt, "feature").unwrap();
+        for i in 1..=3 {
+            std::fs::write(wt.join(format!("step{}.txt", i)), "x").unwrap();
+            git(&wt, &["add", "."]);
+            git(&wt, &["commit", "-q", "-m", &format!("WIP {}", i)]);
+        }
+        repo
     }
 
-    Ok(worktrees)
+    #[test]
+    fn test_merge_preview_lists_conflicts() {
+        let repo = repo_with_feature_commits();
+        for file in ["step1.txt", "step3.txt"] {
+            std::fs::write(repo.join(file), "main").unwrap();
+        }
+        git(&repo, &["add", "."]);
+        git(&repo, &["commit", "-q", "-m", "main edits"]);
+
+        let expected = vec!["step1.txt".to_string(), "step3.txt".to_string()];
+        assert_eq!(merge_preview(&repo, "feature").unwrap().conflicts, expected);
+        // The fallback for old gits agrees and leaves nothing behind
+        assert_eq!(trial_merge(&repo, "feature").unwrap().conflicts, expected);
+        assert!(!is_merge_in_progress(&repo));
+        assert!(!has_uncommitted_changes(&repo).unwrap());
+
+        assert!(merge_preview(&repo.join("wt-feature"), "main~1").unwrap().is_clean());
+        assert!(trial_merge(&repo.join("wt-feature"), "main~1").unwrap().is_clean());
+        assert!(merge_preview(&repo, "no-such-branch").is_err());
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_divergence() {
+        let repo = repo_with_feature_commits();
+        for i in 1..=2 {
+            git(&repo, &["commit", "-q", "--allow-empty", "-m", &format!("main {}", i)]);
+        }
+        git(&repo.join("wt-feature"), &["commit", "-q", "--allow-empty", "-m", "pipes | in | subject"]);
+
+        let divergence = divergence_in(&repo, "feature", "main").unwrap();
+        assert_eq!(divergence.behind, 2);
+        let subjects: Vec<&str> = divergence.ahead.iter().map(|c| c.message.as_str()).collect();
+        assert_eq!(subjects, vec!["pipes | in | subject", "WIP 3", "WIP 2", "WIP 1"]);
+        assert_eq!(divergence.ahead[0].author, "Test");
+        assert!(divergence.ahead[0].timestamp > 1_600_000_000);
+
+        let reverse = divergence_in(&repo, "main", "feature").unwrap();
+        assert_eq!((reverse.ahead.len(), reverse.behind), (2, 4));
+        assert!(divergence_in(&repo, "no-such-branch", "main").is_err());
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_delete_branch() {
+        let repo = repo_with_feature_commits();
+        git(&repo, &["branch", "merged"]);
+        git(&repo, &["branch", "unmerged", "feature"]);
+
+        delete_branch_in(&repo, "merged", false).unwrap();
+        assert!(!branch_exists_in(&repo, "merged").unwrap());
+
+        let err = delete_branch_in(&repo, "unmerged", false).unwrap_err().to_string();
+        assert!(err.contains("not fully merged") && err.contains("git branch -D unmerged"), "{}", err);
+        assert!(branch_exists_in(&repo, "unmerged").unwrap());
+        delete_branch_in(&repo, "unmerged", true).unwrap();
+        assert!(!branch_exists_in(&repo, "unmerged").unwrap());
+
+        // Still checked out in its worktree
+        assert!(delete_branch_in(&repo, "feature", true).is_err());
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_unique_commits_and_bundle() {
+        let repo = repo_with_feature_commits();
+        let wt = repo.join("wt-feature");
+        let unique = unique_commits_in(&repo, "feature", "main").unwrap();
+        assert_eq!(unique.iter().map(|c| c.message.as_str()).collect::<Vec<_>>(), vec!["WIP 3", "WIP 2", "WIP 1"]);
+
+        // Pushed commits exist on the remote
+        let remote = repo.with_extension("remote.git");
+        git(&repo, &["init", "-q", "--bare", &remote.to_string_lossy()]);
+        git(&wt, &["remote", "add", "origin", &remote.to_string_lossy()]);
+        git(&wt, &["push", "-q", "-u", "origin", "feature"]);
+        assert_eq!(upstream_in(&repo, "feature").unwrap().as_deref(), Some("origin/feature"));
+        git(&wt, &["commit", "-q", "--allow-empty", "-m", "local only"]);
+        let unique = unique_commits_in(&repo, "feature", "main").unwrap();
+        assert_eq!(unique.iter().map(|c| c.message.as_str()).collect::<Vec<_>>(), vec!["local only"]);
+
+        // Fully merged into main
+        git(&repo, &["merge", "-q", "--ff-only", "feature"]);
+        assert!(unique_commits_in(&repo, "feature", "main").unwrap().is_empty());
+        assert_eq!(upstream_in(&repo, "main").unwrap(), None);
+
+        let bundle = repo.join(".hydra/archives/feature.bundle");
+        bundle_in(&repo, &["-1", "feature"], &bundle).unwrap();
+        git(&repo, &["bundle", "verify", "-q", &bundle.to_string_lossy()]);
+        let heads = bundle_heads_in(&repo, &bundle).unwrap();
+        assert_eq!(heads, vec![("refs/heads/feature".to_string(), get_head_commit(&wt).unwrap())]);
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_merge_squash() {
+        let repo = repo_with_feature_commits();
+        let before = get_head_commit(&repo).unwrap();
+
+        let commit = match merge(&repo, "feature", MergeMode::Squash).unwrap() {
+            MergeResult::Squashed { commit, subject, squashed } => {
+                assert_eq!(squashed, 3);
+                assert_eq!(subject, "Squash merge branch 'feature' (3 commits)");
+                assert_eq!(commit_subject_in(&repo, &commit).unwrap(), subject);
+                commit
+            }
+            other => panic!("expected a squash, got {:?}", other),
+        };
+        assert_eq!(get_head_commit(&repo).unwrap(), commit);
+        assert!(repo.join("step3.txt").exists());
+        assert!(!is_merge_in_progress(&repo));
+
+        // One new commit on main, with the summary as its message
+        let log = git(&repo, &["log", "--format=%P%n%B", "-1"]);
+        assert!(log.starts_with(&format!("{}\n", before)), "{}", log);
+        assert!(log.contains("Squash merge branch 'feature' (3 commits)\n\n* WIP 1\n* WIP 2\n* WIP 3"), "{}", log);
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_merge_with_message_and_signing() {
+        let repo = repo_with_feature_commits();
+        let before = get_head_commit(&repo).unwrap();
+
+        // No key to sign with: the merge is refused and not left in progress
+        git(&repo, &["config", "gpg.program", "false"]);
+        let opts = MergeOptions { message: Some("chore(merge): feature into main".to_string()), sign: true };
+        match merge_with(&repo, "feature", MergeMode::NoFastForward, &opts) {
+            Err(WtError::SigningFailed { stderr }) => assert!(stderr.contains("gpg"), "{}", stderr),
+            other => panic!("expected a signing failure, got {:?}", other),
+        }
+        assert!(!is_merge_in_progress(&repo));
+        assert!(!has_uncommitted_changes(&repo).unwrap());
+        assert_eq!(get_head_commit(&repo).unwrap(), before);
+
+        let opts = MergeOptions { sign: false, ..opts };
+        match merge_with(&repo, "feature", MergeMode::NoFastForward, &opts).unwrap() {
+            MergeResult::Success { merge_commit, subject } => {
+                assert_eq!(subject, "chore(merge): feature into main");
+                assert_eq!(commit_subject_in(&repo, &merge_commit).unwrap(), subject);
+                assert_eq!(get_head_commit(&repo).unwrap(), merge_commit);
+            }
+            other => panic!("expected a merge commit, got {:?}", other),
+        }
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_abort_conflicted_squash() {
+        let repo = repo_with_feature_commits();
+        std::fs::write(repo.join("step1.txt"), "conflicting").unwrap();
+        git(&repo, &["add", "."]);
+        git(&repo, &["commit", "-q", "-m", "main change"]);
+        let before = get_head_commit(&repo).unwrap();
+
+        match merge(&repo, "feature", MergeMode::Squash).unwrap() {
+            MergeResult::Conflict { files } => assert_eq!(files, vec!["step1.txt"]),
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+        assert!(is_merge_in_progress(&repo));
+
+        merge_abort(&repo).unwrap();
+        assert!(!is_merge_in_progress(&repo));
+        assert!(!has_uncommitted_changes(&repo).unwrap());
+        assert_eq!(get_head_commit(&repo).unwrap(), before);
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_rebase_onto_main() {
+        let repo = repo_with_feature_commits();
+        let wt = repo.join("wt-feature");
+        std::fs::write(repo.join("main.txt"), "x").unwrap();
+        git(&repo, &["add", "."]);
+        git(&repo, &["commit", "-q", "-m", "main moved on"]);
+        let main_head = get_head_commit(&repo).unwrap();
+
+        assert!(matches!(rebase(&wt, "main").unwrap(), RebaseResult::Rebased { .. }));
+        assert!(wt.join("main.txt").exists());
+        assert!(commits_ahead_in(&repo, "main", "feature").unwrap().is_empty());
+        assert_eq!(commits_ahead_in(&repo, "feature", &main_head).unwrap().len(), 3);
+        assert!(matches!(rebase(&wt, "main").unwrap(), RebaseResult::UpToDate));
+
+        // Dirty worktrees are refused before git is run
+        std::fs::write(wt.join("step1.txt"), "dirty").unwrap();
+        let err = rebase(&wt, "main").unwrap_err().to_string();
+        assert!(err.contains("uncommitted changes"), "{}", err);
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_rebase_conflict_left_in_progress_until_aborted() {
+        let repo = repo_with_feature_commits();
+        let wt = repo.join("wt-feature");
+        let before = get_head_commit(&wt).unwrap();
+        std::fs::write(repo.join("step2.txt"), "conflicting").unwrap();
+        git(&repo, &["add", "."]);
+        git(&repo, &["commit", "-q", "-m", "main change"]);
+
+        match rebase(&wt, "main").unwrap() {
+            RebaseResult::Conflict { files } => assert_eq!(files, vec!["step2.txt"]),
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+        assert!(is_rebase_in_progress(&wt));
+        let state = work_state(&wt);
+        assert!(state.rebase_in_progress && !state.merge_in_progress);
+        assert_eq!(state.uncommitted, Some(true));
+
+        rebase_abort(&wt).unwrap();
+        assert!(!is_rebase_in_progress(&wt));
+        assert_eq!(get_head_commit(&wt).unwrap(), before);
+        assert_eq!(get_current_branch(&wt).unwrap(), "feature");
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_add_logs_git_command_when_verbose() {
+        let logged = add_logged(Verbosity::Verbose);
+        let line = logged
+            .lines()
+            .find(|line| line.contains("git -C") && line.contains("worktree add -b feature"))
+            .unwrap_or_else(|| panic!("no git worktree add line in:\n{}", logged));
+        assert!(line.starts_with("debug: $ git"), "{}", line);
+        assert!(line.contains("-> exit 0 in "), "{}", line);
+    }
+
+    #[test]
+    fn test_add_logs_nothing_by_default() {
+        assert_eq!(add_logged(Verbosity::Normal), "");
+    }
+
+    #[test]
+    fn test_parse_worktree_list() {
+        let porcelain = "worktree /repo\nHEAD 1111\nbranch refs/heads/main\n\n\
+            worktree /wts/locked\nHEAD 2222\nbranch refs/heads/feature/x\nlocked on a usb drive\n\n\
+            worktree /wts/detached\nHEAD 3333\ndetached\nlocked\n\n\
+            worktree /wts/gone\nHEAD 4444\nbranch refs/heads/gone\nprunable gitdir file points to non-existent location\n\n\
+            worktree /bare.git\nbare\n";
+        let worktrees = parse_worktree_list(porcelain);
+        assert_eq!(worktrees.len(), 4);
+
+        assert_eq!(worktrees[0].branch.as_deref(), Some("main"));
+        assert!(!worktrees[0].detached && worktrees[0].locked.is_none() && worktrees[0].prunable.is_none());
+
+        assert_eq!(worktrees[1].branch.as_deref(), Some("feature/x"));
+        assert_eq!(worktrees[1].locked.as_deref(), Some("on a usb drive"));
+
+        assert_eq!(worktrees[2].path, "/wts/detached");
+        assert_eq!(worktrees[2].head, "3333");
+        assert!(worktrees[2].branch.is_none() && worktrees[2].detached);
+        assert_eq!(worktrees[2].locked.as_deref(), Some(""));
+
+        assert_eq!(worktrees[3].prunable.as_deref(), Some("gitdir file points to non-existent location"));
+    }
+
+    #[test]
+    fn test_parse_numstat() {
+        let changes = parse_numstat(concat!(
+            "3\t1\tsrc/a.rs\0",
+            "-\t-\tlogo.png\0",
+            "0\t2\t\0old.rs\0src/new.rs\0",
+        ));
+        assert_eq!(
+            changes,
+            vec![
+                FileChange { path: "src/a.rs".into(), old_path: None, additions: Some(3), deletions: Some(1) },
+                FileChange { path: "logo.png".into(), old_path: None, additions: None, deletions: None },
+                FileChange { path: "src/new.rs".into(), old_path: Some("old.rs".into()), additions: Some(0), deletions: Some(2) },
+            ]
+        );
+        assert!(parse_numstat("").is_empty());
+    }
+
+    #[test]
+    fn test_diff_against_merge_base() {
+        let repo = scratch::repo("worktree");
+        scratch::commit_all(&repo, "init");
+        git(&repo, &["checkout", "-q", "-b", "feature"]);
+        std::fs::write(repo.join("a.txt"), "one\ntwo\n").unwrap();
+        git(&repo, &["add", "a.txt"]);
+        git(&repo, &["commit", "-q", "-m", "add a"]);
+        // Changes on main after the branch point are not the branch's
+        git(&repo, &["checkout", "-q", "main"]);
+        std::fs::write(repo.join("b.txt"), "main\n").unwrap();
+        git(&repo, &["add", "b.txt"]);
+        git(&repo, &["commit", "-q", "-m", "add b"]);
+
+        let base = merge_base_in(&repo, "feature", "main").unwrap();
+        assert_eq!(
+            diff_files_in(&repo, &base, "feature").unwrap(),
+            vec![FileChange { path: "a.txt".into(), old_path: None, additions: Some(2), deletions: Some(0) }]
+        );
+        assert_eq!(diff_in(&repo, &base, "feature", DiffFormat::NameOnly, false).unwrap(), "a.txt\n");
+        let stat = diff_in(&repo, &base, "feature", DiffFormat::Stat, false).unwrap();
+        assert!(stat.contains("1 file changed, 2 insertions(+)"), "{}", stat);
+        let patch = diff_in(&repo, &base, "feature", DiffFormat::Patch, false).unwrap();
+        assert!(patch.contains("+two"), "{}", patch);
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_change_summary() {
+        let repo = scratch::repo("worktree");
+        scratch::commit_all(&repo, "init");
+        std::fs::write(repo.join("old.txt"), "1\n2\n3\n4\n5\n").unwrap();
+        git(&repo, &["add", "old.txt"]);
+        git(&repo, &["commit", "-q", "-m", "add old"]);
+        git(&repo, &["checkout", "-q", "-b", "feature"]);
+        git(&repo, &["mv", "old.txt", "new.txt"]);
+        std::fs::write(repo.join("new.txt"), "1\n2\n3\n4\nfive\n").unwrap();
+        std::fs::write(repo.join("logo.png"), [0u8, 1, 2, 0]).unwrap();
+        git(&repo, &["add", "."]);
+        git(&repo, &["commit", "-q", "-m", "rename and logo"]);
+        // main moving on doesn't count against the branch
+        git(&repo, &["checkout", "-q", "main"]);
+        git(&repo, &["commit", "-q", "--allow-empty", "-m", "main"]);
+
+        let summary = change_summary_in(&repo, "feature", "main").unwrap();
+        assert_eq!(
+            summary.files,
+            vec![
+                FileChange { path: "logo.png".into(), old_path: None, additions: None, deletions: None },
+                FileChange { path: "new.txt".into(), old_path: Some("old.txt".into()), additions: Some(1), deletions: Some(1) },
+            ]
+        );
+        assert_eq!((summary.insertions, summary.deletions), (1, 1));
+        assert_eq!(summary.describe(), "2 files changed, +1 -1");
+        assert!(change_summary_in(&repo, "main", "feature").unwrap().files.is_empty());
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_add_errors() {
+        let repo = scratch::repo("worktree");
+        scratch::commit_all(&repo, "init");
+        add_in(&repo, &repo.join("wt-feature"), "feature").unwrap();
+
+        match add_in(&repo, &repo.join("wt-other"), "feature") {
+            Err(WtError::BranchCheckedOut { branch, path }) => {
+                assert_eq!(branch, "feature");
+                assert!(path.ends_with("wt-feature"), "{}", path.display());
+            }
+            other => panic!("expected BranchCheckedOut, got {:?}", other),
+        }
+        assert!(!repo.join("wt-other").exists());
+
+        std::fs::create_dir_all(repo.join("wt-taken")).unwrap();
+        std::fs::write(repo.join("wt-taken/file"), "").unwrap();
+        match add_in(&repo, &repo.join("wt-taken"), "taken") {
+            Err(WtError::WorktreeExists { path }) => assert_eq!(path, repo.join("wt-taken")),
+            other => panic!("expected WorktreeExists, got {:?}", other),
+        }
+        assert!(!branch_exists_in(&repo, "taken").unwrap());
+        scratch::remove(&repo);
+    }
+
+    #[test]
+    fn test_parse_checkout_progress() {
+        let progress = parse_checkout_progress("Updating files:  45% (90000/200000)\r").unwrap();
+        assert_eq!(progress, CheckoutProgress { done: 90000, total: 200000 });
+        assert_eq!(progress.percent(), 45);
+        assert_eq!(
+            parse_checkout_progress("Updating files: 100% (3/3), done."),
+            Some(CheckoutProgress { done: 3, total: 3 })
+        );
+        assert_eq!(parse_checkout_progress("Preparing worktree (new branch 'x')"), None);
+    }
 }
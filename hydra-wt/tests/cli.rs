@@ -0,0 +1,918 @@
+//! Verbosity flags, machine-readable output and working-directory
+//! handling of the hydra-wt binary
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn project() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("hydra-wt-cli-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(dir.join(".hydra")).unwrap();
+    dir
+}
+
+fn hydra_wt(dir: &PathBuf, args: &[&str]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_hydra-wt"))
+        .args(args)
+        .current_dir(dir)
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+#[test]
+fn test_init_progress_goes_to_stderr() {
+    let dir = project();
+    let output = hydra_wt(&dir, &["init"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Created .hydra/wt.local.toml"), "{}", stderr);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hydra-wt initialized successfully\n");
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_quiet_suppresses_progress() {
+    let dir = project();
+    let output = hydra_wt(&dir, &["-q", "init"]);
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+    // The result itself is not progress and still prints
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hydra-wt initialized successfully\n");
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_quiet_still_reports_errors() {
+    let dir = std::env::temp_dir().join(format!("hydra-wt-cli-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_hydra-wt"))
+        .args(["init", "--quiet"])
+        .current_dir(&dir)
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("error: .hydra/ directory not found"), "{}", stderr);
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+/// A git repo set up for hydra-wt, keeping worktrees in `worktrees_dir`
+fn git_project(worktrees_dir: &str) -> PathBuf {
+    let dir = project();
+    std::fs::write(dir.join(".gitignore"), ".hydra/\nwts/\n").unwrap();
+    for args in [
+        &["init", "-q", "-b", "main"][..],
+        &["add", ".gitignore"],
+        &["-c", "user.email=test@example.com", "-c", "user.name=Test", "commit", "-q", "-m", "init"],
+    ] {
+        assert!(Command::new("git").arg("-C").arg(&dir).args(args).status().unwrap().success());
+    }
+    hydra_wt(&dir, &["-q", "init"]);
+    std::fs::write(
+        dir.join(".hydra/wt.local.toml"),
+        format!("[worktrees]\ndirectory = \"{}\"\n", worktrees_dir),
+    )
+    .unwrap();
+    dir
+}
+
+#[test]
+fn test_exec_in_every_worktree() {
+    let dir = git_project("wts");
+    for branch in ["broken", "gone", "ok"] {
+        hydra_wt(&dir, &["-q", "create", branch]);
+    }
+    std::fs::remove_dir_all(dir.join("wts/gone")).unwrap();
+    let script = r#"echo "$HYDRA_WT_BRANCH $(basename "$PWD") ${HYDRA_WT_PORT:-none}"; test "$HYDRA_WT_BRANCH" != broken"#;
+
+    let output = Command::new(env!("CARGO_BIN_EXE_hydra-wt"))
+        .args(["exec", "--parallel", "2", "--", "sh", "-c", script])
+        .current_dir(&dir)
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("✗ broken (exit 1)\nbroken broken "), "{}", stdout);
+    assert!(stdout.contains("✓ ok (exit 0)\nok ok "), "{}", stdout);
+    // The missing worktree is skipped, and every run had a port
+    assert!(!stdout.contains("gone") && !stdout.contains("none"), "{}", stdout);
+    let summary: Vec<&str> = stdout.lines().skip_while(|line| !line.starts_with("BRANCH")).skip(2).collect();
+    assert_eq!(summary.iter().map(|line| line.split_whitespace().collect::<Vec<_>>()).collect::<Vec<_>>(),
+        vec![vec!["broken", "1"], vec!["ok", "0"]]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("1 of 2 worktree(s) failed"), "{}", stderr);
+
+    let stdout = String::from_utf8(hydra_wt(&dir, &["exec", "--branch", "ok", "--", "sh", "-c", script]).stdout).unwrap();
+    assert!(stdout.starts_with("✓ ok (exit 0)\n") && !stdout.contains("broken"), "{}", stdout);
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_list_and_status_json() {
+    let wts = std::env::temp_dir().join(format!("hydra-wt-cli-wts-{}", uuid::Uuid::new_v4()));
+    let dir = git_project(&wts.to_string_lossy());
+    hydra_wt(&dir, &["-q", "create", "feature"]);
+
+    let output = hydra_wt(&dir, &["list", "--json"]);
+    let list: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let entry = &list[0];
+    assert_eq!(entry["branch"], "feature");
+    // 3001 unless something on this machine already listens there
+    assert_eq!(entry["ports"], serde_json::json!([entry["port"]]));
+    assert_eq!(entry["path"], wts.join("feature").to_string_lossy().as_ref());
+    assert_eq!(entry["exists"], true);
+    assert_eq!(entry["commits_ahead"], 0);
+    assert_eq!(entry["commits_behind"], 0);
+    assert_eq!(entry["conflicts"], false);
+    assert_eq!(entry["head"].as_str().unwrap().len(), 40);
+
+    let output = hydra_wt(&dir, &["status", "--json"]);
+    let status: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!((status["total"].as_u64(), status["missing"].as_u64()), (Some(1), Some(0)));
+    assert_eq!(status["worktrees"], list);
+
+    let output = hydra_wt(&dir, &["status", "feature", "--json"]);
+    let one: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(one, *entry);
+    assert_eq!((&one["uncommitted"], &one["merge_in_progress"], &one["rebase_in_progress"]), (&serde_json::json!(false), &serde_json::json!(false), &serde_json::json!(false)));
+
+    std::fs::write(wts.join("feature/scratch.txt"), "wip").unwrap();
+    let output = hydra_wt(&dir, &["status", "--json"]);
+    let status: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!((status["uncommitted"].as_u64(), status["merge_in_progress"].as_u64()), (Some(1), Some(0)));
+    assert_eq!(status["worktrees"][0]["uncommitted"], true);
+    let output = hydra_wt(&dir, &["status"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Uncommitted changes: 1 (feature)"));
+    std::fs::remove_dir_all(&dir).ok();
+    std::fs::remove_dir_all(&wts).ok();
+}
+
+#[test]
+fn test_env_overrides_directory_and_registry() {
+    let dir = git_project("wts");
+    let runner = std::env::temp_dir().join(format!("hydra-wt-cli-runner-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&runner).unwrap();
+    let with_env = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_hydra-wt"))
+            .args(args)
+            .current_dir(&dir)
+            .env("HYDRA_WT_DIR", runner.join("wts"))
+            .env("HYDRA_WT_REGISTRY", runner.join("ports.json"))
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        output
+    };
+
+    with_env(&["-q", "create", "feature"]);
+    assert!(runner.join("wts/feature/.git").exists());
+    assert!(!dir.join("wts/feature").exists());
+    assert!(std::fs::read_to_string(runner.join("ports.json")).unwrap().contains("feature"));
+    assert!(!std::fs::read_to_string(dir.join(".hydra/wt-ports.json")).unwrap().contains("feature"));
+
+    let stdout = String::from_utf8_lossy(&with_env(&["status"]).stdout).to_string();
+    assert!(stdout.contains(&format!("From environment: HYDRA_WT_DIR={}", runner.join("wts").display())), "{}", stdout);
+    let output = with_env(&["config", "show", "--origin"]);
+    assert!(String::from_utf8_lossy(&output.stdout).lines().any(|l| l.starts_with("worktrees.directory") && l.ends_with("# env")));
+
+    // Without the variables the config and the committed registry apply again
+    let output = hydra_wt(&dir, &["status", "--json"]);
+    let status: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!((status["total"].as_u64(), &status["from_env"]), (Some(0), &serde_json::json!({})));
+    std::fs::remove_dir_all(&dir).ok();
+    std::fs::remove_dir_all(&runner).ok();
+}
+
+#[test]
+fn test_runs_from_subdirectory_and_worktree() {
+    let dir = git_project("wts");
+    let nested = dir.join("src/deep");
+    std::fs::create_dir_all(&nested).unwrap();
+
+    // The config is found at the root and `wts` is relative to the root
+    hydra_wt(&nested, &["-q", "create", "feature"]);
+    let feature = dir.join("wts/feature");
+    assert!(feature.join(".git").exists());
+    assert!(!nested.join("wts").exists());
+
+    // Inside a worktree, the main checkout's registry is the one used
+    hydra_wt(&feature, &["-q", "create", "other"]);
+    assert!(dir.join("wts/other/.git").exists());
+    assert!(!feature.join(".hydra").exists());
+    assert!(!feature.join("wts").exists());
+
+    let output = hydra_wt(&feature, &["list", "--json"]);
+    let list: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let branches: Vec<&str> = list.as_array().unwrap().iter().map(|e| e["branch"].as_str().unwrap()).collect();
+    assert_eq!(branches, vec!["feature", "other"]);
+    assert_eq!(list[1]["path"], dir.join("wts/other").to_string_lossy().as_ref());
+
+    hydra_wt(&nested, &["-q", "remove", "other"]);
+    assert!(!dir.join("wts/other").exists());
+    let registry = std::fs::read_to_string(dir.join(".hydra/wt-ports.json")).unwrap();
+    assert!(registry.contains("feature") && !registry.contains("other"), "{}", registry);
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_name_template_and_slash_branches() {
+    let dir = git_project("wts");
+    hydra_wt(&dir, &["-q", "create", "nested/one"]);
+    assert!(dir.join("wts/nested/one/.git").exists());
+
+    let local = dir.join(".hydra/wt.local.toml");
+    let config = std::fs::read_to_string(&local).unwrap();
+    std::fs::write(&local, format!("{}name_template = \"{{branch_slug}}\"\n", config)).unwrap();
+    hydra_wt(&dir, &["-q", "create", "feature/login"]);
+    assert!(dir.join("wts/feature-login/.git").exists());
+
+    let output = hydra_wt(&dir, &["list", "--json"]);
+    let list: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(list[0]["branch"], "feature/login");
+    assert_eq!(list[0]["path"], dir.join("wts/feature-login").to_string_lossy().as_ref());
+    // Created before the template changed, still found where it was put
+    assert_eq!(list[1]["path"], dir.join("wts/nested/one").to_string_lossy().as_ref());
+    assert_eq!(list[1]["exists"], true);
+
+    hydra_wt(&dir, &["-q", "remove", "nested/one"]);
+    assert!(!dir.join("wts/nested").exists());
+    assert!(dir.join("wts").exists());
+    hydra_wt(&dir, &["-q", "remove", "feature/login"]);
+    assert!(!dir.join("wts/feature-login").exists());
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_remove_guards_unique_commits() {
+    let dir = git_project("wts");
+    hydra_wt(&dir, &["-q", "create", "merged"]);
+    hydra_wt(&dir, &["-q", "create", "work"]);
+    let work = dir.join("wts/work");
+    let commit = ["-c", "user.email=test@example.com", "-c", "user.name=Test", "commit", "-q", "--allow-empty", "-m", "agent work"];
+    assert!(Command::new("git").arg("-C").arg(&work).args(commit).status().unwrap().success());
+
+    // Nothing on `merged` that main doesn't have
+    hydra_wt(&dir, &["-q", "remove", "merged"]);
+    assert!(!dir.join("wts/merged").exists());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_hydra-wt"))
+        .args(["remove", "work"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("1 commit(s) not on main and never pushed") && stdout.contains("agent work"), "{}", stdout);
+    assert!(work.exists());
+
+    let output = hydra_wt(&dir, &["-q", "remove", "work", "--archive"]);
+    assert!(!work.exists());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let bundle = stdout.lines().find_map(|l| l.trim().strip_prefix("Restore with: hydra-wt restore ")).unwrap();
+    assert!(bundle.ends_with(".bundle") && Path::new(bundle).with_extension("json").exists(), "{}", stdout);
+
+    // Brought back along with its worktree once the branch is deleted
+    assert!(Command::new("git").arg("-C").arg(&dir).args(["branch", "-q", "-D", "work"]).status().unwrap().success());
+    let name = Path::new(bundle).file_name().unwrap().to_str().unwrap();
+    hydra_wt(&dir, &["-q", "restore", name, "--worktree"]);
+    let log = Command::new("git").arg("-C").arg(&work).args(["log", "-1", "--format=%s"]).output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&log.stdout).trim(), "agent work");
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_remove_locked_worktree() {
+    let dir = git_project("wts");
+    hydra_wt(&dir, &["-q", "create", "usb"]);
+    let wt = dir.join("wts/usb");
+    let lock = ["worktree", "lock", "--reason", "on a usb drive"];
+    assert!(Command::new("git").arg("-C").arg(&dir).args(lock).arg(&wt).status().unwrap().success());
+
+    let stdout = String::from_utf8(hydra_wt(&dir, &["list"]).stdout).unwrap();
+    let row = stdout.lines().find(|l| l.starts_with("usb")).unwrap();
+    assert!(row.contains("LOCKED"), "{}", stdout);
+    assert!(stdout.contains("    locked: on a usb drive"), "{}", stdout);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_hydra-wt"))
+        .args(["remove", "usb"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("'usb' is locked (on a usb drive); unlock it with `git worktree unlock"), "{}", stderr);
+    assert!(wt.exists());
+
+    hydra_wt(&dir, &["-q", "remove", "usb", "--force"]);
+    assert!(!wt.exists());
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_clean_missing_worktrees() {
+    let dir = git_project("wts");
+    hydra_wt(&dir, &["-q", "create", "kept"]);
+    hydra_wt(&dir, &["-q", "create", "deleted"]);
+    std::fs::remove_dir_all(dir.join("wts/deleted")).unwrap();
+
+    let stdout = String::from_utf8(hydra_wt(&dir, &["clean", "--dry-run"]).stdout).unwrap();
+    assert!(stdout.starts_with("Would clean 'deleted' (port "), "{}", stdout);
+    assert!(!stdout.contains("'kept'"), "{}", stdout);
+
+    // Without --yes, no answer on stdin keeps everything
+    let stdout = String::from_utf8(hydra_wt(&dir, &["clean"]).stdout).unwrap();
+    assert!(stdout.contains("Clean cancelled."), "{}", stdout);
+    assert!(std::fs::read_to_string(dir.join(".hydra/wt-ports.json")).unwrap().contains("deleted"));
+
+    let stdout = String::from_utf8(hydra_wt(&dir, &["clean", "--yes"]).stdout).unwrap();
+    assert!(stdout.ends_with("Cleaned 1 registry entry\n"), "{}", stdout);
+    let registry = std::fs::read_to_string(dir.join(".hydra/wt-ports.json")).unwrap();
+    assert!(!registry.contains("deleted") && registry.contains("kept"), "{}", registry);
+    let stdout = String::from_utf8(hydra_wt(&dir, &["clean"]).stdout).unwrap();
+    assert_eq!(stdout, "Nothing to clean\n");
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_owner_filters() {
+    let dir = git_project("wts");
+    hydra_wt(&dir, &["-q", "create", "human"]);
+    hydra_wt(&dir, &["-q", "create", "agent", "--owner", "orchestrator:4f2a"]);
+    // An entry written before owners were recorded
+    let path = dir.join(".hydra/wt-ports.json");
+    let mut registry: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    registry["legacy"] = serde_json::json!(3050);
+    std::fs::write(&path, registry.to_string()).unwrap();
+
+    let list = |owner: &str| -> Vec<(String, String)> {
+        let output = hydra_wt(&dir, &["list", "--json", "--owner", owner]);
+        let list: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        list.as_array()
+            .unwrap()
+            .iter()
+            .map(|st| (st["branch"].as_str().unwrap().to_string(), st["owner"].as_str().unwrap().to_string()))
+            .collect()
+    };
+    assert_eq!(list("orchestrator"), vec![("agent".to_string(), "orchestrator:4f2a".to_string())]);
+    assert_eq!(list("cli"), vec![("human".to_string(), "cli".to_string())]);
+    assert_eq!(list("unknown"), vec![("legacy".to_string(), "unknown".to_string())]);
+
+    // Only the orchestrator's missing worktree is cleaned; the legacy entry
+    // is missing too but never matches an owner
+    std::fs::remove_dir_all(dir.join("wts/human")).unwrap();
+    std::fs::remove_dir_all(dir.join("wts/agent")).unwrap();
+    let stdout = String::from_utf8(hydra_wt(&dir, &["clean", "--yes", "--owner", "orchestrator"]).stdout).unwrap();
+    assert!(stdout.contains("'agent'") && !stdout.contains("'human'") && !stdout.contains("'legacy'"), "{}", stdout);
+    let registry = std::fs::read_to_string(&path).unwrap();
+    assert!(!registry.contains("agent") && registry.contains("human") && registry.contains("legacy"), "{}", registry);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_hydra-wt"))
+        .args(["clean", "--yes", "--owner", "unknown"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("never cleaned by owner"));
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_worktree_age() {
+    let dir = git_project("wts");
+    hydra_wt(&dir, &["-q", "create", "fresh"]);
+    hydra_wt(&dir, &["-q", "create", "stale"]);
+    let registry_path = dir.join(".hydra/wt-ports.json");
+    let mut registry: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&registry_path).unwrap()).unwrap();
+    assert!(registry["fresh"]["created_at"].is_string(), "{}", registry);
+    registry["stale"]["created_at"] = "2020-01-01T00:00:00Z".into();
+    std::fs::write(&registry_path, registry.to_string()).unwrap();
+
+    let stdout = String::from_utf8(hydra_wt(&dir, &["list"]).stdout).unwrap();
+    assert!(stdout.lines().next().unwrap().contains(" AGE "), "{}", stdout);
+    let fresh = stdout.lines().find(|l| l.starts_with("fresh")).unwrap();
+    assert!(fresh.contains(" <1m "), "{}", stdout);
+
+    let stdout = String::from_utf8(hydra_wt(&dir, &["list", "--older-than", "7d"]).stdout).unwrap();
+    assert!(stdout.contains("stale") && !stdout.contains("fresh"), "{}", stdout);
+    let stdout = String::from_utf8(hydra_wt(&dir, &["list", "--older-than", "520w", "--json"]).stdout).unwrap();
+    assert_eq!(stdout.trim(), "[]");
+
+    std::fs::remove_dir_all(dir.join("wts/fresh")).unwrap();
+    std::fs::remove_dir_all(dir.join("wts/stale")).unwrap();
+    let stdout = String::from_utf8(hydra_wt(&dir, &["clean", "--yes", "--older-than", "7d"]).stdout).unwrap();
+    assert!(stdout.contains("'stale'") && !stdout.contains("'fresh'"), "{}", stdout);
+    let registry = std::fs::read_to_string(&registry_path).unwrap();
+    assert!(registry.contains("fresh") && !registry.contains("stale"), "{}", registry);
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_diff_without_worktree() {
+    let dir = git_project("wts");
+    let git = |args: &[&str]| {
+        let identity = ["-c", "user.email=test@example.com", "-c", "user.name=Test"];
+        assert!(Command::new("git").arg("-C").arg(&dir).args(identity).args(args).status().unwrap().success());
+    };
+    git(&["checkout", "-q", "-b", "agent"]);
+    std::fs::write(dir.join("notes.md"), "one\ntwo\nthree\n").unwrap();
+    git(&["add", "notes.md"]);
+    git(&["commit", "-q", "-m", "notes"]);
+    git(&["checkout", "-q", "main"]);
+
+    let stdout = String::from_utf8(hydra_wt(&dir, &["diff", "agent"]).stdout).unwrap();
+    assert!(stdout.contains("notes.md") && stdout.contains("1 file changed, 3 insertions(+)"), "{}", stdout);
+    let stdout = String::from_utf8(hydra_wt(&dir, &["diff", "agent", "--name-only"]).stdout).unwrap();
+    assert_eq!(stdout, "notes.md\n");
+    let stdout = String::from_utf8(hydra_wt(&dir, &["diff", "agent", "--full"]).stdout).unwrap();
+    assert!(stdout.contains("+three"), "{}", stdout);
+    let stdout = String::from_utf8(hydra_wt(&dir, &["diff", "agent", "--summary"]).stdout).unwrap();
+    assert!(stdout.contains("notes.md") && stdout.ends_with("1 file changed, +3 -0\n"), "{}", stdout);
+
+    let json: serde_json::Value = serde_json::from_slice(&hydra_wt(&dir, &["diff", "agent", "--json"]).stdout).unwrap();
+    assert_eq!(json["base"], "main");
+    assert_eq!(json["additions"], 3);
+    assert_eq!(json["files"], serde_json::json!([{"path": "notes.md", "additions": 3, "deletions": 0}]));
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_render_after_template_change() {
+    let dir = git_project("wts");
+    std::fs::write(dir.join(".env.template"), "PORT={{ port }}\n").unwrap();
+    hydra_wt(&dir, &["-q", "create", "feature"]);
+    let env = dir.join("wts/feature/.env.local");
+    let before = std::fs::read_to_string(&env).unwrap();
+    assert!(before.starts_with("# Rendered by hydra-wt from .env.template (template sha256:"), "{}", before);
+
+    std::fs::write(dir.join(".env.template"), "PORT={{ port }}\nNAME={{ worktree }}\n").unwrap();
+    let stdout = String::from_utf8(hydra_wt(&dir, &["render", "feature", "--diff"]).stdout).unwrap();
+    assert!(stdout.contains("feature: .env.local") && stdout.contains("  +NAME=feature"), "{}", stdout);
+    assert_eq!(std::fs::read_to_string(&env).unwrap(), before);
+
+    let stdout = String::from_utf8(hydra_wt(&dir, &["render", "--all"]).stdout).unwrap();
+    assert!(stdout.contains("✓ feature: re-rendered .env.local, previous kept in .env.local.bak"), "{}", stdout);
+    assert!(std::fs::read_to_string(&env).unwrap().ends_with("NAME=feature\n"));
+    assert_eq!(std::fs::read_to_string(dir.join("wts/feature/.env.local.bak")).unwrap(), before);
+    let stdout = String::from_utf8(hydra_wt(&dir, &["render", "feature"]).stdout).unwrap();
+    assert_eq!(stdout, "✓ feature: up to date\n");
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_create_without_port() {
+    let dir = git_project("wts");
+    std::fs::write(dir.join(".env.template"), "PORT={{ port }}\n").unwrap();
+    hydra_wt(&dir, &["-q", "create", "docs", "--no-port"]);
+    hydra_wt(&dir, &["-q", "create", "feature"]);
+    assert!(!dir.join("wts/docs/.env.local").exists());
+    assert!(dir.join("wts/feature/.env.local").exists());
+
+    let output = hydra_wt(&dir, &["list", "--json"]);
+    let list: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(list[0]["branch"], "docs");
+    assert_eq!((&list[0]["port"], &list[0]["ports"]), (&serde_json::Value::Null, &serde_json::json!([])));
+    let output = hydra_wt(&dir, &["status"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Total managed: 2") && stdout.contains("Without ports: 1"), "{}", stdout);
+
+    let output = hydra_wt(&dir, &["remove", "docs"]);
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("no port allocation"));
+    assert!(!dir.join("wts/docs").exists());
+    let output = hydra_wt(&dir, &["list", "--json"]);
+    let list: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(list.as_array().unwrap().len(), 1);
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_merge_several_sources_and_continue() {
+    let dir = git_project("wts");
+    let git = |dir: &PathBuf, args: &[&str]| {
+        assert!(Command::new("git").arg("-C").arg(dir).args(args).status().unwrap().success());
+    };
+    // The merges commit in the project, and worktrees share its config
+    git(&dir, &["config", "user.email", "test@example.com"]);
+    git(&dir, &["config", "user.name", "Test"]);
+    // `a` and `b` both add shared.txt, so `b` conflicts once `a` has landed
+    for (branch, file, content) in [("a", "shared.txt", "a"), ("b", "shared.txt", "b"), ("c", "c.txt", "c")] {
+        hydra_wt(&dir, &["-q", "create", branch]);
+        let wt = dir.join("wts").join(branch);
+        std::fs::write(wt.join(file), content).unwrap();
+        git(&wt, &["add", file]);
+        git(&wt, &["commit", "-q", "-m", branch]);
+    }
+
+    let output = hydra_wt(&dir, &["merge", "a", "b", "c", "--into", "main", "--force", "--cleanup"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("a: 1 commit(s)") && stdout.contains("c: 1 commit(s)"), "{}", stdout);
+    assert!(stdout.contains("Merged: a\nStopped at: b\nRemaining: c"), "{}", stdout);
+    assert!(!dir.join("wts/a").exists() && dir.join("wts/b").exists());
+    assert!(dir.join(".hydra/wt-merge.json").exists());
+
+    // Not resolved yet
+    let output = Command::new(env!("CARGO_BIN_EXE_hydra-wt"))
+        .args(["merge", "--continue"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not committed yet"));
+
+    std::fs::write(dir.join("shared.txt"), "a and b").unwrap();
+    git(&dir, &["add", "shared.txt"]);
+    git(&dir, &["commit", "-q", "--no-edit"]);
+    let output = hydra_wt(&dir, &["merge", "--continue"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("✓ Merged a, b, c into main"), "{}", stdout);
+    assert!(dir.join("c.txt").exists());
+    assert!(!dir.join("wts/b").exists() && !dir.join("wts/c").exists());
+    assert!(!dir.join(".hydra/wt-merge.json").exists());
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_merge_check_gates_each_source() {
+    let dir = git_project("wts");
+    let git = |dir: &PathBuf, args: &[&str]| {
+        assert!(Command::new("git").arg("-C").arg(dir).args(args).status().unwrap().success());
+    };
+    git(&dir, &["config", "user.email", "test@example.com"]);
+    git(&dir, &["config", "user.name", "Test"]);
+    let local = dir.join(".hydra/wt.local.toml");
+    let config = std::fs::read_to_string(&local).unwrap();
+    std::fs::write(&local, format!("{}\n[merge]\ncheck_command = \"echo port=$PORT; test -f passing\"\n", config)).unwrap();
+    // Only `a` and `c` commit the file the check looks for
+    for (branch, files) in [("a", &["a.txt", "passing"][..]), ("b", &["b.txt"]), ("c", &["c.txt", "passing"])] {
+        hydra_wt(&dir, &["-q", "create", branch]);
+        let wt = dir.join("wts").join(branch);
+        for file in files {
+            std::fs::write(wt.join(file), branch).unwrap();
+            git(&wt, &["add", file]);
+        }
+        git(&wt, &["commit", "-q", "-m", branch]);
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_hydra-wt"))
+        .args(["merge", "a", "b", "c", "--into", "main", "--force", "--check"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("✓ a: Check passed") && stdout.contains("✗ b: Check failed"), "{}", stdout);
+    assert!(stdout.contains("Merged: a\nNot merged: b, c"), "{}", stdout);
+    assert!(dir.join("a.txt").exists() && !dir.join("b.txt").exists() && !dir.join("c.txt").exists());
+    // The check sees the source's port
+    let log = std::fs::read_to_string(dir.join(".hydra/merge-checks/a.log")).unwrap();
+    assert!(log.contains("port=3001\n"), "{}", log);
+    assert!(dir.join(".hydra/merge-checks/b.log").exists());
+
+    hydra_wt(&dir, &["-q", "merge", "c", "main", "--force", "--check"]);
+    assert!(dir.join("c.txt").exists());
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_merge_message_template_and_override() {
+    let dir = git_project("wts");
+    let git = |dir: &PathBuf, args: &[&str]| {
+        assert!(Command::new("git").arg("-C").arg(dir).args(args).status().unwrap().success());
+    };
+    git(&dir, &["config", "user.email", "test@example.com"]);
+    git(&dir, &["config", "user.name", "Test"]);
+    let local = dir.join(".hydra/wt.local.toml");
+    let config = std::fs::read_to_string(&local).unwrap();
+    std::fs::write(&local, format!("{}\n[merge]\nmessage_template = \"chore(merge): {{source}} into {{target}} ({{count}})\"\n", config)).unwrap();
+    for branch in ["a", "b"] {
+        hydra_wt(&dir, &["-q", "create", branch]);
+        let wt = dir.join("wts").join(branch);
+        std::fs::write(wt.join(format!("{}.txt", branch)), branch).unwrap();
+        git(&wt, &["add", "."]);
+        git(&wt, &["commit", "-q", "-m", branch]);
+    }
+    let subject = |dir: &PathBuf| {
+        let output = Command::new("git").arg("-C").arg(dir).args(["log", "-1", "--format=%s"]).output().unwrap();
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    };
+
+    let output = hydra_wt(&dir, &["merge", "a", "main", "--force", "--no-ff"]);
+    assert_eq!(subject(&dir), "chore(merge): a into main (1)");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("chore(merge): a into main (1)"));
+
+    hydra_wt(&dir, &["-q", "merge", "b", "main", "--force", "--squash", "-m", "feat: land {source}"]);
+    assert_eq!(subject(&dir), "feat: land b");
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_merge_push() {
+    let dir = git_project("wts");
+    let git = |dir: &Path, args: &[&str]| -> String {
+        let output = Command::new("git").arg("-C").arg(dir).args(args).output().unwrap();
+        assert!(output.status.success(), "git {:?}: {}", args, String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    };
+    git(&dir, &["config", "user.email", "test@example.com"]);
+    git(&dir, &["config", "user.name", "Test"]);
+    let remote = dir.join(".hydra/origin.git");
+    git(&dir, &["init", "-q", "--bare", &remote.to_string_lossy()]);
+    git(&dir, &["remote", "add", "origin", &remote.to_string_lossy()]);
+    git(&dir, &["push", "-q", "origin", "main"]);
+    for branch in ["a", "b", "c"] {
+        hydra_wt(&dir, &["-q", "create", branch]);
+        let wt = dir.join("wts").join(branch);
+        std::fs::write(wt.join(format!("{}.txt", branch)), branch).unwrap();
+        git(&wt, &["add", "."]);
+        git(&wt, &["commit", "-q", "-m", branch]);
+    }
+
+    let output = hydra_wt(&dir, &["merge", "a", "main", "--force", "--push"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("✓ Pushed main to origin"));
+    assert_eq!(git(&remote, &["rev-parse", "main"]), git(&dir, &["rev-parse", "main"]));
+
+    // merge.push is on, but --no-push keeps this one local
+    let local = dir.join(".hydra/wt.local.toml");
+    let config = std::fs::read_to_string(&local).unwrap();
+    std::fs::write(&local, format!("{}\n[merge]\npush = true\nremote = \"nowhere\"\n", config)).unwrap();
+    let pushed = git(&remote, &["rev-parse", "main"]);
+    let output = hydra_wt(&dir, &["merge", "b", "main", "--force", "--no-push"]);
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("Push"));
+
+    // A push that fails leaves the merge in place and exits 0
+    let output = hydra_wt(&dir, &["merge", "c", "main", "--force"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("✗ Push of main to nowhere failed, the merge is kept"), "{}", stdout);
+    assert!(dir.join("c.txt").exists());
+    assert_eq!(git(&remote, &["rev-parse", "main"]), pushed);
+    let queued = std::fs::read_to_string(dir.join(".hydra/wt-events.pending")).unwrap();
+    let failed: serde_json::Value = queued
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .find(|event| event["channel"] == "merge:push-failed")
+        .expect("no merge:push-failed event queued");
+    assert_eq!(failed["data"]["source"], "c");
+    assert_eq!(failed["data"]["remote"], "nowhere");
+    assert_eq!(failed["data"]["commit"].as_str().unwrap(), git(&dir, &["rev-parse", "main"]));
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_doctor_exit_code_and_fix() {
+    let dir = git_project("wts");
+    hydra_wt(&dir, &["-q", "create", "feature"]);
+    let output = hydra_wt(&dir, &["doctor"]);
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("✓"));
+
+    std::fs::remove_dir_all(dir.join("wts/feature")).unwrap();
+    let doctor = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_hydra-wt"))
+            .args(args)
+            .current_dir(&dir)
+            .stdin(std::process::Stdio::null())
+            .output()
+            .unwrap()
+    };
+    let output = doctor(&["doctor"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("✗ missing directory") && stdout.contains("✗ orphaned allocation"), "{}", stdout);
+
+    let output = doctor(&["doctor", "--fix"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(doctor(&["doctor"]).status.success());
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_env_exports() {
+    let dir = git_project("wts");
+    let local = dir.join(".hydra/wt.local.toml");
+    let config = std::fs::read_to_string(&local).unwrap();
+    std::fs::write(&local, format!("{}\n[template.vars]\ndb = \"app_{{{{ branch_slug }}}}\"\n", config)).unwrap();
+
+    // stdout holds nothing but the exports
+    let output = hydra_wt(&dir, &["create", "feature/login", "--print-env"]);
+    let path = dir.join("wts/feature/login");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!(
+            "export HYDRA_WT_BRANCH='feature/login'\nexport HYDRA_WT_PATH='{}'\nexport HYDRA_WT_PORT='3001'\n\
+             export HYDRA_WT_BRANCH_SLUG='feature-login'\nexport HYDRA_WT_DB='app_feature-login'\n",
+            path.display()
+        )
+    );
+
+    // Comes from the registry, not the env file
+    hydra_wt(&dir, &["-q", "create", "docs", "--no-port"]);
+    let output = hydra_wt(&dir, &["env", "docs", "--format", "json"]);
+    let env: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(env["HYDRA_WT_BRANCH"], "docs");
+    assert!(env.get("HYDRA_WT_PORT").is_none());
+
+    let output = hydra_wt(&dir, &["env", "docs", "--format", "fish"]);
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("set -gx HYDRA_WT_BRANCH 'docs'\n"));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_hydra-wt")).args(["env", "nope"]).current_dir(&dir).output().unwrap();
+    assert!(!output.status.success());
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+#[cfg(unix)]
+fn test_open_shell_and_editor() {
+    let dir = git_project("wts");
+    let local = dir.join(".hydra/wt.local.toml");
+    let config = std::fs::read_to_string(&local).unwrap();
+    std::fs::write(&local, format!("{}\n[open]\neditor = \"echo editing\"\n", config)).unwrap();
+    hydra_wt(&dir, &["-q", "create", "feature"]);
+    let path = dir.join("wts/feature");
+    let open = |args: &[&str], shell: &str| {
+        Command::new(env!("CARGO_BIN_EXE_hydra-wt"))
+            .args(["-q", "open"])
+            .args(args)
+            .current_dir(&dir)
+            .env_remove("TMUX")
+            .env("SHELL", shell)
+            .output()
+            .unwrap()
+    };
+
+    // Outside tmux, $SHELL starts in the worktree with its variables set
+    let shell = dir.join("shell.sh");
+    std::fs::write(&shell, "#!/bin/sh\necho \"$PWD $HYDRA_WT_PORT\"\n").unwrap();
+    std::fs::set_permissions(&shell, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+    let output = open(&["feature"], &shell.to_string_lossy());
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let expected = format!("{} 3001\n", path.canonicalize().unwrap().display());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+
+    let output = open(&["feature", "--code"], "false");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), format!("editing {}\n", path.display()));
+
+    std::fs::remove_dir_all(&path).ok();
+    let output = open(&["feature"], "false");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("create it with: hydra-wt create feature"));
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_sparse_worktrees() {
+    let dir = git_project("wts");
+    for file in ["packages/web/index.js", "packages/api/main.rs", "tools/build.sh"] {
+        let path = dir.join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, "").unwrap();
+    }
+    let commit = ["-c", "user.email=test@example.com", "-c", "user.name=Test", "commit", "-q", "-m", "packages"];
+    assert!(Command::new("git").arg("-C").arg(&dir).args(["add", "."]).status().unwrap().success());
+    assert!(Command::new("git").arg("-C").arg(&dir).args(commit).status().unwrap().success());
+    let local = dir.join(".hydra/wt.local.toml");
+    let config = std::fs::read_to_string(&local).unwrap();
+    std::fs::write(
+        &local,
+        format!("{}\n[[overrides]]\npattern = \"agent/*\"\nworktrees = {{ sparse = [\"packages/web\"] }}\n", config),
+    )
+    .unwrap();
+
+    hydra_wt(&dir, &["-q", "create", "agent/one"]);
+    let wt = dir.join("wts/agent/one");
+    assert!(wt.join("packages/web/index.js").exists());
+    assert!(wt.join(".gitignore").exists(), "cone mode keeps top-level files");
+    assert!(!wt.join("packages/api").exists() && !wt.join("tools").exists());
+    let output = hydra_wt(&dir, &["status", "agent/one"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("  Sparse: packages/web\n"));
+
+    let output = hydra_wt(&dir, &["sparse", "add", "agent/one", "tools"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("now checks out: packages/web, tools"));
+    assert!(wt.join("tools/build.sh").exists());
+
+    // Branches the override doesn't match get everything
+    hydra_wt(&dir, &["-q", "create", "feature"]);
+    assert!(dir.join("wts/feature/packages/api/main.rs").exists());
+    let output = hydra_wt(&dir, &["status", "feature"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("  Sparse: no\n"));
+    let output = Command::new(env!("CARGO_BIN_EXE_hydra-wt"))
+        .args(["sparse", "add", "feature", "tools"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_create_branch_checked_out_elsewhere() {
+    let dir = git_project("wts");
+    let elsewhere = std::env::temp_dir().join(format!("hydra-wt-cli-elsewhere-{}", uuid::Uuid::new_v4()));
+    assert!(Command::new("git").arg("-C").arg(&dir)
+        .args(["worktree", "add", "-q", "-b", "feature"]).arg(&elsewhere)
+        .status().unwrap().success());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_hydra-wt"))
+        .args(["create", "feature"])
+        .current_dir(&dir)
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("'feature' is already checked out at"), "{}", stderr);
+    assert!(stderr.contains("use `hydra-wt open feature` or pick another branch"), "{}", stderr);
+    assert!(!dir.join("wts/feature").exists());
+    std::fs::remove_dir_all(&dir).ok();
+    std::fs::remove_dir_all(&elsewhere).ok();
+}
+
+#[test]
+fn test_bare_repo_worktrees_and_merge() {
+    let dir = std::env::temp_dir().join(format!("hydra-wt-cli-bare-{}", uuid::Uuid::new_v4()));
+    let git = |dir: &Path, args: &[&str]| {
+        assert!(Command::new("git").arg("-C").arg(dir).args(args).status().unwrap().success(), "git {:?}", args);
+    };
+    let seed = dir.join("seed");
+    std::fs::create_dir_all(&seed).unwrap();
+    git(&seed, &["init", "-q", "-b", "main"]);
+    git(&seed, &["-c", "user.email=test@example.com", "-c", "user.name=Test", "commit", "-q", "--allow-empty", "-m", "init"]);
+    let bare = dir.join("project.git");
+    git(&dir, &["clone", "-q", "--bare", "seed", "project.git"]);
+    git(&bare, &["config", "user.email", "test@example.com"]);
+    git(&bare, &["config", "user.name", "Test"]);
+    std::fs::create_dir_all(bare.join(".hydra")).unwrap();
+
+    // Worktrees go next to the bare repository with the default "../"
+    hydra_wt(&bare, &["-q", "init"]);
+    hydra_wt(&bare, &["-q", "create", "main"]);
+    hydra_wt(&bare, &["-q", "create", "feature"]);
+    assert!(dir.join("main/.git").is_file() && dir.join("feature/.git").is_file());
+    std::fs::write(dir.join("feature/feature.txt"), "feature").unwrap();
+    git(&dir.join("feature"), &["add", "."]);
+    git(&dir.join("feature"), &["commit", "-q", "-m", "feature"]);
+
+    // Run from inside a worktree, the registry in the bare repository is found
+    let output = hydra_wt(&dir.join("feature"), &["list", "--json"]);
+    let list: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(list.as_array().unwrap().len(), 2);
+    hydra_wt(&bare, &["doctor"]);
+
+    hydra_wt(&dir.join("main"), &["-q", "merge", "feature", "main", "--force"]);
+    assert!(dir.join("main/feature.txt").exists());
+
+    // A target without a worktree can't fall back to a main checkout
+    git(&bare, &["branch", "release"]);
+    let output = Command::new(env!("CARGO_BIN_EXE_hydra-wt"))
+        .args(["merge", "feature", "release", "--force"])
+        .current_dir(&bare)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("bare repository has no main checkout"), "{}", stderr);
+    assert!(stderr.contains("hydra-wt create release"), "{}", stderr);
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_watch_emits_deltas_until_worktree_removed() {
+    let dir = git_project("wts");
+    hydra_wt(&dir, &["-q", "create", "feature"]);
+    let path = dir.join("wts/feature");
+    let mut watch = Command::new(env!("CARGO_BIN_EXE_hydra-wt"))
+        .args(["watch", "feature"])
+        .current_dir(&dir)
+        .env_remove("RUST_LOG")
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    std::fs::write(path.join("notes.txt"), "one\ntwo\n").unwrap();
+    std::fs::create_dir_all(path.join("wts")).unwrap();
+    std::fs::write(path.join("wts/ignored.txt"), "ignored\n").unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(3000));
+    std::fs::remove_dir_all(&path).unwrap();
+
+    let started = std::time::Instant::now();
+    while watch.try_wait().unwrap().is_none() {
+        assert!(started.elapsed().as_secs() < 10, "watch kept running after its worktree was removed");
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    let output = watch.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1 file(s) changed, +2 -0: notes.txt\n");
+
+    let queued = std::fs::read_to_string(dir.join(".hydra/wt-events.pending")).unwrap();
+    let delta: serde_json::Value = queued
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .find(|event| event["channel"] == "repo:delta")
+        .expect("no repo:delta event queued");
+    assert_eq!(delta["type"], "delta");
+    assert_eq!(delta["data"]["target"], "feature");
+    assert_eq!(delta["data"]["summary"], "1 file changed, +2 -0");
+    assert_eq!(delta["data"]["paths"], serde_json::json!(["notes.txt"]));
+    std::fs::remove_dir_all(&dir).ok();
+}
@@ -0,0 +1,131 @@
+//! Throwaway git repositories for the integration tests
+//!
+//! Every [`TestRepo`] lives in its own directory under the system temp dir,
+//! with the repository one level down so worktrees placed next to it (the
+//! default `worktrees.directory` is `..`) stay inside that directory too.
+//! The registry, config and `.hydra/` state of a test therefore never reach
+//! the checkout the tests run from, and dropping the repo removes it all.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A `git` command that behaves the same on every machine: no user or
+/// system config (signing, hooks, default branch), English output, and
+/// none of the caller's identity, `GIT_DIR` or hydra-wt overrides.
+fn git() -> Command {
+    let mut command = Command::new("git");
+    command
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("LC_ALL", "C");
+    for key in [
+        "GIT_AUTHOR_NAME",
+        "GIT_AUTHOR_EMAIL",
+        "GIT_COMMITTER_NAME",
+        "GIT_COMMITTER_EMAIL",
+        "GIT_DIR",
+        "GIT_WORK_TREE",
+        "HYDRA_WT_DIR",
+        "HYDRA_WT_REGISTRY",
+    ] {
+        command.env_remove(key);
+    }
+    command
+}
+
+/// A repository on `main` with one commit of `README.md`, removed on drop
+pub struct TestRepo {
+    /// The directory of the test, holding the repository and its worktrees
+    pub dir: PathBuf,
+    /// The main checkout
+    pub root: PathBuf,
+}
+
+impl TestRepo {
+    pub fn new() -> Self {
+        let dir = std::env::temp_dir().join(format!("hydra-wt-it-{}", uuid::Uuid::new_v4()));
+        let root = dir.join("repo");
+        std::fs::create_dir_all(root.join(".hydra")).unwrap();
+        let repo = Self { dir, root };
+        repo.git(&["init", "-q", "-b", "main"]);
+        // hydra-wt runs git itself, in the test process; settings of the
+        // repository win over the caller's, so its commits, merge commits
+        // included, get a fixed identity and are never signed or hooked
+        for (key, value) in [
+            ("user.name", "Test"),
+            ("user.email", "test@example.com"),
+            ("commit.gpgsign", "false"),
+            ("core.hooksPath", "/dev/null"),
+        ] {
+            repo.git(&["config", key, value]);
+        }
+        std::fs::write(repo.root.join(".git/info/exclude"), ".hydra/\n").unwrap();
+        repo.commit_file(&repo.root, "README.md", "test repo\n", "init");
+        repo
+    }
+
+    /// Create `branch` at the tip of `main`, with one commit per
+    /// `(file, content)` on top, and leave `main` checked out
+    pub fn with_branch(self, branch: &str, commits: &[(&str, &str)]) -> Self {
+        self.git(&["checkout", "-q", "-b", branch, "main"]);
+        for (file, content) in commits {
+            self.commit_file(&self.root, file, content, &format!("{}: {}", branch, file));
+        }
+        self.git(&["checkout", "-q", "main"]);
+        self
+    }
+
+    /// Add one commit per `(file, content)` to `main`
+    pub fn with_main_commits(self, commits: &[(&str, &str)]) -> Self {
+        for (file, content) in commits {
+            self.commit_file(&self.root, file, content, &format!("main: {}", file));
+        }
+        self
+    }
+
+    /// Run git in the main checkout, returning its trimmed stdout
+    pub fn git(&self, args: &[&str]) -> String {
+        git_in(&self.root, args)
+    }
+
+    /// Write `file` in the checkout at `dir` and commit it, returning the
+    /// new commit
+    pub fn commit_file(&self, dir: &Path, file: &str, content: &str, message: &str) -> String {
+        let path = dir.join(file);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).unwrap();
+        }
+        std::fs::write(&path, content).unwrap();
+        git_in(dir, &["add", "--", file]);
+        git_in(dir, &["commit", "-q", "-m", message]);
+        git_in(dir, &["rev-parse", "HEAD"])
+    }
+
+    /// Commit `rev` points at
+    pub fn rev(&self, rev: &str) -> String {
+        self.git(&["rev-parse", rev])
+    }
+
+    /// A path next to the main checkout, where worktrees go
+    pub fn sibling(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+}
+
+impl Drop for TestRepo {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.dir).ok();
+    }
+}
+
+/// Run git in `dir`, returning its trimmed stdout
+pub fn git_in(dir: &Path, args: &[&str]) -> String {
+    let output = git().arg("-C").arg(dir).args(args).output().unwrap();
+    assert!(
+        output.status.success(),
+        "git {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
@@ -0,0 +1,119 @@
+//! Worktree creation, removal and merging against real git repositories
+
+mod support;
+
+use hydra_wt::config::WtConfig;
+use hydra_wt::{create, worktree, CreateOptions, MergeMode, MergeResult, PortRegistry};
+use support::TestRepo;
+
+#[test]
+fn test_create_worktree_for_new_branch() {
+    let repo = TestRepo::new();
+    let outcome = create::create_opts(&WtConfig::default(), &repo.root, "feature", CreateOptions::default()).unwrap();
+
+    // The default `worktrees.directory` of `..` puts it next to the repo
+    assert_eq!(outcome.path, repo.sibling("feature"));
+    assert!(worktree::exists(&outcome.path));
+    assert!(worktree::branch_exists_in(&repo.root, "feature").unwrap());
+    assert_eq!(repo.rev("feature"), repo.rev("main"));
+    assert_eq!(worktree::get_worktree_path_in(&repo.root, "feature").unwrap(), Some(outcome.path.clone()));
+    let registry = PortRegistry::load_at(&repo.root).unwrap();
+    assert_eq!(registry.get("feature"), Some(outcome.port));
+    assert!(repo.root.join(".hydra/wt-ports.json").exists());
+
+    worktree::remove_in(&repo.root, &outcome.path).unwrap();
+    assert!(!outcome.path.exists());
+    assert_eq!(worktree::get_worktree_path_in(&repo.root, "feature").unwrap(), None);
+    // Removing the worktree keeps its branch
+    assert!(worktree::branch_exists_in(&repo.root, "feature").unwrap());
+}
+
+#[test]
+fn test_create_worktree_for_existing_branch() {
+    let repo = TestRepo::new().with_branch("feature", &[("a.txt", "a\n"), ("b.txt", "b\n")]);
+    let head = repo.rev("feature");
+    let path = repo.sibling("wt-feature");
+
+    worktree::add_in(&repo.root, &path, "feature").unwrap();
+    assert!(worktree::exists(&path));
+    assert_eq!(worktree::get_head_commit(&path).unwrap(), head);
+    assert_eq!(worktree::get_current_branch(&path).unwrap(), "feature");
+    assert!(path.join("b.txt").exists());
+    assert!(!repo.root.join("b.txt").exists());
+
+    // A branch can be checked out in one worktree only
+    assert!(worktree::add_in(&repo.root, &repo.sibling("again"), "feature").is_err());
+}
+
+#[test]
+fn test_add_sparse_worktree() {
+    let repo = TestRepo::new().with_branch("feature", &[("web/app.js", "app\n"), ("api/main.rs", "fn main() {}\n")]);
+    let path = repo.sibling("wt-sparse");
+
+    worktree::add_sparse_in(&repo.root, &path, "feature", &["web".to_string()]).unwrap();
+    assert_eq!(worktree::get_current_branch(&path).unwrap(), "feature");
+    assert!(path.join("web/app.js").exists());
+    assert!(!path.join("api").exists());
+    assert_eq!(worktree::sparse_paths_in(&path).unwrap(), Some(vec!["web".to_string()]));
+
+    // Without paths it's a full checkout
+    let full = repo.sibling("wt-full");
+    worktree::add_sparse_in(&repo.root, &full, "other", &[]).unwrap();
+    assert_eq!(worktree::sparse_paths_in(&full).unwrap(), None);
+}
+
+#[test]
+fn test_merge_fast_forward() {
+    let repo = TestRepo::new().with_branch("feature", &[("a.txt", "a\n")]);
+    let head = repo.rev("feature");
+
+    match worktree::merge(&repo.root, "feature", MergeMode::FastForward).unwrap() {
+        MergeResult::FastForward { new_head, subject } => {
+            assert_eq!(new_head, head);
+            assert_eq!(subject, repo.git(&["log", "-1", "--format=%s", "feature"]));
+        }
+        other => panic!("expected a fast-forward, got {:?}", other),
+    }
+    assert_eq!(repo.rev("main"), head);
+    assert!(matches!(
+        worktree::merge(&repo.root, "feature", MergeMode::FastForward).unwrap(),
+        MergeResult::NothingToMerge
+    ));
+}
+
+#[test]
+fn test_merge_conflict_then_abort() {
+    let repo = TestRepo::new()
+        .with_branch("feature", &[("shared.txt", "feature\n")])
+        .with_main_commits(&[("shared.txt", "main\n")]);
+    let before = repo.rev("main");
+
+    match worktree::merge(&repo.root, "feature", MergeMode::NoFastForward).unwrap() {
+        MergeResult::Conflict { files } => assert_eq!(files, vec!["shared.txt".to_string()]),
+        other => panic!("expected a conflict, got {:?}", other),
+    }
+    assert!(worktree::is_merge_in_progress(&repo.root));
+
+    worktree::merge_abort(&repo.root).unwrap();
+    assert!(!worktree::is_merge_in_progress(&repo.root));
+    assert!(!worktree::has_uncommitted_changes(&repo.root).unwrap());
+    assert_eq!(repo.rev("main"), before);
+    assert_eq!(std::fs::read_to_string(repo.root.join("shared.txt")).unwrap(), "main\n");
+}
+
+#[test]
+fn test_commits_ahead_of_diverged_branches() {
+    let repo = TestRepo::new()
+        .with_branch("feature", &[("a.txt", "a\n"), ("b.txt", "b\n"), ("c.txt", "c\n")])
+        .with_main_commits(&[("x.txt", "x\n"), ("y.txt", "y\n")]);
+
+    let ahead = worktree::commits_ahead_in(&repo.root, "feature", "main").unwrap();
+    let messages: Vec<&str> = ahead.iter().map(|c| c.message.as_str()).collect();
+    assert_eq!(messages, vec!["feature: c.txt", "feature: b.txt", "feature: a.txt"]);
+    assert!(ahead.iter().all(|c| c.author == "Test"));
+
+    let behind = worktree::commits_ahead_in(&repo.root, "main", "feature").unwrap();
+    assert_eq!(behind.len(), 2);
+    let divergence = worktree::divergence_in(&repo.root, "feature", "main").unwrap();
+    assert_eq!((divergence.ahead.len(), divergence.behind), (3, 2));
+}
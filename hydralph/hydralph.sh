@@ -83,7 +83,11 @@ emit "ralph:started" "$status_payload"
 # Main loop
 # ═══════════════════════════════════════════════════════════════════════════
 
-for i in $(seq 1 $MAX_ITERATIONS); do
+# A while loop, not seq, so `hydra retry-blocked` can raise MAX_ITERATIONS
+i=0
+while (( i < MAX_ITERATIONS )); do
+    i=$((i + 1))
+
     # Check for pause marker (from hydra pause)
     PAUSE_MARKER="$SCRIPT_DIR/.pause"
     if [[ -f "$PAUSE_MARKER" ]]; then
@@ -143,10 +147,25 @@ for i in $(seq 1 $MAX_ITERATIONS); do
         echo "║  ⚠️  BLOCKED - Agent signaled it cannot proceed"
         echo "╚═══════════════════════════════════════════════════════════════════════╝"
 
-        status_payload=$(status_json "blocked" $i)
+        # Carry the agent's "Reason:" line so hydra ls/status can show it
+        reason=$(echo "$OUTPUT" | sed -n 's/^Reason: *//p' | head -1)
+        status_payload=$(status_json "blocked" $i "\"reason\":$(jq -n --arg r "$reason" '$r' 2>/dev/null || echo '""')")
         write_status "$status_payload"
         emit "ralph:blocked" "$status_payload"
-        exit 2
+
+        # Wait for `hydra retry-blocked`; the marker holds extra iterations to grant
+        UNBLOCK_MARKER="$SCRIPT_DIR/.unblock"
+        echo "⏸️  Waiting for hydra retry-blocked..."
+        while [[ ! -f "$UNBLOCK_MARKER" ]]; do
+            sleep 1
+        done
+        extra=$(cat "$UNBLOCK_MARKER" 2>/dev/null || echo 0)
+        rm -f "$UNBLOCK_MARKER"
+        if [[ "$extra" =~ ^[0-9]+$ ]]; then
+            MAX_ITERATIONS=$((MAX_ITERATIONS + extra))
+        fi
+        echo "▶️  Unblocked (max iterations: $MAX_ITERATIONS)"
+        continue
     fi
 
     # Brief pause between iterations